@@ -0,0 +1,112 @@
+use std::f64::consts::PI;
+
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::mono_font::MonoTextStyleBuilder;
+use embedded_graphics::pixelcolor::Rgb888;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{Line, PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Baseline, Text};
+use image::{imageops, RgbImage};
+
+use crate::keys::Canvas;
+
+/// Color of arrows, boxes and text.
+pub const COLOR: [u8; 3] = [229, 57, 53];
+/// Frame height that gets 3px strokes and the font at its native size;
+/// taller frames scale both up.
+const BASE_FRAME_HEIGHT: u32 = 540;
+const STROKE_WIDTH: u32 = 3;
+/// Strong enough that text under a blur can't be read.
+const BLUR_SIGMA: f32 = 12.0;
+
+/// A mark added to a capture, in frame pixels.
+#[derive(Clone, Debug)]
+pub enum Annotation {
+    /// An arrow pointing at `to`
+    Arrow { from: (i32, i32), to: (i32, i32) },
+    /// An outlined rectangle between two corners
+    Box { from: (i32, i32), to: (i32, i32) },
+    /// A caption with its top-left corner at `at`
+    Text { at: (i32, i32), text: String },
+    /// A blurred rectangle, e.g. over a password or email address
+    Blur { from: (i32, i32), to: (i32, i32) },
+}
+
+/// Stroke width in frame pixels for a frame `height` pixels tall, so marks
+/// look the same on Retina captures.
+pub fn scale(height: u32) -> u32 {
+    (height / BASE_FRAME_HEIGHT).max(1)
+}
+
+/// Draw `annotations` onto an RGB8 frame. Blurs are applied first, so marks
+/// placed over a blurred area stay sharp.
+pub fn render(width: u32, height: u32, rgb_data: &mut [u8], annotations: &[Annotation]) {
+    for annotation in annotations {
+        if let Annotation::Blur { from, to } = annotation
+            && let Some(rect) = clamp_rect(*from, *to, width, height)
+        {
+            blur(width, rgb_data, rect);
+        }
+    }
+
+    let scale = scale(height);
+    let color = Rgb888::new(COLOR[0], COLOR[1], COLOR[2]);
+    let stroke = PrimitiveStyle::with_stroke(color, STROKE_WIDTH * scale);
+    for annotation in annotations {
+        match annotation {
+            Annotation::Arrow { from, to } => {
+                let mut canvas = Canvas { rgb_data, width, height, scale: 1 };
+                let (from, to) = (Point::new(from.0, from.1), Point::new(to.0, to.1));
+                let _ = Line::new(from, to).into_styled(stroke).draw(&mut canvas);
+                for (a, b) in arrow_head(from, to, scale) {
+                    let _ = Line::new(a, b).into_styled(stroke).draw(&mut canvas);
+                }
+            }
+            Annotation::Box { from, to } => {
+                let mut canvas = Canvas { rgb_data, width, height, scale: 1 };
+                let rectangle = Rectangle::with_corners(Point::new(from.0, from.1), Point::new(to.0, to.1));
+                let _ = rectangle.into_styled(stroke).draw(&mut canvas);
+            }
+            Annotation::Text { at, text } => {
+                // Each font pixel becomes a scale x scale block
+                let mut canvas = Canvas { rgb_data, width, height, scale };
+                let style = MonoTextStyleBuilder::new().font(&FONT_10X20).text_color(color).background_color(Rgb888::WHITE).build();
+                let origin = Point::new(at.0 / scale as i32, at.1 / scale as i32);
+                let _ = Text::with_baseline(text, origin, style, Baseline::Top).draw(&mut canvas);
+            }
+            Annotation::Blur { .. } => {}
+        }
+    }
+}
+
+/// The two strokes of an arrow's head at `to`, angled back along the shaft.
+pub fn arrow_head(from: Point, to: Point, scale: u32) -> [(Point, Point); 2] {
+    let angle = ((to.y - from.y) as f64).atan2((to.x - from.x) as f64);
+    let length = (STROKE_WIDTH * scale * 5) as f64;
+    [angle + PI * 0.85, angle - PI * 0.85].map(|side| {
+        let end = Point::new(to.x + (side.cos() * length).round() as i32, to.y + (side.sin() * length).round() as i32);
+        (to, end)
+    })
+}
+
+/// The rectangle between two corners, clipped to the frame, as x, y, width,
+/// height. None when nothing of it is inside.
+fn clamp_rect(from: (i32, i32), to: (i32, i32), width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+    let clamp = |value: i32, max: u32| value.clamp(0, max as i32) as u32;
+    let (left, right) = (clamp(from.0.min(to.0), width), clamp(from.0.max(to.0), width));
+    let (top, bottom) = (clamp(from.1.min(to.1), height), clamp(from.1.max(to.1), height));
+    (right > left && bottom > top).then_some((left, top, right - left, bottom - top))
+}
+
+fn blur(width: u32, rgb_data: &mut [u8], (x, y, w, h): (u32, u32, u32, u32)) {
+    let offset = |px: u32, py: u32| (((y + py) * width + x + px) * 3) as usize;
+    let region = RgbImage::from_fn(w, h, |px, py| {
+        let offset = offset(px, py);
+        image::Rgb([rgb_data[offset], rgb_data[offset + 1], rgb_data[offset + 2]])
+    });
+    let blurred = imageops::blur(&region, BLUR_SIGMA);
+    for (px, py, pixel) in blurred.enumerate_pixels() {
+        let offset = offset(px, py);
+        rgb_data[offset..offset + 3].copy_from_slice(&pixel.0);
+    }
+}
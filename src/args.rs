@@ -0,0 +1,210 @@
+use clap::Args;
+
+use crate::{audio, change, diff, monitor, track};
+
+/// Longest time a seconds flag accepts, about 30 years: enough to mean
+/// "forever" while still fitting a deadline computed from now.
+const MAX_SECONDS: f64 = 1e9;
+
+/// Value parser for flags given in seconds: a finite, non-negative number,
+/// so durations built from it can't panic.
+pub fn parse_seconds(value: &str) -> Result<f64, String> {
+    let seconds: f64 = value.parse().map_err(|_| format!("\"{}\" is not a number of seconds", value))?;
+    if !(0.0..=MAX_SECONDS).contains(&seconds) {
+        return Err(format!("{} seconds is out of range (0 to {})", value, MAX_SECONDS));
+    }
+    Ok(seconds)
+}
+
+#[derive(Args)]
+pub struct RemoteCaptureArgs {
+    /// Local file to save the capture to (defaults to remote_<timestamp>.jpg)
+    #[arg(short, long)]
+    pub output: Option<String>,
+    /// Crop on the remote machine before sending: X,Y,WIDTH,HEIGHT or @NAME from its config file
+    #[arg(long, value_name = "X,Y,W,H|@NAME")]
+    pub crop: Option<String>,
+    /// Downscale on the remote machine to at most this many pixels wide
+    #[arg(long, value_name = "PIXELS")]
+    pub max_width: Option<u32>,
+}
+
+#[derive(Args)]
+pub struct SessionArgs {
+    /// Bundle to write: a directory, or a zip archive if the name ends in .zip
+    #[arg(short, long, default_value = "session")]
+    pub output: String,
+    /// Seconds between compared frames
+    #[arg(long, value_parser = parse_seconds, default_value_t = 1.0)]
+    pub interval: f64,
+    /// Minimum difference (percent) for a frame to be stored
+    #[arg(long, default_value_t = 1.0)]
+    pub threshold: f64,
+    /// Store OCR snapshots of each stored frame
+    #[arg(long)]
+    pub ocr: bool,
+    /// Stop after this many seconds instead of waiting for Ctrl-C
+    #[arg(long, value_parser = parse_seconds, value_name = "SECONDS")]
+    pub duration: Option<f64>,
+}
+
+#[derive(Args)]
+pub struct TrackArgs {
+    /// Timeline file to append to; a .csv name writes CSV
+    #[arg(short, long, default_value = "timeline.jsonl")]
+    pub output: String,
+    /// Timeline format, overriding the output's extension
+    #[arg(long, value_enum)]
+    pub format: Option<track::TimelineFormat>,
+    /// Seconds between samples
+    #[arg(long, value_parser = parse_seconds, default_value_t = 5.0)]
+    pub interval: f64,
+    /// Stop after this many seconds instead of waiting for Ctrl-C
+    #[arg(long, value_parser = parse_seconds, value_name = "SECONDS")]
+    pub duration: Option<f64>,
+    /// Also save a small screenshot with each sample (off by default, no pixels are captured)
+    #[arg(long)]
+    pub with_thumbnails: bool,
+    /// Width of thumbnails in pixels
+    #[arg(long, default_value_t = 320, value_name = "PIXELS")]
+    pub thumbnail_width: u32,
+}
+
+#[derive(Args)]
+pub struct MonitorArgs {
+    /// Part of the frame to read, or @NAME for a region from the config file (defaults to the whole frame)
+    #[arg(long, value_name = "X,Y,WIDTH,HEIGHT|@NAME")]
+    pub region: Option<change::RegionArg>,
+    /// Record the number in the text (e.g. 1,234.5) instead of the text itself; readings without one are skipped
+    #[arg(long)]
+    pub extract_number: bool,
+    /// Sample format
+    #[arg(long, value_enum, default_value_t = monitor::Sink::Csv)]
+    pub emit: monitor::Sink,
+    /// File to append samples to, or an InfluxDB write URL (defaults to stdout)
+    #[arg(short, long, value_name = "FILE|URL")]
+    pub output: Option<String>,
+    /// InfluxDB measurement name
+    #[arg(long, default_value = "captest")]
+    pub measurement: String,
+    /// InfluxDB API token for a write URL
+    #[arg(long, env = "CAPTEST_INFLUX_TOKEN", hide_env_values = true)]
+    pub token: Option<String>,
+    /// Seconds between readings
+    #[arg(long, value_parser = parse_seconds, default_value_t = 10.0)]
+    pub interval: f64,
+    /// Stop after this many seconds instead of waiting for Ctrl-C
+    #[arg(long, value_parser = parse_seconds, value_name = "SECONDS")]
+    pub duration: Option<f64>,
+    /// Don't read or write the on-disk OCR result cache
+    #[arg(long)]
+    pub no_cache: bool,
+}
+
+#[derive(Args)]
+pub struct WatchArgs {
+    /// Seconds between checks for changes
+    #[arg(long, value_parser = parse_seconds, default_value_t = 1.0, value_name = "SECONDS")]
+    pub interval: f64,
+    /// Minimum mean difference (percent) between frames that counts as a change
+    #[arg(long, default_value_t = 1.0, value_name = "PERCENT")]
+    pub threshold: f64,
+    /// Save every changed frame as watch_<timestamp>.jpg
+    #[arg(long)]
+    pub save: bool,
+    /// Extract text from every changed frame using OCR
+    #[arg(long)]
+    pub ocr: bool,
+    /// Analyze changed frames with the LLM, subject to the limits below
+    #[arg(long)]
+    pub analyze: bool,
+    /// Custom prompt for LLM analysis
+    #[arg(long)]
+    pub prompt: Option<String>,
+    /// Minimum seconds between LLM analyses; changes in between are coalesced
+    #[arg(long, value_parser = parse_seconds, value_name = "SECONDS")]
+    pub analyze_interval: Option<f64>,
+    /// Maximum LLM analyses in any 60 second window
+    #[arg(long, value_name = "N")]
+    pub max_analyses_per_minute: Option<u32>,
+    /// Keep the first frame's size when a watched window is resized, letterboxing later frames
+    #[arg(long)]
+    pub lock_size: bool,
+    /// When the watched window closes, wait for a window with the same title instead of exiting
+    #[arg(long)]
+    pub reattach_by_title: bool,
+    /// Ignore changes inside a region, e.g. a clock (repeatable, adds to ignore_regions from the config file)
+    #[arg(long, value_name = "X,Y,WIDTH,HEIGHT|@NAME")]
+    pub ignore_region: Vec<change::RegionArg>,
+    /// Capture and notify webhooks when the window title (the active window's, for screens) changes to one matching REGEX (repeatable)
+    #[arg(long, value_name = "REGEX")]
+    pub title_trigger: Vec<regex::Regex>,
+}
+
+#[derive(Args)]
+pub struct DiffArgs {
+    /// Largest percentage of changed pixels that still counts as the same image
+    #[arg(long, default_value_t = 0.0, value_name = "PERCENT")]
+    pub threshold: f64,
+    /// Decide pass/fail with another score instead: pixels:PERCENT, ssim:MIN or delta-e:MAX
+    #[arg(long, value_name = "METRIC:VALUE", conflicts_with = "threshold")]
+    pub threshold_metric: Option<diff::ThresholdMetric>,
+    /// Per-channel difference (0-255) below which a pixel counts as unchanged
+    #[arg(long, default_value_t = 16)]
+    pub tolerance: u8,
+    /// Count differences that look like anti-aliased edges as changes
+    #[arg(long)]
+    pub count_antialiasing: bool,
+    /// Save an image highlighting the changed pixels
+    #[arg(long, value_name = "FILE")]
+    pub diff_output: Option<String>,
+    /// Leave a region out of the comparison (repeatable, adds to ignore_regions from the config file)
+    #[arg(long, value_name = "X,Y,WIDTH,HEIGHT|@NAME")]
+    pub ignore_region: Vec<change::RegionArg>,
+}
+
+#[derive(Args)]
+pub struct RecordArgs {
+    /// Output file; the extension picks the format: .mkv, .mp4 (needs the `mp4` feature) or .gif
+    #[arg(short, long, default_value = "recording.mkv")]
+    pub output: String,
+    /// Frames per second to record
+    #[arg(long, default_value_t = 10)]
+    pub fps: u32,
+    /// Stop after this many seconds instead of waiting for Ctrl-C
+    #[arg(long, value_parser = parse_seconds, value_name = "SECONDS")]
+    pub duration: Option<f64>,
+    /// Seconds of video per segment synced to disk, at most this much is lost on a crash; the keyframe interval of MP4
+    #[arg(long, value_parser = parse_seconds, default_value_t = 2.0, value_name = "SECONDS")]
+    pub segment: f64,
+    /// Scale frames down to at most this width
+    #[arg(long, value_name = "PIXELS")]
+    pub max_width: Option<u32>,
+    /// Keep the first frame's size when a recorded window is resized, letterboxing later frames
+    #[arg(long)]
+    pub lock_size: bool,
+    /// When the recorded window closes, wait for a window with the same title instead of stopping
+    #[arg(long)]
+    pub reattach_by_title: bool,
+    /// Include the mouse pointer in the video
+    #[arg(long)]
+    pub show_cursor: bool,
+    /// Draw a ring where the mouse is clicked and a dot while a button is held (implies --show-cursor)
+    #[arg(long)]
+    pub highlight_clicks: bool,
+    /// Caption pressed keys and shortcuts into the video. Records everything typed, asks for confirmation first
+    #[arg(long)]
+    pub show_keys: bool,
+    /// Confirm --show-keys without a prompt, e.g. in scripts
+    #[arg(long, requires = "show_keys")]
+    pub accept_keystroke_capture: bool,
+    /// Record audio into a second track (.mkv only); `app` takes only the recorded window's application
+    #[arg(long, value_enum, requires = "window")]
+    pub audio: Option<audio::AudioSource>,
+    /// Burn each frame's arrival time (UTC, and seconds since captest started) into its top left corner
+    #[arg(long)]
+    pub timestamp_overlay: bool,
+    /// Write each frame's video timestamp with its display, arrival and monotonic times to <output>.frames.jsonl
+    #[arg(long)]
+    pub timestamp_sidecar: bool,
+}
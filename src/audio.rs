@@ -0,0 +1,123 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use clap::ValueEnum;
+use scap::Target;
+use scap::capturer::Options;
+use scap::frame::{AudioFormat, AudioFrame};
+
+use crate::error::{CaptestError, ErrorKind};
+use crate::platforms;
+
+/// Sample rate and channel count of recorded audio. Sources with another
+/// layout are converted.
+pub const SAMPLE_RATE: u32 = 48_000;
+pub const CHANNELS: u16 = 2;
+/// How long to wait for the audio client to fail before assuming it works.
+const CAPTURE_STARTUP: Duration = Duration::from_millis(500);
+
+/// Where `record --audio` takes sound from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum AudioSource {
+    /// Only the recorded window's application
+    App,
+}
+
+/// Audio of the recorded window's app, captured next to the video. On macOS
+/// ScreenCaptureKit delivers it with the video frames, limited to the
+/// window's app by the window filter; on Windows it comes from a WASAPI
+/// process loopback client on its own thread.
+pub struct AppAudio {
+    chunks: mpsc::Receiver<Vec<f32>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl AppAudio {
+    /// Set up app audio for the window `options` captures. Returns None when
+    /// the audio arrives as `Frame::Audio` from the capture session instead.
+    pub fn start(options: &mut Options) -> Result<Option<Self>, CaptestError> {
+        let Some(Target::Window(window)) = &options.target else {
+            return Err(CaptestError::new(ErrorKind::Unsupported, "--audio app needs a --window target"));
+        };
+        if cfg!(target_os = "macos") {
+            options.captures_audio = true;
+            options.exclude_current_process_audio = true;
+            return Ok(None);
+        }
+
+        let pid = platforms::window_pid(window.id)
+            .ok_or_else(|| CaptestError::new(ErrorKind::TargetNotFound, "Can't find the process of the recorded window"))?;
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sender, chunks) = mpsc::channel();
+        let thread_stop = stop.clone();
+        let capture = std::thread::spawn(move || platforms::watch_app_audio(pid, sender, &thread_stop));
+        std::thread::sleep(CAPTURE_STARTUP);
+        if capture.is_finished() {
+            let message = match capture.join() {
+                Ok(Err(message)) => message,
+                _ => "App audio capture stopped unexpectedly".to_string(),
+            };
+            return Err(CaptestError::new(ErrorKind::Unsupported, message));
+        }
+        Ok(Some(Self { chunks, stop }))
+    }
+
+    /// Interleaved samples captured since the last call.
+    pub fn drain(&self) -> Vec<f32> {
+        self.chunks.try_iter().flatten().collect()
+    }
+}
+
+impl Drop for AppAudio {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Convert a backend audio frame to interleaved samples at `SAMPLE_RATE`
+/// with `CHANNELS` channels: mono is duplicated, channels past the second
+/// are dropped.
+pub fn frame_to_samples(frame: &AudioFrame) -> Vec<f32> {
+    let channels = frame.channels().max(1) as usize;
+    let count = frame.sample_count();
+    let decoded = decode(frame.format(), frame.raw_data());
+    let sample = |index: usize, channel: usize| {
+        let channel = channel.min(channels - 1);
+        let at = if frame.is_planar() { channel * count + index } else { index * channels + channel };
+        decoded.get(at).copied().unwrap_or(0.0)
+    };
+
+    let rate = frame.rate().max(1);
+    let out_count = (count as u64 * SAMPLE_RATE as u64 / rate as u64) as usize;
+    let mut samples = Vec::with_capacity(out_count * CHANNELS as usize);
+    for out in 0..out_count {
+        // Linear interpolation between the two nearest source samples
+        let position = out as f64 * rate as f64 / SAMPLE_RATE as f64;
+        let (index, fraction) = (position as usize, position.fract() as f32);
+        for channel in 0..CHANNELS as usize {
+            let (a, b) = (sample(index, channel), sample((index + 1).min(count.saturating_sub(1)), channel));
+            samples.push(a + (b - a) * fraction);
+        }
+    }
+    samples
+}
+
+/// Samples of any backend format as floats in -1.0..1.0.
+fn decode(format: AudioFormat, data: &[u8]) -> Vec<f32> {
+    fn each<const N: usize>(data: &[u8], convert: impl Fn([u8; N]) -> f32) -> Vec<f32> {
+        data.chunks_exact(N).map(|bytes| convert(bytes.try_into().unwrap())).collect()
+    }
+    match format {
+        AudioFormat::I8 => each(data, |b: [u8; 1]| i8::from_ne_bytes(b) as f32 / 128.0),
+        AudioFormat::U8 => each(data, |b: [u8; 1]| (b[0] as f32 - 128.0) / 128.0),
+        AudioFormat::I16 => each(data, |b| i16::from_ne_bytes(b) as f32 / 32_768.0),
+        AudioFormat::U16 => each(data, |b| (u16::from_ne_bytes(b) as f32 - 32_768.0) / 32_768.0),
+        AudioFormat::I32 => each(data, |b| (i32::from_ne_bytes(b) as f64 / 2_147_483_648.0) as f32),
+        AudioFormat::U32 => each(data, |b| ((u32::from_ne_bytes(b) as f64 - 2_147_483_648.0) / 2_147_483_648.0) as f32),
+        AudioFormat::I64 => each(data, |b| (i64::from_ne_bytes(b) as f64 / 9_223_372_036_854_775_808.0) as f32),
+        AudioFormat::U64 => each(data, |b| ((u64::from_ne_bytes(b) as f64 - 9_223_372_036_854_775_808.0) / 9_223_372_036_854_775_808.0) as f32),
+        AudioFormat::F32 => each(data, f32::from_ne_bytes),
+        AudioFormat::F64 => each(data, |b| f64::from_ne_bytes(b) as f32),
+    }
+}
@@ -0,0 +1,127 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::zip::ZipWriter;
+
+/// Size of a tar header and of the blocks entries are padded to.
+const TAR_BLOCK: usize = 512;
+/// Longest entry name that fits a ustar header's name and prefix fields.
+const TAR_NAME: usize = 100;
+const TAR_PREFIX: usize = 155;
+
+/// One archive that `--bundle` streams a run's images, sidecars and report
+/// into as they are produced: a zip, or a tar when the name ends in `.tar`.
+pub struct Bundle {
+    path: String,
+    writer: Writer,
+    names: HashSet<String>,
+}
+
+enum Writer {
+    Zip(ZipWriter),
+    Tar(BufWriter<File>),
+}
+
+impl Bundle {
+    pub fn create(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        if let Some(parent) = Path::new(path).parent()
+            && !parent.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        let writer = match path.to_lowercase() {
+            name if name.ends_with(".tar") => Writer::Tar(BufWriter::new(File::create(path)?)),
+            name if name.ends_with(".zip") => Writer::Zip(ZipWriter::create(Path::new(path))?),
+            _ => return Err(format!("Bundle {} must end in .zip or .tar", path).into()),
+        };
+        status!("Bundling outputs into {}", path);
+        Ok(Self { path: path.to_string(), writer, names: HashSet::new() })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Add a file and return the name it was stored under: `name`, or with a
+    /// number added when an earlier entry already has that name.
+    pub fn add(&mut self, name: &str, data: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
+        let name = self.unique_name(name);
+        match &mut self.writer {
+            Writer::Zip(zip) => zip.add(&name, data)?,
+            Writer::Tar(file) => {
+                file.write_all(&tar_header(&name, data.len() as u64)?)?;
+                file.write_all(data)?;
+                file.write_all(&vec![0u8; data.len().next_multiple_of(TAR_BLOCK) - data.len()])?;
+            }
+        }
+        self.names.insert(name.clone());
+        Ok(name)
+    }
+
+    /// Add `value` as pretty-printed JSON.
+    pub fn add_json(&mut self, name: &str, value: &impl Serialize) -> Result<String, Box<dyn std::error::Error>> {
+        self.add(name, serde_json::to_string_pretty(value)?.as_bytes())
+    }
+
+    /// Write the archive's closing records; it can't be read without them.
+    pub fn finish(self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.writer {
+            Writer::Zip(zip) => zip.finish()?,
+            Writer::Tar(mut file) => {
+                file.write_all(&[0u8; TAR_BLOCK * 2])?;
+                file.flush()?;
+                file.get_ref().sync_all()?;
+            }
+        }
+        result!("Bundled {} files into {}", self.names.len(), self.path);
+        Ok(())
+    }
+
+    fn unique_name(&self, name: &str) -> String {
+        if !self.names.contains(name) {
+            return name.to_string();
+        }
+        let (stem, extension) = match name.rsplit_once('.') {
+            Some((stem, extension)) if !stem.is_empty() && !extension.contains('/') => (stem, format!(".{}", extension)),
+            _ => (name, String::new()),
+        };
+        (2..).map(|n| format!("{}-{}{}", stem, n, extension)).find(|candidate| !self.names.contains(candidate)).unwrap_or_default()
+    }
+}
+
+/// A ustar header for a regular file. Names longer than 100 bytes are split
+/// at a `/` into the prefix field.
+fn tar_header(name: &str, size: u64) -> Result<[u8; TAR_BLOCK], String> {
+    let (prefix, name) = match name.len() <= TAR_NAME {
+        true => ("", name),
+        false => name.char_indices()
+            .filter(|&(at, c)| c == '/' && at <= TAR_PREFIX && name.len() - at - 1 <= TAR_NAME)
+            .map(|(at, _)| (&name[..at], &name[at + 1..]))
+            .next()
+            .ok_or_else(|| format!("{} is too long for a tar entry name", name))?,
+    };
+    let mtime = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+
+    let mut header = [0u8; TAR_BLOCK];
+    let mut field = |at: usize, value: &[u8]| header[at..at + value.len()].copy_from_slice(value);
+    field(0, name.as_bytes());
+    field(100, b"0000644\0");
+    field(108, b"0000000\0");
+    field(116, b"0000000\0");
+    field(124, format!("{:011o}\0", size).as_bytes());
+    field(136, format!("{:011o}\0", mtime).as_bytes());
+    // The checksum is computed with its own field as spaces
+    field(148, b"        ");
+    field(156, b"0");
+    field(257, b"ustar\0");
+    field(263, b"00");
+    field(345, prefix.as_bytes());
+    let checksum: u32 = header.iter().map(|&b| b as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    Ok(header)
+}
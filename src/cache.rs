@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::digest;
+
+/// A small on-disk key/value cache, one JSON file per entry, grouped by namespace
+/// (e.g. `llm`) under the user cache directory.
+pub struct DiskCache {
+    dir: PathBuf,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    created: u64,
+    value: String,
+}
+
+impl DiskCache {
+    pub fn new(namespace: &str) -> Self {
+        Self { dir: cache_dir(namespace) }
+    }
+
+    /// Look up `key`, ignoring entries older than `ttl`.
+    pub fn get(&self, key: &str, ttl: Duration) -> Option<String> {
+        let contents = std::fs::read_to_string(self.dir.join(format!("{}.json", key))).ok()?;
+        let entry: Entry = serde_json::from_str(&contents).ok()?;
+        let age = now_secs().saturating_sub(entry.created);
+        (age <= ttl.as_secs()).then_some(entry.value)
+    }
+
+    /// Store `value` under `key`. Failures are reported but never fatal, since
+    /// the cache is only an optimization.
+    pub fn put(&self, key: &str, value: &str) {
+        let entry = Entry { created: now_secs(), value: value.to_string() };
+        let result = std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::write(self.dir.join(format!("{}.json", key)), serde_json::to_string(&entry)?));
+        if let Err(e) = result {
+            status!("Failed to write cache entry: {}", e);
+        }
+    }
+}
+
+/// Hex SHA-256 over all parts, length-prefixed so part boundaries can't collide.
+pub fn cache_key(parts: &[&[u8]]) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_le_bytes());
+        hasher.update(part);
+    }
+    digest::hex(&hasher.finalize())
+}
+
+/// Per-namespace directory under the user cache directory, for files that
+/// aren't key/value entries (e.g. downloaded models).
+pub fn cache_dir(namespace: &str) -> PathBuf {
+    cache_root().join(namespace)
+}
+
+fn cache_root() -> PathBuf {
+    if let Some(dir) = std::env::var_os("XDG_CACHE_HOME") {
+        PathBuf::from(dir).join("captest")
+    } else if let Some(dir) = std::env::var_os("LOCALAPPDATA") {
+        PathBuf::from(dir).join("captest").join("cache")
+    } else if let Some(home) = std::env::var_os("HOME") {
+        PathBuf::from(home).join(".cache").join("captest")
+    } else {
+        std::env::temp_dir().join("captest-cache")
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
@@ -0,0 +1,295 @@
+use std::ops::{Deref, DerefMut};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use scap::capturer::{Capturer, CapturerBuildError, Options};
+use scap::frame::{Frame, VideoFrame};
+use tokio::sync::{mpsc, oneshot};
+use tokio_util::sync::CancellationToken;
+
+use crate::config;
+use crate::error::{self, CaptestError, ErrorKind};
+use crate::memory;
+use crate::privacy;
+use crate::session_pool::{self, SessionPool, TargetKey};
+use crate::timings::{self, Stage};
+
+/// Frames an [`AsyncCaptureSession`] buffers before its capture thread
+/// waits, unless `--buffer-depth` says otherwise.
+const FRAME_BUFFER: usize = 4;
+
+/// Fail early with the right error kind when capture can't work at all.
+pub fn ensure_capture_allowed() -> Result<(), Box<dyn std::error::Error>> {
+    if !scap::is_supported() {
+        return Err(CaptestError::new(ErrorKind::Unsupported, "Screen capture not supported").into());
+    }
+    if !scap::has_permission() {
+        request_permission();
+        return Err(CaptestError::new(ErrorKind::PermissionDenied, "Please grant screen recording permission and rerun.").into());
+    }
+    Ok(())
+}
+
+/// Ask the OS for screen recording permission, except in CI, where nobody
+/// can answer the prompt and it would only hold up the job.
+pub fn request_permission() {
+    if non_interactive() {
+        status!("Not prompting for screen recording permission, since CI is set");
+        return;
+    }
+    scap::request_permission();
+}
+
+/// Whether captest runs unattended, as CI systems announce by setting `CI`.
+pub fn non_interactive() -> bool {
+    std::env::var("CI").is_ok_and(|value| !value.is_empty() && value != "0" && value != "false")
+}
+
+/// Capture a single frame of `key` as RGB8, for commands that only need pixels.
+/// Frames too large to OCR within `--max-memory` are downscaled.
+pub async fn grab_rgb8(key: TargetKey, cancel: &CancellationToken) -> Result<(u32, u32, Vec<u8>), Box<dyn std::error::Error>> {
+    into_ocr_rgb8(grab_video_frame(key, cancel).await?)
+}
+
+/// Like [`grab_rgb8`], but never downscaled, for commands that compare exact
+/// pixels at screen coordinates.
+pub async fn grab_rgb8_full_resolution(key: TargetKey, cancel: &CancellationToken) -> Result<(u32, u32, Vec<u8>), Box<dyn std::error::Error>> {
+    Ok(crate::convert::video_frame_into_rgb8(grab_video_frame(key, cancel).await?))
+}
+
+/// Capture a single video frame of `key`, with sensitive windows masked.
+async fn grab_video_frame(key: TargetKey, cancel: &CancellationToken) -> Result<VideoFrame, Box<dyn std::error::Error>> {
+    ensure_capture_allowed()?;
+    let target = session_pool::resolve_target(key)
+        .map_err(|e| CaptestError::new(ErrorKind::TargetNotFound, e))?;
+
+    let mut options = Options {
+        fps: 1,
+        show_highlight: config::settings().show_highlight,
+        output_type: scap::frame::FrameType::BGRAFrame,
+        target: Some(target),
+        ..Default::default()
+    };
+    privacy::exclude_sensitive(&mut options)?;
+
+    let mut session = AsyncCaptureSession::start(options.clone(), cancel).await?;
+    loop {
+        match session.next_frame().await? {
+            Frame::Video(mut video_frame) => {
+                privacy::mask_frame(&options, &mut video_frame);
+                return Ok(video_frame);
+            }
+            Frame::Audio(_) => continue,
+        }
+    }
+}
+
+/// A frame as RGB8, downscaled if it is too large to OCR within `--max-memory`.
+fn into_ocr_rgb8(video_frame: VideoFrame) -> Result<(u32, u32, Vec<u8>), Box<dyn std::error::Error>> {
+    let (width, height, rgb_data, _) = into_ocr_rgb8_scaled(video_frame)?;
+    Ok((width, height, rgb_data))
+}
+
+/// Like [`into_ocr_rgb8`], also returning the factor the frame was divided by.
+fn into_ocr_rgb8_scaled(video_frame: VideoFrame) -> Result<(u32, u32, Vec<u8>, u32), Box<dyn std::error::Error>> {
+    let (width, height) = crate::convert::video_frame_size(&video_frame);
+    let frame_bytes = width as u64 * height as u64 * 4;
+    let factor = memory::downscale_factor(width, height, frame_bytes, memory::OCR_BYTES_PER_PIXEL, "Recognizing text in")?;
+    let (width, height, rgb_data) = crate::convert::video_frame_into_rgb8_downscaled(video_frame, factor);
+    Ok((width, height, rgb_data, factor))
+}
+
+/// Grabs frames of one target from a capturer that keeps running between
+/// grabs, for commands that capture the same target over and over. Like
+/// [`grab_rgb8`], but the backend session is built once; it stops when the
+/// grabber is dropped.
+pub struct FrameGrabber {
+    key: TargetKey,
+    pool: Arc<SessionPool>,
+}
+
+impl FrameGrabber {
+    /// `fps` should be high enough that the newest frame is still current
+    /// when [`grab`](Self::grab) is called.
+    pub fn new(key: TargetKey, fps: u32) -> Result<Self, Box<dyn std::error::Error>> {
+        ensure_capture_allowed()?;
+        session_pool::resolve_target(key).map_err(|e| CaptestError::new(ErrorKind::TargetNotFound, e))?;
+        Ok(Self { key, pool: Arc::new(SessionPool::new(Duration::MAX, fps)) })
+    }
+
+    /// The newest frame as RGB8, downscaled like [`grab_rgb8`]'s. The first
+    /// call waits for the capturer to start.
+    pub async fn grab(&self, cancel: &CancellationToken) -> Result<(u32, u32, Vec<u8>), Box<dyn std::error::Error>> {
+        let (width, height, rgb_data, _) = self.grab_scaled(cancel).await?;
+        Ok((width, height, rgb_data))
+    }
+
+    /// Like [`grab`](Self::grab), also returning the factor the frame was
+    /// downscaled by, for mapping screen coordinates onto it.
+    pub async fn grab_scaled(&self, cancel: &CancellationToken) -> Result<(u32, u32, Vec<u8>, u32), Box<dyn std::error::Error>> {
+        if cancel.is_cancelled() {
+            return Err(cancelled().into());
+        }
+        // Waiting on the first frame blocks, keep it off the runtime workers
+        let (pool, key) = (self.pool.clone(), self.key);
+        match tokio::task::spawn_blocking(move || pool.latest_frame(key)).await?? {
+            Frame::Video(video_frame) => into_ocr_rgb8_scaled(video_frame),
+            Frame::Audio(_) => Err(CaptestError::new(ErrorKind::Failure, "Received a non-video frame").into()),
+        }
+    }
+}
+
+/// Every screen and window the backend offers, timed for `--timings`.
+pub fn all_targets() -> Vec<scap::Target> {
+    let _timing = timings::span(Stage::Enumerate);
+    scap::get_all_targets()
+}
+
+/// Frames to discard at the start of a one-shot capture on this platform:
+/// PipeWire often delivers an empty buffer before the first real frame, and
+/// Windows Graphics Capture and ScreenCaptureKit can hand back a frame
+/// composed before the capture started.
+pub fn default_skip_frames() -> usize {
+    if cfg!(target_os = "linux") { 2 } else { 1 }
+}
+
+/// A token that is cancelled when the user presses Ctrl-C. Only commands that
+/// can be interrupted mid-capture install the handler, since it replaces the
+/// default of ending the process.
+pub fn cancel_on_ctrl_c() -> CancellationToken {
+    let cancel = CancellationToken::new();
+    let token = cancel.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            token.cancel();
+        }
+    });
+    cancel
+}
+
+fn cancelled() -> CaptestError {
+    CaptestError::new(ErrorKind::Cancelled, "Capture cancelled")
+}
+
+/// A capture session for async code. The scap capturer lives on its own
+/// thread, where its blocking calls can't stall runtime workers, and frames
+/// arrive through a bounded channel. Cancelling the token, or dropping the
+/// session, stops the capturer.
+pub struct AsyncCaptureSession {
+    frames: mpsc::Receiver<Result<Frame, String>>,
+    cancel: CancellationToken,
+    /// When the capturer started, until the first frame arrives
+    awaiting_first_frame: Option<Instant>,
+}
+
+impl AsyncCaptureSession {
+    /// Build and start a capturer for `options` on a dedicated thread. The
+    /// session stops when `cancel` (or the session's own child token) is cancelled.
+    pub async fn start(options: Options, cancel: &CancellationToken) -> Result<Self, CaptestError> {
+        let cancel = cancel.child_token();
+        let (started_sender, started) = oneshot::channel();
+        let (frame_sender, frames) = mpsc::channel(config::settings().buffer_depth.unwrap_or(FRAME_BUFFER));
+
+        let thread_cancel = cancel.clone();
+        thread::spawn(move || {
+            // The capturer is created and dropped on this thread, so it never
+            // has to cross thread boundaries
+            let session = match CaptureSession::start(options) {
+                Ok(session) => session,
+                Err(e) => {
+                    let _ = started_sender.send(Err(CaptestError::new(error::error_kind(&*e), e.to_string())));
+                    return;
+                }
+            };
+            let _ = started_sender.send(Ok(()));
+
+            while !thread_cancel.is_cancelled() {
+                let frame = session.get_next_frame().map_err(|e| e.to_string());
+                let failed = frame.is_err();
+                if frame_sender.blocking_send(frame).is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        tokio::select! {
+            _ = cancel.cancelled() => Err(cancelled()),
+            started = started => match started {
+                Ok(Ok(())) => Ok(Self { frames, cancel, awaiting_first_frame: Some(Instant::now()) }),
+                Ok(Err(e)) => Err(e),
+                Err(_) => Err(CaptestError::new(ErrorKind::Failure, "Capture thread exited during startup")),
+            },
+        }
+    }
+
+    /// Wait for the next frame without blocking the runtime.
+    pub async fn next_frame(&mut self) -> Result<Frame, CaptestError> {
+        tokio::select! {
+            _ = self.cancel.cancelled() => Err(cancelled()),
+            frame = self.frames.recv() => match frame {
+                Some(Ok(frame)) => {
+                    if let Some(started) = self.awaiting_first_frame.take() {
+                        timings::record(Stage::FirstFrame, started.elapsed());
+                    }
+                    Ok(frame)
+                }
+                Some(Err(e)) => Err(CaptestError::new(ErrorKind::CaptureTimeout, format!("Failed to get frame: {}", e))),
+                None => Err(CaptestError::new(ErrorKind::CaptureTimeout, "Capture session ended")),
+            },
+        }
+    }
+}
+
+impl Drop for AsyncCaptureSession {
+    fn drop(&mut self) {
+        self.cancel.cancel();
+    }
+}
+
+/// A started scap capturer that is always stopped when dropped, including on
+/// early returns and `?` error paths, so backend sessions are never leaked.
+pub struct CaptureSession {
+    capturer: Capturer,
+}
+
+impl CaptureSession {
+    /// Build a capturer for `options` and start streaming.
+    pub fn start(options: Options) -> Result<Self, Box<dyn std::error::Error>> {
+        let _timing = timings::span(Stage::BuildCapturer);
+        status!("Building capturer...");
+        let mut capturer = Capturer::build(options).map_err(|err| {
+            let kind = match err {
+                CapturerBuildError::NotSupported => ErrorKind::Unsupported,
+                CapturerBuildError::PermissionNotGranted => ErrorKind::PermissionDenied,
+            };
+            CaptestError::new(kind, format!("Error building capturer: {err}"))
+        })?;
+        status!("Capturer built successfully");
+
+        capturer.start_capture();
+        status!("Scap capturer initialized successfully");
+
+        Ok(Self { capturer })
+    }
+}
+
+impl Deref for CaptureSession {
+    type Target = Capturer;
+
+    fn deref(&self) -> &Capturer {
+        &self.capturer
+    }
+}
+
+impl DerefMut for CaptureSession {
+    fn deref_mut(&mut self) -> &mut Capturer {
+        &mut self.capturer
+    }
+}
+
+impl Drop for CaptureSession {
+    fn drop(&mut self) {
+        self.capturer.stop_capture();
+    }
+}
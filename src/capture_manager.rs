@@ -0,0 +1,181 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use scap::capturer::Options;
+use scap::frame::Frame;
+
+use crate::capture::CaptureSession;
+use crate::session_pool::TargetKey;
+
+/// A bounded queue of frames from one capture thread. When the consumer falls
+/// behind, the oldest queued frame is dropped so live targets never stall.
+pub struct FrameQueue {
+    state: Mutex<QueueState>,
+    ready: Condvar,
+    capacity: usize,
+}
+
+struct QueueState {
+    frames: VecDeque<Frame>,
+    /// Newest frame ever received, kept even after it was popped
+    newest: Option<Frame>,
+    closed: bool,
+}
+
+impl FrameQueue {
+    fn new(capacity: usize) -> Self {
+        Self {
+            state: Mutex::new(QueueState { frames: VecDeque::new(), newest: None, closed: false }),
+            ready: Condvar::new(),
+            capacity: capacity.max(1),
+        }
+    }
+
+    fn push(&self, frame: Frame) {
+        let mut state = self.state.lock().unwrap();
+        if state.frames.len() >= self.capacity {
+            state.frames.pop_front();
+        }
+        state.newest = Some(frame.clone());
+        state.frames.push_back(frame);
+        self.ready.notify_all();
+    }
+
+    fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+        self.ready.notify_all();
+    }
+
+    /// Take the oldest queued frame, waiting up to `timeout` for one. None on
+    /// timeout or once the capture has ended and the queue is drained.
+    pub fn pop(&self, timeout: Duration) -> Option<Frame> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(frame) = state.frames.pop_front() {
+                return Some(frame);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if state.closed || remaining.is_zero() {
+                return None;
+            }
+            state = self.ready.wait_timeout(state, remaining).unwrap().0;
+        }
+    }
+
+    /// The most recent frame, waiting up to `timeout` for the first one.
+    /// Unlike [`pop`](Self::pop) this doesn't consume anything, so a target
+    /// that stopped changing still has a frame.
+    pub fn latest(&self, timeout: Duration) -> Option<Frame> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.state.lock().unwrap();
+        loop {
+            if let Some(frame) = &state.newest {
+                return Some(frame.clone());
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if state.closed || remaining.is_zero() {
+                return None;
+            }
+            state = self.ready.wait_timeout(state, remaining).unwrap().0;
+        }
+    }
+
+    /// Whether the capture thread has exited.
+    pub fn is_closed(&self) -> bool {
+        self.state.lock().unwrap().closed
+    }
+}
+
+struct RunningCapture {
+    queue: Arc<FrameQueue>,
+    stop: Arc<AtomicBool>,
+}
+
+/// Runs capturers for several targets at once, each on its own thread feeding
+/// its own [`FrameQueue`]. Safe to share between threads; stopping a target or
+/// dropping the manager ends the capture threads.
+pub struct CaptureManager {
+    captures: Mutex<HashMap<TargetKey, RunningCapture>>,
+    queue_len: usize,
+}
+
+impl CaptureManager {
+    /// `queue_len` is how many frames each target buffers before dropping the oldest.
+    pub fn new(queue_len: usize) -> Self {
+        Self { captures: Mutex::new(HashMap::new()), queue_len }
+    }
+
+    /// Start capturing `key` with `options`, or return its queue if it is
+    /// already running. A capture whose thread has exited is restarted.
+    pub fn start(&self, key: TargetKey, options: Options) -> Arc<FrameQueue> {
+        let mut captures = self.captures.lock().unwrap();
+        if let Some(capture) = captures.get(&key)
+            && !capture.queue.is_closed()
+        {
+            return capture.queue.clone();
+        }
+
+        let queue = Arc::new(FrameQueue::new(self.queue_len));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        status!("Starting capture thread for {}", key);
+        let thread_queue = queue.clone();
+        let thread_stop = stop.clone();
+        thread::spawn(move || {
+            // The capturer is created and dropped on this thread, so it never
+            // has to cross thread boundaries
+            let session = match CaptureSession::start(options) {
+                Ok(session) => session,
+                Err(e) => {
+                    status!("Failed to start capture session for {}: {}", key, e);
+                    thread_queue.close();
+                    return;
+                }
+            };
+
+            while !thread_stop.load(Ordering::Relaxed) {
+                match session.get_next_frame() {
+                    Ok(frame @ Frame::Video(_)) => thread_queue.push(frame),
+                    Ok(Frame::Audio(_)) => {}
+                    Err(e) => {
+                        status!("Capture session for {} ended: {}", key, e);
+                        break;
+                    }
+                }
+            }
+            thread_queue.close();
+        });
+
+        captures.insert(key, RunningCapture { queue: queue.clone(), stop });
+        queue
+    }
+
+    /// The frame queue of a running target.
+    pub fn queue(&self, key: TargetKey) -> Option<Arc<FrameQueue>> {
+        self.captures.lock().unwrap().get(&key).map(|capture| capture.queue.clone())
+    }
+
+    /// Stop capturing `key`. Frames already queued can still be read.
+    pub fn stop(&self, key: TargetKey) {
+        if let Some(capture) = self.captures.lock().unwrap().remove(&key) {
+            capture.stop.store(true, Ordering::Relaxed);
+        }
+    }
+
+    /// Targets with a capture thread, running or not yet cleaned up.
+    pub fn targets(&self) -> Vec<TargetKey> {
+        self.captures.lock().unwrap().keys().copied().collect()
+    }
+}
+
+impl Drop for CaptureManager {
+    fn drop(&mut self) {
+        for capture in self.captures.get_mut().unwrap().values() {
+            capture.stop.store(true, Ordering::Relaxed);
+        }
+    }
+}
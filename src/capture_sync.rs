@@ -0,0 +1,189 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use scap::Target;
+use scap::frame::{Frame, VideoFrame};
+use serde::Serialize;
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+use crate::bundle::Bundle;
+use crate::capture::{self, AsyncCaptureSession};
+use crate::error::{CaptestError, ErrorKind};
+use crate::session_pool::{self, TargetKey};
+use crate::{config, convert};
+
+/// Frame rate of the capturers, which bounds how far apart the frames can be.
+const SYNC_FPS: u32 = 60;
+/// How often the capturers' latest frames are checked.
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+/// How long the capturers may take to start and deliver a frame.
+const START_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The newest frame from a capturer and when it arrived.
+type Slot = Arc<Mutex<Option<(VideoFrame, SystemTime)>>>;
+
+/// One window's frame of a synchronized capture.
+#[derive(Serialize)]
+pub struct SyncFrame {
+    pub window: usize,
+    pub id: u32,
+    pub title: String,
+    pub width: u32,
+    pub height: u32,
+    /// Unix milliseconds the backend stamped the frame with
+    pub timestamp: u128,
+    /// Milliseconds between the capture instant and this frame
+    pub offset_ms: f64,
+    /// Saved file, or the entry name in the bundle
+    pub output: String,
+}
+
+#[derive(Serialize)]
+pub struct SyncReport {
+    pub status: String,
+    /// ID shared by every output of this capture
+    pub sequence: String,
+    /// Unix milliseconds of the instant the frames were aligned to
+    pub requested_at: u128,
+    /// Milliseconds between the earliest and latest frame
+    pub skew_ms: f64,
+    pub frames: Vec<SyncFrame>,
+    /// Archive holding the images and this report, with `--bundle`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bundle: Option<String>,
+}
+
+/// The `capture-sync` command: run a capturer per window in parallel and,
+/// once all of them are streaming, take from each the first frame at or
+/// after one shared instant, so the images show the windows' state at (as
+/// near as the frame rate allows) the same moment. Files are named
+/// `<prefix>_<sequence>_window<N>.jpg`; with `bundle` they go into that
+/// archive along with a `report.json`.
+pub async fn capture_sync(windows: &[usize], prefix: &str, sequence: Option<&str>, bundle: Option<&str>, cancel: &CancellationToken) -> Result<SyncReport, Box<dyn std::error::Error>> {
+    capture::ensure_capture_allowed()?;
+    if windows.len() < 2 {
+        return Err("capture-sync needs at least two windows, e.g. --windows 1,2".into());
+    }
+
+    let mut targets = Vec::with_capacity(windows.len());
+    for &window in windows {
+        let key = TargetKey::Window(window);
+        let Target::Window(info) = session_pool::resolve_target(key).map_err(|e| CaptestError::new(ErrorKind::TargetNotFound, e))? else {
+            unreachable!("window keys resolve to windows");
+        };
+        targets.push((window, info, session_pool::capture_options(key, SYNC_FPS)?));
+    }
+
+    // Stopping the capturers when done doesn't cancel the caller's token
+    let cancel = cancel.child_token();
+    let mut capturers = JoinSet::new();
+    let mut slots: Vec<Slot> = Vec::with_capacity(targets.len());
+    for (_, _, options) in &targets {
+        let slot: Slot = Arc::default();
+        let (options, latest, cancel) = (options.clone(), slot.clone(), cancel.clone());
+        capturers.spawn(async move {
+            let mut session = AsyncCaptureSession::start(options, &cancel).await?;
+            loop {
+                if let Frame::Video(video_frame) = session.next_frame().await? {
+                    *latest.lock().unwrap() = Some((video_frame, SystemTime::now()));
+                }
+            }
+        });
+        slots.push(slot);
+    }
+
+    status!("Starting {} capturers...", targets.len());
+    let started = Instant::now();
+    while slots.iter().any(|slot| slot.lock().unwrap().is_none()) {
+        wait(&mut capturers, &cancel, started).await?;
+    }
+
+    // Every capturer is streaming, so the next frame from each follows promptly
+    let requested_at = SystemTime::now();
+    let mut taken: Vec<Option<(VideoFrame, SystemTime)>> = targets.iter().map(|_| None).collect();
+    while taken.iter().any(Option::is_none) {
+        for (slot, taken) in slots.iter().zip(taken.iter_mut()).filter(|(_, taken)| taken.is_none()) {
+            let mut latest = slot.lock().unwrap();
+            if latest.as_ref().is_some_and(|(_, received_at)| *received_at >= requested_at) {
+                *taken = latest.take();
+            }
+        }
+        if taken.iter().any(Option::is_none) {
+            wait(&mut capturers, &cancel, started).await?;
+        }
+    }
+    cancel.cancel();
+    capturers.shutdown().await;
+
+    let sequence = sequence.map(str::to_string).unwrap_or_else(|| unix_millis(requested_at).to_string());
+    let mut bundle = bundle.map(|path| Bundle::create(&config::resolve_output_path(path))).transpose()?;
+    let mut frames = Vec::with_capacity(targets.len());
+    for ((window, info, _), (video_frame, _)) in targets.into_iter().zip(taken.into_iter().flatten()) {
+        let display_time = convert::video_frame_display_time(&video_frame);
+        let (width, height, rgb_data) = convert::video_frame_into_rgb8(video_frame);
+        let name = format!("{}_{}_window{}.jpg", prefix, sequence, window);
+        let jpeg_bytes = crate::rgb8_to_jpeg_bytes(width, height, &rgb_data)?;
+        let output = match &mut bundle {
+            Some(bundle) => bundle.add(&name, &jpeg_bytes)?,
+            None => crate::save_image_bytes(&jpeg_bytes, &config::resolve_output_path(&name))?,
+        };
+        status!("Saved window {} ('{}') to {}", window, info.title, output);
+        frames.push(SyncFrame {
+            window,
+            id: info.id,
+            title: info.title,
+            width,
+            height,
+            timestamp: unix_millis(display_time),
+            offset_ms: signed_millis(requested_at, display_time),
+            output,
+        });
+    }
+
+    let offsets = frames.iter().map(|frame| frame.offset_ms);
+    let skew_ms = offsets.clone().fold(f64::MIN, f64::max) - offsets.fold(f64::MAX, f64::min);
+    result!("Captured {} windows as sequence {} within {:.1} ms", frames.len(), sequence, skew_ms);
+    let report = SyncReport {
+        status: "ok".to_string(),
+        sequence,
+        requested_at: unix_millis(requested_at),
+        skew_ms,
+        frames,
+        bundle: bundle.as_ref().map(|bundle| bundle.path().to_string()),
+    };
+    if let Some(mut bundle) = bundle {
+        bundle.add_json("report.json", &report)?;
+        bundle.finish()?;
+    }
+    Ok(report)
+}
+
+/// Sleep one poll interval, failing if a capturer stopped, the user
+/// cancelled or the capturers took too long to deliver.
+async fn wait(capturers: &mut JoinSet<Result<(), CaptestError>>, cancel: &CancellationToken, started: Instant) -> Result<(), Box<dyn std::error::Error>> {
+    if started.elapsed() > START_TIMEOUT {
+        return Err(CaptestError::new(ErrorKind::CaptureTimeout, "Timed out waiting for every window to deliver a frame").into());
+    }
+    tokio::select! {
+        _ = cancel.cancelled() => Err(CaptestError::new(ErrorKind::Cancelled, "Capture cancelled").into()),
+        // Capturers only stop on errors
+        Some(stopped) = capturers.join_next() => match stopped {
+            Ok(Err(e)) => Err(e.into()),
+            _ => Err("A capturer stopped unexpectedly".into()),
+        },
+        _ = tokio::time::sleep(POLL_INTERVAL) => Ok(()),
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0)
+}
+
+/// `time` relative to `reference` in milliseconds, negative when earlier.
+fn signed_millis(reference: SystemTime, time: SystemTime) -> f64 {
+    match time.duration_since(reference) {
+        Ok(after) => after.as_secs_f64() * 1000.0,
+        Err(before) => -before.duration().as_secs_f64() * 1000.0,
+    }
+}
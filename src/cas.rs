@@ -0,0 +1,136 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::digest;
+use crate::error::ErrorKind;
+
+/// The manifest in each directory of content-addressed captures.
+pub const MANIFEST: &str = "cas-manifest.json";
+
+/// Serializes manifest updates from concurrent saves in this process.
+static MANIFEST_LOCK: Mutex<()> = Mutex::new(());
+
+/// What a logical file name refers to.
+#[derive(Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Hex SHA-256 of the file's contents
+    pub sha256: String,
+    /// File holding the contents, relative to the manifest
+    pub object: String,
+    pub bytes: u64,
+    /// Unix milliseconds of the last save under this name
+    pub saved_at: u64,
+}
+
+/// Manifest entries by logical file name.
+type Manifest = BTreeMap<String, ManifestEntry>;
+
+/// Save `bytes` for `filename` in `--cas` mode: the contents go to
+/// `<sha256>.<extension>` in the directory `filename` names, and the
+/// directory's manifest maps the file name to that object. Identical
+/// contents are stored once. Returns the object's path.
+pub fn store(bytes: &[u8], filename: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let path = Path::new(filename);
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let name = path.file_name().ok_or_else(|| format!("{} has no file name", filename))?.to_string_lossy().into_owned();
+    let extension = path.extension().map(|extension| extension.to_string_lossy().into_owned()).unwrap_or_else(|| "jpg".to_string());
+
+    let sha256 = digest::sha256_hex(bytes);
+    let object = format!("{}.{}", sha256, extension);
+    let object_path = dir.join(&object);
+    std::fs::create_dir_all(dir)?;
+    if object_path.exists() {
+        status!("{} is identical to {}, not storing it again", name, object_path.display());
+    } else {
+        status!("Saving {} as {}", name, object_path.display());
+        write_atomically(&object_path, bytes)?;
+    }
+
+    let _guard = MANIFEST_LOCK.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    let manifest_path = dir.join(MANIFEST);
+    let mut manifest = load_manifest(&manifest_path)?.unwrap_or_default();
+    let saved_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0);
+    manifest.insert(name, ManifestEntry { sha256, object, bytes: bytes.len() as u64, saved_at });
+    write_atomically(&manifest_path, serde_json::to_string_pretty(&manifest)?.as_bytes())?;
+    Ok(object_path.to_string_lossy().into_owned())
+}
+
+/// A manifest entry whose object is missing or doesn't match its hash.
+#[derive(Serialize)]
+pub struct Problem {
+    pub name: String,
+    pub object: String,
+    /// "missing" or "corrupt"
+    pub problem: &'static str,
+}
+
+#[derive(Serialize)]
+pub struct VerifyReport {
+    /// "ok", or "failed" when any object is missing or corrupt
+    pub status: String,
+    pub manifest: String,
+    /// Logical names checked
+    pub names: usize,
+    /// Distinct objects hashed
+    pub objects: usize,
+    pub problems: Vec<Problem>,
+    #[serde(skip)]
+    pub failure: Option<ErrorKind>,
+}
+
+/// The `cas-verify` command: hash every object the manifest in `dir`
+/// refers to and report the ones that are missing or were altered.
+pub fn verify(dir: &Path) -> Result<VerifyReport, Box<dyn std::error::Error>> {
+    let manifest_path = dir.join(MANIFEST);
+    let manifest = load_manifest(&manifest_path)?
+        .ok_or_else(|| format!("No {} in {}; was it written with --cas?", MANIFEST, dir.display()))?;
+
+    // Many names can share an object; hash each one once
+    let mut checked: HashMap<&str, Option<&'static str>> = HashMap::new();
+    let mut problems = Vec::new();
+    for (name, entry) in &manifest {
+        let problem = *checked.entry(&entry.object).or_insert_with(|| match std::fs::read(dir.join(&entry.object)) {
+            Err(_) => Some("missing"),
+            Ok(bytes) if digest::sha256_hex(&bytes) != entry.sha256 => Some("corrupt"),
+            Ok(_) => None,
+        });
+        if let Some(problem) = problem {
+            result!("{}: {} is {}", name, entry.object, problem);
+            problems.push(Problem { name: name.clone(), object: entry.object.clone(), problem });
+        }
+    }
+
+    result!("Checked {} names ({} objects) in {}: {} problems", manifest.len(), checked.len(), manifest_path.display(), problems.len());
+    let failure = (!problems.is_empty()).then_some(ErrorKind::Failure);
+    Ok(VerifyReport {
+        status: if failure.is_some() { "failed" } else { "ok" }.to_string(),
+        manifest: manifest_path.to_string_lossy().into_owned(),
+        names: manifest.len(),
+        objects: checked.len(),
+        problems,
+        failure,
+    })
+}
+
+fn load_manifest(path: &Path) -> Result<Option<Manifest>, Box<dyn std::error::Error>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e).into()),
+    };
+    let manifest = serde_json::from_str(&contents).map_err(|e| format!("Invalid manifest {}: {}", path.display(), e))?;
+    Ok(Some(manifest))
+}
+
+/// Write through a temporary file, so readers never see a partial object or
+/// manifest.
+fn write_atomically(path: &Path, bytes: &[u8]) -> std::io::Result<()> {
+    let mut temporary = PathBuf::from(path);
+    temporary.set_file_name(format!(".{}.{}.tmp", path.file_name().unwrap_or_default().to_string_lossy(), std::process::id()));
+    std::fs::write(&temporary, bytes)?;
+    std::fs::rename(&temporary, path)
+}
@@ -0,0 +1,233 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use serde::Serialize;
+
+use crate::config::{self, SavedRegion};
+
+/// Width and height of the thumbnail compared between frames.
+const SIGNATURE_SIZE: (usize, usize) = (64, 36);
+
+/// A small grayscale thumbnail of a frame. Comparing thumbnails instead of
+/// full frames averages out noise and makes change checks cheap.
+#[derive(Clone)]
+pub struct Signature {
+    width: u32,
+    height: u32,
+    cells: Vec<u8>,
+}
+
+/// Bounding box of the changed area of a frame, in frame pixels.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChangedRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// A rectangle of frame pixels left out of change detection and diffs, such
+/// as a clock or notification area. Parsed from `X,Y,WIDTH,HEIGHT`.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IgnoreRegion {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl IgnoreRegion {
+    pub fn contains(&self, x: u32, y: u32) -> bool {
+        x >= self.x && y >= self.y && x - self.x < self.width && y - self.y < self.height
+    }
+}
+
+impl FromStr for IgnoreRegion {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        let values: Vec<u32> = value.split(',').map(|part| part.trim().parse()).collect::<Result<_, _>>()
+            .map_err(|_| format!("Invalid region \"{}\", expected X,Y,WIDTH,HEIGHT", value))?;
+        let [x, y, width, height] = values[..] else {
+            return Err(format!("Invalid region \"{}\", expected X,Y,WIDTH,HEIGHT", value));
+        };
+        // Edges past u32::MAX would wrap around when added up in containment checks
+        if x.checked_add(width).is_none() || y.checked_add(height).is_none() {
+            return Err(format!("Invalid region \"{}\", its right or bottom edge is out of range", value));
+        }
+        Ok(Self { x, y, width, height })
+    }
+}
+
+/// A rectangle given as an argument: `X,Y,WIDTH,HEIGHT`, or `@NAME` for a
+/// region saved in the config file. Names are looked up when the command
+/// runs, since arguments are parsed before the config file is read.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RegionArg {
+    Rect(IgnoreRegion),
+    Named(String),
+}
+
+impl FromStr for RegionArg {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        match value.strip_prefix('@') {
+            Some("") => Err("Expected a region name after @".to_string()),
+            Some(name) => Ok(Self::Named(name.to_string())),
+            None => value.parse().map(Self::Rect),
+        }
+    }
+}
+
+impl RegionArg {
+    /// The rectangle, looking `@NAME` up in the config file.
+    pub fn resolve(&self) -> Result<SavedRegion, String> {
+        self.resolve_in(&config::settings().regions)
+    }
+
+    pub fn resolve_in(&self, regions: &HashMap<String, SavedRegion>) -> Result<SavedRegion, String> {
+        match self {
+            Self::Rect(rect) => Ok(SavedRegion { display: None, rect: *rect }),
+            Self::Named(name) => regions.get(name).copied().ok_or_else(|| {
+                let mut names: Vec<&String> = regions.keys().collect();
+                names.sort();
+                if names.is_empty() {
+                    format!("Unknown region @{}, the config file defines no [regions]", name)
+                } else {
+                    let names: Vec<String> = names.iter().map(|name| format!("@{}", name)).collect();
+                    format!("Unknown region @{}, the config file defines {}", name, names.join(", "))
+                }
+            }),
+        }
+    }
+}
+
+/// The rectangles of several region arguments.
+pub fn resolve_regions(regions: &[RegionArg]) -> Result<Vec<IgnoreRegion>, String> {
+    regions.iter().map(|region| region.resolve().map(|region| region.rect)).collect()
+}
+
+impl Signature {
+    /// Pixels inside `ignore` don't contribute to the thumbnail.
+    pub fn new(width: u32, height: u32, rgb_data: &[u8], ignore: &[IgnoreRegion]) -> Self {
+        let (cols, rows) = SIGNATURE_SIZE;
+        let (w, h) = (width as usize, height as usize);
+        let mut sums = vec![(0u64, 0u64); cols * rows];
+
+        for y in 0..h {
+            let row = y * rows / h.max(1);
+            for x in 0..w {
+                if ignore.iter().any(|region| region.contains(x as u32, y as u32)) {
+                    continue;
+                }
+                let col = x * cols / w.max(1);
+                let i = (y * w + x) * 3;
+                let Some(pixel) = rgb_data.get(i..i + 3) else {
+                    continue;
+                };
+                let luma = (pixel[0] as u64 * 299 + pixel[1] as u64 * 587 + pixel[2] as u64 * 114) / 1000;
+                let cell = &mut sums[row * cols + col];
+                cell.0 += luma;
+                cell.1 += 1;
+            }
+        }
+
+        let cells = sums.iter().map(|(sum, count)| (sum / (*count).max(1)) as u8).collect();
+        Self { width, height, cells }
+    }
+
+    /// Mean absolute difference to `other` as a percentage.
+    pub fn difference(&self, other: &Signature) -> f64 {
+        let total: u64 = self.cells.iter().zip(&other.cells).map(|(a, b)| a.abs_diff(*b) as u64).sum();
+        total as f64 * 100.0 / (self.cells.len().max(1) as f64 * 255.0)
+    }
+
+    /// The area covering every thumbnail cell that changed noticeably, or None
+    /// if nothing did (or the frame size changed, in which case compare
+    /// against a new baseline).
+    pub fn changed_region(&self, other: &Signature) -> Option<ChangedRegion> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return None;
+        }
+
+        let (cols, rows) = SIGNATURE_SIZE;
+        let (mut min_col, mut min_row, mut max_col, mut max_row) = (cols, rows, 0, 0);
+        for (i, (a, b)) in self.cells.iter().zip(&other.cells).enumerate() {
+            // About 3% of the value range, enough to ignore compression noise
+            if a.abs_diff(*b) > 8 {
+                let (col, row) = (i % cols, i / cols);
+                min_col = min_col.min(col);
+                min_row = min_row.min(row);
+                max_col = max_col.max(col);
+                max_row = max_row.max(row);
+            }
+        }
+        if min_col > max_col {
+            return None;
+        }
+
+        let x = |col: usize| (col as u64 * self.width as u64 / cols as u64) as u32;
+        let y = |row: usize| (row as u64 * self.height as u64 / rows as u64) as u32;
+        Some(ChangedRegion {
+            x: x(min_col),
+            y: y(min_row),
+            width: x(max_col + 1) - x(min_col),
+            height: y(max_row + 1) - y(min_row),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> IgnoreRegion {
+        IgnoreRegion { x, y, width, height }
+    }
+
+    #[test]
+    fn parses_regions() {
+        assert_eq!("10,20,300,400".parse(), Ok(rect(10, 20, 300, 400)));
+        assert_eq!(" 10, 20 ,300,400 ".parse(), Ok(rect(10, 20, 300, 400)));
+    }
+
+    #[test]
+    fn rejects_malformed_regions() {
+        for value in ["", "10,20,300", "10,20,300,400,5", "10,20,-300,400", "a,b,c,d", "10;20;300;400"] {
+            assert!(value.parse::<IgnoreRegion>().is_err(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn accepts_edges_at_the_largest_coordinate() {
+        let max = u32::MAX.to_string();
+        assert_eq!(format!("0,0,{},{}", max, max).parse(), Ok(rect(0, 0, u32::MAX, u32::MAX)));
+        assert_eq!(format!("{},{},0,0", max, max).parse(), Ok(rect(u32::MAX, u32::MAX, 0, 0)));
+    }
+
+    #[test]
+    fn rejects_edges_that_overflow() {
+        let max = u32::MAX;
+        for value in [format!("1,0,{},10", max), format!("0,1,10,{}", max), format!("{},{},{},{}", max, max, max, max)] {
+            let error = value.parse::<IgnoreRegion>().unwrap_err();
+            assert!(error.contains("out of range"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn parses_region_arguments() {
+        assert_eq!("1,2,3,4".parse(), Ok(RegionArg::Rect(rect(1, 2, 3, 4))));
+        assert_eq!("@clock".parse(), Ok(RegionArg::Named("clock".to_string())));
+        assert!("@".parse::<RegionArg>().is_err());
+        assert!("clock".parse::<RegionArg>().is_err());
+    }
+
+    #[test]
+    fn resolves_named_regions() {
+        let regions = HashMap::from([("clock".to_string(), SavedRegion { display: Some(1), rect: rect(1, 2, 3, 4) })]);
+        assert_eq!(RegionArg::Named("clock".to_string()).resolve_in(&regions), Ok(SavedRegion { display: Some(1), rect: rect(1, 2, 3, 4) }));
+        let error = RegionArg::Named("tray".to_string()).resolve_in(&regions).unwrap_err();
+        assert!(error.contains("@clock"), "{}", error);
+    }
+}
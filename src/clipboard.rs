@@ -0,0 +1,83 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::encode::ImageFormat;
+
+/// Put `text` on the clipboard with the platform's clipboard tool.
+pub fn copy_text(text: &str) -> Result<(), String> {
+    let tools: &[(&str, &[&str])] = if cfg!(target_os = "macos") {
+        &[("pbcopy", &[])]
+    } else if cfg!(target_os = "windows") {
+        &[("clip", &[])]
+    } else {
+        &[("wl-copy", &[]), ("xclip", &["-selection", "clipboard"]), ("xsel", &["--clipboard", "--input"])]
+    };
+    for (program, args) in tools {
+        let Ok(mut child) = Command::new(program).args(*args).stdin(Stdio::piped()).spawn() else {
+            continue;
+        };
+        // clip reads the ANSI code page unless given UTF-16 with a byte order mark
+        let bytes: Vec<u8> = match *program {
+            "clip" => [0xFEFF].into_iter().chain(text.encode_utf16()).flat_map(u16::to_le_bytes).collect(),
+            _ => text.as_bytes().to_vec(),
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(&bytes).map_err(|e| format!("Failed to write to {}: {}", program, e))?;
+        }
+        let status = child.wait().map_err(|e| format!("{} failed: {}", program, e))?;
+        return match status.success() {
+            true => Ok(()),
+            false => Err(format!("{} failed with {}", program, status)),
+        };
+    }
+    let names: Vec<&str> = tools.iter().map(|(program, _)| *program).collect();
+    Err(format!("No clipboard tool found (tried {})", names.join(", ")))
+}
+
+/// Put an encoded image on the clipboard. Linux tools read it from stdin;
+/// macOS and Windows load it from a temporary file.
+pub fn copy_image(image_bytes: &[u8], format: ImageFormat) -> Result<(), String> {
+    if cfg!(any(target_os = "macos", target_os = "windows")) {
+        // AppleScript reads JPEG and PNG; Windows decodes all of them
+        let class = match format {
+            ImageFormat::Jpeg => "JPEG picture",
+            ImageFormat::Png => "«class PNGf»",
+            _ if cfg!(target_os = "macos") => return Err(format!("The macOS clipboard takes JPEG or PNG images, not {}", format.extension())),
+            _ => "",
+        };
+        let path = std::env::temp_dir().join(format!("captest-clipboard-{}.{}", std::process::id(), format.extension()));
+        std::fs::write(&path, image_bytes).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        let path_text = path.display().to_string();
+        let (program, args) = match cfg!(target_os = "macos") {
+            true => ("osascript", vec!["-e".to_string(), format!("set the clipboard to (read (POSIX file \"{}\") as {})", path_text, class)]),
+            false => ("powershell", vec!["-NoProfile".to_string(), "-Sta".to_string(), "-Command".to_string(), format!(
+                "Add-Type -AssemblyName System.Windows.Forms, System.Drawing; \
+                 $image = [System.Drawing.Image]::FromFile('{}'); [System.Windows.Forms.Clipboard]::SetImage($image); $image.Dispose()",
+                path_text.replace('\'', "''"))]),
+        };
+        let output = Command::new(program).args(&args).stdin(Stdio::null()).output();
+        let _ = std::fs::remove_file(&path);
+        let output = output.map_err(|e| format!("Failed to run {}: {}", program, e))?;
+        return match output.status.success() {
+            true => Ok(()),
+            false => Err(format!("{} failed: {}", program, String::from_utf8_lossy(&output.stderr).trim())),
+        };
+    }
+
+    let mime_type = format.mime_type();
+    let tools: &[(&str, &[&str])] = &[("wl-copy", &["--type", mime_type]), ("xclip", &["-selection", "clipboard", "-t", mime_type])];
+    for (program, args) in tools {
+        let Ok(mut child) = Command::new(program).args(*args).stdin(Stdio::piped()).spawn() else {
+            continue;
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(image_bytes).map_err(|e| format!("Failed to write to {}: {}", program, e))?;
+        }
+        let status = child.wait().map_err(|e| format!("{} failed: {}", program, e))?;
+        return match status.success() {
+            true => Ok(()),
+            false => Err(format!("{} failed with {}", program, status)),
+        };
+    }
+    Err("No clipboard tool for images found (tried wl-copy, xclip)".to_string())
+}
@@ -0,0 +1,336 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use crate::change::IgnoreRegion;
+use crate::digest;
+use crate::encode::Encoder;
+use crate::llm::Provider;
+use crate::memory::OnMemoryLimit;
+use crate::upload::{ShareHost, ShareSettings, UploadSettings};
+use crate::webhook::Webhook;
+
+pub const DEFAULT_LLM_URL: &str = "http://localhost:1234/v1";
+/// Header carrying the run ID on webhook deliveries and LLM requests.
+pub const RUN_ID_HEADER: &str = "X-Captest-Run-Id";
+/// Placeholder replaced by the run ID in output filenames and prompts.
+const RUN_ID_PLACEHOLDER: &str = "{run_id}";
+
+/// Settings read from the TOML config file. Every field is optional; values
+/// are overridden by `CAPTEST_*` environment variables and then CLI flags.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    pub llm_url: Option<String>,
+    pub llm_provider: Option<String>,
+    pub llm_model: Option<String>,
+    pub ocr_model_dir: Option<PathBuf>,
+    pub ocr_model_url: Option<String>,
+    pub ocr_lang: Option<String>,
+    pub ocr_alphabet: Option<String>,
+    pub ocr_allowed_chars: Option<String>,
+    pub ocr_beam_width: Option<u32>,
+    pub ocr_dictionary: Option<PathBuf>,
+    pub output_dir: Option<PathBuf>,
+    pub format: Option<String>,
+    pub encoder: Option<String>,
+    pub gpu: Option<bool>,
+    pub max_memory_mb: Option<u64>,
+    pub on_memory_limit: Option<String>,
+    pub buffer_depth: Option<usize>,
+    /// Window title fragments excluded from display captures, replacing the built-in list
+    pub privacy_blocklist: Option<Vec<String>>,
+    pub privacy_strict: Option<bool>,
+    pub show_highlight: Option<bool>,
+    pub cas: Option<bool>,
+    /// Regions (`X,Y,WIDTH,HEIGHT` or `@NAME`) ignored by diff, verify and watch
+    pub ignore_regions: Option<Vec<String>>,
+    /// Named regions, used as `@NAME` wherever a rectangle is expected
+    pub regions: Option<HashMap<String, RegionConfig>>,
+    /// Endpoints notified of events from long-running commands
+    pub webhooks: Option<Vec<WebhookConfig>>,
+    /// Broker that events are published to and capture commands come from
+    pub mqtt: Option<MqttConfig>,
+    /// Where `capture --share` uploads to
+    pub share: Option<ShareConfig>,
+    /// Credentials for `--upload` destinations
+    pub upload: Option<UploadConfig>,
+    pub input_token_price: Option<f64>,
+    pub output_token_price: Option<f64>,
+}
+
+/// A `[regions.NAME]` table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RegionConfig {
+    /// Screen the region is on, captured when no other screen is given
+    pub display: Option<usize>,
+    /// `X,Y,WIDTH,HEIGHT` in frame pixels
+    pub rect: String,
+}
+
+/// A `[[webhooks]]` table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Event names to send; every event when empty
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Key for the `X-Captest-Signature` HMAC of each body
+    pub secret: Option<String>,
+    /// Regex limiting `text-found` to matching lines
+    #[serde(rename = "match")]
+    pub text_match: Option<String>,
+    /// `json` (default) or `slack`
+    pub format: Option<String>,
+}
+
+/// The `[mqtt]` table.
+#[derive(Deserialize, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MqttConfig {
+    /// `mqtt://HOST[:PORT]`, or `mqtts://` for TLS
+    pub broker: String,
+    /// Defaults to `captest-<pid>`; give each fleet member its own
+    pub client_id: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Parent of the default topics
+    #[serde(default = "default_topic_prefix")]
+    pub topic_prefix: String,
+    pub events_topic: Option<String>,
+    pub ocr_topic: Option<String>,
+    pub thumbnail_topic: Option<String>,
+    pub command_topic: Option<String>,
+    /// Also publish a JPEG thumbnail of each change and commanded capture
+    #[serde(default)]
+    pub thumbnails: bool,
+    #[serde(default = "default_thumbnail_width")]
+    pub thumbnail_width: u32,
+}
+
+fn default_topic_prefix() -> String {
+    "captest".to_string()
+}
+
+fn default_thumbnail_width() -> u32 {
+    320
+}
+
+/// The `[share]` table.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShareConfig {
+    /// `0x0` (default) or `imgur`
+    pub host: Option<String>,
+    /// Upload endpoint of a self-hosted 0x0 instance
+    pub url: Option<String>,
+    pub imgur_client_id: Option<String>,
+    pub expires_hours: Option<u32>,
+    pub copy_url: Option<bool>,
+}
+
+/// The `[upload]` table.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadConfig {
+    pub ssh_key: Option<PathBuf>,
+    pub webdav_username: Option<String>,
+    pub webdav_password: Option<String>,
+}
+
+/// A rectangle and the screen it belongs to, from `--region`-style arguments
+/// or the config file's `[regions]`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SavedRegion {
+    pub display: Option<usize>,
+    pub rect: IgnoreRegion,
+}
+
+/// Effective settings after merging config file, environment and CLI flags.
+pub struct Settings {
+    /// Identifies this run in filenames, JSON output, webhooks and LLM requests
+    pub run_id: String,
+    pub llm_url: String,
+    pub llm_provider: Provider,
+    /// Model requested from the endpoint; None uses the provider's default
+    pub llm_model: Option<String>,
+    pub ocr_model_dir: Option<PathBuf>,
+    pub ocr_model_url: String,
+    /// Selects text-recognition-<lang>.rten instead of the default Latin model
+    pub ocr_lang: Option<String>,
+    pub ocr_alphabet: Option<String>,
+    pub ocr_allowed_chars: Option<String>,
+    pub ocr_beam_width: Option<u32>,
+    /// Word list for correcting OCR transcripts; None leaves them as recognized
+    pub ocr_dictionary: Option<PathBuf>,
+    pub output_dir: Option<PathBuf>,
+    pub encoder: Encoder,
+    /// Convert streamed frames on the GPU (only in builds with the `gpu` feature)
+    pub gpu: bool,
+    /// Working memory allowed per capture, in MiB
+    pub max_memory_mb: Option<u64>,
+    pub on_memory_limit: OnMemoryLimit,
+    /// Frames buffered between a capturer and its consumer; None keeps each command's default
+    pub buffer_depth: Option<usize>,
+    /// Window title fragments (case-insensitive) kept out of display captures
+    pub privacy_blocklist: Vec<String>,
+    /// Fail display captures showing sensitive windows that can't be excluded
+    pub privacy_strict: bool,
+    /// Ask the backend to outline captured targets; only Windows draws a border
+    pub show_highlight: bool,
+    /// Save captures under their content hash, with a manifest of requested names
+    pub cas: bool,
+    /// Regions ignored by diff, verify and watch in addition to --ignore-region
+    pub ignore_regions: Vec<IgnoreRegion>,
+    /// Named regions from the config file, by name without the `@`
+    pub regions: HashMap<String, SavedRegion>,
+    pub webhooks: Vec<Webhook>,
+    pub mqtt: Option<MqttConfig>,
+    pub share: ShareSettings,
+    pub upload: UploadSettings,
+    /// USD per million prompt tokens, used to estimate analysis cost
+    pub input_token_price: Option<f64>,
+    /// USD per million completion tokens
+    pub output_token_price: Option<f64>,
+}
+
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+pub fn init(settings: Settings) {
+    let _ = SETTINGS.set(settings);
+}
+
+pub fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(|| Settings {
+        run_id: new_run_id(),
+        llm_url: DEFAULT_LLM_URL.to_string(),
+        llm_provider: Provider::OpenAi,
+        llm_model: None,
+        ocr_model_dir: None,
+        ocr_model_url: crate::ocr::DEFAULT_MODEL_URL.to_string(),
+        ocr_lang: None,
+        ocr_alphabet: None,
+        ocr_allowed_chars: None,
+        ocr_beam_width: None,
+        ocr_dictionary: None,
+        output_dir: None,
+        encoder: Encoder::Auto,
+        gpu: false,
+        max_memory_mb: None,
+        on_memory_limit: OnMemoryLimit::Downscale,
+        buffer_depth: None,
+        privacy_blocklist: crate::privacy::DEFAULT_BLOCKLIST.iter().map(|entry| entry.to_string()).collect(),
+        privacy_strict: false,
+        show_highlight: false,
+        cas: false,
+        ignore_regions: Vec::new(),
+        regions: HashMap::new(),
+        webhooks: Vec::new(),
+        mqtt: None,
+        share: ShareSettings::default(),
+        upload: UploadSettings::default(),
+        input_token_price: None,
+        output_token_price: None,
+    })
+}
+
+/// A fresh run ID: 12 hex digits from the start time and process ID, unique
+/// enough to tell apart the runs of one automated job.
+pub fn new_run_id() -> String {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or(0);
+    let mut hasher = Sha256::new();
+    hasher.update(nanos.to_le_bytes());
+    hasher.update(std::process::id().to_le_bytes());
+    digest::hex(&hasher.finalize()[..6])
+}
+
+/// Replace `{run_id}` in a filename or prompt with the run ID.
+pub fn expand_run_id(text: &str) -> String {
+    text.replace(RUN_ID_PLACEHOLDER, &settings().run_id)
+}
+
+/// Load the config file. An explicit path must exist; otherwise `captest.toml`
+/// in the current directory and then the per-user config file are tried.
+pub fn load_config_file(explicit: Option<&Path>) -> Result<ConfigFile, Box<dyn std::error::Error>> {
+    let path = match explicit {
+        Some(path) => path.to_path_buf(),
+        None => match default_config_paths().into_iter().find(|path| path.exists()) {
+            Some(path) => path,
+            None => return Ok(ConfigFile::default()),
+        },
+    };
+
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
+    let config = toml::from_str(&contents)
+        .map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?;
+    Ok(config)
+}
+
+/// Parse the config file's `[regions]` tables.
+pub fn saved_regions(config: &ConfigFile) -> Result<HashMap<String, SavedRegion>, String> {
+    config.regions.iter().flatten().map(|(name, region)| {
+        let rect = region.rect.parse().map_err(|e| format!("regions.{}: {}", name, e))?;
+        Ok((name.clone(), SavedRegion { display: region.display, rect }))
+    }).collect()
+}
+
+/// Parse the config file's `[[webhooks]]` tables.
+pub fn webhooks(config: &ConfigFile) -> Result<Vec<Webhook>, String> {
+    config.webhooks.iter().flatten().enumerate()
+        .map(|(index, webhook)| Webhook::from_config(webhook).map_err(|e| format!("webhooks[{}]: {}", index, e)))
+        .collect()
+}
+
+/// Parse the config file's `[share]` table.
+pub fn share_settings(config: &ConfigFile) -> Result<ShareSettings, String> {
+    let Some(share) = &config.share else {
+        return Ok(ShareSettings::default());
+    };
+    let host = match &share.host {
+        Some(host) => ShareHost::from_str(host, true).map_err(|e| format!("Invalid host \"{}\": {}", host, e))?,
+        None => ShareHost::default(),
+    };
+    Ok(ShareSettings {
+        host,
+        url: share.url.clone(),
+        imgur_client_id: share.imgur_client_id.clone(),
+        expires_hours: share.expires_hours,
+        copy_url: share.copy_url.unwrap_or(false),
+    })
+}
+
+fn default_config_paths() -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::from("captest.toml")];
+
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME") {
+        paths.push(PathBuf::from(dir).join("captest").join("config.toml"));
+    } else if let Some(dir) = std::env::var_os("APPDATA") {
+        paths.push(PathBuf::from(dir).join("captest").join("config.toml"));
+    } else if let Some(home) = std::env::var_os("HOME") {
+        paths.push(PathBuf::from(home).join(".config").join("captest").join("config.toml"));
+    }
+
+    paths
+}
+
+/// Resolve an output filename against the configured output directory,
+/// after expanding `{run_id}`. Absolute paths and names with an explicit
+/// directory are left alone.
+pub fn resolve_output_path(filename: &str) -> String {
+    let filename = expand_run_id(filename);
+    let path = Path::new(&filename);
+    match &settings().output_dir {
+        Some(dir) if path.is_relative() && path.parent().is_none_or(|p| p.as_os_str().is_empty()) => {
+            dir.join(path).to_string_lossy().into_owned()
+        }
+        _ => filename,
+    }
+}
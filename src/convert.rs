@@ -0,0 +1,377 @@
+use std::time::SystemTime;
+
+use scap::frame::VideoFrame;
+
+use crate::memory;
+use crate::timings::{self, Stage};
+
+/// Convert any video frame delivered by scap into tightly packed RGB8.
+/// Returns `(width, height, rgb_data)`. The frame is consumed so 4-byte
+/// pixel layouts can be repacked in their own buffer instead of a new one.
+pub fn video_frame_into_rgb8(video_frame: VideoFrame) -> (u32, u32, Vec<u8>) {
+    let _timing = timings::span(Stage::Convert);
+    into_rgb8(video_frame)
+}
+
+fn into_rgb8(video_frame: VideoFrame) -> (u32, u32, Vec<u8>) {
+    match video_frame {
+        VideoFrame::BGRA(f) => bgra_into_rgb8(f),
+        VideoFrame::YUVFrame(f) => nv12_to_rgb8(&f),
+        VideoFrame::RGB(f) => (f.width as u32, f.height as u32, f.data),
+        VideoFrame::RGBx(f) => (f.width as u32, f.height as u32, packed_into_rgb8(f.data, [0, 1, 2])),
+        VideoFrame::XBGR(f) => (f.width as u32, f.height as u32, packed_into_rgb8(f.data, [3, 2, 1])),
+        VideoFrame::BGRx(f) => (f.width as u32, f.height as u32, packed_into_rgb8(f.data, [2, 1, 0])),
+        VideoFrame::BGR0(f) => (f.width as u32, f.height as u32, packed_into_rgb8(f.data, [2, 1, 0])),
+    }
+}
+
+/// Like [`video_frame_into_rgb8`], then shrink each dimension by `factor` by
+/// averaging `factor` x `factor` blocks. Both steps work inside the frame's own
+/// buffer for 4-byte layouts, so huge frames need no second full-size copy.
+pub fn video_frame_into_rgb8_downscaled(video_frame: VideoFrame, factor: u32) -> (u32, u32, Vec<u8>) {
+    let _timing = timings::span(Stage::Convert);
+    let (width, height, rgb_data) = into_rgb8(video_frame);
+    if factor <= 1 {
+        return (width, height, rgb_data);
+    }
+    downscale_rgb8_in_place(width, height, rgb_data, factor)
+}
+
+fn downscale_rgb8_in_place(width: u32, height: u32, mut rgb_data: Vec<u8>, factor: u32) -> (u32, u32, Vec<u8>) {
+    let (w, f) = (width as usize, factor as usize);
+    let (out_width, out_height) = ((width / factor).max(1) as usize, (height / factor).max(1) as usize);
+    let block = (f.min(w) * f.min(height as usize)) as u32;
+
+    // Output pixel (x, y) lands at or before the first input pixel of its
+    // block, and every later block starts after it, so nothing unread is
+    // overwritten
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let mut sum = [0u32; 3];
+            for dy in 0..f.min(height as usize) {
+                let row = (y * f + dy) * w;
+                for dx in 0..f.min(w) {
+                    let i = (row + x * f + dx) * 3;
+                    sum[0] += rgb_data[i] as u32;
+                    sum[1] += rgb_data[i + 1] as u32;
+                    sum[2] += rgb_data[i + 2] as u32;
+                }
+            }
+            let o = (y * out_width + x) * 3;
+            for c in 0..3 {
+                rgb_data[o + c] = ((sum[c] + block / 2) / block) as u8;
+            }
+        }
+    }
+    rgb_data.truncate(out_width * out_height * 3);
+    (out_width as u32, out_height as u32, rgb_data)
+}
+
+/// A rectangle of a frame, in frame pixels.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Convert a frame for streaming and recording, optionally cropping it and
+/// scaling it down to at most `max_width`. Runs on the GPU when `--gpu` is set
+/// and the build and machine support it, otherwise on the CPU.
+pub fn video_frame_to_stream_rgb8(video_frame: VideoFrame, crop: Option<Crop>, max_width: Option<u32>) -> Result<(u32, u32, Vec<u8>), String> {
+    let _timing = timings::span(Stage::Convert);
+    let (width, height) = video_frame_size(&video_frame);
+    let crop = crop.unwrap_or(Crop { x: 0, y: 0, width, height });
+    check_crop(crop, width, height)?;
+    let (out_width, out_height) = match max_width {
+        Some(max_width) if max_width > 0 && max_width < crop.width => {
+            let out_height = (crop.height as u64 * max_width as u64 / crop.width as u64).max(1) as u32;
+            (max_width, out_height)
+        }
+        _ => (crop.width, crop.height),
+    };
+    let frame_bytes = width as u64 * height as u64 * 4;
+    let factor = memory::downscale_factor(out_width, out_height, frame_bytes, 3 + memory::ENCODE_BYTES_PER_PIXEL, "Converting")
+        .map_err(|e| e.to_string())?;
+    let (out_width, out_height) = ((out_width / factor).max(1), (out_height / factor).max(1));
+
+    #[cfg(feature = "gpu")]
+    if crate::config::settings().gpu
+        && let Some(converter) = crate::gpu::converter()
+    {
+        let source = match &video_frame {
+            VideoFrame::BGRA(f) => Some(crate::gpu::GpuSource::Bgra { data: &f.data, stride: width * 4 }),
+            VideoFrame::YUVFrame(f) => Some(crate::gpu::GpuSource::Nv12 {
+                luma: &f.luminance_bytes,
+                luma_stride: f.luminance_stride.max(0) as u32,
+                chroma: &f.chrominance_bytes,
+                chroma_stride: f.chrominance_stride.max(0) as u32,
+            }),
+            // Other layouts are rare and cheap to repack on the CPU
+            _ => None,
+        };
+        if let Some(source) = source {
+            match converter.convert(source, crop, out_width, out_height) {
+                Ok(rgb_data) => return Ok((out_width, out_height, rgb_data)),
+                Err(e) => status!("GPU conversion failed, using the CPU: {}", e),
+            }
+        }
+    }
+
+    let (_, _, rgb_data) = into_rgb8(video_frame);
+    Ok((out_width, out_height, crop_and_scale_rgb8(width, rgb_data, crop, out_width, out_height)))
+}
+
+/// Cut `crop` out of an RGB8 frame.
+pub fn crop_rgb8(width: u32, height: u32, rgb_data: Vec<u8>, crop: Crop) -> Result<Vec<u8>, String> {
+    check_crop(crop, width, height)?;
+    Ok(crop_and_scale_rgb8(width, rgb_data, crop, crop.width, crop.height))
+}
+
+/// Cut `crop` out of a borrowed RGB8 frame and scale it to `out_width` x
+/// `out_height`.
+pub fn crop_scaled_rgb8(width: u32, height: u32, rgb_data: &[u8], crop: Crop, out_width: u32, out_height: u32) -> Result<Vec<u8>, String> {
+    check_crop(crop, width, height)?;
+    let row_len = crop.width as usize * 3;
+    let mut cropped = Vec::with_capacity(row_len * crop.height as usize);
+    for y in crop.y..crop.y + crop.height {
+        let start = (y as usize * width as usize + crop.x as usize) * 3;
+        cropped.extend_from_slice(&rgb_data[start..start + row_len]);
+    }
+    let whole = Crop { x: 0, y: 0, ..crop };
+    Ok(crop_and_scale_rgb8(crop.width, cropped, whole, out_width, out_height))
+}
+
+fn check_crop(crop: Crop, width: u32, height: u32) -> Result<(), String> {
+    // Edges are summed in u64, so a crop reaching past u32::MAX can't wrap
+    // around into the frame
+    let (right, bottom) = (crop.x as u64 + crop.width as u64, crop.y as u64 + crop.height as u64);
+    if crop.width == 0 || crop.height == 0 || right > width as u64 || bottom > height as u64 {
+        return Err(format!("Crop {}x{}+{}+{} is outside the {}x{} frame", crop.width, crop.height, crop.x, crop.y, width, height));
+    }
+    Ok(())
+}
+
+fn crop_and_scale_rgb8(width: u32, rgb_data: Vec<u8>, crop: Crop, out_width: u32, out_height: u32) -> Vec<u8> {
+    let cropped = if crop.x == 0 && crop.width == width {
+        let start = crop.y as usize * width as usize * 3;
+        let mut rgb_data = rgb_data;
+        rgb_data.drain(..start);
+        rgb_data.truncate(crop.width as usize * crop.height as usize * 3);
+        rgb_data
+    } else {
+        let row_len = crop.width as usize * 3;
+        let mut cropped = Vec::with_capacity(row_len * crop.height as usize);
+        for y in crop.y..crop.y + crop.height {
+            let start = (y as usize * width as usize + crop.x as usize) * 3;
+            cropped.extend_from_slice(&rgb_data[start..start + row_len]);
+        }
+        cropped
+    };
+    if (out_width, out_height) == (crop.width, crop.height) {
+        return cropped;
+    }
+
+    let image = image::RgbImage::from_raw(crop.width, crop.height, cropped).expect("cropped buffer matches its size");
+    image::imageops::resize(&image, out_width, out_height, image::imageops::FilterType::Triangle).into_raw()
+}
+
+/// Fit an RGB8 image into `out_width` x `out_height` keeping its aspect
+/// ratio, centered between black bars.
+pub fn letterbox_rgb8(width: u32, height: u32, rgb_data: Vec<u8>, out_width: u32, out_height: u32) -> Vec<u8> {
+    if (width, height) == (out_width, out_height) {
+        return rgb_data;
+    }
+    let scale = f64::min(out_width as f64 / width as f64, out_height as f64 / height as f64);
+    let fit_width = ((width as f64 * scale).round() as u32).clamp(1, out_width);
+    let fit_height = ((height as f64 * scale).round() as u32).clamp(1, out_height);
+
+    let image = image::RgbImage::from_raw(width, height, rgb_data).expect("frame buffer matches its size");
+    let fitted = image::imageops::resize(&image, fit_width, fit_height, image::imageops::FilterType::Triangle);
+    let mut canvas = image::RgbImage::new(out_width, out_height);
+    image::imageops::replace(&mut canvas, &fitted, ((out_width - fit_width) / 2) as i64, ((out_height - fit_height) / 2) as i64);
+    canvas.into_raw()
+}
+
+/// Width and height of a frame in pixels.
+pub fn video_frame_size(video_frame: &VideoFrame) -> (u32, u32) {
+    let (width, height) = match video_frame {
+        VideoFrame::BGRA(f) => (f.width, f.height),
+        VideoFrame::YUVFrame(f) => (f.width, f.height),
+        VideoFrame::RGB(f) => (f.width, f.height),
+        VideoFrame::RGBx(f) => (f.width, f.height),
+        VideoFrame::XBGR(f) => (f.width, f.height),
+        VideoFrame::BGRx(f) => (f.width, f.height),
+        VideoFrame::BGR0(f) => (f.width, f.height),
+    };
+    (width.max(0) as u32, height.max(0) as u32)
+}
+
+/// Presentation timestamp the backend attached to the frame.
+pub fn video_frame_display_time(video_frame: &VideoFrame) -> SystemTime {
+    match video_frame {
+        VideoFrame::BGRA(f) => f.display_time,
+        VideoFrame::YUVFrame(f) => f.display_time,
+        VideoFrame::RGB(f) => f.display_time,
+        VideoFrame::RGBx(f) => f.display_time,
+        VideoFrame::XBGR(f) => f.display_time,
+        VideoFrame::BGRx(f) => f.display_time,
+        VideoFrame::BGR0(f) => f.display_time,
+    }
+}
+
+pub fn bgra_into_rgb8(bgra_frame: scap::frame::BGRAFrame) -> (u32, u32, Vec<u8>) {
+    (bgra_frame.width as u32, bgra_frame.height as u32, packed_into_rgb8(bgra_frame.data, [2, 1, 0]))
+}
+
+fn packed_into_rgb8(mut data: Vec<u8>, rgb_offsets: [usize; 3]) -> Vec<u8> {
+    // Move the R, G and B bytes of each 4-byte pixel to the front of the
+    // buffer. Pixel i is written to 3i..3i+3, which never passes the bytes of
+    // pixels not yet read (4i onwards), so no second buffer is needed.
+    let pixels = data.len() / 4;
+    for i in 0..pixels {
+        let pixel = i * 4;
+        let rgb = [data[pixel + rgb_offsets[0]], data[pixel + rgb_offsets[1]], data[pixel + rgb_offsets[2]]];
+        data[i * 3..i * 3 + 3].copy_from_slice(&rgb);
+    }
+    data.truncate(pixels * 3);
+    data
+}
+
+pub fn nv12_to_rgb8(yuv_frame: &scap::frame::YUVFrame) -> (u32, u32, Vec<u8>) {
+    let width = yuv_frame.width.max(0) as usize;
+    let height = yuv_frame.height.max(0) as usize;
+    let y_stride = yuv_frame.luminance_stride.max(0) as usize;
+    let uv_stride = yuv_frame.chrominance_stride.max(0) as usize;
+    let y_plane = &yuv_frame.luminance_bytes;
+    let uv_plane = &yuv_frame.chrominance_bytes;
+
+    let mut rgb_data = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        for x in 0..width {
+            let luma = y_plane.get(y * y_stride + x).copied().unwrap_or(16);
+            // Chroma is subsampled 2x2 and stored as interleaved U, V pairs
+            let uv_index = (y / 2) * uv_stride + (x / 2) * 2;
+            let u = uv_plane.get(uv_index).copied().unwrap_or(128);
+            let v = uv_plane.get(uv_index + 1).copied().unwrap_or(128);
+
+            // BT.601 video range to full range RGB, fixed point
+            let c = luma as i32 - 16;
+            let d = u as i32 - 128;
+            let e = v as i32 - 128;
+            rgb_data.push(((298 * c + 409 * e + 128) >> 8).clamp(0, 255) as u8);
+            rgb_data.push(((298 * c - 100 * d - 208 * e + 128) >> 8).clamp(0, 255) as u8);
+            rgb_data.push(((298 * c + 516 * d + 128) >> 8).clamp(0, 255) as u8);
+        }
+    }
+
+    (width as u32, height as u32, rgb_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame whose pixel at (x, y) is [x, y, x + y].
+    fn gradient(width: u32, height: u32) -> Vec<u8> {
+        (0..height).flat_map(|y| (0..width).flat_map(move |x| [x as u8, y as u8, (x + y) as u8])).collect()
+    }
+
+    /// Pixels of varied values, so misplaced bytes show up.
+    fn noise(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 37 % 251) as u8).collect()
+    }
+
+    /// The copying repack: a new buffer with the picked bytes of each pixel.
+    fn packed_copy(data: &[u8], rgb_offsets: [usize; 3]) -> Vec<u8> {
+        data.chunks_exact(4).flat_map(|pixel| rgb_offsets.map(|offset| pixel[offset])).collect()
+    }
+
+    /// The copying downscale: block averages written to a new buffer.
+    fn downscale_copy(width: u32, height: u32, rgb_data: &[u8], factor: u32) -> (u32, u32, Vec<u8>) {
+        let (w, h, f) = (width as usize, height as usize, factor as usize);
+        let (out_width, out_height) = ((w / f).max(1), (h / f).max(1));
+        let (block_width, block_height) = (f.min(w), f.min(h));
+        let mut out = Vec::with_capacity(out_width * out_height * 3);
+        for y in 0..out_height {
+            for x in 0..out_width {
+                for c in 0..3 {
+                    let sum: usize = (0..block_height)
+                        .flat_map(|dy| (0..block_width).map(move |dx| ((y * f + dy) * w + x * f + dx) * 3 + c))
+                        .map(|i| rgb_data[i] as usize)
+                        .sum();
+                    let block = block_width * block_height;
+                    out.push(((sum + block / 2) / block) as u8);
+                }
+            }
+        }
+        (out_width as u32, out_height as u32, out)
+    }
+
+    #[test]
+    fn repacks_4_byte_layouts_like_a_copy() {
+        let data = noise(7 * 5 * 4);
+        // BGRA and BGRx, RGBx, XBGR
+        for offsets in [[2, 1, 0], [0, 1, 2], [3, 2, 1]] {
+            assert_eq!(packed_into_rgb8(data.clone(), offsets), packed_copy(&data, offsets), "{:?}", offsets);
+        }
+    }
+
+    #[test]
+    fn repacks_a_bgra_pixel() {
+        assert_eq!(packed_into_rgb8(vec![10, 20, 30, 255, 40, 50, 60, 0], [2, 1, 0]), [30, 20, 10, 60, 50, 40]);
+    }
+
+    #[test]
+    fn downscales_in_place_like_a_copy() {
+        for (width, height) in [(8, 6), (7, 5), (5, 7), (2, 1), (1, 1)] {
+            let rgb_data = noise((width * height * 3) as usize);
+            for factor in [1, 2, 3] {
+                assert_eq!(
+                    downscale_rgb8_in_place(width, height, rgb_data.clone(), factor),
+                    downscale_copy(width, height, &rgb_data, factor),
+                    "{}x{} by {}", width, height, factor
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn downscales_by_averaging_blocks() {
+        let rgb_data = [0, 0, 0, 10, 20, 30, 20, 40, 60, 30, 60, 90].to_vec();
+        assert_eq!(downscale_rgb8_in_place(2, 2, rgb_data, 2), (1, 1, vec![15, 30, 45]));
+    }
+
+    #[test]
+    fn crops_inside_the_frame() {
+        let cropped = crop_rgb8(4, 3, gradient(4, 3), Crop { x: 1, y: 1, width: 2, height: 2 }).unwrap();
+        assert_eq!(cropped, [1, 1, 2, 2, 1, 3, 1, 2, 3, 2, 2, 4]);
+    }
+
+    #[test]
+    fn rejects_crops_outside_the_frame() {
+        for crop in [
+            Crop { x: 3, y: 0, width: 2, height: 1 },
+            Crop { x: 0, y: 2, width: 1, height: 2 },
+            Crop { x: 0, y: 0, width: 0, height: 1 },
+            Crop { x: 4, y: 3, width: 1, height: 1 },
+        ] {
+            assert!(crop_rgb8(4, 3, gradient(4, 3), crop).is_err(), "{:?}", crop);
+        }
+    }
+
+    #[test]
+    fn rejects_crops_whose_edges_overflow() {
+        let crops = [
+            Crop { x: u32::MAX, y: 0, width: 2, height: 2 },
+            Crop { x: 0, y: u32::MAX, width: 2, height: 2 },
+            Crop { x: 1, y: 1, width: u32::MAX, height: u32::MAX },
+        ];
+        for crop in crops {
+            assert!(check_crop(crop, 4, 3).is_err(), "{:?}", crop);
+            assert!(crop_rgb8(4, 3, gradient(4, 3), crop).is_err(), "{:?}", crop);
+            assert!(crop_scaled_rgb8(4, 3, &gradient(4, 3), crop, 2, 2).is_err(), "{:?}", crop);
+        }
+    }
+}
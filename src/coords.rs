@@ -0,0 +1,295 @@
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use scap::Target;
+use scap::capturer::{Options, Resolution};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::capture::{self, CaptureSession};
+use crate::config;
+use crate::error::{self, CaptestError, ErrorKind};
+use crate::platforms;
+use crate::session_pool::{self, TargetKey};
+
+/// Coordinate spaces a point can be given in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Space {
+    /// Desktop coordinates shared by all displays (points on macOS, pixels on Windows)
+    Global,
+    /// Relative to the top-left corner of the target's display
+    Display,
+    /// Relative to the top-left corner of the target window
+    Window,
+    /// Pixels of a captured frame of the target
+    Capture,
+}
+
+impl Space {
+    const ALL: [Space; 4] = [Space::Global, Space::Display, Space::Window, Space::Capture];
+
+    fn name(self) -> &'static str {
+        match self {
+            Space::Global => "global",
+            Space::Display => "display",
+            Space::Window => "window",
+            Space::Capture => "capture",
+        }
+    }
+}
+
+/// A point, parsed from `X,Y`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
+}
+
+impl FromStr for Point {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid point \"{}\", expected X,Y", value);
+        let (x, y) = value.split_once(',').ok_or_else(invalid)?;
+        Ok(Self {
+            x: x.trim().parse().map_err(|_| invalid())?,
+            y: y.trim().parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// A rectangle in global desktop coordinates.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub fn from_bounds((x, y, width, height): (i32, i32, i32, i32)) -> Self {
+        Self { x, y, width, height }
+    }
+
+    fn contains(&self, x: i32, y: i32) -> bool {
+        x >= self.x && y >= self.y && x < self.x + self.width && y < self.y + self.height
+    }
+}
+
+/// Where a capture target is on the desktop and how large its frames are,
+/// which is all the conversions between spaces need.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+pub struct Geometry {
+    /// The display showing the target (for windows, the one with the window's center)
+    pub display: Rect,
+    /// The window, for window targets
+    pub window: Option<Rect>,
+    /// Size of a captured frame in pixels, after display scaling and any resize
+    pub frame_width: u32,
+    pub frame_height: u32,
+}
+
+impl Geometry {
+    /// The area a captured frame shows.
+    fn captured(&self) -> Rect {
+        self.window.unwrap_or(self.display)
+    }
+
+    /// Capture pixels per global unit, horizontally and vertically.
+    fn scale(&self) -> (f64, f64) {
+        let captured = self.captured();
+        (self.frame_width as f64 / captured.width.max(1) as f64, self.frame_height as f64 / captured.height.max(1) as f64)
+    }
+}
+
+/// Convert `point` from global desktop coordinates to `space`.
+pub fn from_global(point: Point, space: Space, geometry: &Geometry) -> Result<Point, String> {
+    let origin = origin(space, geometry)?;
+    let (x, y) = (point.x - origin.x as f64, point.y - origin.y as f64);
+    if space != Space::Capture {
+        return Ok(Point { x, y });
+    }
+    let (scale_x, scale_y) = geometry.scale();
+    Ok(Point { x: x * scale_x, y: y * scale_y })
+}
+
+/// Convert `point` from `space` to global desktop coordinates.
+pub fn to_global(point: Point, space: Space, geometry: &Geometry) -> Result<Point, String> {
+    let origin = origin(space, geometry)?;
+    let (x, y) = match space {
+        Space::Capture => {
+            let (scale_x, scale_y) = geometry.scale();
+            (point.x / scale_x, point.y / scale_y)
+        }
+        _ => (point.x, point.y),
+    };
+    Ok(Point { x: x + origin.x as f64, y: y + origin.y as f64 })
+}
+
+/// Convert `point` between two spaces of the same target.
+pub fn convert(point: Point, from: Space, to: Space, geometry: &Geometry) -> Result<Point, String> {
+    from_global(to_global(point, from, geometry)?, to, geometry)
+}
+
+/// Top-left corner of `space` in global coordinates.
+fn origin(space: Space, geometry: &Geometry) -> Result<Rect, String> {
+    match space {
+        Space::Global => Ok(Rect { x: 0, y: 0, width: 0, height: 0 }),
+        Space::Display => Ok(geometry.display),
+        Space::Window => geometry.window.ok_or_else(|| "Window coordinates need a --window target".to_string()),
+        Space::Capture => Ok(geometry.captured()),
+    }
+}
+
+/// Look up the geometry of `key`. The frame size is measured by starting a
+/// capturer, so it matches what `capture` (or with `streamed`, what `record`,
+/// `watch` and `serve`) produce: window captures by `capture-window` are
+/// resized to 1080p, the others come at the display's pixel density.
+pub async fn geometry(key: TargetKey, streamed: bool) -> Result<Geometry, Box<dyn std::error::Error>> {
+    capture::ensure_capture_allowed()?;
+    let target = session_pool::resolve_target(key)
+        .map_err(|e| CaptestError::new(ErrorKind::TargetNotFound, e))?;
+    let unavailable = || CaptestError::new(ErrorKind::Unsupported, "Display and window geometry aren't available on this platform");
+
+    let displays: Vec<Rect> = capture::all_targets()
+        .iter()
+        .filter_map(|target| match target {
+            Target::Display(display) => platforms::display_bounds(display.id).map(Rect::from_bounds),
+            _ => None,
+        })
+        .collect();
+    let (display, window) = match &target {
+        Target::Display(display) => (platforms::display_bounds(display.id).map(Rect::from_bounds).ok_or_else(unavailable)?, None),
+        Target::Window(window) => {
+            let window = platforms::window_bounds(window.id).map(Rect::from_bounds).ok_or_else(unavailable)?;
+            let (center_x, center_y) = (window.x + window.width / 2, window.y + window.height / 2);
+            let display = displays.iter().find(|display| display.contains(center_x, center_y)).or(displays.first()).copied().ok_or_else(unavailable)?;
+            (display, Some(window))
+        }
+    };
+
+    let options = Options {
+        fps: 1,
+        show_highlight: config::settings().show_highlight,
+        output_type: scap::frame::FrameType::BGRAFrame,
+        output_resolution: match (&target, streamed) {
+            (Target::Window(_), false) => Resolution::_1080p,
+            _ => Resolution::Captured,
+        },
+        target: Some(target),
+        ..Default::default()
+    };
+    // Building a capturer blocks, keep it off the runtime workers
+    let [frame_width, frame_height] = tokio::task::spawn_blocking(move || {
+        CaptureSession::start(options)
+            .map(|mut session| session.get_output_frame_size())
+            .map_err(|e| CaptestError::new(error::error_kind(&*e), e.to_string()))
+    }).await??;
+
+    Ok(Geometry { display, window, frame_width, frame_height })
+}
+
+/// The `coords` command: convert points given in `from` to every space, or
+/// only to `to`.
+pub async fn coords(key: TargetKey, points: &[Point], from: Space, to: Option<Space>, streamed: bool) -> Result<Value, Box<dyn std::error::Error>> {
+    let geometry = geometry(key, streamed).await?;
+    status!(
+        "{}: display {}x{} at {},{}; frames are {}x{} pixels",
+        key, geometry.display.width, geometry.display.height, geometry.display.x, geometry.display.y, geometry.frame_width, geometry.frame_height
+    );
+
+    let spaces: Vec<Space> = match to {
+        Some(space) => vec![space],
+        // Window coordinates only exist for window targets
+        None => Space::ALL.into_iter().filter(|&space| space != Space::Window || geometry.window.is_some()).collect(),
+    };
+    let mut converted = Vec::new();
+    for &point in points {
+        let mut row = serde_json::Map::new();
+        let mut text = Vec::new();
+        for &space in &spaces {
+            let Point { x, y } = convert(point, from, space, &geometry)?;
+            let (x, y) = (round(x), round(y));
+            text.push(format!("{} {},{}", space.name(), x, y));
+            row.insert(space.name().to_string(), json!({ "x": x, "y": y }));
+        }
+        result!("{}", text.join("  "));
+        converted.push(Value::Object(row));
+    }
+
+    Ok(json!({
+        "status": "ok",
+        "target": key.to_string(),
+        "geometry": geometry,
+        "from": from,
+        "points": converted,
+    }))
+}
+
+/// Two decimals are plenty for pixels and keep the output readable.
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 1200x800 window on a second display to the right of the first, at
+    /// 2x pixel density.
+    fn window_geometry() -> Geometry {
+        Geometry {
+            display: Rect { x: 1920, y: 0, width: 1440, height: 900 },
+            window: Some(Rect { x: 2000, y: 50, width: 1200, height: 800 }),
+            frame_width: 2400,
+            frame_height: 1600,
+        }
+    }
+
+    fn assert_close(actual: Point, expected: Point) {
+        assert!((actual.x - expected.x).abs() < 1e-9 && (actual.y - expected.y).abs() < 1e-9, "{:?} != {:?}", actual, expected);
+    }
+
+    #[test]
+    fn converts_a_global_point_to_every_space() {
+        let geometry = window_geometry();
+        let global = Point { x: 2100.0, y: 250.0 };
+        assert_close(from_global(global, Space::Global, &geometry).unwrap(), global);
+        assert_close(from_global(global, Space::Display, &geometry).unwrap(), Point { x: 180.0, y: 250.0 });
+        assert_close(from_global(global, Space::Window, &geometry).unwrap(), Point { x: 100.0, y: 200.0 });
+        assert_close(from_global(global, Space::Capture, &geometry).unwrap(), Point { x: 200.0, y: 400.0 });
+    }
+
+    #[test]
+    fn round_trips_through_every_space() {
+        let geometry = window_geometry();
+        let start = Point { x: 123.25, y: 456.5 };
+        for from in Space::ALL {
+            for to in Space::ALL {
+                let there = convert(start, from, to, &geometry).unwrap();
+                assert_close(convert(there, to, from, &geometry).unwrap(), start);
+            }
+        }
+    }
+
+    #[test]
+    fn display_captures_have_no_window_space() {
+        let geometry = Geometry {
+            display: Rect { x: 0, y: 0, width: 1920, height: 1080 },
+            window: None,
+            frame_width: 3840,
+            frame_height: 2160,
+        };
+        assert_close(convert(Point { x: 960.0, y: 540.0 }, Space::Display, Space::Capture, &geometry).unwrap(), Point { x: 1920.0, y: 1080.0 });
+        assert!(convert(Point { x: 1.0, y: 1.0 }, Space::Window, Space::Global, &geometry).is_err());
+    }
+
+    #[test]
+    fn parses_points() {
+        assert_eq!("12, 34.5".parse::<Point>().unwrap(), Point { x: 12.0, y: 34.5 });
+        assert!("12".parse::<Point>().is_err());
+        assert!("a,b".parse::<Point>().is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use scap::Target;
+use scap::capturer::Options;
+
+use crate::platforms;
+
+/// How often the pointer is sampled. Presses shorter than this can be missed.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// How long a click ring stays visible while it expands and fades.
+const RING_DURATION: Duration = Duration::from_millis(600);
+/// Ring radius at the click and when it disappears, and its thickness, in
+/// screen points.
+const RING_START_RADIUS: f64 = 8.0;
+const RING_END_RADIUS: f64 = 32.0;
+const RING_THICKNESS: f64 = 3.0;
+/// Radius of the dot shown while a button is held.
+const HOLD_RADIUS: f64 = 12.0;
+const HIGHLIGHT_COLOR: [u8; 3] = [255, 200, 0];
+
+#[derive(Default)]
+struct PointerState {
+    position: Option<(i32, i32)>,
+    pressed: bool,
+    /// Recent presses in screen coordinates, oldest first
+    clicks: VecDeque<((i32, i32), Instant)>,
+}
+
+/// Samples the pointer on a background thread and draws click highlights
+/// onto recorded frames: an expanding, fading ring where each click
+/// happened and a dot while a button is held. Polling the button state
+/// stands in for a mouse hook, so no input monitoring permission is needed.
+pub struct ClickHighlighter {
+    state: Arc<Mutex<PointerState>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl ClickHighlighter {
+    /// Start sampling, or None where the pointer isn't available (Linux).
+    pub fn start() -> Option<Self> {
+        platforms::pointer()?;
+        let state = Arc::new(Mutex::new(PointerState::default()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (thread_state, thread_stop) = (state.clone(), stop.clone());
+        std::thread::spawn(move || {
+            while !thread_stop.load(Ordering::Relaxed) {
+                if let Some((x, y, pressed)) = platforms::pointer() {
+                    let mut state = thread_state.lock().unwrap();
+                    if pressed && !state.pressed {
+                        state.clicks.push_back(((x, y), Instant::now()));
+                    }
+                    while state.clicks.front().is_some_and(|(_, at)| at.elapsed() > RING_DURATION) {
+                        state.clicks.pop_front();
+                    }
+                    state.position = Some((x, y));
+                    state.pressed = pressed;
+                }
+                std::thread::sleep(POLL_INTERVAL);
+            }
+        });
+        Some(Self { state, stop })
+    }
+
+    /// Draw the current highlights onto an RGB8 frame of the capture
+    /// configured by `options`.
+    pub fn draw(&self, options: &Options, width: u32, height: u32, rgb_data: &mut [u8]) {
+        let Some((origin_x, origin_y, bounds_width, bounds_height)) = capture_bounds(options) else {
+            return;
+        };
+        if bounds_width <= 0 || bounds_height <= 0 {
+            return;
+        }
+        // Frames may be scaled relative to screen coordinates
+        let scale = width as f64 / bounds_width as f64;
+        let to_frame = |(x, y): (i32, i32)| ((x - origin_x) as f64 * scale, (y - origin_y) as f64 * height as f64 / bounds_height as f64);
+
+        let state = self.state.lock().unwrap();
+        for (position, at) in &state.clicks {
+            let progress = (at.elapsed().as_secs_f64() / RING_DURATION.as_secs_f64()).min(1.0);
+            let radius = (RING_START_RADIUS + (RING_END_RADIUS - RING_START_RADIUS) * progress) * scale;
+            draw_circle(rgb_data, width, height, to_frame(*position), radius, Some(RING_THICKNESS * scale), 1.0 - progress);
+        }
+        if state.pressed
+            && let Some(position) = state.position
+        {
+            draw_circle(rgb_data, width, height, to_frame(position), HOLD_RADIUS * scale, None, 0.5);
+        }
+    }
+}
+
+impl Drop for ClickHighlighter {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Screen area (x, y, width, height) a capture shows.
+fn capture_bounds(options: &Options) -> Option<(i32, i32, i32, i32)> {
+    match &options.target {
+        Some(Target::Display(display)) => platforms::display_bounds(display.id),
+        Some(Target::Window(window)) => platforms::window_bounds(window.id),
+        None => None,
+    }
+}
+
+/// Blend a disc, or a ring of `thickness`, into an RGB8 frame.
+fn draw_circle(rgb_data: &mut [u8], width: u32, height: u32, (center_x, center_y): (f64, f64), radius: f64, thickness: Option<f64>, opacity: f64) {
+    let outer = radius + thickness.unwrap_or(0.0) / 2.0;
+    let left = (center_x - outer).floor().max(0.0) as u32;
+    let top = (center_y - outer).floor().max(0.0) as u32;
+    let right = ((center_x + outer).ceil().max(0.0) as u32).min(width);
+    let bottom = ((center_y + outer).ceil().max(0.0) as u32).min(height);
+
+    for y in top..bottom {
+        for x in left..right {
+            let distance = ((x as f64 + 0.5 - center_x).powi(2) + (y as f64 + 0.5 - center_y).powi(2)).sqrt();
+            let inside = match thickness {
+                Some(thickness) => (distance - radius).abs() <= thickness / 2.0,
+                None => distance <= radius,
+            };
+            if !inside {
+                continue;
+            }
+            let offset = ((y * width + x) * 3) as usize;
+            for (channel, color) in rgb_data[offset..offset + 3].iter_mut().zip(HIGHLIGHT_COLOR) {
+                *channel = (*channel as f64 * (1.0 - opacity) + color as f64 * opacity).round() as u8;
+            }
+        }
+    }
+}
@@ -0,0 +1,553 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use image::{Rgb, RgbImage};
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::change::{self, ChangedRegion, IgnoreRegion};
+use crate::error::ErrorKind;
+use crate::session_pool::TargetKey;
+use crate::{capture, config, DiffArgs};
+
+/// Color of changed pixels in the diff image.
+const CHANGED_COLOR: Rgb<u8> = Rgb([255, 0, 0]);
+/// Color of differences explained by anti-aliasing in the diff image.
+const ANTIALIASED_COLOR: Rgb<u8> = Rgb([255, 200, 0]);
+/// Side of the square windows SSIM is computed over, and the step between them.
+const SSIM_WINDOW: u32 = 8;
+const SSIM_STEP: u32 = 4;
+
+/// Score that decides whether two images count as the same.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    /// Percentage of changed pixels; passes at or below the value
+    Pixels,
+    /// Structural similarity from 0 to 1; passes at or above the value
+    Ssim,
+    /// Mean CIEDE2000 color difference; passes at or below the value
+    DeltaE,
+}
+
+/// A metric and the value it must meet, parsed from `METRIC:VALUE`, e.g. `ssim:0.98`.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct ThresholdMetric {
+    pub metric: Metric,
+    pub value: f64,
+}
+
+impl FromStr for ThresholdMetric {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid threshold \"{}\", expected pixels:PERCENT, ssim:MIN or delta-e:MAX", value);
+        let (metric, limit) = value.split_once(':').ok_or_else(invalid)?;
+        let metric = match metric.trim().to_lowercase().as_str() {
+            "pixels" => Metric::Pixels,
+            "ssim" => Metric::Ssim,
+            "delta-e" | "delta_e" | "deltae" => Metric::DeltaE,
+            _ => return Err(invalid()),
+        };
+        let value = limit.trim().parse().map_err(|_| invalid())?;
+        Ok(Self { metric, value })
+    }
+}
+
+impl fmt::Display for ThresholdMetric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.metric {
+            Metric::Pixels => write!(f, "pixels <= {}%", self.value),
+            Metric::Ssim => write!(f, "ssim >= {}", self.value),
+            Metric::DeltaE => write!(f, "delta-e <= {}", self.value),
+        }
+    }
+}
+
+/// Similarity scores of a comparison; ignored regions count as identical.
+#[derive(Serialize)]
+pub struct Scores {
+    /// Percentage of compared pixels that changed
+    pub pixels: f64,
+    /// Mean structural similarity of luma, 1.0 for identical images
+    pub ssim: f64,
+    /// Mean and largest CIEDE2000 difference per pixel; around 2.3 is just noticeable
+    pub delta_e: f64,
+    pub delta_e_max: f64,
+}
+
+impl Scores {
+    fn passes(&self, threshold: ThresholdMetric) -> bool {
+        match threshold.metric {
+            Metric::Pixels => self.pixels <= threshold.value,
+            Metric::Ssim => self.ssim >= threshold.value,
+            Metric::DeltaE => self.delta_e <= threshold.value,
+        }
+    }
+}
+
+/// Outcome of comparing two images.
+#[derive(Serialize)]
+pub struct DiffReport {
+    /// "same" or "different" (the threshold metric isn't met)
+    pub status: String,
+    pub baseline: String,
+    pub current: String,
+    pub width: u32,
+    pub height: u32,
+    /// Pixels compared, excluding ignored regions
+    pub pixels: u64,
+    pub ignored_pixels: u64,
+    pub changed_pixels: u64,
+    /// Differing pixels that look like anti-aliasing; not counted as changed
+    /// unless `--count-antialiasing` is given
+    pub antialiased_pixels: u64,
+    /// Changed pixels as a percentage of compared pixels
+    pub difference: f64,
+    pub scores: Scores,
+    /// The metric that decided the status
+    pub threshold: ThresholdMetric,
+    /// Bounding box of all changed pixels
+    pub region: Option<ChangedRegion>,
+    pub ignore_regions: Vec<IgnoreRegion>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff_image: Option<String>,
+    /// Set when the difference exceeds the threshold, used for the exit code
+    #[serde(skip)]
+    pub failure: Option<ErrorKind>,
+}
+
+/// Pixel counts of a comparison, with the diff image.
+struct Comparison {
+    pixels: u64,
+    ignored_pixels: u64,
+    changed_pixels: u64,
+    antialiased_pixels: u64,
+    region: Option<ChangedRegion>,
+    /// Sum and maximum of per-pixel CIEDE2000 differences
+    delta_e_sum: f64,
+    delta_e_max: f64,
+    image: RgbImage,
+}
+
+/// Compare two image files.
+pub fn diff_files(baseline: &Path, current: &Path, args: &DiffArgs) -> Result<DiffReport, Box<dyn std::error::Error>> {
+    let baseline_image = open_rgb8(baseline)?;
+    let current_image = open_rgb8(current)?;
+    report(baseline, &current.display().to_string(), &baseline_image, &current_image, args)
+}
+
+/// Compare a live capture of `key` against a baseline image.
+pub async fn verify(key: TargetKey, baseline: &Path, args: &DiffArgs, cancel: &CancellationToken) -> Result<DiffReport, Box<dyn std::error::Error>> {
+    let baseline_image = open_rgb8(baseline)?;
+    let (width, height, rgb_data) = capture::grab_rgb8(key, cancel).await?;
+    let current_image = RgbImage::from_raw(width, height, rgb_data).ok_or("Captured frame has an unexpected size")?;
+    report(baseline, &key.to_string(), &baseline_image, &current_image, args)
+}
+
+pub fn open_rgb8(path: &Path) -> Result<RgbImage, Box<dyn std::error::Error>> {
+    Ok(image::open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?
+        .to_rgb8())
+}
+
+fn report(baseline: &Path, current: &str, baseline_image: &RgbImage, current_image: &RgbImage, args: &DiffArgs) -> Result<DiffReport, Box<dyn std::error::Error>> {
+    let diff_output = args.diff_output.as_deref().map(config::resolve_output_path);
+    let report = evaluate(baseline, current, baseline_image, current_image, args, diff_output)?;
+    if let Some(filename) = &report.diff_image {
+        status!("Saved {}", filename);
+    }
+    if report.antialiased_pixels > 0 {
+        status!(
+            "{} differing pixels look like anti-aliasing{}",
+            report.antialiased_pixels,
+            if args.count_antialiasing { "" } else { " and were not counted" }
+        );
+    }
+    status!(
+        "{} of {} pixels differ ({:.3}%), SSIM {:.4}, mean delta E {:.2} (max {:.1}); passing needs {}",
+        report.changed_pixels, report.pixels, report.difference, report.scores.ssim, report.scores.delta_e, report.scores.delta_e_max, report.threshold
+    );
+    result!("{}", report.status);
+    Ok(report)
+}
+
+/// Compare two images of the same size without printing anything, saving
+/// the diff image to `diff_output` when given.
+pub fn evaluate(
+    baseline: &Path,
+    current: &str,
+    baseline_image: &RgbImage,
+    current_image: &RgbImage,
+    args: &DiffArgs,
+    diff_output: Option<String>,
+) -> Result<DiffReport, Box<dyn std::error::Error>> {
+    if baseline_image.dimensions() != current_image.dimensions() {
+        let (bw, bh) = baseline_image.dimensions();
+        let (cw, ch) = current_image.dimensions();
+        return Err(format!("Image sizes differ: baseline is {}x{}, current is {}x{}", bw, bh, cw, ch).into());
+    }
+
+    let ignore = [config::settings().ignore_regions.clone(), change::resolve_regions(&args.ignore_region)?].concat();
+    let comparison = compare(baseline_image, current_image, args, &ignore);
+    let difference = comparison.changed_pixels as f64 * 100.0 / comparison.pixels.max(1) as f64;
+    let scores = Scores {
+        pixels: difference,
+        ssim: ssim(baseline_image, current_image, &ignore),
+        delta_e: comparison.delta_e_sum / comparison.pixels.max(1) as f64,
+        delta_e_max: comparison.delta_e_max,
+    };
+    let threshold = args.threshold_metric.unwrap_or(ThresholdMetric { metric: Metric::Pixels, value: args.threshold });
+    let different = !scores.passes(threshold);
+
+    if let Some(filename) = &diff_output {
+        comparison.image.save(filename).map_err(|e| format!("Failed to save {}: {}", filename, e))?;
+    }
+
+    let (width, height) = baseline_image.dimensions();
+    Ok(DiffReport {
+        status: if different { "different" } else { "same" }.to_string(),
+        baseline: baseline.display().to_string(),
+        current: current.to_string(),
+        width,
+        height,
+        pixels: comparison.pixels,
+        ignored_pixels: comparison.ignored_pixels,
+        changed_pixels: comparison.changed_pixels,
+        antialiased_pixels: comparison.antialiased_pixels,
+        difference,
+        scores,
+        threshold,
+        region: comparison.region,
+        ignore_regions: ignore,
+        diff_image: diff_output,
+        failure: different.then_some(ErrorKind::DiffOverThreshold),
+    })
+}
+
+/// Count pixels whose channels differ by more than `--tolerance`, skipping
+/// ignored regions and, unless `--count-antialiasing`, anti-aliased edges.
+/// The diff image shows the baseline faded to gray with changes in red,
+/// anti-aliasing in amber and ignored regions darkened.
+fn compare(baseline: &RgbImage, current: &RgbImage, args: &DiffArgs, ignore: &[IgnoreRegion]) -> Comparison {
+    let (width, height) = baseline.dimensions();
+    let mut image = RgbImage::new(width, height);
+    let (mut pixels, mut ignored_pixels, mut changed_pixels, mut antialiased_pixels) = (0, 0, 0, 0);
+    let (mut delta_e_sum, mut delta_e_max) = (0.0, 0.0f64);
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+
+    for (x, y, before) in baseline.enumerate_pixels() {
+        let after = current.get_pixel(x, y);
+        let luma = (before[0] as u32 * 299 + before[1] as u32 * 587 + before[2] as u32 * 114) / 1000;
+        let faded = (255 - (255 - luma) / 10) as u8;
+
+        if ignore.iter().any(|region| region.contains(x, y)) {
+            ignored_pixels += 1;
+            image.put_pixel(x, y, Rgb([faded / 2, faded / 2, faded / 2]));
+            continue;
+        }
+        pixels += 1;
+
+        if before != after {
+            let delta_e = ciede2000(to_lab(before), to_lab(after));
+            delta_e_sum += delta_e;
+            delta_e_max = delta_e_max.max(delta_e);
+        }
+        let changed = before.0.iter().zip(after.0).any(|(a, b)| a.abs_diff(b) > args.tolerance);
+        let antialiased = changed && (antialiased(baseline, current, x, y) || antialiased(current, baseline, x, y));
+        if antialiased {
+            antialiased_pixels += 1;
+        }
+        if changed && (!antialiased || args.count_antialiasing) {
+            changed_pixels += 1;
+            (min_x, min_y, max_x, max_y) = (min_x.min(x), min_y.min(y), max_x.max(x), max_y.max(y));
+        }
+        if changed {
+            image.put_pixel(x, y, if antialiased { ANTIALIASED_COLOR } else { CHANGED_COLOR });
+        } else {
+            image.put_pixel(x, y, Rgb([faded, faded, faded]));
+        }
+    }
+
+    let region = (changed_pixels > 0).then(|| ChangedRegion {
+        x: min_x,
+        y: min_y,
+        width: max_x - min_x + 1,
+        height: max_y - min_y + 1,
+    });
+    Comparison { pixels, ignored_pixels, changed_pixels, antialiased_pixels, region, delta_e_sum, delta_e_max, image }
+}
+
+fn brightness(pixel: &Rgb<u8>) -> f64 {
+    pixel[0] as f64 * 0.29889531 + pixel[1] as f64 * 0.58662247 + pixel[2] as f64 * 0.11448223
+}
+
+/// The in-bounds neighbours of (x, y), not including itself, and whether it
+/// lies on the image edge.
+fn neighbours(image: &RgbImage, x: u32, y: u32) -> (impl Iterator<Item = (u32, u32)>, bool) {
+    let (width, height) = image.dimensions();
+    let (x0, y0, x1, y1) = (x.saturating_sub(1), y.saturating_sub(1), (x + 1).min(width - 1), (y + 1).min(height - 1));
+    let edge = x == x0 || x == x1 || y == y0 || y == y1;
+    let cells = (y0..=y1).flat_map(move |ny| (x0..=x1).map(move |nx| (nx, ny))).filter(move |&cell| cell != (x, y));
+    (cells, edge)
+}
+
+/// Whether the pixel at (x, y) of `image` looks like part of an
+/// anti-aliased edge, as in pixelmatch: it sits between a darker and a
+/// brighter neighbour, and one of those belongs to a flat area of the same
+/// color in both images.
+fn antialiased(image: &RgbImage, other: &RgbImage, x: u32, y: u32) -> bool {
+    let center = brightness(image.get_pixel(x, y));
+    let (cells, edge) = neighbours(image, x, y);
+    let mut equal = edge as u32;
+    let (mut darkest, mut brightest) = ((0.0, (0, 0)), (0.0, (0, 0)));
+
+    for (nx, ny) in cells {
+        let delta = center - brightness(image.get_pixel(nx, ny));
+        if delta == 0.0 {
+            equal += 1;
+            // More than two equal neighbours mean a flat area, not an edge
+            if equal > 2 {
+                return false;
+            }
+        } else if delta < darkest.0 {
+            darkest = (delta, (nx, ny));
+        } else if delta > brightest.0 {
+            brightest = (delta, (nx, ny));
+        }
+    }
+    if darkest.0 == 0.0 || brightest.0 == 0.0 {
+        return false;
+    }
+
+    let flat = |(nx, ny): (u32, u32)| has_many_siblings(image, nx, ny) && has_many_siblings(other, nx, ny);
+    flat(darkest.1) || flat(brightest.1)
+}
+
+/// Whether at least three neighbours of (x, y) have exactly its color.
+fn has_many_siblings(image: &RgbImage, x: u32, y: u32) -> bool {
+    let pixel = image.get_pixel(x, y);
+    let (cells, edge) = neighbours(image, x, y);
+    let mut equal = edge as u32;
+    for (nx, ny) in cells {
+        if image.get_pixel(nx, ny) == pixel {
+            equal += 1;
+            if equal > 2 {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Mean SSIM of the luma of two images over overlapping square windows.
+/// Ignored pixels take the baseline's value in both images.
+fn ssim(baseline: &RgbImage, current: &RgbImage, ignore: &[IgnoreRegion]) -> f64 {
+    let (width, height) = baseline.dimensions();
+    let luma = |pixel: &Rgb<u8>| pixel[0] as f64 * 0.299 + pixel[1] as f64 * 0.587 + pixel[2] as f64 * 0.114;
+    let before: Vec<f64> = baseline.pixels().map(luma).collect();
+    let after: Vec<f64> = current
+        .enumerate_pixels()
+        .map(|(x, y, pixel)| match ignore.iter().any(|region| region.contains(x, y)) {
+            true => before[(y * width + x) as usize],
+            false => luma(pixel),
+        })
+        .collect();
+
+    // Stabilizing constants for 8-bit values
+    const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+    const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+    let window = SSIM_WINDOW.min(width).min(height);
+    if window == 0 {
+        return 1.0;
+    }
+
+    let (mut total, mut windows) = (0.0, 0u64);
+    let mut y = 0;
+    while y + window <= height {
+        let mut x = 0;
+        while x + window <= width {
+            let (mut sum_a, mut sum_b, mut sum_aa, mut sum_bb, mut sum_ab) = (0.0, 0.0, 0.0, 0.0, 0.0);
+            for row in y..y + window {
+                let start = (row * width + x) as usize;
+                for (a, b) in before[start..start + window as usize].iter().zip(&after[start..start + window as usize]) {
+                    sum_a += a;
+                    sum_b += b;
+                    sum_aa += a * a;
+                    sum_bb += b * b;
+                    sum_ab += a * b;
+                }
+            }
+            let n = (window * window) as f64;
+            let (mean_a, mean_b) = (sum_a / n, sum_b / n);
+            let var_a = sum_aa / n - mean_a * mean_a;
+            let var_b = sum_bb / n - mean_b * mean_b;
+            let covariance = sum_ab / n - mean_a * mean_b;
+            total += ((2.0 * mean_a * mean_b + C1) * (2.0 * covariance + C2))
+                / ((mean_a * mean_a + mean_b * mean_b + C1) * (var_a + var_b + C2));
+            windows += 1;
+            x += SSIM_STEP;
+        }
+        y += SSIM_STEP;
+    }
+    total / windows.max(1) as f64
+}
+
+/// CIE L*a*b* (D65) of an sRGB pixel.
+fn to_lab(pixel: &Rgb<u8>) -> (f64, f64, f64) {
+    let linear = |channel: u8| {
+        let c = channel as f64 / 255.0;
+        if c <= 0.04045 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+    };
+    let (r, g, b) = (linear(pixel[0]), linear(pixel[1]), linear(pixel[2]));
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f64| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    (116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz))
+}
+
+/// CIEDE2000 color difference between two L*a*b* colors.
+fn ciede2000((l1, a1, b1): (f64, f64, f64), (l2, a2, b2): (f64, f64, f64)) -> f64 {
+    let c_bar = ((a1 * a1 + b1 * b1).sqrt() + (a2 * a2 + b2 * b2).sqrt()) / 2.0;
+    let g = 0.5 * (1.0 - (c_bar.powi(7) / (c_bar.powi(7) + 25f64.powi(7))).sqrt());
+    let (a1, a2) = (a1 * (1.0 + g), a2 * (1.0 + g));
+    let (c1, c2) = ((a1 * a1 + b1 * b1).sqrt(), (a2 * a2 + b2 * b2).sqrt());
+    let hue = |b: f64, a: f64| if a == 0.0 && b == 0.0 { 0.0 } else { b.atan2(a).to_degrees().rem_euclid(360.0) };
+    let (h1, h2) = (hue(b1, a1), hue(b2, a2));
+
+    let delta_l = l2 - l1;
+    let delta_c = c2 - c1;
+    let delta_h = if c1 * c2 == 0.0 {
+        0.0
+    } else if (h2 - h1).abs() <= 180.0 {
+        h2 - h1
+    } else if h2 <= h1 {
+        h2 - h1 + 360.0
+    } else {
+        h2 - h1 - 360.0
+    };
+    let delta_big_h = 2.0 * (c1 * c2).sqrt() * (delta_h / 2.0).to_radians().sin();
+
+    let l_bar = (l1 + l2) / 2.0;
+    let c_bar = (c1 + c2) / 2.0;
+    let h_bar = if c1 * c2 == 0.0 {
+        h1 + h2
+    } else if (h1 - h2).abs() <= 180.0 {
+        (h1 + h2) / 2.0
+    } else if h1 + h2 < 360.0 {
+        (h1 + h2 + 360.0) / 2.0
+    } else {
+        (h1 + h2 - 360.0) / 2.0
+    };
+    let t = 1.0 - 0.17 * (h_bar - 30.0).to_radians().cos()
+        + 0.24 * (2.0 * h_bar).to_radians().cos()
+        + 0.32 * (3.0 * h_bar + 6.0).to_radians().cos()
+        - 0.20 * (4.0 * h_bar - 63.0).to_radians().cos();
+    let delta_theta = 30.0 * (-((h_bar - 275.0) / 25.0).powi(2)).exp();
+    let r_c = 2.0 * (c_bar.powi(7) / (c_bar.powi(7) + 25f64.powi(7))).sqrt();
+    let s_l = 1.0 + 0.015 * (l_bar - 50.0).powi(2) / (20.0 + (l_bar - 50.0).powi(2)).sqrt();
+    let s_c = 1.0 + 0.045 * c_bar;
+    let s_h = 1.0 + 0.015 * c_bar * t;
+    let r_t = -(2.0 * delta_theta).to_radians().sin() * r_c;
+
+    let (l, c, h) = (delta_l / s_l, delta_c / s_c, delta_big_h / s_h);
+    (l * l + c * c + h * h + r_t * c * h).sqrt()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A gradient with some detail, so SSIM windows have variance.
+    fn pattern(width: u32, height: u32) -> RgbImage {
+        RgbImage::from_fn(width, height, |x, y| Rgb([(x * 7 + y * 3) as u8, (x * y) as u8, ((x ^ y) * 9) as u8]))
+    }
+
+    #[test]
+    fn identical_images_have_ssim_one() {
+        let image = pattern(40, 30);
+        assert!((ssim(&image, &image, &[]) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn changes_lower_ssim_unless_ignored() {
+        let baseline = pattern(40, 30);
+        let mut current = baseline.clone();
+        for y in 8..16 {
+            for x in 8..24 {
+                current.put_pixel(x, y, Rgb([255, 255, 255]));
+            }
+        }
+        assert!(ssim(&baseline, &current, &[]) < 0.95);
+
+        let ignore = [IgnoreRegion { x: 8, y: 8, width: 16, height: 8 }];
+        assert!((ssim(&baseline, &current, &ignore) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn ssim_of_images_smaller_than_a_window() {
+        let image = pattern(3, 2);
+        assert!((ssim(&image, &image, &[]) - 1.0).abs() < 1e-9);
+        assert_eq!(ssim(&RgbImage::new(0, 0), &RgbImage::new(0, 0), &[]), 1.0);
+    }
+
+    #[test]
+    fn lab_of_white_and_black() {
+        // The rounded sRGB matrix leaves white a hair off neutral
+        let (l, a, b) = to_lab(&Rgb([255, 255, 255]));
+        assert!((l - 100.0).abs() < 0.01 && a.abs() < 0.05 && b.abs() < 0.05, "{} {} {}", l, a, b);
+        let (l, a, b) = to_lab(&Rgb([0, 0, 0]));
+        assert!(l.abs() < 0.01 && a.abs() < 0.01 && b.abs() < 0.01, "{} {} {}", l, a, b);
+    }
+
+    #[test]
+    fn ciede2000_matches_sharma_reference_data() {
+        // Pairs and expected differences from Sharma, Wu and Dalal (2005), "The
+        // CIEDE2000 Color-Difference Formula: Implementation Notes,
+        // Supplementary Test Data, and Mathematical Observations"
+        let pairs = [
+            ((50.0, 2.6772, -79.7751), (50.0, 0.0, -82.7485), 2.0425),
+            ((50.0, 3.1571, -77.2803), (50.0, 0.0, -82.7485), 2.8615),
+            ((50.0, 2.8361, -74.0200), (50.0, 0.0, -82.7485), 3.4412),
+            ((50.0, 0.0, 0.0), (50.0, -1.0, 2.0), 2.3669),
+            ((50.0, 2.4900, -0.0010), (50.0, -2.4900, 0.0009), 7.1792),
+            ((50.0, 2.4900, -0.0010), (50.0, -2.4900, 0.0010), 7.1792),
+            ((50.0, 2.4900, -0.0010), (50.0, -2.4900, 0.0011), 7.2195),
+            ((50.0, 2.4900, -0.0010), (50.0, -2.4900, 0.0012), 7.2195),
+            ((50.0, 2.5, 0.0), (73.0, 25.0, -18.0), 27.1492),
+            ((50.0, 2.5, 0.0), (61.0, -5.0, 29.0), 22.8977),
+            ((50.0, 2.5, 0.0), (56.0, -27.0, -3.0), 31.9030),
+            ((50.0, 2.5, 0.0), (58.0, 24.0, 15.0), 19.4535),
+            ((60.2574, -34.0099, 36.2677), (60.4626, -34.1751, 39.4387), 1.2644),
+            ((63.0109, -31.0961, -5.8663), (62.8187, -29.7946, -4.0864), 1.2630),
+            ((22.7233, 20.0904, -46.6940), (23.0331, 14.9730, -42.5619), 2.0373),
+        ];
+        for (first, second, expected) in pairs {
+            let forward = ciede2000(first, second);
+            let backward = ciede2000(second, first);
+            assert!((forward - expected).abs() < 1e-4, "{:?} {:?}: {} != {}", first, second, forward, expected);
+            assert!((backward - expected).abs() < 1e-4, "{:?} {:?}: {} != {}", second, first, backward, expected);
+        }
+        assert_eq!(ciede2000((50.0, 10.0, -10.0), (50.0, 10.0, -10.0)), 0.0);
+    }
+
+    #[test]
+    fn threshold_metrics_parse_and_decide() {
+        let ssim: ThresholdMetric = "ssim:0.98".parse().unwrap();
+        assert_eq!((ssim.metric, ssim.value), (Metric::Ssim, 0.98));
+        let delta_e: ThresholdMetric = " Delta-E : 2.3".parse().unwrap();
+        assert_eq!((delta_e.metric, delta_e.value), (Metric::DeltaE, 2.3));
+        assert!("ssim".parse::<ThresholdMetric>().is_err());
+        assert!("psnr:30".parse::<ThresholdMetric>().is_err());
+
+        let scores = Scores { pixels: 1.5, ssim: 0.99, delta_e: 2.0, delta_e_max: 20.0 };
+        assert!(scores.passes(ssim));
+        assert!(scores.passes(delta_e));
+        assert!(!scores.passes(ThresholdMetric { metric: Metric::Pixels, value: 1.0 }));
+        assert!(!scores.passes(ThresholdMetric { metric: Metric::Ssim, value: 0.995 }));
+    }
+}
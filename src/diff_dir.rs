@@ -0,0 +1,218 @@
+use std::collections::BTreeSet;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::diff::{self, DiffReport};
+use crate::error::ErrorKind;
+use crate::{config, DiffArgs};
+
+/// Extensions of the files paired up for comparison.
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "bmp", "gif", "webp", "tif", "tiff"];
+
+/// Result of comparing one file name between the two directories.
+#[derive(Serialize)]
+pub struct FileResult {
+    pub name: String,
+    /// "same", "different", "only_in_baseline", "only_in_current" or "error"
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub diff: Option<DiffReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct DirDiffReport {
+    /// "same" when every file has a matching counterpart, otherwise "different"
+    pub status: String,
+    pub baseline: String,
+    pub current: String,
+    pub compared: usize,
+    pub same: usize,
+    pub mismatched: usize,
+    pub files: Vec<FileResult>,
+    /// HTML page listing the mismatches side by side
+    pub html_report: String,
+    /// Set when any file differs, is missing or failed, used for the exit code
+    #[serde(skip)]
+    pub failure: Option<ErrorKind>,
+}
+
+/// Compare every image in `baseline` with the file of the same name in
+/// `current`, e.g. the screenshot suites of two builds. Diff images of the
+/// mismatches and an HTML report go to `report_dir`.
+pub fn diff_dirs(baseline: &Path, current: &Path, report_dir: &str, args: &DiffArgs) -> Result<DirDiffReport, Box<dyn std::error::Error>> {
+    let baseline_names = image_names(baseline)?;
+    let current_names = image_names(current)?;
+    let report_dir = PathBuf::from(config::resolve_output_path(report_dir));
+    std::fs::create_dir_all(&report_dir)?;
+
+    status!("Comparing {} with {}", baseline.display(), current.display());
+    let mut files = Vec::new();
+    for name in baseline_names.union(&current_names) {
+        let file = match (baseline_names.contains(name), current_names.contains(name)) {
+            (true, false) => FileResult { name: name.clone(), status: "only_in_baseline".to_string(), diff: None, error: None },
+            (false, true) => FileResult { name: name.clone(), status: "only_in_current".to_string(), diff: None, error: None },
+            _ => compare_pair(&baseline.join(name), &current.join(name), name, &report_dir, args),
+        };
+        files.push(file);
+    }
+    if files.is_empty() {
+        return Err(format!("No images found in {} or {}", baseline.display(), current.display()).into());
+    }
+
+    let compared = files.iter().filter(|file| file.diff.is_some()).count();
+    let same = files.iter().filter(|file| file.status == "same").count();
+    let mismatched = files.len() - same;
+
+    let html_report = report_dir.join("index.html");
+    std::fs::write(&html_report, html(baseline, current, &files))
+        .map_err(|e| format!("Failed to write {}: {}", html_report.display(), e))?;
+
+    print_summary(&files);
+    status!("{} of {} files match; report saved to {}", same, files.len(), html_report.display());
+
+    Ok(DirDiffReport {
+        status: if mismatched > 0 { "different" } else { "same" }.to_string(),
+        baseline: baseline.display().to_string(),
+        current: current.display().to_string(),
+        compared,
+        same,
+        mismatched,
+        files,
+        html_report: html_report.display().to_string(),
+        failure: (mismatched > 0).then_some(ErrorKind::DiffOverThreshold),
+    })
+}
+
+/// Names of the image files directly inside `dir`.
+fn image_names(dir: &Path) -> Result<BTreeSet<String>, Box<dyn std::error::Error>> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    let mut names = BTreeSet::new();
+    for entry in entries {
+        let path = entry?.path();
+        let is_image = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()));
+        if path.is_file()
+            && is_image
+            && let Some(name) = path.file_name().and_then(|name| name.to_str())
+        {
+            names.insert(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+fn compare_pair(baseline: &Path, current: &Path, name: &str, report_dir: &Path, args: &DiffArgs) -> FileResult {
+    let diff_image = report_dir.join(format!("{}.diff.png", Path::new(name).file_stem().unwrap_or_default().to_string_lossy()));
+    let compared = (|| {
+        let baseline_image = diff::open_rgb8(baseline)?;
+        let current_image = diff::open_rgb8(current)?;
+        diff::evaluate(baseline, &current.display().to_string(), &baseline_image, &current_image, args, Some(diff_image.display().to_string()))
+    })();
+
+    match compared {
+        Ok(report) => {
+            // Only mismatches keep their diff image
+            if report.status == "same" {
+                let _ = std::fs::remove_file(&diff_image);
+            }
+            FileResult {
+                name: name.to_string(),
+                status: report.status.clone(),
+                diff: Some(DiffReport { diff_image: (report.status != "same").then(|| diff_image.display().to_string()), ..report }),
+                error: None,
+            }
+        }
+        Err(e) => FileResult { name: name.to_string(), status: "error".to_string(), diff: None, error: Some(e.to_string()) },
+    }
+}
+
+fn print_summary(files: &[FileResult]) {
+    let width = files.iter().map(|file| file.name.len()).max().unwrap_or(0).max(4);
+    result!("{:<width$}  {:<16}  {:>9}  {:>7}  {:>7}", "FILE", "STATUS", "CHANGED", "SSIM", "DELTA E");
+    for file in files {
+        match &file.diff {
+            Some(diff) => result!(
+                "{:<width$}  {:<16}  {:>8.3}%  {:>7.4}  {:>7.2}",
+                file.name, file.status, diff.difference, diff.scores.ssim, diff.scores.delta_e
+            ),
+            None => result!("{:<width$}  {:<16}  {}", file.name, file.status, file.error.as_deref().unwrap_or("")),
+        }
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// A link to `path` usable from the report, absolute so the report can
+/// stay where it was written.
+fn file_url(path: &Path) -> String {
+    let absolute = std::fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let path = absolute.to_string_lossy().replace('\\', "/");
+    let path = path.trim_start_matches("//?/");
+    format!("file://{}{}", if path.starts_with('/') { "" } else { "/" }, escape(path))
+}
+
+/// An HTML page with a row per mismatched file: baseline, current and diff
+/// image side by side, with the scores.
+fn html(baseline: &Path, current: &Path, files: &[FileResult]) -> String {
+    let mismatches: Vec<&FileResult> = files.iter().filter(|file| file.status != "same").collect();
+    let mut page = String::new();
+    let _ = write!(
+        page,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>captest diff report</title>\n<style>\n\
+         body {{ font-family: sans-serif; margin: 2em; }}\n\
+         table {{ border-collapse: collapse; }}\n\
+         td, th {{ border: 1px solid #ccc; padding: 0.5em; vertical-align: top; text-align: left; }}\n\
+         img {{ max-width: 400px; }}\n\
+         .status {{ font-weight: bold; color: #c00; }}\n\
+         </style>\n</head>\n<body>\n<h1>Screenshot differences</h1>\n\
+         <p>Baseline: {}<br>Current: {}</p>\n<p>{} of {} files match.</p>\n",
+        escape(&baseline.display().to_string()),
+        escape(&current.display().to_string()),
+        files.len() - mismatches.len(),
+        files.len()
+    );
+    if mismatches.is_empty() {
+        page.push_str("</body>\n</html>\n");
+        return page;
+    }
+
+    page.push_str("<table>\n<tr><th>File</th><th>Baseline</th><th>Current</th><th>Diff</th></tr>\n");
+    for file in mismatches {
+        let cell = |path: PathBuf| match path.exists() {
+            true => format!("<td><a href=\"{0}\"><img src=\"{0}\"></a></td>", file_url(&path)),
+            false => "<td>missing</td>".to_string(),
+        };
+        let details = match (&file.diff, &file.error) {
+            (Some(diff), _) => format!(
+                "{:.3}% changed<br>SSIM {:.4}<br>delta E {:.2}",
+                diff.difference, diff.scores.ssim, diff.scores.delta_e
+            ),
+            (None, Some(error)) => escape(error),
+            (None, None) => String::new(),
+        };
+        let diff_cell = match file.diff.as_ref().and_then(|diff| diff.diff_image.as_ref()) {
+            Some(image) => cell(PathBuf::from(image)),
+            None => "<td></td>".to_string(),
+        };
+        let _ = writeln!(
+            page,
+            "<tr><td>{}<br><span class=\"status\">{}</span><br>{}</td>{}{}{}</tr>",
+            escape(&file.name),
+            escape(&file.status.replace('_', " ")),
+            details,
+            cell(baseline.join(&file.name)),
+            cell(current.join(&file.name)),
+            diff_cell
+        );
+    }
+    page.push_str("</table>\n</body>\n</html>\n");
+    page
+}
@@ -0,0 +1,153 @@
+use crate::convert::Crop;
+
+/// Edge, in pixels, of the square tiles frames are compared in.
+const TILE: u32 = 64;
+/// Share of the frame that, once changed, is sent whole rather than in patches.
+const WHOLE_FRAME: f64 = 0.5;
+
+/// Remembers the last frame sent to a viewer, to find what changed since.
+#[derive(Default)]
+pub struct DirtyRects {
+    width: u32,
+    height: u32,
+    previous: Vec<u8>,
+}
+
+impl DirtyRects {
+    /// Take the next `width` x `height` RGB8 frame and return the rectangles
+    /// that differ from the previous one, in whole tiles. Changed tiles next
+    /// to each other in a row, and rows of the same span, are joined. The
+    /// first frame, a change of size or a mostly changed frame is one
+    /// rectangle covering everything.
+    pub fn update(&mut self, width: u32, height: u32, rgb_data: Vec<u8>) -> Vec<Crop> {
+        let whole = Crop { x: 0, y: 0, width, height };
+        if (width, height) != (self.width, self.height) || self.previous.len() != rgb_data.len() {
+            (self.width, self.height, self.previous) = (width, height, rgb_data);
+            return vec![whole];
+        }
+
+        let mut rects: Vec<Crop> = Vec::new();
+        let mut changed_pixels = 0u64;
+        for y in (0..height).step_by(TILE as usize) {
+            let tile_height = TILE.min(height - y);
+            let mut run: Option<Crop> = None;
+            for x in (0..width).step_by(TILE as usize) {
+                let tile = Crop { x, y, width: TILE.min(width - x), height: tile_height };
+                if !self.differs(&rgb_data, tile) {
+                    rects.extend(run.take());
+                    continue;
+                }
+                changed_pixels += tile.width as u64 * tile.height as u64;
+                match &mut run {
+                    Some(run) => run.width += tile.width,
+                    None => run = Some(tile),
+                }
+            }
+            rects.extend(run);
+        }
+        self.previous = rgb_data;
+
+        if changed_pixels as f64 >= WHOLE_FRAME * width as f64 * height as f64 {
+            return vec![whole];
+        }
+        join_rows(rects)
+    }
+
+    /// The frame last passed to `update`.
+    pub fn frame(&self) -> &[u8] {
+        &self.previous
+    }
+
+    fn differs(&self, rgb_data: &[u8], tile: Crop) -> bool {
+        let stride = self.width as usize * 3;
+        (tile.y..tile.y + tile.height).any(|y| {
+            let start = y as usize * stride + tile.x as usize * 3;
+            let end = start + tile.width as usize * 3;
+            rgb_data[start..end] != self.previous[start..end]
+        })
+    }
+}
+
+/// Merge each rectangle into the one directly above it when they span the
+/// same columns, e.g. a changed panel several tiles tall.
+fn join_rows(rects: Vec<Crop>) -> Vec<Crop> {
+    let mut joined: Vec<Crop> = Vec::with_capacity(rects.len());
+    for rect in rects {
+        let above = joined.iter_mut().find(|above| above.x == rect.x && above.width == rect.width && above.y + above.height == rect.y);
+        match above {
+            Some(above) => above.height += rect.height,
+            None => joined.push(rect),
+        }
+    }
+    joined
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 256;
+    const HEIGHT: u32 = 192;
+
+    /// A tracker that has already seen a black frame.
+    fn primed() -> DirtyRects {
+        let mut rects = DirtyRects::default();
+        rects.update(WIDTH, HEIGHT, vec![0; (WIDTH * HEIGHT * 3) as usize]);
+        rects
+    }
+
+    /// The black frame with one pixel set in each of the tiles at `tiles`.
+    fn with_changed_tiles(tiles: &[(u32, u32)]) -> Vec<u8> {
+        let mut frame = vec![0; (WIDTH * HEIGHT * 3) as usize];
+        for &(column, row) in tiles {
+            let (x, y) = (column * TILE + 5, row * TILE + 7);
+            frame[((y * WIDTH + x) * 3) as usize] = 255;
+        }
+        frame
+    }
+
+    fn rect(x: u32, y: u32, width: u32, height: u32) -> Crop {
+        Crop { x, y, width, height }
+    }
+
+    #[test]
+    fn sends_the_first_frame_whole() {
+        let mut rects = DirtyRects::default();
+        assert_eq!(rects.update(WIDTH, HEIGHT, with_changed_tiles(&[])), [rect(0, 0, WIDTH, HEIGHT)]);
+    }
+
+    #[test]
+    fn finds_nothing_in_an_unchanged_frame() {
+        assert_eq!(primed().update(WIDTH, HEIGHT, with_changed_tiles(&[])), []);
+    }
+
+    #[test]
+    fn sends_a_single_changed_tile() {
+        assert_eq!(primed().update(WIDTH, HEIGHT, with_changed_tiles(&[(1, 2)])), [rect(64, 128, 64, 64)]);
+    }
+
+    #[test]
+    fn joins_adjacent_tiles_in_a_row() {
+        let changed = primed().update(WIDTH, HEIGHT, with_changed_tiles(&[(0, 1), (1, 1), (3, 1)]));
+        assert_eq!(changed, [rect(0, 64, 128, 64), rect(192, 64, 64, 64)]);
+    }
+
+    #[test]
+    fn joins_runs_of_the_same_span_in_consecutive_rows() {
+        let changed = primed().update(WIDTH, HEIGHT, with_changed_tiles(&[(2, 0), (2, 1), (0, 2)]));
+        assert_eq!(changed, [rect(128, 0, 64, 128), rect(0, 128, 64, 64)]);
+    }
+
+    #[test]
+    fn sends_a_mostly_changed_frame_whole() {
+        let tiles = [(0, 0), (1, 0), (2, 0), (3, 0), (0, 1), (1, 1)];
+        assert_eq!(primed().update(WIDTH, HEIGHT, with_changed_tiles(&tiles)), [rect(0, 0, WIDTH, HEIGHT)]);
+    }
+
+    #[test]
+    fn compares_against_the_last_frame_sent() {
+        let mut rects = primed();
+        rects.update(WIDTH, HEIGHT, with_changed_tiles(&[(1, 1)]));
+        assert_eq!(rects.update(WIDTH, HEIGHT, with_changed_tiles(&[(1, 1)])), []);
+    }
+}
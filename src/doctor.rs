@@ -0,0 +1,180 @@
+use std::time::Duration;
+
+use scap::Target;
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::capture;
+use crate::error::{self, ErrorKind};
+use crate::platforms;
+use crate::session_pool::TargetKey;
+use crate::validate;
+
+/// How long the test capture may take before it counts as failed.
+const TEST_CAPTURE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// One thing `doctor` looked at.
+#[derive(Serialize)]
+pub struct Check {
+    pub name: &'static str,
+    /// "ok", "warn" or "fail"
+    pub status: &'static str,
+    pub detail: String,
+    /// What to do about a warning or failure
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hint: Option<String>,
+    /// Exit code category of a failure
+    #[serde(skip)]
+    kind: Option<ErrorKind>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Self { name, status: "ok", detail: detail.into(), hint: None, kind: None }
+    }
+
+    fn warn(name: &'static str, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self { name, status: "warn", detail: detail.into(), hint: Some(hint.into()), kind: None }
+    }
+
+    fn fail(name: &'static str, kind: ErrorKind, detail: impl Into<String>, hint: impl Into<String>) -> Self {
+        Self { name, status: "fail", detail: detail.into(), hint: Some(hint.into()), kind: Some(kind) }
+    }
+}
+
+#[derive(Serialize)]
+pub struct DoctorReport {
+    /// "ok", or "fail" when any check failed
+    pub status: String,
+    /// Whether captest treats this run as unattended (`CI` is set) and won't prompt for permission
+    pub non_interactive: bool,
+    /// The virtual display captures come from, e.g. "Xvfb :99"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub virtual_display: Option<String>,
+    pub checks: Vec<Check>,
+    /// Category of the first failed check, used for the exit code
+    #[serde(skip)]
+    pub failure: Option<ErrorKind>,
+}
+
+/// The `doctor` command: check that this machine can take screenshots, as
+/// a first step in CI jobs and containers. Looks for a display (real,
+/// virtual such as Xvfb, or a Linux framebuffer), capture support and
+/// permission, capture targets, and takes a test capture of screen 0 to
+/// make sure it isn't blank. Never prompts for anything.
+pub async fn doctor(cancel: &CancellationToken) -> Result<DoctorReport, Box<dyn std::error::Error>> {
+    let non_interactive = capture::non_interactive();
+    let virtual_display = platforms::virtual_display();
+    let display = display_check(virtual_display.as_deref());
+    let framebuffer_only = display.status == "ok" && headless();
+    let mut checks = vec![display];
+    if checks[0].status == "ok" && !framebuffer_only {
+        checks.extend(backend_checks());
+    }
+    if checks.iter().all(|check| check.status != "fail") {
+        checks.push(test_capture(framebuffer_only, cancel).await);
+    }
+
+    for check in &checks {
+        let hint = check.hint.as_deref().map(|hint| format!(" ({})", hint)).unwrap_or_default();
+        result!("[{}] {}: {}{}", check.status, check.name, check.detail, hint);
+    }
+    let failure = checks.iter().find_map(|check| check.kind);
+    Ok(DoctorReport {
+        status: if failure.is_some() { "fail" } else { "ok" }.to_string(),
+        non_interactive,
+        virtual_display,
+        checks,
+        failure,
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn headless() -> bool {
+    platforms::linux::headless()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn headless() -> bool {
+    false
+}
+
+/// Whether there is a display to capture, and what kind.
+fn display_check(virtual_display: Option<&str>) -> Check {
+    if let Some(name) = virtual_display {
+        return Check::ok("display", format!("virtual display: {}", name));
+    }
+    if !headless() {
+        return Check::ok("display", "display session found");
+    }
+    if std::path::Path::new("/dev/fb0").exists() {
+        return Check::ok("display", "no X11 or Wayland session; screens are read from /dev/fb0");
+    }
+    Check::fail("display", ErrorKind::Unsupported, "no X11 or Wayland session and no framebuffer",
+        "start a virtual display, e.g. `Xvfb :99 -screen 0 1920x1080x24 &` and `export DISPLAY=:99`, or run under `xvfb-run`")
+}
+
+/// Capture support, permission and targets of the scap backend.
+fn backend_checks() -> Vec<Check> {
+    if !scap::is_supported() {
+        return vec![Check::fail("capture_support", ErrorKind::Unsupported, "screen capture is not supported on this system",
+            "macOS needs 12.3 or later, Windows 10 1903 or later, and Linux a PipeWire or X11 session")];
+    }
+    let mut checks = vec![Check::ok("capture_support", "screen capture is supported")];
+
+    // Only checks; prompting would hang a job nobody is watching
+    if !scap::has_permission() {
+        checks.push(Check::fail("permission", ErrorKind::PermissionDenied, "screen recording permission has not been granted",
+            "on macOS, grant it to the terminal or CI agent ahead of time (System Settings, or a PPPC profile via MDM); \
+             run `captest capture` once interactively to be prompted"));
+        return checks;
+    }
+    checks.push(Check::ok("permission", "screen recording permission granted"));
+
+    let targets = capture::all_targets();
+    let displays = targets.iter().filter(|target| matches!(target, Target::Display(_))).count();
+    let windows = targets.len() - displays;
+    checks.push(match displays {
+        0 => Check::fail("targets", ErrorKind::TargetNotFound, "no displays to capture", "check that the display server has a screen attached"),
+        _ => Check::ok("targets", format!("{} display(s), {} window(s)", displays, windows)),
+    });
+    checks
+}
+
+/// Grab screen 0 and check that it has content.
+async fn test_capture(framebuffer_only: bool, cancel: &CancellationToken) -> Check {
+    let grabbed = match framebuffer_only {
+        true => framebuffer_rgb8(),
+        false => match tokio::time::timeout(TEST_CAPTURE_TIMEOUT, capture::grab_rgb8(TargetKey::Screen(0), cancel)).await {
+            Ok(grabbed) => grabbed,
+            Err(_) => return Check::fail("test_capture", ErrorKind::CaptureTimeout, "no frame within 15 seconds",
+                "the capture backend is not delivering frames; on Linux, check that PipeWire or the X server is running"),
+        },
+    };
+    let (width, height, rgb_data) = match grabbed {
+        Ok(frame) => frame,
+        Err(e) => {
+            let kind = match error::error_kind(&*e) {
+                ErrorKind::Failure => ErrorKind::CaptureTimeout,
+                kind => kind,
+            };
+            return Check::fail("test_capture", kind, format!("capturing screen 0 failed: {}", e), "see the messages above for the backend's error");
+        }
+    };
+    match validate::detect_blank(&rgb_data) {
+        Some(blank) => Check::warn("test_capture", format!("screen 0 ({}x{}) is {}", width, height, blank.describe()),
+            "a fresh virtual display is often empty; start the app under test first. A blank real display can mean missing permission"),
+        None => Check::ok("test_capture", format!("captured screen 0 ({}x{})", width, height)),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn framebuffer_rgb8() -> Result<(u32, u32, Vec<u8>), Box<dyn std::error::Error>> {
+    let (width, height, bgra) = platforms::linux::capture_framebuffer(0)?;
+    Ok((width, height, bgra.chunks_exact(4).flat_map(|p| [p[2], p[1], p[0]]).collect()))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn framebuffer_rgb8() -> Result<(u32, u32, Vec<u8>), Box<dyn std::error::Error>> {
+    Err("Framebuffer capture is only available on Linux".into())
+}
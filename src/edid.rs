@@ -0,0 +1,180 @@
+use serde::Serialize;
+
+const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+/// Offsets of the four 18-byte display descriptors in the base block.
+const DESCRIPTORS: [usize; 4] = [54, 72, 90, 108];
+const TAG_SERIAL: u8 = 0xff;
+const TAG_NAME: u8 = 0xfc;
+
+/// What a monitor says about itself, from its EDID. Unlike display IDs and
+/// indices, this stays the same across reboots and reconnects.
+#[derive(Serialize, Clone, Debug, Default)]
+pub struct Monitor {
+    /// Three-letter PNP ID of the maker, e.g. "GSM"
+    pub manufacturer_id: String,
+    /// The maker's name for well-known PNP IDs, e.g. "LG"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub manufacturer: Option<String>,
+    /// Model name the monitor reports, e.g. "LG HDR 4K"
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    pub product_code: u16,
+    /// Serial number string, or the numeric serial when there is none
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    /// Year of manufacture
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub year: Option<u16>,
+}
+
+impl Monitor {
+    /// Identity from the vendor, product and serial numbers alone, for
+    /// platforms that decode the EDID themselves.
+    pub fn from_numbers(vendor: u16, product_code: u16, serial: u32, model: Option<String>) -> Self {
+        let manufacturer_id = manufacturer_id(vendor);
+        Self {
+            manufacturer: manufacturer_name(&manufacturer_id).map(str::to_string),
+            manufacturer_id,
+            model,
+            product_code,
+            serial: (serial != 0).then(|| serial.to_string()),
+            year: None,
+        }
+    }
+
+    /// "LG HDR 4K (GSM, serial 104NTAB1C123)"
+    pub fn describe(&self) -> String {
+        let name = self.model.clone()
+            .or_else(|| self.manufacturer.clone())
+            .unwrap_or_else(|| format!("{} {:04X}", self.manufacturer_id, self.product_code));
+        match &self.serial {
+            Some(serial) => format!("{} ({}, serial {})", name, self.manufacturer_id, serial),
+            None => format!("{} ({})", name, self.manufacturer_id),
+        }
+    }
+}
+
+/// Decode the base block of an EDID, or None if it isn't one.
+pub fn parse(edid: &[u8]) -> Option<Monitor> {
+    if edid.len() < 128 || edid[..8] != HEADER {
+        return None;
+    }
+    let mut monitor = Monitor::from_numbers(
+        u16::from_be_bytes([edid[8], edid[9]]),
+        u16::from_le_bytes([edid[10], edid[11]]),
+        u32::from_le_bytes([edid[12], edid[13], edid[14], edid[15]]),
+        None,
+    );
+    // Year 0 means none was given; 0xff marks a model year instead
+    if edid[17] != 0 && edid[17] != 0xff {
+        monitor.year = Some(1990 + edid[17] as u16);
+    }
+    for offset in DESCRIPTORS {
+        let descriptor = &edid[offset..offset + 18];
+        // Display descriptors start with a zero pixel clock
+        if descriptor[..3] != [0, 0, 0] {
+            continue;
+        }
+        match descriptor[3] {
+            TAG_NAME => monitor.model = descriptor_text(descriptor).or(monitor.model),
+            TAG_SERIAL => monitor.serial = descriptor_text(descriptor).or(monitor.serial),
+            _ => {}
+        }
+    }
+    Some(monitor)
+}
+
+/// Text of a descriptor: up to 13 bytes, ended by a newline and padded with spaces.
+fn descriptor_text(descriptor: &[u8]) -> Option<String> {
+    let text = &descriptor[5..];
+    let end = text.iter().position(|&b| b == b'\n').unwrap_or(text.len());
+    let text = String::from_utf8_lossy(&text[..end]).trim().to_string();
+    (!text.is_empty()).then_some(text)
+}
+
+/// The three letters packed 5 bits each into the EDID vendor field.
+fn manufacturer_id(vendor: u16) -> String {
+    [10, 5, 0].iter().map(|shift| (b'@' + ((vendor >> shift) & 0x1f) as u8) as char).collect()
+}
+
+fn manufacturer_name(id: &str) -> Option<&'static str> {
+    Some(match id {
+        "ACR" => "Acer",
+        "AOC" => "AOC",
+        "APP" => "Apple",
+        "AUO" => "AU Optronics",
+        "AUS" => "ASUS",
+        "BNQ" => "BenQ",
+        "BOE" => "BOE",
+        "CMN" => "Chimei Innolux",
+        "DEL" => "Dell",
+        "EIZ" => "EIZO",
+        "GSM" => "LG",
+        "HPN" | "HWP" => "HP",
+        "IVM" => "Iiyama",
+        "LEN" => "Lenovo",
+        "LGD" => "LG Display",
+        "MSI" => "MSI",
+        "NEC" => "NEC",
+        "PHL" => "Philips",
+        "SAM" | "SEC" => "Samsung",
+        "SHP" => "Sharp",
+        "SNY" => "Sony",
+        "VSC" => "ViewSonic",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A complete EDID 1.4 base block with LG's vendor ID: a detailed timing,
+    /// range limits, name and serial descriptors and a valid checksum.
+    const LG_MONITOR: [u8; 128] = [
+        0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00, 0x1e, 0x6d, 0x50, 0x77, 0xb3, 0xa2, 0x01, 0x00,
+        0x0c, 0x1d, 0x01, 0x04, 0xb5, 0x3c, 0x22, 0x78, 0x9e, 0x3e, 0x31, 0xa0, 0x54, 0x4f, 0x9f, 0x27,
+        0x11, 0x50, 0x54, 0x21, 0x08, 0x00, 0x71, 0x40, 0x81, 0x80, 0x81, 0xc0, 0xa9, 0xc0, 0xd1, 0xc0,
+        0x81, 0x00, 0x01, 0x01, 0x01, 0x01, 0x4d, 0xd0, 0x00, 0xa0, 0xf0, 0x70, 0x3e, 0x80, 0x30, 0x20,
+        0x35, 0x00, 0x58, 0x54, 0x21, 0x00, 0x00, 0x1a, 0x00, 0x00, 0x00, 0xfd, 0x00, 0x28, 0x3d, 0x87,
+        0x87, 0x38, 0x01, 0x0a, 0x20, 0x20, 0x20, 0x20, 0x20, 0x20, 0x00, 0x00, 0x00, 0xfc, 0x00, 0x4c,
+        0x47, 0x20, 0x48, 0x44, 0x52, 0x20, 0x34, 0x4b, 0x0a, 0x20, 0x20, 0x20, 0x00, 0x00, 0x00, 0xff,
+        0x00, 0x39, 0x31, 0x32, 0x4e, 0x54, 0x41, 0x42, 0x35, 0x4d, 0x31, 0x32, 0x33, 0x0a, 0x01, 0x0c,
+    ];
+
+    #[test]
+    fn parses_a_base_block() {
+        let monitor = parse(&LG_MONITOR).unwrap();
+        assert_eq!(monitor.manufacturer_id, "GSM");
+        assert_eq!(monitor.manufacturer.as_deref(), Some("LG"));
+        assert_eq!(monitor.model.as_deref(), Some("LG HDR 4K"));
+        assert_eq!(monitor.product_code, 0x7750);
+        assert_eq!(monitor.serial.as_deref(), Some("912NTAB5M123"));
+        assert_eq!(monitor.year, Some(2019));
+        assert_eq!(monitor.describe(), "LG HDR 4K (GSM, serial 912NTAB5M123)");
+    }
+
+    #[test]
+    fn falls_back_to_the_numeric_serial() {
+        let mut edid = LG_MONITOR;
+        // Turn the serial descriptor into a dummy one
+        edid[111] = 0x10;
+        assert_eq!(parse(&edid).unwrap().serial.as_deref(), Some("107187"));
+    }
+
+    #[test]
+    fn rejects_short_blocks_and_bad_headers() {
+        assert!(parse(&LG_MONITOR[..127]).is_none());
+        assert!(parse(&[]).is_none());
+        let mut edid = LG_MONITOR;
+        edid[0] = 0xff;
+        assert!(parse(&edid).is_none());
+    }
+
+    #[test]
+    fn unpacks_manufacturer_ids() {
+        assert_eq!(manufacturer_id(0x1e6d), "GSM");
+        assert_eq!(manufacturer_id(0x10ac), "DEL");
+        assert_eq!(manufacturer_id(0x4c2d), "SAM");
+    }
+}
@@ -0,0 +1,198 @@
+use std::sync::{Arc, Mutex};
+
+use eframe::egui;
+
+use crate::annotate::{self, Annotation};
+use crate::error::{CaptestError, ErrorKind};
+
+/// What dragging (or, for text, clicking) on the image adds.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Tool {
+    Arrow,
+    Box,
+    Text,
+    Blur,
+}
+
+/// A minimal editor window for marking up a capture before it is saved:
+/// arrows, boxes, captions and blurred areas, with undo.
+struct Editor {
+    /// The frame until it has been uploaded as a texture
+    image: Option<egui::ColorImage>,
+    texture: Option<egui::TextureHandle>,
+    width: u32,
+    height: u32,
+    tool: Tool,
+    caption: String,
+    annotations: Vec<Annotation>,
+    /// Where the current drag started and where the pointer is now, in frame pixels
+    drag: Option<((i32, i32), (i32, i32))>,
+    /// Set to the annotations when the user saves; left empty on cancel
+    outcome: Arc<Mutex<Option<Vec<Annotation>>>>,
+}
+
+/// Open the editor on an RGB8 frame and draw what the user adds onto it.
+/// Closing the editor without saving cancels the capture.
+pub fn annotate(width: u32, height: u32, mut rgb_data: Vec<u8>) -> Result<Vec<u8>, CaptestError> {
+    let outcome = Arc::new(Mutex::new(None));
+    let editor = Editor {
+        image: Some(egui::ColorImage::from_rgb([width as usize, height as usize], &rgb_data)),
+        texture: None,
+        width,
+        height,
+        tool: Tool::Arrow,
+        caption: String::new(),
+        annotations: Vec::new(),
+        drag: None,
+        outcome: outcome.clone(),
+    };
+    let options = eframe::NativeOptions {
+        viewport: egui::ViewportBuilder::default().with_title("captest: annotate capture").with_inner_size([1280.0, 800.0]),
+        ..Default::default()
+    };
+
+    status!("Annotate the capture, then save with Ctrl+S (Esc cancels)");
+    eframe::run_native("captest", options, Box::new(|_| Box::new(editor)))
+        .map_err(|e| CaptestError::new(ErrorKind::Failure, format!("Failed to open the annotation editor: {}", e)))?;
+    let Some(annotations) = outcome.lock().unwrap().take() else {
+        return Err(CaptestError::new(ErrorKind::Cancelled, "Annotation cancelled"));
+    };
+    annotate::render(width, height, &mut rgb_data, &annotations);
+    status!("Added {} annotations", annotations.len());
+    Ok(rgb_data)
+}
+
+impl Editor {
+    /// The annotation a drag from `from` to `to` makes with the current tool.
+    fn shape(&self, from: (i32, i32), to: (i32, i32)) -> Option<Annotation> {
+        // Ignore clicks that barely moved
+        if (to.0 - from.0).abs().max((to.1 - from.1).abs()) < 4 {
+            return None;
+        }
+        match self.tool {
+            Tool::Arrow => Some(Annotation::Arrow { from, to }),
+            Tool::Box => Some(Annotation::Box { from, to }),
+            Tool::Blur => Some(Annotation::Blur { from, to }),
+            Tool::Text => None,
+        }
+    }
+
+    fn toolbar(&mut self, ui: &mut egui::Ui) -> Option<bool> {
+        let mut finished = None;
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.tool, Tool::Arrow, "Arrow");
+            ui.selectable_value(&mut self.tool, Tool::Box, "Box");
+            ui.selectable_value(&mut self.tool, Tool::Text, "Text");
+            ui.selectable_value(&mut self.tool, Tool::Blur, "Blur");
+            if self.tool == Tool::Text {
+                ui.add(egui::TextEdit::singleline(&mut self.caption).hint_text("Type a caption, then click the image"));
+            }
+            ui.separator();
+            if ui.add_enabled(!self.annotations.is_empty(), egui::Button::new("Undo")).clicked() {
+                self.annotations.pop();
+            }
+            if ui.button("Save").clicked() {
+                finished = Some(true);
+            }
+            if ui.button("Cancel").clicked() {
+                finished = Some(false);
+            }
+        });
+        finished
+    }
+
+    /// Draw an annotation over the image shown at `origin`, `scale` screen
+    /// points per frame pixel. Blurs are previewed as grey boxes; the real
+    /// blur is applied on save.
+    fn preview(&self, painter: &egui::Painter, annotation: &Annotation, origin: egui::Pos2, scale: f32) {
+        let color = egui::Color32::from_rgb(annotate::COLOR[0], annotate::COLOR[1], annotate::COLOR[2]);
+        let frame_scale = annotate::scale(self.height) as f32;
+        let stroke = egui::Stroke::new(3.0 * frame_scale * scale, color);
+        let at = |(x, y): (i32, i32)| origin + egui::vec2(x as f32, y as f32) * scale;
+        match annotation {
+            Annotation::Arrow { from, to } => painter.arrow(at(*from), at(*to) - at(*from), stroke),
+            Annotation::Box { from, to } => {
+                painter.rect_stroke(egui::Rect::from_two_pos(at(*from), at(*to)), 0.0, stroke);
+            }
+            Annotation::Text { at: position, text } => {
+                let font = egui::FontId::monospace(20.0 * frame_scale * scale);
+                let galley = painter.layout_no_wrap(text.clone(), font, color);
+                painter.rect_filled(egui::Rect::from_min_size(at(*position), galley.size()), 0.0, egui::Color32::WHITE);
+                painter.galley(at(*position), galley, color);
+            }
+            Annotation::Blur { from, to } => {
+                painter.rect_filled(egui::Rect::from_two_pos(at(*from), at(*to)), 0.0, egui::Color32::from_gray(128).gamma_multiply(0.85));
+            }
+        }
+    }
+}
+
+impl eframe::App for Editor {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        let texture = match &self.texture {
+            Some(texture) => texture.clone(),
+            None => {
+                let image = self.image.take().unwrap_or_default();
+                self.texture.insert(ctx.load_texture("capture", image, egui::TextureOptions::LINEAR)).clone()
+            }
+        };
+
+        let (save, cancel, undo) = ctx.input(|input| (
+            input.modifiers.command && input.key_pressed(egui::Key::S),
+            input.key_pressed(egui::Key::Escape),
+            input.modifiers.command && input.key_pressed(egui::Key::Z),
+        ));
+        if undo {
+            self.annotations.pop();
+        }
+        let mut finished = save.then_some(true).or(cancel.then_some(false));
+        egui::TopBottomPanel::top("tools").show(ctx, |ui| {
+            finished = self.toolbar(ui).or(finished);
+        });
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            // Fit the frame into the window, never enlarging it
+            let available = ui.available_size();
+            let scale = (available.x / self.width as f32).min(available.y / self.height as f32).min(1.0);
+            let size = egui::vec2(self.width as f32, self.height as f32) * scale;
+            let response = ui.add(egui::Image::new((texture.id(), size)).sense(egui::Sense::click_and_drag()));
+            let origin = response.rect.min;
+            let pointer = response.interact_pointer_pos().map(|pos| {
+                let pos = (pos - origin) / scale;
+                (pos.x.round() as i32, pos.y.round() as i32)
+            });
+
+            if response.drag_started() {
+                self.drag = pointer.map(|pointer| (pointer, pointer));
+            } else if let (Some((_, end)), Some(pointer)) = (&mut self.drag, pointer) {
+                *end = pointer;
+            }
+            if response.drag_stopped() {
+                if let Some(annotation) = self.drag.take().and_then(|(from, to)| self.shape(from, to)) {
+                    self.annotations.push(annotation);
+                }
+            } else if response.clicked()
+                && self.tool == Tool::Text
+                && !self.caption.trim().is_empty()
+                && let Some(at) = pointer
+            {
+                self.annotations.push(Annotation::Text { at, text: std::mem::take(&mut self.caption) });
+            }
+
+            let painter = ui.painter_at(response.rect);
+            for annotation in &self.annotations {
+                self.preview(&painter, annotation, origin, scale);
+            }
+            if let Some(annotation) = self.drag.and_then(|(from, to)| self.shape(from, to)) {
+                self.preview(&painter, &annotation, origin, scale);
+            }
+        });
+
+        if let Some(save) = finished {
+            if save {
+                *self.outcome.lock().unwrap() = Some(self.annotations.clone());
+            }
+            ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+        }
+    }
+}
@@ -0,0 +1,384 @@
+use serde::Serialize;
+
+use crate::layout::LineBox;
+
+/// Brightness step between neighbouring pixels that counts as an edge.
+const EDGE_STEP: i16 = 24;
+/// Largest brightness step inside one flat area, e.g. a gradient button face.
+const FLAT_STEP: i16 = 4;
+/// Share of a flat area's bounding box it must fill to be a widget face; the
+/// rest is the label or icon drawn on it.
+const MIN_FILL: f64 = 0.55;
+/// Share of the pixels just outside a widget that must be edges: a border or
+/// a change of color all the way round.
+const MIN_OUTLINE: f64 = 0.75;
+/// Distance content may be off the middle of a button, as a share of its width.
+const CENTERED: f64 = 0.15;
+/// Edges of letters closer than this merge into one word when looking for icons.
+const WORD_GAP: usize = 2;
+/// Share of the smaller of two detections covered by the other for it to be a duplicate.
+const DUPLICATE: f64 = 0.7;
+
+/// A widget found on screen, in frame pixels.
+#[derive(Serialize, Debug)]
+pub struct Element {
+    /// `button`, `field`, `checkbox` or `icon`
+    pub kind: &'static str,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// 0 to 1; how clearly the area looks like its kind
+    pub score: f64,
+    /// Text recognized inside the element, with `--ocr`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+}
+
+impl Element {
+    pub fn describe(&self) -> String {
+        let text = self.text.as_ref().map(|text| format!(" {:?}", text)).unwrap_or_default();
+        format!("{} at {},{} {}x{} ({:.2}){}", self.kind, self.x, self.y, self.width, self.height, self.score, text)
+    }
+
+    fn area(&self) -> f64 {
+        self.width as f64 * self.height as f64
+    }
+
+    fn overlap(&self, other: &Element) -> f64 {
+        let width = (self.x + self.width).min(other.x + other.width).saturating_sub(self.x.max(other.x));
+        let height = (self.y + self.height).min(other.y + other.height).saturating_sub(self.y.max(other.y));
+        width as f64 * height as f64
+    }
+}
+
+/// Bounding box and pixel count of a connected area.
+#[derive(Clone, Copy)]
+struct Area {
+    left: usize,
+    top: usize,
+    right: usize,
+    bottom: usize,
+    pixels: usize,
+}
+
+impl Area {
+    fn width(&self) -> usize {
+        self.right - self.left + 1
+    }
+
+    fn height(&self) -> usize {
+        self.bottom - self.top + 1
+    }
+}
+
+/// A brightness image with its edge map.
+struct Edges {
+    width: usize,
+    height: usize,
+    gray: Vec<i16>,
+    edge: Vec<bool>,
+}
+
+impl Edges {
+    fn new(width: usize, height: usize, rgb_data: &[u8]) -> Self {
+        let gray: Vec<i16> = rgb_data.chunks_exact(3)
+            .map(|p| ((p[0] as u32 * 77 + p[1] as u32 * 150 + p[2] as u32 * 29) >> 8) as i16)
+            .collect();
+        let mut edge = vec![false; width * height];
+        for y in 0..height {
+            for x in 0..width {
+                let at = y * width + x;
+                let right = x + 1 < width && (gray[at + 1] - gray[at]).abs() >= EDGE_STEP;
+                let below = y + 1 < height && (gray[at + width] - gray[at]).abs() >= EDGE_STEP;
+                edge[at] = right || below;
+            }
+        }
+        Self { width, height, gray, edge }
+    }
+
+    /// Share of the pixels on the rectangle just outside `area` that are
+    /// edges; the frame's border counts as one.
+    fn outline(&self, area: &Area) -> f64 {
+        let (mut edges, mut total) = (0, 0);
+        let mut check = |x: isize, y: isize| {
+            let outside = x < 0 || y < 0 || x >= self.width as isize || y >= self.height as isize;
+            total += 1;
+            edges += (outside || self.edge[y as usize * self.width + x as usize]) as usize;
+        };
+        let (left, top, right, bottom) = (area.left as isize - 1, area.top as isize - 1, area.right as isize + 1, area.bottom as isize + 1);
+        for x in left..=right {
+            check(x, top);
+            check(x, bottom);
+        }
+        for y in top + 1..bottom {
+            check(left, y);
+            check(right, y);
+        }
+        edges as f64 / total.max(1) as f64
+    }
+
+    /// Bounding box of the edges inside `area`, e.g. a label, and their share
+    /// of its pixels.
+    fn content(&self, area: &Area) -> Option<(Area, f64)> {
+        let mut content: Option<Area> = None;
+        for y in area.top + 1..area.bottom {
+            for x in area.left + 1..area.right {
+                if self.edge[y * self.width + x] {
+                    let found = content.get_or_insert(Area { left: x, top: y, right: x, bottom: y, pixels: 0 });
+                    found.left = found.left.min(x);
+                    found.right = found.right.max(x);
+                    found.bottom = y;
+                    found.pixels += 1;
+                }
+            }
+        }
+        content.map(|content| (content, content.pixels as f64 / (area.width() * area.height()) as f64))
+    }
+
+    /// Mean brightness of `area`'s pixels and of the ring two pixels outside it.
+    fn contrast(&self, area: &Area) -> f64 {
+        let mean = |pixels: &mut dyn Iterator<Item = (usize, usize)>| {
+            let (sum, count) = pixels.fold((0i64, 0i64), |(sum, count), (x, y)| (sum + self.gray[y * self.width + x] as i64, count + 1));
+            sum as f64 / count.max(1) as f64
+        };
+        let inside = mean(&mut (area.top..=area.bottom).flat_map(|y| (area.left..=area.right).map(move |x| (x, y))));
+        let (left, top) = (area.left.saturating_sub(2), area.top.saturating_sub(2));
+        let (right, bottom) = ((area.right + 2).min(self.width - 1), (area.bottom + 2).min(self.height - 1));
+        let ring = (left..=right).flat_map(|x| [(x, top), (x, bottom)])
+            .chain((top..=bottom).flat_map(|y| [(left, y), (right, y)]));
+        (inside - mean(&mut ring.into_iter())).abs()
+    }
+}
+
+/// Find buttons, text fields, checkboxes and icons in an RGB8 frame with
+/// classical image analysis: widgets are flat areas with a clean outline
+/// (buttons have their label centered, fields start it at the left or are
+/// empty, checkboxes are small squares), icons are compact clusters of edges
+/// that aren't part of a line of text.
+pub fn detect(width: u32, height: u32, rgb_data: &[u8]) -> Vec<Element> {
+    let (w, h) = (width as usize, height as usize);
+    if w < 8 || h < 8 || rgb_data.len() < w * h * 3 {
+        return Vec::new();
+    }
+    let edges = Edges::new(w, h, rgb_data);
+
+    let mut elements: Vec<Element> = flat_areas(&edges).iter().filter_map(|area| widget(&edges, area)).collect();
+    let icons: Vec<Element> = icon_areas(&edges).iter()
+        .filter_map(|area| icon(&edges, area, rgb_data))
+        // A checkbox's border and a field's text look like icons too
+        .filter(|icon| !elements.iter().any(|element| element.kind != "button" && icon.overlap(element) >= DUPLICATE * element.area().min(icon.area())))
+        .collect();
+    elements.extend(icons);
+
+    // Keep the clearest of detections that cover each other
+    elements.sort_by(|a, b| b.score.total_cmp(&a.score));
+    let mut kept: Vec<Element> = Vec::new();
+    for element in elements {
+        if !kept.iter().any(|other| other.kind == element.kind && element.overlap(other) >= DUPLICATE * element.area().min(other.area())) {
+            kept.push(element);
+        }
+    }
+    kept.sort_by_key(|element| (element.y, element.x));
+    kept
+}
+
+/// Areas of nearly uniform brightness bounded by edges.
+fn flat_areas(edges: &Edges) -> Vec<Area> {
+    let (w, h) = (edges.width, edges.height);
+    let mut visited = vec![false; w * h];
+    let mut areas = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0..w * h {
+        if visited[start] || edges.edge[start] {
+            continue;
+        }
+        visited[start] = true;
+        stack.push(start);
+        let mut area = Area { left: start % w, top: start / w, right: start % w, bottom: start / w, pixels: 0 };
+        while let Some(at) = stack.pop() {
+            let (x, y) = (at % w, at / w);
+            area.left = area.left.min(x);
+            area.right = area.right.max(x);
+            area.top = area.top.min(y);
+            area.bottom = area.bottom.max(y);
+            area.pixels += 1;
+            let neighbours = [
+                (x > 0).then(|| at - 1),
+                (x + 1 < w).then(|| at + 1),
+                (y > 0).then(|| at - w),
+                (y + 1 < h).then(|| at + w),
+            ];
+            for next in neighbours.into_iter().flatten() {
+                if !visited[next] && !edges.edge[next] && (edges.gray[next] - edges.gray[at]).abs() <= FLAT_STEP {
+                    visited[next] = true;
+                    stack.push(next);
+                }
+            }
+        }
+        areas.push(area);
+    }
+    areas
+}
+
+/// Judge a flat area as a button, field or checkbox.
+fn widget(edges: &Edges, area: &Area) -> Option<Element> {
+    let (width, height) = (area.width(), area.height());
+    if width < 10 || !(10..=96).contains(&height) || width > edges.width * 3 / 4 {
+        return None;
+    }
+    let fill = area.pixels as f64 / (width * height) as f64;
+    let outline = edges.outline(area);
+    if fill < MIN_FILL || outline < MIN_OUTLINE {
+        return None;
+    }
+    let aspect = width as f64 / height as f64;
+    let content = edges.content(area);
+
+    let kind = match content {
+        _ if (10..=28).contains(&height) && (0.8..=1.25).contains(&aspect) => "checkbox",
+        None if aspect >= 3.0 && height >= 16 => "field",
+        Some((label, _)) if (16..=80).contains(&height) && (1.2..=12.0).contains(&aspect) => {
+            let label_middle = (label.left + label.right) as f64 / 2.0;
+            let middle = (area.left + area.right) as f64 / 2.0;
+            let starts_left = ((label.left - area.left) as f64) < width as f64 * 0.2;
+            match ((label_middle - middle).abs() / width as f64) <= CENTERED {
+                true => "button",
+                false if starts_left && aspect >= 3.0 => "field",
+                false => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    let contrast = (edges.contrast(area) / 48.0).min(1.0);
+    // A field may look just like the page around it; its border tells it apart
+    let contrast = if kind == "field" { contrast.max(outline) } else { contrast };
+    let score = (fill + outline + contrast) / 3.0;
+    // The last column and row of a face are edges themselves
+    let area = Area { right: (area.right + 1).min(edges.width - 1), bottom: (area.bottom + 1).min(edges.height - 1), ..*area };
+    Some(element(kind, &area, score))
+}
+
+/// Clusters of edges, with letters closer than WORD_GAP joined into words.
+fn icon_areas(edges: &Edges) -> Vec<Area> {
+    let (w, h) = (edges.width, edges.height);
+    // Grow the edges, so each word or icon becomes one connected blob
+    let mut grown = vec![false; w * h];
+    for y in 0..h {
+        for x in 0..w {
+            if edges.edge[y * w + x] {
+                for gy in y.saturating_sub(WORD_GAP)..=(y + WORD_GAP).min(h - 1) {
+                    grown[gy * w + x.saturating_sub(WORD_GAP)..=gy * w + (x + WORD_GAP).min(w - 1)].fill(true);
+                }
+            }
+        }
+    }
+
+    let mut visited = vec![false; w * h];
+    let mut areas = Vec::new();
+    let mut stack = Vec::new();
+    for start in 0..w * h {
+        if visited[start] || !grown[start] {
+            continue;
+        }
+        visited[start] = true;
+        stack.push(start);
+        let mut area = Area { left: start % w, top: start / w, right: start % w, bottom: start / w, pixels: 0 };
+        while let Some(at) = stack.pop() {
+            let (x, y) = (at % w, at / w);
+            area.left = area.left.min(x);
+            area.right = area.right.max(x);
+            area.top = area.top.min(y);
+            area.bottom = area.bottom.max(y);
+            area.pixels += 1;
+            for ny in y.saturating_sub(1)..=(y + 1).min(h - 1) {
+                for nx in x.saturating_sub(1)..=(x + 1).min(w - 1) {
+                    let next = ny * w + nx;
+                    if !visited[next] && grown[next] {
+                        visited[next] = true;
+                        stack.push(next);
+                    }
+                }
+            }
+        }
+        // Undo the growth, so the box fits the icon
+        area.left = (area.left + WORD_GAP).min(area.right);
+        area.top = (area.top + WORD_GAP).min(area.bottom);
+        area.right = area.right.saturating_sub(WORD_GAP).max(area.left);
+        area.bottom = area.bottom.saturating_sub(WORD_GAP).max(area.top);
+        areas.push(area);
+    }
+
+    // Words sit in a row with others of the same height; icons stand alone
+    // or next to a single label
+    let neighbours = |area: &Area| areas.iter().filter(|other| {
+        let same_height = (other.height() * 10).abs_diff(area.height() * 10) <= area.height() * 4;
+        let same_row = (other.top + other.bottom).abs_diff(area.top + area.bottom) <= area.height();
+        let gap = other.left.saturating_sub(area.right).max(area.left.saturating_sub(other.right));
+        same_height && same_row && gap > 0 && gap <= area.height()
+    }).count();
+    areas.iter().filter(|area| neighbours(area) <= 1).copied().collect()
+}
+
+/// Judge an edge cluster as an icon: square, 12 to 64 pixels, busy but not
+/// solid.
+fn icon(edges: &Edges, area: &Area, rgb_data: &[u8]) -> Option<Element> {
+    let (width, height) = (area.width(), area.height());
+    let aspect = width as f64 / height as f64;
+    if !(12..=64).contains(&width) || !(12..=64).contains(&height) || !(0.75..=1.33).contains(&aspect) {
+        return None;
+    }
+    let (_, density) = edges.content(area)?;
+    if !(0.08..=0.6).contains(&density) {
+        return None;
+    }
+
+    // Colored glyphs are more likely icons than letters
+    let saturation = (area.top..=area.bottom)
+        .flat_map(|y| (area.left..=area.right).map(move |x| (y * edges.width + x) * 3))
+        .map(|at| {
+            let pixel = &rgb_data[at..at + 3];
+            let (max, min) = (pixel.iter().max().copied().unwrap_or(0), pixel.iter().min().copied().unwrap_or(0));
+            (max - min) as f64 / 255.0
+        })
+        .sum::<f64>() / (width * height) as f64;
+    let squareness = 1.0 - (aspect.ln().abs() / 1.33f64.ln()).min(1.0);
+    let score = (0.5 + squareness * 0.3 + (saturation * 2.0).min(1.0) * 0.2).min(1.0);
+    Some(element("icon", area, score))
+}
+
+fn element(kind: &'static str, area: &Area, score: f64) -> Element {
+    Element {
+        kind,
+        x: area.left as u32,
+        y: area.top as u32,
+        width: area.width() as u32,
+        height: area.height() as u32,
+        score: (score * 100.0).round() / 100.0,
+        text: None,
+    }
+}
+
+/// Fill in each button's, field's and checkbox's text from the OCR words
+/// centered inside it; a checkbox takes the label just to its right.
+pub fn label(elements: &mut [Element], lines: &[LineBox]) {
+    for element in elements.iter_mut().filter(|element| element.kind != "icon") {
+        let (left, top) = (element.x as i32, element.y as i32);
+        let (mut right, bottom) = (left + element.width as i32, top + element.height as i32);
+        if element.kind == "checkbox" {
+            right += element.width as i32 * 12;
+        }
+        let words: Vec<&str> = lines.iter()
+            .flat_map(|line| &line.words)
+            .filter(|word| {
+                let (x, y) = ((word.left + word.right) / 2, (word.top + word.bottom) / 2);
+                x >= left && x < right && y >= top && y < bottom
+            })
+            .map(|word| word.text.as_str())
+            .collect();
+        if !words.is_empty() {
+            element.text = Some(words.join(" "));
+        }
+    }
+}
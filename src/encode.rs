@@ -0,0 +1,141 @@
+use clap::ValueEnum;
+
+use crate::config;
+use crate::timings::{self, Stage};
+
+/// JPEG encoder implementation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum Encoder {
+    /// turbojpeg when built with the `turbojpeg` feature, otherwise image
+    #[default]
+    Auto,
+    /// Pure-Rust encoder from the image crate
+    Image,
+    /// libjpeg-turbo; several times faster on 4K frames (needs the `turbojpeg` feature)
+    Turbojpeg,
+}
+
+impl Encoder {
+    /// The encoder `Auto` stands for in this build.
+    fn resolve(self) -> Encoder {
+        match self {
+            Encoder::Auto if cfg!(feature = "turbojpeg") => Encoder::Turbojpeg,
+            Encoder::Auto => Encoder::Image,
+            encoder => encoder,
+        }
+    }
+}
+
+/// File format captures are saved in.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ImageFormat {
+    /// Lossless, best for screenshots of text and UI
+    Png,
+    /// Lossy and small; --quality sets the trade-off
+    #[default]
+    Jpeg,
+    /// Lossless WebP, usually smaller than PNG
+    Webp,
+    /// Uncompressed bitmap
+    Bmp,
+}
+
+impl ImageFormat {
+    /// The format a filename's extension names, if any.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let extension = std::path::Path::new(path).extension()?.to_string_lossy().to_lowercase();
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png),
+            "jpg" | "jpeg" => Some(ImageFormat::Jpeg),
+            "webp" => Some(ImageFormat::Webp),
+            "bmp" => Some(ImageFormat::Bmp),
+            _ => None,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Jpeg => "jpg",
+            ImageFormat::Webp => "webp",
+            ImageFormat::Bmp => "bmp",
+        }
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "image/png",
+            ImageFormat::Jpeg => "image/jpeg",
+            ImageFormat::Webp => "image/webp",
+            ImageFormat::Bmp => "image/bmp",
+        }
+    }
+}
+
+/// Encode tightly packed RGB8 pixels as `format`. `quality` (1-100) only
+/// applies to JPEG; the other formats are lossless.
+pub fn encode_rgb8(width: u32, height: u32, rgb_data: &[u8], format: ImageFormat, quality: u8) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    use image::codecs::bmp::BmpEncoder;
+    use image::codecs::png::PngEncoder;
+    use image::codecs::webp::WebPEncoder;
+    use image::ImageEncoder;
+
+    if format == ImageFormat::Jpeg {
+        return rgb8_to_jpeg(width, height, rgb_data, quality);
+    }
+    if rgb_data.len() != width as usize * height as usize * 3 {
+        return Err("Failed to create image buffer".into());
+    }
+    let _timing = timings::span(Stage::Encode);
+    let mut bytes = Vec::new();
+    match format {
+        ImageFormat::Png => PngEncoder::new(&mut bytes).write_image(rgb_data, width, height, image::ColorType::Rgb8)?,
+        ImageFormat::Webp => WebPEncoder::new_lossless(&mut bytes).write_image(rgb_data, width, height, image::ColorType::Rgb8)?,
+        ImageFormat::Bmp => BmpEncoder::new(&mut bytes).write_image(rgb_data, width, height, image::ColorType::Rgb8)?,
+        ImageFormat::Jpeg => unreachable!(),
+    }
+    Ok(bytes)
+}
+
+/// Encode tightly packed RGB8 pixels as JPEG with the configured encoder.
+pub fn rgb8_to_jpeg(width: u32, height: u32, rgb_data: &[u8], quality: u8) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let _timing = timings::span(Stage::Encode);
+    if rgb_data.len() != width as usize * height as usize * 3 {
+        return Err("Failed to create image buffer".into());
+    }
+
+    match config::settings().encoder.resolve() {
+        Encoder::Turbojpeg => turbojpeg_encode(width, height, rgb_data, quality),
+        _ => image_encode(width, height, rgb_data, quality),
+    }
+}
+
+fn image_encode(width: u32, height: u32, rgb_data: &[u8], quality: u8) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    use image::codecs::jpeg::JpegEncoder;
+    use image::ImageEncoder;
+
+    // Encode straight from the borrowed pixels; JPEG output is usually well
+    // under a tenth of the raw size
+    let mut jpeg_bytes = Vec::with_capacity(rgb_data.len() / 10);
+    let encoder = JpegEncoder::new_with_quality(&mut jpeg_bytes, quality);
+    encoder.write_image(rgb_data, width, height, image::ColorType::Rgb8)?;
+    Ok(jpeg_bytes)
+}
+
+#[cfg(feature = "turbojpeg")]
+fn turbojpeg_encode(width: u32, height: u32, rgb_data: &[u8], quality: u8) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let image = turbojpeg::Image {
+        pixels: rgb_data,
+        width: width as usize,
+        pitch: width as usize * 3,
+        height: height as usize,
+        format: turbojpeg::PixelFormat::RGB,
+    };
+    let jpeg = turbojpeg::compress(image, quality as i32, turbojpeg::Subsamp::Sub2x2)?;
+    Ok(jpeg.to_vec())
+}
+
+#[cfg(not(feature = "turbojpeg"))]
+fn turbojpeg_encode(_width: u32, _height: u32, _rgb_data: &[u8], _quality: u8) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    Err("The turbojpeg encoder is not available; rebuild with `--features turbojpeg`".into())
+}
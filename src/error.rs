@@ -0,0 +1,115 @@
+use std::fmt;
+
+/// Failure categories with a stable process exit code each, so scripts can
+/// tell why a run failed without parsing messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// Any failure without a more specific category
+    Failure,
+    /// The requested screen or window does not exist
+    TargetNotFound,
+    /// Screen recording permission has not been granted
+    PermissionDenied,
+    /// No frame could be obtained from the capture backend
+    CaptureTimeout,
+    /// OCR was requested but failed
+    OcrFailed,
+    /// LLM analysis was requested but failed
+    LlmFailed,
+    /// Screen capture is not supported on this system
+    Unsupported,
+    /// The operation was cancelled, e.g. by Ctrl-C
+    Cancelled,
+    /// Processing would exceed the configured memory limit
+    MemoryLimit,
+    /// The captured window closed during a continuous capture
+    TargetClosed,
+    /// Sensitive windows were visible and couldn't be excluded (`--privacy-strict`)
+    PrivacyBlocked,
+    /// Compared images differ by more than the allowed threshold
+    DiffOverThreshold,
+    /// Uploading a capture (`--share` or `--upload`) failed
+    UploadFailed,
+    /// The frame came back black, most likely protected content (`--allow-black-frames` overrides)
+    ProtectedContent,
+    /// The frame was one color or had no detail (`--no-validate` overrides)
+    BlankFrame,
+    /// The image searched for isn't on screen
+    ImageNotFound,
+    /// A region's pixels don't hash to the expected value
+    HashMismatch,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Failure => 1,
+            ErrorKind::TargetNotFound => 2,
+            ErrorKind::PermissionDenied => 3,
+            ErrorKind::CaptureTimeout => 4,
+            ErrorKind::OcrFailed => 5,
+            ErrorKind::LlmFailed => 6,
+            ErrorKind::Unsupported => 7,
+            ErrorKind::MemoryLimit => 8,
+            ErrorKind::TargetClosed => 9,
+            ErrorKind::PrivacyBlocked => 10,
+            ErrorKind::DiffOverThreshold => 11,
+            ErrorKind::UploadFailed => 12,
+            ErrorKind::ProtectedContent => 13,
+            ErrorKind::BlankFrame => 14,
+            ErrorKind::ImageNotFound => 15,
+            ErrorKind::HashMismatch => 16,
+            // Same as a shell reports for a process ended by SIGINT
+            ErrorKind::Cancelled => 130,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Failure => "failure",
+            ErrorKind::TargetNotFound => "target_not_found",
+            ErrorKind::PermissionDenied => "permission_denied",
+            ErrorKind::CaptureTimeout => "capture_timeout",
+            ErrorKind::OcrFailed => "ocr_failed",
+            ErrorKind::LlmFailed => "llm_failed",
+            ErrorKind::Unsupported => "unsupported",
+            ErrorKind::Cancelled => "cancelled",
+            ErrorKind::MemoryLimit => "memory_limit",
+            ErrorKind::TargetClosed => "target_closed",
+            ErrorKind::PrivacyBlocked => "privacy_blocked",
+            ErrorKind::DiffOverThreshold => "diff_over_threshold",
+            ErrorKind::UploadFailed => "upload_failed",
+            ErrorKind::ProtectedContent => "protected_content",
+            ErrorKind::BlankFrame => "blank_frame",
+            ErrorKind::ImageNotFound => "image_not_found",
+            ErrorKind::HashMismatch => "hash_mismatch",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct CaptestError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl CaptestError {
+    pub fn new(kind: ErrorKind, message: impl Into<String>) -> Self {
+        Self { kind, message: message.into() }
+    }
+}
+
+impl fmt::Display for CaptestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CaptestError {}
+
+/// Classify an arbitrary error; errors not raised as `CaptestError` are generic failures.
+pub fn error_kind(err: &(dyn std::error::Error + 'static)) -> ErrorKind {
+    err.downcast_ref::<CaptestError>()
+        .map(|e| e.kind)
+        .unwrap_or(ErrorKind::Failure)
+}
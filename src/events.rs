@@ -0,0 +1,290 @@
+use std::collections::HashSet;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, SystemTime};
+
+use futures_core::Stream;
+use scap::frame::Frame;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+use crate::capture_manager::CaptureManager;
+use crate::change::{ChangedRegion, IgnoreRegion, Signature};
+use crate::follow::{WindowChange, WindowFollower};
+use crate::pause::{Gap, PauseMonitor};
+use crate::privacy::PrivacyMonitor;
+use crate::session_pool::{self, TargetKey};
+use crate::{config, convert, ocr, privacy};
+
+/// How many undelivered events a subscription buffers before polling pauses.
+const EVENT_BUFFER: usize = 16;
+
+/// What a subscription reports about its target.
+#[derive(Clone)]
+pub struct SubscribeOptions {
+    /// Time between compared frames
+    pub interval: Duration,
+    /// Minimum mean difference (percent) that counts as a change
+    pub threshold: f64,
+    /// Send the pixels of each changed frame
+    pub frames: bool,
+    /// OCR each changed frame and send the text lines and their delta
+    pub ocr: bool,
+    /// Letterbox frames of a resized window into the first frame's size
+    /// instead of sending them at the window's new size
+    pub lock_size: bool,
+    /// When the window closes, wait for a window with the same title instead
+    /// of ending the subscription
+    pub reattach_by_title: bool,
+    /// Regions whose changes are ignored
+    pub ignore: Vec<IgnoreRegion>,
+}
+
+impl Default for SubscribeOptions {
+    fn default() -> Self {
+        Self { interval: Duration::from_secs(1), threshold: 1.0, frames: true, ocr: false, lock_size: false, reattach_by_title: false, ignore: Vec::new() }
+    }
+}
+
+/// A changed frame as RGB8.
+#[derive(Clone)]
+pub struct CapturedFrame {
+    pub width: u32,
+    pub height: u32,
+    pub rgb_data: Arc<Vec<u8>>,
+    pub captured_at: SystemTime,
+}
+
+/// Events for one change are sent in order: `Frame` (if requested), `Changed`,
+/// then `Ocr` (if requested).
+#[derive(Clone)]
+pub enum CaptureEvent {
+    Frame(CapturedFrame),
+    /// How much and where the frame changed; both None for the first frame
+    Changed { difference: Option<f64>, region: Option<ChangedRegion> },
+    /// All text lines of the changed frame, plus the lines that appeared and
+    /// disappeared since the previous OCR
+    Ocr { lines: Vec<String>, added: Vec<String>, removed: Vec<String> },
+    /// The watched window closed. The subscription ends afterwards unless it
+    /// is `reattaching` to the next window with the same title.
+    Closed { title: String, reattaching: bool },
+    /// Capture resumed on a new window with the closed window's title
+    Reattached { title: String },
+    /// Capture paused because the session was locked, the screensaver
+    /// started or the display went to sleep
+    Paused { reason: &'static str },
+    /// Capture resumed after a pause
+    Resumed { gap: Gap },
+    /// Fatal errors end the subscription; OCR failures don't
+    Error { message: String, fatal: bool },
+}
+
+/// A live stream of [`CaptureEvent`]s for one target. Polling stops when the
+/// subscription is dropped.
+pub struct Subscription {
+    events: mpsc::Receiver<CaptureEvent>,
+    task: JoinHandle<()>,
+}
+
+impl Subscription {
+    /// The next event, or None once the subscription has ended.
+    pub async fn next(&mut self) -> Option<CaptureEvent> {
+        self.events.recv().await
+    }
+}
+
+impl Stream for Subscription {
+    type Item = CaptureEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<CaptureEvent>> {
+        self.events.poll_recv(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Start watching `key` for changes. Must be called within a tokio runtime.
+pub fn subscribe(key: TargetKey, options: SubscribeOptions) -> Subscription {
+    let (sender, events) = mpsc::channel(EVENT_BUFFER);
+    let task = tokio::spawn(poll_target(key, options, sender));
+    Subscription { events, task }
+}
+
+async fn poll_target(key: TargetKey, options: SubscribeOptions, sender: mpsc::Sender<CaptureEvent>) {
+    let fps = (1.0 / options.interval.as_secs_f64().max(0.001)).ceil().max(1.0) as u32;
+    let mut capture_options = match session_pool::capture_options(key, fps) {
+        Ok(capture_options) => capture_options,
+        Err(e) => {
+            let _ = sender.send(CaptureEvent::Error { message: e.to_string(), fatal: true }).await;
+            return;
+        }
+    };
+    // By default only the newest frame is compared, older ones are dropped
+    // unseen; --buffer-depth keeps more. The manager (and its capture thread)
+    // goes away when the subscription does.
+    let captures = CaptureManager::new(config::settings().buffer_depth.unwrap_or(1));
+    let mut queue = captures.start(key, capture_options.clone());
+    let mut follower = WindowFollower::new(&capture_options);
+    let mut locked_size: Option<(u32, u32)> = None;
+    let mut last_signature: Option<Signature> = None;
+    let mut last_lines: Vec<String> = Vec::new();
+    let mut session_ended = false;
+    let mut monitor = PauseMonitor::default();
+    let mut privacy_monitor = PrivacyMonitor::default();
+
+    loop {
+        if let Some(reason) = monitor.poll() {
+            captures.stop(key);
+            if sender.send(CaptureEvent::Paused { reason }).await.is_err() {
+                return;
+            }
+            let gap = tokio::select! {
+                _ = sender.closed() => return,
+                gap = monitor.wait_for_resume(reason) => gap,
+            };
+            queue = captures.start(key, capture_options.clone());
+            if sender.send(CaptureEvent::Resumed { gap }).await.is_err() {
+                return;
+            }
+        }
+        if let Some(follower) = &mut follower {
+            let change = if session_ended { Some(WindowChange::Closed) } else { follower.poll(&capture_options) };
+            match change {
+                Some(WindowChange::Moved(moved)) => {
+                    captures.stop(key);
+                    queue = captures.start(key, moved.clone());
+                    capture_options = moved;
+                }
+                Some(WindowChange::Closed) => {
+                    let title = follower.title().to_string();
+                    let reattaching = options.reattach_by_title;
+                    let closed = CaptureEvent::Closed { title: title.clone(), reattaching };
+                    if sender.send(closed).await.is_err() || !reattaching {
+                        return;
+                    }
+                    let reattached = tokio::select! {
+                        _ = sender.closed() => return,
+                        reattached = follower.wait_for_reattach(&capture_options) => reattached,
+                    };
+                    captures.stop(key);
+                    queue = captures.start(key, reattached.clone());
+                    capture_options = reattached;
+                    session_ended = false;
+                    // The new window's first frame is reported as an initial frame
+                    last_signature = None;
+                    if sender.send(CaptureEvent::Reattached { title }).await.is_err() {
+                        return;
+                    }
+                }
+                None => {}
+            }
+        }
+        match privacy_monitor.poll(&capture_options) {
+            Ok(false) => {}
+            Ok(true) => {
+                if let Err(e) = privacy::exclude_sensitive(&mut capture_options) {
+                    let _ = sender.send(CaptureEvent::Error { message: e.to_string(), fatal: true }).await;
+                    return;
+                }
+                captures.stop(key);
+                queue = captures.start(key, capture_options.clone());
+            }
+            Err(e) => {
+                let _ = sender.send(CaptureEvent::Error { message: e.to_string(), fatal: true }).await;
+                return;
+            }
+        }
+
+        let frame_queue = queue.clone();
+        let interval = options.interval;
+        let frame = match tokio::task::spawn_blocking(move || frame_queue.pop(interval)).await {
+            Ok(Some(frame)) => frame,
+            // The backend may end the session of a window that went away
+            Ok(None) if queue.is_closed() && follower.as_ref().is_some_and(WindowFollower::is_gone) => {
+                session_ended = true;
+                continue;
+            }
+            Ok(None) if queue.is_closed() => {
+                let message = format!("Capture session for {} ended", key);
+                let _ = sender.send(CaptureEvent::Error { message, fatal: true }).await;
+                return;
+            }
+            // No new frame, so nothing changed
+            Ok(None) => continue,
+            Err(e) => {
+                let _ = sender.send(CaptureEvent::Error { message: e.to_string(), fatal: true }).await;
+                return;
+            }
+        };
+        let Frame::Video(mut video_frame) = frame else {
+            continue;
+        };
+        privacy::mask_frame(&capture_options, &mut video_frame);
+        let (mut width, mut height, mut rgb_data) = match convert::video_frame_to_stream_rgb8(video_frame, None, None) {
+            Ok(converted) => converted,
+            Err(message) => {
+                let _ = sender.send(CaptureEvent::Error { message, fatal: false }).await;
+                continue;
+            }
+        };
+        if options.lock_size {
+            let (locked_width, locked_height) = *locked_size.get_or_insert((width, height));
+            rgb_data = convert::letterbox_rgb8(width, height, rgb_data, locked_width, locked_height);
+            (width, height) = (locked_width, locked_height);
+        }
+
+        let signature = Signature::new(width, height, &rgb_data, &options.ignore);
+        let difference = last_signature.as_ref().map(|last| last.difference(&signature));
+        if difference.is_some_and(|difference| difference < options.threshold) {
+            continue;
+        }
+        let region = last_signature.as_ref().and_then(|last| last.changed_region(&signature));
+        last_signature = Some(signature);
+
+        let rgb_data = Arc::new(rgb_data);
+        let mut events = Vec::new();
+        if options.frames {
+            events.push(CaptureEvent::Frame(CapturedFrame {
+                width,
+                height,
+                rgb_data: rgb_data.clone(),
+                captured_at: SystemTime::now(),
+            }));
+        }
+        events.push(CaptureEvent::Changed { difference, region });
+        for event in events {
+            if sender.send(event).await.is_err() {
+                return;
+            }
+        }
+
+        if options.ocr {
+            let event = match ocr::extract_text(width, height, &rgb_data, true).await.map_err(|e| e.to_string()) {
+                Ok(lines) => {
+                    let event = ocr_delta(&last_lines, &lines);
+                    last_lines = lines;
+                    event
+                }
+                Err(message) => CaptureEvent::Error { message: format!("OCR extraction failed: {}", message), fatal: false },
+            };
+            if sender.send(event).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn ocr_delta(previous: &[String], lines: &[String]) -> CaptureEvent {
+    let before: HashSet<&String> = previous.iter().collect();
+    let after: HashSet<&String> = lines.iter().collect();
+    CaptureEvent::Ocr {
+        lines: lines.to_vec(),
+        added: lines.iter().filter(|line| !before.contains(line)).cloned().collect(),
+        removed: previous.iter().filter(|line| !after.contains(line)).cloned().collect(),
+    }
+}
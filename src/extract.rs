@@ -0,0 +1,188 @@
+use std::str::FromStr;
+
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::track::csv_field;
+
+/// An `--extract` pattern: `NAME=REGEX`, whose first capture group (or whole
+/// match) is the value of NAME, or a bare regex whose named groups
+/// (`(?P<name>...)`) are the values.
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    name: Option<String>,
+    regex: Regex,
+}
+
+impl FromStr for Pattern {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        let invalid = |e: regex::Error| format!("Invalid --extract pattern \"{}\": {}", value, e);
+        if let Some((name, regex)) = value.split_once('=')
+            && !name.is_empty()
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_')
+        {
+            return Ok(Self { name: Some(name.to_string()), regex: Regex::new(regex).map_err(invalid)? });
+        }
+        let regex = Regex::new(value).map_err(invalid)?;
+        if regex.capture_names().flatten().next().is_none() {
+            return Err(format!("Invalid --extract pattern \"{}\": name the value with NAME=REGEX or a (?P<name>...) group", value));
+        }
+        Ok(Self { name: None, regex })
+    }
+}
+
+impl Pattern {
+    fn fields(&self) -> Vec<String> {
+        match &self.name {
+            Some(name) => vec![name.clone()],
+            None => self.regex.capture_names().flatten().map(str::to_string).collect(),
+        }
+    }
+
+    /// The fields of every match in `text`, in order.
+    fn matches(&self, text: &str) -> Vec<Map<String, Value>> {
+        self.regex.captures_iter(text).map(|captures| {
+            let mut values = Map::new();
+            match &self.name {
+                Some(name) => {
+                    let value = captures.iter().skip(1).flatten().next().or_else(|| captures.get(0));
+                    values.insert(name.clone(), value.map_or(Value::Null, |value| Value::String(value.as_str().to_string())));
+                }
+                None => {
+                    for name in self.regex.capture_names().flatten() {
+                        let value = captures.name(name).map_or(Value::Null, |value| Value::String(value.as_str().to_string()));
+                        values.insert(name.to_string(), value);
+                    }
+                }
+            }
+            values
+        }).collect()
+    }
+}
+
+/// Values captured from a transcript: row N holds the Nth match of each
+/// pattern, so patterns for the columns of a listing line up as records.
+#[derive(Serialize)]
+pub struct Extracted {
+    /// Column names, in pattern order
+    pub fields: Vec<String>,
+    pub rows: Vec<Map<String, Value>>,
+}
+
+impl Extracted {
+    /// The values as CSV with a header row.
+    pub fn to_csv(&self) -> String {
+        let mut csv = csv_row(self.fields.iter().map(String::as_str));
+        for row in &self.rows {
+            csv.push('\n');
+            csv.push_str(&csv_row(self.fields.iter().map(|field| row.get(field).and_then(Value::as_str).unwrap_or_default())));
+        }
+        csv
+    }
+}
+
+/// Apply `patterns` to an OCR transcript and print the values as CSV.
+pub fn extract(patterns: &[Pattern], text: &str) -> Extracted {
+    let mut fields: Vec<String> = Vec::new();
+    let mut rows: Vec<Map<String, Value>> = Vec::new();
+    for pattern in patterns {
+        for field in pattern.fields() {
+            if !fields.contains(&field) {
+                fields.push(field);
+            }
+        }
+        for (index, values) in pattern.matches(text).into_iter().enumerate() {
+            if index == rows.len() {
+                rows.push(Map::new());
+            }
+            for (field, value) in values {
+                // A field named by several patterns keeps its first value
+                rows[index].entry(field).or_insert(value);
+            }
+        }
+    }
+
+    let extracted = Extracted { fields, rows };
+    if extracted.rows.is_empty() {
+        status!("No values matched the --extract patterns");
+    }
+    result!("{}", extracted.to_csv());
+    extracted
+}
+
+fn csv_row<'a>(values: impl Iterator<Item = &'a str>) -> String {
+    values.map(csv_field).collect::<Vec<_>>().join(",")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LISTING: &str = "Order 1041 shipped $12.50\nOrder 1042 pending $7.25\nTotal: $19.75";
+
+    fn patterns(values: &[&str]) -> Vec<Pattern> {
+        values.iter().map(|value| value.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn parses_named_and_bare_patterns() {
+        let named: Pattern = r"total=Total: \$([\d.]+)".parse().unwrap();
+        assert_eq!(named.fields(), ["total"]);
+        let groups: Pattern = r"Order (?P<order>\d+) (?P<status>\w+)".parse().unwrap();
+        assert_eq!(groups.fields(), ["order", "status"]);
+        // An `=` inside the regex doesn't make a name of what precedes it
+        let bare: Pattern = r"(?P<key>\w+ ?)=(?P<value>\d+)".parse().unwrap();
+        assert_eq!(bare.fields(), ["key", "value"]);
+    }
+
+    #[test]
+    fn rejects_invalid_patterns() {
+        for value in ["total=(unclosed", "(?P<order>\\d+", "[z-a]"] {
+            let error = value.parse::<Pattern>().unwrap_err();
+            assert!(error.starts_with("Invalid --extract pattern"), "{}", error);
+        }
+        let error = r"Order \d+".parse::<Pattern>().unwrap_err();
+        assert!(error.contains("NAME=REGEX"), "{}", error);
+    }
+
+    #[test]
+    fn named_patterns_take_the_first_group_or_the_whole_match() {
+        let extracted = extract(&patterns(&[r"amount=\$([\d.]+)", r"order=Order \d+"]), LISTING);
+        assert_eq!(extracted.to_csv(), "amount,order\n12.50,Order 1041\n7.25,Order 1042\n19.75,");
+    }
+
+    #[test]
+    fn named_groups_become_columns() {
+        let extracted = extract(&patterns(&[r"Order (?P<order>\d+) (?P<status>\w+)(?: (?P<note>note))?"]), LISTING);
+        assert_eq!(extracted.fields, ["order", "status", "note"]);
+        assert_eq!(extracted.rows[1].get("status"), Some(&Value::String("pending".to_string())));
+        assert_eq!(extracted.rows[1].get("note"), Some(&Value::Null));
+        assert_eq!(extracted.to_csv(), "order,status,note\n1041,shipped,\n1042,pending,");
+    }
+
+    #[test]
+    fn patterns_that_dont_match_leave_empty_values() {
+        let extracted = extract(&patterns(&[r"refund=Refund \$([\d.]+)"]), LISTING);
+        assert_eq!(extracted.fields, ["refund"]);
+        assert!(extracted.rows.is_empty());
+        assert_eq!(extracted.to_csv(), "refund");
+
+        let extracted = extract(&patterns(&[r"order=Order (\d+)", r"refund=Refund \$([\d.]+)"]), LISTING);
+        assert_eq!(extracted.to_csv(), "order,refund\n1041,\n1042,");
+    }
+
+    #[test]
+    fn the_first_pattern_naming_a_field_wins() {
+        let extracted = extract(&patterns(&[r"id=Order (\d+)", r"id=\$([\d.]+)"]), LISTING);
+        assert_eq!(extracted.to_csv(), "id\n1041\n1042\n19.75");
+    }
+
+    #[test]
+    fn values_are_quoted_for_csv() {
+        let extracted = extract(&patterns(&[r"name=Name: (.+)"]), "Name: Doe, \"Jo\"");
+        assert_eq!(extracted.to_csv(), "name\n\"Doe, \"\"Jo\"\"\"");
+    }
+}
@@ -0,0 +1,180 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use tokio::task::JoinSet;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{self, ErrorKind};
+use crate::session_pool::TargetKey;
+use crate::bundle::Bundle;
+use crate::{config, remote};
+
+/// Port of `captest serve` on hosts that don't set one.
+const DEFAULT_PORT: u16 = 8080;
+
+/// The hosts file: a `[[host]]` table per machine.
+#[derive(Deserialize)]
+struct HostsFile {
+    host: Vec<Host>,
+}
+
+#[derive(Deserialize)]
+struct Host {
+    /// Name of the host's output directory, e.g. "macos-14"
+    name: String,
+    /// SSH destination, e.g. "qa@mac-mini-1"
+    destination: String,
+    port: Option<u16>,
+    /// Target on this host, overriding the command's --screen/--window
+    screen: Option<usize>,
+    window: Option<usize>,
+}
+
+/// One host's part of a fleet capture.
+#[derive(Serialize)]
+pub struct HostResult {
+    pub name: String,
+    pub destination: String,
+    pub target: String,
+    /// "ok" or "error"
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct FleetReport {
+    /// "ok" when every host delivered a capture, otherwise "partial" or "failed"
+    pub status: String,
+    /// Unix seconds shared by every capture's file name
+    pub timestamp: u64,
+    /// The combined summary written next to the host directories, or its name in the bundle
+    pub summary: String,
+    /// Archive holding the images and summary, with `--bundle`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bundle: Option<String>,
+    pub hosts: Vec<HostResult>,
+    /// Category of the first host that failed, used for the exit code
+    #[serde(skip)]
+    pub failure: Option<ErrorKind>,
+}
+
+/// The `fleet capture` command: capture on every host of `hosts_path`
+/// concurrently through `remote` SSH tunnels, saving each image as
+/// `<output_dir>/<name>/capture_<timestamp>.jpg` and a combined
+/// `summary_<timestamp>.json` in `output_dir`, or with `bundle` writing
+/// them into that archive as they arrive.
+pub async fn capture(hosts_path: &Path, key: TargetKey, output_dir: &str, crop: Option<&str>, max_width: Option<u32>, bundle: Option<&str>, cancel: &CancellationToken) -> Result<FleetReport, Box<dyn std::error::Error>> {
+    let contents = std::fs::read_to_string(hosts_path)
+        .map_err(|e| format!("Failed to read hosts file {}: {}", hosts_path.display(), e))?;
+    let hosts: HostsFile = toml::from_str(&contents)
+        .map_err(|e| format!("Invalid hosts file {}: {}", hosts_path.display(), e))?;
+    if hosts.host.is_empty() {
+        return Err(format!("{} lists no [[host]] entries", hosts_path.display()).into());
+    }
+    let mut names = std::collections::HashSet::new();
+    if let Some(host) = hosts.host.iter().find(|host| !names.insert(&host.name)) {
+        return Err(format!("Host name '{}' is used twice in {}", host.name, hosts_path.display()).into());
+    }
+
+    let output_dir = PathBuf::from(config::resolve_output_path(output_dir));
+    let mut bundle = bundle.map(|path| Bundle::create(&config::resolve_output_path(path))).transpose()?;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    status!("Capturing on {} hosts...", hosts.host.len());
+
+    let mut captures = JoinSet::new();
+    for (index, host) in hosts.host.into_iter().enumerate() {
+        let key = match (host.screen, host.window) {
+            (_, Some(window)) => TargetKey::Window(window),
+            (Some(screen), None) => TargetKey::Screen(screen),
+            (None, None) => key,
+        };
+        let (crop, cancel) = (crop.map(str::to_string), cancel.clone());
+        captures.spawn(async move {
+            let fetched = remote::fetch(&host.destination, host.port.unwrap_or(DEFAULT_PORT), key, crop.as_deref(), max_width, &cancel).await
+                .map_err(|e| (e.to_string(), error::error_kind(&*e)));
+            (index, host, key, fetched)
+        });
+    }
+
+    // Images are saved as they arrive, so a bundle is written in completion order
+    let mut results = Vec::new();
+    while let Some(joined) = captures.join_next().await {
+        let (index, host, key, fetched) = joined?;
+        let mut result = HostResult {
+            name: host.name,
+            destination: host.destination,
+            target: key.to_string(),
+            status: "ok".to_string(),
+            output: None,
+            width: None,
+            height: None,
+            error: None,
+        };
+        let entry = format!("{}/capture_{}.jpg", result.name, timestamp);
+        let saved = fetched.and_then(|fetched| {
+            let output = match &mut bundle {
+                Some(bundle) => bundle.add(&entry, &fetched.jpeg_bytes),
+                None => crate::save_image_bytes(&fetched.jpeg_bytes, &output_dir.join(&entry).to_string_lossy()),
+            };
+            output.map(|output| (output, fetched.width, fetched.height)).map_err(|e| (e.to_string(), ErrorKind::Failure))
+        });
+        let kind = match saved {
+            Ok((output, width, height)) => {
+                status!("{}: saved {} ({}x{})", result.name, output, width, height);
+                result.output = Some(output);
+                result.width = Some(width as u64);
+                result.height = Some(height as u64);
+                None
+            }
+            Err((e, kind)) => {
+                status!("{}: {}", result.name, e);
+                result.status = "error".to_string();
+                result.error = Some(e);
+                Some(kind)
+            }
+        };
+        results.push((index, result, kind));
+    }
+    // Report hosts in the order of the hosts file
+    results.sort_by_key(|(index, _, _)| *index);
+    let failure = results.iter().find_map(|(_, _, kind)| *kind);
+    let hosts: Vec<HostResult> = results.into_iter().map(|(_, result, _)| result).collect();
+    let succeeded = hosts.iter().filter(|host| host.status == "ok").count();
+
+    let summary_name = format!("summary_{}.json", timestamp);
+    let mut report = FleetReport {
+        status: match succeeded {
+            n if n == hosts.len() => "ok",
+            0 => "failed",
+            _ => "partial",
+        }.to_string(),
+        timestamp,
+        summary: output_dir.join(&summary_name).to_string_lossy().into_owned(),
+        bundle: bundle.as_ref().map(|bundle| bundle.path().to_string()),
+        hosts,
+        failure,
+    };
+    match bundle {
+        Some(mut bundle) => {
+            report.summary = summary_name.clone();
+            bundle.add_json(&summary_name, &report)?;
+            bundle.finish()?;
+            result!("Captured {} of {} hosts into {}", succeeded, report.hosts.len(), report.bundle.as_deref().unwrap_or_default());
+        }
+        None => {
+            std::fs::create_dir_all(&output_dir)?;
+            std::fs::write(&report.summary, serde_json::to_string_pretty(&report)?)
+                .map_err(|e| format!("Failed to write {}: {}", report.summary, e))?;
+            result!("Captured {} of {} hosts into {} (summary: {})", succeeded, report.hosts.len(), output_dir.display(), report.summary);
+        }
+    }
+    Ok(report)
+}
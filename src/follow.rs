@@ -0,0 +1,146 @@
+use std::time::{Duration, Instant};
+
+use scap::Target;
+use scap::capturer::Options;
+
+use crate::capture;
+use crate::platforms;
+
+/// How often the window's geometry is checked.
+pub const CHECK_INTERVAL: Duration = Duration::from_millis(250);
+/// How often the window list is checked where geometry isn't available, and
+/// while waiting for a closed window to reappear.
+const LISTING_INTERVAL: Duration = Duration::from_secs(1);
+/// How long new geometry (or the window's absence) must hold before it is
+/// acted on, so a window that is being dragged isn't restarted on every check.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+
+/// What happened to a followed window.
+pub enum WindowChange {
+    /// The window settled at new geometry; capture it with these options
+    Moved(Options),
+    /// The window no longer exists
+    Closed,
+}
+
+/// Watches the window a capture targets and reports when it has moved,
+/// resized, changed display or closed, so the capture can be rebuilt for its
+/// new geometry instead of streaming stale frames or waiting forever.
+pub struct WindowFollower {
+    window_id: u32,
+    title: String,
+    /// None on platforms that can't report window geometry
+    bounds: Option<(i32, i32, i32, i32)>,
+    pending: Option<((i32, i32, i32, i32), Instant)>,
+    missing_since: Option<Instant>,
+    next_check: Instant,
+}
+
+impl WindowFollower {
+    /// A follower for the window in `options`, or None for screen targets.
+    pub fn new(options: &Options) -> Option<Self> {
+        let Some(Target::Window(window)) = &options.target else {
+            return None;
+        };
+        let bounds = platforms::window_bounds(window.id);
+        Some(Self {
+            window_id: window.id,
+            title: window.title.clone(),
+            bounds,
+            pending: None,
+            missing_since: None,
+            next_check: Instant::now() + Self::check_interval(bounds),
+        })
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    fn check_interval(bounds: Option<(i32, i32, i32, i32)>) -> Duration {
+        if bounds.is_some() { CHECK_INTERVAL } else { LISTING_INTERVAL }
+    }
+
+    /// Whether the window is gone right now, e.g. after its capture session ended.
+    pub fn is_gone(&self) -> bool {
+        match self.bounds {
+            Some(_) => platforms::window_bounds(self.window_id).is_none(),
+            None => !window_listed(self.window_id),
+        }
+    }
+
+    /// A change once the window has settled at new geometry or stayed closed,
+    /// otherwise None. Cheap to call on every frame.
+    pub fn poll(&mut self, options: &Options) -> Option<WindowChange> {
+        let now = Instant::now();
+        if now < self.next_check {
+            return None;
+        }
+        self.next_check = now + Self::check_interval(self.bounds);
+
+        if self.is_gone() {
+            let since = *self.missing_since.get_or_insert(now);
+            return (now.duration_since(since) >= SETTLE_TIME).then_some(WindowChange::Closed);
+        }
+        self.missing_since = None;
+
+        // Without geometry only closing can be detected
+        let bounds = platforms::window_bounds(self.window_id)?;
+        if Some(bounds) == self.bounds {
+            self.pending = None;
+            return None;
+        }
+        match self.pending {
+            Some((pending, since)) if pending == bounds => {
+                if now.duration_since(since) < SETTLE_TIME {
+                    return None;
+                }
+            }
+            _ => {
+                self.pending = Some((bounds, now));
+                return None;
+            }
+        }
+        self.bounds = Some(bounds);
+        self.pending = None;
+
+        let (x, y, width, height) = bounds;
+        status!("Window moved to {},{} ({}x{}), reconfiguring capture", x, y, width, height);
+        // Targets carry the geometry they were listed with, so look the window up again
+        let target = capture::all_targets()
+            .into_iter()
+            .find(|target| matches!(target, Target::Window(window) if window.id == self.window_id));
+        Some(WindowChange::Moved(Options {
+            target: target.or_else(|| options.target.clone()),
+            ..options.clone()
+        }))
+    }
+
+    /// Wait for a window with the closed window's title to appear, e.g. after
+    /// its app restarts, and follow it from then on. Returns the options to
+    /// capture it; drop the future to stop waiting.
+    pub async fn wait_for_reattach(&mut self, options: &Options) -> Options {
+        loop {
+            if !self.title.is_empty()
+                && let Some(target) = capture::all_targets()
+                    .into_iter()
+                    .find(|target| matches!(target, Target::Window(window) if window.title == self.title))
+                && let Target::Window(window) = &target
+            {
+                status!("Reattached to window \"{}\"", self.title);
+                self.window_id = window.id;
+                self.bounds = platforms::window_bounds(window.id);
+                self.pending = None;
+                self.missing_since = None;
+                return Options { target: Some(target), ..options.clone() };
+            }
+            tokio::time::sleep(LISTING_INTERVAL).await;
+        }
+    }
+}
+
+fn window_listed(window_id: u32) -> bool {
+    capture::all_targets()
+        .iter()
+        .any(|target| matches!(target, Target::Window(window) if window.id == window_id))
+}
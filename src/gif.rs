@@ -0,0 +1,58 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, DynamicImage, Frame, RgbImage, RgbaImage};
+
+/// Palette quantizer speed, from 1 (best colors) to 30 (fastest). Screen
+/// content has few colors, so the fast end still looks right.
+const QUANTIZER_SPEED: i32 = 10;
+/// Browsers show frames with shorter delays for 100ms instead.
+const MIN_DELAY_MS: u64 = 20;
+
+/// Writes frames into an animated GIF that loops forever. A frame's delay is
+/// only known once the next frame arrives, so each frame is held back until
+/// then. Nothing is playable until [`finish`](Self::finish).
+pub struct GifWriter {
+    encoder: GifEncoder<BufWriter<File>>,
+    /// The last frame and its timestamp, waiting for its delay
+    pending: Option<(u64, RgbaImage)>,
+    frames: usize,
+}
+
+impl GifWriter {
+    pub fn create(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut encoder = GifEncoder::new_with_speed(BufWriter::new(File::create(path)?), QUANTIZER_SPEED);
+        encoder.set_repeat(Repeat::Infinite)?;
+        Ok(Self { encoder, pending: None, frames: 0 })
+    }
+
+    pub fn write_frame(&mut self, timestamp_ms: u64, width: u32, height: u32, rgb_data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let image = RgbImage::from_raw(width, height, rgb_data.to_vec()).ok_or("Frame data doesn't match its size")?;
+        let image = DynamicImage::ImageRgb8(image).to_rgba8();
+        if let Some(pending) = self.pending.replace((timestamp_ms, image)) {
+            self.encode(pending, timestamp_ms)?;
+        }
+        Ok(())
+    }
+
+    /// Write the last frame, shown until `end_ms`, and the GIF trailer.
+    /// Returns the number of frames written.
+    pub fn finish(mut self, end_ms: u64) -> Result<usize, Box<dyn std::error::Error>> {
+        if let Some(pending) = self.pending.take() {
+            self.encode(pending, end_ms)?;
+        }
+        // The trailer is written when the encoder is dropped
+        drop(self.encoder);
+        Ok(self.frames)
+    }
+
+    fn encode(&mut self, (timestamp_ms, image): (u64, RgbaImage), next_ms: u64) -> Result<(), Box<dyn std::error::Error>> {
+        let delay_ms = next_ms.saturating_sub(timestamp_ms).max(MIN_DELAY_MS);
+        let delay = Delay::from_numer_denom_ms(delay_ms.min(u32::MAX as u64) as u32, 1);
+        self.encoder.encode_frame(Frame::from_parts(image, 0, 0, delay))?;
+        self.frames += 1;
+        Ok(())
+    }
+}
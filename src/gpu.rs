@@ -0,0 +1,249 @@
+use std::borrow::Cow;
+use std::sync::OnceLock;
+
+use crate::convert::Crop;
+
+/// Converts, crops and bilinearly scales a frame in one pass. Each invocation
+/// produces four output pixels, which are exactly three words of packed RGB8.
+const SHADER: &str = r#"
+struct Params {
+    format: u32,
+    src_stride: u32,
+    uv_offset: u32,
+    uv_stride: u32,
+    crop_x: u32,
+    crop_y: u32,
+    crop_width: u32,
+    crop_height: u32,
+    out_width: u32,
+    out_height: u32,
+    _pad0: u32,
+    _pad1: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> src: array<u32>;
+@group(0) @binding(2) var<storage, read_write> dst: array<u32>;
+
+fn byte_at(offset: u32) -> u32 {
+    return (src[offset / 4u] >> ((offset % 4u) * 8u)) & 0xffu;
+}
+
+fn rgb_at(x: u32, y: u32) -> vec3<f32> {
+    if (params.format == 0u) {
+        let o = y * params.src_stride + x * 4u;
+        return vec3<f32>(f32(byte_at(o + 2u)), f32(byte_at(o + 1u)), f32(byte_at(o)));
+    }
+    // NV12, BT.601 video range
+    let c = 1.164 * (f32(byte_at(y * params.src_stride + x)) - 16.0);
+    let uv = params.uv_offset + (y / 2u) * params.uv_stride + (x / 2u) * 2u;
+    let d = f32(byte_at(uv)) - 128.0;
+    let e = f32(byte_at(uv + 1u)) - 128.0;
+    return vec3<f32>(c + 1.596 * e, c - 0.392 * d - 0.813 * e, c + 2.017 * d);
+}
+
+fn sample(out_x: u32, out_y: u32) -> vec3<f32> {
+    let scale = vec2<f32>(f32(params.crop_width) / f32(params.out_width), f32(params.crop_height) / f32(params.out_height));
+    let last = vec2<f32>(f32(params.crop_width - 1u), f32(params.crop_height - 1u));
+    let pos = clamp((vec2<f32>(f32(out_x), f32(out_y)) + 0.5) * scale - 0.5, vec2<f32>(0.0), last);
+    let p0 = vec2<u32>(floor(pos));
+    let p1 = min(p0 + 1u, vec2<u32>(last));
+    let t = pos - floor(pos);
+    let x0 = params.crop_x + p0.x;
+    let x1 = params.crop_x + p1.x;
+    let y0 = params.crop_y + p0.y;
+    let y1 = params.crop_y + p1.y;
+    let top = mix(rgb_at(x0, y0), rgb_at(x1, y0), t.x);
+    let bottom = mix(rgb_at(x0, y1), rgb_at(x1, y1), t.x);
+    return mix(top, bottom, t.y);
+}
+
+@compute @workgroup_size(64)
+fn main(@builtin(global_invocation_id) id: vec3<u32>, @builtin(num_workgroups) groups: vec3<u32>) {
+    let group = id.x + id.y * groups.x * 64u;
+    let pixels = params.out_width * params.out_height;
+    if (group * 4u >= pixels) {
+        return;
+    }
+
+    var bytes: array<u32, 12>;
+    for (var i = 0u; i < 4u; i++) {
+        let pixel = group * 4u + i;
+        if (pixel < pixels) {
+            let rgb = clamp(round(sample(pixel % params.out_width, pixel / params.out_width)), vec3<f32>(0.0), vec3<f32>(255.0));
+            bytes[i * 3u] = u32(rgb.r);
+            bytes[i * 3u + 1u] = u32(rgb.g);
+            bytes[i * 3u + 2u] = u32(rgb.b);
+        }
+    }
+
+    let words = (pixels * 3u + 3u) / 4u;
+    for (var w = 0u; w < 3u; w++) {
+        if (group * 3u + w < words) {
+            dst[group * 3u + w] = bytes[w * 4u] | (bytes[w * 4u + 1u] << 8u) | (bytes[w * 4u + 2u] << 16u) | (bytes[w * 4u + 3u] << 24u);
+        }
+    }
+}
+"#;
+
+/// Output pixels each workgroup produces (64 invocations of 4 pixels).
+const PIXELS_PER_WORKGROUP: u32 = 256;
+/// Largest workgroup count per dispatch dimension.
+const MAX_WORKGROUPS: u32 = 65535;
+
+/// Pixel data the GPU path can read directly.
+pub enum GpuSource<'a> {
+    Bgra { data: &'a [u8], stride: u32 },
+    Nv12 { luma: &'a [u8], luma_stride: u32, chroma: &'a [u8], chroma_stride: u32 },
+}
+
+pub struct GpuConverter {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+}
+
+static CONVERTER: OnceLock<Option<GpuConverter>> = OnceLock::new();
+
+/// The shared converter, set up on first use. None (reported once) when no
+/// usable GPU adapter exists, so callers fall back to the CPU.
+pub fn converter() -> Option<&'static GpuConverter> {
+    CONVERTER
+        .get_or_init(|| match pollster::block_on(GpuConverter::new()) {
+            Ok(converter) => Some(converter),
+            Err(e) => {
+                status!("GPU conversion unavailable, using the CPU: {}", e);
+                None
+            }
+        })
+        .as_ref()
+}
+
+impl GpuConverter {
+    async fn new() -> Result<Self, String> {
+        let instance = wgpu::Instance::new(&wgpu::InstanceDescriptor::default());
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| e.to_string())?;
+        // Full frames of 8K captures exceed the default storage buffer limit
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: Some("captest"),
+                required_limits: adapter.limits(),
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| e.to_string())?;
+        status!("Using GPU {} for frame conversion", adapter.get_info().name);
+
+        let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("convert"),
+            source: wgpu::ShaderSource::Wgsl(SHADER.into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("convert"),
+            layout: None,
+            module: &module,
+            entry_point: Some("main"),
+            compilation_options: Default::default(),
+            cache: None,
+        });
+
+        Ok(Self { device, queue, pipeline })
+    }
+
+    /// Convert the `crop` area of a `source` frame to packed RGB8 of
+    /// `out_width` x `out_height`.
+    pub fn convert(&self, source: GpuSource, crop: Crop, out_width: u32, out_height: u32) -> Result<Vec<u8>, String> {
+        use wgpu::util::DeviceExt;
+
+        // Planes are concatenated for NV12; buffer sizes must be word aligned,
+        // which BGRA data always is, so it is uploaded without a copy
+        let (format, src_stride, uv_offset, uv_stride, src) = match source {
+            GpuSource::Bgra { data, stride } => (0u32, stride, 0, 0, Cow::Borrowed(data)),
+            GpuSource::Nv12 { luma, luma_stride, chroma, chroma_stride } => {
+                let len = (luma.len() + chroma.len()).next_multiple_of(4);
+                let mut planes = Vec::with_capacity(len);
+                planes.extend_from_slice(luma);
+                planes.extend_from_slice(chroma);
+                planes.resize(len, 0);
+                (1, luma_stride, luma.len() as u32, chroma_stride, Cow::Owned(planes))
+            }
+        };
+        if src.is_empty() || src.len() % 4 != 0 {
+            return Err("Frame data is not word aligned".to_string());
+        }
+
+        let params: Vec<u8> = [
+            format, src_stride, uv_offset, uv_stride,
+            crop.x, crop.y, crop.width, crop.height,
+            out_width, out_height, 0, 0,
+        ]
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect();
+
+        let out_len = out_width as usize * out_height as usize * 3;
+        let out_size = out_len.next_multiple_of(4) as u64;
+
+        let params_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("params"),
+            contents: &params,
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+        let src_buffer = self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("source"),
+            contents: &src,
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+        let dst_buffer = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("rgb"),
+            size: out_size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("readback"),
+            size: out_size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("convert"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry { binding: 0, resource: params_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 1, resource: src_buffer.as_entire_binding() },
+                wgpu::BindGroupEntry { binding: 2, resource: dst_buffer.as_entire_binding() },
+            ],
+        });
+
+        let workgroups = (out_width * out_height).div_ceil(PIXELS_PER_WORKGROUP);
+        let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: Some("convert") });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor { label: Some("convert"), timestamp_writes: None });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups(workgroups.min(MAX_WORKGROUPS), workgroups.div_ceil(MAX_WORKGROUPS), 1);
+        }
+        encoder.copy_buffer_to_buffer(&dst_buffer, 0, &readback, 0, out_size);
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = readback.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::PollType::Wait).map_err(|e| e.to_string())?;
+        receiver.recv().map_err(|e| e.to_string())?.map_err(|e| e.to_string())?;
+
+        let rgb_data = slice.get_mapped_range()[..out_len].to_vec();
+        readback.unmap();
+        Ok(rgb_data)
+    }
+}
@@ -0,0 +1,71 @@
+use crate::layout::{self, LineBox};
+
+/// Render OCR lines of a `width` x `height` image as hOCR, the HTML format
+/// Tesseract and other OCR tools write, so existing hOCR consumers (PDF
+/// text layers, proofreading tools) can read captest's results. Blocks of
+/// lines that belong together become paragraphs. Words carry no `x_wconf`,
+/// since ocrs reports no recognition confidence to put there.
+pub fn to_hocr(lines: Vec<LineBox>, width: u32, height: u32) -> String {
+    let mut out = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<!DOCTYPE html PUBLIC \"-//W3C//DTD XHTML 1.0 Transitional//EN\" \"http://www.w3.org/TR/xhtml1/DTD/xhtml1-transitional.dtd\">\n",
+        "<html xmlns=\"http://www.w3.org/1999/xhtml\" xml:lang=\"en\" lang=\"en\">\n",
+        "<head>\n",
+        "<title></title>\n",
+        "<meta http-equiv=\"Content-Type\" content=\"text/html;charset=utf-8\"/>\n",
+        "<meta name=\"ocr-system\" content=\"captest\"/>\n",
+        "<meta name=\"ocr-capabilities\" content=\"ocr_page ocr_par ocr_line ocrx_word\"/>\n",
+        "</head>\n",
+        "<body>\n",
+    ));
+    out.push_str(&format!("<div class=\"ocr_page\" id=\"page_1\" title=\"bbox 0 0 {} {}\">\n", width, height));
+
+    let (mut line_number, mut word_number) = (0, 0);
+    for (block_index, block) in layout::reading_order_blocks(lines).into_iter().enumerate() {
+        let bbox = block.iter().fold((i32::MAX, i32::MAX, i32::MIN, i32::MIN), |(left, top, right, bottom), line| {
+            (left.min(line.left), top.min(line.top), right.max(line.right), bottom.max(line.bottom))
+        });
+        out.push_str(&format!("<p class=\"ocr_par\" id=\"par_1_{}\" title=\"{}\">\n", block_index + 1, bbox_title(bbox)));
+        for line in block {
+            line_number += 1;
+            let mut title = bbox_title((line.left, line.top, line.right, line.bottom));
+            // Text running down or up the page, like the words it holds
+            if let Some(word) = line.words.first()
+                && word.angle.abs() >= 45.0
+            {
+                title.push_str(&format!("; textangle {}", (-word.angle).rem_euclid(360.0).round()));
+            }
+            out.push_str(&format!("<span class=\"ocr_line\" id=\"line_1_{}\" title=\"{}\">", line_number, title));
+            if line.words.is_empty() {
+                out.push_str(&escape(&line.text));
+            }
+            for (index, word) in line.words.iter().enumerate() {
+                word_number += 1;
+                if index > 0 {
+                    out.push(' ');
+                }
+                out.push_str(&format!(
+                    "<span class=\"ocrx_word\" id=\"word_1_{}\" title=\"{}\">{}</span>",
+                    word_number,
+                    bbox_title((word.left, word.top, word.right, word.bottom)),
+                    escape(&word.text)
+                ));
+            }
+            out.push_str("</span>\n");
+        }
+        out.push_str("</p>\n");
+    }
+    out.push_str("</div>\n</body>\n</html>\n");
+    out
+}
+
+fn bbox_title((left, top, right, bottom): (i32, i32, i32, i32)) -> String {
+    format!("bbox {} {} {} {}", left.max(0), top.max(0), right.max(0), bottom.max(0))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,63 @@
+use image::codecs::bmp::BmpEncoder;
+use image::codecs::png::PngEncoder;
+use image::codecs::webp::WebPEncoder;
+use image::imageops::{self, FilterType};
+use image::{ImageEncoder, RgbaImage};
+use scap::Target;
+use serde_json::{json, Value};
+
+use crate::encode::ImageFormat;
+use crate::error::{CaptestError, ErrorKind};
+use crate::session_pool::{self, TargetKey};
+use crate::{config, platforms};
+
+/// The `icon` command: save the icon of the app owning window `window`,
+/// `size` pixels square with its transparency, as PNG unless the output's
+/// extension asks for WebP or BMP. Icons the platform only has smaller are
+/// scaled up; non-square ones are centered.
+pub fn save_icon(window: usize, size: u32, output: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let Target::Window(info) = session_pool::resolve_target(TargetKey::Window(window)).map_err(|e| CaptestError::new(ErrorKind::TargetNotFound, e))? else {
+        unreachable!("window keys resolve to windows");
+    };
+    let output = config::resolve_output_path(output);
+    let format = ImageFormat::from_path(&output).unwrap_or(ImageFormat::Png);
+    if format == ImageFormat::Jpeg {
+        return Err("Icons are transparent, which JPEG can't store; save them as .png, .webp or .bmp".into());
+    }
+
+    let (width, height, rgba) = platforms::window_icon(info.id, size).map_err(|e| CaptestError::new(ErrorKind::Unsupported, e))?;
+    let icon = RgbaImage::from_raw(width, height, rgba).ok_or("Icon data doesn't match its size")?;
+    let icon = fit_square(icon, size);
+
+    let mut bytes = Vec::new();
+    match format {
+        ImageFormat::Webp => WebPEncoder::new_lossless(&mut bytes).write_image(&icon, size, size, image::ColorType::Rgba8)?,
+        ImageFormat::Bmp => BmpEncoder::new(&mut bytes).write_image(&icon, size, size, image::ColorType::Rgba8)?,
+        _ => PngEncoder::new(&mut bytes).write_image(&icon, size, size, image::ColorType::Rgba8)?,
+    }
+    let saved = crate::save_image_bytes(&bytes, &output)?;
+    result!("{}", saved);
+
+    Ok(json!({
+        "status": "ok",
+        "target": { "kind": "window", "index": window, "id": info.id, "title": info.title },
+        "output": saved,
+        "size": size,
+        "source_width": width,
+        "source_height": height,
+    }))
+}
+
+/// Scale `icon` to fit a `size` square and center it on a transparent one.
+fn fit_square(icon: RgbaImage, size: u32) -> RgbaImage {
+    if icon.dimensions() == (size, size) {
+        return icon;
+    }
+    let scale = size as f64 / icon.width().max(icon.height()) as f64;
+    let width = ((icon.width() as f64 * scale).round() as u32).clamp(1, size);
+    let height = ((icon.height() as f64 * scale).round() as u32).clamp(1, size);
+    let scaled = imageops::resize(&icon, width, height, FilterType::Lanczos3);
+    let mut square = RgbaImage::new(size, size);
+    imageops::overlay(&mut square, &scaled, ((size - width) / 2) as i64, ((size - height) / 2) as i64);
+    square
+}
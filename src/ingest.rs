@@ -0,0 +1,205 @@
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use notify::{EventKind, RecursiveMode, Watcher};
+use serde::Serialize;
+use serde_json::json;
+use tokio_util::sync::CancellationToken;
+
+use crate::ocr::SharedEngine;
+use crate::{ocr_batch, output};
+
+/// Default index file, inside the ingested directory.
+const INDEX_NAME: &str = "captest-index.jsonl";
+/// Suffix of the OCR sidecar written next to each image.
+const SIDECAR_SUFFIX: &str = ".ocr.json";
+/// How long a new file's size must stay the same before it is read, since
+/// screenshot tools often write the image in several steps.
+const SETTLE_TIME: Duration = Duration::from_millis(500);
+/// Give up on files that are still changing after this long.
+const SETTLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// OCR result for one image, written as its sidecar and appended to the index.
+#[derive(Serialize)]
+struct Entry {
+    file: String,
+    /// Unix milliseconds the image was ingested
+    timestamp: u128,
+    width: u32,
+    height: u32,
+    text: String,
+    lines: Vec<String>,
+}
+
+/// The `ingest` command: OCR the images in `dir` that have no sidecar yet,
+/// writing `<image>.ocr.json` next to each and appending it to the index.
+/// With `watch`, keep monitoring the directory and ingest every image dropped
+/// there until interrupted, so OS screenshot shortcuts feed the same index.
+pub async fn ingest(dir: &Path, index: Option<&Path>, watch: bool, use_cache: bool, cancel: &CancellationToken) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    if !dir.is_dir() {
+        return Err(format!("{} is not a directory", dir.display()).into());
+    }
+    let index = index.map(Path::to_path_buf).unwrap_or_else(|| dir.join(INDEX_NAME));
+
+    // Start watching before the initial pass, so nothing dropped meanwhile is missed
+    let (sender, mut events) = tokio::sync::mpsc::unbounded_channel();
+    let watcher = match watch {
+        true => {
+            let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                if let Ok(event) = event
+                    && matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_))
+                {
+                    for path in event.paths {
+                        let _ = sender.send(path);
+                    }
+                }
+            })?;
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+            Some(watcher)
+        }
+        false => None,
+    };
+
+    let engine = SharedEngine::shared().await?;
+    let mut ingested = 0;
+    let mut failed = 0;
+    let mut seen = HashSet::new();
+
+    let mut pending: Vec<PathBuf> = std::fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| wants(path) && !sidecar_path(path).exists())
+        .collect();
+    pending.sort();
+    status!("Ingesting {} new images in {}", pending.len(), dir.display());
+    for path in pending {
+        seen.insert(path.clone());
+        match ingest_blocking(&engine, &path, &index, use_cache).await {
+            Ok(()) => ingested += 1,
+            Err(e) => {
+                failed += 1;
+                status!("{}: {}", path.display(), e);
+            }
+        }
+    }
+
+    if watcher.is_some() {
+        status!("Watching {} for new screenshots, press Ctrl-C to stop", dir.display());
+        loop {
+            let path = tokio::select! {
+                _ = cancel.cancelled() => break,
+                path = events.recv() => match path {
+                    Some(path) => path,
+                    None => break,
+                },
+            };
+            // Creation and the writes after it all report the same file
+            if !wants(&path) || seen.contains(&path) {
+                continue;
+            }
+            if !wait_until_settled(&path, cancel).await {
+                continue;
+            }
+            seen.insert(path.clone());
+            match ingest_blocking(&engine, &path, &index, use_cache).await {
+                Ok(()) => ingested += 1,
+                Err(e) => {
+                    failed += 1;
+                    status!("{}: {}", path.display(), e);
+                    output::event(&json!({ "event": "error", "file": path.display().to_string(), "error": e }));
+                }
+            }
+        }
+    }
+    drop(watcher);
+
+    status!("Ingested {} images into {}", ingested, index.display());
+    Ok(json!({
+        "status": "ok",
+        "directory": dir.display().to_string(),
+        "index": index.display().to_string(),
+        "ingested": ingested,
+        "failed": failed,
+    }))
+}
+
+/// Images worth ingesting: not our own sidecars, index or hidden temp files.
+fn wants(path: &Path) -> bool {
+    let hidden = path.file_name().and_then(|name| name.to_str()).is_none_or(|name| name.starts_with('.'));
+    !hidden && path.is_file() && ocr_batch::is_image(path)
+}
+
+fn sidecar_path(path: &Path) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(SIDECAR_SUFFIX);
+    PathBuf::from(sidecar)
+}
+
+/// Wait until `path` stops growing. False if it vanished, never settled or
+/// the run was cancelled.
+async fn wait_until_settled(path: &Path, cancel: &CancellationToken) -> bool {
+    let started = Instant::now();
+    let mut last_size = None;
+    while started.elapsed() < SETTLE_TIMEOUT {
+        let Ok(metadata) = std::fs::metadata(path) else {
+            return false;
+        };
+        let size = metadata.len();
+        if size > 0 && last_size == Some(size) {
+            return true;
+        }
+        last_size = Some(size);
+        tokio::select! {
+            _ = cancel.cancelled() => return false,
+            _ = tokio::time::sleep(SETTLE_TIME) => {}
+        }
+    }
+    status!("{} kept changing, skipping it", path.display());
+    false
+}
+
+/// `ingest_file` on the blocking pool, since decoding and recognition would
+/// otherwise hold an executor thread for the whole image.
+async fn ingest_blocking(engine: &Arc<SharedEngine>, path: &Path, index: &Path, use_cache: bool) -> Result<(), String> {
+    let (engine, path, index) = (engine.clone(), path.to_path_buf(), index.to_path_buf());
+    tokio::task::spawn_blocking(move || ingest_file(&engine, &path, &index, use_cache))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+/// OCR one image, write its sidecar and append it to the index.
+fn ingest_file(engine: &SharedEngine, path: &Path, index: &Path, use_cache: bool) -> Result<(), String> {
+    let image = image::open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?
+        .to_rgb8();
+    let lines = engine.extract_text(image.width(), image.height(), image.as_raw(), use_cache)?;
+    let entry = Entry {
+        file: path.display().to_string(),
+        timestamp: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0),
+        width: image.width(),
+        height: image.height(),
+        text: lines.join("\n"),
+        lines,
+    };
+
+    let sidecar = sidecar_path(path);
+    let document = serde_json::to_string_pretty(&entry).map_err(|e| e.to_string())?;
+    std::fs::write(&sidecar, document).map_err(|e| format!("Failed to write {}: {}", sidecar.display(), e))?;
+    let record = serde_json::to_string(&entry).map_err(|e| e.to_string())?;
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(index)
+        .and_then(|mut file| writeln!(file, "{}", record))
+        .map_err(|e| format!("Failed to append to {}: {}", index.display(), e))?;
+
+    status!("{}: {} lines", path.display(), entry.lines.len());
+    let mut event = serde_json::to_value(&entry).unwrap_or_default();
+    event["event"] = json!("ingested");
+    output::event(&event);
+    Ok(())
+}
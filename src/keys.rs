@@ -0,0 +1,206 @@
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::io::{BufRead, IsTerminal};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::Rgb888;
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::{Baseline, Text};
+
+use crate::error::{CaptestError, ErrorKind};
+use crate::platforms::{self, KeyPress};
+use crate::privacy;
+
+/// How long the caption stays up after the last key press.
+const CAPTION_DURATION: Duration = Duration::from_millis(2000);
+/// Longest caption shown; older keys scroll off the left.
+const MAX_CAPTION_CHARS: usize = 40;
+/// How long to wait for the keyboard hook to fail before assuming it works.
+const HOOK_STARTUP: Duration = Duration::from_millis(300);
+/// Frame height that gets the font at its native size; taller frames scale it up.
+const BASE_FRAME_HEIGHT: u32 = 540;
+const BACKGROUND_OPACITY: f64 = 0.65;
+
+const WARNING: &str = "\
+================================================================
+  WARNING: --show-keys records every key you press into the video,
+  including passwords, messages and anything else you type while
+  recording. Keys typed while a window from the privacy blocklist
+  is active are left out, but nothing else is filtered.
+  Only share the recording if everything typed may be seen.
+================================================================";
+
+/// Print the keystroke warning and get the user's consent: an interactive
+/// confirmation, or `accepted` (`--accept-keystroke-capture`) when stdin
+/// isn't a terminal.
+pub fn confirm_consent(accepted: bool) -> Result<(), CaptestError> {
+    // Printed even with --quiet, so the warning can't be missed
+    eprintln!("{}", WARNING);
+    if accepted {
+        return Ok(());
+    }
+    let refused = || CaptestError::new(ErrorKind::PermissionDenied, "Keystroke capture was not confirmed");
+    if !std::io::stdin().is_terminal() {
+        return Err(CaptestError::new(
+            ErrorKind::PermissionDenied,
+            "--show-keys needs confirmation; pass --accept-keystroke-capture when running non-interactively",
+        ));
+    }
+    eprint!("Record keystrokes into the video? [y/N] ");
+    let mut answer = String::new();
+    std::io::stdin().lock().read_line(&mut answer).map_err(|_| refused())?;
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => Err(refused()),
+    }
+}
+
+/// Collects key presses from the platform keyboard hook and renders them as
+/// a screenkey-style caption at the bottom of recorded frames.
+pub struct KeyCaptions {
+    keys: Arc<Mutex<VecDeque<(String, Instant)>>>,
+    stop: Arc<AtomicBool>,
+}
+
+impl KeyCaptions {
+    pub fn start() -> Result<Self, CaptestError> {
+        let keys = Arc::new(Mutex::new(VecDeque::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sender, receiver) = mpsc::channel::<KeyPress>();
+
+        let hook_stop = stop.clone();
+        let hook = std::thread::spawn(move || platforms::watch_keys(sender, &hook_stop));
+        std::thread::sleep(HOOK_STARTUP);
+        if hook.is_finished() {
+            let message = match hook.join() {
+                Ok(Err(message)) => message,
+                _ => "The keyboard hook stopped unexpectedly".to_string(),
+            };
+            return Err(CaptestError::new(ErrorKind::PermissionDenied, message));
+        }
+
+        let collected = keys.clone();
+        // Ends when the hook thread drops its sender
+        std::thread::spawn(move || {
+            for (modifiers, key) in receiver {
+                let sensitive = platforms::active_window().is_some_and(|(_, title)| privacy::is_sensitive_title(&title));
+                if sensitive {
+                    continue;
+                }
+                let mut keys = collected.lock().unwrap();
+                keys.push_back((label(&modifiers, key), Instant::now()));
+                while keys.len() > MAX_CAPTION_CHARS {
+                    keys.pop_front();
+                }
+            }
+        });
+        Ok(Self { keys, stop })
+    }
+
+    /// The keys typed since the last pause of `CAPTION_DURATION`, or None once
+    /// the caption has timed out.
+    fn caption(&self) -> Option<String> {
+        let mut keys = self.keys.lock().unwrap();
+        if keys.back().is_none_or(|(_, at)| at.elapsed() > CAPTION_DURATION) {
+            keys.clear();
+            return None;
+        }
+        let caption: String = keys.iter().map(|(label, _)| label.as_str()).collect::<String>();
+        let caption = caption.split_whitespace().collect::<Vec<_>>().join(" ");
+        let skip = caption.chars().count().saturating_sub(MAX_CAPTION_CHARS);
+        Some(caption.chars().skip(skip).collect())
+    }
+
+    /// Draw the current caption onto an RGB8 frame.
+    pub fn draw(&self, width: u32, height: u32, rgb_data: &mut [u8]) {
+        let Some(caption) = self.caption() else {
+            return;
+        };
+        let scale = (height / BASE_FRAME_HEIGHT).max(1);
+        let font = &FONT_10X20;
+        let text_width = font.character_size.width * caption.chars().count() as u32 * scale;
+        let text_height = font.character_size.height * scale;
+        let padding = 8 * scale;
+
+        let box_width = (text_width + 2 * padding).min(width);
+        let box_height = (text_height + 2 * padding).min(height);
+        let left = (width - box_width) / 2;
+        let top = height.saturating_sub(box_height + height / 10);
+        for y in top..top + box_height {
+            let row = (y * width) as usize * 3;
+            for value in &mut rgb_data[row + left as usize * 3..row + (left + box_width) as usize * 3] {
+                *value = (*value as f64 * (1.0 - BACKGROUND_OPACITY)) as u8;
+            }
+        }
+
+        let mut canvas = Canvas { rgb_data, width, height, scale };
+        let style = MonoTextStyle::new(font, Rgb888::WHITE);
+        let origin = Point::new(((left + padding) / scale) as i32, ((top + padding) / scale) as i32);
+        let _ = Text::with_baseline(&caption, origin, style, Baseline::Top).draw(&mut canvas);
+    }
+}
+
+impl Drop for KeyCaptions {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+/// How a key press appears in the caption: typed characters as they are,
+/// shortcuts and special keys as "Ctrl+C" or "Return" between spaces.
+fn label(modifiers: &[&str], key: &str) -> String {
+    let printable = key.chars().count() == 1;
+    match modifiers {
+        [] if printable => key.to_string(),
+        ["Shift"] if printable && key.chars().all(|c| c.is_ascii_alphabetic()) => key.to_uppercase(),
+        _ => {
+            let key = if printable { key.to_uppercase() } else { key.to_string() };
+            let mut parts = modifiers.to_vec();
+            parts.push(&key);
+            format!(" {} ", parts.join("+"))
+        }
+    }
+}
+
+/// An RGB8 frame as a drawing target, with every font pixel drawn as a
+/// `scale` x `scale` block.
+pub struct Canvas<'a> {
+    pub rgb_data: &'a mut [u8],
+    pub width: u32,
+    pub height: u32,
+    pub scale: u32,
+}
+
+impl OriginDimensions for Canvas<'_> {
+    fn size(&self) -> Size {
+        Size::new(self.width / self.scale, self.height / self.scale)
+    }
+}
+
+impl DrawTarget for Canvas<'_> {
+    type Color = Rgb888;
+    type Error = Infallible;
+
+    fn draw_iter<I: IntoIterator<Item = Pixel<Rgb888>>>(&mut self, pixels: I) -> Result<(), Infallible> {
+        for Pixel(point, color) in pixels {
+            let (Ok(x), Ok(y)) = (u32::try_from(point.x), u32::try_from(point.y)) else {
+                continue;
+            };
+            for dy in 0..self.scale {
+                for dx in 0..self.scale {
+                    let (px, py) = (x * self.scale + dx, y * self.scale + dy);
+                    if px >= self.width || py >= self.height {
+                        continue;
+                    }
+                    let offset = ((py * self.width + px) * 3) as usize;
+                    self.rgb_data[offset..offset + 3].copy_from_slice(&[color.r(), color.g(), color.b()]);
+                }
+            }
+        }
+        Ok(())
+    }
+}
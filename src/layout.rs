@@ -0,0 +1,179 @@
+use serde::{Deserialize, Serialize};
+
+/// A recognized text line with its axis-aligned bounding box in image pixels.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LineBox {
+    pub text: String,
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+    /// The line's words, left to right along the baseline
+    #[serde(default)]
+    pub words: Vec<WordBox>,
+}
+
+/// A recognized word with its axis-aligned bounding box in image pixels.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WordBox {
+    pub text: String,
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+    /// Direction of the baseline in degrees clockwise from horizontal: 0 for
+    /// upright text, 90 for text running top to bottom, -90 for bottom to top
+    pub angle: f32,
+    /// 0 to 1 heuristic of how word-like the text is, from its characters
+    /// alone; not a recognizer score, since ocrs doesn't report one
+    pub plausibility: f32,
+}
+
+impl LineBox {
+    pub fn height(&self) -> i32 {
+        (self.bottom - self.top).max(1)
+    }
+}
+
+/// A run of lines that belong together: vertically adjacent and horizontally
+/// overlapping, e.g. a paragraph or one panel of a sidebar.
+struct Block {
+    lines: Vec<LineBox>,
+    left: i32,
+    top: i32,
+    right: i32,
+    bottom: i32,
+}
+
+impl Block {
+    fn new(line: LineBox) -> Self {
+        Self { left: line.left, top: line.top, right: line.right, bottom: line.bottom, lines: vec![line] }
+    }
+
+    fn push(&mut self, line: LineBox) {
+        self.left = self.left.min(line.left);
+        self.top = self.top.min(line.top);
+        self.right = self.right.max(line.right);
+        self.bottom = self.bottom.max(line.bottom);
+        self.lines.push(line);
+    }
+
+    /// Whether `line` continues this block: it starts close below the last
+    /// line and shares most of its horizontal extent.
+    fn accepts(&self, line: &LineBox) -> bool {
+        let last = self.lines.last().unwrap();
+        let gap = line.top - last.bottom;
+        let overlap = last.right.min(line.right) - last.left.max(line.left);
+        let narrower = (last.right - last.left).min(line.right - line.left).max(1);
+        gap <= last.height().max(line.height()) * 3 / 2 && overlap * 2 >= narrower
+    }
+}
+
+/// Order lines the way a person would read them. Lines are grouped into blocks
+/// and the blocks are ordered by recursively cutting the page at vertical gaps
+/// (left to right) and horizontal gaps (top to bottom), so side-by-side
+/// columns and panels are read one after another instead of interleaved.
+pub fn reading_order(lines: Vec<LineBox>) -> Vec<LineBox> {
+    reading_order_blocks(lines).into_iter().flatten().collect()
+}
+
+/// Like [`reading_order`], but keeps the lines of each block together so
+/// callers can tell paragraphs and panels apart.
+pub fn reading_order_blocks(mut lines: Vec<LineBox>) -> Vec<Vec<LineBox>> {
+    lines.sort_by_key(|line| (line.top, line.left));
+
+    let mut blocks: Vec<Block> = Vec::new();
+    for line in lines {
+        // Newer blocks are checked first since they are closest to this line
+        match blocks.iter_mut().rev().find(|block| block.accepts(&line)) {
+            Some(block) => block.push(line),
+            None => blocks.push(Block::new(line)),
+        }
+    }
+
+    let mut ordered = Vec::new();
+    xy_cut(blocks, &mut ordered);
+    ordered
+}
+
+fn xy_cut(mut blocks: Vec<Block>, out: &mut Vec<Vec<LineBox>>) {
+    if blocks.len() <= 1 {
+        out.extend(blocks.into_iter().map(|block| block.lines));
+        return;
+    }
+
+    // Column gutters first; headings spanning the columns block that cut and get
+    // separated by a horizontal one instead. Trying horizontal cuts first would
+    // split columns wherever their paragraph gaps happen to line up.
+    for horizontal in [false, true] {
+        let span = |block: &Block| if horizontal { (block.top, block.bottom) } else { (block.left, block.right) };
+        blocks.sort_by_key(|block| span(block).0);
+
+        let mut end = span(&blocks[0]).1;
+        for i in 1..blocks.len() {
+            let (start, stop) = span(&blocks[i]);
+            if start > end {
+                let rest = blocks.split_off(i);
+                xy_cut(blocks, out);
+                xy_cut(rest, out);
+                return;
+            }
+            end = end.max(stop);
+        }
+    }
+
+    // No clean cut, e.g. overlapping blocks: fall back to top to bottom
+    blocks.sort_by_key(|block| (block.top, block.left));
+    out.extend(blocks.into_iter().map(|block| block.lines));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(text: &str, left: i32, top: i32, right: i32, bottom: i32) -> LineBox {
+        LineBox { text: text.to_string(), left, top, right, bottom, words: Vec::new() }
+    }
+
+    fn texts(lines: &[LineBox]) -> Vec<&str> {
+        lines.iter().map(|line| line.text.as_str()).collect()
+    }
+
+    #[test]
+    fn reads_side_by_side_columns_one_after_another() {
+        let lines = vec![
+            line("left 1", 0, 0, 400, 20),
+            line("right 1", 600, 0, 1000, 20),
+            line("left 2", 0, 25, 400, 45),
+            line("right 2", 600, 25, 1000, 45),
+            line("left 3", 0, 50, 400, 70),
+            line("right 3", 600, 50, 1000, 70),
+        ];
+        assert_eq!(texts(&reading_order(lines)), ["left 1", "left 2", "left 3", "right 1", "right 2", "right 3"]);
+    }
+
+    #[test]
+    fn reads_a_full_width_heading_before_the_columns_below_it() {
+        let lines = vec![
+            line("right", 600, 150, 1000, 170),
+            line("left", 0, 150, 400, 170),
+            line("title", 0, 0, 1000, 40),
+        ];
+        let blocks = reading_order_blocks(lines);
+        let blocks: Vec<Vec<&str>> = blocks.iter().map(|block| texts(block)).collect();
+        assert_eq!(blocks, [vec!["title"], vec!["left"], vec!["right"]]);
+    }
+
+    #[test]
+    fn separates_paragraphs_at_large_vertical_gaps() {
+        let lines = vec![
+            line("one", 0, 0, 500, 20),
+            line("two", 0, 25, 500, 45),
+            line("three", 0, 200, 500, 220),
+        ];
+        let blocks = reading_order_blocks(lines);
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(texts(&blocks[0]), ["one", "two"]);
+        assert_eq!(texts(&blocks[1]), ["three"]);
+    }
+}
@@ -0,0 +1,251 @@
+//! Screen capture, OCR and LLM analysis as a library, for embedding the
+//! `captest` pipeline in other tools instead of running the binary.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), captest::CaptestError> {
+//! let image = captest::capture_screen_to_image(0).await?;
+//! for line in captest::ocr_image(&image).await? {
+//!     println!("{} at {},{}", line.text, line.left, line.top);
+//! }
+//! let analysis = captest::analyze_image(&image, Some("Is an error dialog showing?")).await?;
+//! println!("{}", analysis.text);
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Settings such as the LLM endpoint and OCR models come from
+//! [`config::init`]; without it the defaults the command line uses apply.
+//! Progress text is not printed; `output::set_quiet(false)` prints it like the
+//! command line does.
+
+use std::time::Duration;
+
+use image::RgbImage;
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+#[macro_use]
+pub mod output;
+#[cfg(feature = "editor")]
+mod annotate;
+pub mod args;
+pub mod audio;
+mod bundle;
+mod cache;
+pub mod capture;
+pub mod capture_manager;
+pub mod capture_sync;
+pub mod cas;
+pub mod change;
+pub mod clipboard;
+pub mod config;
+pub mod convert;
+pub mod coords;
+mod cursor;
+pub mod diff;
+pub mod diff_dir;
+mod digest;
+mod dirty_rects;
+pub mod doctor;
+pub mod edid;
+#[cfg(feature = "editor")]
+pub mod editor;
+pub mod elements;
+pub mod encode;
+pub mod error;
+pub mod events;
+pub mod extract;
+pub mod fleet;
+mod follow;
+mod gif;
+mod hocr;
+mod keys;
+#[cfg(feature = "gpu")]
+mod gpu;
+pub mod icon;
+pub mod ingest;
+mod layout;
+pub mod links;
+pub mod llm;
+pub mod memory;
+pub mod monitor;
+mod mkv;
+mod mp4;
+pub mod mqtt;
+pub mod ocr;
+pub mod ocr_batch;
+mod pause;
+pub mod platforms;
+pub mod preset;
+pub mod privacy;
+pub mod progress;
+pub mod quality;
+mod rate_limit;
+pub mod raw_dump;
+pub mod record;
+pub mod region_hash;
+pub mod remote;
+pub mod scroll_capture;
+pub mod server;
+pub mod session;
+pub mod session_pool;
+pub mod sink;
+mod spell;
+pub mod template;
+pub mod text_diff;
+pub mod timestamp;
+pub mod timings;
+mod title_trigger;
+pub mod track;
+pub mod transcribe;
+pub mod upload;
+pub mod validate;
+pub mod watch;
+pub mod webhook;
+mod websocket;
+pub mod window_match;
+mod zip;
+
+pub use args::{DiffArgs, MonitorArgs, RecordArgs, RemoteCaptureArgs, SessionArgs, TrackArgs, WatchArgs};
+pub use capture_manager::{CaptureManager, FrameQueue};
+pub use error::{CaptestError, ErrorKind};
+pub use events::{subscribe, CaptureEvent, CapturedFrame, SubscribeOptions, Subscription};
+pub use layout::{LineBox, WordBox};
+pub use llm::{ImageMapping, Usage};
+pub use pause::Gap;
+pub use session_pool::TargetKey;
+
+/// Capture screen `index` (as numbered by `captest list`) as an RGB image.
+pub async fn capture_screen_to_image(index: usize) -> Result<RgbImage, CaptestError> {
+    capture_to_image(TargetKey::Screen(index)).await
+}
+
+/// Capture window `index` (as numbered by `captest list-windows`) as an RGB image.
+pub async fn capture_window_to_image(index: usize) -> Result<RgbImage, CaptestError> {
+    capture_to_image(TargetKey::Window(index)).await
+}
+
+async fn capture_to_image(key: TargetKey) -> Result<RgbImage, CaptestError> {
+    let (width, height, rgb_data) = capture::grab_rgb8(key, &CancellationToken::new()).await.map_err(typed)?;
+    RgbImage::from_raw(width, height, rgb_data)
+        .ok_or_else(|| CaptestError::new(ErrorKind::Failure, "Frame buffer doesn't match its size"))
+}
+
+/// Recognize the text in an image: lines in reading order, each with its
+/// bounding box and words.
+pub async fn ocr_image(image: &RgbImage) -> Result<Vec<LineBox>, CaptestError> {
+    ocr::extract_lines(image.width(), image.height(), image.as_raw(), false).await
+        .map_err(|e| CaptestError::new(ErrorKind::OcrFailed, e.to_string()))
+}
+
+/// Analyze an image with the configured LLM, with the default prompt unless
+/// `prompt` is given. Large images are scaled or tiled as for `capture
+/// --analyze`, and coordinates in the answer are in image pixels.
+pub async fn analyze_image(image: &RgbImage, prompt: Option<&str>) -> Result<FrameAnalysis, CaptestError> {
+    let jpeg_bytes = rgb8_to_jpeg_bytes(image.width(), image.height(), image.as_raw()).map_err(typed)?;
+    let frame = ImageMapping::frame(image.width(), image.height(), (0, 0), 1);
+    analyze_frame(image.as_raw(), &jpeg_bytes, frame, prompt, None, false, false).await
+        .map_err(|e| CaptestError::new(ErrorKind::LlmFailed, e.to_string()))
+}
+
+/// Keep the kind of errors raised as `CaptestError`; anything else is a
+/// generic failure.
+fn typed(err: Box<dyn std::error::Error>) -> CaptestError {
+    match err.downcast::<CaptestError>() {
+        Ok(err) => *err,
+        Err(err) => CaptestError::new(ErrorKind::Failure, err.to_string()),
+    }
+}
+
+/// Save an encoded image and return where it went: `filename`, or with `--cas` the
+/// content-addressed object that `filename` now maps to.
+pub fn save_image_bytes(image_bytes: &[u8], filename: &str) -> Result<String, Box<dyn std::error::Error>> {
+    use std::fs::File;
+    use std::io::Write;
+    
+    let _timing = timings::span(timings::Stage::Save);
+    if config::settings().cas {
+        return cas::store(image_bytes, filename);
+    }
+    status!("Saving {filename}");
+    if let Some(parent) = std::path::Path::new(filename).parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut file = File::create(filename)?;
+    file.write_all(image_bytes)?;
+    
+    Ok(filename.to_string())
+}
+
+pub fn rgb8_to_jpeg_bytes(width: u32, height: u32, rgb_data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    encode::rgb8_to_jpeg(width, height, rgb_data, preset::DEFAULT_JPEG_QUALITY)
+}
+
+/// What the LLM was sent for a frame and its answer, with coordinates in
+/// screen pixels.
+#[derive(Serialize)]
+pub struct FrameAnalysis {
+    pub text: String,
+    /// The answer before its coordinates were mapped, when that changed it
+    pub raw_analysis: Option<String>,
+    pub usage: Option<llm::Usage>,
+    /// The image sent, when the frame was analyzed whole
+    pub image: Option<llm::ImageMapping>,
+    /// The images sent, when the frame was analyzed in tiles
+    pub tiles: Vec<llm::ImageMapping>,
+}
+
+/// Analyze a frame with the LLM: whole, scaled to the provider's preferred
+/// resolution, or in overlapping tiles when that would shrink it too much.
+pub async fn analyze_frame(rgb_data: &[u8], jpeg_bytes: &[u8], frame: llm::ImageMapping, prompt: Option<&str>, cache_ttl: Option<Duration>, full_resolution: bool, no_tiles: bool) -> Result<FrameAnalysis, Box<dyn std::error::Error>> {
+    if !full_resolution && !no_tiles && let Some(tiles) = llm::plan_tiles(&frame) {
+        status!("Analyzing the {}x{} frame in {} overlapping tiles", frame.width, frame.height, tiles.len());
+        let analysis = llm::analyze_tiles(frame.width, frame.height, rgb_data, &tiles, prompt, cache_ttl).await?;
+        return Ok(FrameAnalysis {
+            text: analysis.text,
+            raw_analysis: None,
+            usage: analysis.usage,
+            image: None,
+            tiles: tiles.iter().map(llm::Tile::mapping).collect(),
+        });
+    }
+
+    let mapping = llm_mapping(frame, full_resolution);
+    let resized = llm_jpeg(rgb_data, &frame, &mapping)?;
+    let analysis = llm::analyze_image_cached(resized.as_deref().unwrap_or(jpeg_bytes), prompt, cache_ttl).await?;
+    let (text, raw_analysis) = map_coordinates(analysis.text, &mapping);
+    Ok(FrameAnalysis { text, raw_analysis, usage: analysis.usage, image: Some(mapping), tiles: Vec::new() })
+}
+
+/// The size to send the LLM a frame at: scaled down to the resolution the
+/// provider works at, unless it already fits or `full_resolution` is set.
+fn llm_mapping(frame: llm::ImageMapping, full_resolution: bool) -> llm::ImageMapping {
+    frame.fitted().filter(|_| !full_resolution).unwrap_or(frame)
+}
+
+/// A JPEG of the frame resized for `mapping`, or None when the frame is
+/// sent as is.
+fn llm_jpeg(rgb_data: &[u8], frame: &llm::ImageMapping, mapping: &llm::ImageMapping) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+    if (mapping.width, mapping.height) == (frame.width, frame.height) {
+        return Ok(None);
+    }
+    status!("Sending the LLM a {}x{} copy of the {}x{} frame", mapping.width, mapping.height, frame.width, frame.height);
+    let image = image::RgbImage::from_raw(frame.width, frame.height, rgb_data.to_vec()).ok_or("Frame buffer doesn't match its size")?;
+    let resized = image::imageops::resize(&image, mapping.width, mapping.height, image::imageops::FilterType::Triangle);
+    Ok(Some(rgb8_to_jpeg_bytes(mapping.width, mapping.height, resized.as_raw())?))
+}
+
+/// Rewrite the coordinates in an analysis of an image sent with `mapping`
+/// into screen pixels. Returns the text to report and, when anything was
+/// rewritten, the model's original answer.
+fn map_coordinates(text: String, mapping: &llm::ImageMapping) -> (String, Option<String>) {
+    match mapping.is_identity() {
+        true => (text, None),
+        false => match llm::rescale_coordinates(&text, mapping) {
+            Some(rescaled) => (rescaled, Some(text)),
+            None => (text, None),
+        },
+    }
+}
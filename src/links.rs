@@ -0,0 +1,204 @@
+use std::collections::HashSet;
+use std::sync::LazyLock;
+
+use regex::Regex;
+use serde::Serialize;
+
+use crate::layout::LineBox;
+
+static EMAIL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b[a-z0-9._%+-]+@[a-z0-9-]+(?:\.[a-z0-9-]+)*\.[a-z]{2,}\b").unwrap()
+});
+
+static URL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(concat!(
+        r#"(?i)\b(?:https?://|ftp://|www\.)[^\s<>"'`]+"#,
+        r#"|\b[a-z0-9-]+(?:\.[a-z0-9-]+)*\.(?:com|org|net|io|dev|app|edu|gov|co|ai|rs|me|info|uk|de)\b(?:/[^\s<>"'`]*)?"#,
+    )).unwrap()
+});
+
+/// Common ways links are defanged or garbled: `hxxp`, `[.]`, `(at)`, and
+/// spaces OCR inserts after the scheme.
+static OBFUSCATIONS: LazyLock<Vec<(Regex, &'static str)>> = LazyLock::new(|| {
+    vec![
+        (Regex::new(r"(?i)\bhxxp").unwrap(), "http"),
+        (Regex::new(r"(?i)\s*[\[({]\s*(?:dot|\.)\s*[\])}]\s*").unwrap(), "."),
+        (Regex::new(r"(?i)\s*[\[({]\s*(?:at|@)\s*[\])}]\s*").unwrap(), "@"),
+        (Regex::new(r"\[(:|://)\]").unwrap(), "$1"),
+        (Regex::new(r"(?i)\b(https?|ftp)\s*:\s*/\s*/\s*").unwrap(), "$1://"),
+    ]
+});
+
+/// A URL continues on the next line when it breaks right after one of these.
+const WRAP_CHARS: &[char] = &['/', '-', '_', '.', '?', '&', '=', '#'];
+
+#[derive(Serialize, Debug)]
+pub struct Link {
+    /// `url` or `email`
+    pub kind: &'static str,
+    pub text: String,
+    /// Shown shortened with an ellipsis, so the text is incomplete
+    pub truncated: bool,
+    /// Approximate on-screen position, interpolated along the OCR line
+    pub left: i32,
+    pub top: i32,
+    pub right: i32,
+    pub bottom: i32,
+}
+
+/// Find the unique URLs and email addresses in OCR lines (in reading order),
+/// joining URLs that wrap onto the following line.
+pub fn find_links(lines: &[LineBox]) -> Vec<Link> {
+    let texts: Vec<String> = lines.iter().map(|line| deobfuscate(&line.text)).collect();
+    let mut links = Vec::new();
+    let mut seen = HashSet::new();
+    // Bytes at the start of each line already used as the tail of a wrapped URL
+    let mut consumed = vec![0; lines.len()];
+
+    for (i, (line, text)) in lines.iter().zip(&texts).enumerate() {
+        let scan = &text[consumed[i]..];
+        let offset = consumed[i];
+
+        let emails: Vec<_> = EMAIL.find_iter(scan).collect();
+        for email in &emails {
+            push_unique(&mut links, &mut seen, "email", email.as_str().to_lowercase(),
+                line_span(line, text, offset + email.start(), offset + email.end()));
+        }
+
+        for url in URL.find_iter(scan) {
+            if emails.iter().any(|email| url.start() < email.end() && email.start() < url.end()) {
+                continue;
+            }
+
+            let mut url_text = url.as_str().to_string();
+            let mut span = line_span(line, text, offset + url.start(), offset + url.end());
+            if url.end() == scan.len()
+                && let Some((next_line, next_text)) = lines.get(i + 1).zip(texts.get(i + 1))
+                && let Some(tail) = wrapped_tail(&url_text, next_text)
+            {
+                url_text.push_str(tail);
+                consumed[i + 1] = tail.len();
+                let tail_span = line_span(next_line, next_text, 0, tail.len());
+                span = (span.0.min(tail_span.0), span.1, span.2.max(tail_span.2), tail_span.3);
+            }
+            push_unique(&mut links, &mut seen, "url", url_text, span);
+        }
+    }
+
+    links
+}
+
+fn deobfuscate(text: &str) -> String {
+    OBFUSCATIONS.iter().fold(text.to_string(), |text, (pattern, replacement)| {
+        pattern.replace_all(&text, *replacement).into_owned()
+    })
+}
+
+/// The start of `next` that continues a URL broken at the end of a line.
+fn wrapped_tail<'a>(url: &str, next: &'a str) -> Option<&'a str> {
+    let token = next.split_whitespace().next()?;
+    let continues = url.ends_with(WRAP_CHARS) || token.starts_with(['/', '?', '&', '=', '#']);
+    let url_like = token.chars().all(|c| c.is_ascii_alphanumeric() || "/?&=#%_.~+-:".contains(c));
+    (continues && url_like && next.starts_with(token)).then_some(token)
+}
+
+fn push_unique(links: &mut Vec<Link>, seen: &mut HashSet<String>, kind: &'static str, text: String, span: (i32, i32, i32, i32)) {
+    let (text, truncated) = clean_link(&text);
+    if text.len() < 4 || !seen.insert(text.clone()) {
+        return;
+    }
+    let (left, top, right, bottom) = span;
+    links.push(Link { kind, text, truncated, left, top, right, bottom });
+}
+
+/// Strip sentence punctuation and ellipses the match picked up at the end.
+fn clean_link(text: &str) -> (String, bool) {
+    let truncated = text.contains('…') || text.ends_with("...");
+    let text = text
+        .trim_end_matches(['…', '.', ',', ';', ':', '!', '?', ')', ']', '}', '\'', '"'])
+        .to_string();
+    (text, truncated)
+}
+
+/// Estimate the box of `text[start..end]` by interpolating along the line.
+fn line_span(line: &LineBox, text: &str, start: usize, end: usize) -> (i32, i32, i32, i32) {
+    let len = text.len().max(1) as f64;
+    let width = (line.right - line.left) as f64;
+    let x = |pos: usize| line.left + (width * pos as f64 / len).round() as i32;
+    (x(start), line.top, x(end), line.bottom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// OCR lines of the given texts, one below the other, 10 pixels a byte.
+    fn lines(texts: &[&str]) -> Vec<LineBox> {
+        texts.iter().enumerate().map(|(i, text)| {
+            let top = i as i32 * 20;
+            LineBox { text: text.to_string(), left: 0, top, right: text.len() as i32 * 10, bottom: top + 16, words: Vec::new() }
+        }).collect()
+    }
+
+    fn texts(links: &[Link]) -> Vec<(&str, &str)> {
+        links.iter().map(|link| (link.kind, link.text.as_str())).collect()
+    }
+
+    #[test]
+    fn finds_defanged_links() {
+        let links = find_links(&lines(&[
+            "Blocked hxxp://evil[.]example[.]com/login today",
+            "Also hxxps[://]phish(dot)example.org and admin(at)example.net",
+            "OCR read http : / / spaced.example.io/x",
+        ]));
+        assert_eq!(texts(&links), [
+            ("url", "http://evil.example.com/login"),
+            ("email", "admin@example.net"),
+            ("url", "https://phish.example.org"),
+            ("url", "http://spaced.example.io/x"),
+        ]);
+    }
+
+    #[test]
+    fn joins_urls_wrapped_across_lines() {
+        let links = find_links(&lines(&["Docs at https://example.com/docs/getting-", "started/install.html for setup"]));
+        assert_eq!(texts(&links), [("url", "https://example.com/docs/getting-started/install.html")]);
+        let link = &links[0];
+        assert_eq!((link.left, link.top, link.right, link.bottom), (0, 0, 410, 36));
+    }
+
+    #[test]
+    fn leaves_following_text_off_urls() {
+        let links = find_links(&lines(&["See https://example.com/a", "and more"]));
+        assert_eq!(texts(&links), [("url", "https://example.com/a")]);
+    }
+
+    #[test]
+    fn strips_trailing_punctuation() {
+        let links = find_links(&lines(&[
+            "Go to https://example.com/page.",
+            "(see www.example.org/help),",
+            "Is it example.dev?",
+            "Read https://example.com/a/very/long…",
+        ]));
+        assert_eq!(texts(&links), [
+            ("url", "https://example.com/page"),
+            ("url", "www.example.org/help"),
+            ("url", "example.dev"),
+            ("url", "https://example.com/a/very/long"),
+        ]);
+        assert_eq!(links.iter().map(|link| link.truncated).collect::<Vec<_>>(), [false, false, false, true]);
+    }
+
+    #[test]
+    fn reports_each_link_once() {
+        let links = find_links(&lines(&["Mail Bob@Example.com or bob@example.com", "https://example.com and https://example.com"]));
+        assert_eq!(texts(&links), [("email", "bob@example.com"), ("url", "https://example.com")]);
+    }
+
+    #[test]
+    fn ignores_text_without_links() {
+        let links = find_links(&lines(&["Version 1.2.3 costs $4.50, e.g. in file.txt", "See section 2.1 (at the end) for a.b.c", ""]));
+        assert!(links.is_empty(), "{:?}", links);
+    }
+}
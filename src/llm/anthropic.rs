@@ -0,0 +1,89 @@
+use serde_json::json;
+
+use super::{model, priced_usage, Analysis, Message, RequestError};
+use crate::{config, progress};
+
+const API_VERSION: &str = "2023-06-01";
+
+/// Send messages to the Anthropic Messages API. The API key is read from
+/// `ANTHROPIC_API_KEY`.
+pub async fn chat(messages: &[Message]) -> Result<Analysis, Box<dyn std::error::Error>> {
+    // Turns have to alternate between user and assistant, so everything goes
+    // into one user turn. Images come first, each followed by its text.
+    let mut content = Vec::new();
+    for message in messages {
+        content.extend(message.images.iter().map(|image| json!({
+            "type": "image",
+            "source": {
+                "type": "base64",
+                "media_type": "image/jpeg",
+                "data": image
+            }
+        })));
+        content.push(json!({ "type": "text", "text": message.text }));
+    }
+
+    // Unlike the other APIs, max_tokens is required here
+    let payload = json!({
+        "model": model(),
+        "max_tokens": 1000,
+        "messages": [{ "role": "user", "content": content }]
+    });
+
+    let api_key = api_key()?;
+    let llm_url = &config::settings().llm_url;
+    let pb = progress::spinner("Waiting for LLM response");
+    let response = reqwest::Client::new()
+        .post(format!("{}/v1/messages", llm_url.trim_end_matches('/')))
+        .header("x-api-key", api_key)
+        .header("anthropic-version", API_VERSION)
+        .header(config::RUN_ID_HEADER, &config::settings().run_id)
+        .json(&payload)
+        .send()
+        .await;
+    pb.finish_and_clear();
+    let response = response?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let error_text = response.text().await?;
+        return Err(RequestError::new(status.as_u16(), error_text, None).into());
+    }
+
+    let response_json: serde_json::Value = response.json().await?;
+    let usage = response_json["usage"]["input_tokens"].as_u64().map(|prompt_tokens| {
+        let completion_tokens = response_json["usage"]["output_tokens"].as_u64().unwrap_or(0);
+        priced_usage(prompt_tokens, completion_tokens, prompt_tokens + completion_tokens)
+    });
+
+    let text: Vec<&str> = response_json["content"].as_array().into_iter().flatten()
+        .filter(|block| block["type"] == "text")
+        .filter_map(|block| block["text"].as_str())
+        .collect();
+    if text.is_empty() {
+        return Err("No content in LLM response".into());
+    }
+    Ok(Analysis { text: text.join("\n"), usage })
+}
+
+pub async fn list_models() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let api_key = api_key()?;
+    let llm_url = &config::settings().llm_url;
+    let response = reqwest::Client::new()
+        .get(format!("{}/v1/models", llm_url.trim_end_matches('/')))
+        .header("x-api-key", api_key)
+        .header("anthropic-version", API_VERSION)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let response_json: serde_json::Value = response.json().await?;
+    Ok(response_json["data"].as_array().into_iter().flatten()
+        .filter_map(|model| model["id"].as_str().map(str::to_string))
+        .collect())
+}
+
+fn api_key() -> Result<String, Box<dyn std::error::Error>> {
+    std::env::var("ANTHROPIC_API_KEY")
+        .map_err(|_| "Set ANTHROPIC_API_KEY to use the anthropic provider".into())
+}
@@ -0,0 +1,266 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use base64::{Engine as _, engine::general_purpose};
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::timings::{self, Stage};
+use crate::{cache, config};
+
+mod anthropic;
+mod ollama;
+mod openai;
+mod scale;
+mod tile;
+
+pub use scale::{rescale_coordinates, ImageMapping};
+pub use tile::{analyze_tiles, plan_tiles, Tile};
+
+pub const DEFAULT_PROMPT: &str = "Analyze this screenshot and describe all UI elements, text, images and other information. Analyze text carefully and include the full text recognized in each area.";
+
+/// API dialect spoken by the LLM endpoint.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Debug, ValueEnum)]
+pub enum Provider {
+    /// OpenAI-compatible `/chat/completions` (LM Studio, vLLM, OpenAI, ...)
+    #[default]
+    #[value(name = "openai")]
+    OpenAi,
+    /// Ollama's native `/api/chat`
+    Ollama,
+    /// Anthropic Messages API (Claude), key from ANTHROPIC_API_KEY
+    Anthropic,
+}
+
+impl Provider {
+    pub fn default_url(self) -> &'static str {
+        match self {
+            Provider::OpenAi => config::DEFAULT_LLM_URL,
+            Provider::Ollama => "http://localhost:11434",
+            Provider::Anthropic => "https://api.anthropic.com",
+        }
+    }
+
+    fn default_model(self) -> &'static str {
+        match self {
+            Provider::OpenAi => "gpt-4-vision-preview",
+            Provider::Ollama => "llava",
+            Provider::Anthropic => "claude-sonnet-4-5",
+        }
+    }
+}
+
+/// One user message: text followed by zero or more base64 encoded JPEGs.
+struct Message {
+    text: String,
+    images: Vec<String>,
+}
+
+/// An endpoint answered a chat request with an error status.
+#[derive(Debug)]
+pub struct RequestError {
+    pub status: u16,
+    /// Response body, usually the provider's error message
+    pub body: String,
+    /// Advice appended to the message, e.g. which server should be running
+    hint: Option<String>,
+}
+
+impl RequestError {
+    fn new(status: u16, body: String, hint: Option<String>) -> Self {
+        Self { status, body, hint }
+    }
+
+    /// Whether the endpoint refused the request for holding more images than
+    /// it accepts per message, as servers for single-image models do, e.g.
+    /// "At most 1 image(s) may be provided in one request".
+    fn rejects_multiple_images(&self) -> bool {
+        let body = self.body.to_lowercase();
+        self.status == 400
+            && body.contains("image")
+            && ["at most", "only one", "only 1", "too many", "multiple", "maximum", "exceed"].iter().any(|phrase| body.contains(phrase))
+    }
+}
+
+impl std::fmt::Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "LLM request failed with status {}: {}", self.status, self.body)?;
+        if let Some(hint) = &self.hint {
+            write!(f, ". {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+/// Token counts reported by the endpoint in the response `usage` field.
+#[derive(Serialize, Clone, Copy, Default, Debug)]
+pub struct Usage {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    /// Estimated cost in USD, when token prices are configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cost_usd: Option<f64>,
+}
+
+impl Usage {
+    fn add(&mut self, other: &Usage) {
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        self.cost_usd = match (self.cost_usd, other.cost_usd) {
+            (None, None) => None,
+            (a, b) => Some(a.unwrap_or(0.0) + b.unwrap_or(0.0)),
+        };
+    }
+}
+
+impl std::fmt::Display for Usage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} prompt + {} completion = {} tokens",
+            self.prompt_tokens, self.completion_tokens, self.total_tokens)?;
+        if let Some(cost) = self.cost_usd {
+            write!(f, " (~${:.4})", cost)?;
+        }
+        Ok(())
+    }
+}
+
+/// Totals across every LLM request made by this process.
+#[derive(Serialize, Clone, Copy, Default, Debug)]
+pub struct SessionUsage {
+    pub requests: u64,
+    pub cache_hits: u64,
+    pub usage: Usage,
+}
+
+static SESSION_USAGE: Mutex<SessionUsage> = Mutex::new(SessionUsage {
+    requests: 0,
+    cache_hits: 0,
+    usage: Usage { prompt_tokens: 0, completion_tokens: 0, total_tokens: 0, cost_usd: None },
+});
+
+pub fn session_usage() -> SessionUsage {
+    *SESSION_USAGE.lock().unwrap()
+}
+
+pub struct Analysis {
+    pub text: String,
+    /// None for cache hits and endpoints that don't report usage
+    pub usage: Option<Usage>,
+}
+
+/// Analyze a JPEG, serving identical image/prompt/endpoint/model combinations
+/// from the on-disk cache when `cache_ttl` is set.
+pub async fn analyze_image_cached(jpeg_bytes: &[u8], prompt: Option<&str>, cache_ttl: Option<Duration>) -> Result<Analysis, Box<dyn std::error::Error>> {
+    let prompt = prompt.map(config::expand_run_id);
+    let prompt = prompt.as_deref();
+    // Identical pixels with the same prompt, endpoint and model give a cache hit
+    let cache = cache::DiskCache::new("llm");
+    let settings = config::settings();
+    let key = cache::cache_key(&[
+        jpeg_bytes,
+        prompt.unwrap_or_default().as_bytes(),
+        settings.llm_url.as_bytes(),
+        format!("{:?}", settings.llm_provider).as_bytes(),
+        model().as_bytes(),
+    ]);
+
+    if let Some(ttl) = cache_ttl
+        && let Some(text) = cache.get(&key, ttl)
+    {
+        status!("Using cached LLM analysis");
+        SESSION_USAGE.lock().unwrap().cache_hits += 1;
+        return Ok(Analysis { text, usage: None });
+    }
+
+    let base64_image = general_purpose::STANDARD.encode(jpeg_bytes);
+    let analysis = analyze_image_with_llm_base64(&base64_image, prompt).await?;
+    if cache_ttl.is_some() {
+        cache.put(&key, &analysis.text);
+    }
+    Ok(analysis)
+}
+
+pub async fn analyze_image_with_llm_base64(base64_image: &str, custom_prompt: Option<&str>) -> Result<Analysis, Box<dyn std::error::Error>> {
+    let prompt = custom_prompt.map(config::expand_run_id).unwrap_or_else(|| DEFAULT_PROMPT.to_string());
+    complete(&[Message { text: prompt, images: vec![base64_image.to_string()] }]).await
+}
+
+/// Analyze several JPEGs with one prompt in a single request. All images go in
+/// one message unless `split` is set, or the endpoint says it takes fewer
+/// images per message, in which case each image gets its own message.
+pub async fn analyze_images(jpeg_images: &[Vec<u8>], prompt: &str, split: bool) -> Result<Analysis, Box<dyn std::error::Error>> {
+    let images: Vec<String> = jpeg_images.iter().map(|jpeg| general_purpose::STANDARD.encode(jpeg)).collect();
+    let prompt = &config::expand_run_id(prompt);
+
+    if !split {
+        match complete(&[Message { text: prompt.to_string(), images: images.clone() }]).await {
+            Err(e) if images.len() > 1 && e.downcast_ref::<RequestError>().is_some_and(RequestError::rejects_multiple_images) => {
+                status!("Endpoint rejected multiple images in one message, retrying with one image per message");
+            }
+            result => return result,
+        }
+    }
+
+    let mut messages: Vec<Message> = images.iter().enumerate()
+        .map(|(i, image)| Message {
+            text: format!("Image {} of {}", i + 1, images.len()),
+            images: vec![image.clone()],
+        })
+        .collect();
+    messages.push(Message { text: prompt.to_string(), images: Vec::new() });
+    complete(&messages).await
+}
+
+/// Names of the models the configured endpoint offers.
+pub async fn list_models() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    match config::settings().llm_provider {
+        Provider::OpenAi => openai::list_models().await,
+        Provider::Ollama => ollama::list_models().await,
+        Provider::Anthropic => anthropic::list_models().await,
+    }
+}
+
+/// Send the conversation to the configured provider and record token usage.
+async fn complete(messages: &[Message]) -> Result<Analysis, Box<dyn std::error::Error>> {
+    let _timing = timings::span(Stage::Llm);
+    let analysis = match config::settings().llm_provider {
+        Provider::OpenAi => openai::chat(messages).await?,
+        Provider::Ollama => ollama::chat(messages).await?,
+        Provider::Anthropic => anthropic::chat(messages).await?,
+    };
+    record_usage(analysis.usage.as_ref());
+    Ok(analysis)
+}
+
+/// The model named in the settings, or the provider's usual vision model.
+fn model() -> String {
+    let settings = config::settings();
+    settings.llm_model.clone()
+        .unwrap_or_else(|| settings.llm_provider.default_model().to_string())
+}
+
+/// Price token counts with the configured rates.
+fn priced_usage(prompt_tokens: u64, completion_tokens: u64, total_tokens: u64) -> Usage {
+    let settings = config::settings();
+    let cost_usd = match (settings.input_token_price, settings.output_token_price) {
+        (None, None) => None,
+        // Prices are USD per million tokens
+        (input, output) => Some(
+            prompt_tokens as f64 * input.unwrap_or(0.0) / 1_000_000.0
+                + completion_tokens as f64 * output.unwrap_or(0.0) / 1_000_000.0,
+        ),
+    };
+    Usage { prompt_tokens, completion_tokens, total_tokens, cost_usd }
+}
+
+fn record_usage(usage: Option<&Usage>) {
+    let mut session = SESSION_USAGE.lock().unwrap();
+    session.requests += 1;
+    if let Some(usage) = usage {
+        session.usage.add(usage);
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use serde_json::json;
+
+use super::{model, priced_usage, Analysis, Message, RequestError};
+use crate::{config, progress};
+
+/// Set once the configured model is known to be present locally.
+static MODEL_READY: AtomicBool = AtomicBool::new(false);
+
+/// Send messages to Ollama's native `/api/chat`, pulling the model first if
+/// the server doesn't have it yet.
+pub async fn chat(messages: &[Message]) -> Result<Analysis, Box<dyn std::error::Error>> {
+    let model = model();
+    ensure_model(&model).await?;
+
+    let messages: Vec<serde_json::Value> = messages.iter().map(|message| json!({
+        "role": "user",
+        "content": message.text,
+        "images": message.images,
+    })).collect();
+
+    let payload = json!({
+        "model": model,
+        "messages": messages,
+        "stream": false,
+        "options": { "num_predict": 1000 }
+    });
+
+    let llm_url = &config::settings().llm_url;
+    let pb = progress::spinner("Waiting for LLM response");
+    let response = reqwest::Client::new()
+        .post(format!("{}/api/chat", llm_url.trim_end_matches('/')))
+        .header(config::RUN_ID_HEADER, &config::settings().run_id)
+        .json(&payload)
+        .send()
+        .await;
+    pb.finish_and_clear();
+    let response = response?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let error_text = response.text().await?;
+        let hint = format!("Make sure Ollama is running at {}.", llm_url);
+        return Err(RequestError::new(status.as_u16(), error_text, Some(hint)).into());
+    }
+
+    let response_json: serde_json::Value = response.json().await?;
+    let usage = response_json["prompt_eval_count"].as_u64().map(|prompt_tokens| {
+        let completion_tokens = response_json["eval_count"].as_u64().unwrap_or(0);
+        priced_usage(prompt_tokens, completion_tokens, prompt_tokens + completion_tokens)
+    });
+
+    if let Some(content) = response_json["message"]["content"].as_str() {
+        Ok(Analysis { text: content.to_string(), usage })
+    } else {
+        Err("No content in LLM response".into())
+    }
+}
+
+pub async fn list_models() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let llm_url = &config::settings().llm_url;
+    let response = reqwest::Client::new()
+        .get(format!("{}/api/tags", llm_url.trim_end_matches('/')))
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let response_json: serde_json::Value = response.json().await?;
+    Ok(response_json["models"].as_array().into_iter().flatten()
+        .filter_map(|model| model["name"].as_str().map(str::to_string))
+        .collect())
+}
+
+async fn ensure_model(model: &str) -> Result<(), Box<dyn std::error::Error>> {
+    if MODEL_READY.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    // Ollama reports untagged models as `name:latest`
+    let installed = list_models().await?;
+    let wanted = if model.contains(':') { model.to_string() } else { format!("{}:latest", model) };
+    if !installed.iter().any(|name| *name == wanted || name == model) {
+        let llm_url = &config::settings().llm_url;
+        let pb = progress::spinner(format!("Pulling {} (first use, this can take a while)", model));
+        status!("Model {} not found in Ollama, pulling it", model);
+        let response = reqwest::Client::new()
+            .post(format!("{}/api/pull", llm_url.trim_end_matches('/')))
+            .json(&json!({ "model": model, "stream": false }))
+            .send()
+            .await;
+        pb.finish_and_clear();
+
+        let response = response?;
+        if !response.status().is_success() {
+            let status = response.status().as_u16();
+            let error_text = response.text().await?;
+            return Err(format!("Failed to pull {}: status {}: {}", model, status, error_text).into());
+        }
+    }
+
+    MODEL_READY.store(true, Ordering::Relaxed);
+    Ok(())
+}
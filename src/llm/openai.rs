@@ -0,0 +1,77 @@
+use serde_json::json;
+
+use super::{model, priced_usage, Analysis, Message, RequestError, Usage};
+use crate::{config, progress};
+
+/// Send messages to an OpenAI-compatible `/chat/completions` endpoint.
+pub async fn chat(messages: &[Message]) -> Result<Analysis, Box<dyn std::error::Error>> {
+    let messages: Vec<serde_json::Value> = messages.iter().map(|message| {
+        let mut content = vec![json!({ "type": "text", "text": message.text })];
+        content.extend(message.images.iter().map(|image| json!({
+            "type": "image_url",
+            "image_url": {
+                "url": format!("data:image/jpeg;base64,{}", image)
+            }
+        })));
+        json!({ "role": "user", "content": content })
+    }).collect();
+
+    // Use reqwest directly to ensure proper vision API format
+    let vision_payload = json!({
+        "model": model(),
+        "messages": messages,
+        "max_tokens": 1000
+    });
+
+    let llm_url = &config::settings().llm_url;
+    let pb = progress::spinner("Waiting for LLM response");
+    let response = reqwest::Client::new()
+        .post(format!("{}/chat/completions", llm_url.trim_end_matches('/')))
+        .header("Authorization", "Bearer lm-studio")
+        .header("Content-Type", "application/json")
+        .header(config::RUN_ID_HEADER, &config::settings().run_id)
+        .json(&vision_payload)
+        .send()
+        .await;
+    pb.finish_and_clear();
+    let response = response?;
+
+    let status = response.status();
+    if !status.is_success() {
+        let error_text = response.text().await?;
+        let hint = format!("Make sure LMStudio is running at {} with a vision model loaded.", llm_url);
+        return Err(RequestError::new(status.as_u16(), error_text, Some(hint)).into());
+    }
+
+    let response_json: serde_json::Value = response.json().await?;
+    let usage = parse_usage(&response_json["usage"]);
+
+    if let Some(content) = response_json["choices"][0]["message"]["content"].as_str() {
+        Ok(Analysis { text: content.to_string(), usage })
+    } else {
+        Err("No content in LLM response".into())
+    }
+}
+
+pub async fn list_models() -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let llm_url = &config::settings().llm_url;
+    let response = reqwest::Client::new()
+        .get(format!("{}/models", llm_url.trim_end_matches('/')))
+        .header("Authorization", "Bearer lm-studio")
+        .send()
+        .await?
+        .error_for_status()?;
+
+    let response_json: serde_json::Value = response.json().await?;
+    Ok(response_json["data"].as_array().into_iter().flatten()
+        .filter_map(|model| model["id"].as_str().map(str::to_string))
+        .collect())
+}
+
+/// Parse an OpenAI-style `usage` object.
+fn parse_usage(usage: &serde_json::Value) -> Option<Usage> {
+    let prompt_tokens = usage["prompt_tokens"].as_u64()?;
+    let completion_tokens = usage["completion_tokens"].as_u64().unwrap_or(0);
+    let total_tokens = usage["total_tokens"].as_u64().unwrap_or(prompt_tokens + completion_tokens);
+    Some(priced_usage(prompt_tokens, completion_tokens, total_tokens))
+}
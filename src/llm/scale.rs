@@ -0,0 +1,207 @@
+use std::sync::LazyLock;
+
+use regex::{Captures, Regex};
+use serde::Serialize;
+
+use super::Provider;
+use crate::config;
+
+const NUMBER: &str = r"(\d+(?:\.\d+)?)";
+
+/// `[X1, Y1, X2, Y2]` boxes.
+static BOX: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(r"\[\s*{n}\s*,\s*{n}\s*,\s*{n}\s*,\s*{n}\s*\]", n = NUMBER)).expect("valid regex")
+});
+/// `(X, Y)` points.
+static POINT: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(r"\(\s*{n}\s*,\s*{n}\s*\)", n = NUMBER)).expect("valid regex")
+});
+/// `x: 12`, `"top": 40`, `width=300` and the like.
+static KEYED: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(&format!(r#"(?i)\b(x1|y1|x2|y2|x|y|left|top|right|bottom|width|height)("?\s*[:=]\s*){}"#, NUMBER)).expect("valid regex")
+});
+
+impl Provider {
+    /// Largest image the provider's vision models look at as is, as (long
+    /// edge, short edge). Services scale anything bigger down themselves,
+    /// so sending more pixels costs bandwidth and tokens without adding detail.
+    pub(super) fn image_limits(self) -> (u32, u32) {
+        match self {
+            // High detail fits the image into 2048x2048, then the short side into 768
+            Provider::OpenAi => (2048, 768),
+            // Larger images are resized to 1568 px on the long edge
+            Provider::Anthropic => (1568, 1568),
+            // LLaVA style models tile at 336 px; 1344 covers the largest grid
+            Provider::Ollama => (1344, 1344),
+        }
+    }
+}
+
+/// How an image sent to the LLM relates to the captured frame. Coordinates
+/// the model reports in the image are divided by `scale` and offset by the
+/// origin to give capture pixels.
+#[derive(Serialize, Clone, Copy, Debug)]
+pub struct ImageMapping {
+    /// Size of the image the model saw
+    pub width: u32,
+    pub height: u32,
+    /// Image pixels per capture pixel
+    pub scale: f64,
+    /// Capture pixel at the image's top-left corner, e.g. the --region origin
+    pub origin_x: u32,
+    pub origin_y: u32,
+}
+
+impl ImageMapping {
+    /// The mapping of a `width` x `height` frame that was cut out at
+    /// `origin` and downscaled by `factor` after capture.
+    pub fn frame(width: u32, height: u32, origin: (u32, u32), factor: u32) -> Self {
+        Self { width, height, scale: 1.0 / factor.max(1) as f64, origin_x: origin.0, origin_y: origin.1 }
+    }
+
+    /// The size this image should be sent to the configured provider at:
+    /// the mapping for the resized image, or None when it already fits.
+    pub fn fitted(&self) -> Option<Self> {
+        self.fitted_for(config::settings().llm_provider)
+    }
+
+    /// Like [`Self::fitted`], for the given provider.
+    pub(super) fn fitted_for(&self, provider: Provider) -> Option<Self> {
+        let (long, short) = provider.image_limits();
+        let (width, height) = (self.width.max(1) as f64, self.height.max(1) as f64);
+        let fit = (long as f64 / width.max(height)).min(short as f64 / width.min(height));
+        (fit < 1.0).then(|| Self {
+            width: ((width * fit).round() as u32).max(1),
+            height: ((height * fit).round() as u32).max(1),
+            scale: self.scale * fit,
+            ..*self
+        })
+    }
+
+    /// Whether image and capture coordinates are the same.
+    pub fn is_identity(&self) -> bool {
+        self.scale == 1.0 && self.origin_x == 0 && self.origin_y == 0
+    }
+
+    fn x(&self, x: f64) -> f64 {
+        self.origin_x as f64 + x / self.scale
+    }
+
+    fn y(&self, y: f64) -> f64 {
+        self.origin_y as f64 + y / self.scale
+    }
+}
+
+/// Rewrite the coordinates in an analysis from image pixels to capture
+/// pixels: `[X1, Y1, X2, Y2]` boxes, `(X, Y)` points and values labelled
+/// x, y, left, top, right, bottom, width or height. None when the text has
+/// none.
+pub fn rescale_coordinates(text: &str, mapping: &ImageMapping) -> Option<String> {
+    let value = |captures: &Captures, group: usize| captures[group].parse::<f64>().unwrap_or(0.0);
+    let pixels = |value: f64| format!("{}", value.round() as i64);
+
+    let boxes = BOX.replace_all(text, |captures: &Captures| format!("[{}, {}, {}, {}]",
+        pixels(mapping.x(value(captures, 1))), pixels(mapping.y(value(captures, 2))),
+        pixels(mapping.x(value(captures, 3))), pixels(mapping.y(value(captures, 4)))));
+    let points = POINT.replace_all(&boxes, |captures: &Captures| format!("({}, {})",
+        pixels(mapping.x(value(captures, 1))), pixels(mapping.y(value(captures, 2)))));
+    let rescaled = KEYED.replace_all(&points, |captures: &Captures| {
+        let number = value(captures, 3);
+        let number = match captures[1].to_lowercase().as_str() {
+            "x" | "x1" | "x2" | "left" | "right" => mapping.x(number),
+            "y" | "y1" | "y2" | "top" | "bottom" => mapping.y(number),
+            // Sizes scale but don't move
+            _ => number / mapping.scale,
+        };
+        format!("{}{}{}", &captures[1], &captures[2], pixels(number))
+    });
+    (rescaled != text).then(|| rescaled.into_owned())
+}
+
+/// The boxes and points in a line of an analysis, as `[x1, y1, x2, y2]`
+/// rectangles; a point is a rectangle of no size.
+pub(super) fn locations(line: &str) -> Vec<[f64; 4]> {
+    let value = |captures: &Captures, group: usize| captures[group].parse::<f64>().unwrap_or(0.0);
+    let boxes = BOX.captures_iter(line).map(|c| [value(&c, 1), value(&c, 2), value(&c, 3), value(&c, 4)]);
+    let points = POINT.captures_iter(line).map(|c| [value(&c, 1), value(&c, 2), value(&c, 1), value(&c, 2)]);
+    boxes.chain(points).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The mapping of a `--region` at (100, 50) downscaled by 2.
+    fn region_at_half_size() -> ImageMapping {
+        ImageMapping::frame(400, 300, (100, 50), 2)
+    }
+
+    #[test]
+    fn identity_mappings_leave_text_alone() {
+        let mapping = ImageMapping::frame(1920, 1080, (0, 0), 1);
+        assert!(mapping.is_identity());
+        assert_eq!(rescale_coordinates("The OK button is at [10, 20, 30, 40], (5, 6), x: 7", &mapping), None);
+    }
+
+    #[test]
+    fn text_without_coordinates_is_left_alone() {
+        assert_eq!(rescale_coordinates("No error dialog is showing.", &region_at_half_size()), None);
+    }
+
+    #[test]
+    fn boxes_and_points_are_scaled_and_offset() {
+        let mapping = region_at_half_size();
+        assert!(!mapping.is_identity());
+        assert_eq!(rescale_coordinates("OK at [10, 20, 30, 40]", &mapping).as_deref(), Some("OK at [120, 90, 160, 130]"));
+        assert_eq!(rescale_coordinates("Cursor at (10.4,20)", &mapping).as_deref(), Some("Cursor at (121, 90)"));
+    }
+
+    #[test]
+    fn labelled_positions_are_offset_and_sizes_only_scaled() {
+        let mapping = region_at_half_size();
+        let text = r#"{"left": 10, "top": 20, "width": 30, "height": 40} x=1 Y2: 2"#;
+        let expected = r#"{"left": 120, "top": 90, "width": 60, "height": 80} x=102 Y2: 54"#;
+        assert_eq!(rescale_coordinates(text, &mapping).as_deref(), Some(expected));
+    }
+
+    #[test]
+    fn each_coordinate_is_rewritten_once() {
+        let mapping = region_at_half_size();
+        let text = r#"{"box": [10, 20, 30, 40], "center": (20, 30), "x": 10}"#;
+        let expected = r#"{"box": [120, 90, 160, 130], "center": (140, 110), "x": 120}"#;
+        assert_eq!(rescale_coordinates(text, &mapping).as_deref(), Some(expected));
+    }
+
+    #[test]
+    fn images_within_the_limits_are_sent_as_is() {
+        for provider in [Provider::OpenAi, Provider::Anthropic, Provider::Ollama] {
+            assert!(ImageMapping::frame(800, 600, (0, 0), 1).fitted_for(provider).is_none());
+        }
+    }
+
+    #[test]
+    fn large_images_are_fitted_to_each_providers_limits() {
+        for (provider, width, height, fit) in [
+            (Provider::OpenAi, 1024, 768, 0.256),
+            (Provider::Anthropic, 1568, 1176, 0.392),
+            (Provider::Ollama, 1344, 1008, 0.336),
+        ] {
+            let fitted = ImageMapping::frame(4000, 3000, (100, 50), 2).fitted_for(provider).unwrap();
+            assert_eq!((fitted.width, fitted.height), (width, height), "{:?}", provider);
+            assert!((fitted.scale - 0.5 * fit).abs() < 1e-9, "{:?}: {}", provider, fitted.scale);
+            assert_eq!((fitted.origin_x, fitted.origin_y), (100, 50));
+        }
+    }
+
+    #[test]
+    fn portrait_images_are_limited_by_their_short_edge() {
+        let fitted = ImageMapping::frame(1000, 2000, (0, 0), 1).fitted_for(Provider::OpenAi).unwrap();
+        assert_eq!((fitted.width, fitted.height), (768, 1536));
+    }
+
+    #[test]
+    fn fitted_coordinates_map_back_to_the_capture() {
+        let fitted = ImageMapping::frame(4000, 3000, (0, 0), 1).fitted_for(Provider::Ollama).unwrap();
+        assert_eq!(rescale_coordinates("(1344, 1008)", &fitted).as_deref(), Some("(4000, 3000)"));
+    }
+}
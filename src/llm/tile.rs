@@ -0,0 +1,289 @@
+use std::time::Duration;
+
+use super::{analyze_image_cached, rescale_coordinates, scale, Analysis, ImageMapping, Provider, Usage, DEFAULT_PROMPT};
+use crate::convert::{self, Crop};
+use crate::{config, encode};
+
+/// Frames the model would see at less than this share of their resolution
+/// are analyzed in tiles instead.
+const MIN_SCALE: f64 = 0.5;
+/// Share of a tile's edge it shares with its neighbour, so anything cut by
+/// one seam is whole in the next tile.
+const OVERLAP: f64 = 0.15;
+/// Most tiles a frame is split into; larger frames get larger tiles.
+const MAX_TILES: usize = 24;
+/// Points are compared as squares this many screen pixels across.
+const POINT_SIZE: f64 = 24.0;
+/// Share of the smaller of two boxes that must lie in the other for them to
+/// be the same detection.
+const SAME_BOX: f64 = 0.5;
+
+/// One part of a frame that is analyzed on its own.
+pub struct Tile {
+    /// The part of the frame, in frame pixels
+    crop: Crop,
+    /// How the image sent for the tile maps to the screen
+    mapping: ImageMapping,
+}
+
+impl Tile {
+    pub fn mapping(&self) -> ImageMapping {
+        self.mapping
+    }
+}
+
+/// The tiles to analyze `frame` in, or None when the model can see it whole
+/// at a useful resolution. Very large screens and tall scrolling captures
+/// would otherwise be shrunk until their text is unreadable.
+pub fn plan_tiles(frame: &ImageMapping) -> Option<Vec<Tile>> {
+    plan_tiles_for(frame, config::settings().llm_provider)
+}
+
+fn plan_tiles_for(frame: &ImageMapping, provider: Provider) -> Option<Vec<Tile>> {
+    let fitted = frame.fitted_for(provider)?;
+    if fitted.scale / frame.scale >= MIN_SCALE {
+        return None;
+    }
+
+    // Tiles the model sees at half resolution or better, grown until there
+    // aren't too many of them
+    let (_, short) = provider.image_limits();
+    let mut edge = (short as f64 / MIN_SCALE) as u32;
+    let (columns, rows) = loop {
+        let (columns, rows) = (spans(frame.width, edge), spans(frame.height, edge));
+        if columns.len() * rows.len() <= MAX_TILES {
+            break (columns, rows);
+        }
+        edge += edge / 4;
+    };
+
+    let tiles = rows.iter()
+        .flat_map(|&(y, height)| columns.iter().map(move |&(x, width)| Crop { x, y, width, height }))
+        .map(|crop| {
+            let whole = ImageMapping {
+                width: crop.width,
+                height: crop.height,
+                scale: frame.scale,
+                origin_x: frame.origin_x + (crop.x as f64 / frame.scale).round() as u32,
+                origin_y: frame.origin_y + (crop.y as f64 / frame.scale).round() as u32,
+            };
+            Tile { crop, mapping: whole.fitted_for(provider).unwrap_or(whole) }
+        })
+        .collect();
+    Some(tiles)
+}
+
+/// Evenly spaced (start, length) spans of at most `edge` pixels covering
+/// `length`, each overlapping the next by at least OVERLAP.
+fn spans(length: u32, edge: u32) -> Vec<(u32, u32)> {
+    if length <= edge {
+        return vec![(0, length)];
+    }
+    let overlap = (edge as f64 * OVERLAP) as u32;
+    let count = (length - overlap).div_ceil(edge - overlap);
+    let step = (length - edge) as f64 / (count - 1) as f64;
+    (0..count).map(|i| ((i as f64 * step).round() as u32, edge)).collect()
+}
+
+/// Analyze each tile of a `width` x `height` RGB8 frame with the prompt and
+/// merge the answers, with coordinates in screen pixels.
+pub async fn analyze_tiles(width: u32, height: u32, rgb_data: &[u8], tiles: &[Tile], prompt: Option<&str>, cache_ttl: Option<Duration>) -> Result<Analysis, Box<dyn std::error::Error>> {
+    let prompt = prompt.unwrap_or(DEFAULT_PROMPT);
+    let mut usage: Option<Usage> = None;
+    let mut answers = Vec::with_capacity(tiles.len());
+    for (i, tile) in tiles.iter().enumerate() {
+        let name = format!("tile {} of {}", i + 1, tiles.len());
+        status!("Analyzing {}", name);
+        let rgb = convert::crop_scaled_rgb8(width, height, rgb_data, tile.crop, tile.mapping.width, tile.mapping.height)?;
+        let jpeg = encode::rgb8_to_jpeg(tile.mapping.width, tile.mapping.height, &rgb, 75)?;
+        let tile_prompt = format!("{}\n\nThis image is {} of a larger screenshot that was split into overlapping tiles. \
+            Only describe what is visible in this tile.", prompt, name);
+        let analysis = analyze_image_cached(&jpeg, Some(&tile_prompt), cache_ttl).await
+            .map_err(|e| format!("Analysis of {} failed: {}", name, e))?;
+        if let Some(tile_usage) = &analysis.usage {
+            usage.get_or_insert_default().add(tile_usage);
+        }
+        answers.push(rescale_coordinates(&analysis.text, &tile.mapping).unwrap_or(analysis.text));
+    }
+
+    let (text, duplicates) = merge(tiles, &answers);
+    if duplicates > 0 {
+        status!("Dropped {} detections already reported by an overlapping tile", duplicates);
+    }
+    Ok(Analysis { text, usage })
+}
+
+/// Join the tiles' answers under a heading each, leaving out lines that
+/// repeat a detection from an earlier tile: the same words at an overlapping
+/// box or point. Returns the text and the number of lines left out.
+fn merge(tiles: &[Tile], answers: &[String]) -> (String, usize) {
+    let mut seen: Vec<(String, Vec<[f64; 4]>)> = Vec::new();
+    let mut duplicates = 0;
+    let mut sections = Vec::with_capacity(answers.len());
+    for (i, (tile, answer)) in tiles.iter().zip(answers).enumerate() {
+        let mut kept = Vec::new();
+        let mut found = Vec::new();
+        for line in answer.lines() {
+            let locations = scale::locations(line);
+            if locations.is_empty() {
+                kept.push(line);
+                continue;
+            }
+            let words = words(line);
+            let repeated = seen.iter().any(|(seen_words, seen_locations)| {
+                *seen_words == words && locations.iter().any(|a| seen_locations.iter().any(|b| same_place(a, b)))
+            });
+            if repeated {
+                duplicates += 1;
+                continue;
+            }
+            found.push((words, locations));
+            kept.push(line);
+        }
+        // Only earlier tiles count, so repeated lines within one answer stay
+        seen.extend(found);
+
+        let mapping = tile.mapping;
+        sections.push(format!("[Tile {} of {}: {}x{} at {},{}]\n{}", i + 1, tiles.len(),
+            (mapping.width as f64 / mapping.scale).round(), (mapping.height as f64 / mapping.scale).round(),
+            mapping.origin_x, mapping.origin_y, kept.join("\n").trim()));
+    }
+    (sections.join("\n\n"), duplicates)
+}
+
+/// The words of a line without its numbers, for comparing detections.
+fn words(line: &str) -> String {
+    let letters: String = line.chars().map(|c| if c.is_alphabetic() { c } else { ' ' }).collect();
+    letters.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase()
+}
+
+/// Whether two `[x1, y1, x2, y2]` locations overlap enough to be the same
+/// thing seen from two tiles.
+fn same_place(a: &[f64; 4], b: &[f64; 4]) -> bool {
+    let rect = |r: &[f64; 4]| {
+        let (left, right) = (r[0].min(r[2]), r[0].max(r[2]));
+        let (top, bottom) = (r[1].min(r[3]), r[1].max(r[3]));
+        let grow = |low: f64, high: f64| match high - low < POINT_SIZE {
+            true => ((low + high - POINT_SIZE) / 2.0, (low + high + POINT_SIZE) / 2.0),
+            false => (low, high),
+        };
+        let ((left, right), (top, bottom)) = (grow(left, right), grow(top, bottom));
+        (left, top, right, bottom)
+    };
+    let (a, b) = (rect(a), rect(b));
+    let overlap = (a.2.min(b.2) - a.0.max(b.0)).max(0.0) * (a.3.min(b.3) - a.1.max(b.1)).max(0.0);
+    let area = |r: (f64, f64, f64, f64)| (r.2 - r.0) * (r.3 - r.1);
+    overlap >= SAME_BOX * area(a).min(area(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROVIDERS: [Provider; 3] = [Provider::OpenAi, Provider::Anthropic, Provider::Ollama];
+
+    /// The distinct (start, length) spans of the tiles along one axis.
+    fn axis(tiles: &[Tile], span: impl Fn(&Crop) -> (u32, u32)) -> Vec<(u32, u32)> {
+        let mut spans: Vec<(u32, u32)> = tiles.iter().map(|tile| span(&tile.crop)).collect();
+        spans.sort();
+        spans.dedup();
+        spans
+    }
+
+    /// Check that `spans` run from 0 to `length`, each overlapping the next.
+    fn assert_covers(spans: &[(u32, u32)], length: u32) {
+        assert_eq!(spans[0].0, 0, "{:?}", spans);
+        let (start, size) = spans[spans.len() - 1];
+        assert_eq!(start + size, length, "{:?}", spans);
+        for pair in spans.windows(2) {
+            let ((a, a_size), (b, _)) = (pair[0], pair[1]);
+            assert!(a + a_size >= b + (a_size as f64 * OVERLAP) as u32, "{:?}", spans);
+        }
+    }
+
+    fn tile(x: u32, y: u32, size: u32) -> Tile {
+        let crop = Crop { x, y, width: size, height: size };
+        Tile { crop, mapping: ImageMapping::frame(size, size, (x, y), 1) }
+    }
+
+    /// Every location in a text, line by line.
+    fn all_locations(text: &str) -> Vec<[f64; 4]> {
+        text.lines().flat_map(scale::locations).collect()
+    }
+
+    #[test]
+    fn frames_the_model_sees_well_are_not_tiled() {
+        for provider in PROVIDERS {
+            assert!(plan_tiles_for(&ImageMapping::frame(1920, 1080, (0, 0), 1), provider).is_none(), "{:?}", provider);
+        }
+    }
+
+    #[test]
+    fn spans_overlap_and_reach_both_ends() {
+        assert_eq!(spans(100, 200), [(0, 100)]);
+        assert_eq!(spans(1000, 400), [(0, 400), (300, 400), (600, 400)]);
+        for length in [1537, 2000, 4321, 7680, 20000] {
+            assert_covers(&spans(length, 1536), length);
+        }
+    }
+
+    #[test]
+    fn tiles_cover_large_frames_with_overlap() {
+        for provider in PROVIDERS {
+            for (width, height) in [(7680, 4320), (5120, 2880), (1280, 20000)] {
+                let tiles = plan_tiles_for(&ImageMapping::frame(width, height, (0, 0), 1), provider).unwrap();
+                assert!(tiles.len() > 1 && tiles.len() <= MAX_TILES, "{:?} {}x{}: {}", provider, width, height, tiles.len());
+                assert_covers(&axis(&tiles, |crop| (crop.x, crop.width)), width);
+                assert_covers(&axis(&tiles, |crop| (crop.y, crop.height)), height);
+            }
+        }
+    }
+
+    #[test]
+    fn tiles_map_to_the_screen_and_fit_the_provider() {
+        let frame = ImageMapping::frame(7680, 4320, (100, 50), 2);
+        for provider in PROVIDERS {
+            for tile in plan_tiles_for(&frame, provider).unwrap() {
+                assert_eq!(tile.mapping.origin_x, 100 + tile.crop.x * 2);
+                assert_eq!(tile.mapping.origin_y, 50 + tile.crop.y * 2);
+                assert!(tile.mapping.fitted_for(provider).is_none(), "{:?}", provider);
+                // Seen at half the frame's resolution or better
+                assert!(tile.mapping.scale / frame.scale >= MIN_SCALE, "{:?}: {}", provider, tile.mapping.scale);
+            }
+        }
+    }
+
+    #[test]
+    fn merging_drops_detections_repeated_by_an_overlapping_tile() {
+        let tiles = [tile(0, 0, 1000), tile(800, 0, 1000)];
+        let answers = [
+            "Error dialog at [850, 100, 950, 200]\nClock at (20, 10)".to_string(),
+            "Error dialog at [852, 103, 949, 198]\nOK button at (1500, 600)".to_string(),
+        ];
+        let (text, duplicates) = merge(&tiles, &answers);
+        assert_eq!(duplicates, 1);
+        assert_eq!(all_locations(&text), [[850.0, 100.0, 950.0, 200.0], [20.0, 10.0, 20.0, 10.0], [1500.0, 600.0, 1500.0, 600.0]]);
+        assert!(text.starts_with("[Tile 1 of 2: 1000x1000 at 0,0]\n"), "{}", text);
+    }
+
+    #[test]
+    fn merging_keeps_detections_that_differ() {
+        let tiles = [tile(0, 0, 1000), tile(800, 0, 1000)];
+        let answers = [
+            "Error dialog at [850, 100, 950, 200]\nIcon at (900, 500)\nIcon at (900, 500)".to_string(),
+            // Other words at the same place, the same words elsewhere, and lines without a location
+            "Warning dialog at [850, 100, 950, 200]\nError dialog at [1500, 700, 1600, 800]\nNo other errors".to_string(),
+        ];
+        let (text, duplicates) = merge(&tiles, &answers);
+        assert_eq!(duplicates, 0);
+        assert_eq!(all_locations(&text).len(), 5);
+        assert!(text.contains("No other errors"), "{}", text);
+    }
+
+    #[test]
+    fn nearby_points_are_the_same_place() {
+        assert!(same_place(&[100.0, 100.0, 100.0, 100.0], &[105.0, 104.0, 105.0, 104.0]));
+        assert!(!same_place(&[100.0, 100.0, 100.0, 100.0], &[130.0, 100.0, 130.0, 100.0]));
+        assert!(!same_place(&[0.0, 0.0, 100.0, 100.0], &[60.0, 0.0, 160.0, 100.0]));
+    }
+}
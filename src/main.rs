@@ -1,24 +1,29 @@
-use clap::{Parser, Subcommand};
-use std::process;
-use base64::{Engine as _, engine::general_purpose};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use serde_json::json;
 
 // Import from the local scap library
-use scap::{capturer::{Capturer, Options}, frame::VideoFrame, Target};
+use scap::{capturer::Options, frame::{Frame, VideoFrame}, Target};
+use tokio_util::sync::CancellationToken;
 
-// Import OCR libraries
-use ocrs::{ImageSource, OcrEngine, OcrEngineParams};
-use rten::Model;
-
-mod platforms;
+use captest::{analyze_frame, result, rgb8_to_jpeg_bytes, save_image_bytes, status};
+use captest::{DiffArgs, MonitorArgs, RecordArgs, RemoteCaptureArgs, SessionArgs, TrackArgs, WatchArgs};
+use captest::{args, capture, capture_sync, cas, change, clipboard, config, convert, coords, diff, diff_dir, doctor, elements, encode, error, extract, fleet, icon, ingest, links, llm, memory, monitor, mqtt, ocr, ocr_batch, output, platforms, preset, privacy, progress, quality, raw_dump, record, region_hash, remote, scroll_capture, server, session, session_pool, sink, template, text_diff, timestamp, timings, track, transcribe, upload, validate, watch, webhook, window_match};
+#[cfg(feature = "editor")]
+use captest::editor;
+use captest::capture::AsyncCaptureSession;
+use captest::error::{CaptestError, ErrorKind};
+use captest::output::{CaptureReport, OcrReport, OutputFormat, ScreenInfo, SinkReport, TargetInfo, WindowSummary};
 
 #[cfg(target_os = "macos")]
-use crate::platforms::mac::list_windows;
+use captest::platforms::mac::list_windows;
 
 #[cfg(target_os = "windows")]
-use crate::platforms::windows::list_windows;
+use captest::platforms::windows::list_windows;
 
 #[cfg(target_os = "linux")]
-use crate::platforms::linux::list_windows;
+use captest::platforms::linux::list_windows;
 
 #[derive(Parser)]
 #[command(name = "captest")]
@@ -26,6 +31,87 @@ use crate::platforms::linux::list_windows;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Output format; `json` prints a single result document on stdout and moves human text to stderr
+    #[arg(long, value_enum, global = true, env = "CAPTEST_FORMAT")]
+    output_format: Option<OutputFormat>,
+    /// Shorthand for `--output-format json`
+    #[arg(long, global = true, conflicts_with = "output_format")]
+    json: bool,
+    /// ID tying this run's files, JSON output, webhooks and LLM requests together [default: generated]
+    #[arg(long, global = true, env = "CAPTEST_RUN_ID", value_name = "ID")]
+    run_id: Option<String>,
+    /// Config file (defaults to ./captest.toml, then ~/.config/captest/config.toml)
+    #[arg(long, global = true, env = "CAPTEST_CONFIG")]
+    config: Option<std::path::PathBuf>,
+    /// Base URL of the LLM API [default: depends on --llm-provider, http://localhost:1234/v1 for openai]
+    #[arg(long, global = true, env = "CAPTEST_LLM_URL")]
+    llm_url: Option<String>,
+    /// API used to talk to the LLM endpoint
+    #[arg(long, value_enum, global = true, env = "CAPTEST_LLM_PROVIDER")]
+    llm_provider: Option<llm::Provider>,
+    /// Vision model to request (defaults to the provider's usual vision model, e.g. llava for Ollama)
+    #[arg(long, global = true, env = "CAPTEST_LLM_MODEL")]
+    llm_model: Option<String>,
+    /// Directory containing text-detection.rten and text-recognition.rten
+    #[arg(long, global = true, env = "CAPTEST_OCR_MODEL_DIR")]
+    ocr_model_dir: Option<std::path::PathBuf>,
+    /// Directory that relative output filenames are written to
+    #[arg(long, global = true, env = "CAPTEST_OUTPUT_DIR")]
+    output_dir: Option<std::path::PathBuf>,
+    /// Price in USD per million prompt tokens, for cost estimates of paid endpoints
+    #[arg(long, global = true, env = "CAPTEST_INPUT_TOKEN_PRICE", value_name = "USD")]
+    input_token_price: Option<f64>,
+    /// Price in USD per million completion tokens
+    #[arg(long, global = true, env = "CAPTEST_OUTPUT_TOKEN_PRICE", value_name = "USD")]
+    output_token_price: Option<f64>,
+    /// Recognize another script using text-recognition-<LANG>.rten, downloaded on demand
+    #[arg(long, global = true, env = "CAPTEST_OCR_LANG", value_name = "LANG", value_parser = ocr::parse_lang)]
+    ocr_lang: Option<String>,
+    /// Base URL OCR models are downloaded from when missing locally
+    #[arg(long, global = true, env = "CAPTEST_OCR_MODEL_URL", value_name = "URL")]
+    ocr_model_url: Option<String>,
+    /// Characters the recognition model outputs, overriding its default alphabet
+    #[arg(long, global = true, env = "CAPTEST_OCR_ALPHABET")]
+    ocr_alphabet: Option<String>,
+    /// Only allow these characters in recognized text
+    #[arg(long, global = true, env = "CAPTEST_OCR_ALLOWED_CHARS", value_name = "CHARS")]
+    ocr_allowed_chars: Option<String>,
+    /// Use beam search decoding with this width instead of greedy decoding (slower, more accurate)
+    #[arg(long, global = true, env = "CAPTEST_OCR_BEAM_WIDTH", value_name = "N")]
+    ocr_beam_width: Option<u32>,
+    /// Correct OCR transcripts against this word list (one word per line, optionally followed by a count)
+    #[arg(long, global = true, env = "CAPTEST_OCR_DICTIONARY", value_name = "FILE")]
+    ocr_dictionary: Option<std::path::PathBuf>,
+    /// JPEG encoder; auto uses turbojpeg when this build includes it
+    #[arg(long, value_enum, global = true, env = "CAPTEST_ENCODER")]
+    encoder: Option<encode::Encoder>,
+    /// Convert, crop and scale frames on the GPU in watch and serve (needs the `gpu` feature)
+    #[arg(long, global = true, env = "CAPTEST_GPU")]
+    gpu: bool,
+    /// Memory a capture may use for conversion, averaging, OCR and encoding
+    #[arg(long, global = true, env = "CAPTEST_MAX_MEMORY", value_name = "MB")]
+    max_memory: Option<u64>,
+    /// What to do when a capture would exceed --max-memory
+    #[arg(long, value_enum, global = true, env = "CAPTEST_ON_MEMORY_LIMIT")]
+    on_memory_limit: Option<memory::OnMemoryLimit>,
+    /// Frames buffered between the capturer and captest in capture, record and watch; more rides out stalls, fewer keeps latency down
+    #[arg(long, global = true, env = "CAPTEST_BUFFER_DEPTH", value_name = "FRAMES", value_parser = clap::value_parser!(u32).range(1..=256))]
+    buffer_depth: Option<u32>,
+    /// Fail screen captures that show a sensitive window (see privacy_blocklist) which can't be excluded
+    #[arg(long, global = true, env = "CAPTEST_PRIVACY_STRICT")]
+    privacy_strict: bool,
+    /// Let the capture backend outline what is being captured (the yellow Windows Graphics Capture border)
+    #[arg(long, global = true, env = "CAPTEST_SHOW_HIGHLIGHT")]
+    show_highlight: bool,
+    /// Name saved captures by the SHA-256 of their contents and record the requested names in cas-manifest.json
+    #[arg(long, global = true, env = "CAPTEST_CAS")]
+    cas: bool,
+    /// Suppress progress output; only results and errors are printed
+    #[arg(short, long, global = true)]
+    quiet: bool,
+    /// Print where the time went (enumerating targets, capturer setup, first frame, conversion, encoding, saving, OCR, LLM) when the command finishes, and add it to JSON output
+    #[arg(long, global = true, env = "CAPTEST_TIMINGS")]
+    timings: bool,
 }
 
 #[derive(Subcommand)]
@@ -34,253 +120,1249 @@ enum Commands {
     List,
     /// List available windows with detailed info
     ListWindows,
+    /// Check that screenshots can be taken here (display, permission, a test capture), e.g. in CI
+    Doctor,
+    /// Check that every capture in a --cas manifest is present and unaltered
+    CasVerify {
+        /// Directory holding cas-manifest.json (defaults to --output-dir, then the current directory)
+        dir: Option<std::path::PathBuf>,
+    },
     /// Capture a screen by number
     Capture {
-        /// Screen number to capture
-        screen: usize,
-        /// Output filename (optional, defaults to screenshot_<timestamp>.jpg)
+        /// Screen number to capture (defaults to the --region's display, then 0)
+        screen: Option<usize>,
+        #[command(flatten)]
+        args: CaptureArgs,
+    },
+    /// Capture a window by number
+    CaptureWindow {
+        /// Window number to capture; omit it to pick the window with --title, --pid or --process
+        #[arg(required_unless_present_any = ["title", "pid", "process"], conflicts_with_all = ["title", "pid", "process"])]
+        window: Option<usize>,
+        #[command(flatten)]
+        select: WindowSelectArgs,
+        #[command(flatten)]
+        args: CaptureArgs,
+    },
+    /// Capture several windows at the same instant, labelled with a shared sequence ID
+    CaptureSync {
+        /// Window numbers to capture, e.g. 1,2,5
+        #[arg(long, required = true, value_delimiter = ',', value_name = "N,N,...")]
+        windows: Vec<usize>,
+        /// Prefix of the saved files, named PREFIX_SEQUENCE_windowN.jpg
+        #[arg(short, long, default_value = "sync")]
+        output: String,
+        /// Sequence ID shared by the outputs (default: the capture instant in Unix milliseconds)
+        #[arg(long)]
+        sequence: Option<String>,
+        /// Write the images and a report.json into this .zip or .tar archive instead of separate files
+        #[arg(long, value_name = "FILE")]
+        bundle: Option<String>,
+    },
+    /// Scroll a window and stitch the frames into one tall image of the whole document
+    CaptureScroll {
+        /// Window number to capture
+        #[arg(long)]
+        window: usize,
+        /// Output image, PNG or JPEG by extension (default: scroll_<timestamp>.png)
         #[arg(short, long)]
         output: Option<String>,
-        /// Analyze the captured image with LLM (requires LMStudio running locally)
+        /// Mouse wheel notches per scroll step
+        #[arg(long, default_value_t = 5)]
+        lines: u32,
+        /// Most frames to capture
+        #[arg(long, default_value_t = 50)]
+        max_frames: usize,
+        /// Seconds to let the content settle after each scroll
+        #[arg(long, value_parser = args::parse_seconds, default_value_t = 0.4, value_name = "SECONDS")]
+        settle: f64,
+    },
+    /// Save the icon of the app owning a window, for pickers and reports
+    Icon {
+        /// Window number; omit it to pick the window with --title, --pid or --process
+        #[arg(required_unless_present_any = ["title", "pid", "process"], conflicts_with_all = ["title", "pid", "process"])]
+        window: Option<usize>,
+        #[command(flatten)]
+        select: WindowSelectArgs,
+        /// Width and height of the saved icon
+        #[arg(long, default_value_t = 256, value_parser = clap::value_parser!(u32).range(16..=1024), value_name = "PIXELS")]
+        size: u32,
+        /// Output image, PNG unless the extension is .webp or .bmp
+        #[arg(short, long, visible_alias = "out", default_value = "icon.png")]
+        output: String,
+    },
+    /// Run an HTTP server that keeps warm capture sessions for recently used targets
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        bind: std::net::SocketAddr,
+        /// Seconds an unused capture session is kept running
+        #[arg(long, default_value_t = 60)]
+        idle_timeout: u64,
+        /// Frames per second for warm sessions
+        #[arg(long, default_value_t = 10)]
+        fps: u32,
+    },
+    /// List the models offered by the LLM endpoint
+    ListModels,
+    /// OCR an image file, or image bytes on stdin with `-`
+    Ocr {
+        /// Image file to read, or `-` for stdin (e.g. `grim - | captest ocr -`)
+        input: std::path::PathBuf,
+        /// Print only the values these regexes capture from the text, as CSV (repeatable)
+        #[arg(long, value_name = "NAME=REGEX|REGEX")]
+        extract: Vec<extract::Pattern>,
+        /// Print the text plain, or as JSON or hOCR with line and word boxes
+        #[arg(long, value_enum, default_value_t = ocr::OcrFormat::Text, conflicts_with = "extract")]
+        ocr_format: ocr::OcrFormat,
+        /// Don't read or write the on-disk OCR result cache
         #[arg(long)]
-        analyze: bool,
+        no_cache: bool,
+    },
+    /// Analyze an image file, or image bytes on stdin with `-`, with the LLM
+    Analyze {
+        /// Image file to read, or `-` for stdin
+        input: std::path::PathBuf,
         /// Custom prompt for LLM analysis
         #[arg(long)]
         prompt: Option<String>,
-        /// Extract text from the captured image using OCR
+        /// Don't read or write the on-disk LLM response cache
+        #[arg(long)]
+        no_cache: bool,
+        /// Seconds a cached LLM response stays valid
+        #[arg(long, default_value_t = 86400, value_name = "SECONDS")]
+        cache_ttl: u64,
+        /// Send the image at full size instead of scaling it to the provider's preferred resolution
+        #[arg(long)]
+        llm_full_resolution: bool,
+        /// Send a large image whole instead of splitting it into tiles
+        #[arg(long)]
+        no_tiles: bool,
+    },
+    /// Analyze several image files together with one prompt in a single LLM request
+    AnalyzeBatch {
+        /// Image files to analyze, in order
+        #[arg(required = true)]
+        files: Vec<std::path::PathBuf>,
+        /// Prompt applied to all images, e.g. "summarize the workflow across these screens"
+        #[arg(long)]
+        prompt: Option<String>,
+        /// Send each image in its own message, for providers that only accept one image per message
+        #[arg(long)]
+        split_images: bool,
+    },
+    /// OCR many image files in parallel with one loaded OCR engine
+    OcrBatch {
+        /// Image files, directories or glob patterns such as "shots/*.png"
+        #[arg(required = true)]
+        inputs: Vec<String>,
+        /// Files recognized at once; defaults to the number of CPUs
+        #[arg(long)]
+        jobs: Option<usize>,
+        /// Don't read or write the on-disk OCR result cache
+        #[arg(long)]
+        no_cache: bool,
+        /// Write a .txt transcript per file and a report.json into this .zip or .tar archive
+        #[arg(long, value_name = "FILE")]
+        bundle: Option<String>,
+    },
+    /// OCR the screenshots in a directory into sidecar files and a searchable index
+    Ingest {
+        /// Directory of screenshots, e.g. ~/Screenshots
+        dir: std::path::PathBuf,
+        /// Keep watching the directory and ingest new images until interrupted
+        #[arg(long)]
+        watch: bool,
+        /// JSON lines index to append to (default: captest-index.jsonl in the directory)
+        #[arg(long)]
+        index: Option<std::path::PathBuf>,
+        /// Don't read or write the on-disk OCR result cache
+        #[arg(long)]
+        no_cache: bool,
+    },
+    /// Watch a screen or window and report each visible change until interrupted
+    Watch {
+        #[command(flatten)]
+        target: TargetArgs,
+        #[command(flatten)]
+        args: WatchArgs,
+    },
+    /// OCR a screen, window or image and list the URLs and email addresses on it
+    ExtractLinks {
+        #[command(flatten)]
+        target: TargetArgs,
+        /// Read this image file (or `-` for stdin) instead of capturing
+        #[arg(long, conflicts_with_all = ["screen", "window"])]
+        image: Option<std::path::PathBuf>,
+        /// Don't read or write the on-disk OCR result cache
+        #[arg(long)]
+        no_cache: bool,
+    },
+    /// Find buttons, text fields, checkboxes and icons on a screen, window or image without an LLM
+    DetectElements {
+        #[command(flatten)]
+        target: TargetArgs,
+        /// Read this image file (or `-` for stdin) instead of capturing
+        #[arg(long, conflicts_with_all = ["screen", "window"])]
+        image: Option<std::path::PathBuf>,
+        /// Also OCR the frame and report each element's text
         #[arg(long)]
         ocr: bool,
+        /// Don't read or write the on-disk OCR result cache
+        #[arg(long)]
+        no_cache: bool,
     },
-    /// Capture a window by number
-    CaptureWindow {
-        /// Window number to capture
-        window: usize,
-        /// Output filename (optional, defaults to screenshot_<timestamp>.jpg)
+    /// Find where an image, e.g. a button or icon, appears on a screen, window or image
+    FindImage {
+        /// Image to look for
+        needle: std::path::PathBuf,
+        #[command(flatten)]
+        target: TargetArgs,
+        /// Search this image file (or `-` for stdin) instead of capturing
+        #[arg(long, conflicts_with_all = ["screen", "window"])]
+        image: Option<std::path::PathBuf>,
+        #[command(flatten)]
+        matching: MatchArgs,
+    },
+    /// Capture a screen or window repeatedly until an image appears on it
+    WaitForImage {
+        /// Image to wait for
+        needle: std::path::PathBuf,
+        #[command(flatten)]
+        target: TargetArgs,
+        /// Give up after this many seconds
+        #[arg(long, value_parser = args::parse_seconds, default_value_t = 30.0, value_name = "SECONDS")]
+        timeout: f64,
+        /// Seconds between captures
+        #[arg(long, value_parser = args::parse_seconds, default_value_t = 0.5, value_name = "SECONDS")]
+        interval: f64,
+        #[command(flatten)]
+        matching: MatchArgs,
+    },
+    /// Transcribe the visible document on a screen, window or image into Markdown
+    Transcribe {
+        #[command(flatten)]
+        target: TargetArgs,
+        /// Read this image file (or `-` for stdin) instead of capturing
+        #[arg(long, conflicts_with_all = ["screen", "window"])]
+        image: Option<std::path::PathBuf>,
+        /// Write the Markdown to this file instead of printing it
         #[arg(short, long)]
         output: Option<String>,
-        /// Analyze the captured image with LLM (requires LMStudio running locally)
+        /// Have the LLM fix recognition errors and formatting using the image
+        #[arg(long)]
+        llm_cleanup: bool,
+        /// Don't read or write the on-disk OCR result cache
+        #[arg(long)]
+        no_cache: bool,
+    },
+    /// Record a screen or window to Matroska (stays playable if captest is killed), MP4 or an animated GIF
+    Record {
+        #[command(flatten)]
+        target: TargetArgs,
+        #[command(flatten)]
+        args: RecordArgs,
+    },
+    /// Finalize a recording left incomplete by a crash or power loss
+    Recover {
+        /// Recording to repair in place
+        file: std::path::PathBuf,
+    },
+    /// Compare two images pixel by pixel
+    Diff {
+        /// Reference image
+        baseline: std::path::PathBuf,
+        /// Image compared against the baseline
+        current: std::path::PathBuf,
+        /// Compare the OCR text of the images instead of their pixels
         #[arg(long)]
-        analyze: bool,
+        text: bool,
+        #[command(flatten)]
+        args: DiffArgs,
+    },
+    /// Compare the images in two directories, pairing files by name
+    DiffDir {
+        /// Directory of reference images
+        baseline: std::path::PathBuf,
+        /// Directory of images compared against the baseline
+        current: std::path::PathBuf,
+        /// Directory for the HTML report and the diff images of mismatches
+        #[arg(long, default_value = "diff-report", value_name = "DIR")]
+        report_dir: String,
+        #[command(flatten)]
+        args: DiffArgs,
+    },
+    /// Record, inspect and export replayable session bundles
+    Session {
+        #[command(subcommand)]
+        command: SessionCommand,
+    },
+    /// Run captures on several machines running `captest serve` at once
+    Fleet {
+        #[command(subcommand)]
+        command: FleetCommand,
+    },
+    /// Log the active window's app and title to a JSONL or CSV timeline
+    Track {
+        /// Screen or window to thumbnail with --with-thumbnails
+        #[command(flatten)]
+        target: TargetArgs,
+        #[command(flatten)]
+        args: TrackArgs,
+    },
+    /// Periodically OCR a region and append its value (e.g. a dashboard counter) to a CSV or InfluxDB time series
+    Monitor {
+        #[command(flatten)]
+        target: TargetArgs,
+        #[command(flatten)]
+        args: MonitorArgs,
+    },
+    /// Capture a region, hash its pixels and fail unless the hash is an expected one, e.g. for kiosk watchdogs
+    AssertRegionHash {
+        #[command(flatten)]
+        target: TargetArgs,
+        /// Part of the frame to hash, or @NAME for a region from the config file
+        #[arg(long, value_name = "X,Y,WIDTH,HEIGHT|@NAME")]
+        region: change::RegionArg,
+        /// Hash (or its first 8+ hex digits) the region must have; repeat to accept any of several (omit to print the hash)
+        #[arg(long, value_name = "HASH")]
+        expect: Vec<String>,
+    },
+    /// Convert points between global, display, window and capture pixel coordinates
+    Coords {
+        #[command(flatten)]
+        target: TargetArgs,
+        /// Points to convert
+        #[arg(required = true, value_name = "X,Y")]
+        points: Vec<coords::Point>,
+        /// Space the points are given in
+        #[arg(long, value_enum, default_value_t = coords::Space::Global)]
+        from: coords::Space,
+        /// Only convert to this space instead of all of them
+        #[arg(long, value_enum)]
+        to: Option<coords::Space>,
+        /// Capture pixels of record, watch and serve frames rather than capture and capture-window output
+        #[arg(long)]
+        streamed: bool,
+    },
+    /// Capture on another machine running `captest serve`, through an SSH tunnel
+    Remote {
+        /// SSH destination of the machine, e.g. user@host or a Host from ~/.ssh/config
+        destination: String,
+        /// Port `captest serve` listens on there, on its loopback interface
+        #[arg(long, default_value_t = 8080)]
+        port: u16,
+        #[command(subcommand)]
+        command: RemoteCommand,
+    },
+    /// Compare a live screen or window against a baseline image
+    Verify {
+        #[command(flatten)]
+        target: TargetArgs,
+        /// Reference image
+        baseline: std::path::PathBuf,
+        /// Compare the OCR text of the capture and the baseline instead of their pixels
+        #[arg(long)]
+        text: bool,
+        #[command(flatten)]
+        args: DiffArgs,
+    },
+}
+
+#[derive(Subcommand)]
+enum RemoteCommand {
+    /// Capture a screen of the remote machine
+    Capture {
+        /// Screen number to capture
+        #[arg(default_value_t = 0)]
+        screen: usize,
+        #[command(flatten)]
+        args: RemoteCaptureArgs,
+    },
+    /// Capture a window of the remote machine
+    CaptureWindow {
+        /// Window number to capture
+        window: usize,
+        #[command(flatten)]
+        args: RemoteCaptureArgs,
+    },
+    /// Capture a screen or window of the remote machine and analyze it with that machine's LLM
+    Analyze {
+        #[command(flatten)]
+        target: TargetArgs,
         /// Custom prompt for LLM analysis
         #[arg(long)]
         prompt: Option<String>,
-        /// Extract text from the captured image using OCR
-        #[arg(long)]
-        ocr: bool,
     },
 }
 
+#[derive(Subcommand)]
+enum FleetCommand {
+    /// Capture on every host concurrently, into a directory per host with a combined JSON summary
+    Capture {
+        /// TOML file with a [[host]] table (name, destination, optional port, screen, window) per machine
+        #[arg(long, value_name = "FILE")]
+        hosts: std::path::PathBuf,
+        /// Target on hosts that don't set their own
+        #[command(flatten)]
+        target: TargetArgs,
+        /// Directory for the per-host directories and the summary
+        #[arg(short, long, default_value = "fleet", value_name = "DIR")]
+        output_dir: String,
+        /// Crop on each machine before sending: X,Y,WIDTH,HEIGHT or @NAME from its config file
+        #[arg(long, value_name = "X,Y,W,H|@NAME")]
+        crop: Option<String>,
+        /// Downscale on each machine to at most this many pixels wide
+        #[arg(long, value_name = "PIXELS")]
+        max_width: Option<u32>,
+        /// Write the images and summary into this .zip or .tar archive instead of the output directory
+        #[arg(long, value_name = "FILE")]
+        bundle: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum SessionCommand {
+    /// Record changed frames, OCR snapshots, the active window and capture events into a bundle
+    Record {
+        #[command(flatten)]
+        target: TargetArgs,
+        #[command(flatten)]
+        args: SessionArgs,
+    },
+    /// Summarize a bundle: duration, event counts and time per active window
+    Inspect {
+        /// Bundle directory or .zip file
+        bundle: std::path::PathBuf,
+    },
+    /// Render a bundle's frames into a video with their recorded timing
+    Export {
+        /// Bundle directory or .zip file
+        bundle: std::path::PathBuf,
+        /// Matroska video to write
+        #[arg(long, value_name = "FILE")]
+        video: String,
+    },
+}
+
+#[derive(Args)]
+struct TargetArgs {
+    /// Screen number (defaults to 0)
+    #[arg(long, conflicts_with = "window")]
+    screen: Option<usize>,
+    /// Window number
+    #[arg(long)]
+    window: Option<usize>,
+}
+
+impl TargetArgs {
+    fn key(&self) -> session_pool::TargetKey {
+        match self.window {
+            Some(window) => session_pool::TargetKey::Window(window),
+            None => session_pool::TargetKey::Screen(self.screen.unwrap_or(0)),
+        }
+    }
+}
+
+#[derive(Args)]
+struct WindowSelectArgs {
+    /// Capture the window whose title contains this text (case-insensitive)
+    #[arg(long)]
+    title: Option<String>,
+    /// Capture the window owned by this process ID
+    #[arg(long)]
+    pid: Option<u32>,
+    /// Capture the window of this app or executable, e.g. Slack or chrome.exe (case-insensitive)
+    #[arg(long, value_name = "NAME")]
+    process: Option<String>,
+    /// When several windows match, capture the first listed instead of failing
+    #[arg(long)]
+    first: bool,
+}
+
+impl WindowSelectArgs {
+    /// Index of the window to capture: `window` when given, otherwise the
+    /// one matching --title, --pid and --process.
+    fn resolve(&self, window: Option<usize>) -> Result<usize, Box<dyn std::error::Error>> {
+        if let Some(window) = window {
+            return Ok(window);
+        }
+        let query = window_match::WindowQuery { title: self.title.clone(), pid: self.pid, process: self.process.clone() };
+        let index = window_match::find_window(&query, &list_window_summaries(), self.first)?;
+        status!("Matched window {}", index);
+        Ok(index)
+    }
+}
+
+#[derive(Args)]
+struct MatchArgs {
+    /// Lowest normalized cross-correlation (-1 to 1) that counts as a match
+    #[arg(long, default_value_t = 0.9, value_parser = template::parse_threshold)]
+    threshold: f64,
+    /// Sizes of the needle to try, relative to the file, for screens at other display scalings
+    #[arg(long, value_delimiter = ',', default_values_t = template::DEFAULT_SCALES.to_vec(), value_parser = template::parse_scale)]
+    scales: Vec<f64>,
+}
+
+#[derive(Args)]
+struct CaptureArgs {
+    /// Output filename (optional, defaults to screenshot_<timestamp>.jpg)
+    #[arg(short, long)]
+    output: Option<String>,
+    /// Settings tuned for text extraction, archiving or LLM analysis; explicit options still apply on top
+    #[arg(long, value_enum)]
+    preset: Option<preset::Preset>,
+    /// Image format to save [default: from the --output extension, else jpeg]
+    #[arg(long, value_enum)]
+    format: Option<encode::ImageFormat>,
+    /// JPEG quality from 1 to 100 [default: 75]; the other formats are lossless
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=100))]
+    quality: Option<u8>,
+    /// Analyze the captured image with LLM (requires LMStudio running locally)
+    #[arg(long)]
+    analyze: bool,
+    /// Custom prompt for LLM analysis
+    #[arg(long)]
+    prompt: Option<String>,
+    /// Send the frame to the LLM at full size instead of scaling it to the provider's preferred resolution
+    #[arg(long, requires = "analyze")]
+    llm_full_resolution: bool,
+    /// Send a large frame to the LLM whole instead of splitting it into tiles
+    #[arg(long, requires = "analyze")]
+    no_tiles: bool,
+    /// Extract text from the captured image using OCR
+    #[arg(long)]
+    ocr: bool,
+    /// Print the OCR text plain, or as JSON or hOCR with line and word boxes (implies --ocr)
+    #[arg(long, value_enum, conflicts_with = "extract")]
+    ocr_format: Option<ocr::OcrFormat>,
+    /// Only keep this part of the frame, or @NAME for a region from the config file
+    #[arg(long, value_name = "X,Y,WIDTH,HEIGHT|@NAME")]
+    region: Option<change::RegionArg>,
+    /// Only run OCR on this part of the frame (in the same coordinates as --region)
+    #[arg(long, value_name = "X,Y,WIDTH,HEIGHT|@NAME")]
+    ocr_region: Option<change::RegionArg>,
+    /// Print only the values these regexes capture from the OCR text, as CSV (repeatable; implies --ocr)
+    #[arg(long, value_name = "NAME=REGEX|REGEX")]
+    extract: Vec<extract::Pattern>,
+    /// Seconds to wait before grabbing the frame. The capture stream is started
+    /// immediately so open menus and tooltips are not dismissed by capturer setup.
+    #[arg(long, value_parser = args::parse_seconds, value_name = "SECONDS")]
+    delay: Option<f64>,
+    /// Average this many consecutive frames to remove temporal dithering and video noise (helps OCR)
+    #[arg(long, default_value_t = 1, value_name = "N")]
+    average: usize,
+    /// Write the unconverted frame to this file plus a <file>.json pixel format descriptor
+    #[arg(long, value_name = "FILE")]
+    dump_raw: Option<String>,
+    /// Pixel format requested from the capture backend
+    #[arg(long, value_enum, default_value_t = FrameTypeArg::Bgra)]
+    frame_type: FrameTypeArg,
+    /// Frames per second requested from the capture backend (defaults to 1, or 30 for --delay/--average/--skip-frames)
+    #[arg(long)]
+    fps: Option<u32>,
+    /// Frames to discard before the one that is kept, while the stream stabilizes (default depends on the platform)
+    #[arg(long, value_name = "N")]
+    skip_frames: Option<usize>,
+    /// Retry a failed capturer start or frame grab up to N times; permission errors are never retried
+    #[arg(long, default_value_t = 0, value_name = "N")]
+    retries: u32,
+    /// Milliseconds to wait between retries
+    #[arg(long, default_value_t = 500, value_name = "MS")]
+    retry_delay: u64,
+    /// Don't read or write the on-disk LLM response and OCR result caches
+    #[arg(long)]
+    no_cache: bool,
+    /// Seconds a cached LLM response stays valid
+    #[arg(long, default_value_t = 86400, value_name = "SECONDS")]
+    cache_ttl: u64,
+    /// Print extra diagnostics such as measured frame latency
+    #[arg(short, long)]
+    verbose: bool,
+    /// Upload the capture to the share host from the config file (0x0.st by default) and print its URL
+    #[arg(long)]
+    share: bool,
+    /// Ask the share host to delete the upload after this many hours
+    #[arg(long, value_name = "HOURS", requires = "share")]
+    share_expires: Option<u32>,
+    /// Copy the shared URL to the clipboard
+    #[arg(long, requires = "share")]
+    copy_url: bool,
+    /// Put the OCR text and LLM analysis on the clipboard, ready to paste
+    #[arg(long)]
+    copy_text: bool,
+    /// Also copy the capture to sftp://[USER@]HOST[:PORT]/PATH, scp://... or a dav(s):// WebDAV URL (repeatable)
+    #[arg(long, value_name = "DESTINATION")]
+    upload: Vec<upload::Destination>,
+    /// Send the capture to each of these: file:PATH, clipboard, stdout, http(s)://URL, s3://BUCKET/KEY,
+    /// sftp://, scp://, dav(s):// or mqtt:TOPIC (comma separated or repeated)
+    #[arg(long, value_name = "SINK,...", value_delimiter = ',', value_parser = sink::parse)]
+    to: Vec<Arc<dyn sink::Sink>>,
+    /// Mark up the capture with arrows, boxes, text and blur in an editor window before it is saved
+    #[arg(long)]
+    edit: bool,
+    /// Save frames that came back entirely black (usually protected content) instead of failing
+    #[arg(long)]
+    allow_black_frames: bool,
+    /// Keep captures that are a single color or have no detail instead of failing
+    #[arg(long)]
+    no_validate: bool,
+    /// Measure and print sharpness (Laplacian variance), entropy and estimated text density of the capture
+    #[arg(long)]
+    report_quality: bool,
+    /// Burn the time the frame arrived (UTC, and seconds since captest started) into its top left corner
+    #[arg(long)]
+    timestamp_overlay: bool,
+    /// Write the frame's display, arrival and monotonic timestamps to <output>.json next to the capture
+    #[arg(long)]
+    timestamp_sidecar: bool,
+}
+
+impl CaptureArgs {
+    /// Turn on what `--preset` and `--ocr-format` imply. Steps a preset
+    /// enables stay on, and options given explicitly keep their values.
+    fn apply_preset(&mut self) {
+        self.ocr |= self.ocr_format.is_some();
+        let Some(preset) = self.preset else {
+            return;
+        };
+        self.ocr |= preset.ocr();
+        self.analyze |= preset.analyze();
+        self.timestamp_sidecar |= preset.timestamp_sidecar();
+        if self.average == 1 {
+            self.average = preset.average();
+        }
+    }
+
+    fn jpeg_quality(&self) -> u8 {
+        self.quality
+            .or(self.preset.map(preset::Preset::jpeg_quality))
+            .unwrap_or(preset::DEFAULT_JPEG_QUALITY)
+    }
+
+    /// The format to save in: `--format`, else the one the output's
+    /// extension names, else the preset's, else JPEG.
+    fn image_format(&self) -> encode::ImageFormat {
+        self.format
+            .or_else(|| self.output.as_deref().and_then(encode::ImageFormat::from_path))
+            .or(self.preset.map(preset::Preset::format))
+            .unwrap_or_default()
+    }
+
+    /// Where `--output` is saved: given an extension for the format when it
+    /// has none, and in the configured output directory.
+    fn output_path(&self) -> Option<String> {
+        let output = self.output.as_deref()?;
+        let format = self.image_format();
+        match encode::ImageFormat::from_path(output) {
+            Some(named) => {
+                if named != format {
+                    status!("Saving {} as {}, as --format asks, although its extension names another format", output, format.extension());
+                }
+                Some(config::resolve_output_path(output))
+            }
+            None => Some(config::resolve_output_path(&format!("{}.{}", output, format.extension()))),
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum FrameTypeArg {
+    /// 32-bit BGRA, best for stills
+    Bgra,
+    /// NV12 YUV, cheaper for video encoding
+    Yuv,
+    /// 24-bit RGB
+    Rgb,
+}
+
+impl From<FrameTypeArg> for scap::frame::FrameType {
+    fn from(frame_type: FrameTypeArg) -> Self {
+        match frame_type {
+            FrameTypeArg::Bgra => scap::frame::FrameType::BGRAFrame,
+            FrameTypeArg::Yuv => scap::frame::FrameType::YUVFrame,
+            FrameTypeArg::Rgb => scap::frame::FrameType::RGB,
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let cli = Cli::parse();
+    timestamp::start_clock();
+    let mut cli = Cli::parse();
+    // The library is quiet by default; the command line prints progress unless --quiet
+    output::set_quiet(cli.quiet);
+    if let Commands::Capture { args, .. } | Commands::CaptureWindow { args, .. } = &mut cli.command {
+        args.apply_preset();
+    }
+    if cli.timings {
+        timings::enable();
+    }
 
-    match &cli.command {
-        Commands::List => list_screens()?,
-        Commands::ListWindows => list_windows()?,
-        Commands::Capture { screen, output, analyze, prompt, ocr } => {
-            capture_screen(*screen, output.as_deref(), *analyze, prompt.as_deref(), *ocr).await?
+    // An output format from the command line already applies to errors in
+    // the config file, which scripts asking for JSON need to parse too
+    let cli_format = cli.output_format.or(cli.json.then_some(OutputFormat::Json));
+    if let Some(format) = cli_format {
+        output::set_format(format);
+    }
+
+    // Settings precedence: CLI flag, then CAPTEST_* environment variable (both
+    // handled by clap), then config file, then built-in default
+    let config = match config::load_config_file(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(e) => output::emit(Err(e), None),
+    };
+    let output_format = match (cli_format, config.format.as_deref()) {
+        (Some(format), _) => format,
+        (None, Some(format)) => match OutputFormat::from_str(format, true) {
+            Ok(format) => format,
+            Err(e) => output::emit(Err(format!("Invalid format in config file: {}", e).into()), None),
         },
-        Commands::CaptureWindow { window, output, analyze, prompt, ocr } => {
-            capture_window(*window, output.as_deref(), *analyze, prompt.as_deref(), *ocr).await?
+        (None, None) => OutputFormat::Text,
+    };
+    output::set_format(output_format);
+    // Checked here rather than by clap, since presets only turn OCR on after parsing
+    if let Commands::Capture { args, .. } | Commands::CaptureWindow { args, .. } = &cli.command
+        && args.ocr_region.is_some() && !args.ocr && args.extract.is_empty()
+    {
+        output::emit(Err("--ocr-region needs OCR: add --ocr, --ocr-format, --extract or a preset that runs OCR".into()), None);
+    }
+    if let Commands::Capture { args, .. } | Commands::CaptureWindow { args, .. } = &cli.command
+        && args.to.iter().any(|sink| sink.uses_stdout())
+    {
+        if output::is_json() {
+            output::emit(Err("--to stdout can't be combined with --output-format json, which prints the report on stdout".into()), None);
+        }
+        output::reserve_stdout();
+    }
+    let llm_provider = match (cli.llm_provider, config.llm_provider.as_deref()) {
+        (Some(provider), _) => provider,
+        (None, Some(provider)) => match llm::Provider::from_str(provider, true) {
+            Ok(provider) => provider,
+            Err(e) => output::emit(Err(format!("Invalid llm_provider in config file: {}", e).into()), None),
         },
+        (None, None) => llm::Provider::default(),
+    };
+    let encoder = match (cli.encoder, config.encoder.as_deref()) {
+        (Some(encoder), _) => encoder,
+        (None, Some(encoder)) => match encode::Encoder::from_str(encoder, true) {
+            Ok(encoder) => encoder,
+            Err(e) => output::emit(Err(format!("Invalid encoder in config file: {}", e).into()), None),
+        },
+        (None, None) => encode::Encoder::default(),
+    };
+    let on_memory_limit = match (cli.on_memory_limit, config.on_memory_limit.as_deref()) {
+        (Some(action), _) => action,
+        (None, Some(action)) => match memory::OnMemoryLimit::from_str(action, true) {
+            Ok(action) => action,
+            Err(e) => output::emit(Err(format!("Invalid on_memory_limit in config file: {}", e).into()), None),
+        },
+        (None, None) => memory::OnMemoryLimit::default(),
+    };
+    let regions = match config::saved_regions(&config) {
+        Ok(regions) => regions,
+        Err(e) => output::emit(Err(format!("Invalid region in config file: {}", e).into()), None),
+    };
+    let ignore_regions = config.ignore_regions.iter().flatten()
+        .map(|region| region.parse::<change::RegionArg>()?.resolve_in(&regions).map(|region| region.rect))
+        .collect();
+    let ignore_regions = match ignore_regions {
+        Ok(regions) => regions,
+        Err(e) => output::emit(Err(format!("Invalid ignore_regions in config file: {}", e).into()), None),
+    };
+    let share = match config::share_settings(&config) {
+        Ok(share) => share,
+        Err(e) => output::emit(Err(format!("Invalid share settings in config file: {}", e).into()), None),
+    };
+    let webhooks = match config::webhooks(&config) {
+        Ok(webhooks) => webhooks,
+        Err(e) => output::emit(Err(format!("Invalid webhook in config file: {}", e).into()), None),
+    };
+    config::init(config::Settings {
+        run_id: cli.run_id.clone().unwrap_or_else(config::new_run_id),
+        llm_url: cli.llm_url.clone()
+            .or_else(|| config.llm_url.clone())
+            .unwrap_or_else(|| llm_provider.default_url().to_string()),
+        llm_provider,
+        llm_model: cli.llm_model.clone().or_else(|| config.llm_model.clone()),
+        ocr_model_dir: cli.ocr_model_dir.clone().or_else(|| config.ocr_model_dir.clone()),
+        ocr_model_url: cli.ocr_model_url.clone()
+            .or_else(|| config.ocr_model_url.clone())
+            .unwrap_or_else(|| ocr::DEFAULT_MODEL_URL.to_string()),
+        ocr_lang: cli.ocr_lang.clone().or_else(|| config.ocr_lang.clone()),
+        ocr_alphabet: cli.ocr_alphabet.clone().or_else(|| config.ocr_alphabet.clone()),
+        ocr_allowed_chars: cli.ocr_allowed_chars.clone().or_else(|| config.ocr_allowed_chars.clone()),
+        ocr_beam_width: cli.ocr_beam_width.or(config.ocr_beam_width),
+        ocr_dictionary: cli.ocr_dictionary.clone().or_else(|| config.ocr_dictionary.clone()),
+        output_dir: cli.output_dir.clone().or_else(|| config.output_dir.clone()),
+        encoder,
+        gpu: cli.gpu || config.gpu.unwrap_or(false),
+        max_memory_mb: cli.max_memory.or(config.max_memory_mb),
+        on_memory_limit,
+        buffer_depth: cli.buffer_depth.map(|depth| depth as usize).or(config.buffer_depth).map(|depth| depth.max(1)),
+        privacy_blocklist: config.privacy_blocklist.clone()
+            .unwrap_or_else(|| privacy::DEFAULT_BLOCKLIST.iter().map(|entry| entry.to_string()).collect()),
+        privacy_strict: cli.privacy_strict || config.privacy_strict.unwrap_or(false),
+        show_highlight: cli.show_highlight || config.show_highlight.unwrap_or(false),
+        cas: cli.cas || config.cas.unwrap_or(false),
+        ignore_regions,
+        regions,
+        webhooks,
+        mqtt: config.mqtt.clone(),
+        share,
+        upload: config.upload.as_ref().map(|upload| upload::UploadSettings {
+            ssh_key: upload.ssh_key.clone(),
+            webdav_username: upload.webdav_username.clone(),
+            webdav_password: upload.webdav_password.clone(),
+        }).unwrap_or_default(),
+        input_token_price: cli.input_token_price.or(config.input_token_price),
+        output_token_price: cli.output_token_price.or(config.output_token_price),
+    });
+
+    if config::settings().gpu && !cfg!(feature = "gpu") {
+        status!("This build has no GPU support (feature `gpu`); converting frames on the CPU");
     }
 
-    Ok(())
+    let mut partial_failure = None;
+    let result = match &cli.command {
+        Commands::List => list_screens()
+            .map(|screens| json!({ "status": "ok", "screens": screens })),
+        Commands::ListWindows => list_windows()
+            .map(|_| json!({ "status": "ok", "windows": list_window_summaries() })),
+        Commands::CasVerify { dir } => {
+            let dir = dir.clone().or_else(|| config::settings().output_dir.clone()).unwrap_or_else(|| ".".into());
+            let report = cas::verify(&dir);
+            partial_failure = report.as_ref().ok().and_then(|report| report.failure);
+            to_document(report)
+        },
+        Commands::Doctor => {
+            let report = doctor::doctor(&capture::cancel_on_ctrl_c()).await;
+            partial_failure = report.as_ref().ok().and_then(|report| report.failure);
+            to_document(report)
+        },
+        Commands::Capture { screen, args } => {
+            let report = capture_screen(*screen, args, &capture::cancel_on_ctrl_c()).await;
+            partial_failure = report.as_ref().ok().and_then(|report| report.failure);
+            to_document(report)
+        },
+        Commands::CaptureWindow { window, select, args } => {
+            let report = match select.resolve(*window) {
+                Ok(window) => capture_window(window, args, &capture::cancel_on_ctrl_c()).await,
+                Err(e) => Err(e),
+            };
+            partial_failure = report.as_ref().ok().and_then(|report| report.failure);
+            to_document(report)
+        },
+        Commands::CaptureSync { windows, output, sequence, bundle } => {
+            to_document(capture_sync::capture_sync(windows, output, sequence.as_deref(), bundle.as_deref(), &capture::cancel_on_ctrl_c()).await)
+        },
+        Commands::CaptureScroll { window, output, lines, max_frames, settle } => {
+            scroll_capture::capture_scroll(*window, output.as_deref(), *lines, *max_frames, *settle, &capture::cancel_on_ctrl_c()).await
+        },
+        Commands::Icon { window, select, size, output } => {
+            select.resolve(*window).and_then(|window| icon::save_icon(window, *size, output))
+        },
+        Commands::Serve { bind, idle_timeout, fps } => {
+            server::serve(*bind, Duration::from_secs(*idle_timeout), *fps).await
+                .map(|_| serde_json::Value::Null)
+        },
+        Commands::ListModels => llm::list_models().await
+            .map(|models| {
+                for model in &models {
+                    result!("{}", model);
+                }
+                json!({ "status": "ok", "models": models })
+            }),
+        Commands::Ocr { input, extract, ocr_format, no_cache } => ocr_image(input, extract, *ocr_format, !no_cache).await,
+        Commands::Analyze { input, prompt, no_cache, cache_ttl, llm_full_resolution, no_tiles } => {
+            let cache_ttl = (!no_cache).then(|| Duration::from_secs(*cache_ttl));
+            analyze_image(input, prompt.as_deref(), cache_ttl, *llm_full_resolution, *no_tiles).await
+        },
+        Commands::AnalyzeBatch { files, prompt, split_images } => {
+            analyze_batch(files, prompt.as_deref(), *split_images).await
+        },
+        Commands::OcrBatch { inputs, jobs, no_cache, bundle } => {
+            let jobs = jobs.unwrap_or_else(|| std::thread::available_parallelism().map_or(1, usize::from));
+            let report = ocr_batch::ocr_batch(inputs, jobs, !no_cache, bundle.as_deref()).await;
+            partial_failure = report.as_ref().ok().and_then(|report| report.failure);
+            to_document(report)
+        },
+        Commands::Ingest { dir, watch, index, no_cache } => {
+            ingest::ingest(dir, index.as_deref(), *watch, !no_cache, &capture::cancel_on_ctrl_c()).await
+        },
+        Commands::Watch { target, args } => {
+            let result = watch::watch(target.key(), args).await;
+            webhook::flush().await;
+            mqtt::stop().await;
+            result.map(|_| serde_json::Value::Null)
+        },
+        Commands::ExtractLinks { target, image, no_cache } => {
+            extract_links(target, image.as_deref(), !no_cache, &capture::cancel_on_ctrl_c()).await
+        },
+        Commands::DetectElements { target, image, ocr, no_cache } => {
+            detect_elements(target, image.as_deref(), *ocr, !no_cache, &capture::cancel_on_ctrl_c()).await
+        },
+        Commands::FindImage { needle, target, image, matching } => {
+            let report = find_image(target, image.as_deref(), needle, matching, &capture::cancel_on_ctrl_c()).await;
+            partial_failure = report.as_ref().ok().and_then(|report| report.failure);
+            to_document(report)
+        },
+        Commands::WaitForImage { needle, target, timeout, interval, matching } => {
+            let report = wait_for_image(target, needle, *timeout, *interval, matching, &capture::cancel_on_ctrl_c()).await;
+            partial_failure = report.as_ref().ok().and_then(|report| report.failure);
+            to_document(report)
+        },
+        Commands::Transcribe { target, image, output, llm_cleanup, no_cache } => {
+            transcribe(target, image.as_deref(), output.as_deref(), *llm_cleanup, !no_cache, &capture::cancel_on_ctrl_c()).await
+        },
+        Commands::Record { target, args } => {
+            let report = record::record(target.key(), args, &capture::cancel_on_ctrl_c()).await;
+            partial_failure = report.as_ref().ok().and_then(|report| report.failure);
+            to_document(report)
+        },
+        Commands::Recover { file } => record::recover(file),
+        Commands::Diff { baseline, current, text: true, .. } => {
+            let report = text_diff::diff_files(baseline, current).await;
+            partial_failure = report.as_ref().ok().and_then(|report| report.failure);
+            to_document(report)
+        },
+        Commands::Diff { baseline, current, args, .. } => {
+            let report = diff::diff_files(baseline, current, args);
+            partial_failure = report.as_ref().ok().and_then(|report| report.failure);
+            to_document(report)
+        },
+        Commands::DiffDir { baseline, current, report_dir, args } => {
+            let report = diff_dir::diff_dirs(baseline, current, report_dir, args);
+            partial_failure = report.as_ref().ok().and_then(|report| report.failure);
+            to_document(report)
+        },
+        Commands::Session { command: SessionCommand::Record { target, args } } => {
+            session::record(target.key(), args, &capture::cancel_on_ctrl_c()).await
+        },
+        Commands::Session { command: SessionCommand::Inspect { bundle } } => session::inspect(bundle),
+        Commands::Session { command: SessionCommand::Export { bundle, video } } => session::export_video(bundle, video),
+        Commands::Remote { destination, port, command: RemoteCommand::Capture { screen, args } } => {
+            remote::capture(destination, *port, session_pool::TargetKey::Screen(*screen), args, &capture::cancel_on_ctrl_c()).await
+        },
+        Commands::Remote { destination, port, command: RemoteCommand::CaptureWindow { window, args } } => {
+            remote::capture(destination, *port, session_pool::TargetKey::Window(*window), args, &capture::cancel_on_ctrl_c()).await
+        },
+        Commands::Remote { destination, port, command: RemoteCommand::Analyze { target, prompt } } => {
+            remote::analyze(destination, *port, target.key(), prompt.as_deref(), &capture::cancel_on_ctrl_c()).await
+        },
+        Commands::Fleet { command: FleetCommand::Capture { hosts, target, output_dir, crop, max_width, bundle } } => {
+            let report = fleet::capture(hosts, target.key(), output_dir, crop.as_deref(), *max_width, bundle.as_deref(), &capture::cancel_on_ctrl_c()).await;
+            partial_failure = report.as_ref().ok().and_then(|report| report.failure);
+            to_document(report)
+        },
+        Commands::Track { target, args } => track::track(target.key(), args, &capture::cancel_on_ctrl_c()).await,
+        Commands::AssertRegionHash { target, region, expect } => {
+            // A named region picks its screen unless a target was given
+            let display = region.resolve().ok().and_then(|region| region.display);
+            let key = match (target.screen, target.window, display) {
+                (None, None, Some(display)) => session_pool::TargetKey::Screen(display),
+                _ => target.key(),
+            };
+            let report = region_hash::assert_region_hash(key, region, expect, &capture::cancel_on_ctrl_c()).await;
+            partial_failure = report.as_ref().ok().and_then(|report| report.failure);
+            to_document(report)
+        },
+        Commands::Monitor { target, args } => {
+            // A named region picks its screen unless a target was given
+            let display = args.region.as_ref().and_then(|region| region.resolve().ok()).and_then(|region| region.display);
+            let key = match (target.screen, target.window, display) {
+                (None, None, Some(display)) => session_pool::TargetKey::Screen(display),
+                _ => target.key(),
+            };
+            monitor::monitor(key, args, &capture::cancel_on_ctrl_c()).await
+        },
+        Commands::Coords { target, points, from, to, streamed } => coords::coords(target.key(), points, *from, *to, *streamed).await,
+        Commands::Verify { target, baseline, text: true, .. } => {
+            let report = text_diff::verify(target.key(), baseline, &capture::cancel_on_ctrl_c()).await;
+            partial_failure = report.as_ref().ok().and_then(|report| report.failure);
+            to_document(report)
+        },
+        Commands::Verify { target, baseline, args, .. } => {
+            let report = diff::verify(target.key(), baseline, args, &capture::cancel_on_ctrl_c()).await;
+            partial_failure = report.as_ref().ok().and_then(|report| report.failure);
+            to_document(report)
+        },
+    };
+
+    output::emit(result, partial_failure)
 }
 
-fn save_jpeg_bytes(jpeg_bytes: &[u8], filename: &str) -> Result<(), Box<dyn std::error::Error>> {
-    use std::fs::File;
-    use std::io::Write;
-    
-    println!("Saving {filename}");
-    let mut file = File::create(filename)?;
-    file.write_all(jpeg_bytes)?;
-    
-    Ok(())
+fn to_document<T: serde::Serialize>(result: Result<T, Box<dyn std::error::Error>>) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    Ok(serde_json::to_value(result?)?)
 }
 
-async fn analyze_image_with_llm_base64(base64_image: &str, custom_prompt: Option<&str>) -> Result<String, Box<dyn std::error::Error>> {
-    use serde_json::json;
-    
-    let default_prompt = "Analyze this screenshot and describe all UI elements, text, images and other information. Analyze text carefully and include the full text recognized in each area.";
-    let prompt = custom_prompt.unwrap_or(default_prompt);
-    
-    // Use reqwest directly to ensure proper vision API format
-    let vision_payload = json!({
-        "model": "gpt-4-vision-preview",
-        "messages": [
-            {
-                "role": "user",
-                "content": [
-                    {
-                        "type": "text",
-                        "text": prompt
-                    },
-                    {
-                        "type": "image_url",
-                        "image_url": {
-                            "url": format!("data:image/jpeg;base64,{}", base64_image)
-                        }
-                    }
-                ]
-            }
-        ],
-        "max_tokens": 1000
-    });
-    
-    let response = reqwest::Client::new()
-        .post("http://localhost:1234/v1/chat/completions")
-        .header("Authorization", "Bearer lm-studio")
-        .header("Content-Type", "application/json")
-        .json(&vision_payload)
-        .send()
-        .await?;
-    
-    let status = response.status();
-    if !status.is_success() {
-        let error_text = response.text().await?;
-        return Err(format!("LLM request failed with status {}: {}. Make sure LMStudio is running on localhost:1234 with a vision model loaded.", status, error_text).into());
-    }
-    
-    let response_json: serde_json::Value = response.json().await?;
-    
-    if let Some(content) = response_json["choices"][0]["message"]["content"].as_str() {
-        Ok(content.to_string())
-    } else {
-        Err("No content in LLM response".into())
+async fn analyze_batch(files: &[std::path::PathBuf], prompt: Option<&str>, split: bool) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    // Re-encode everything as JPEG so any format the image crate reads is accepted
+    let mut images = Vec::with_capacity(files.len());
+    for file in files {
+        status!("Loading {}", file.display());
+        let image = image::open(file)
+            .map_err(|e| format!("Failed to open {}: {}", file.display(), e))?
+            .to_rgb8();
+        images.push(rgb8_to_jpeg_bytes(image.width(), image.height(), image.as_raw())?);
     }
+
+    let prompt = prompt.unwrap_or("Describe each of these screenshots and summarize what happens across them.");
+    status!("Analyzing {} images", images.len());
+    let analysis = llm::analyze_images(&images, prompt, split).await
+        .map_err(|e| CaptestError::new(ErrorKind::LlmFailed, e.to_string()))?;
+    result!("LLM Analysis:\n{}", analysis.text);
+
+    Ok(json!({
+        "status": "ok",
+        "files": files,
+        "analysis": analysis.text,
+        "usage": analysis.usage,
+    }))
 }
 
-async fn extract_text_with_ocr(width: u32, height: u32, rgb_data: &[u8]) -> Result<String, Box<dyn std::error::Error>> {
-    use std::path::PathBuf;
+/// Read an image file as RGB8, or with a path of `-`, image bytes in any
+/// format the image crate knows from stdin, so other capture tools can pipe
+/// into captest.
+fn open_image_input(path: &std::path::Path) -> Result<image::RgbImage, Box<dyn std::error::Error>> {
+    if path.as_os_str() != "-" {
+        return Ok(image::open(path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?
+            .to_rgb8());
+    }
+    let mut bytes = Vec::new();
+    std::io::Read::read_to_end(&mut std::io::stdin().lock(), &mut bytes)
+        .map_err(|e| format!("Failed to read stdin: {}", e))?;
+    if bytes.is_empty() {
+        return Err("No image data on stdin".into());
+    }
+    Ok(image::load_from_memory(&bytes)
+        .map_err(|e| format!("Failed to decode the image on stdin: {}", e))?
+        .to_rgb8())
+}
 
-    println!("Extracting text with OCR");
-    
-    // Model paths - these should be downloaded using the download-models.sh script from ocrs examples
-    let mut detection_model_path = PathBuf::from("../ocrs/ocrs/examples/text-detection.rten");
-    let mut rec_model_path = PathBuf::from("../ocrs/ocrs/examples/text-recognition.rten");
-    
-    // If the models don't exist in the ocrs examples directory, try current directory
-    if !detection_model_path.exists() {
-        detection_model_path = PathBuf::from("text-detection.rten");
+/// Pixels for commands that work on either a live target or an image file.
+async fn load_or_grab_rgb8(target: &TargetArgs, image: Option<&std::path::Path>, cancel: &CancellationToken) -> Result<(u32, u32, Vec<u8>), Box<dyn std::error::Error>> {
+    match image {
+        Some(path) => {
+            let image = open_image_input(path)?;
+            Ok((image.width(), image.height(), image.into_raw()))
+        }
+        None => capture::grab_rgb8(target.key(), cancel).await,
     }
-    if !rec_model_path.exists() {
-        rec_model_path = PathBuf::from("text-recognition.rten");
+}
+
+async fn ocr_image(input: &std::path::Path, patterns: &[extract::Pattern], format: ocr::OcrFormat, use_cache: bool) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let image = open_image_input(input)?;
+    let lines = ocr::extract_lines(image.width(), image.height(), image.as_raw(), use_cache).await
+        .map_err(|e| CaptestError::new(ErrorKind::OcrFailed, e.to_string()))?;
+    let formatted = (format != ocr::OcrFormat::Text).then(|| ocr::format_lines(lines.clone(), image.width(), image.height(), format));
+    let report = OcrReport::from_lines(lines);
+    let extracted = match patterns.is_empty() {
+        true => {
+            result!("{}", formatted.as_deref().unwrap_or(&report.text));
+            None
+        }
+        false => Some(extract::extract(patterns, &report.text)),
+    };
+
+    Ok(json!({
+        "status": "ok",
+        "width": image.width(),
+        "height": image.height(),
+        "ocr": report,
+        "extracted": extracted,
+    }))
+}
+
+async fn analyze_image(input: &std::path::Path, prompt: Option<&str>, cache_ttl: Option<Duration>, full_resolution: bool, no_tiles: bool) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let image = open_image_input(input)?;
+    let jpeg_bytes = rgb8_to_jpeg_bytes(image.width(), image.height(), image.as_raw())?;
+    let frame = llm::ImageMapping::frame(image.width(), image.height(), (0, 0), 1);
+    let analysis = analyze_frame(image.as_raw(), &jpeg_bytes, frame, prompt, cache_ttl, full_resolution, no_tiles).await
+        .map_err(|e| CaptestError::new(ErrorKind::LlmFailed, e.to_string()))?;
+    result!("LLM Analysis:\n{}", analysis.text);
+
+    Ok(json!({
+        "status": "ok",
+        "width": image.width(),
+        "height": image.height(),
+        "analysis": analysis.text,
+        "raw_analysis": analysis.raw_analysis,
+        "llm_image": analysis.image,
+        "llm_tiles": analysis.tiles,
+        "usage": analysis.usage,
+    }))
+}
+
+async fn extract_links(target: &TargetArgs, image: Option<&std::path::Path>, use_cache: bool, cancel: &CancellationToken) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let (width, height, rgb_data) = load_or_grab_rgb8(target, image, cancel).await?;
+
+    let lines = ocr::extract_lines(width, height, &rgb_data, use_cache).await
+        .map_err(|e| CaptestError::new(ErrorKind::OcrFailed, e.to_string()))?;
+    let links = links::find_links(&lines);
+    if links.is_empty() {
+        status!("No links found");
     }
-    
-    // Check if models exist
-    if !detection_model_path.exists() || !rec_model_path.exists() {
-        return Err(format!(
-            "OCR models not found. Please download models using the download-models.sh script from the ocrs examples directory.\nLooked for:\n- {}\n- {}",
-            detection_model_path.display(),
-            rec_model_path.display()
-        ).into());
+    for link in &links {
+        result!("{}", link.text);
     }
-    
-    // Load the models
-    println!("Loading models");
-    let detection_model = Model::load_file(detection_model_path)?;
-    let recognition_model = Model::load_file(rec_model_path)?;
-    
-    // Create OCR engine
-    let engine = OcrEngine::new(OcrEngineParams {
-        detection_model: Some(detection_model),
-        recognition_model: Some(recognition_model),
-        ..Default::default()
-    })?;
 
-    println!("Preparing image for OCR");
-    
-    // Create image source directly from RGB8 data
-    let img_source = ImageSource::from_bytes(rgb_data, (width, height))?;
-    let ocr_input = engine.prepare_input(img_source)?;
-    
-    println!("Performing OCR analysis");
-    // Perform OCR: detect words, find lines, recognize text
-    let word_rects = engine.detect_words(&ocr_input)?;
-    let line_rects = engine.find_text_lines(&ocr_input, &word_rects);
-    let line_texts = engine.recognize_text(&ocr_input, &line_rects)?;
-    
-    // Collect all text lines into a single string
-    let extracted_text: Vec<String> = line_texts
-        .iter()
-        .flatten()
-        // Filter likely spurious detections
-        .filter(|l| l.to_string().len() > 1)
-        .map(|l| l.to_string())
-        .collect();
-    
-    if extracted_text.is_empty() {
-        Ok("No text detected in the image.".to_string())
-    } else {
-        Ok(extracted_text.join("\n"))
+    Ok(json!({ "status": "ok", "width": width, "height": height, "links": links }))
+}
+
+async fn detect_elements(target: &TargetArgs, image: Option<&std::path::Path>, with_text: bool, use_cache: bool, cancel: &CancellationToken) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let (width, height, rgb_data) = load_or_grab_rgb8(target, image, cancel).await?;
+
+    let mut elements = tokio::task::block_in_place(|| elements::detect(width, height, &rgb_data));
+    if with_text {
+        let lines = ocr::extract_lines(width, height, &rgb_data, use_cache).await
+            .map_err(|e| CaptestError::new(ErrorKind::OcrFailed, e.to_string()))?;
+        elements::label(&mut elements, &lines);
+    }
+    if elements.is_empty() {
+        status!("No elements found");
+    }
+    for element in &elements {
+        result!("{}", element.describe());
     }
+
+    Ok(json!({ "status": "ok", "width": width, "height": height, "elements": elements }))
 }
 
-fn bgra_to_rgb8(bgra_frame: &scap::frame::BGRAFrame) -> (u32, u32, Vec<u8>) {
-    // Convert BGRA to RGB by swapping B and R channels and dropping alpha
-    let mut rgb_data = Vec::with_capacity((bgra_frame.data.len() * 3) / 4);
-    for chunk in bgra_frame.data.chunks_exact(4) {
-        rgb_data.push(chunk[2]); // R (was B)
-        rgb_data.push(chunk[1]); // G
-        rgb_data.push(chunk[0]); // B (was R)
-        // Drop alpha channel
+async fn find_image(target: &TargetArgs, image: Option<&std::path::Path>, needle: &std::path::Path, matching: &MatchArgs, cancel: &CancellationToken) -> Result<template::FindReport, Box<dyn std::error::Error>> {
+    let needle = open_image_input(needle)?;
+    let (width, height, rgb_data) = load_or_grab_rgb8(target, image, cancel).await?;
+
+    let matches = tokio::task::block_in_place(|| template::find(width, height, &rgb_data, &needle, &matching.scales, matching.threshold))?;
+    if matches.is_empty() {
+        status!("No match scored {} or more", matching.threshold);
     }
-    
-    (bgra_frame.width as u32, bgra_frame.height as u32, rgb_data)
+    for found in &matches {
+        result!("{}", found.describe());
+    }
+    Ok(template::FindReport::new(width, height, matches))
 }
 
-fn rgb8_to_jpeg_bytes(width: u32, height: u32, rgb_data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
-    use image::{ImageBuffer, Rgb};
-    
-    // Create image buffer from RGB8 data
-    let img_buffer = ImageBuffer::<Rgb<u8>, Vec<u8>>::from_raw(
+async fn wait_for_image(target: &TargetArgs, needle: &std::path::Path, timeout: f64, interval: f64, matching: &MatchArgs, cancel: &CancellationToken) -> Result<template::WaitReport, Box<dyn std::error::Error>> {
+    status!("Waiting up to {:.0}s for {} on {}", timeout, needle.display(), target.key());
+    let needle = open_image_input(needle)?;
+    let started = Instant::now();
+    let deadline = started + Duration::from_secs_f64(timeout.max(0.0));
+    let mut ticker = tokio::time::interval(Duration::from_secs_f64(interval.max(0.05)));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let (mut attempts, mut width, mut height) = (0, 0, 0);
+    let grabber = capture::FrameGrabber::new(target.key(), (1.0 / interval.max(0.05)).ceil() as u32)?;
+
+    loop {
+        tokio::select! {
+            _ = cancel.cancelled() => return Err(CaptestError::new(ErrorKind::Cancelled, "Waiting cancelled").into()),
+            _ = tokio::time::sleep_until(deadline.into()) => break,
+            _ = ticker.tick() => {}
+        }
+
+        // A frame that fails to arrive is retried; a missing target or permission is not
+        let grabbed = grabber.grab(cancel).await
+            .map_err(|e| (error::error_kind(&*e), e.to_string()));
+        let (frame_width, frame_height, rgb_data) = match grabbed {
+            Ok(frame) => frame,
+            Err((ErrorKind::CaptureTimeout | ErrorKind::Failure, e)) => {
+                status!("Capture failed, retrying: {}", e);
+                continue;
+            }
+            Err((kind, e)) => return Err(CaptestError::new(kind, e).into()),
+        };
+        (width, height) = (frame_width, frame_height);
+        attempts += 1;
+
+        let matches = tokio::task::block_in_place(|| template::find(width, height, &rgb_data, &needle, &matching.scales, matching.threshold))?;
+        if !matches.is_empty() {
+            for found in &matches {
+                result!("{}", found.describe());
+            }
+            let elapsed_ms = started.elapsed().as_millis();
+            status!("Found after {} captures in {} ms", attempts, elapsed_ms);
+            return Ok(template::WaitReport { status: "ok".to_string(), width, height, matches, attempts, elapsed_ms, failure: None });
+        }
+    }
+
+    status!("Not found within {:.0}s ({} captures)", timeout, attempts);
+    Ok(template::WaitReport {
+        status: "timeout".to_string(),
         width,
         height,
-        rgb_data.to_vec(),
-    ).ok_or("Failed to create image buffer")?;
-    
-    // Convert to JPEG bytes with high quality
-    let mut jpeg_bytes = Vec::new();
-    {
-        use image::codecs::jpeg::JpegEncoder;
-        use image::ImageEncoder;
-        
-        let encoder = JpegEncoder::new_with_quality(&mut jpeg_bytes, 75);
-        encoder.write_image(
-            &img_buffer,
-            img_buffer.width(),
-            img_buffer.height(),
-            image::ColorType::Rgb8,
-        )?;
+        matches: Vec::new(),
+        attempts,
+        elapsed_ms: started.elapsed().as_millis(),
+        failure: Some(ErrorKind::ImageNotFound),
+    })
+}
+
+async fn transcribe(target: &TargetArgs, image: Option<&std::path::Path>, output: Option<&str>, llm_cleanup: bool, use_cache: bool, cancel: &CancellationToken) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let (width, height, rgb_data) = load_or_grab_rgb8(target, image, cancel).await?;
+
+    let lines = ocr::extract_lines(width, height, &rgb_data, use_cache).await
+        .map_err(|e| CaptestError::new(ErrorKind::OcrFailed, e.to_string()))?;
+    let mut markdown = transcribe::to_markdown(lines);
+
+    let mut usage = None;
+    if llm_cleanup {
+        let prompt = format!(
+            "Below is a Markdown transcription of this screenshot produced by OCR. Using the image, fix recognition errors, \
+             headings, lists and tables. Reply with only the corrected Markdown.\n\n{}", markdown);
+        let jpeg_bytes = rgb8_to_jpeg_bytes(width, height, &rgb_data)?;
+        let analysis = llm::analyze_image_cached(&jpeg_bytes, Some(&prompt), None).await
+            .map_err(|e| CaptestError::new(ErrorKind::LlmFailed, e.to_string()))?;
+        markdown = analysis.text;
+        usage = analysis.usage;
     }
-    
-    Ok(jpeg_bytes)
+
+    let output = output.map(config::resolve_output_path);
+    match &output {
+        Some(filename) => {
+            std::fs::write(filename, &markdown)?;
+            status!("Saved transcription to {}", filename);
+        }
+        None => result!("{}", markdown.trim_end()),
+    }
+
+    Ok(json!({ "status": "ok", "markdown": markdown, "output": output, "usage": usage }))
 }
 
 
-fn list_screens() -> Result<(), Box<dyn std::error::Error>> {
+fn list_screens() -> Result<Vec<ScreenInfo>, Box<dyn std::error::Error>> {
     // Check if screen capture is supported
     if !scap::is_supported() {
-        println!("Screen capture not supported");
-        return Ok(());
+        return Err(CaptestError::new(ErrorKind::Unsupported, "Screen capture not supported").into());
     }
 
-    let targets = scap::get_all_targets();
+    let targets = capture::all_targets();
     
-    println!("Available screens:");
-    println!("==================");
+    status!("Available screens:");
+    status!("==================");
     
+    let mut screens = Vec::new();
     let mut screen_index = 0;
     for target in targets.iter() {
         match target {
             Target::Display(display) => {
-                println!("Screen {}: Display ID {}", 
+                status!("Screen {}: Display ID {}", 
                     screen_index, 
                     display.id
                 );
-                println!("          Title: {}", display.title);
-                println!();
+                status!("          Title: {}", display.title);
+                let monitor = platforms::display_monitor(display.id);
+                if let Some(monitor) = &monitor {
+                    status!("          Monitor: {}", monitor.describe());
+                }
+                status!();
+                screens.push(ScreenInfo {
+                    index: screen_index,
+                    id: display.id,
+                    title: display.title.clone(),
+                    bounds: platforms::display_bounds(display.id).map(coords::Rect::from_bounds),
+                    monitor,
+                });
                 screen_index += 1;
             }
             Target::Window(_) => {
@@ -290,26 +1372,47 @@ fn list_screens() -> Result<(), Box<dyn std::error::Error>> {
         }
     }
     
-    Ok(())
+    Ok(screens)
 }
 
+fn list_window_summaries() -> Vec<WindowSummary> {
+    if !scap::is_supported() {
+        return Vec::new();
+    }
+
+    capture::all_targets()
+        .into_iter()
+        .filter_map(|target| match target {
+            Target::Window(window) => Some(window),
+            Target::Display(_) => None,
+        })
+        .enumerate()
+        .map(|(index, window)| WindowSummary {
+            index,
+            id: window.id,
+            title: window.title,
+            pid: platforms::window_pid(window.id),
+            process: platforms::window_process(window.id),
+            bounds: platforms::window_bounds(window.id).map(coords::Rect::from_bounds),
+        })
+        .collect()
+}
 
 
-async fn capture_window(window_index: usize, output_filename: Option<&str>, analyze: bool, prompt: Option<&str>, ocr: bool) -> Result<(), Box<dyn std::error::Error>> {
+
+async fn capture_window(window_index: usize, args: &CaptureArgs, cancel: &CancellationToken) -> Result<CaptureReport, Box<dyn std::error::Error>> {
     // Check if screen capture is supported
     if !scap::is_supported() {
-        println!("Screen capture not supported");
-        return Ok(());
+        return Err(CaptestError::new(ErrorKind::Unsupported, "Screen capture not supported").into());
     }
 
     // Request permission if not already granted
     if !scap::has_permission() {
-        scap::request_permission();
-        println!("Please grant screen recording permission and rerun.");
-        return Ok(());
+        capture::request_permission();
+        return Err(CaptestError::new(ErrorKind::PermissionDenied, "Please grant screen recording permission and rerun.").into());
     }
 
-    let targets = scap::get_all_targets();
+    let targets = capture::all_targets();
     
     // Filter to only get windows
     let windows: Vec<_> = targets.iter()
@@ -323,169 +1426,76 @@ async fn capture_window(window_index: usize, output_filename: Option<&str>, anal
         .collect();
     
     if window_index >= windows.len() {
-        eprintln!("Error: Window {} not found. Available windows: 0-{}",
-            window_index, windows.len().saturating_sub(1));
-        std::process::exit(1);
+        return Err(CaptestError::new(ErrorKind::TargetNotFound, format!("Window {} not found. Available windows: 0-{}",
+            window_index, windows.len().saturating_sub(1))).into());
     }
     
     let window = windows[window_index];
     let target = Target::Window(window.clone());
     
-    println!("Capturing window {} (ID: {}) - '{}'...",
+    status!("Capturing window {} (ID: {}) - '{}'...",
         window_index, window.id, window.title);
     
     // Check permissions first
-    println!("Checking scap permissions...");
+    status!("Checking scap permissions...");
     if !scap::has_permission() {
-        println!("No screen recording permission! Requesting...");
-        scap::request_permission();
-        return Err("Please grant screen recording permission in System Preferences and try again".into());
+        status!("No screen recording permission! Requesting...");
+        capture::request_permission();
+        return Err(CaptestError::new(ErrorKind::PermissionDenied, "Please grant screen recording permission in System Preferences and try again").into());
     }
-    println!("Permissions OK");
+    status!("Permissions OK");
 
     // Set up capturer options with the specific window target
-    println!("Setting up capturer options for window target...");
+    status!("Setting up capturer options for window target...");
     let options = Options {
-        fps: 1,
-        show_highlight: false,
+        fps: capture_fps(args),
+        show_highlight: config::settings().show_highlight,
         excluded_targets: None,
-        output_type: scap::frame::FrameType::BGRAFrame,
+        output_type: args.frame_type.into(),
         target: Some(target),
-        output_resolution: scap::capturer::Resolution::_1080p,
+        output_resolution: match args.preset.is_some_and(preset::Preset::native_resolution) {
+            true => scap::capturer::Resolution::Captured,
+            false => scap::capturer::Resolution::_1080p,
+        },
         ..Default::default()
     };
 
-    // Initialize capturer
-    println!("Building capturer...");
-    let mut capturer = Capturer::build(options).unwrap_or_else(|err| {
-        println!("Error building capturer: {err}");
-        process::exit(1);
-    });
-    println!("Capturer built successfully");
-
     // Generate filename only if output is specified
-    let filename = output_filename;
+    let filename = args.output_path();
 
-    capturer.start_capture();
-    println!("Scap capturer initialized successfully");
-    
-    // Try to get a frame synchronously with debug output
-    println!("Attempting to get next frame...");
-    match capturer.get_next_frame() {
-        Ok(frame) => {
-            match frame {
-                scap::frame::Frame::Video(video_frame) => {
-                    match video_frame {
-                        VideoFrame::YUVFrame(yuv_frame) => {
-                            println!(
-                                "Received YUV frame of width {} and height {} and pts {:?}",
-                                yuv_frame.width, yuv_frame.height, yuv_frame.display_time
-                            );
-                        }
-                        VideoFrame::BGR0(bgr_frame) => {
-                            println!(
-                                "Received BGR0 frame of width {} and height {}",
-                                bgr_frame.width, bgr_frame.height
-                            );
-                        }
-                        VideoFrame::RGB(rgb_frame) => {
-                            println!(
-                                "Received RGB frame of width {} and height {} and time {:?}",
-                                rgb_frame.width, rgb_frame.height, rgb_frame.display_time
-                            );
-                        }
-                        VideoFrame::RGBx(rgbx_frame) => {
-                            println!(
-                                "Received RGBx frame of width {} and height {}",
-                                rgbx_frame.width, rgbx_frame.height
-                            );
-                        }
-                        VideoFrame::XBGR(xbgr_frame) => {
-                            println!(
-                                "Received XBGR frame of width {} and height {}",
-                                xbgr_frame.width, xbgr_frame.height
-                            );
-                        }
-                        VideoFrame::BGRx(bgrx_frame) => {
-                            println!(
-                                "Received BGRx frame of width {} and height {}",
-                                bgrx_frame.width, bgrx_frame.height
-                            );
-                        }
-                        VideoFrame::BGRA(bgra_frame) => {
-                            println!(
-                                "Received BGRA frame of width {} and height {} and time {:?}",
-                                bgra_frame.width, bgra_frame.height, bgra_frame.display_time
-                            );
-                            
-                            // Convert to JPEG for both saving and LLM analysis
-                            let (width, height, rgb_data) = bgra_to_rgb8(&bgra_frame);
-                            let jpeg_bytes = match rgb8_to_jpeg_bytes(width, height, &rgb_data) {
-                                Ok(bytes) => bytes,
-                                Err(e) => {
-                                    println!("Failed to convert frame to JPEG: {}", e);
-                                    return Err(e);
-                                }
-                            };
-                            
-                            // Save JPEG if output filename was specified
-                            if let Some(ref filename) = filename {
-                                match save_jpeg_bytes(&jpeg_bytes, filename) {
-                                    Ok(_) => println!("Successfully saved window screenshot to: {}", filename),
-                                    Err(e) => println!("Failed to save window screenshot: {}", e),
-                                }
-                            } else {
-                                println!("Frame captured successfully (no output file specified, not saving)");
-                            }
-                            
-                            // Analyze with LLM if requested
-                            if analyze {
-                                let base64_image = general_purpose::STANDARD.encode(&jpeg_bytes);
-                                match analyze_image_with_llm_base64(&base64_image, prompt).await {
-                                    Ok(analysis) => println!("LLM Analysis:\n{}", analysis),
-                                    Err(e) => println!("LLM analysis failed: {}", e),
-                                }
-                            }
-                            
-                            // Extract text with OCR if requested
-                            if ocr {
-                                match extract_text_with_ocr(width, height, &rgb_data).await {
-                                    Ok(text) => println!("OCR Text Extraction:\n{}", text),
-                                    Err(e) => println!("OCR extraction failed: {}", e),
-                                }
-                            }
-                        }
-                    }
-                }
-                scap::frame::Frame::Audio(_audio_frame) => {
-                    println!("Received audio frame (unexpected for screen capture)");
-                }
-            }
-            println!("Frame captured successfully!");
-            Ok(())
-        }
-        Err(e) => {
-            println!("Frame capture failed with error: {}", e);
-            Err(format!("Frame capture failed: {}", e).into())
-        }
-    }
+    let target_info = TargetInfo {
+        kind: "window".to_string(),
+        index: window_index,
+        id: window.id,
+        title: window.title.clone(),
+    };
+
+    capture_target(FrameSource::Capturer(options), filename, args, "window screenshot", target_info, cancel).await
 }
 
-async fn capture_screen(screen_index: usize, output_filename: Option<&str>, analyze: bool, prompt: Option<&str>, ocr: bool) -> Result<(), Box<dyn std::error::Error>> {
+async fn capture_screen(screen_index: Option<usize>, args: &CaptureArgs, cancel: &CancellationToken) -> Result<CaptureReport, Box<dyn std::error::Error>> {
+    // Kiosks and embedded devices without a display server still have a framebuffer
+    #[cfg(target_os = "linux")]
+    if platforms::linux::headless() {
+        return capture_framebuffer(screen_index.unwrap_or(0), args, cancel).await;
+    }
+
     // Check if screen capture is supported
     if !scap::is_supported() {
-        println!("Screen capture not supported");
-        return Ok(());
+        return Err(CaptestError::new(ErrorKind::Unsupported, "Screen capture not supported").into());
     }
 
     // Request permission if not already granted
     if !scap::has_permission() {
-        scap::request_permission();
-        println!("Please grant screen recording permission and rerun.");
-        return Ok(());
+        capture::request_permission();
+        return Err(CaptestError::new(ErrorKind::PermissionDenied, "Please grant screen recording permission and rerun.").into());
     }
 
-    let targets = scap::get_all_targets();
+    // A named region picks its screen unless one was given
+    let region = args.region.as_ref().map(change::RegionArg::resolve).transpose()?;
+    let screen_index = screen_index.or(region.and_then(|region| region.display)).unwrap_or(0);
+
+    let targets = capture::all_targets();
     
     // Filter to only get displays (screens)
     let displays: Vec<_> = targets.iter()
@@ -499,155 +1509,680 @@ async fn capture_screen(screen_index: usize, output_filename: Option<&str>, anal
         .collect();
     
     if screen_index >= displays.len() {
-        eprintln!("Error: Screen {} not found. Available screens: 0-{}", 
-            screen_index, displays.len().saturating_sub(1));
-        std::process::exit(1);
+        return Err(CaptestError::new(ErrorKind::TargetNotFound, format!("Screen {} not found. Available screens: 0-{}",
+            screen_index, displays.len().saturating_sub(1))).into());
     }
     
     let display = displays[screen_index];
     let target = Target::Display(display.clone());
     
-    println!("Capturing screen {} (ID: {})...", 
+    status!("Capturing screen {} (ID: {})...", 
         screen_index, display.id);
     
     // Check permissions first
-    println!("Checking scap permissions...");
+    status!("Checking scap permissions...");
     if !scap::has_permission() {
-        println!("No screen recording permission! Requesting...");
-        scap::request_permission();
-        return Err("Please grant screen recording permission in System Preferences and try again".into());
+        status!("No screen recording permission! Requesting...");
+        capture::request_permission();
+        return Err(CaptestError::new(ErrorKind::PermissionDenied, "Please grant screen recording permission in System Preferences and try again").into());
     }
-    println!("Permissions OK");
+    status!("Permissions OK");
 
     // Set up capturer options for the specified screen
-    println!("Setting up capturer options for screen {}...", screen_index);
-    let options = Options {
-        fps: 1,
-        show_highlight: false,
-        output_type: scap::frame::FrameType::BGRAFrame,
+    status!("Setting up capturer options for screen {}...", screen_index);
+    let mut options = Options {
+        fps: capture_fps(args),
+        show_highlight: config::settings().show_highlight,
+        output_type: args.frame_type.into(),
         target: Some(target),
         ..Default::default()
     };
-
-    // Initialize capturer
-    println!("Building capturer...");
-    let mut capturer = Capturer::build(options).unwrap_or_else(|err| {
-        println!("Error building capturer: {err}");
-        process::exit(1);
-    });
-    println!("Capturer built successfully");
+    privacy::exclude_sensitive(&mut options)?;
 
     // Generate filename only if output is specified
-    let filename = output_filename.map(|name| {
-        if name.ends_with(".png") {
-            name.to_string()
-        } else {
-            format!("{}.png", name)
+    let filename = args.output_path();
+
+    let target_info = TargetInfo {
+        kind: "screen".to_string(),
+        index: screen_index,
+        id: display.id,
+        title: display.title.clone(),
+    };
+
+    capture_target(FrameSource::Capturer(options), filename, args, "screenshot", target_info, cancel).await
+}
+
+/// Capture screen `index` from `/dev/fb<index>`, for Linux systems without
+/// X11 or Wayland.
+#[cfg(target_os = "linux")]
+async fn capture_framebuffer(index: usize, args: &CaptureArgs, cancel: &CancellationToken) -> Result<CaptureReport, Box<dyn std::error::Error>> {
+    let device = format!("/dev/fb{}", index);
+    if !std::path::Path::new(&device).exists() {
+        return Err(CaptestError::new(ErrorKind::Unsupported, format!(
+            "No X11 or Wayland session (DISPLAY and WAYLAND_DISPLAY are unset) and no framebuffer at {}", device)).into());
+    }
+    status!("No display server, capturing screen {} from {}...", index, device);
+
+    let filename = args.output_path();
+    let target_info = TargetInfo {
+        kind: "screen".to_string(),
+        index,
+        id: index as u32,
+        title: device,
+    };
+    capture_target(FrameSource::Framebuffer(index), filename, args, "screenshot", target_info, cancel).await
+}
+
+fn capture_fps(args: &CaptureArgs) -> u32 {
+    if let Some(fps) = args.fps {
+        return fps.max(1);
+    }
+
+    // A delayed capture keeps the stream running while it waits, so pull frames
+    // often enough that the one taken at the deadline is actually current.
+    // Averaging and skipping also want consecutive frames without a one
+    // second gap each.
+    if args.delay.is_some() || args.average > 1 || skip_frames(args) > 0 { 30 } else { 1 }
+}
+
+/// Warmup frames to discard. A delayed capture drains the stream until its
+/// deadline anyway, so it skips none.
+fn skip_frames(args: &CaptureArgs) -> usize {
+    match args.delay {
+        Some(_) => 0,
+        None => args.skip_frames.unwrap_or_else(capture::default_skip_frames),
+    }
+}
+
+async fn grab_frame(session: &mut AsyncCaptureSession, args: &CaptureArgs) -> Result<Frame, Box<dyn std::error::Error>> {
+    let skip = skip_frames(args);
+    if skip > 0 {
+        status!("Skipping {} warmup frame(s)...", skip);
+    }
+    let mut skipped = 0;
+    while skipped < skip {
+        if let Frame::Video(_) = session.next_frame().await? {
+            skipped += 1;
         }
-    });
+    }
+    let frame = wait_for_frame(session, args.delay).await?;
+    if let Some(path) = &args.dump_raw {
+        raw_dump::dump_raw_frame(&frame, path)?;
+    }
+    if args.average <= 1 {
+        return Ok(frame);
+    }
+    average_frames(session, frame, args.average).await
+}
 
-    capturer.start_capture();
-    println!("Scap capturer initialized successfully");
-    
-    // Try to get a frame synchronously with debug output
-    println!("Attempting to get next frame...");
-    match capturer.get_next_frame() {
+/// Start a capturer and grab a frame, starting over up to `--retries` times
+/// when the backend fails transiently. Errors that another attempt can't fix,
+/// such as missing permission or an unsupported system, are returned at once.
+async fn grab_with_retries(options: &Options, args: &CaptureArgs, cancel: &CancellationToken) -> Result<Frame, Box<dyn std::error::Error>> {
+    let mut attempt = 0;
+    loop {
+        let grabbed = match AsyncCaptureSession::start(options.clone(), cancel).await {
+            Ok(mut session) => {
+                status!("Attempting to get next frame...");
+                grab_frame(&mut session, args).await
+            }
+            Err(e) => Err(e.into()),
+        };
+        let e = match grabbed {
+            Ok(frame) => return Ok(frame),
+            Err(e) => e,
+        };
+        let transient = matches!(error::error_kind(&*e), ErrorKind::Failure | ErrorKind::CaptureTimeout);
+        if !transient || attempt >= args.retries {
+            return Err(e);
+        }
+        attempt += 1;
+        status!("Capture failed ({}), retrying in {} ms ({}/{})", e, args.retry_delay, attempt, args.retries);
+        tokio::select! {
+            _ = cancel.cancelled() => return Err(CaptestError::new(ErrorKind::Cancelled, "Capture cancelled").into()),
+            _ = tokio::time::sleep(Duration::from_millis(args.retry_delay)) => {}
+        }
+    }
+}
+
+/// Windows Graphics Capture can't capture some console and legacy Win32
+/// windows: it fails or delivers frames of a single color. Grab such a
+/// window with GDI instead; other results are passed through.
+#[cfg(target_os = "windows")]
+fn gdi_fallback(grabbed: Result<Frame, Box<dyn std::error::Error>>, target: &TargetInfo) -> Result<Frame, Box<dyn std::error::Error>> {
+    if target.kind != "window" {
+        return grabbed;
+    }
+    let reason = match &grabbed {
+        Ok(Frame::Video(VideoFrame::BGRA(frame))) if frame.width <= 0 || frame.height <= 0 || frame.data.len() < 4 => "returned an empty frame".to_string(),
+        Ok(Frame::Video(VideoFrame::BGRA(frame))) if frame.data.chunks_exact(4).all(|pixel| pixel[..3] == frame.data[..3]) => {
+            "returned a frame of a single color".to_string()
+        }
+        Ok(_) => return grabbed,
+        Err(e) if matches!(error::error_kind(&**e), ErrorKind::Failure | ErrorKind::CaptureTimeout) => format!("failed ({})", e),
+        Err(_) => return grabbed,
+    };
+
+    status!("Windows Graphics Capture {}, falling back to GDI", reason);
+    match platforms::capture_window_gdi(target.id) {
+        Ok((width, height, data)) => Ok(Frame::Video(VideoFrame::BGRA(scap::frame::BGRAFrame {
+            display_time: SystemTime::now(),
+            width: width as i32,
+            height: height as i32,
+            data,
+        }))),
+        Err(e) => {
+            status!("GDI capture failed too: {}", e);
+            grabbed
+        }
+    }
+}
+
+async fn wait_for_frame(session: &mut AsyncCaptureSession, delay: Option<f64>) -> Result<Frame, CaptestError> {
+    let Some(delay) = delay else {
+        return session.next_frame().await;
+    };
+
+    status!("Waiting {:.1}s before grabbing frame...", delay);
+    let deadline = Instant::now() + Duration::from_secs_f64(delay.max(0.0));
+
+    // Keep draining the stream until the deadline so the returned frame is the
+    // one delivered at that moment rather than a stale buffered one
+    let pb = progress::bar((delay.max(0.0) * 10.0).ceil() as u64, "Waiting");
+    loop {
+        let frame = session.next_frame().await;
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        pb.set_position(pb.length().unwrap_or(0).saturating_sub((remaining.as_secs_f64() * 10.0).ceil() as u64));
+        if frame.is_err() || remaining.is_zero() {
+            pb.finish_and_clear();
+            return frame;
+        }
+    }
+}
+
+async fn average_frames(session: &mut AsyncCaptureSession, first: Frame, count: usize) -> Result<Frame, Box<dyn std::error::Error>> {
+    let Frame::Video(VideoFrame::BGRA(first)) = first else {
+        return Err("Frame averaging requires BGRA frames".into());
+    };
+
+    let frame_bytes = first.data.len() as u64;
+    let sums_bytes = first.width.max(0) as u64 * first.height.max(0) as u64 * memory::AVERAGE_BYTES_PER_PIXEL;
+    if !memory::fits(frame_bytes * 2 + sums_bytes, &format!("Averaging {}x{} frames", first.width, first.height))? {
+        status!("Skipping averaging, using a single frame");
+        return Ok(Frame::Video(VideoFrame::BGRA(first)));
+    }
+
+    status!("Averaging {} frames...", count);
+
+    // Accumulate per-channel sums, then divide with rounding
+    let mut sums: Vec<u32> = first.data.iter().map(|&b| b as u32).collect();
+    let mut averaged: u32 = 1;
+    let pb = progress::bar(count as u64, "Averaging frames");
+    pb.inc(1);
+    while (averaged as usize) < count {
+        match session.next_frame().await? {
+            Frame::Video(VideoFrame::BGRA(frame)) => {
+                if frame.width != first.width || frame.height != first.height || frame.data.len() != sums.len() {
+                    return Err("Frame size changed while averaging".into());
+                }
+                for (sum, &b) in sums.iter_mut().zip(&frame.data) {
+                    *sum += b as u32;
+                }
+                averaged += 1;
+                pb.inc(1);
+            }
+            // Skip anything that isn't a BGRA video frame (e.g. audio)
+            _ => continue,
+        }
+    }
+    pb.finish_and_clear();
+
+    let data = sums.iter().map(|&sum| ((sum + averaged / 2) / averaged) as u8).collect();
+    Ok(Frame::Video(VideoFrame::BGRA(scap::frame::BGRAFrame { data, ..first })))
+}
+
+/// Where `capture_target` gets its frame from.
+enum FrameSource {
+    /// A scap capturer for these options
+    Capturer(Options),
+    /// The Linux framebuffer device `/dev/fb<N>`
+    #[cfg(target_os = "linux")]
+    Framebuffer(usize),
+}
+
+async fn capture_target(source: FrameSource, filename: Option<String>, args: &CaptureArgs, label: &str, target: TargetInfo, cancel: &CancellationToken) -> Result<CaptureReport, Box<dyn std::error::Error>> {
+    if args.edit && !cfg!(feature = "editor") {
+        return Err(CaptestError::new(ErrorKind::Unsupported, "This build has no annotation editor (feature `editor`)").into());
+    }
+    let started = Instant::now();
+    let mut report = CaptureReport {
+        status: "ok".to_string(),
+        target,
+        ..Default::default()
+    };
+
+    // The session is dropped (and the backend stopped) as soon as the frame is
+    // in hand, before the slower encode/OCR/LLM steps run
+    let (options, grabbed) = match source {
+        FrameSource::Capturer(options) => {
+            let grabbed = grab_with_retries(&options, args, cancel).await;
+            (Some(options), grabbed)
+        }
+        #[cfg(target_os = "linux")]
+        FrameSource::Framebuffer(index) => {
+            let grabbed = tokio::task::block_in_place(|| platforms::linux::capture_framebuffer(index)).map(|(width, height, data)| {
+                Frame::Video(VideoFrame::BGRA(scap::frame::BGRAFrame { display_time: SystemTime::now(), width: width as i32, height: height as i32, data }))
+            });
+            (None, grabbed.map_err(Into::into))
+        }
+    };
+    #[cfg(target_os = "windows")]
+    let grabbed = gdi_fallback(grabbed, &report.target);
+
+    match grabbed {
         Ok(frame) => {
+            let (received_at, received) = (SystemTime::now(), Instant::now());
+            report.timing.capture_ms = started.elapsed().as_millis();
             match frame {
-                scap::frame::Frame::Video(video_frame) => {
-                    match video_frame {
+                Frame::Video(mut video_frame) => {
+                    if let Some(options) = &options {
+                        privacy::mask_frame(options, &mut video_frame);
+                    }
+                    match &video_frame {
                         VideoFrame::YUVFrame(yuv_frame) => {
-                            println!(
+                            status!(
                                 "Received YUV frame of width {} and height {} and pts {:?}",
                                 yuv_frame.width, yuv_frame.height, yuv_frame.display_time
                             );
                         }
                         VideoFrame::BGR0(bgr_frame) => {
-                            println!(
+                            status!(
                                 "Received BGR0 frame of width {} and height {}",
                                 bgr_frame.width, bgr_frame.height
                             );
                         }
                         VideoFrame::RGB(rgb_frame) => {
-                            println!(
+                            status!(
                                 "Received RGB frame of width {} and height {} and time {:?}",
                                 rgb_frame.width, rgb_frame.height, rgb_frame.display_time
                             );
                         }
                         VideoFrame::RGBx(rgbx_frame) => {
-                            println!(
+                            status!(
                                 "Received RGBx frame of width {} and height {}",
                                 rgbx_frame.width, rgbx_frame.height
                             );
                         }
                         VideoFrame::XBGR(xbgr_frame) => {
-                            println!(
+                            status!(
                                 "Received XBGR frame of width {} and height {}",
                                 xbgr_frame.width, xbgr_frame.height
                             );
                         }
                         VideoFrame::BGRx(bgrx_frame) => {
-                            println!(
+                            status!(
                                 "Received BGRx frame of width {} and height {}",
                                 bgrx_frame.width, bgrx_frame.height
                             );
                         }
                         VideoFrame::BGRA(bgra_frame) => {
-                            println!(
+                            status!(
                                 "Received BGRA frame of width {} and height {} and time {:?}",
                                 bgra_frame.width, bgra_frame.height, bgra_frame.display_time
                             );
-                            
-                            // Convert to JPEG for both saving and LLM analysis
-                            let (width, height, rgb_data) = bgra_to_rgb8(&bgra_frame);
-                            let jpeg_bytes = match rgb8_to_jpeg_bytes(width, height, &rgb_data) {
-                                Ok(bytes) => bytes,
-                                Err(e) => {
-                                    println!("Failed to convert frame to JPEG: {}", e);
-                                    return Err(e);
-                                }
-                            };
-                            
-                            // Save JPEG if output filename was specified
-                            if let Some(ref filename) = filename {
-                                match save_jpeg_bytes(&jpeg_bytes, filename) {
-                                    Ok(_) => println!("Successfully saved screenshot to: {}", filename),
-                                    Err(e) => println!("Failed to save screenshot: {}", e),
-                                }
-                            } else {
-                                println!("Frame captured successfully (no output file specified, not saving)");
-                            }
-                            
-                            // Analyze with LLM if requested
-                            if analyze {
-                                let base64_image = general_purpose::STANDARD.encode(&jpeg_bytes);
-                                match analyze_image_with_llm_base64(&base64_image, prompt).await {
-                                    Ok(analysis) => println!("LLM Analysis:\n{}", analysis),
-                                    Err(e) => println!("LLM analysis failed: {}", e),
-                                }
-                            }
-                            
-                            // Extract text with OCR if requested
-                            if ocr {
-                                match extract_text_with_ocr(width, height, &rgb_data).await {
-                                    Ok(text) => println!("OCR Text Extraction:\n{}", text),
-                                    Err(e) => println!("OCR extraction failed: {}", e),
-                                }
-                            }
                         }
                     }
+
+                    let display_time = convert::video_frame_display_time(&video_frame);
+                    let times = timestamp::FrameTimes::new(display_time, received_at, received);
+                    let (width, height) = convert::video_frame_size(&video_frame);
+                    let frame_bytes = width as u64 * height as u64 * 4;
+                    let mut bytes_per_pixel = memory::ENCODE_BYTES_PER_PIXEL;
+                    if args.ocr {
+                        bytes_per_pixel += memory::OCR_BYTES_PER_PIXEL;
+                    }
+                    let factor = memory::downscale_factor(width, height, frame_bytes, bytes_per_pixel, "Processing")?;
+                    let (width, height, rgb_data) = convert::video_frame_into_rgb8_downscaled(video_frame, factor);
+                    let (region, ocr_region) = capture_regions(args, factor)?;
+                    let (width, height, rgb_data) = match region {
+                        Some(region) => (region.width, region.height, convert::crop_rgb8(width, height, rgb_data, region)?),
+                        None => (width, height, rgb_data),
+                    };
+                    validate_frame(width, height, &rgb_data, args, &mut report)?;
+                    // Annotate before OCR, so blurred areas stay out of the transcript too
+                    #[cfg(feature = "editor")]
+                    let rgb_data = match args.edit {
+                        true => tokio::task::block_in_place(|| editor::annotate(width, height, rgb_data))?,
+                        false => rgb_data,
+                    };
+                    if args.report_quality {
+                        let quality = quality::measure(width, height, &rgb_data);
+                        result!("Quality: {}", quality.describe());
+                        report.quality = Some(quality);
+                    }
+                    let ocr_crop = ocr_region
+                        .map(|crop| convert::crop_rgb8(width, height, rgb_data.clone(), crop).map(|data| (crop.width, crop.height, data)))
+                        .transpose()?;
+                    let mut rgb_data = rgb_data;
+                    // OCR reads the frame as captured, without the overlay
+                    let ocr_crop = match ocr_crop {
+                        None if args.timestamp_overlay && (args.ocr || !args.extract.is_empty()) => Some((width, height, rgb_data.clone())),
+                        ocr_crop => ocr_crop,
+                    };
+                    if args.timestamp_overlay {
+                        timestamp::draw_overlay(width, height, &mut rgb_data, &times);
+                    }
+                    if args.verbose {
+                        report_latency(display_time, received_at);
+                    }
+                    let origin = args.region.as_ref().and_then(|region| region.resolve().ok()).map(|region| (region.rect.x, region.rect.y));
+                    let frame = llm::ImageMapping::frame(width, height, origin.unwrap_or((0, 0)), factor);
+                    process_rgb8(frame, &rgb_data, filename.as_deref(), args, label, &mut report).await?;
+                    if args.timestamp_sidecar {
+                        write_timestamp_sidecar(&times, &mut report);
+                    }
+                    report.frame_times = Some(times);
+                    if args.ocr || !args.extract.is_empty() {
+                        let (width, height, rgb_data) = ocr_crop.unwrap_or((width, height, rgb_data));
+                        extract_capture_text(width, height, &rgb_data, args, &mut report).await;
+                    }
+                    if args.copy_text {
+                        copy_capture_text(&mut report);
+                    }
+                }
+                Frame::Audio(_audio_frame) => {
+                    status!("Received audio frame (unexpected for screen capture)");
+                }
+            }
+            status!("Frame captured successfully!");
+            report.timing.total_ms = started.elapsed().as_millis();
+            Ok(report)
+        }
+        Err(e) => {
+            status!("Frame capture failed with error: {}", e);
+            // Keep specific kinds such as Cancelled; anything else is a capture failure
+            let kind = match error::error_kind(&*e) {
+                ErrorKind::Failure => ErrorKind::CaptureTimeout,
+                kind => kind,
+            };
+            Err(CaptestError::new(kind, format!("Frame capture failed: {}", e)).into())
+        }
+    }
+}
+
+/// Sanity-check a captured frame. A black frame or black area is how capture
+/// backends blank out DRM video and secure input fields; an entirely black
+/// frame fails the capture unless `--allow-black-frames` is given. Any other
+/// frame that is one color or has no detail fails unless `--no-validate` is
+/// given.
+fn validate_frame(width: u32, height: u32, rgb_data: &[u8], args: &CaptureArgs, report: &mut CaptureReport) -> Result<(), CaptestError> {
+    let Some(black) = validate::detect_black(width, height, rgb_data) else {
+        if let Some(blank) = validate::detect_blank(rgb_data) {
+            if !args.no_validate {
+                return Err(CaptestError::new(ErrorKind::BlankFrame, format!(
+                    "The capture is {}, which usually means screen recording permission is missing or the backend \
+                     returned an empty frame; pass --no-validate to keep it anyway", blank.describe())));
+            }
+            status!("Warning: the capture is {}", blank.describe());
+            report.blank_frame = Some(blank);
+        }
+        return Ok(());
+    };
+    match black.scope {
+        "frame" if !args.allow_black_frames => {
+            return Err(CaptestError::new(ErrorKind::ProtectedContent,
+                "The captured frame is entirely black, most likely protected content (DRM video or a secure input field) \
+                 that the capture backend blanks out; pass --allow-black-frames to save it anyway"));
+        }
+        "frame" => status!("Warning: the captured frame is entirely black (protected content?)"),
+        _ => status!("Warning: a {}x{} area at {},{} is pure black and may be protected content", black.width, black.height, black.x, black.y),
+    }
+    report.black_frame = Some(black);
+    Ok(())
+}
+
+/// The `--region` crop and the `--ocr-region` crop within it, scaled to a
+/// frame downscaled by `factor`.
+fn capture_regions(args: &CaptureArgs, factor: u32) -> Result<(Option<convert::Crop>, Option<convert::Crop>), String> {
+    let scale = |rect: change::IgnoreRegion| convert::Crop {
+        x: rect.x / factor,
+        y: rect.y / factor,
+        width: (rect.width / factor).max(1),
+        height: (rect.height / factor).max(1),
+    };
+    let region = args.region.as_ref().map(change::RegionArg::resolve).transpose()?.map(|region| region.rect);
+    let ocr_region = args.ocr_region.as_ref().map(change::RegionArg::resolve).transpose()?.map(|region| region.rect);
+    let ocr_region = match (region, ocr_region) {
+        (Some(region), Some(ocr)) => {
+            // Right and bottom edges are summed in u64 so they can't wrap around
+            let edges = |rect: change::IgnoreRegion| (rect.x as u64 + rect.width as u64, rect.y as u64 + rect.height as u64);
+            let ((right, bottom), (ocr_right, ocr_bottom)) = (edges(region), edges(ocr));
+            let inside = ocr.x >= region.x && ocr.y >= region.y && ocr_right <= right && ocr_bottom <= bottom;
+            if !inside {
+                return Err("--ocr-region must lie inside --region".to_string());
+            }
+            Some(change::IgnoreRegion { x: ocr.x - region.x, y: ocr.y - region.y, ..ocr })
+        }
+        (_, ocr) => ocr,
+    };
+    Ok((region.map(scale), ocr_region.map(scale)))
+}
+
+/// Save the frame's timestamps as `<output>.json`, next to the capture.
+fn write_timestamp_sidecar(times: &timestamp::FrameTimes, report: &mut CaptureReport) {
+    let Some(output) = &report.output else {
+        status!("Not writing a timestamp sidecar, the capture wasn't saved to a file");
+        return;
+    };
+    let path = format!("{}.json", output);
+    let sidecar = json!({
+        "image": output,
+        "run_id": config::settings().run_id,
+        "target": report.target,
+        "width": report.width,
+        "height": report.height,
+        "frame_times": times,
+    });
+    let written = serde_json::to_string_pretty(&sidecar).map_err(|e| e.to_string())
+        .and_then(|text| std::fs::write(&path, text).map_err(|e| e.to_string()));
+    match written {
+        Ok(()) => status!("Saved frame timestamps to {}", path),
+        Err(e) => {
+            status!("Failed to write {}: {}", path, e);
+            report.errors.push(format!("Failed to write {}: {}", path, e));
+        }
+    }
+}
+
+fn report_latency(display_time: SystemTime, received_at: SystemTime) {
+    // Backends stamp frames with wall-clock time, so a clock adjustment can make this negative
+    let to_ms = |from: SystemTime, to: SystemTime| match to.duration_since(from) {
+        Ok(elapsed) => format!("{:.1} ms", elapsed.as_secs_f64() * 1000.0),
+        Err(_) => "n/a".to_string(),
+    };
+
+    let converted_at = SystemTime::now();
+    status!(
+        "Frame latency: backend {} + convert {} = {} end-to-end",
+        to_ms(display_time, received_at),
+        to_ms(received_at, converted_at),
+        to_ms(display_time, converted_at)
+    );
+}
+
+/// Save, deliver and analyze a captured frame. `frame` gives its size and
+/// where its pixels lie on the screen.
+async fn process_rgb8(frame: llm::ImageMapping, rgb_data: &[u8], filename: Option<&str>, args: &CaptureArgs, label: &str, report: &mut CaptureReport) -> Result<(), Box<dyn std::error::Error>> {
+    let (width, height) = (frame.width, frame.height);
+    report.width = width;
+    report.height = height;
+
+    // Encode once for saving and every delivery
+    let encode_started = Instant::now();
+    let format = args.image_format();
+    let image_bytes = match encode::encode_rgb8(width, height, rgb_data, format, args.jpeg_quality()) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            status!("Failed to convert frame to {}: {}", format.extension(), e);
+            return Err(e);
+        }
+    };
+    report.timing.encode_ms = encode_started.elapsed().as_millis();
+
+    // Save the image if output filename was specified
+    if let Some(filename) = filename {
+        match save_image_bytes(&image_bytes, filename) {
+            Ok(saved) => {
+                status!("Successfully saved {} to: {}", label, saved);
+                report.output = Some(saved);
+            }
+            Err(e) => {
+                status!("Failed to save {}: {}", label, e);
+                report.errors.push(format!("Failed to save {}: {}", label, e));
+            }
+        }
+    } else {
+        status!("Frame captured successfully (no output file specified, not saving)");
+    }
+
+    if args.share {
+        share_capture(&image_bytes, filename, args, report).await;
+    }
+    for destination in &args.upload {
+        match upload::upload(&image_bytes, &upload_file_name(filename, format), destination).await {
+            Ok(upload) => {
+                status!("Uploaded to {}", upload.location);
+                report.uploads.push(upload);
+            }
+            Err(e) => {
+                status!("Upload failed: {}", e);
+                report.errors.push(format!("Upload failed: {}", e));
+                report.failure.get_or_insert(ErrorKind::UploadFailed);
+            }
+        }
+    }
+    let file_name = upload_file_name(filename, format);
+    let capture = sink::Capture { image_bytes: &image_bytes, format, file_name: &file_name };
+    for sink in &args.to {
+        match sink.write(&capture).await {
+            Ok(location) => {
+                status!("Sent to {}", location);
+                report.sinks.push(SinkReport { sink: sink.describe(), location });
+            }
+            Err(e) => {
+                status!("Sending to {} failed: {}", sink.describe(), e);
+                report.errors.push(format!("Sending to {} failed: {}", sink.describe(), e));
+                report.failure.get_or_insert(ErrorKind::UploadFailed);
+            }
+        }
+    }
+
+    // Analyze with LLM if requested
+    if args.analyze {
+        let analysis_started = Instant::now();
+        let cache_ttl = (!args.no_cache).then(|| Duration::from_secs(args.cache_ttl));
+        // The LLM always gets a JPEG
+        let jpeg_bytes = match format {
+            encode::ImageFormat::Jpeg => std::borrow::Cow::Borrowed(&image_bytes),
+            _ => std::borrow::Cow::Owned(rgb8_to_jpeg_bytes(width, height, rgb_data)?),
+        };
+        let analysis = analyze_frame(rgb_data, &jpeg_bytes, frame, args.prompt.as_deref(), cache_ttl, args.llm_full_resolution, args.no_tiles).await;
+        match analysis {
+            Ok(analysis) => {
+                result!("LLM Analysis:\n{}", analysis.text);
+                if args.verbose && let Some(usage) = &analysis.usage {
+                    status!("Token usage: {}", usage);
                 }
-                scap::frame::Frame::Audio(_audio_frame) => {
-                    println!("Received audio frame (unexpected for screen capture)");
+                report.analysis = Some(analysis.text);
+                report.raw_analysis = analysis.raw_analysis;
+                report.llm_image = analysis.image;
+                report.llm_tiles = analysis.tiles;
+                report.usage = analysis.usage;
+            }
+            Err(e) => {
+                status!("LLM analysis failed: {}", e);
+                report.errors.push(format!("LLM analysis failed: {}", e));
+                report.failure.get_or_insert(ErrorKind::LlmFailed);
+            }
+        }
+        report.timing.analysis_ms = Some(analysis_started.elapsed().as_millis());
+    }
+
+    Ok(())
+}
+
+/// Upload the image for `--share`, print the URL and copy it if asked.
+async fn share_capture(image_bytes: &[u8], filename: Option<&str>, args: &CaptureArgs, report: &mut CaptureReport) {
+    let expires_hours = args.share_expires.or(config::settings().share.expires_hours);
+    match upload::share(image_bytes, &upload_file_name(filename, args.image_format()), expires_hours).await {
+        Ok(share) => {
+            result!("{}", share.url);
+            if args.copy_url || config::settings().share.copy_url {
+                match clipboard::copy_text(&share.url) {
+                    Ok(()) => status!("Copied the URL to the clipboard"),
+                    Err(e) => report.errors.push(format!("Failed to copy the URL: {}", e)),
                 }
             }
-            println!("Frame captured successfully!");
-            Ok(())
+            report.share = Some(share);
         }
         Err(e) => {
-            println!("Frame capture failed with error: {}", e);
-            Err(format!("Frame capture failed: {}", e).into())
+            status!("Upload failed: {}", e);
+            report.errors.push(format!("Upload failed: {}", e));
+            report.failure.get_or_insert(ErrorKind::UploadFailed);
         }
     }
 }
 
+/// Name an uploaded capture gets: the saved file's name, or `capture.jpg`
+/// (with the extension of `format`).
+fn upload_file_name(filename: Option<&str>, format: encode::ImageFormat) -> String {
+    filename
+        .and_then(|filename| std::path::Path::new(filename).file_name())
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| format!("capture.{}", format.extension()))
+}
+
+/// Put the OCR transcript, then the analysis, on the clipboard for `--copy-text`.
+fn copy_capture_text(report: &mut CaptureReport) {
+    let text: Vec<&str> = [report.ocr.as_ref().map(|ocr| ocr.text.as_str()), report.analysis.as_deref()]
+        .into_iter()
+        .flatten()
+        .filter(|text| !text.is_empty())
+        .collect();
+    if text.is_empty() {
+        report.errors.push("Nothing to copy: --copy-text needs text from --ocr or --analyze".to_string());
+        return;
+    }
+    match clipboard::copy_text(&text.join("\n\n")) {
+        Ok(()) => status!("Copied the text to the clipboard"),
+        Err(e) => report.errors.push(format!("Failed to copy the text: {}", e)),
+    }
+}
+
+/// Extract text with OCR from the frame, or the `--ocr-region` part of it.
+async fn extract_capture_text(width: u32, height: u32, rgb_data: &[u8], args: &CaptureArgs, report: &mut CaptureReport) {
+    let ocr_started = Instant::now();
+    match ocr::extract_lines(width, height, rgb_data, !args.no_cache).await {
+        Ok(lines) => {
+            let format = args.ocr_format.unwrap_or_default();
+            let formatted = (format != ocr::OcrFormat::Text).then(|| ocr::format_lines(lines.clone(), width, height, format));
+            let ocr = OcrReport::from_lines(lines);
+            if !args.extract.is_empty() {
+                report.extracted = Some(extract::extract(&args.extract, &ocr.text));
+            } else if let Some(formatted) = formatted {
+                result!("{}", formatted);
+            } else if ocr.lines.is_empty() {
+                result!("OCR Text Extraction:\nNo text detected in the image.");
+            } else {
+                result!("OCR Text Extraction:\n{}", ocr.text);
+            }
+            report.ocr = Some(ocr);
+        }
+        Err(e) => {
+            status!("OCR extraction failed: {}", e);
+            report.errors.push(format!("OCR extraction failed: {}", e));
+            report.failure.get_or_insert(ErrorKind::OcrFailed);
+        }
+    }
+    report.timing.ocr_ms = Some(ocr_started.elapsed().as_millis());
+}
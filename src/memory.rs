@@ -0,0 +1,63 @@
+use clap::ValueEnum;
+
+use crate::config;
+use crate::error::{CaptestError, ErrorKind};
+
+/// Working memory per pixel for JPEG encoding (output and encoder buffers).
+pub const ENCODE_BYTES_PER_PIXEL: u64 = 1;
+/// Working memory per pixel for OCR, dominated by its f32 image tensors.
+pub const OCR_BYTES_PER_PIXEL: u64 = 16;
+/// Working memory per pixel for frame averaging: a u32 sum per BGRA channel.
+pub const AVERAGE_BYTES_PER_PIXEL: u64 = 16;
+
+/// What to do when a capture would need more memory than `--max-memory`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OnMemoryLimit {
+    /// Shrink the frame (or skip averaging) until it fits
+    #[default]
+    Downscale,
+    /// Fail with the memory_limit exit code
+    Error,
+}
+
+/// Whether `bytes` of working memory for `what` fit the configured limit.
+/// Over the limit this is an error with `--on-memory-limit error`, and false
+/// otherwise so the caller can degrade.
+pub fn fits(bytes: u64, what: &str) -> Result<bool, CaptestError> {
+    let Some(limit_mb) = config::settings().max_memory_mb else {
+        return Ok(true);
+    };
+    if bytes <= limit_mb.saturating_mul(1 << 20) {
+        return Ok(true);
+    }
+
+    let message = format!("{} needs about {} MB, over the {} MB limit (--max-memory)", what, bytes.div_ceil(1 << 20), limit_mb);
+    match config::settings().on_memory_limit {
+        OnMemoryLimit::Error => Err(CaptestError::new(ErrorKind::MemoryLimit, message)),
+        OnMemoryLimit::Downscale => {
+            status!("{}", message);
+            Ok(false)
+        }
+    }
+}
+
+/// How much to shrink each dimension of a `width` x `height` frame so that
+/// `bytes_per_pixel` of working memory per remaining pixel, on top of the
+/// `frame_bytes` the frame already occupies, fits the limit. 1 means as is.
+pub fn downscale_factor(width: u32, height: u32, frame_bytes: u64, bytes_per_pixel: u64, what: &str) -> Result<u32, CaptestError> {
+    let needed = |factor: u32| frame_bytes + (width / factor) as u64 * (height / factor) as u64 * bytes_per_pixel;
+    let what = format!("{} a {}x{} frame", what, width, height);
+    if fits(needed(1), &what)? {
+        return Ok(1);
+    }
+
+    let limit = config::settings().max_memory_mb.unwrap_or(u64::MAX).saturating_mul(1 << 20);
+    match (2..=width.max(height).max(2)).find(|&factor| needed(factor) <= limit) {
+        Some(factor) => {
+            status!("Downscaling by {} to {}x{} to stay within the memory limit", factor, width / factor, height / factor);
+            Ok(factor)
+        }
+        None => Err(CaptestError::new(ErrorKind::MemoryLimit, format!(
+            "The captured {}x{} frame alone is over the memory limit (--max-memory)", width, height))),
+    }
+}
@@ -0,0 +1,541 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use serde::Serialize;
+
+// Matroska element IDs, with their length marker bits as they appear on disk
+const EBML: u32 = 0x1A45DFA3;
+const EBML_VERSION: u32 = 0x4286;
+const EBML_READ_VERSION: u32 = 0x42F7;
+const EBML_MAX_ID_LENGTH: u32 = 0x42F2;
+const EBML_MAX_SIZE_LENGTH: u32 = 0x42F3;
+const DOC_TYPE: u32 = 0x4282;
+const DOC_TYPE_VERSION: u32 = 0x4287;
+const DOC_TYPE_READ_VERSION: u32 = 0x4285;
+const SEGMENT: u32 = 0x18538067;
+const SEEK_HEAD: u32 = 0x114D9B74;
+const SEEK: u32 = 0x4DBB;
+const SEEK_ID: u32 = 0x53AB;
+const SEEK_POSITION: u32 = 0x53AC;
+const VOID: u32 = 0xEC;
+const INFO: u32 = 0x1549A966;
+const TIMESTAMP_SCALE: u32 = 0x2AD7B1;
+const MUXING_APP: u32 = 0x4D80;
+const WRITING_APP: u32 = 0x5741;
+const DURATION: u32 = 0x4489;
+const TRACKS: u32 = 0x1654AE6B;
+const TRACK_ENTRY: u32 = 0xAE;
+const TRACK_NUMBER: u32 = 0xD7;
+const TRACK_UID: u32 = 0x73C5;
+const TRACK_TYPE: u32 = 0x83;
+const FLAG_LACING: u32 = 0x9C;
+const CODEC_ID: u32 = 0x86;
+const VIDEO: u32 = 0xE0;
+const PIXEL_WIDTH: u32 = 0xB0;
+const PIXEL_HEIGHT: u32 = 0xBA;
+const AUDIO: u32 = 0xE1;
+const SAMPLING_FREQUENCY: u32 = 0xB5;
+const CHANNELS: u32 = 0x9F;
+const BIT_DEPTH: u32 = 0x6264;
+const CLUSTER: u32 = 0x1F43B675;
+const CLUSTER_TIMESTAMP: u32 = 0xE7;
+const SIMPLE_BLOCK: u32 = 0xA3;
+const CUES: u32 = 0x1C53BB6B;
+const CUE_POINT: u32 = 0xBB;
+const CUE_TIME: u32 = 0xB3;
+const CUE_TRACK_POSITIONS: u32 = 0xB7;
+const CUE_TRACK: u32 = 0xF7;
+const CUE_CLUSTER_POSITION: u32 = 0xF1;
+
+/// Size field meaning "unknown", so a segment cut off at any point still parses.
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+/// Space reserved after the segment header for the seek head written on finalize.
+const SEEK_HEAD_RESERVE: usize = 96;
+/// Block timestamps are 16-bit offsets from their cluster's timestamp.
+pub const MAX_CLUSTER_MS: u64 = i16::MAX as u64;
+const VIDEO_TRACK: u8 = 1;
+const AUDIO_TRACK: u8 = 2;
+
+/// Writes MJPEG frames, and optionally 32-bit float PCM audio, into a
+/// Matroska file as a series of self-contained clusters. Each cluster is
+/// written and synced to disk as a whole, and the segment has an unknown size
+/// until [`finish`](Self::finish), so the file stays playable up to the last
+/// complete cluster if the process dies.
+pub struct MkvWriter {
+    file: BufWriter<File>,
+    cluster: Vec<u8>,
+    cluster_start_ms: u64,
+}
+
+impl MkvWriter {
+    /// `audio` adds an interleaved float PCM track with this sample rate and
+    /// channel count, written with [`write_audio`](Self::write_audio).
+    pub fn create(path: &Path, width: u32, height: u32, audio: Option<(u32, u16)>) -> std::io::Result<Self> {
+        let mut file = BufWriter::new(File::create(path)?);
+
+        let mut header = Vec::new();
+        element(&mut header, EBML, &[
+            uint_element(EBML_VERSION, 1),
+            uint_element(EBML_READ_VERSION, 1),
+            uint_element(EBML_MAX_ID_LENGTH, 4),
+            uint_element(EBML_MAX_SIZE_LENGTH, 8),
+            bytes_element(DOC_TYPE, b"matroska"),
+            uint_element(DOC_TYPE_VERSION, 4),
+            uint_element(DOC_TYPE_READ_VERSION, 2),
+        ].concat());
+        write_id(&mut header, SEGMENT);
+        header.extend_from_slice(&UNKNOWN_SIZE);
+        header.extend(void(SEEK_HEAD_RESERVE));
+
+        let app = format!("captest {}", env!("CARGO_PKG_VERSION"));
+        element(&mut header, INFO, &[
+            uint_element(TIMESTAMP_SCALE, 1_000_000),
+            bytes_element(MUXING_APP, app.as_bytes()),
+            bytes_element(WRITING_APP, app.as_bytes()),
+            // Filled in on finalize
+            bytes_element(DURATION, &0f64.to_be_bytes()),
+        ].concat());
+
+        let mut video = Vec::new();
+        element(&mut video, VIDEO, &[
+            uint_element(PIXEL_WIDTH, width as u64),
+            uint_element(PIXEL_HEIGHT, height as u64),
+        ].concat());
+        let mut tracks = Vec::new();
+        element(&mut tracks, TRACK_ENTRY, &[
+            uint_element(TRACK_NUMBER, VIDEO_TRACK as u64),
+            uint_element(TRACK_UID, VIDEO_TRACK as u64),
+            uint_element(TRACK_TYPE, 1),
+            uint_element(FLAG_LACING, 0),
+            bytes_element(CODEC_ID, b"V_MJPEG"),
+            video,
+        ].concat());
+        if let Some((sample_rate, channels)) = audio {
+            let mut settings = Vec::new();
+            element(&mut settings, AUDIO, &[
+                bytes_element(SAMPLING_FREQUENCY, &(sample_rate as f64).to_be_bytes()),
+                uint_element(CHANNELS, channels as u64),
+                uint_element(BIT_DEPTH, 32),
+            ].concat());
+            element(&mut tracks, TRACK_ENTRY, &[
+                uint_element(TRACK_NUMBER, AUDIO_TRACK as u64),
+                uint_element(TRACK_UID, AUDIO_TRACK as u64),
+                uint_element(TRACK_TYPE, 2),
+                uint_element(FLAG_LACING, 0),
+                bytes_element(CODEC_ID, b"A_PCM/FLOAT/IEEE"),
+                settings,
+            ].concat());
+        }
+        element(&mut header, TRACKS, &tracks);
+
+        file.write_all(&header)?;
+        file.flush()?;
+        Ok(Self { file, cluster: Vec::new(), cluster_start_ms: 0 })
+    }
+
+    /// Add a JPEG frame shown at `timestamp_ms` since the start of the recording.
+    /// Starts a new cluster when the current one spans `cluster_ms`.
+    pub fn write_frame(&mut self, timestamp_ms: u64, jpeg: &[u8], cluster_ms: u64) -> std::io::Result<()> {
+        self.write_block(VIDEO_TRACK, timestamp_ms, jpeg, cluster_ms)
+    }
+
+    /// Add interleaved audio samples starting at `timestamp_ms`. Only valid
+    /// for writers created with an audio track.
+    pub fn write_audio(&mut self, timestamp_ms: u64, samples: &[f32], cluster_ms: u64) -> std::io::Result<()> {
+        let pcm: Vec<u8> = samples.iter().flat_map(|sample| sample.to_le_bytes()).collect();
+        self.write_block(AUDIO_TRACK, timestamp_ms, &pcm, cluster_ms)
+    }
+
+    fn write_block(&mut self, track: u8, timestamp_ms: u64, data: &[u8], cluster_ms: u64) -> std::io::Result<()> {
+        let cluster_ms = cluster_ms.clamp(1, MAX_CLUSTER_MS);
+        if !self.cluster.is_empty() && timestamp_ms.saturating_sub(self.cluster_start_ms) >= cluster_ms {
+            self.flush_cluster()?;
+        }
+        if self.cluster.is_empty() {
+            self.cluster_start_ms = timestamp_ms;
+            self.cluster = uint_element(CLUSTER_TIMESTAMP, timestamp_ms);
+        }
+
+        // Track number, offset from the cluster timestamp, keyframe flag. Audio
+        // can arrive slightly out of order with the video, never before the cluster
+        let offset = timestamp_ms.saturating_sub(self.cluster_start_ms) as i16;
+        let mut block = vec![0x80 | track];
+        block.extend_from_slice(&offset.to_be_bytes());
+        block.push(0x80);
+        block.extend_from_slice(data);
+        element(&mut self.cluster, SIMPLE_BLOCK, &block);
+        Ok(())
+    }
+
+    /// Write the pending cluster and make sure it reached the disk.
+    pub fn flush_cluster(&mut self) -> std::io::Result<()> {
+        if self.cluster.is_empty() {
+            return Ok(());
+        }
+        let mut cluster = Vec::with_capacity(self.cluster.len() + 12);
+        element(&mut cluster, CLUSTER, &self.cluster);
+        self.cluster.clear();
+        self.file.write_all(&cluster)?;
+        self.file.flush()?;
+        self.file.get_ref().sync_data()
+    }
+
+    /// Write the last cluster and finalize the file with a seek index and duration.
+    pub fn finish(mut self, path: &Path) -> Result<Recovery, Box<dyn std::error::Error>> {
+        self.flush_cluster()?;
+        drop(self.file);
+        finalize(path)
+    }
+}
+
+/// What [`finalize`] found and fixed.
+#[derive(Serialize, Debug)]
+pub struct Recovery {
+    pub clusters: usize,
+    pub frames: usize,
+    pub duration_ms: u64,
+    /// Bytes of incomplete data cut from the end
+    pub truncated_bytes: u64,
+    /// False when the file already had its index
+    pub finalized: bool,
+}
+
+/// Make a recording seekable: drop any incomplete trailing cluster, append
+/// cues, and fill in the seek head, duration and segment size. Used both when
+/// a recording ends normally and by `recover` after a crash.
+pub fn finalize(path: &Path) -> Result<Recovery, Box<dyn std::error::Error>> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+    let mut reader = Reader::new(&mut file, len);
+
+    let (id, size) = reader.header()?;
+    if id != EBML {
+        return Err(format!("{} is not a Matroska file", path.display()).into());
+    }
+    reader.skip(size)?;
+    let (id, segment_size) = reader.header()?;
+    if id != SEGMENT {
+        return Err(format!("{} has no Matroska segment", path.display()).into());
+    }
+    let segment_size_pos = reader.pos - 8;
+    let segment_start = reader.pos;
+
+    let mut positions = Vec::new();
+    let mut void_pos = None;
+    let mut duration_pos = None;
+    let mut cues = Vec::new();
+    let mut frames = 0;
+    let mut first_ms = None;
+    let mut end_ms = 0;
+    let mut end = segment_start;
+
+    while end < len {
+        let Ok((id, size)) = reader.header() else {
+            break;
+        };
+        let data_pos = reader.pos;
+        if data_pos.checked_add(size).is_none_or(|data_end| data_end > len) {
+            break;
+        }
+        match id {
+            VOID if void_pos.is_none() => void_pos = Some((end, data_pos + size - end)),
+            INFO => {
+                let Ok(pos) = reader.find_child(data_pos + size, DURATION) else {
+                    break;
+                };
+                duration_pos = pos;
+            }
+            CLUSTER => {
+                // A damaged cluster is cut off like an incomplete one
+                let Ok((timestamp, blocks, last_offset)) = reader.cluster(data_pos + size) else {
+                    break;
+                };
+                cues.push((timestamp, end - segment_start));
+                if blocks > 0 {
+                    frames += blocks;
+                    first_ms.get_or_insert(timestamp);
+                    end_ms = timestamp + last_offset;
+                }
+            }
+            SEEK_HEAD | TRACKS | CUES | VOID => {}
+            // Anything else means the file is damaged from here on
+            _ => break,
+        }
+        positions.push((id, end - segment_start));
+        reader.seek(data_pos + size)?;
+        end = data_pos + size;
+    }
+
+    // The last frame is shown for about as long as the ones before it
+    let frame_ms = match first_ms {
+        Some(first_ms) if frames > 1 => (end_ms - first_ms) / (frames as u64 - 1),
+        _ => 0,
+    };
+    let duration_ms = end_ms + frame_ms;
+
+    // A file that already has cues and a known size has been finalized
+    if segment_size != u64::MAX && positions.iter().any(|(id, _)| *id == CUES) {
+        return Ok(Recovery { clusters: cues.len(), frames, duration_ms, truncated_bytes: 0, finalized: false });
+    }
+
+    let truncated_bytes = len - end;
+    file.set_len(end)?;
+
+    let mut cue_points = Vec::new();
+    for (timestamp, position) in &cues {
+        let mut track_positions = Vec::new();
+        element(&mut track_positions, CUE_TRACK_POSITIONS, &[
+            uint_element(CUE_TRACK, 1),
+            uint_element(CUE_CLUSTER_POSITION, *position),
+        ].concat());
+        element(&mut cue_points, CUE_POINT, &[uint_element(CUE_TIME, *timestamp), track_positions].concat());
+    }
+    let mut cues_element = Vec::new();
+    element(&mut cues_element, CUES, &cue_points);
+    file.seek(SeekFrom::Start(end))?;
+    file.write_all(&cues_element)?;
+    let cues_pos = end - segment_start;
+
+    if let Some(pos) = duration_pos {
+        file.seek(SeekFrom::Start(pos))?;
+        file.write_all(&(duration_ms as f64).to_be_bytes())?;
+    }
+
+    if let Some((pos, void_len)) = void_pos {
+        let mut seeks = Vec::new();
+        for (id, position) in positions.iter().filter(|(id, _)| matches!(*id, INFO | TRACKS)).chain([&(CUES, cues_pos)]) {
+            let mut seek_id = Vec::new();
+            write_id(&mut seek_id, *id);
+            element(&mut seeks, SEEK, &[bytes_element(SEEK_ID, &seek_id), uint_element(SEEK_POSITION, *position)].concat());
+        }
+        let mut seek_head = Vec::new();
+        element(&mut seek_head, SEEK_HEAD, &seeks);
+        if let Some(padding) = (void_len as usize).checked_sub(seek_head.len())
+            && padding != 1
+        {
+            if padding > 0 {
+                seek_head.extend(void(padding));
+            }
+            file.seek(SeekFrom::Start(pos))?;
+            file.write_all(&seek_head)?;
+        }
+    }
+
+    let segment_size = end + cues_element.len() as u64 - segment_start;
+    file.seek(SeekFrom::Start(segment_size_pos))?;
+    file.write_all(&(segment_size | 0x0100_0000_0000_0000).to_be_bytes())?;
+    file.sync_all()?;
+
+    Ok(Recovery { clusters: cues.len(), frames, duration_ms, truncated_bytes, finalized: true })
+}
+
+/// Minimal EBML reader over the parts of the file `finalize` needs.
+struct Reader<'a> {
+    file: &'a mut File,
+    len: u64,
+    pos: u64,
+}
+
+impl<'a> Reader<'a> {
+    fn new(file: &'a mut File, len: u64) -> Self {
+        Self { file, len, pos: 0 }
+    }
+
+    fn seek(&mut self, pos: u64) -> std::io::Result<()> {
+        self.file.seek(SeekFrom::Start(pos))?;
+        self.pos = pos;
+        Ok(())
+    }
+
+    fn skip(&mut self, size: u64) -> std::io::Result<()> {
+        let pos = self.pos.checked_add(size).ok_or(std::io::ErrorKind::InvalidData)?;
+        self.seek(pos)
+    }
+
+    fn byte(&mut self) -> std::io::Result<u8> {
+        if self.pos >= self.len {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+        let mut byte = [0];
+        self.file.read_exact(&mut byte)?;
+        self.pos += 1;
+        Ok(byte[0])
+    }
+
+    /// A variable-length integer; with `keep_marker` the length bits are kept (IDs).
+    fn vint(&mut self, keep_marker: bool) -> std::io::Result<(u64, usize)> {
+        let first = self.byte()?;
+        let len = first.leading_zeros() as usize + 1;
+        if len > 8 {
+            return Err(std::io::ErrorKind::InvalidData.into());
+        }
+        let mut value = if keep_marker { first as u64 } else { (first as u64) & (0xFF >> len) };
+        for _ in 1..len {
+            value = (value << 8) | self.byte()? as u64;
+        }
+        Ok((value, len))
+    }
+
+    /// Element ID and data size; u64::MAX stands for an unknown size.
+    fn header(&mut self) -> std::io::Result<(u32, u64)> {
+        let (id, _) = self.vint(true)?;
+        let (size, len) = self.vint(false)?;
+        let unknown = (1u64 << (7 * len)) - 1;
+        Ok((id as u32, if size == unknown { u64::MAX } else { size }))
+    }
+
+    fn uint(&mut self, size: u64) -> std::io::Result<u64> {
+        let mut value = 0;
+        for _ in 0..size.min(8) {
+            value = (value << 8) | self.byte()? as u64;
+        }
+        Ok(value)
+    }
+
+    /// Position of the data of the first `id` child before `end`.
+    fn find_child(&mut self, end: u64, id: u32) -> std::io::Result<Option<u64>> {
+        while self.pos < end {
+            let (child, size) = self.header()?;
+            if child == id {
+                return Ok(Some(self.pos));
+            }
+            self.skip(size)?;
+        }
+        Ok(None)
+    }
+
+    /// Cluster timestamp, number of video blocks and the last one's offset.
+    /// A child of unknown size, or one running past the cluster, is an error.
+    fn cluster(&mut self, end: u64) -> std::io::Result<(u64, usize, u64)> {
+        let (mut timestamp, mut blocks, mut last_offset) = (0, 0, 0);
+        while self.pos < end {
+            let (child, size) = self.header()?;
+            let data_end = self.pos.checked_add(size)
+                .filter(|&data_end| data_end <= end)
+                .ok_or(std::io::ErrorKind::InvalidData)?;
+            match child {
+                CLUSTER_TIMESTAMP => timestamp = self.uint(size)?,
+                SIMPLE_BLOCK => {
+                    let (track, _) = self.vint(false)?;
+                    let offset = i16::from_be_bytes([self.byte()?, self.byte()?]);
+                    if track == VIDEO_TRACK as u64 {
+                        last_offset = offset.max(0) as u64;
+                        blocks += 1;
+                    }
+                }
+                _ => {}
+            }
+            self.seek(data_end)?;
+        }
+        Ok((timestamp, blocks, last_offset))
+    }
+}
+
+fn write_id(out: &mut Vec<u8>, id: u32) {
+    let bytes = id.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&byte| byte == 0).count();
+    out.extend_from_slice(&bytes[skip..]);
+}
+
+fn write_size(out: &mut Vec<u8>, size: u64) {
+    // Shortest encoding whose all-ones value (reserved for unknown) isn't hit
+    let len = (1..=8).find(|&len| size < (1u64 << (7 * len)) - 1).unwrap_or(8);
+    let marked = size | (1u64 << (7 * len));
+    out.extend_from_slice(&marked.to_be_bytes()[8 - len..]);
+}
+
+fn element(out: &mut Vec<u8>, id: u32, data: &[u8]) {
+    write_id(out, id);
+    write_size(out, data.len() as u64);
+    out.extend_from_slice(data);
+}
+
+fn bytes_element(id: u32, data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    element(&mut out, id, data);
+    out
+}
+
+fn uint_element(id: u32, value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take_while(|&&byte| byte == 0).count().min(7);
+    bytes_element(id, &bytes[skip..])
+}
+
+/// A Void element exactly `len` bytes long (at least 2).
+fn void(len: usize) -> Vec<u8> {
+    // One ID byte plus an 8-byte size keeps the arithmetic simple for larger voids
+    let mut out = vec![0xEC];
+    if len < 10 {
+        write_size(&mut out, (len - 2) as u64);
+    } else {
+        out.extend_from_slice(&((len - 9) as u64 | 0x0100_0000_0000_0000).to_be_bytes());
+    }
+    out.resize(len, 0);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("captest-mkv-{}-{}.mkv", std::process::id(), name))
+    }
+
+    /// A recording of `frames` one-frame clusters, left unfinalized as after a crash.
+    fn write_unfinished(path: &Path, frames: u64) {
+        let mut writer = MkvWriter::create(path, 64, 48, None).unwrap();
+        for frame in 0..frames {
+            writer.write_frame(frame * 100, b"not really a jpeg", 100).unwrap();
+        }
+        writer.flush_cluster().unwrap();
+    }
+
+    fn append(path: &Path, bytes: &[u8]) {
+        OpenOptions::new().append(true).open(path).unwrap().write_all(bytes).unwrap();
+    }
+
+    #[test]
+    fn finalize_cuts_off_a_truncated_cluster() {
+        let path = temp_path("truncated");
+        write_unfinished(&path, 3);
+        let mut partial = Vec::new();
+        element(&mut partial, CLUSTER, &[uint_element(CLUSTER_TIMESTAMP, 300), bytes_element(SIMPLE_BLOCK, &[0x81, 0, 0, 0x80, 1, 2, 3])].concat());
+        append(&path, &partial[..partial.len() - 4]);
+
+        let recovery = finalize(&path).unwrap();
+        assert_eq!(recovery.clusters, 3);
+        assert_eq!(recovery.frames, 3);
+        assert_eq!(recovery.duration_ms, 300);
+        assert_eq!(recovery.truncated_bytes, partial.len() as u64 - 4);
+        assert!(recovery.finalized);
+
+        // Finalizing again finds the cues and leaves the file alone
+        let again = finalize(&path).unwrap();
+        assert_eq!(again.frames, 3);
+        assert!(!again.finalized);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn finalize_cuts_off_a_cluster_with_damaged_child_sizes() {
+        for (name, size) in [("unknown", UNKNOWN_SIZE.to_vec()), ("oversized", vec![0x81, 0x7F])] {
+            let path = temp_path(name);
+            write_unfinished(&path, 2);
+            // A complete cluster whose block claims more data than the cluster holds
+            let mut damaged = vec![0xA3];
+            damaged.extend_from_slice(&size);
+            damaged.extend_from_slice(&[0x81, 0, 0, 0x80]);
+            let mut cluster = Vec::new();
+            element(&mut cluster, CLUSTER, &damaged);
+            append(&path, &cluster);
+
+            let recovery = finalize(&path).unwrap();
+            assert_eq!(recovery.frames, 2, "{}", name);
+            assert_eq!(recovery.truncated_bytes, cluster.len() as u64, "{}", name);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,246 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use regex::Regex;
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::change::RegionArg;
+use crate::session_pool::TargetKey;
+use crate::track::csv_field;
+use crate::{capture, config, convert, ocr, output, MonitorArgs};
+
+const CSV_HEADER: &str = "timestamp,target,region,value";
+
+/// A number with optional thousands separators, fraction and minus sign.
+static NUMBER: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"[-−]?\d{1,3}(?:,\d{3})+(?:\.\d+)?|[-−]?\d+(?:\.\d+)?").expect("valid number pattern")
+});
+
+/// Digit runs with an O or l in them.
+static MISREAD_DIGITS: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:\d[Ool]|[Ool]\d)[\dOol]*").expect("valid misread digits pattern")
+});
+
+/// How samples are written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Sink {
+    /// `timestamp,target,region,value` rows with a header
+    Csv,
+    /// InfluxDB line protocol, to a file or an InfluxDB write URL
+    Influxdb,
+}
+
+/// Where samples go: a file to append to, an InfluxDB HTTP write endpoint,
+/// or stdout.
+enum Destination {
+    File(String),
+    Http(String),
+    Stdout,
+}
+
+/// One OCR reading of the monitored region.
+struct Sample {
+    /// Unix milliseconds
+    timestamp: u128,
+    /// The number in the text with `--extract-number`, otherwise None
+    number: Option<f64>,
+    text: String,
+}
+
+/// Every `--interval` seconds, OCR `--region` of the target and append the
+/// reading (the number in it, with `--extract-number`) to a time-series sink
+/// until Ctrl-C or `--duration`, for dashboards that have no API.
+pub async fn monitor(key: TargetKey, args: &MonitorArgs, cancel: &CancellationToken) -> Result<Value, Box<dyn std::error::Error>> {
+    let region = args.region.as_ref().map(RegionArg::resolve).transpose()?.map(|region| region.rect);
+    let region_name = match &args.region {
+        Some(RegionArg::Named(name)) => name.clone(),
+        Some(RegionArg::Rect(rect)) => format!("{},{},{},{}", rect.x, rect.y, rect.width, rect.height),
+        None => "frame".to_string(),
+    };
+    let destination = match args.output.as_deref() {
+        Some(url) if url.starts_with("http://") || url.starts_with("https://") => Destination::Http(url.to_string()),
+        Some(path) => Destination::File(config::resolve_output_path(path)),
+        None => Destination::Stdout,
+    };
+    match (&destination, args.emit) {
+        (Destination::Http(_), Sink::Csv) => return Err("Sending samples to a URL needs --emit influxdb".into()),
+        (Destination::File(path), Sink::Csv) if std::fs::metadata(path).map_or(true, |metadata| metadata.len() == 0) => {
+            append(path, CSV_HEADER)?;
+        }
+        (Destination::Stdout, Sink::Csv) => result!("{}", CSV_HEADER),
+        _ => {}
+    }
+
+    let interval = Duration::from_secs_f64(args.interval.max(0.1));
+    let deadline = args.duration.map(|duration| Instant::now() + Duration::from_secs_f64(duration.max(0.0)));
+    let mut ticker = tokio::time::interval(interval);
+    let (mut samples, mut missed) = (0usize, 0usize);
+    let mut last = None;
+    let grabber = capture::FrameGrabber::new(key, (1.0 / interval.as_secs_f64()).ceil() as u32)?;
+
+    status!("Monitoring {} of {} every {:.1}s (Ctrl-C to stop)", region_name, key, interval.as_secs_f64());
+    loop {
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = tokio::time::sleep(remaining.unwrap_or(Duration::MAX)) => break,
+            _ = ticker.tick() => {}
+        }
+
+        let sample = match read_sample(&grabber, region, args, cancel).await {
+            Ok(sample) => sample,
+            Err(e) => {
+                status!("Reading failed: {}", e);
+                missed += 1;
+                continue;
+            }
+        };
+        if args.extract_number && sample.number.is_none() {
+            status!("No number in \"{}\"", sample.text);
+            missed += 1;
+            continue;
+        }
+
+        let record = match args.emit {
+            Sink::Csv => format_csv(&sample, key, &region_name),
+            Sink::Influxdb => format_influx(&sample, key, &region_name, &args.measurement),
+        };
+        let written = match &destination {
+            Destination::File(path) => append(path, &record),
+            Destination::Http(url) => post(url, args.token.as_deref(), &record).await,
+            Destination::Stdout => {
+                result!("{}", record);
+                Ok(())
+            }
+        };
+        if let Err(e) = written {
+            status!("Failed to write the sample: {}", e);
+            missed += 1;
+            continue;
+        }
+        output::event(&json!({
+            "event": "sample",
+            "timestamp": sample.timestamp,
+            "target": key.to_string(),
+            "region": region_name,
+            "value": sample.number.map_or(json!(sample.text), |number| json!(number)),
+        }));
+        samples += 1;
+        last = Some(sample);
+    }
+
+    status!("Recorded {} samples ({} missed)", samples, missed);
+    Ok(json!({
+        "status": "ok",
+        "target": key.to_string(),
+        "region": region_name,
+        "samples": samples,
+        "missed": missed,
+        "last": last.map(|sample| sample.number.map_or(json!(sample.text), |number| json!(number))),
+    }))
+}
+
+async fn read_sample(grabber: &capture::FrameGrabber, region: Option<crate::change::IgnoreRegion>, args: &MonitorArgs, cancel: &CancellationToken) -> Result<Sample, Box<dyn std::error::Error>> {
+    let (width, height, rgb_data, factor) = grabber.grab_scaled(cancel).await?;
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+    let (width, height, rgb_data) = match region {
+        Some(rect) => {
+            // The region is in screen pixels, frames too large to OCR come downscaled
+            let crop = convert::Crop {
+                x: rect.x / factor,
+                y: rect.y / factor,
+                width: (rect.width / factor).max(1),
+                height: (rect.height / factor).max(1),
+            };
+            (crop.width, crop.height, convert::crop_rgb8(width, height, rgb_data, crop)?)
+        }
+        None => (width, height, rgb_data),
+    };
+    let text = ocr::extract_text(width, height, &rgb_data, !args.no_cache).await?.join(" ");
+    let number = args.extract_number.then(|| parse_number(&text)).flatten();
+    Ok(Sample { timestamp, number, text })
+}
+
+/// The first number in `text`, allowing thousands separators (`1,234.5`),
+/// a leading minus and a letter misread as a digit (`1O5`).
+fn parse_number(text: &str) -> Option<f64> {
+    // O and l inside digit runs are almost always misread 0 and 1
+    let fixed = MISREAD_DIGITS.replace_all(text, |captures: &regex::Captures| captures[0].replace(['O', 'o'], "0").replace('l', "1"));
+    let found = NUMBER.find(&fixed)?;
+    found.as_str().replace(',', "").replace('−', "-").parse().ok()
+}
+
+fn format_csv(sample: &Sample, key: TargetKey, region: &str) -> String {
+    let value = sample.number.map_or_else(|| csv_field(&sample.text), |number| number.to_string());
+    format!("{},{},{},{}", sample.timestamp, key, csv_field(region), value)
+}
+
+/// `measurement,target=screen0,region=counter value=42 <nanoseconds>`.
+fn format_influx(sample: &Sample, key: TargetKey, region: &str, measurement: &str) -> String {
+    let escape = |value: &str| value.replace('\\', "\\\\").replace(',', "\\,").replace('=', "\\=").replace(' ', "\\ ");
+    let field = match sample.number {
+        Some(number) => format!("value={}", number),
+        None => format!("text=\"{}\"", sample.text.replace('\\', "\\\\").replace('"', "\\\"")),
+    };
+    format!(
+        "{},target={},region={} {} {}",
+        measurement.replace(',', "\\,").replace(' ', "\\ "),
+        escape(&key.to_string()),
+        escape(region),
+        field,
+        sample.timestamp * 1_000_000
+    )
+}
+
+fn append(path: &str, line: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = OpenOptions::new().create(true).append(true).open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    writeln!(file, "{}", line)?;
+    Ok(())
+}
+
+/// Send a line to an InfluxDB write endpoint, e.g.
+/// `http://localhost:8086/api/v2/write?org=home&bucket=screens&precision=ns`.
+async fn post(url: &str, token: Option<&str>, line: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut request = reqwest::Client::new().post(url).header("Content-Type", "text/plain; charset=utf-8").body(format!("{}\n", line));
+    if let Some(token) = token {
+        request = request.header("Authorization", format!("Token {}", token));
+    }
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        return Err(format!("InfluxDB answered {}: {}", response.status().as_u16(), response.text().await.unwrap_or_default().trim()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_thousands_separators() {
+        assert_eq!(parse_number("Total: 1,234.5 items"), Some(1234.5));
+        assert_eq!(parse_number("12,34"), Some(12.0));
+    }
+
+    #[test]
+    fn reads_misread_letters_in_numbers_as_digits() {
+        assert_eq!(parse_number("1O5 users"), Some(105.0));
+        assert_eq!(parse_number("l0 left"), Some(10.0));
+    }
+
+    #[test]
+    fn parses_unicode_and_ascii_minus_signs() {
+        assert_eq!(parse_number("Delta −3 today"), Some(-3.0));
+        assert_eq!(parse_number("-0.25"), Some(-0.25));
+    }
+
+    #[test]
+    fn finds_no_number_in_plain_text() {
+        assert_eq!(parse_number("Offline"), None);
+    }
+}
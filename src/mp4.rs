@@ -0,0 +1,317 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// Media timescale: sample times are in milliseconds, like the other writers.
+const TIMESCALE: u32 = 1000;
+/// H.264 NAL unit types the muxer looks at.
+const NAL_IDR: u8 = 5;
+const NAL_SPS: u8 = 7;
+const NAL_PPS: u8 = 8;
+const NAL_ACCESS_UNIT_DELIMITER: u8 = 9;
+/// Identity transform of mvhd and tkhd, in 16.16 and 2.30 fixed point.
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+
+struct Sample {
+    offset: u64,
+    size: u32,
+    timestamp_ms: u64,
+    keyframe: bool,
+}
+
+/// Writes frames as H.264 into an MP4 file. Samples go straight into the
+/// media data box; the index (moov) is only written by
+/// [`finish`](Self::finish), so unlike the Matroska writer a recording that is
+/// cut short can't be played.
+pub struct Mp4Writer {
+    file: BufWriter<File>,
+    encoder: H264Encoder,
+    /// Encoded size; H.264 4:2:0 needs even dimensions, so an odd last column
+    /// or row is cropped
+    width: u32,
+    height: u32,
+    /// Where the mdat box starts, for filling in its size
+    mdat_start: u64,
+    position: u64,
+    samples: Vec<Sample>,
+    sps: Option<Vec<u8>>,
+    pps: Option<Vec<u8>>,
+    /// Timestamp of the last forced keyframe
+    keyframe_ms: Option<u64>,
+}
+
+impl Mp4Writer {
+    pub fn create(path: &Path, width: u32, height: u32) -> Result<Self, Box<dyn std::error::Error>> {
+        let encoder = H264Encoder::new()?;
+        let (width, height) = (width & !1, height & !1);
+        if width == 0 || height == 0 {
+            return Err("Frames must be at least 2x2 pixels for MP4".into());
+        }
+        let mut file = BufWriter::new(File::create(path)?);
+        let ftyp = boxed(b"ftyp", &[b"isom".as_slice(), &512u32.to_be_bytes(), b"isom", b"iso2", b"avc1", b"mp41"].concat());
+        file.write_all(&ftyp)?;
+        let mdat_start = ftyp.len() as u64;
+        // 64-bit size, filled in by finish
+        file.write_all(&1u32.to_be_bytes())?;
+        file.write_all(b"mdat")?;
+        file.write_all(&0u64.to_be_bytes())?;
+        Ok(Self {
+            file,
+            encoder,
+            width,
+            height,
+            mdat_start,
+            position: mdat_start + 16,
+            samples: Vec::new(),
+            sps: None,
+            pps: None,
+            keyframe_ms: None,
+        })
+    }
+
+    /// Encode a frame of the size the writer was created with. A keyframe is
+    /// forced every `keyframe_interval_ms` so players can seek.
+    pub fn write_frame(&mut self, timestamp_ms: u64, width: u32, height: u32, rgb_data: &[u8], keyframe_interval_ms: u64) -> Result<(), Box<dyn std::error::Error>> {
+        if self.keyframe_ms.is_some_and(|keyframe_ms| timestamp_ms.saturating_sub(keyframe_ms) >= keyframe_interval_ms) {
+            self.encoder.force_keyframe();
+        }
+        let rgb_data = crop_rgb8(width, height, rgb_data, self.width, self.height);
+        let bitstream = self.encoder.encode(self.width, self.height, &rgb_data)?;
+
+        let mut sample = Vec::with_capacity(bitstream.len());
+        let mut keyframe = false;
+        for nal in nal_units(&bitstream) {
+            match nal[0] & 0x1f {
+                NAL_SPS => self.sps = Some(nal.to_vec()),
+                NAL_PPS => self.pps = Some(nal.to_vec()),
+                NAL_ACCESS_UNIT_DELIMITER => {}
+                kind => {
+                    keyframe |= kind == NAL_IDR;
+                    sample.extend_from_slice(&(nal.len() as u32).to_be_bytes());
+                    sample.extend_from_slice(nal);
+                }
+            }
+        }
+        // The encoder skips frames to stay within its rate
+        if sample.is_empty() {
+            return Ok(());
+        }
+        if keyframe {
+            self.keyframe_ms = Some(timestamp_ms);
+        }
+        self.file.write_all(&sample)?;
+        self.samples.push(Sample { offset: self.position, size: sample.len() as u32, timestamp_ms, keyframe });
+        self.position += sample.len() as u64;
+        Ok(())
+    }
+
+    /// Write the index, with the last frame shown until `end_ms`. Returns the
+    /// number of frames written.
+    pub fn finish(mut self, end_ms: u64) -> Result<usize, Box<dyn std::error::Error>> {
+        let (Some(sps), Some(pps)) = (&self.sps, &self.pps) else {
+            return Err("The H.264 encoder produced no parameter sets".into());
+        };
+        if sps.len() < 4 || self.samples.is_empty() {
+            return Err("The H.264 encoder produced no frames".into());
+        }
+        let moov = self.moov(sps, pps, end_ms);
+        self.file.write_all(&moov)?;
+        self.file.seek(SeekFrom::Start(self.mdat_start + 8))?;
+        self.file.write_all(&(self.position - self.mdat_start).to_be_bytes())?;
+        self.file.flush()?;
+        self.file.get_ref().sync_data()?;
+        Ok(self.samples.len())
+    }
+
+    fn moov(&self, sps: &[u8], pps: &[u8], end_ms: u64) -> Vec<u8> {
+        let first_ms = self.samples.first().map_or(0, |sample| sample.timestamp_ms);
+        let duration = end_ms.max(first_ms + 1).saturating_sub(first_ms).min(u32::MAX as u64) as u32;
+
+        let mut mvhd = Vec::new();
+        put_u32s(&mut mvhd, &[0, 0, TIMESCALE, duration, 0x0001_0000]);
+        mvhd.extend_from_slice(&0x0100u16.to_be_bytes());
+        mvhd.extend_from_slice(&[0; 10]);
+        put_u32s(&mut mvhd, &MATRIX);
+        put_u32s(&mut mvhd, &[0; 6]);
+        put_u32s(&mut mvhd, &[2]);
+
+        let mut tkhd = Vec::new();
+        put_u32s(&mut tkhd, &[0, 0, 1, 0, duration, 0, 0]);
+        tkhd.extend_from_slice(&[0; 8]);
+        put_u32s(&mut tkhd, &MATRIX);
+        put_u32s(&mut tkhd, &[self.width << 16, self.height << 16]);
+
+        let mut mdhd = Vec::new();
+        put_u32s(&mut mdhd, &[0, 0, TIMESCALE, duration]);
+        // Language "und", packed as three 5-bit letters
+        mdhd.extend_from_slice(&[0x55, 0xc4, 0, 0]);
+
+        let mut hdlr = Vec::new();
+        put_u32s(&mut hdlr, &[0]);
+        hdlr.extend_from_slice(b"vide");
+        put_u32s(&mut hdlr, &[0; 3]);
+        hdlr.extend_from_slice(b"VideoHandler\0");
+
+        let dinf = boxed(b"dinf", &full_box(b"dref", 0, 0, &[1u32.to_be_bytes().as_slice(), &full_box(b"url ", 0, 1, &[])].concat()));
+        let minf = boxed(b"minf", &[full_box(b"vmhd", 0, 1, &[0; 8]), dinf, boxed(b"stbl", &self.stbl(sps, pps, end_ms))].concat());
+        let mdia = boxed(b"mdia", &[full_box(b"mdhd", 0, 0, &mdhd), full_box(b"hdlr", 0, 0, &hdlr), minf].concat());
+        let trak = boxed(b"trak", &[full_box(b"tkhd", 0, 3, &tkhd), mdia].concat());
+        boxed(b"moov", &[full_box(b"mvhd", 0, 0, &mvhd), trak].concat())
+    }
+
+    fn stbl(&self, sps: &[u8], pps: &[u8], end_ms: u64) -> Vec<u8> {
+        let mut avcc = vec![1, sps[1], sps[2], sps[3], 0xff, 0xe1];
+        avcc.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+        avcc.extend_from_slice(sps);
+        avcc.push(1);
+        avcc.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+        avcc.extend_from_slice(pps);
+
+        let mut avc1 = vec![0; 6];
+        avc1.extend_from_slice(&1u16.to_be_bytes());
+        avc1.extend_from_slice(&[0; 16]);
+        avc1.extend_from_slice(&(self.width as u16).to_be_bytes());
+        avc1.extend_from_slice(&(self.height as u16).to_be_bytes());
+        put_u32s(&mut avc1, &[0x0048_0000, 0x0048_0000, 0]);
+        avc1.extend_from_slice(&1u16.to_be_bytes());
+        avc1.extend_from_slice(&[0; 32]);
+        avc1.extend_from_slice(&[0x00, 0x18, 0xff, 0xff]);
+        avc1.extend_from_slice(&boxed(b"avcC", &avcc));
+        let stsd = [1u32.to_be_bytes().as_slice(), &boxed(b"avc1", &avc1)].concat();
+
+        // Sample durations, run-length encoded; the last frame lasts until the end
+        let mut runs: Vec<(u32, u32)> = Vec::new();
+        for (index, sample) in self.samples.iter().enumerate() {
+            let next_ms = self.samples.get(index + 1).map_or(end_ms, |next| next.timestamp_ms);
+            let delta = next_ms.saturating_sub(sample.timestamp_ms).clamp(1, u32::MAX as u64) as u32;
+            match runs.last_mut() {
+                Some((count, last)) if *last == delta => *count += 1,
+                _ => runs.push((1, delta)),
+            }
+        }
+        let mut stts = (runs.len() as u32).to_be_bytes().to_vec();
+        for (count, delta) in runs {
+            put_u32s(&mut stts, &[count, delta]);
+        }
+
+        let keyframes: Vec<u32> = (1..).zip(&self.samples).filter(|(_, sample)| sample.keyframe).map(|(number, _)| number).collect();
+        let mut stss = (keyframes.len() as u32).to_be_bytes().to_vec();
+        put_u32s(&mut stss, &keyframes);
+
+        // One sample per chunk
+        let mut stsc = Vec::new();
+        put_u32s(&mut stsc, &[1, 1, 1, 1]);
+        let mut stsz = Vec::new();
+        put_u32s(&mut stsz, &[0, self.samples.len() as u32]);
+        let mut co64 = (self.samples.len() as u32).to_be_bytes().to_vec();
+        for sample in &self.samples {
+            stsz.extend_from_slice(&sample.size.to_be_bytes());
+            co64.extend_from_slice(&sample.offset.to_be_bytes());
+        }
+
+        [
+            full_box(b"stsd", 0, 0, &stsd),
+            full_box(b"stts", 0, 0, &stts),
+            full_box(b"stss", 0, 0, &stss),
+            full_box(b"stsc", 0, 0, &stsc),
+            full_box(b"stsz", 0, 0, &stsz),
+            full_box(b"co64", 0, 0, &co64),
+        ].concat()
+    }
+}
+
+/// Fail unless this build can encode MP4, so a recording stops before it
+/// starts rather than at its first frame.
+pub fn check_available() -> Result<(), Box<dyn std::error::Error>> {
+    match cfg!(feature = "mp4") {
+        true => Ok(()),
+        false => Err("MP4 recording is not available; rebuild with `--features mp4`, or record to .mkv or .gif".into()),
+    }
+}
+
+#[cfg(feature = "mp4")]
+struct H264Encoder(openh264::encoder::Encoder);
+
+#[cfg(feature = "mp4")]
+impl H264Encoder {
+    fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self(openh264::encoder::Encoder::new()?))
+    }
+
+    fn force_keyframe(&mut self) {
+        self.0.force_intra_frame();
+    }
+
+    /// One frame as an Annex B byte stream.
+    fn encode(&mut self, width: u32, height: u32, rgb_data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        use openh264::formats::{RgbSliceU8, YUVBuffer};
+        let yuv = YUVBuffer::from_rgb_source(RgbSliceU8::new(rgb_data, (width as usize, height as usize)));
+        Ok(self.0.encode(&yuv)?.to_vec())
+    }
+}
+
+#[cfg(not(feature = "mp4"))]
+struct H264Encoder;
+
+#[cfg(not(feature = "mp4"))]
+impl H264Encoder {
+    fn new() -> Result<Self, Box<dyn std::error::Error>> {
+        check_available().map(|()| H264Encoder)
+    }
+
+    fn force_keyframe(&mut self) {}
+
+    fn encode(&mut self, _width: u32, _height: u32, _rgb_data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        unreachable!("H264Encoder can't be created without the mp4 feature")
+    }
+}
+
+/// NAL units of an Annex B byte stream, without their start codes.
+fn nal_units(stream: &[u8]) -> Vec<&[u8]> {
+    let starts: Vec<usize> = (0..stream.len().saturating_sub(2))
+        .filter(|&i| stream[i..i + 3] == [0, 0, 1])
+        .map(|i| i + 3)
+        .collect();
+    let mut units = Vec::with_capacity(starts.len());
+    for (index, &start) in starts.iter().enumerate() {
+        let mut end = starts.get(index + 1).map_or(stream.len(), |next| next - 3);
+        // A 4-byte start code leaves a zero at the end of the previous unit
+        while end > start && stream[end - 1] == 0 {
+            end -= 1;
+        }
+        if end > start {
+            units.push(&stream[start..end]);
+        }
+    }
+    units
+}
+
+/// The top left `to_width` x `to_height` of an RGB8 frame.
+fn crop_rgb8(width: u32, height: u32, rgb_data: &[u8], to_width: u32, to_height: u32) -> Cow<'_, [u8]> {
+    if (width, height) == (to_width, to_height) {
+        return Cow::Borrowed(rgb_data);
+    }
+    let row_len = to_width as usize * 3;
+    Cow::Owned(rgb_data.chunks_exact(width as usize * 3).take(to_height as usize).flat_map(|row| &row[..row_len]).copied().collect())
+}
+
+fn boxed(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(8 + body.len());
+    data.extend_from_slice(&(8 + body.len() as u32).to_be_bytes());
+    data.extend_from_slice(kind);
+    data.extend_from_slice(body);
+    data
+}
+
+/// A box with a version and flags ahead of its body.
+fn full_box(kind: &[u8; 4], version: u8, flags: u32, body: &[u8]) -> Vec<u8> {
+    let header = (version as u32) << 24 | flags;
+    boxed(kind, &[header.to_be_bytes().as_slice(), body].concat())
+}
+
+fn put_u32s(data: &mut Vec<u8>, values: &[u32]) {
+    for value in values {
+        data.extend_from_slice(&value.to_be_bytes());
+    }
+}
@@ -0,0 +1,266 @@
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use image::RgbImage;
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Packet, QoS, Transport};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::{self, MqttConfig};
+use crate::session_pool::TargetKey;
+use crate::{capture, ocr, output};
+
+/// Requests queued for the connection before publishing fails.
+const REQUEST_CAPACITY: usize = 64;
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+/// Pause after a connection error before the client reconnects.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// How long `stop` waits for queued messages and the disconnect to go out.
+const STOP_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long `publish_once` waits for the broker to acknowledge a message.
+const PUBLISH_TIMEOUT: Duration = Duration::from_secs(30);
+/// Incoming packets `publish_once` accepts (rumqttc's default).
+const MAX_INCOMING_PACKET: usize = 10 * 1024;
+
+/// Topics used by the client, from the `[mqtt]` table.
+struct Topics {
+    events: String,
+    ocr: String,
+    thumbnail: String,
+    command: String,
+    status: String,
+}
+
+struct Publisher {
+    client: AsyncClient,
+    topics: Topics,
+    thumbnail_width: Option<u32>,
+    connection: Mutex<Option<JoinHandle<()>>>,
+}
+
+static PUBLISHER: OnceLock<Publisher> = OnceLock::new();
+
+/// A message on the command topic: `{"screen": 0}` or `{"window": 3}`,
+/// optionally with `"ocr": true`.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Command {
+    screen: Option<usize>,
+    window: Option<usize>,
+    #[serde(default)]
+    ocr: bool,
+}
+
+/// Connect to the broker in the config file's `[mqtt]` table, if there is
+/// one, announce `online` on the status topic and start answering capture
+/// commands. Does nothing when MQTT isn't configured.
+pub async fn start() -> Result<(), Box<dyn std::error::Error>> {
+    let Some(settings) = &config::settings().mqtt else {
+        return Ok(());
+    };
+    let client_id = settings.client_id.clone().unwrap_or_else(|| format!("captest-{}", std::process::id()));
+    let (mut options, topics) = client_options(settings, client_id)?;
+    // Dashboards see a fleet member drop off even if captest is killed
+    options.set_last_will(LastWill::new(&topics.status, "offline", QoS::AtLeastOnce, true));
+    let (client, mut eventloop) = AsyncClient::new(options, REQUEST_CAPACITY);
+    client.subscribe(&topics.command, QoS::AtLeastOnce).await?;
+    client.publish(&topics.status, QoS::AtLeastOnce, true, "online").await?;
+    status!("Publishing to MQTT broker {} under {}", settings.broker, topics.events);
+
+    let commands = client.clone();
+    let command_topic = topics.command.clone();
+    let connection = tokio::spawn(async move {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::Publish(publish))) if publish.topic == command_topic => {
+                    let payload = publish.payload.to_vec();
+                    tokio::spawn(run_command(commands.clone(), payload));
+                }
+                Ok(Event::Outgoing(rumqttc::Outgoing::Disconnect)) => break,
+                Ok(_) => {}
+                Err(e) => {
+                    status!("MQTT connection failed: {}", e);
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    });
+
+    let publisher = Publisher { client, topics, thumbnail_width: settings.thumbnails.then_some(settings.thumbnail_width), connection: Mutex::new(Some(connection)) };
+    if PUBLISHER.set(publisher).is_err() {
+        return Err("The MQTT client was already started".into());
+    }
+    Ok(())
+}
+
+/// Announce `offline` and disconnect, giving queued messages a moment to go out.
+pub async fn stop() {
+    let Some(publisher) = PUBLISHER.get() else {
+        return;
+    };
+    let _ = publisher.client.try_publish(&publisher.topics.status, QoS::AtLeastOnce, true, "offline");
+    let _ = publisher.client.try_disconnect();
+    let connection = publisher.connection.lock().unwrap().take();
+    if let Some(connection) = connection {
+        let _ = tokio::time::timeout(STOP_TIMEOUT, connection).await;
+    }
+}
+
+/// Publish a JSON event (as printed by `watch`): `ocr` events go to the OCR
+/// topic, everything else to the events topic.
+pub fn publish_event(event: &Value) {
+    let Some(publisher) = PUBLISHER.get() else {
+        return;
+    };
+    let topic = match event["event"].as_str() {
+        Some("ocr") => &publisher.topics.ocr,
+        _ => &publisher.topics.events,
+    };
+    let event = output::with_run_id(event.clone());
+    if let Err(e) = publisher.client.try_publish(topic, QoS::AtLeastOnce, false, event.to_string()) {
+        status!("Failed to publish to {}: {}", topic, e);
+    }
+}
+
+/// Publish a JPEG thumbnail of a frame of `target`, when `thumbnails` is on.
+pub fn publish_thumbnail(target: TargetKey, width: u32, height: u32, rgb_data: &[u8]) {
+    let Some(publisher) = PUBLISHER.get() else {
+        return;
+    };
+    let Some(thumbnail_width) = publisher.thumbnail_width else {
+        return;
+    };
+    let jpeg_bytes = match thumbnail_jpeg(width, height, rgb_data, thumbnail_width) {
+        Ok(jpeg_bytes) => jpeg_bytes,
+        Err(e) => {
+            status!("Failed to encode thumbnail: {}", e);
+            return;
+        }
+    };
+    let topic = format!("{}/{}", publisher.topics.thumbnail, topic_segment(target));
+    if let Err(e) = publisher.client.try_publish(&topic, QoS::AtMostOnce, true, jpeg_bytes) {
+        status!("Failed to publish to {}: {}", topic, e);
+    }
+}
+
+/// Publish `payload` to `topic` over a connection of its own and wait for
+/// the broker to acknowledge it, for one-off deliveries such as
+/// `--to mqtt:TOPIC`. Leaves the status topic alone, so it can run next to a
+/// `watch` client of the same config.
+pub async fn publish_once(topic: &str, payload: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+    let settings = config::settings().mqtt.as_ref().ok_or("Publishing to MQTT needs a broker in the config file's [mqtt] table")?;
+    let (mut options, _) = client_options(settings, format!("captest-{}-publish", std::process::id()))?;
+    // Captures are far larger than rumqttc's default packet limit
+    options.set_max_packet_size(MAX_INCOMING_PACKET, payload.len() + topic.len() + 64);
+    let (client, mut eventloop) = AsyncClient::new(options, REQUEST_CAPACITY);
+    client.publish(topic, QoS::AtLeastOnce, false, payload).await?;
+
+    let acknowledged = tokio::time::timeout(PUBLISH_TIMEOUT, async {
+        loop {
+            if let Event::Incoming(Packet::PubAck(_)) = eventloop.poll().await? {
+                return Ok::<_, rumqttc::ConnectionError>(());
+            }
+        }
+    }).await;
+    match acknowledged {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => return Err(format!("MQTT connection to {} failed: {}", settings.broker, e).into()),
+        Err(_) => return Err(format!("{} didn't acknowledge the message within {} seconds", settings.broker, PUBLISH_TIMEOUT.as_secs()).into()),
+    }
+    let _ = client.try_disconnect();
+    let _ = tokio::time::timeout(STOP_TIMEOUT, eventloop.poll()).await;
+    Ok(())
+}
+
+/// Options for `settings.broker`, an `mqtt://` (or `mqtts://` for TLS) URL.
+fn client_options(settings: &MqttConfig, client_id: String) -> Result<(MqttOptions, Topics), String> {
+    let url = url::Url::parse(&settings.broker).map_err(|e| format!("Invalid MQTT broker \"{}\": {}", settings.broker, e))?;
+    let (tls, default_port) = match url.scheme() {
+        "mqtt" | "tcp" => (false, 1883),
+        "mqtts" | "ssl" => (true, 8883),
+        scheme => return Err(format!("Invalid MQTT broker \"{}\": unsupported scheme {}", settings.broker, scheme)),
+    };
+    let host = url.host_str().ok_or_else(|| format!("Invalid MQTT broker \"{}\": no host", settings.broker))?;
+
+    let prefix = settings.topic_prefix.trim_end_matches('/');
+    let topic = |configured: &Option<String>, name: &str| configured.clone().unwrap_or_else(|| format!("{}/{}", prefix, name));
+    let topics = Topics {
+        events: topic(&settings.events_topic, "events"),
+        ocr: topic(&settings.ocr_topic, "ocr"),
+        thumbnail: topic(&settings.thumbnail_topic, "thumbnail"),
+        command: topic(&settings.command_topic, "command"),
+        status: format!("{}/status", prefix),
+    };
+
+    let mut options = MqttOptions::new(client_id, host, url.port().unwrap_or(default_port));
+    options.set_keep_alive(KEEP_ALIVE);
+    if let Some(username) = &settings.username {
+        options.set_credentials(username, settings.password.clone().unwrap_or_default());
+    }
+    if tls {
+        options.set_transport(Transport::tls_with_default_config());
+    }
+    Ok((options, topics))
+}
+
+/// Capture the target a command names and publish the result as a
+/// `capture` event (plus its thumbnail), or an `error` event.
+async fn run_command(client: AsyncClient, payload: Vec<u8>) {
+    let Some(publisher) = PUBLISHER.get() else {
+        return;
+    };
+    let event = match capture_for_command(&payload).await {
+        Ok(event) => event,
+        Err(e) => json!({ "event": "error", "error": e.to_string(), "timestamp": unix_millis() }),
+    };
+    if let Err(e) = client.publish(&publisher.topics.events, QoS::AtLeastOnce, false, event.to_string()).await {
+        status!("Failed to publish to {}: {}", publisher.topics.events, e);
+    }
+}
+
+async fn capture_for_command(payload: &[u8]) -> Result<Value, Box<dyn std::error::Error>> {
+    let command: Command = serde_json::from_slice(payload).map_err(|e| format!("Invalid MQTT command: {}", e))?;
+    let key = match (command.screen, command.window) {
+        (_, Some(window)) => TargetKey::Window(window),
+        (screen, None) => TargetKey::Screen(screen.unwrap_or(0)),
+    };
+    status!("MQTT command: capture {}", key);
+    let (width, height, rgb_data) = capture::grab_rgb8(key, &CancellationToken::new()).await?;
+    publish_thumbnail(key, width, height, &rgb_data);
+
+    let mut event = json!({
+        "event": "capture",
+        "target": key.to_string(),
+        "width": width,
+        "height": height,
+        "timestamp": unix_millis(),
+    });
+    if command.ocr {
+        let lines = ocr::extract_text(width, height, &rgb_data, true).await?;
+        event["text"] = json!(lines.join("\n"));
+        event["lines"] = json!(lines);
+    }
+    Ok(event)
+}
+
+fn thumbnail_jpeg(width: u32, height: u32, rgb_data: &[u8], thumbnail_width: u32) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let image = RgbImage::from_raw(width, height, rgb_data.to_vec()).ok_or("Captured frame has an unexpected size")?;
+    let thumbnail_width = thumbnail_width.clamp(1, width.max(1));
+    let thumbnail_height = ((height as u64 * thumbnail_width as u64) / width.max(1) as u64).max(1) as u32;
+    let thumbnail = image::imageops::thumbnail(&image, thumbnail_width, thumbnail_height);
+    crate::rgb8_to_jpeg_bytes(thumbnail_width, thumbnail_height, thumbnail.as_raw())
+}
+
+/// `screen0` or `window3`, as a topic level.
+fn topic_segment(target: TargetKey) -> String {
+    match target {
+        TargetKey::Screen(index) => format!("screen{}", index),
+        TargetKey::Window(index) => format!("window{}", index),
+    }
+}
+
+fn unix_millis() -> u128 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0)
+}
@@ -0,0 +1,456 @@
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use image::{imageops, RgbImage};
+use ocrs::{DecodeMethod, ImageSource, OcrEngine, OcrEngineParams, RotatedRect, TextItem};
+use rten::Model;
+use tokio::sync::OnceCell;
+
+use crate::layout::{self, LineBox, WordBox};
+use crate::timings::{self, Stage};
+use crate::{cache, config, hocr, progress};
+
+/// Where the published ocrs models live. Other recognition models are expected
+/// under the same base URL as `text-recognition-<lang>.rten`.
+pub const DEFAULT_MODEL_URL: &str = "https://ocrs-models.s3-accelerate.amazonaws.com";
+
+const DETECTION_MODEL: &str = "text-detection.rten";
+
+/// OCR output only depends on pixels and settings, so entries can live long;
+/// the TTL just keeps the cache directory from growing forever.
+const CACHE_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+/// Bumped when cached results gain fields, so older entries aren't reused.
+const CACHE_FORMAT: u32 = 3;
+
+/// The engine for the configured models, loaded on first use and kept for the
+/// rest of the process, so polling loops don't reload the models every tick.
+static ENGINE: OnceCell<Arc<SharedEngine>> = OnceCell::const_new();
+
+/// How recognized text is printed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum OcrFormat {
+    /// Plain text, one line per recognized line
+    #[default]
+    Text,
+    /// JSON with each line's bounding box and its words' boxes and plausibility
+    Json,
+    /// hOCR, the HTML format of Tesseract and other OCR tools
+    Hocr,
+}
+
+/// Render lines recognized in a `width` x `height` image in `format`.
+/// Coordinates are pixels of that image.
+pub fn format_lines(lines: Vec<LineBox>, width: u32, height: u32, format: OcrFormat) -> String {
+    match format {
+        OcrFormat::Text => lines.into_iter().map(|line| line.text).collect::<Vec<_>>().join("\n"),
+        OcrFormat::Json => {
+            let document = serde_json::json!({ "width": width, "height": height, "lines": lines });
+            serde_json::to_string_pretty(&document).unwrap_or_default()
+        }
+        OcrFormat::Hocr => hocr::to_hocr(lines, width, height),
+    }
+}
+
+/// Recognize text lines in an RGB8 frame, in reading order.
+pub async fn extract_text(width: u32, height: u32, rgb_data: &[u8], use_cache: bool) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let lines = extract_lines(width, height, rgb_data, use_cache).await?;
+    Ok(lines.into_iter().map(|line| line.text).collect())
+}
+
+/// Recognize text lines with their positions, in reading order. With
+/// `use_cache`, results are looked up by a hash of the pixels and OCR settings
+/// first, so polling loops over an unchanged screen skip model loading and
+/// recognition entirely.
+pub async fn extract_lines(width: u32, height: u32, rgb_data: &[u8], use_cache: bool) -> Result<Vec<LineBox>, Box<dyn std::error::Error>> {
+    let _timing = timings::span(Stage::Ocr);
+    let cache = cache::DiskCache::new("ocr");
+    let key = cache_key(width, height, rgb_data);
+    if use_cache
+        && let Some(lines) = cache.get(&key, CACHE_TTL).and_then(|value| serde_json::from_str(&value).ok())
+    {
+        status!("Using cached OCR result");
+        return Ok(lines);
+    }
+
+    let lines = recognize(width, height, rgb_data).await?;
+    if use_cache {
+        cache.put(&key, &serde_json::to_string(&lines)?);
+    }
+    Ok(lines)
+}
+
+/// An OCR engine loaded once and shared by worker threads, for recognizing
+/// many images without reloading the models for each.
+pub struct SharedEngine {
+    engine: OcrEngine,
+}
+
+impl SharedEngine {
+    /// The process-wide engine, loading it the first time. A failed load is
+    /// retried on the next call.
+    pub async fn shared() -> Result<Arc<Self>, Box<dyn std::error::Error>> {
+        ENGINE.get_or_try_init(|| async { Ok(Arc::new(Self { engine: load_engine().await? })) }).await.cloned()
+    }
+
+    /// Recognize the text lines of an RGB8 image, in reading order, going
+    /// through the OCR cache like `extract_text`. Blocks; call from a worker
+    /// thread.
+    pub fn extract_text(&self, width: u32, height: u32, rgb_data: &[u8], use_cache: bool) -> Result<Vec<String>, String> {
+        let _timing = timings::span(Stage::Ocr);
+        let cache = cache::DiskCache::new("ocr");
+        let key = cache_key(width, height, rgb_data);
+        if use_cache
+            && let Some(lines) = cache.get(&key, CACHE_TTL).and_then(|value| serde_json::from_str::<Vec<LineBox>>(&value).ok())
+        {
+            return Ok(lines.into_iter().map(|line| line.text).collect());
+        }
+
+        let lines = recognize_lines(&self.engine, width, height, rgb_data, true).map_err(|e| e.to_string())?;
+        let lines = layout::reading_order(lines);
+        if use_cache && let Ok(value) = serde_json::to_string(&lines) {
+            cache.put(&key, &value);
+        }
+        Ok(lines.into_iter().map(|line| line.text).collect())
+    }
+}
+
+fn cache_key(width: u32, height: u32, rgb_data: &[u8]) -> String {
+    let settings = config::settings();
+    // Models are identified by the files they load from, so another model
+    // directory, or a model replaced in place, doesn't reuse old transcripts
+    let models: Vec<String> = [Ok(DETECTION_MODEL.to_string()), recognition_model_name()]
+        .into_iter()
+        .map(|name| {
+            let path = resolve_model(&name.unwrap_or_default());
+            let modified = std::fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+            format!("{}@{:?}", path.display(), modified)
+        })
+        .collect();
+    let options = format!("{}|{:?}|{:?}|{:?}|{:?}|{}",
+        CACHE_FORMAT, settings.ocr_lang, settings.ocr_alphabet, settings.ocr_allowed_chars, settings.ocr_beam_width, models.join("|"));
+    cache::cache_key(&[
+        &width.to_le_bytes(),
+        &height.to_le_bytes(),
+        rgb_data,
+        options.as_bytes(),
+    ])
+}
+
+async fn recognize(width: u32, height: u32, rgb_data: &[u8]) -> Result<Vec<LineBox>, Box<dyn std::error::Error>> {
+    status!("Extracting text with OCR");
+
+    let engine = SharedEngine::shared().await?;
+
+    status!("Performing OCR analysis");
+    let pb = progress::spinner("Recognizing text");
+    // Recognition keeps a core busy for up to seconds, too long to hold an
+    // executor thread, so it runs on the blocking pool
+    let rgb_data = rgb_data.to_vec();
+    let lines = tokio::task::spawn_blocking(move || {
+        recognize_lines(&engine.engine, width, height, &rgb_data, true).map_err(|e| e.to_string())
+    }).await;
+    pb.finish_and_clear();
+
+    Ok(layout::reading_order(lines??))
+}
+
+/// Detect and recognize the lines of an RGB8 image. With `handle_vertical`,
+/// words that are much taller than wide are treated as rotated text: they are
+/// grouped into regions that get cropped, turned upright and recognized
+/// separately, since the line finder only copes with roughly horizontal text.
+fn recognize_lines(engine: &OcrEngine, width: u32, height: u32, rgb_data: &[u8], handle_vertical: bool) -> Result<Vec<LineBox>, Box<dyn std::error::Error>> {
+    // Create image source directly from RGB8 data
+    let img_source = ImageSource::from_bytes(rgb_data, (width, height))?;
+    let ocr_input = engine.prepare_input(img_source)?;
+
+    // Perform OCR: detect words, find lines, recognize text
+    let word_rects = engine.detect_words(&ocr_input)?;
+    let (vertical_words, word_rects): (Vec<_>, Vec<_>) = word_rects
+        .into_iter()
+        .partition(|word| handle_vertical && is_vertical(word));
+    let line_rects = engine.find_text_lines(&ocr_input, &word_rects);
+    let line_texts = engine.recognize_text(&ocr_input, &line_rects)?;
+
+    // Collect the recognized text lines
+    let mut lines: Vec<LineBox> = line_texts
+        .iter()
+        .flatten()
+        // Filter likely spurious detections
+        .filter(|l| l.to_string().len() > 1)
+        .map(|l| {
+            let rect = l.bounding_rect();
+            LineBox {
+                text: l.to_string(),
+                left: rect.left(),
+                top: rect.top(),
+                right: rect.right(),
+                bottom: rect.bottom(),
+                words: l.words().map(|word| {
+                    let rect = word.bounding_rect();
+                    let text = word.to_string();
+                    WordBox {
+                        plausibility: word_plausibility(&text),
+                        angle: baseline_angle(&word.rotated_rect()),
+                        text,
+                        left: rect.left(),
+                        top: rect.top(),
+                        right: rect.right(),
+                        bottom: rect.bottom(),
+                    }
+                }).collect(),
+            }
+        })
+        .collect();
+
+    for region in vertical_regions(&vertical_words, width, height) {
+        if let Some(line) = recognize_vertical_region(engine, width, height, rgb_data, region)? {
+            lines.push(line);
+        }
+    }
+
+    Ok(lines)
+}
+
+/// Baseline direction of a word box in degrees clockwise from horizontal,
+/// from the box's up axis (which points to -y for upright text).
+fn baseline_angle(rect: &RotatedRect) -> f32 {
+    let up = rect.up_axis();
+    let angle = up.x.atan2(-up.y).to_degrees();
+    (angle * 10.0).round() / 10.0
+}
+
+/// How word-like a recognized word looks from its characters alone: letters
+/// and digits are typical, stray symbols and letters mixed into numbers
+/// (`1O0`, `l0g`) are what misreads look like. ocrs doesn't report
+/// recognition probabilities, so this is a heuristic, not a confidence.
+fn word_plausibility(word: &str) -> f32 {
+    let chars: Vec<char> = word.chars().collect();
+    if chars.is_empty() {
+        return 0.0;
+    }
+    let score: f32 = chars.iter().map(|&c| match c {
+        c if c.is_alphanumeric() => 1.0,
+        '.' | ',' | ':' | ';' | '\'' | '"' | '!' | '?' | '-' | '(' | ')' | '/' | '%' | '$' | '@' | '&' | '#' | '+' | '=' => 0.8,
+        _ => 0.3,
+    }).sum::<f32>() / chars.len() as f32;
+
+    let letters = chars.iter().filter(|c| c.is_alphabetic()).count();
+    let digits = chars.iter().filter(|c| c.is_ascii_digit()).count();
+    // Identifiers like v2 or A4 mix a few of each; a word mostly of one kind
+    // with one or two of the other usually has a 0/O or 1/l confusion
+    let confusable = letters > 0 && digits > 0 && letters.min(digits) <= 2 && letters.max(digits) >= 3;
+    let score = if confusable { score * 0.6 } else { score };
+    let score = if chars.len() == 1 && !chars[0].is_alphanumeric() { score * 0.5 } else { score };
+    (score * 100.0).round() / 100.0
+}
+
+/// A word box at least twice as tall as it is wide is almost always a word
+/// written top-to-bottom or bottom-to-top, single characters aside.
+fn is_vertical(word: &RotatedRect) -> bool {
+    let rect = word.bounding_rect();
+    rect.height() > rect.width() * 2.0 && rect.height() > 20.0
+}
+
+/// Merge vertical words stacked above each other into padded regions,
+/// as (left, top, right, bottom) clamped to the image.
+fn vertical_regions(words: &[RotatedRect], width: u32, height: u32) -> Vec<(u32, u32, u32, u32)> {
+    let mut regions: Vec<(f32, f32, f32, f32)> = Vec::new();
+    let mut rects: Vec<_> = words.iter().map(|word| word.bounding_rect()).collect();
+    rects.sort_by(|a, b| a.top().total_cmp(&b.top()));
+
+    for rect in rects {
+        let margin = rect.width();
+        let joined = regions.iter_mut().find(|region| {
+            rect.left() < region.2 && rect.right() > region.0 && rect.top() - region.3 < margin
+        });
+        match joined {
+            Some(region) => {
+                region.0 = region.0.min(rect.left());
+                region.2 = region.2.max(rect.right());
+                region.3 = region.3.max(rect.bottom());
+            }
+            None => regions.push((rect.left(), rect.top(), rect.right(), rect.bottom())),
+        }
+    }
+
+    regions.into_iter().map(|(left, top, right, bottom)| {
+        // Some room around the glyphs helps detection on the crop
+        let pad = (right - left) * 0.5;
+        let clamp = |value: f32, max: u32| value.max(0.0).min(max as f32) as u32;
+        (clamp(left - pad, width), clamp(top - pad, height), clamp(right + pad, width), clamp(bottom + pad, height))
+    }).collect()
+}
+
+/// Recognize a region of rotated text. The reading direction isn't known from
+/// the box alone, so both quarter turns are tried and the more plausible text
+/// is kept.
+fn recognize_vertical_region(engine: &OcrEngine, width: u32, height: u32, rgb_data: &[u8], region: (u32, u32, u32, u32)) -> Result<Option<LineBox>, Box<dyn std::error::Error>> {
+    let (left, top, right, bottom) = region;
+    if right <= left || bottom <= top {
+        return Ok(None);
+    }
+    let Some(image) = RgbImage::from_raw(width, height, rgb_data.to_vec()) else {
+        return Ok(None);
+    };
+    let crop = imageops::crop_imm(&image, left, top, right - left, bottom - top).to_image();
+    let (crop_width, crop_height) = (crop.width() as i32, crop.height() as i32);
+
+    let mut best: Option<(String, Vec<WordBox>)> = None;
+    // Bottom-to-top text (axis titles) needs a clockwise turn, top-to-bottom
+    // text (vertical tab labels) a counter-clockwise one
+    for (clockwise, upright) in [(true, imageops::rotate90(&crop)), (false, imageops::rotate270(&crop))] {
+        let lines = layout::reading_order(recognize_lines(engine, upright.width(), upright.height(), upright.as_raw(), false)?);
+        let text = lines.iter().map(|line| line.text.as_str()).collect::<Vec<_>>().join(" ");
+        if best.as_ref().is_none_or(|(best, _)| plausibility(&text) > plausibility(best)) {
+            // Map the word boxes from the turned crop back onto the image
+            let words = lines.into_iter().flat_map(|line| line.words).map(|word| {
+                let (x, y) = match clockwise {
+                    true => ((word.top, word.bottom), (crop_height - word.right, crop_height - word.left)),
+                    false => ((crop_width - word.bottom, crop_width - word.top), (word.left, word.right)),
+                };
+                WordBox {
+                    left: left as i32 + x.0,
+                    top: top as i32 + y.0,
+                    right: left as i32 + x.1,
+                    bottom: top as i32 + y.1,
+                    angle: if clockwise { -90.0 } else { 90.0 },
+                    ..word
+                }
+            }).collect();
+            best = Some((text, words));
+        }
+    }
+
+    Ok(best.filter(|(text, _)| text.len() > 1).map(|(text, words)| LineBox {
+        text,
+        left: left as i32,
+        top: top as i32,
+        right: right as i32,
+        bottom: bottom as i32,
+        words,
+    }))
+}
+
+/// Text read with the wrong rotation is mostly punctuation and stray symbols.
+fn plausibility(text: &str) -> i32 {
+    text.chars().map(|c| if c.is_alphanumeric() || c == ' ' { 1 } else { -2 }).sum()
+}
+
+/// Value parser for `--ocr-lang`. The language becomes part of a model file
+/// name and download URL, so only letters, digits, `_` and `-` are allowed.
+pub fn parse_lang(value: &str) -> Result<String, String> {
+    if value.is_empty() || !value.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+        return Err(format!("Invalid OCR language \"{}\": use letters, digits, '_' and '-' only", value));
+    }
+    Ok(value.to_string())
+}
+
+/// Build an engine from the configured models, language and decoding options.
+async fn load_engine() -> Result<OcrEngine, Box<dyn std::error::Error>> {
+    let settings = config::settings();
+
+    let recognition_name = recognition_model_name()?;
+    let detection_model_path = find_or_download(DETECTION_MODEL).await?;
+    let rec_model_path = find_or_download(&recognition_name).await?;
+
+    // Non-Latin recognition models ship their character set next to the model
+    let alphabet = match (&settings.ocr_alphabet, &settings.ocr_lang) {
+        (Some(alphabet), _) => Some(alphabet.clone()),
+        (None, Some(_)) => std::fs::read_to_string(rec_model_path.with_extension("txt"))
+            .ok()
+            .map(|alphabet| alphabet.trim_end_matches(['\r', '\n']).to_string()),
+        (None, None) => None,
+    };
+
+    // Load the models
+    status!("Loading models");
+    let pb = progress::spinner("Loading OCR models");
+    let detection_model = Model::load_file(detection_model_path)?;
+    let recognition_model = Model::load_file(rec_model_path)?;
+    pb.finish_and_clear();
+
+    let decode_method = match settings.ocr_beam_width {
+        Some(width) if width > 1 => DecodeMethod::BeamSearch { width },
+        _ => DecodeMethod::Greedy,
+    };
+
+    // Create OCR engine
+    let engine = OcrEngine::new(OcrEngineParams {
+        detection_model: Some(detection_model),
+        recognition_model: Some(recognition_model),
+        decode_method,
+        alphabet,
+        allowed_chars: settings.ocr_allowed_chars.clone(),
+        ..Default::default()
+    })?;
+    Ok(engine)
+}
+
+/// File name of the recognition model for the configured language.
+fn recognition_model_name() -> Result<String, String> {
+    Ok(match &config::settings().ocr_lang {
+        // ocr_lang from the config file hasn't been through the value parser
+        Some(lang) => format!("text-recognition-{}.rten", parse_lang(lang)?),
+        None => "text-recognition.rten".to_string(),
+    })
+}
+
+/// Where a model is looked for: the model directory, the current directory
+/// and the download cache, which is also where a missing model is fetched to.
+fn model_candidates(name: &str) -> [PathBuf; 3] {
+    let settings = config::settings();
+    // Models downloaded using the download-models.sh script from ocrs examples
+    let model_dir = settings.ocr_model_dir.clone()
+        .unwrap_or_else(|| PathBuf::from("../ocrs/ocrs/examples"));
+    let download_dir = settings.ocr_model_dir.clone()
+        .unwrap_or_else(|| cache::cache_dir("models"));
+    [model_dir.join(name), PathBuf::from(name), download_dir.join(name)]
+}
+
+/// The file model `name` is loaded from: the first candidate that exists, or
+/// the download location when none does yet.
+fn resolve_model(name: &str) -> PathBuf {
+    let [model_dir, current_dir, download] = model_candidates(name);
+    [model_dir, current_dir].into_iter().find(|path| path.exists()).unwrap_or(download)
+}
+
+/// Look for a model in the model directory, the current directory and the
+/// download cache, fetching it from the model URL if it's in none of them.
+async fn find_or_download(name: &str) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let candidates = model_candidates(name);
+    if let Some(path) = candidates.iter().find(|path| path.exists()) {
+        return Ok(path.clone());
+    }
+
+    let path = candidates[2].clone();
+    download_model(name, &path).await.map_err(|e| format!(
+        "OCR model {} not found and could not be downloaded: {}\nLooked for:\n- {}\n- {}\n- {}",
+        name, e, candidates[0].display(), candidates[1].display(), candidates[2].display()
+    ))?;
+
+    // An alphabet file is optional, only some recognition models have one
+    if name.starts_with("text-recognition-") {
+        let _ = download_model(&name.replace(".rten", ".txt"), &path.with_extension("txt")).await;
+    }
+    Ok(path)
+}
+
+async fn download_model(name: &str, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    let url = format!("{}/{}", config::settings().ocr_model_url.trim_end_matches('/'), name);
+    let pb = progress::spinner(format!("Downloading {}", name));
+    status!("Downloading {}", url);
+    let response = reqwest::get(&url).await;
+    pb.finish_and_clear();
+
+    let bytes = response?.error_for_status()?.bytes().await?;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // Write to a temporary name first so an interrupted download isn't mistaken for a model
+    let partial = path.with_extension("part");
+    std::fs::write(&partial, &bytes)?;
+    std::fs::rename(&partial, path)?;
+    Ok(())
+}
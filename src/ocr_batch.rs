@@ -0,0 +1,210 @@
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Mutex};
+use std::time::Instant;
+
+use serde::Serialize;
+
+use crate::bundle::Bundle;
+use crate::error::ErrorKind;
+use crate::ocr::SharedEngine;
+use crate::{config, output};
+
+/// Extensions of the files a directory input expands to.
+const IMAGE_EXTENSIONS: &[&str] = &["png", "jpg", "jpeg", "bmp", "gif", "webp", "tif", "tiff"];
+
+/// OCR result for one input file.
+#[derive(Serialize)]
+pub struct FileResult {
+    pub file: String,
+    /// "ok" or "error"
+    pub status: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub lines: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// Time spent loading and recognizing the file
+    pub ms: u128,
+}
+
+#[derive(Serialize)]
+pub struct OcrBatchReport {
+    /// "ok" when every file was recognized, otherwise "partial"
+    pub status: String,
+    pub processed: usize,
+    pub failed: usize,
+    pub jobs: usize,
+    /// Archive holding the transcripts and this report, with `--bundle`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bundle: Option<String>,
+    /// Per-file results in input order. Empty with `--output-format jsonl`,
+    /// which prints each one as its own line as soon as it is done.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<FileResult>,
+    /// Set when any file failed, used for the exit code
+    #[serde(skip)]
+    pub failure: Option<ErrorKind>,
+}
+
+/// The `ocr-batch` command: recognize the text of every image `inputs` name
+/// (files, directories or glob patterns) on `jobs` worker threads sharing one
+/// loaded OCR engine. With `bundle`, each transcript is streamed into that
+/// archive as `<file name>.txt` as soon as it is done, followed by the report.
+pub async fn ocr_batch(inputs: &[String], jobs: usize, use_cache: bool, bundle: Option<&str>) -> Result<OcrBatchReport, Box<dyn std::error::Error>> {
+    let paths = expand_inputs(inputs)?;
+    if paths.is_empty() {
+        return Err(format!("No images match {}", inputs.join(" ")).into());
+    }
+    let jobs = jobs.clamp(1, paths.len());
+
+    // Loading the models is the slow part, so it happens once for all workers
+    let engine = SharedEngine::shared().await?;
+    let bundle = bundle.map(|path| Bundle::create(&config::resolve_output_path(path))).transpose()?;
+    status!("Recognizing {} files with {} jobs", paths.len(), jobs);
+    let started = Instant::now();
+
+    let total = paths.len();
+    // The workers and the loop collecting their results block, so they run on
+    // the blocking pool rather than an executor thread
+    let (mut results, failed, bundle, bundle_error) = tokio::task::spawn_blocking(move || {
+        let mut bundle = bundle;
+        let (results, failed, bundle_error) = run_workers(&engine, &paths, jobs, use_cache, bundle.as_mut());
+        (results, failed, bundle, bundle_error)
+    }).await?;
+
+    if let Some(e) = bundle_error {
+        return Err(format!("Failed to write to the bundle: {}", e).into());
+    }
+
+    results.sort_by_key(|(index, _)| *index);
+    let files: Vec<FileResult> = results.into_iter().map(|(_, file)| file).collect();
+    for file in &files {
+        match (&file.text, &file.error) {
+            (Some(text), _) => result!("==> {} <==\n{}\n", file.file, text),
+            (None, error) => result!("==> {} <==\nError: {}\n", file.file, error.as_deref().unwrap_or_default()),
+        }
+    }
+    status!("Recognized {} of {} files in {:.1}s", total - failed, total, started.elapsed().as_secs_f64());
+
+    let report = OcrBatchReport {
+        status: if failed > 0 { "partial" } else { "ok" }.to_string(),
+        processed: total,
+        failed,
+        jobs,
+        bundle: bundle.as_ref().map(|bundle| bundle.path().to_string()),
+        files,
+        failure: (failed > 0).then_some(ErrorKind::OcrFailed),
+    };
+    if let Some(mut bundle) = bundle {
+        bundle.add_json("report.json", &report)?;
+        bundle.finish()?;
+    }
+    Ok(report)
+}
+
+/// Recognize `paths` on `jobs` scoped worker threads, reporting each file as
+/// it is done and streaming its transcript into `bundle`. Returns the results
+/// with their input index, the number that failed and the first bundle error.
+fn run_workers(engine: &SharedEngine, paths: &[PathBuf], jobs: usize, use_cache: bool, mut bundle: Option<&mut Bundle>) -> (Vec<(usize, FileResult)>, usize, Option<String>) {
+    let mut results = Vec::with_capacity(paths.len());
+    let mut failed = 0;
+    let mut bundle_error = None;
+    let queue = Mutex::new(paths.iter().enumerate());
+    let (sender, receiver) = mpsc::channel();
+    std::thread::scope(|scope| {
+        for _ in 0..jobs {
+            let (queue, sender) = (&queue, sender.clone());
+            scope.spawn(move || {
+                loop {
+                    let Some((index, path)) = queue.lock().unwrap().next() else {
+                        break;
+                    };
+                    if sender.send((index, recognize_file(engine, path, use_cache))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (index, file) in receiver {
+            match &file.error {
+                Some(error) => {
+                    failed += 1;
+                    status!("{}: {}", file.file, error);
+                }
+                None => status!("{}: {} lines in {} ms", file.file, file.lines.as_ref().map_or(0, Vec::len), file.ms),
+            }
+            if let (Some(bundle), Some(text), None) = (&mut bundle, &file.text, &bundle_error) {
+                let name = format!("{}.txt", Path::new(&file.file).file_name().unwrap_or_default().to_string_lossy());
+                // Workers keep going; the error is returned once they are done
+                bundle_error = bundle.add(&name, text.as_bytes()).err().map(|e| e.to_string());
+            }
+            if output::is_jsonl() {
+                output::event(&serde_json::to_value(&file).unwrap_or_default());
+            } else {
+                results.push((index, file));
+            }
+        }
+    });
+    (results, failed, bundle_error)
+}
+
+/// Image files named by `inputs`: files as given, directories by the images
+/// directly inside them, and anything else as a glob pattern (for shells that
+/// don't expand them, or quoted patterns).
+fn expand_inputs(inputs: &[String]) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut paths = Vec::new();
+    for input in inputs {
+        let path = Path::new(input);
+        if path.is_dir() {
+            let mut entries: Vec<PathBuf> = std::fs::read_dir(path)
+                .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_file() && is_image(path))
+                .collect();
+            entries.sort();
+            paths.extend(entries);
+        } else if path.exists() {
+            paths.push(path.to_path_buf());
+        } else {
+            let matches = glob::glob(input).map_err(|e| format!("Invalid pattern \"{}\": {}", input, e))?;
+            let mut matched: Vec<PathBuf> = matches.filter_map(Result::ok).filter(|path| path.is_file()).collect();
+            if matched.is_empty() {
+                return Err(format!("No files match {}", input).into());
+            }
+            matched.sort();
+            paths.append(&mut matched);
+        }
+    }
+    Ok(paths)
+}
+
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+fn recognize_file(engine: &SharedEngine, path: &Path, use_cache: bool) -> FileResult {
+    let started = Instant::now();
+    let recognized = image::open(path)
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))
+        .and_then(|image| {
+            let image = image.to_rgb8();
+            engine.extract_text(image.width(), image.height(), image.as_raw(), use_cache)
+        });
+    let (status, lines, error) = match recognized {
+        Ok(lines) => ("ok", Some(lines), None),
+        Err(e) => ("error", None, Some(e)),
+    };
+    FileResult {
+        file: path.display().to_string(),
+        status: status.to_string(),
+        text: lines.as_ref().map(|lines| lines.join("\n")),
+        lines,
+        error,
+        ms: started.elapsed().as_millis(),
+    }
+}
@@ -0,0 +1,343 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::coords::Rect;
+use crate::edid::Monitor;
+use crate::error::{error_kind, ErrorKind};
+use crate::extract::Extracted;
+use crate::layout::{LineBox, WordBox};
+use crate::llm::{ImageMapping, Usage};
+use crate::pause::Gap;
+use crate::quality::Quality;
+use crate::spell;
+use crate::timestamp::FrameTimes;
+use crate::validate::{BlackFrame, BlankFrame};
+
+#[derive(Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable progress and results on stdout
+    #[default]
+    Text,
+    /// A single JSON document on stdout, human text on stderr
+    Json,
+    /// Like json, but one compact line per record (batch commands) or event, then the result
+    Jsonl,
+}
+
+static FORMAT: OnceLock<OutputFormat> = OnceLock::new();
+/// Quiet until the binary turns progress text on, so embedding the library
+/// prints nothing of its own
+static QUIET: AtomicBool = AtomicBool::new(true);
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+pub fn set_format(format: OutputFormat) {
+    let _ = FORMAT.set(format);
+}
+
+pub fn is_json() -> bool {
+    matches!(FORMAT.get().copied().unwrap_or_default(), OutputFormat::Json | OutputFormat::Jsonl)
+}
+
+pub fn is_jsonl() -> bool {
+    FORMAT.get().copied().unwrap_or_default() == OutputFormat::Jsonl
+}
+
+pub fn set_quiet(quiet: bool) {
+    QUIET.store(quiet, Ordering::Relaxed);
+}
+
+pub fn is_quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
+}
+
+/// Keep stdout for the capture itself (`--to stdout`): text that would
+/// go there is printed on stderr instead.
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::Relaxed);
+}
+
+/// Whether human readable text goes to stderr, because stdout carries a JSON
+/// document or the capture itself.
+pub fn text_on_stderr() -> bool {
+    is_json() || STDOUT_RESERVED.load(Ordering::Relaxed)
+}
+
+/// Print human readable progress text. Goes to stdout normally and to stderr in
+/// JSON mode so stdout only carries the result document. Suppressed by --quiet,
+/// and in the library unless `set_quiet(false)` turns it on.
+#[macro_export]
+macro_rules! status {
+    ($($arg:tt)*) => {
+        if !$crate::output::is_quiet() {
+            if $crate::output::text_on_stderr() {
+                eprintln!($($arg)*)
+            } else {
+                println!($($arg)*)
+            }
+        }
+    };
+}
+
+/// Print a command result (OCR text, analysis, ...). Always shown in text mode,
+/// even with --quiet; in JSON mode the result is already in the document.
+#[macro_export]
+macro_rules! result {
+    ($($arg:tt)*) => {
+        if !$crate::output::text_on_stderr() {
+            println!($($arg)*)
+        } else if !$crate::output::is_json() || !$crate::output::is_quiet() {
+            eprintln!($($arg)*)
+        }
+    };
+}
+
+/// Finish the run: print the result document in JSON mode, report errors, and
+/// exit with the code for the failure category. `partial_failure` marks runs
+/// that produced a result but where a requested step (OCR, analysis) failed.
+pub fn emit(result: Result<serde_json::Value, Box<dyn std::error::Error>>, partial_failure: Option<ErrorKind>) -> ! {
+    let (document, failure) = match result {
+        Ok(document) => (document, partial_failure),
+        Err(e) => {
+            let kind = error_kind(e.as_ref());
+            if !is_json() {
+                eprintln!("Error: {}", e);
+            }
+            let document = serde_json::json!({
+                "status": "error",
+                "error_kind": kind.name(),
+                "exit_code": kind.exit_code(),
+                "error": e.to_string(),
+            });
+            (document, Some(kind))
+        }
+    };
+
+    let mut document = with_run_id(document);
+    if crate::timings::is_enabled() {
+        let timings = crate::timings::summary();
+        crate::timings::print(&timings);
+        if let Some(object) = document.as_object_mut() {
+            object.insert("timings".to_string(), serde_json::to_value(&timings).unwrap_or_default());
+        }
+    }
+    if is_jsonl() && !document.is_null() {
+        println!("{}", document);
+    } else if is_json() && !document.is_null() {
+        println!("{}", serde_json::to_string_pretty(&document).unwrap_or_default());
+    }
+
+    std::process::exit(failure.map(ErrorKind::exit_code).unwrap_or(0));
+}
+
+/// Print one compact JSON event line on stdout, for continuous modes that
+/// produce results until interrupted. Nothing is printed in text mode.
+pub fn event(event: &serde_json::Value) {
+    if is_json() {
+        println!("{}", with_run_id(event.clone()));
+    }
+}
+
+/// Add the run ID to a JSON object, so documents from one job can be matched
+/// up downstream. Anything else is returned unchanged.
+pub fn with_run_id(mut value: serde_json::Value) -> serde_json::Value {
+    if let Some(object) = value.as_object_mut() {
+        object.entry("run_id").or_insert_with(|| crate::config::settings().run_id.clone().into());
+    }
+    value
+}
+
+#[derive(Serialize)]
+pub struct ScreenInfo {
+    pub index: usize,
+    pub id: u32,
+    pub title: String,
+    /// Position and size on the desktop, where the platform reports it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bounds: Option<Rect>,
+    /// Maker, model and serial from the monitor's EDID, where the platform reports them
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub monitor: Option<Monitor>,
+}
+
+#[derive(Serialize)]
+pub struct WindowSummary {
+    pub index: usize,
+    pub id: u32,
+    pub title: String,
+    /// Process owning the window, where the platform reports it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pid: Option<u32>,
+    /// Name of that process: the app on macOS, the executable on Windows
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub process: Option<String>,
+    /// Position and size on the desktop, where the platform reports it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bounds: Option<Rect>,
+}
+
+#[derive(Serialize, Default)]
+pub struct TargetInfo {
+    pub kind: String,
+    pub index: usize,
+    pub id: u32,
+    pub title: String,
+}
+
+#[derive(Serialize, Default)]
+pub struct OcrReport {
+    pub text: String,
+    pub lines: Vec<String>,
+    /// Every word as recognized, with its box, baseline angle and plausibility heuristic
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub words: Vec<OcrWord>,
+    /// The text before `--ocr-dictionary` corrections, when they are enabled
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_lines: Option<Vec<String>>,
+}
+
+impl OcrReport {
+    /// Report lines in reading order, numbering each word with its line and
+    /// applying any `--ocr-dictionary` corrections to the text.
+    pub fn from_lines(lines: Vec<LineBox>) -> Self {
+        let words = lines.iter().enumerate()
+            .flat_map(|(line, line_box)| line_box.words.iter().map(move |word| OcrWord { line, word: word.clone() }))
+            .collect();
+        let raw_lines: Vec<String> = lines.into_iter().map(|line| line.text).collect();
+        match spell::correct_lines(&raw_lines) {
+            Some(lines) => Self {
+                text: lines.join("\n"),
+                lines,
+                words,
+                raw_text: Some(raw_lines.join("\n")),
+                raw_lines: Some(raw_lines),
+            },
+            None => Self { text: raw_lines.join("\n"), lines: raw_lines, words, raw_text: None, raw_lines: None },
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct OcrWord {
+    /// Index of the word's line in `lines`
+    pub line: usize,
+    #[serde(flatten)]
+    pub word: WordBox,
+}
+
+#[derive(Serialize, Default)]
+pub struct Timing {
+    pub capture_ms: u128,
+    pub encode_ms: u128,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ocr_ms: Option<u128>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub analysis_ms: Option<u128>,
+    pub total_ms: u128,
+}
+
+#[derive(Serialize, Default)]
+pub struct CaptureReport {
+    pub status: String,
+    pub target: TargetInfo,
+    pub width: u32,
+    pub height: u32,
+    pub output: Option<String>,
+    /// When the frame was on screen and when it arrived
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_times: Option<FrameTimes>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ocr: Option<OcrReport>,
+    /// Values captured by `--extract` from the OCR text
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extracted: Option<Extracted>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub analysis: Option<String>,
+    /// The model's answer before its coordinates were mapped back to screen pixels
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub raw_analysis: Option<String>,
+    /// Size of the image the LLM was sent and how its pixels map to the screen
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub llm_image: Option<ImageMapping>,
+    /// The images the LLM was sent when the frame was analyzed in tiles
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub llm_tiles: Vec<ImageMapping>,
+    /// Set when the frame, or a large area of it, came back pure black
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub black_frame: Option<BlackFrame>,
+    /// Set when `--no-validate` kept a frame that is one color or has no detail
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blank_frame: Option<BlankFrame>,
+    /// Sharpness, entropy and text density, with `--report-quality`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub quality: Option<Quality>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub share: Option<ShareReport>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub uploads: Vec<UploadReport>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sinks: Vec<SinkReport>,
+    /// Tokens used by the analysis request (absent for cache hits)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    /// Non-fatal failures, e.g. OCR or analysis errors after a successful capture
+    pub errors: Vec<String>,
+    pub timing: Timing,
+    /// Category of the first requested step that failed, used for the exit code
+    #[serde(skip)]
+    pub failure: Option<ErrorKind>,
+}
+
+/// Where `--share` uploaded a capture.
+#[derive(Serialize)]
+pub struct ShareReport {
+    pub url: String,
+    pub host: String,
+    /// Unix milliseconds when the host deletes the upload, if it said
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
+    /// Token or hash for deleting the upload early
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delete_token: Option<String>,
+}
+
+/// Where a `--to` sink delivered a capture.
+#[derive(Serialize)]
+pub struct SinkReport {
+    /// The `--to` destination, without any password
+    pub sink: String,
+    /// Path, URL, topic or other place the capture ended up
+    pub location: String,
+}
+
+/// Where `--upload` copied a capture.
+#[derive(Serialize)]
+pub struct UploadReport {
+    /// The `--upload` destination, without any password
+    pub destination: String,
+    /// Remote path or URL of the uploaded file
+    pub location: String,
+}
+
+#[derive(Serialize)]
+pub struct RecordReport {
+    pub status: String,
+    pub target: String,
+    pub output: String,
+    pub frames: usize,
+    pub duration_ms: u64,
+    /// Times the screen was locked or asleep; they are cut from the recording
+    pub gaps: Vec<Gap>,
+    /// Why recording stopped: "stopped", "duration", "target_closed" or "capture_ended"
+    pub ended: String,
+    /// Per-frame timestamps file, with `--timestamp-sidecar`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_times: Option<String>,
+    /// Set when the recorded window closed, used for the exit code
+    #[serde(skip)]
+    pub failure: Option<ErrorKind>,
+}
@@ -0,0 +1,59 @@
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+use crate::platforms;
+
+/// How often the session is checked for a locked or sleeping screen.
+const CHECK_INTERVAL: Duration = Duration::from_secs(2);
+
+/// A stretch of time in which capture was paused.
+#[derive(Clone, Serialize)]
+pub struct Gap {
+    /// "locked", "screensaver" or "display_asleep"
+    pub reason: &'static str,
+    /// When the pause started, in Unix milliseconds
+    pub started_at: u128,
+    pub duration_ms: u128,
+}
+
+/// Notices when the session is locked, the screensaver is running or the
+/// display sleeps, so continuous captures can pause instead of collecting
+/// black frames.
+pub struct PauseMonitor {
+    next_check: Instant,
+}
+
+impl Default for PauseMonitor {
+    fn default() -> Self {
+        Self { next_check: Instant::now() }
+    }
+}
+
+impl PauseMonitor {
+    /// Why capture should pause, once the screen has become unavailable;
+    /// otherwise None. Cheap to call on every frame.
+    pub fn poll(&mut self) -> Option<&'static str> {
+        let now = Instant::now();
+        if now < self.next_check {
+            return None;
+        }
+        self.next_check = now + CHECK_INTERVAL;
+        platforms::screen_unavailable()
+    }
+
+    /// Wait until the screen is available again and describe the gap.
+    pub async fn wait_for_resume(&mut self, reason: &'static str) -> Gap {
+        let started = Instant::now();
+        let started_at = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+        status!("Screen {}, pausing capture", reason.replace('_', " "));
+        while platforms::screen_unavailable().is_some() {
+            tokio::time::sleep(CHECK_INTERVAL).await;
+        }
+        self.next_check = Instant::now() + CHECK_INTERVAL;
+
+        let duration = started.elapsed();
+        status!("Screen available again after {:.0}s, resuming capture", duration.as_secs_f64());
+        Gap { reason, started_at, duration_ms: duration.as_millis() }
+    }
+}
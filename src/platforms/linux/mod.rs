@@ -1,23 +1,269 @@
 // Import from the local scap library
 use scap::Target;
+use std::process::Command;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Sender;
 
 pub fn list_windows() -> Result<(), Box<dyn std::error::Error>> {
     if scap::is_supported() {
         let targets = scap::get_all_targets();
 
-        println!("Available windows:");
-        println!("==================");
+        status!("Available windows:");
+        status!("==================");
 
         let mut window_index = 0;
         for target in targets.iter() {
             if let Target::Window(window) = target {
-                println!("Window {}: ID {}, Title: {}", window_index, window.id, window.title);
+                status!("Window {}: ID {}, Title: {}", window_index, window.id, window.title);
                 window_index += 1;
             }
         }
     } else {
-        println!("Screen capture not supported");
+        status!("Screen capture not supported");
     }
 
     Ok(())
+}
+
+/// Whether no X11 or Wayland session is reachable, as on kiosks and
+/// embedded devices that draw straight to the framebuffer.
+pub fn headless() -> bool {
+    std::env::var_os("DISPLAY").is_none() && std::env::var_os("WAYLAND_DISPLAY").is_none()
+}
+
+/// Read the visible screen of the framebuffer device `/dev/fb<index>`,
+/// returning its width, height and top-down BGRA pixels. KMS drivers expose
+/// their primary plane here through fbdev emulation, so this works without
+/// a display server, but needs read access to the device (usually the
+/// `video` group).
+pub fn capture_framebuffer(index: usize) -> Result<(u32, u32, Vec<u8>), String> {
+    let attribute = |name: &str| {
+        let path = format!("/sys/class/graphics/fb{}/{}", index, name);
+        std::fs::read_to_string(&path).map(|value| value.trim().to_string()).map_err(|e| format!("Failed to read {}: {}", path, e))
+    };
+    let pair = |value: String| -> Result<(usize, usize), String> {
+        let (a, b) = value.split_once(',').ok_or_else(|| format!("Unexpected framebuffer attribute '{}'", value))?;
+        Ok((a.parse().map_err(|_| format!("Unexpected framebuffer attribute '{}'", value))?,
+            b.parse().map_err(|_| format!("Unexpected framebuffer attribute '{}'", value))?))
+    };
+    // virtual_size can be larger than the screen for panning; modes lists
+    // the active mode first, e.g. "U:1920x1080p-60"
+    let modes = attribute("modes").unwrap_or_default();
+    let mode = modes.lines().next().and_then(|mode| mode.split_once(':')?.1.split(['p', 'i', '-']).next()?.split_once('x'));
+    let (width, height) = match mode {
+        Some((width, height)) => (width.parse().unwrap_or(0), height.parse().unwrap_or(0)),
+        None => pair(attribute("virtual_size")?)?,
+    };
+    let bits_per_pixel: usize = attribute("bits_per_pixel")?.parse().map_err(|_| "Unexpected framebuffer depth".to_string())?;
+    let stride: usize = attribute("stride")?.parse().map_err(|_| "Unexpected framebuffer stride".to_string())?;
+    let (x_offset, y_offset) = attribute("pan").and_then(pair).unwrap_or((0, 0));
+    if width == 0 || height == 0 {
+        return Err(format!("Framebuffer {} has no active mode", index));
+    }
+    if ![16, 24, 32].contains(&bits_per_pixel) {
+        return Err(format!("Unsupported framebuffer depth of {} bits", bits_per_pixel));
+    }
+    let bytes_per_pixel = bits_per_pixel / 8;
+    if (x_offset + width) * bytes_per_pixel > stride {
+        return Err(format!("Framebuffer {} is narrower than its mode", index));
+    }
+
+    let device = format!("/dev/fb{}", index);
+    let mut raw = vec![0u8; stride * height];
+    let mut file = std::fs::File::open(&device).map_err(|e| format!("Failed to open {}: {}", device, e))?;
+    std::io::Seek::seek(&mut file, std::io::SeekFrom::Start((stride * y_offset) as u64))
+        .and_then(|_| std::io::Read::read_exact(&mut file, &mut raw))
+        .map_err(|e| format!("Failed to read {}: {}", device, e))?;
+
+    let mut data = Vec::with_capacity(width * height * 4);
+    for row in raw.chunks_exact(stride) {
+        let row = &row[x_offset * bytes_per_pixel..(x_offset + width) * bytes_per_pixel];
+        match bits_per_pixel {
+            // XRGB8888 and RGB888, stored little-endian
+            32 => data.extend(row.chunks_exact(4).flat_map(|p| [p[0], p[1], p[2], 255])),
+            24 => data.extend(row.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255])),
+            // RGB565
+            16 => data.extend(row.chunks_exact(2).flat_map(|p| {
+                let value = u16::from_le_bytes([p[0], p[1]]);
+                let (r, g, b) = ((value >> 11) & 0x1f, (value >> 5) & 0x3f, value & 0x1f);
+                [(b << 3 | b >> 2) as u8, (g << 2 | g >> 4) as u8, (r << 3 | r >> 2) as u8, 255]
+            })),
+            _ => unreachable!("depth checked above"),
+        }
+    }
+    Ok((width as u32, height as u32, data))
+}
+
+/// The virtual X server behind `DISPLAY`, such as "Xvfb :99", found by
+/// looking for an Xvfb, Xvnc or Xdummy process serving that display. None
+/// for a real display or a Wayland session.
+pub fn virtual_display() -> Option<String> {
+    let display = std::env::var("DISPLAY").ok()?;
+    // ":99.0" and "localhost:99" are both served by the server started with ":99"
+    let number = display.rsplit(':').next()?.split('.').next()?.to_string();
+    for entry in std::fs::read_dir("/proc").ok()?.flatten() {
+        let Ok(cmdline) = std::fs::read(entry.path().join("cmdline")) else {
+            continue;
+        };
+        let mut args = cmdline.split(|&b| b == 0).map(String::from_utf8_lossy);
+        let Some(program) = args.next() else {
+            continue;
+        };
+        let program = program.rsplit('/').next().unwrap_or_default().to_string();
+        if !["Xvfb", "Xvnc", "Xdummy"].contains(&program.as_str()) {
+            continue;
+        }
+        // Without a display argument the server takes :0
+        let served = args.find(|arg| arg.starts_with(':')).map(|arg| arg[1..].to_string()).unwrap_or_else(|| "0".to_string());
+        if served == number {
+            return Some(format!("{} :{}", program, number));
+        }
+    }
+    None
+}
+
+/// Window geometry isn't available here; PipeWire window streams already
+/// follow their window, so there is nothing to reconfigure.
+pub fn window_bounds(_window_id: u32) -> Option<(i32, i32, i32, i32)> {
+    None
+}
+
+/// Why the screen can't show anything useful right now ("locked"), or None
+/// if it can. Asks logind, so sessions without systemd are never locked.
+pub fn screen_unavailable() -> Option<&'static str> {
+    let session = std::env::var("XDG_SESSION_ID").unwrap_or_else(|_| "auto".to_string());
+    let output = Command::new("loginctl")
+        .args(["show-session", &session, "--property=LockedHint", "--value"])
+        .output()
+        .ok()?;
+    (output.status.success() && String::from_utf8_lossy(&output.stdout).trim() == "yes").then_some("locked")
+}
+
+/// Class and title of the active X11 window, read with `xprop`, or None
+/// where that isn't available (e.g. Wayland sessions without XWayland focus).
+pub fn active_window() -> Option<(String, String)> {
+    let xprop = |args: &[&str]| {
+        let output = Command::new("xprop").args(args).output().ok()?;
+        output.status.success().then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    // "_NET_ACTIVE_WINDOW(WINDOW): window id # 0x3a00007"
+    let active = xprop(&["-root", "_NET_ACTIVE_WINDOW"])?;
+    let window = active.split_whitespace().last().filter(|id| id.starts_with("0x") && *id != "0x0")?.to_string();
+    let properties = xprop(&["-id", &window, "WM_CLASS", "_NET_WM_NAME"])?;
+
+    // Values are quoted: WM_CLASS(STRING) = "instance", "Class"
+    let quoted = |line: &str| -> Vec<String> {
+        line.split('"').skip(1).step_by(2).map(str::to_string).collect()
+    };
+    let mut app = String::new();
+    let mut title = String::new();
+    for line in properties.lines() {
+        if line.starts_with("WM_CLASS") {
+            app = quoted(line).pop().unwrap_or_default();
+        } else if line.starts_with("_NET_WM_NAME") {
+            title = line.split_once(" = ").map(|(_, value)| value.trim().trim_matches('"').replace("\\\"", "\"")).unwrap_or_default();
+        }
+    }
+    Some((app, title))
+}
+
+/// Keystrokes can't be read here.
+pub fn watch_keys(_keys: Sender<super::KeyPress>, _stop: &AtomicBool) -> Result<(), String> {
+    Err("Keystrokes can't be read on Linux".to_string())
+}
+
+/// Per-app audio can't be captured here.
+pub fn watch_app_audio(_pid: u32, _chunks: Sender<Vec<f32>>, _stop: &AtomicBool) -> Result<(), String> {
+    Err("Per-app audio capture isn't supported on Linux".to_string())
+}
+
+/// The pointer isn't available here.
+pub fn pointer() -> Option<(i32, i32, bool)> {
+    None
+}
+
+/// Display geometry isn't available here.
+pub fn display_bounds(_display_id: u32) -> Option<(i32, i32, i32, i32)> {
+    None
+}
+
+/// The monitor of the only connected DRM output, from its EDID in sysfs.
+/// Display IDs can't be matched to outputs here, so with several monitors
+/// connected none is reported rather than possibly the wrong one.
+pub fn display_monitor(_display_id: u32) -> Option<crate::edid::Monitor> {
+    let mut edids = std::fs::read_dir("/sys/class/drm").ok()?
+        .filter_map(Result::ok)
+        .filter(|entry| std::fs::read_to_string(entry.path().join("status")).is_ok_and(|status| status.trim() == "connected"))
+        .filter_map(|entry| std::fs::read(entry.path().join("edid")).ok())
+        .filter(|edid| !edid.is_empty());
+    let edid = edids.next()?;
+    if edids.next().is_some() {
+        return None;
+    }
+    crate::edid::parse(&edid)
+}
+
+/// Window owners aren't available here.
+pub fn window_pid(_window_id: u32) -> Option<u32> {
+    None
+}
+
+/// Window owners aren't available here.
+pub fn window_process(_window_id: u32) -> Option<String> {
+    None
+}
+
+/// Icon of the X11 window with `window_id` from its `_NET_WM_ICON` property,
+/// read with `xprop`, as width, height and top-down RGBA pixels. Windows
+/// usually carry several sizes; the smallest at least `size` pixels wide is
+/// taken, or the largest if none is.
+pub fn window_icon(window_id: u32, size: u32) -> Result<(u32, u32, Vec<u8>), String> {
+    let output = Command::new("xprop")
+        .args(["-id", &window_id.to_string(), "-notype", "32c", "_NET_WM_ICON"])
+        .output()
+        .map_err(|e| format!("Reading window icons needs xprop: {}", e))?;
+    if !output.status.success() {
+        return Err(format!("Window {} isn't an X11 window", window_id));
+    }
+    // "_NET_WM_ICON = 16, 16, 4294967295, ..." holds width, height and ARGB
+    // pixels for each size in turn
+    let text = String::from_utf8_lossy(&output.stdout);
+    let values: Vec<u32> = text.split_once(" = ")
+        .map(|(_, values)| values.split(',').filter_map(|value| value.trim().parse().ok()).collect())
+        .unwrap_or_default();
+    let mut icons = Vec::new();
+    let mut at = 0;
+    while at + 2 <= values.len() {
+        let (width, height) = (values[at], values[at + 1]);
+        let end = at + 2 + width as usize * height as usize;
+        if width == 0 || height == 0 || end > values.len() {
+            break;
+        }
+        icons.push((width, height, &values[at + 2..end]));
+        at = end;
+    }
+    let (width, height, pixels) = icons.iter()
+        .filter(|(width, _, _)| *width >= size)
+        .min_by_key(|(width, _, _)| *width)
+        .or_else(|| icons.iter().max_by_key(|(width, _, _)| *width))
+        .ok_or_else(|| format!("Window {} has no icon", window_id))?;
+    let rgba = pixels.iter().flat_map(|argb| {
+        let [alpha, red, green, blue] = argb.to_be_bytes();
+        [red, green, blue, alpha]
+    }).collect();
+    Ok((*width, *height, rgba))
+}
+
+/// Scroll the X11 window with `window_id` by `lines` wheel notches (positive
+/// scrolls down) with `xdotool`, which moves the pointer over the window and
+/// clicks the wheel buttons.
+pub fn scroll(window_id: u32, lines: i32) -> Result<(), String> {
+    let window = window_id.to_string();
+    let button = if lines < 0 { "4" } else { "5" };
+    let repeat = lines.unsigned_abs().to_string();
+    let status = Command::new("xdotool")
+        .args(["mousemove", "--window", &window, "50%", "50%", "click", "--repeat", &repeat, "--delay", "20", button])
+        .status()
+        .map_err(|e| format!("Scrolling needs xdotool: {}", e))?;
+    status.success().then_some(()).ok_or_else(|| format!("xdotool failed to scroll window {}", window_id))
 }
\ No newline at end of file
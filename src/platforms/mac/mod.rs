@@ -1,12 +1,60 @@
 use std::collections::HashMap;
+use std::ffi::{c_char, c_void, CStr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
+use std::time::Duration;
 
 // Import macOS Core Graphics APIs
-use core_graphics_helmer_fork::window::{CGWindowListCopyWindowInfo, kCGWindowListOptionAll};
-use core_foundation::{array::CFArray, dictionary::CFDictionary, string::CFString, number::CFNumber, base::{TCFType, ToVoid}};
+use core_graphics_helmer_fork::event::{
+    CGEvent, CGEventFlags, CGEventTap, CGEventTapLocation, CGEventTapOptions, CGEventTapPlacement, CGEventType, CGMouseButton, EventField, ScrollEventUnit,
+};
+use core_graphics_helmer_fork::event_source::{CGEventSource, CGEventSourceStateID};
+use core_graphics_helmer_fork::geometry::{CGPoint, CGRect, CGSize};
+use core_graphics_helmer_fork::window::{
+    CGWindowListCopyWindowInfo, kCGNullWindowID, kCGWindowListExcludeDesktopElements, kCGWindowListOptionAll,
+    kCGWindowListOptionIncludingWindow, kCGWindowListOptionOnScreenOnly,
+};
+use core_foundation::runloop::{kCFRunLoopCommonModes, kCFRunLoopDefaultMode, CFRunLoop};
+use core_foundation::{array::CFArray, dictionary::{CFDictionary, CFDictionaryRef}, string::CFString, number::CFNumber, base::{TCFType, ToVoid}};
+
+#[link(name = "CoreGraphics", kind = "framework")]
+unsafe extern "C" {
+    fn CGSessionCopyCurrentDictionary() -> CFDictionaryRef;
+    fn CGMainDisplayID() -> u32;
+    fn CGDisplayIsAsleep(display: u32) -> u32;
+    fn CGDisplayVendorNumber(display: u32) -> u32;
+    fn CGDisplayModelNumber(display: u32) -> u32;
+    fn CGDisplaySerialNumber(display: u32) -> u32;
+    fn CGDisplayBounds(display: u32) -> CGRect;
+    fn CGEventSourceButtonState(state: i32, button: u32) -> bool;
+    fn CGColorSpaceCreateDeviceRGB() -> *mut c_void;
+    fn CGColorSpaceRelease(space: *mut c_void);
+    fn CGBitmapContextCreate(data: *mut c_void, width: usize, height: usize, bits_per_component: usize, bytes_per_row: usize, space: *mut c_void, bitmap_info: u32) -> *mut c_void;
+    fn CGContextDrawImage(context: *mut c_void, rect: CGRect, image: *mut c_void);
+    fn CGContextRelease(context: *mut c_void);
+}
+
+// NSRunningApplication and NSImage, reached through the Objective-C runtime
+#[link(name = "AppKit", kind = "framework")]
+unsafe extern "C" {}
+
+#[link(name = "objc")]
+unsafe extern "C" {
+    fn objc_getClass(name: *const c_char) -> *mut c_void;
+    fn sel_registerName(name: *const c_char) -> *mut c_void;
+    fn objc_msgSend();
+}
+
+/// RGBA bytes with alpha premultiplied into the colors.
+const K_CG_IMAGE_ALPHA_PREMULTIPLIED_LAST: u32 = 1;
+/// Vendor number ("unkn") of displays without an EDID.
+const UNKNOWN_VENDOR: u32 = 0x756e_6b6e;
 
 // Import from the local scap library
 use scap::Target;
 
+use crate::edid::Monitor;
+
 pub fn list_windows() -> Result<(), Box<dyn std::error::Error>> {
     // First, get windows from scap with their indices
     let mut scap_indices: HashMap<u32, usize> = HashMap::new();
@@ -60,7 +108,7 @@ pub fn list_windows() -> Result<(), Box<dyn std::error::Error>> {
                         "   -".to_string()
                     };
 
-                    println!("Idx:{} | ID:{:6} | PID:{:6} | Layer:{:12} | {:>8} | {:>1.2} | {:>4},{:<4} | {:>4}x{:<4} | {:<20} | {}",
+                    status!("Idx:{} | ID:{:6} | PID:{:6} | Layer:{:12} | {:>8} | {:>1.2} | {:>4},{:<4} | {:>4}x{:<4} | {:<20} | {}",
                         index_str,
                         window_id,
                         owner_pid,
@@ -85,13 +133,293 @@ pub fn list_windows() -> Result<(), Box<dyn std::error::Error>> {
             }
         }
 
-        println!("\nShowing {} of {} total windows ({} capturable via scap)",
+        status!("\nShowing {} of {} total windows ({} capturable via scap)",
                 shown_count, count, scap_indices.len());
     }
 
     Ok(())
 }
 
+/// Current bounds (x, y, width, height) of the window with `window_id`, or
+/// None if it no longer exists.
+pub fn window_bounds(window_id: u32) -> Option<(i32, i32, i32, i32)> {
+    unsafe {
+        let window_list = CGWindowListCopyWindowInfo(kCGWindowListOptionIncludingWindow, window_id);
+        if window_list.is_null() {
+            return None;
+        }
+        let windows_array: CFArray<CFDictionary> = CFArray::wrap_under_create_rule(window_list);
+        let window_dict = windows_array.get(0)?;
+        Some(get_window_bounds(&window_dict))
+    }
+}
+
+/// Why the screen can't show anything useful right now ("display_asleep" or
+/// "locked"), or None if it can.
+pub fn screen_unavailable() -> Option<&'static str> {
+    unsafe {
+        if CGDisplayIsAsleep(CGMainDisplayID()) != 0 {
+            return Some("display_asleep");
+        }
+        let session = CGSessionCopyCurrentDictionary();
+        if session.is_null() {
+            return None;
+        }
+        // The lock flag is only present in the session dictionary while locked
+        let session: CFDictionary = CFDictionary::wrap_under_create_rule(session);
+        let locked_key = CFString::new("CGSSessionScreenIsLocked");
+        session.find(locked_key.to_void()).map(|_| "locked")
+    }
+}
+
+/// Whether the main display is a virtual one, as in macOS VMs and CI
+/// runners: those report no vendor (or "unkn") where real panels carry an
+/// EDID vendor ID.
+pub fn virtual_display() -> Option<String> {
+    let vendor = unsafe { CGDisplayVendorNumber(CGMainDisplayID()) };
+    matches!(vendor, 0 | UNKNOWN_VENDOR | 0xffff_ffff).then(|| "virtual display (no EDID vendor)".to_string())
+}
+
+/// The monitor showing display `display_id`: maker, product and serial
+/// numbers come from CoreGraphics, which reads them from the EDID, and the
+/// model name from NSScreen.
+pub fn display_monitor(display_id: u32) -> Option<Monitor> {
+    let vendor = unsafe { CGDisplayVendorNumber(display_id) };
+    if matches!(vendor, 0 | UNKNOWN_VENDOR | 0xffff_ffff) {
+        return None;
+    }
+    let (product, serial) = unsafe { (CGDisplayModelNumber(display_id), CGDisplaySerialNumber(display_id)) };
+    Some(Monitor::from_numbers(vendor as u16, product as u16, serial, screen_name(display_id)))
+}
+
+/// Name of the NSScreen for display `display_id`, e.g. "LG HDR 4K".
+fn screen_name(display_id: u32) -> Option<String> {
+    unsafe {
+        let send: unsafe extern "C" fn(*mut c_void, *mut c_void) -> *mut c_void = std::mem::transmute(objc_msgSend as *const ());
+        let send_index: unsafe extern "C" fn(*mut c_void, *mut c_void, usize) -> *mut c_void = std::mem::transmute(objc_msgSend as *const ());
+        let send_object: unsafe extern "C" fn(*mut c_void, *mut c_void, *const c_void) -> *mut c_void = std::mem::transmute(objc_msgSend as *const ());
+        let send_count: unsafe extern "C" fn(*mut c_void, *mut c_void) -> usize = std::mem::transmute(objc_msgSend as *const ());
+        let send_u32: unsafe extern "C" fn(*mut c_void, *mut c_void) -> u32 = std::mem::transmute(objc_msgSend as *const ());
+        let send_text: unsafe extern "C" fn(*mut c_void, *mut c_void) -> *const c_char = std::mem::transmute(objc_msgSend as *const ());
+
+        let screens = send(objc_getClass(c"NSScreen".as_ptr()), sel_registerName(c"screens".as_ptr()));
+        if screens.is_null() {
+            return None;
+        }
+        // CFString is toll-free bridged to NSString
+        let key = CFString::from_static_string("NSScreenNumber");
+        for index in 0..send_count(screens, sel_registerName(c"count".as_ptr())) {
+            let screen = send_index(screens, sel_registerName(c"objectAtIndex:".as_ptr()), index);
+            let description = send(screen, sel_registerName(c"deviceDescription".as_ptr()));
+            let number = send_object(description, sel_registerName(c"objectForKey:".as_ptr()), key.as_concrete_TypeRef() as *const c_void);
+            if number.is_null() || send_u32(number, sel_registerName(c"unsignedIntValue".as_ptr())) != display_id {
+                continue;
+            }
+            // localizedName needs macOS 10.15
+            let name = send(screen, sel_registerName(c"localizedName".as_ptr()));
+            if name.is_null() {
+                return None;
+            }
+            let text = send_text(name, sel_registerName(c"UTF8String".as_ptr()));
+            return (!text.is_null()).then(|| CStr::from_ptr(text).to_string_lossy().into_owned());
+        }
+        None
+    }
+}
+
+/// ID of the process owning the window with `window_id`.
+pub fn window_pid(window_id: u32) -> Option<u32> {
+    unsafe {
+        let window_list = CGWindowListCopyWindowInfo(kCGWindowListOptionIncludingWindow, window_id);
+        if window_list.is_null() {
+            return None;
+        }
+        let windows_array: CFArray<CFDictionary> = CFArray::wrap_under_create_rule(window_list);
+        let window_dict = windows_array.get(0)?;
+        get_cf_number_value(&window_dict, "kCGWindowOwnerPID").map(|pid| pid as u32)
+    }
+}
+
+/// Name of the app owning the window with `window_id`, e.g. "Slack".
+pub fn window_process(window_id: u32) -> Option<String> {
+    unsafe {
+        let window_list = CGWindowListCopyWindowInfo(kCGWindowListOptionIncludingWindow, window_id);
+        if window_list.is_null() {
+            return None;
+        }
+        let windows_array: CFArray<CFDictionary> = CFArray::wrap_under_create_rule(window_list);
+        let window_dict = windows_array.get(0)?;
+        get_cf_string_value(&window_dict, "kCGWindowOwnerName")
+    }
+}
+
+/// Icon of the app owning the window with `window_id`, rendered `size`
+/// pixels square from the icon's best representation, as width, height and
+/// top-down RGBA pixels.
+pub fn window_icon(window_id: u32, size: u32) -> Result<(u32, u32, Vec<u8>), String> {
+    let pid = window_pid(window_id).ok_or_else(|| format!("Window {} not found", window_id))?;
+    unsafe {
+        let send_pid: unsafe extern "C" fn(*mut c_void, *mut c_void, i32) -> *mut c_void = std::mem::transmute(objc_msgSend as *const ());
+        let send: unsafe extern "C" fn(*mut c_void, *mut c_void) -> *mut c_void = std::mem::transmute(objc_msgSend as *const ());
+        let send_rect: unsafe extern "C" fn(*mut c_void, *mut c_void, *mut CGRect, *mut c_void, *mut c_void) -> *mut c_void =
+            std::mem::transmute(objc_msgSend as *const ());
+
+        let class = objc_getClass(c"NSRunningApplication".as_ptr());
+        let app = send_pid(class, sel_registerName(c"runningApplicationWithProcessIdentifier:".as_ptr()), pid as i32);
+        if app.is_null() {
+            return Err(format!("No app is running with pid {}", pid));
+        }
+        let icon = send(app, sel_registerName(c"icon".as_ptr()));
+        if icon.is_null() {
+            return Err(format!("The app owning window {} has no icon", window_id));
+        }
+        let mut rect = CGRect::new(&CGPoint::new(0.0, 0.0), &CGSize::new(size as f64, size as f64));
+        // Owned by the NSImage, not released here
+        let image = send_rect(icon, sel_registerName(c"CGImageForProposedRect:context:hints:".as_ptr()), &mut rect, std::ptr::null_mut(), std::ptr::null_mut());
+        if image.is_null() {
+            return Err(format!("Failed to render the icon of window {}", window_id));
+        }
+
+        let mut data = vec![0u8; size as usize * size as usize * 4];
+        let space = CGColorSpaceCreateDeviceRGB();
+        let context = CGBitmapContextCreate(data.as_mut_ptr() as *mut c_void, size as usize, size as usize, 8, size as usize * 4, space, K_CG_IMAGE_ALPHA_PREMULTIPLIED_LAST);
+        CGColorSpaceRelease(space);
+        if context.is_null() {
+            return Err("Failed to create a bitmap context".to_string());
+        }
+        CGContextDrawImage(context, CGRect::new(&CGPoint::new(0.0, 0.0), &CGSize::new(size as f64, size as f64)), image);
+        CGContextRelease(context);
+
+        for pixel in data.chunks_exact_mut(4) {
+            let alpha = pixel[3] as u32;
+            if alpha > 0 && alpha < 255 {
+                for value in &mut pixel[..3] {
+                    *value = (*value as u32 * 255 / alpha).min(255) as u8;
+                }
+            }
+        }
+        Ok((size, size, data))
+    }
+}
+
+/// Scroll the window with `window_id` by `lines` wheel notches (positive
+/// scrolls down): the pointer is moved to the window's center, since wheel
+/// events go to the window under it, and a scroll wheel event is posted.
+/// Posting events needs the Accessibility permission.
+pub fn scroll(window_id: u32, lines: i32) -> Result<(), String> {
+    let (x, y, width, height) = window_bounds(window_id).ok_or_else(|| format!("Window {} not found", window_id))?;
+    let center = CGPoint::new((x + width / 2) as f64, (y + height / 2) as f64);
+    let denied = "Failed to create input events; allow captest under System Settings > Privacy & Security > Accessibility";
+    let source = || CGEventSource::new(CGEventSourceStateID::HIDSystemState).map_err(|_| denied.to_string());
+    let moved = CGEvent::new_mouse_event(source()?, CGEventType::MouseMoved, center, CGMouseButton::Left).map_err(|_| denied)?;
+    moved.post(CGEventTapLocation::HID);
+    // Wheel deltas are positive for scrolling up
+    let wheel = CGEvent::new_scroll_event(source()?, ScrollEventUnit::LINE, 1, -lines, 0, 0).map_err(|_| denied)?;
+    wheel.post(CGEventTapLocation::HID);
+    Ok(())
+}
+
+/// Pointer position in global coordinates and whether a mouse button is
+/// down. Reading the state needs no input monitoring permission.
+pub fn pointer() -> Option<(i32, i32, bool)> {
+    let source = CGEventSource::new(CGEventSourceStateID::CombinedSessionState).ok()?;
+    let location = CGEvent::new(source).ok()?.location();
+    // Left, right and middle buttons of the combined session state
+    let pressed = (0..3).any(|button| unsafe { CGEventSourceButtonState(CGEventSourceStateID::CombinedSessionState as i32, button) });
+    Some((location.x as i32, location.y as i32, pressed))
+}
+
+/// Send every key pressed in the session to `keys` until `stop` is set.
+/// Listens through an event tap, which needs the Input Monitoring
+/// permission; blocks the calling thread.
+pub fn watch_keys(keys: Sender<super::KeyPress>, stop: &AtomicBool) -> Result<(), String> {
+    let denied = "Keystrokes can't be read; allow captest (or your terminal) under System Settings > Privacy & Security > Input Monitoring";
+    let tap = CGEventTap::new(
+        CGEventTapLocation::Session,
+        CGEventTapPlacement::TailAppendEventTap,
+        CGEventTapOptions::ListenOnly,
+        vec![CGEventType::KeyDown],
+        move |_, _, event| {
+            let keycode = event.get_integer_value_field(EventField::KEYBOARD_EVENT_KEYCODE);
+            if let Some(key) = key_name(keycode) {
+                let flags = event.get_flags();
+                let modifiers = [
+                    (CGEventFlags::CGEventFlagControl, "Ctrl"),
+                    (CGEventFlags::CGEventFlagAlternate, "Alt"),
+                    (CGEventFlags::CGEventFlagShift, "Shift"),
+                    (CGEventFlags::CGEventFlagCommand, "Cmd"),
+                ]
+                .into_iter()
+                .filter(|(flag, _)| flags.contains(*flag))
+                .map(|(_, name)| name)
+                .collect();
+                let _ = keys.send((modifiers, key));
+            }
+            None
+        },
+    )
+    .map_err(|_| denied.to_string())?;
+
+    let source = tap.mach_port.create_runloop_source(0).map_err(|_| denied.to_string())?;
+    unsafe { CFRunLoop::get_current().add_source(&source, kCFRunLoopCommonModes) };
+    tap.enable();
+    while !stop.load(Ordering::Relaxed) {
+        CFRunLoop::run_in_mode(unsafe { kCFRunLoopDefaultMode }, Duration::from_millis(250), false);
+    }
+    Ok(())
+}
+
+/// Name of a virtual key code on an ANSI keyboard.
+fn key_name(keycode: i64) -> Option<&'static str> {
+    Some(match keycode {
+        0 => "a", 1 => "s", 2 => "d", 3 => "f", 4 => "h", 5 => "g", 6 => "z", 7 => "x", 8 => "c", 9 => "v",
+        11 => "b", 12 => "q", 13 => "w", 14 => "e", 15 => "r", 16 => "y", 17 => "t", 18 => "1", 19 => "2",
+        20 => "3", 21 => "4", 22 => "6", 23 => "5", 24 => "=", 25 => "9", 26 => "7", 27 => "-", 28 => "8",
+        29 => "0", 30 => "]", 31 => "o", 32 => "u", 33 => "[", 34 => "i", 35 => "p", 36 => "Return", 37 => "l",
+        38 => "j", 39 => "'", 40 => "k", 41 => ";", 42 => "\\", 43 => ",", 44 => "/", 45 => "n", 46 => "m",
+        47 => ".", 48 => "Tab", 49 => "Space", 50 => "`", 51 => "Backspace", 53 => "Esc", 76 => "Enter",
+        96 => "F5", 97 => "F6", 98 => "F7", 99 => "F3", 100 => "F8", 101 => "F9", 103 => "F11", 109 => "F10",
+        111 => "F12", 115 => "Home", 116 => "PageUp", 117 => "Delete", 118 => "F4", 119 => "End", 120 => "F2",
+        121 => "PageDown", 122 => "F1", 123 => "Left", 124 => "Right", 125 => "Down", 126 => "Up",
+        _ => return None,
+    })
+}
+
+/// App name and title of the frontmost window, or None if there is none.
+/// Titles are empty without the screen recording permission.
+pub fn active_window() -> Option<(String, String)> {
+    unsafe {
+        let window_list = CGWindowListCopyWindowInfo(kCGWindowListOptionOnScreenOnly | kCGWindowListExcludeDesktopElements, kCGNullWindowID);
+        if window_list.is_null() {
+            return None;
+        }
+        // On-screen windows are listed front to back; layer 0 holds app windows
+        let windows_array: CFArray<CFDictionary> = CFArray::wrap_under_create_rule(window_list);
+        let window_dict = windows_array
+            .iter()
+            .find(|window_dict| get_cf_number_value(window_dict, "kCGWindowLayer") == Some(0))?;
+        let app = get_cf_string_value(&window_dict, "kCGWindowOwnerName").unwrap_or_default();
+        let title = get_cf_string_value(&window_dict, "kCGWindowName").unwrap_or_default();
+        Some((app, title))
+    }
+}
+
+/// Not used on macOS: ScreenCaptureKit captures the window's app audio
+/// along with the video (see `audio::AppAudio`).
+pub fn watch_app_audio(_pid: u32, _chunks: Sender<Vec<f32>>, _stop: &AtomicBool) -> Result<(), String> {
+    Err("App audio is captured by ScreenCaptureKit on macOS".to_string())
+}
+
+/// Bounds (x, y, width, height) of the display with `display_id` in the
+/// global coordinates window bounds use.
+pub fn display_bounds(display_id: u32) -> Option<(i32, i32, i32, i32)> {
+    let bounds = unsafe { CGDisplayBounds(display_id) };
+    if bounds.size.width <= 0.0 || bounds.size.height <= 0.0 {
+        return None;
+    }
+    Some((bounds.origin.x as i32, bounds.origin.y as i32, bounds.size.width as i32, bounds.size.height as i32))
+}
+
 fn get_cf_string_value(dict: &CFDictionary, key: &str) -> Option<String> {
     let cf_key = CFString::new(key);
     dict.find(cf_key.to_void()).and_then(|value| {
@@ -1,8 +1,21 @@
-#[cfg(target_os = "macos")]
-pub mod mac;
-
-#[cfg(target_os = "windows")]
-pub mod windows;
-
-#[cfg(target_os = "linux")]
-pub mod linux;
\ No newline at end of file
+#[cfg(target_os = "macos")]
+pub mod mac;
+
+#[cfg(target_os = "windows")]
+pub mod windows;
+
+#[cfg(target_os = "linux")]
+pub mod linux;
+
+/// A pressed key: the modifiers held ("Ctrl", "Alt", "Shift", then "Cmd" or
+/// "Win") and the key's name.
+pub type KeyPress = (Vec<&'static str>, &'static str);
+
+#[cfg(target_os = "macos")]
+pub use mac::{active_window, display_bounds, display_monitor, pointer, screen_unavailable, scroll, virtual_display, watch_app_audio, watch_keys, window_bounds, window_icon, window_pid, window_process};
+
+#[cfg(target_os = "windows")]
+pub use windows::{active_window, capture_window_gdi, display_bounds, display_monitor, pointer, screen_unavailable, scroll, virtual_display, watch_app_audio, watch_keys, window_bounds, window_icon, window_pid, window_process};
+
+#[cfg(target_os = "linux")]
+pub use linux::{active_window, display_bounds, display_monitor, pointer, screen_unavailable, scroll, virtual_display, watch_app_audio, watch_keys, window_bounds, window_icon, window_pid, window_process};
\ No newline at end of file
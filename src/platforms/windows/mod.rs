@@ -1,12 +1,51 @@
 // Import from the local scap library
 use scap::Target;
 use std::collections::HashMap;
-use windows::Win32::Foundation::{HWND, RECT, BOOL, LPARAM};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::time::Duration;
+use windows::core::{implement, ComInterface, IUnknown, HRESULT, PCWSTR, PWSTR};
+use windows::Win32::Foundation::{CloseHandle, ERROR_SUCCESS, HINSTANCE, HWND, POINT, RECT, BOOL, LPARAM, LRESULT, WPARAM};
+use windows::Win32::Graphics::Gdi::{
+    BitBlt, CreateCompatibleBitmap, CreateCompatibleDC, DeleteDC, DeleteObject, EnumDisplayDevicesW, GetDIBits, GetMonitorInfoW, GetWindowDC,
+    PatBlt, ReleaseDC, SelectObject, BITMAPINFO, BITMAPINFOHEADER, BI_RGB, BLACKNESS, CAPTUREBLT, DISPLAY_DEVICEW, DISPLAY_DEVICE_ATTACHED_TO_DESKTOP,
+    DIB_RGB_COLORS, HBITMAP, HBRUSH, HDC, HGDIOBJ, HMONITOR, MONITORINFO, MONITORINFOEXW, ROP_CODE, SRCCOPY, WHITENESS
+};
+use windows::Win32::System::Registry::{RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_BINARY};
+use windows::Win32::Media::Audio::{
+    ActivateAudioInterfaceAsync, IActivateAudioInterfaceAsyncOperation, IActivateAudioInterfaceCompletionHandler,
+    IActivateAudioInterfaceCompletionHandler_Impl, IAudioCaptureClient, IAudioClient, AUDCLNT_BUFFERFLAGS_SILENT,
+    AUDCLNT_SHAREMODE_SHARED, AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM, AUDCLNT_STREAMFLAGS_LOOPBACK, AUDIOCLIENT_ACTIVATION_PARAMS,
+    AUDIOCLIENT_ACTIVATION_PARAMS_0, AUDIOCLIENT_ACTIVATION_TYPE_PROCESS_LOOPBACK, AUDIOCLIENT_PROCESS_LOOPBACK_PARAMS,
+    PROCESS_LOOPBACK_MODE_INCLUDE_TARGET_PROCESS_TREE, VIRTUAL_AUDIO_DEVICE_PROCESS_LOOPBACK, WAVEFORMATEX
+};
+use windows::Win32::System::Com::StructuredStorage::PROPVARIANT;
+use windows::Win32::System::Com::{CoInitializeEx, CoUninitialize, IAgileObject, IAgileObject_Impl, BLOB, COINIT_MULTITHREADED};
+use windows::Win32::System::Variant::VT_BLOB;
+use windows::Win32::System::StationsAndDesktops::{
+    CloseDesktop, OpenInputDesktop, DESKTOP_CONTROL_FLAGS, DESKTOP_SWITCHDESKTOP
+};
+use windows::Win32::UI::Input::KeyboardAndMouse::{
+    GetAsyncKeyState, SendInput, INPUT, INPUT_0, INPUT_MOUSE, MOUSEEVENTF_WHEEL, MOUSEINPUT, VIRTUAL_KEY, VK_CONTROL, VK_LBUTTON,
+    VK_LWIN, VK_MBUTTON, VK_MENU, VK_RBUTTON, VK_RWIN, VK_SHIFT
+};
+use windows::Win32::System::Threading::{
+    OpenProcess, QueryFullProcessImageNameW, PROCESS_NAME_WIN32, PROCESS_QUERY_LIMITED_INFORMATION
+};
 use windows::Win32::UI::WindowsAndMessaging::{
-    EnumWindows, GetWindowTextW, GetWindowRect, GetWindowLongW, IsWindowVisible,
-    GWL_STYLE, GetWindowThreadProcessId
+    CallNextHookEx, DispatchMessageW, EnumWindows, GetCursorPos, GetForegroundWindow, PeekMessageW, SetWindowsHookExW,
+    UnhookWindowsHookEx, HHOOK, KBDLLHOOKSTRUCT, MSG, PM_REMOVE, WH_KEYBOARD_LL, WM_KEYDOWN, WM_SYSKEYDOWN, GetWindowTextW, GetWindowRect, GetWindowLongW, IsWindowVisible,
+    GWL_STYLE, GetWindowThreadProcessId, SystemParametersInfoW, SPI_GETSCREENSAVERRUNNING,
+    SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS, SetCursorPos, WHEEL_DELTA, DrawIconEx, GetClassLongPtrW, SendMessageTimeoutW, DI_NORMAL,
+    GCLP_HICON, GCLP_HICONSM, HICON, ICON_BIG, ICON_SMALL2, SMTO_ABORTIFHUNG, WM_GETICON, EDD_GET_DEVICE_INTERFACE_NAME
 };
 
+use crate::edid::{self, Monitor};
+
+/// Where the low-level keyboard hook sends key presses while `watch_keys` runs.
+static KEY_SENDER: Mutex<Option<Sender<super::KeyPress>>> = Mutex::new(None);
+
 struct WindowCallbackData {
     scap_indices: HashMap<u32, usize>,
     shown_count: usize,
@@ -28,8 +67,8 @@ pub fn list_windows() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     // Then get detailed window info from Windows APIs
-    println!("Idx | ID       | PID     | Style    | Visible | X    Y    | W    H    | Title");
-    println!("----|----------|---------|----------|---------|-----------|-----------|------");
+    status!("Idx | ID       | PID     | Style    | Visible | X    Y    | W    H    | Title");
+    status!("----|----------|---------|----------|---------|-----------|-----------|------");
 
     let mut data = WindowCallbackData {
         scap_indices,
@@ -41,12 +80,518 @@ pub fn list_windows() -> Result<(), Box<dyn std::error::Error>> {
         EnumWindows(Some(enum_window_proc), LPARAM(&mut data as *mut _ as isize))?;
     }
 
-    println!("\nShowing {} of {} total windows ({} capturable via scap)",
+    status!("\nShowing {} of {} total windows ({} capturable via scap)",
              data.shown_count, data.total_count, data.scap_indices.len());
 
     Ok(())
 }
 
+/// Current bounds (x, y, width, height) of the window with `window_id`, or
+/// None if it no longer exists.
+pub fn window_bounds(window_id: u32) -> Option<(i32, i32, i32, i32)> {
+    let hwnd = HWND(window_id as usize as _);
+    let mut rect = RECT::default();
+    unsafe { GetWindowRect(hwnd, &mut rect) }.ok()?;
+    Some((rect.left, rect.top, rect.right - rect.left, rect.bottom - rect.top))
+}
+
+/// ID of the process owning the window with `window_id`.
+pub fn window_pid(window_id: u32) -> Option<u32> {
+    let mut pid = 0u32;
+    unsafe { GetWindowThreadProcessId(HWND(window_id as usize as _), Some(&mut pid)) };
+    (pid != 0).then_some(pid)
+}
+
+/// Executable name of the process owning the window with `window_id`,
+/// e.g. "chrome.exe".
+pub fn window_process(window_id: u32) -> Option<String> {
+    let pid = window_pid(window_id)?;
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, BOOL(0), pid).ok()?;
+        let mut path = [0u16; 1024];
+        let mut len = path.len() as u32;
+        let queried = QueryFullProcessImageNameW(process, PROCESS_NAME_WIN32, PWSTR(path.as_mut_ptr()), &mut len);
+        let _ = CloseHandle(process);
+        queried.ok()?;
+        let path = String::from_utf16_lossy(&path[..len as usize]);
+        std::path::Path::new(&path).file_name().map(|name| name.to_string_lossy().into_owned())
+    }
+}
+
+/// Icon of the window with `window_id` drawn `size` pixels square, as width,
+/// height and top-down RGBA pixels. Takes the icon the window shows in its
+/// title bar and the taskbar, falling back to its class icon. The icon is
+/// drawn over black and over white to recover its transparency.
+pub fn window_icon(window_id: u32, size: u32) -> Result<(u32, u32, Vec<u8>), String> {
+    let hwnd = HWND(window_id as usize as _);
+    let icon = unsafe {
+        let mut icon = 0usize;
+        for kind in [ICON_BIG, ICON_SMALL2] {
+            if icon == 0 {
+                // Hung windows don't answer; don't wait on them
+                SendMessageTimeoutW(hwnd, WM_GETICON, WPARAM(kind as usize), LPARAM(0), SMTO_ABORTIFHUNG, 500, Some(&mut icon));
+            }
+        }
+        for index in [GCLP_HICON, GCLP_HICONSM] {
+            if icon == 0 {
+                icon = GetClassLongPtrW(hwnd, index);
+            }
+        }
+        if icon == 0 {
+            return Err(format!("Window {} has no icon", window_id));
+        }
+        HICON(icon as _)
+    };
+
+    let size = size as i32;
+    unsafe {
+        let window_dc = GetWindowDC(hwnd);
+        if window_dc.is_invalid() {
+            return Err(format!("Failed to get a device context for window {}", window_id));
+        }
+        let memory_dc = CreateCompatibleDC(window_dc);
+        let bitmap = CreateCompatibleBitmap(window_dc, size, size);
+        let on_black = draw_icon(memory_dc, bitmap, icon, size, BLACKNESS);
+        let on_white = draw_icon(memory_dc, bitmap, icon, size, WHITENESS);
+        let _ = DeleteObject(bitmap);
+        let _ = DeleteDC(memory_dc);
+        ReleaseDC(hwnd, window_dc);
+
+        let (Some(on_black), Some(on_white)) = (on_black, on_white) else {
+            return Err(format!("Failed to draw the icon of window {}", window_id));
+        };
+        let rgba = on_black.chunks_exact(4).zip(on_white.chunks_exact(4)).flat_map(|(black, white)| {
+            // A pixel lets through as much of the background as it isn't opaque
+            let alpha = 255 - (0..3).map(|channel| white[channel].saturating_sub(black[channel])).max().unwrap_or(0);
+            let unblend = |value: u8| if alpha == 0 { 0 } else { (value as u32 * 255 / alpha as u32).min(255) as u8 };
+            [unblend(black[2]), unblend(black[1]), unblend(black[0]), alpha]
+        }).collect();
+        Ok((size as u32, size as u32, rgba))
+    }
+}
+
+/// Draw `icon` over a background filled by `background` and read back the
+/// BGRA pixels.
+fn draw_icon(memory_dc: HDC, bitmap: HBITMAP, icon: HICON, size: i32, background: ROP_CODE) -> Option<Vec<u8>> {
+    unsafe {
+        let previous: HGDIOBJ = SelectObject(memory_dc, bitmap);
+        let _ = PatBlt(memory_dc, 0, 0, size, size, background);
+        let drawn = DrawIconEx(memory_dc, 0, 0, icon, size, size, 0, HBRUSH::default(), DI_NORMAL);
+        // GetDIBits needs the bitmap out of the device context
+        SelectObject(memory_dc, previous);
+        drawn.ok()?;
+
+        let mut info = BITMAPINFO {
+            bmiHeader: BITMAPINFOHEADER {
+                biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
+                biWidth: size,
+                biHeight: -size,
+                biPlanes: 1,
+                biBitCount: 32,
+                biCompression: BI_RGB.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut data = vec![0u8; size as usize * size as usize * 4];
+        let lines = GetDIBits(memory_dc, bitmap, 0, size as u32, Some(data.as_mut_ptr() as *mut _), &mut info, DIB_RGB_COLORS);
+        (lines == size).then_some(data)
+    }
+}
+
+/// Scroll the window with `window_id` by `lines` wheel notches (positive
+/// scrolls down): the pointer is moved to the window's center, since wheel
+/// input goes to the window under it, and a wheel event is sent.
+pub fn scroll(window_id: u32, lines: i32) -> Result<(), String> {
+    let (x, y, width, height) = window_bounds(window_id).ok_or_else(|| format!("Window {} not found", window_id))?;
+    unsafe { SetCursorPos(x + width / 2, y + height / 2) }.map_err(|e| format!("Failed to move the pointer: {}", e))?;
+    let input = INPUT {
+        r#type: INPUT_MOUSE,
+        Anonymous: INPUT_0 {
+            mi: MOUSEINPUT {
+                // Wheel deltas are positive for scrolling up
+                mouseData: (-lines * WHEEL_DELTA as i32) as u32,
+                dwFlags: MOUSEEVENTF_WHEEL,
+                ..Default::default()
+            },
+        },
+    };
+    let sent = unsafe { SendInput(&[input], std::mem::size_of::<INPUT>() as i32) };
+    (sent == 1).then_some(()).ok_or_else(|| "Scroll input was blocked (e.g. by a higher-integrity window)".to_string())
+}
+
+/// Copy the window with `window_id` out of its device context with GDI's
+/// BitBlt, returning its width, height and top-down BGRA pixels. Slower than
+/// Windows Graphics Capture and only sees what the window has drawn, but it
+/// works for console and legacy windows that WGC returns errors or empty
+/// frames for.
+pub fn capture_window_gdi(window_id: u32) -> Result<(u32, u32, Vec<u8>), String> {
+    let hwnd = HWND(window_id as usize as _);
+    let (_, _, width, height) = window_bounds(window_id).ok_or_else(|| format!("Window {} not found", window_id))?;
+    if width <= 0 || height <= 0 {
+        return Err(format!("Window {} has no visible area", window_id));
+    }
+
+    unsafe {
+        let window_dc = GetWindowDC(hwnd);
+        if window_dc.is_invalid() {
+            return Err(format!("Failed to get a device context for window {}", window_id));
+        }
+        let memory_dc = CreateCompatibleDC(window_dc);
+        let bitmap = CreateCompatibleBitmap(window_dc, width, height);
+        let previous = SelectObject(memory_dc, bitmap);
+        let copied = BitBlt(memory_dc, 0, 0, width, height, window_dc, 0, 0, SRCCOPY | CAPTUREBLT);
+
+        let mut info = BITMAPINFO {
+            bmiHeader: BITMAPINFOHEADER {
+                biSize: std::mem::size_of::<BITMAPINFOHEADER>() as u32,
+                biWidth: width,
+                // Negative heights ask for rows top-down
+                biHeight: -height,
+                biPlanes: 1,
+                biBitCount: 32,
+                biCompression: BI_RGB.0,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut data = vec![0u8; width as usize * height as usize * 4];
+        let lines = match copied {
+            Ok(()) => GetDIBits(memory_dc, bitmap, 0, height as u32, Some(data.as_mut_ptr() as *mut _), &mut info, DIB_RGB_COLORS),
+            Err(_) => 0,
+        };
+
+        SelectObject(memory_dc, previous);
+        let _ = DeleteObject(bitmap);
+        let _ = DeleteDC(memory_dc);
+        ReleaseDC(hwnd, window_dc);
+
+        if let Err(e) = copied {
+            return Err(format!("BitBlt failed: {}", e));
+        }
+        if lines != height {
+            return Err("Failed to read the window's pixels".to_string());
+        }
+        // GDI leaves the alpha channel undefined
+        for pixel in data.chunks_exact_mut(4) {
+            pixel[3] = 255;
+        }
+        Ok((width as u32, height as u32, data))
+    }
+}
+
+/// Pointer position in virtual screen coordinates and whether a mouse
+/// button is down.
+pub fn pointer() -> Option<(i32, i32, bool)> {
+    let mut point = POINT::default();
+    unsafe { GetCursorPos(&mut point) }.ok()?;
+    // The most significant bit is set while the button is down
+    let pressed = [VK_LBUTTON, VK_RBUTTON, VK_MBUTTON].iter().any(|button| unsafe { GetAsyncKeyState(button.0 as i32) } < 0);
+    Some((point.x, point.y, pressed))
+}
+
+/// Send every key pressed in the session to `keys` until `stop` is set,
+/// through a low-level keyboard hook. Blocks the calling thread, which
+/// pumps the messages the hook is called from.
+pub fn watch_keys(keys: Sender<super::KeyPress>, stop: &AtomicBool) -> Result<(), String> {
+    *KEY_SENDER.lock().unwrap() = Some(keys);
+    let hook = unsafe { SetWindowsHookExW(WH_KEYBOARD_LL, Some(keyboard_proc), HINSTANCE::default(), 0) }
+        .map_err(|e| format!("Failed to install the keyboard hook: {}", e))?;
+    let mut msg = MSG::default();
+    while !stop.load(Ordering::Relaxed) {
+        while unsafe { PeekMessageW(&mut msg, HWND::default(), 0, 0, PM_REMOVE) }.as_bool() {
+            unsafe { DispatchMessageW(&msg) };
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let _ = unsafe { UnhookWindowsHookEx(hook) };
+    KEY_SENDER.lock().unwrap().take();
+    Ok(())
+}
+
+unsafe extern "system" fn keyboard_proc(code: i32, wparam: WPARAM, lparam: LPARAM) -> LRESULT {
+    let message = wparam.0 as u32;
+    if code >= 0 && (message == WM_KEYDOWN || message == WM_SYSKEYDOWN) {
+        let info = unsafe { &*(lparam.0 as *const KBDLLHOOKSTRUCT) };
+        if let Some(key) = key_name(info.vkCode)
+            && let Some(sender) = KEY_SENDER.lock().unwrap().as_ref()
+        {
+            let down = |key: VIRTUAL_KEY| unsafe { GetAsyncKeyState(key.0 as i32) } < 0;
+            let modifiers = [
+                (down(VK_CONTROL), "Ctrl"),
+                (down(VK_MENU), "Alt"),
+                (down(VK_SHIFT), "Shift"),
+                (down(VK_LWIN) || down(VK_RWIN), "Win"),
+            ]
+            .into_iter()
+            .filter(|(held, _)| *held)
+            .map(|(_, name)| name)
+            .collect();
+            let _ = sender.send((modifiers, key));
+        }
+    }
+    unsafe { CallNextHookEx(HHOOK::default(), code, wparam, lparam) }
+}
+
+/// Name of a virtual-key code on a US keyboard; None for modifiers and
+/// keys that aren't shown.
+fn key_name(vk_code: u32) -> Option<&'static str> {
+    const LETTERS: [&str; 26] = [
+        "a", "b", "c", "d", "e", "f", "g", "h", "i", "j", "k", "l", "m", "n", "o", "p", "q", "r", "s", "t", "u", "v", "w", "x", "y", "z",
+    ];
+    const DIGITS: [&str; 10] = ["0", "1", "2", "3", "4", "5", "6", "7", "8", "9"];
+    const FUNCTION_KEYS: [&str; 12] = ["F1", "F2", "F3", "F4", "F5", "F6", "F7", "F8", "F9", "F10", "F11", "F12"];
+    Some(match vk_code {
+        0x30..=0x39 => DIGITS[(vk_code - 0x30) as usize],
+        0x41..=0x5A => LETTERS[(vk_code - 0x41) as usize],
+        0x70..=0x7B => FUNCTION_KEYS[(vk_code - 0x70) as usize],
+        0x08 => "Backspace", 0x09 => "Tab", 0x0D => "Return", 0x1B => "Esc", 0x20 => "Space",
+        0x21 => "PageUp", 0x22 => "PageDown", 0x23 => "End", 0x24 => "Home",
+        0x25 => "Left", 0x26 => "Up", 0x27 => "Right", 0x28 => "Down", 0x2E => "Delete",
+        0xBA => ";", 0xBB => "=", 0xBC => ",", 0xBD => "-", 0xBE => ".", 0xBF => "/", 0xC0 => "`",
+        0xDB => "[", 0xDC => "\\", 0xDD => "]", 0xDE => "'",
+        _ => return None,
+    })
+}
+
+/// App (executable name without extension) and title of the foreground
+/// window, or None if there is none.
+pub fn active_window() -> Option<(String, String)> {
+    let hwnd = unsafe { GetForegroundWindow() };
+    if hwnd == HWND::default() {
+        return None;
+    }
+    let mut title = [0u16; 512];
+    let title_len = unsafe { GetWindowTextW(hwnd, &mut title) };
+    let title = String::from_utf16_lossy(&title[..title_len as usize]);
+
+    let app = window_process(hwnd.0 as u32)
+        .and_then(|name| std::path::Path::new(&name).file_stem().map(|stem| stem.to_string_lossy().into_owned()));
+    Some((app.unwrap_or_default(), title))
+}
+
+/// Format requested from the process loopback client, which has no mix
+/// format of its own: 32-bit float at the rate and channels recordings use.
+const WAVE_FORMAT_IEEE_FLOAT: u16 = 3;
+/// Audio client buffer, in 100 ns units (200 ms)
+const AUDIO_BUFFER_DURATION: i64 = 2_000_000;
+const AUDIO_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Send the audio played by process `pid` and its child processes to
+/// `chunks` as interleaved float samples until `stop` is set, through a
+/// WASAPI process loopback client (Windows 10 build 20348 and later).
+/// Blocks the calling thread.
+pub fn watch_app_audio(pid: u32, chunks: Sender<Vec<f32>>, stop: &AtomicBool) -> Result<(), String> {
+    unsafe { CoInitializeEx(None, COINIT_MULTITHREADED) }.map_err(|e| format!("Failed to initialize COM: {}", e))?;
+    let result = capture_process_audio(pid, &chunks, stop);
+    unsafe { CoUninitialize() };
+    result
+}
+
+fn capture_process_audio(pid: u32, chunks: &Sender<Vec<f32>>, stop: &AtomicBool) -> Result<(), String> {
+    let unavailable = |e: windows::core::Error| format!("Per-app audio needs Windows 10 build 20348 or later: {}", e);
+    let mut params = AUDIOCLIENT_ACTIVATION_PARAMS {
+        ActivationType: AUDIOCLIENT_ACTIVATION_TYPE_PROCESS_LOOPBACK,
+        Anonymous: AUDIOCLIENT_ACTIVATION_PARAMS_0 {
+            ProcessLoopbackParams: AUDIOCLIENT_PROCESS_LOOPBACK_PARAMS {
+                TargetProcessId: pid,
+                ProcessLoopbackMode: PROCESS_LOOPBACK_MODE_INCLUDE_TARGET_PROCESS_TREE,
+            },
+        },
+    };
+    let mut variant = PROPVARIANT::default();
+    unsafe {
+        let value = &mut *variant.Anonymous.Anonymous;
+        value.vt = VT_BLOB;
+        value.Anonymous.blob = BLOB {
+            cbSize: std::mem::size_of::<AUDIOCLIENT_ACTIVATION_PARAMS>() as u32,
+            pBlobData: &mut params as *mut AUDIOCLIENT_ACTIVATION_PARAMS as *mut u8,
+        };
+    }
+
+    // Activation completes on a worker thread
+    let (activated, completion) = mpsc::channel();
+    let handler: IActivateAudioInterfaceCompletionHandler = ActivationHandler(activated).into();
+    let operation = unsafe {
+        ActivateAudioInterfaceAsync(VIRTUAL_AUDIO_DEVICE_PROCESS_LOOPBACK, &IAudioClient::IID, Some(&variant as *const PROPVARIANT), &handler)
+    }.map_err(unavailable)?;
+    completion.recv_timeout(Duration::from_secs(5)).map_err(|_| "Timed out activating the audio client".to_string())?;
+    let mut result = HRESULT(0);
+    let mut interface: Option<IUnknown> = None;
+    unsafe { operation.GetActivateResult(&mut result, &mut interface) }.map_err(unavailable)?;
+    result.ok().map_err(unavailable)?;
+    let client: IAudioClient = interface
+        .ok_or("The audio client wasn't activated")?
+        .cast()
+        .map_err(|e| e.to_string())?;
+
+    let channels = crate::audio::CHANNELS;
+    let block_align = channels * 4;
+    let format = WAVEFORMATEX {
+        wFormatTag: WAVE_FORMAT_IEEE_FLOAT,
+        nChannels: channels,
+        nSamplesPerSec: crate::audio::SAMPLE_RATE,
+        nAvgBytesPerSec: crate::audio::SAMPLE_RATE * block_align as u32,
+        nBlockAlign: block_align,
+        wBitsPerSample: 32,
+        cbSize: 0,
+    };
+    let capture: IAudioCaptureClient = unsafe {
+        client.Initialize(
+            AUDCLNT_SHAREMODE_SHARED,
+            AUDCLNT_STREAMFLAGS_LOOPBACK | AUDCLNT_STREAMFLAGS_AUTOCONVERTPCM,
+            AUDIO_BUFFER_DURATION,
+            0,
+            &format,
+            None,
+        ).map_err(unavailable)?;
+        client.GetService().map_err(|e| e.to_string())?
+    };
+    unsafe { client.Start() }.map_err(|e| e.to_string())?;
+
+    let result = (|| {
+        while !stop.load(Ordering::Relaxed) {
+            std::thread::sleep(AUDIO_POLL_INTERVAL);
+            while unsafe { capture.GetNextPacketSize() }.map_err(|e| e.to_string())? > 0 {
+                let (mut data, mut frames, mut flags) = (std::ptr::null_mut(), 0u32, 0u32);
+                unsafe { capture.GetBuffer(&mut data, &mut frames, &mut flags, None, None) }.map_err(|e| e.to_string())?;
+                let count = frames as usize * channels as usize;
+                let samples = if data.is_null() || flags & AUDCLNT_BUFFERFLAGS_SILENT.0 as u32 != 0 {
+                    vec![0.0; count]
+                } else {
+                    unsafe { std::slice::from_raw_parts(data as *const f32, count) }.to_vec()
+                };
+                unsafe { capture.ReleaseBuffer(frames) }.map_err(|e| e.to_string())?;
+                if chunks.send(samples).is_err() {
+                    return Ok(());
+                }
+            }
+        }
+        Ok(())
+    })();
+    let _ = unsafe { client.Stop() };
+    result
+}
+
+/// Signals when `ActivateAudioInterfaceAsync` is done. Must be agile, since
+/// it is called from a worker thread.
+#[implement(IActivateAudioInterfaceCompletionHandler, IAgileObject)]
+struct ActivationHandler(Sender<()>);
+
+impl IActivateAudioInterfaceCompletionHandler_Impl for ActivationHandler {
+    fn ActivateCompleted(&self, _operation: Option<&IActivateAudioInterfaceAsyncOperation>) -> windows::core::Result<()> {
+        let _ = self.0.send(());
+        Ok(())
+    }
+}
+
+impl IAgileObject_Impl for ActivationHandler {}
+
+/// Bounds (x, y, width, height) of the display with `display_id` (its
+/// monitor handle) in the virtual screen coordinates window bounds use.
+pub fn display_bounds(display_id: u32) -> Option<(i32, i32, i32, i32)> {
+    let mut info = MONITORINFO {
+        cbSize: std::mem::size_of::<MONITORINFO>() as u32,
+        ..Default::default()
+    };
+    if !unsafe { GetMonitorInfoW(HMONITOR(display_id as usize as _), &mut info) }.as_bool() {
+        return None;
+    }
+    let rect = info.rcMonitor;
+    Some((rect.left, rect.top, rect.right - rect.left, rect.bottom - rect.top))
+}
+
+/// The monitor showing display `display_id` (its monitor handle), from the
+/// EDID Windows keeps in the registry under the monitor's device instance.
+pub fn display_monitor(display_id: u32) -> Option<Monitor> {
+    let mut info = MONITORINFOEXW {
+        monitorInfo: MONITORINFO {
+            cbSize: std::mem::size_of::<MONITORINFOEXW>() as u32,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let found = unsafe { GetMonitorInfoW(HMONITOR(display_id as usize as _), &mut info as *mut MONITORINFOEXW as *mut MONITORINFO) };
+    if !found.as_bool() {
+        return None;
+    }
+    let mut device = DISPLAY_DEVICEW {
+        cb: std::mem::size_of::<DISPLAY_DEVICEW>() as u32,
+        ..Default::default()
+    };
+    // The first monitor on the display's adapter output
+    if !unsafe { EnumDisplayDevicesW(PCWSTR(info.szDevice.as_ptr()), 0, &mut device, EDD_GET_DEVICE_INTERFACE_NAME) }.as_bool() {
+        return None;
+    }
+    let length = device.DeviceID.iter().position(|&c| c == 0).unwrap_or(device.DeviceID.len());
+    // "\\?\DISPLAY#GSM5B7F#5&2f3a1b&0&UID4352#{e6f07b5f-...}"
+    let interface = String::from_utf16_lossy(&device.DeviceID[..length]);
+    let mut parts = interface.split('#').skip(1);
+    let (model, instance) = (parts.next()?, parts.next()?);
+
+    let key: Vec<u16> = format!("SYSTEM\\CurrentControlSet\\Enum\\DISPLAY\\{}\\{}\\Device Parameters", model, instance)
+        .encode_utf16()
+        .chain(Some(0))
+        .collect();
+    let value: Vec<u16> = "EDID\0".encode_utf16().collect();
+    let mut data = vec![0u8; 1024];
+    let mut len = data.len() as u32;
+    let status = unsafe {
+        RegGetValueW(HKEY_LOCAL_MACHINE, PCWSTR(key.as_ptr()), PCWSTR(value.as_ptr()), RRF_RT_REG_BINARY, None, Some(data.as_mut_ptr() as *mut _), Some(&mut len))
+    };
+    if status != ERROR_SUCCESS {
+        return None;
+    }
+    data.truncate(len as usize);
+    edid::parse(&data)
+}
+
+/// The first display adapter attached to the desktop that is a virtual or
+/// software one (the basic display adapter of VMs and CI runners, an
+/// indirect display driver, a remote display), by its name.
+pub fn virtual_display() -> Option<String> {
+    const MARKERS: [&str; 6] = ["virtual", "basic display", "indirect", "iddsample", "remote display", "hyper-v"];
+    for index in 0.. {
+        let mut device = DISPLAY_DEVICEW {
+            cb: std::mem::size_of::<DISPLAY_DEVICEW>() as u32,
+            ..Default::default()
+        };
+        if !unsafe { EnumDisplayDevicesW(PCWSTR::null(), index, &mut device, 0) }.as_bool() {
+            break;
+        }
+        if device.StateFlags & DISPLAY_DEVICE_ATTACHED_TO_DESKTOP == 0 {
+            continue;
+        }
+        let length = device.DeviceString.iter().position(|&c| c == 0).unwrap_or(device.DeviceString.len());
+        let name = String::from_utf16_lossy(&device.DeviceString[..length]);
+        if MARKERS.iter().any(|marker| name.to_lowercase().contains(marker)) {
+            return Some(name);
+        }
+    }
+    None
+}
+
+/// Why the screen can't show anything useful right now ("locked" or
+/// "screensaver"), or None if it can.
+pub fn screen_unavailable() -> Option<&'static str> {
+    // A locked session's input desktop is the secure desktop, which can't be opened
+    match unsafe { OpenInputDesktop(DESKTOP_CONTROL_FLAGS(0), BOOL(0), DESKTOP_SWITCHDESKTOP) } {
+        Ok(desktop) => {
+            let _ = unsafe { CloseDesktop(desktop) };
+        }
+        Err(_) => return Some("locked"),
+    }
+
+    let mut running = BOOL(0);
+    let queried = unsafe {
+        SystemParametersInfoW(SPI_GETSCREENSAVERRUNNING, 0, Some(&mut running as *mut BOOL as *mut _), SYSTEM_PARAMETERS_INFO_UPDATE_FLAGS(0))
+    };
+    if queried.is_ok() && running.as_bool() {
+        return Some("screensaver");
+    }
+    None
+}
+
 unsafe extern "system" fn enum_window_proc(hwnd: HWND, lparam: LPARAM) -> BOOL {
     let data = unsafe { &mut *(lparam.0 as *mut WindowCallbackData) };
 
@@ -96,7 +641,7 @@ unsafe extern "system" fn enum_window_proc(hwnd: HWND, lparam: LPARAM) -> BOOL {
         "  -".to_string()
     };
 
-    println!("{:3} | {:8} | {:7} | {:8X} | {:7} | {:3},{:3} | {:3}x{:3} | {}",
+    status!("{:3} | {:8} | {:7} | {:8X} | {:7} | {:3},{:3} | {:3}x{:3} | {}",
              index_str,
              window_id,
              pid,
@@ -0,0 +1,64 @@
+use clap::ValueEnum;
+
+use crate::encode::ImageFormat;
+
+/// JPEG quality of captures without a preset.
+pub const DEFAULT_JPEG_QUALITY: u8 = 75;
+
+/// Capture settings tuned for what the image is for. Text extraction wants
+/// sharp, noise-free glyphs at full resolution; archiving wants every pixel
+/// as captured; LLM analysis wants a small image the model reads well.
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Preset {
+    /// Native resolution, 3 averaged frames against dithering and video noise, JPEG 95, OCR on
+    Ocr,
+    /// Native resolution, lossless PNG, frame timestamps saved next to the image
+    Archive,
+    /// Windows at 1080p, JPEG 85, LLM analysis on
+    Llm,
+}
+
+impl Preset {
+    /// Format of saved captures whose name doesn't pick one.
+    pub fn format(self) -> ImageFormat {
+        match self {
+            Preset::Archive => ImageFormat::Png,
+            Preset::Ocr | Preset::Llm => ImageFormat::Jpeg,
+        }
+    }
+
+    /// Quality of JPEG captures.
+    pub fn jpeg_quality(self) -> u8 {
+        match self {
+            Preset::Ocr => 95,
+            Preset::Archive => 100,
+            Preset::Llm => 85,
+        }
+    }
+
+    /// Frames averaged into the capture.
+    pub fn average(self) -> usize {
+        match self {
+            Preset::Ocr => 3,
+            Preset::Archive | Preset::Llm => 1,
+        }
+    }
+
+    /// Whether windows are captured at their own size instead of being
+    /// scaled to 1080p.
+    pub fn native_resolution(self) -> bool {
+        matches!(self, Preset::Ocr | Preset::Archive)
+    }
+
+    pub fn ocr(self) -> bool {
+        self == Preset::Ocr
+    }
+
+    pub fn analyze(self) -> bool {
+        self == Preset::Llm
+    }
+
+    pub fn timestamp_sidecar(self) -> bool {
+        self == Preset::Archive
+    }
+}
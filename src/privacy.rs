@@ -0,0 +1,265 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use scap::Target;
+use scap::capturer::Options;
+use scap::frame::{
+    BGRAFrame, BGRFrame, BGRxFrame, RGBFrame, RGBxFrame, VideoFrame, XBGRFrame,
+};
+
+use crate::capture;
+use crate::config::settings;
+use crate::error::{CaptestError, ErrorKind};
+use crate::platforms;
+
+/// Window title fragments treated as sensitive when the config file has no
+/// `privacy_blocklist`: password managers, banking and private browsing.
+pub const DEFAULT_BLOCKLIST: &[&str] = &[
+    "1Password",
+    "Bitwarden",
+    "Dashlane",
+    "Enpass",
+    "KeePass",
+    "Keychain Access",
+    "LastPass",
+    "Online Banking",
+    "InPrivate",
+    "Incognito",
+    "Private Browsing",
+];
+
+/// Title reported for active windows matching the blocklist.
+const HIDDEN_TITLE: &str = "[hidden]";
+
+/// How long the list of hidden windows is reused between frames.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A window kept out of display captures.
+#[derive(Clone)]
+struct HiddenWindow {
+    id: u32,
+    title: String,
+    /// Created by captest itself (an overlay or preview) rather than matched
+    /// by the blocklist
+    own: bool,
+}
+
+static HIDDEN: Mutex<Option<(Instant, Vec<HiddenWindow>)>> = Mutex::new(None);
+
+/// Whether a window title matches the blocklist (case-insensitively).
+pub fn is_sensitive_title(title: &str) -> bool {
+    let title = title.to_lowercase();
+    settings()
+        .privacy_blocklist
+        .iter()
+        .any(|entry| !entry.is_empty() && title.contains(&entry.to_lowercase()))
+}
+
+/// App and title of the active window, with the title replaced by
+/// `[hidden]` when it matches the blocklist.
+pub fn active_window() -> Option<(String, String)> {
+    platforms::active_window().map(|(app, title)| match is_sensitive_title(&title) {
+        true => (app, HIDDEN_TITLE.to_string()),
+        false => (app, title),
+    })
+}
+
+/// Open windows whose title matches the blocklist (case-insensitively), plus
+/// any window belonging to this process.
+fn hidden_windows() -> Vec<HiddenWindow> {
+    let mut cached = HIDDEN.lock().unwrap();
+    if let Some((listed_at, windows)) = cached.as_ref()
+        && listed_at.elapsed() < REFRESH_INTERVAL
+    {
+        return windows.clone();
+    }
+
+    let pid = std::process::id();
+    let windows: Vec<HiddenWindow> = capture::all_targets()
+        .into_iter()
+        .filter_map(|target| match target {
+            Target::Window(window) => {
+                let own = platforms::window_pid(window.id) == Some(pid);
+                (own || is_sensitive_title(&window.title)).then_some(HiddenWindow { id: window.id, title: window.title, own })
+            }
+            Target::Display(_) => None,
+        })
+        .collect();
+    *cached = Some((Instant::now(), windows.clone()));
+    windows
+}
+
+/// Keep sensitive windows, and captest's own windows, out of a display
+/// capture. macOS excludes them in the capture backend; elsewhere they are
+/// blanked in each frame by [`mask_frame`], which `--privacy-strict` refuses
+/// to rely on for sensitive windows.
+pub fn exclude_sensitive(options: &mut Options) -> Result<(), CaptestError> {
+    let Some(Target::Display(display)) = &options.target else {
+        return Ok(());
+    };
+    let windows = hidden_windows();
+    if windows.is_empty() {
+        return Ok(());
+    }
+    let sensitive: Vec<String> = windows.iter().filter(|window| !window.own).map(|window| format!("\"{}\"", window.title)).collect();
+    let titles = sensitive.join(", ");
+
+    if cfg!(target_os = "macos") {
+        if !sensitive.is_empty() {
+            status!("Excluding {} sensitive window(s) from the capture: {}", sensitive.len(), titles);
+        }
+        let ids: Vec<u32> = windows.iter().map(|window| window.id).collect();
+        options.excluded_targets = Some(
+            capture::all_targets()
+                .into_iter()
+                .filter(|target| matches!(target, Target::Window(window) if ids.contains(&window.id)))
+                .collect(),
+        );
+        return Ok(());
+    }
+    if sensitive.is_empty() {
+        return Ok(());
+    }
+    if settings().privacy_strict {
+        return Err(strict_error(&titles));
+    }
+    if platforms::display_bounds(display.id).is_some() {
+        status!("Blanking {} sensitive window(s) in the capture: {}", sensitive.len(), titles);
+    } else {
+        status!("Warning: sensitive windows are visible and can't be excluded or blanked on this platform: {}", titles);
+    }
+    Ok(())
+}
+
+fn strict_error(titles: &str) -> CaptestError {
+    CaptestError::new(
+        ErrorKind::PrivacyBlocked,
+        format!("Sensitive windows are visible and can't be excluded on this platform: {}", titles),
+    )
+}
+
+/// Re-checks the blocklist during a continuous display capture, so windows
+/// opened after the capture started are handled too.
+#[derive(Default)]
+pub struct PrivacyMonitor {
+    checked_at: Option<Instant>,
+}
+
+impl PrivacyMonitor {
+    /// Look for newly opened windows to hide, at most once per
+    /// [`REFRESH_INTERVAL`]. Returns true when the capture should be restarted
+    /// with options from [`exclude_sensitive`] so the backend excludes them
+    /// (macOS); [`mask_frame`] blanks them until then. Under
+    /// `--privacy-strict`, a sensitive window that can't be excluded fails the
+    /// capture just as it would at the start.
+    pub fn poll(&mut self, options: &Options) -> Result<bool, CaptestError> {
+        if display_id(options).is_none() || self.checked_at.is_some_and(|checked_at| checked_at.elapsed() < REFRESH_INTERVAL) {
+            return Ok(false);
+        }
+        self.checked_at = Some(Instant::now());
+
+        let windows = unexcluded_windows(options);
+        if windows.is_empty() {
+            return Ok(false);
+        }
+        if cfg!(target_os = "macos") {
+            status!("{} window(s) to hide appeared, restarting the capture to exclude them", windows.len());
+            return Ok(true);
+        }
+        let sensitive: Vec<String> = windows.iter().filter(|window| !window.own).map(|window| format!("\"{}\"", window.title)).collect();
+        if settings().privacy_strict && !sensitive.is_empty() {
+            return Err(strict_error(&sensitive.join(", ")));
+        }
+        Ok(false)
+    }
+}
+
+/// Windows to hide that the backend isn't excluding from a capture with `options`.
+fn unexcluded_windows(options: &Options) -> Vec<HiddenWindow> {
+    let excluded: Vec<u32> = options.excluded_targets.iter().flatten()
+        .filter_map(|target| match target {
+            Target::Window(window) => Some(window.id),
+            Target::Display(_) => None,
+        })
+        .collect();
+    hidden_windows().into_iter().filter(|window| !excluded.contains(&window.id)).collect()
+}
+
+/// The display ID a capture with `options` needs [`mask_frame`] for; None
+/// for window captures.
+pub fn display_id(options: &Options) -> Option<u32> {
+    match &options.target {
+        Some(Target::Display(display)) => Some(display.id),
+        _ => None,
+    }
+}
+
+/// Black out sensitive windows and captest's own windows in a frame of a
+/// display capture with `options`, unless the backend already excluded them:
+/// it can't outside macOS, and on macOS windows opened after the capture
+/// started stay visible until [`PrivacyMonitor`] has it restarted. Windows
+/// whose position is unknown are left as they are.
+pub fn mask_frame(options: &Options, video_frame: &mut VideoFrame) {
+    let Some(display_id) = display_id(options) else {
+        return;
+    };
+    let windows = unexcluded_windows(options);
+    if windows.is_empty() {
+        return;
+    }
+    let Some((display_x, display_y, display_width, display_height)) = platforms::display_bounds(display_id) else {
+        return;
+    };
+    if display_width <= 0 || display_height <= 0 {
+        return;
+    }
+
+    let (width, height) = crate::convert::video_frame_size(video_frame);
+    // Window bounds are in screen coordinates, frames may be scaled
+    let scale_x = width as f64 / display_width as f64;
+    let scale_y = height as f64 / display_height as f64;
+    let to_frame = |value: i32, origin: i32, scale: f64, limit: u32| (((value - origin) as f64 * scale).round().max(0.0) as usize).min(limit as usize);
+
+    for window in windows {
+        let Some((x, y, window_width, window_height)) = platforms::window_bounds(window.id) else {
+            continue;
+        };
+        let left = to_frame(x, display_x, scale_x, width);
+        let right = to_frame(x + window_width, display_x, scale_x, width);
+        let top = to_frame(y, display_y, scale_y, height);
+        let bottom = to_frame(y + window_height, display_y, scale_y, height);
+        if left >= right || top >= bottom {
+            continue;
+        }
+        blank(video_frame, width as usize, height as usize, (left, top, right, bottom));
+    }
+}
+
+/// Fill a rectangle (left, top, right, bottom) of a frame with black.
+fn blank(video_frame: &mut VideoFrame, width: usize, height: usize, (left, top, right, bottom): (usize, usize, usize, usize)) {
+    let fill = |data: &mut [u8], stride: usize, bytes_per_pixel: usize, rows: std::ops::Range<usize>, columns: std::ops::Range<usize>, value: u8| {
+        for row in rows {
+            if let Some(pixels) = data.get_mut(row * stride + columns.start * bytes_per_pixel..row * stride + columns.end * bytes_per_pixel) {
+                pixels.fill(value);
+            }
+        }
+    };
+
+    match video_frame {
+        VideoFrame::YUVFrame(frame) => {
+            // Black in video range NV12 is Y=16 with neutral chroma
+            fill(&mut frame.luminance_bytes, frame.luminance_stride as usize, 1, top..bottom, left..right, 16);
+            let chroma_columns = left / 2 * 2..right.div_ceil(2) * 2;
+            fill(&mut frame.chrominance_bytes, frame.chrominance_stride as usize, 1, top / 2..bottom.div_ceil(2), chroma_columns, 128);
+        }
+        VideoFrame::RGB(RGBFrame { data, .. })
+        | VideoFrame::RGBx(RGBxFrame { data, .. })
+        | VideoFrame::XBGR(XBGRFrame { data, .. })
+        | VideoFrame::BGRx(BGRxFrame { data, .. })
+        | VideoFrame::BGR0(BGRFrame { data, .. })
+        | VideoFrame::BGRA(BGRAFrame { data, .. }) => {
+            let bytes_per_pixel = data.len() / (width * height).max(1);
+            fill(data, width * bytes_per_pixel, bytes_per_pixel, top..bottom, left..right, 0);
+        }
+    }
+}
@@ -0,0 +1,40 @@
+use std::borrow::Cow;
+use std::io::IsTerminal;
+use std::time::Duration;
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// Progress is only drawn for interactive sessions: both stdout and stderr must
+/// be terminals, and --quiet turns it off entirely.
+fn enabled() -> bool {
+    !crate::output::is_quiet() && std::io::stdout().is_terminal() && std::io::stderr().is_terminal()
+}
+
+/// An animated spinner for stages of unknown length (model loading, LLM responses).
+pub fn spinner(message: impl Into<Cow<'static, str>>) -> ProgressBar {
+    if !enabled() {
+        return ProgressBar::hidden();
+    }
+
+    let pb = ProgressBar::new_spinner();
+    pb.set_style(ProgressStyle::with_template("{spinner} {msg} [{elapsed}]").unwrap());
+    pb.set_message(message);
+    pb.enable_steady_tick(Duration::from_millis(100));
+    pb
+}
+
+/// A bar for stages with a known number of steps (frames, files, windows).
+pub fn bar(len: u64, message: impl Into<Cow<'static, str>>) -> ProgressBar {
+    if !enabled() {
+        return ProgressBar::hidden();
+    }
+
+    let pb = ProgressBar::new(len);
+    pb.set_style(
+        ProgressStyle::with_template("{msg} [{bar:30}] {pos}/{len} ({eta})")
+            .unwrap()
+            .progress_chars("=> "),
+    );
+    pb.set_message(message);
+    pb
+}
@@ -0,0 +1,76 @@
+use serde::Serialize;
+
+/// Side of the square cells text density is judged on.
+const CELL: usize = 16;
+/// Horizontal brightness step that counts as a stroke edge.
+const EDGE_STEP: i32 = 40;
+/// Share of edge pixels in a cell of text. Flat areas and smooth photos have
+/// fewer; noise and dense patterns have more.
+const TEXT_EDGES: std::ops::RangeInclusive<f64> = 0.06..=0.45;
+
+/// How usable a capture is likely to be for OCR and analysis.
+#[derive(Serialize)]
+pub struct Quality {
+    /// Variance of the Laplacian of the brightness; low values mean a blurry or flat image
+    pub sharpness: f64,
+    /// Shannon entropy of the brightness histogram, in bits (0-8)
+    pub entropy: f64,
+    /// Estimated share of the image covered by text (0-1)
+    pub text_density: f64,
+}
+
+impl Quality {
+    pub fn describe(&self) -> String {
+        format!("sharpness {:.1}, entropy {:.2} bits, text density {:.0}%", self.sharpness, self.entropy, self.text_density * 100.0)
+    }
+}
+
+/// Measure an RGB8 frame's sharpness, entropy and text density.
+pub fn measure(width: u32, height: u32, rgb_data: &[u8]) -> Quality {
+    let (w, h) = (width as usize, height as usize);
+    let gray: Vec<i32> = rgb_data.chunks_exact(3)
+        .map(|p| ((p[0] as u32 * 77 + p[1] as u32 * 150 + p[2] as u32 * 29) >> 8) as i32)
+        .collect();
+    if w < 3 || h < 3 || gray.len() < w * h {
+        return Quality { sharpness: 0.0, entropy: 0.0, text_density: 0.0 };
+    }
+
+    let (mut sum, mut sum_squares) = (0.0, 0.0);
+    for y in 1..h - 1 {
+        for x in 1..w - 1 {
+            let at = y * w + x;
+            let laplacian = (gray[at - 1] + gray[at + 1] + gray[at - w] + gray[at + w] - 4 * gray[at]) as f64;
+            sum += laplacian;
+            sum_squares += laplacian * laplacian;
+        }
+    }
+    let count = ((w - 2) * (h - 2)) as f64;
+    let sharpness = sum_squares / count - (sum / count).powi(2);
+
+    let mut histogram = [0usize; 256];
+    for &value in &gray {
+        histogram[value as usize] += 1;
+    }
+    let entropy = histogram.iter()
+        .filter(|&&n| n > 0)
+        .map(|&n| n as f64 / gray.len() as f64)
+        .map(|p| -p * p.log2())
+        .sum::<f64>();
+
+    // Text is a dense run of thin strokes: many, but not most, pixels of a
+    // cell sit on a sharp horizontal edge
+    let (mut cells, mut text_cells) = (0, 0);
+    for top in (0..(h + 1).saturating_sub(CELL)).step_by(CELL) {
+        for left in (0..w.saturating_sub(CELL)).step_by(CELL) {
+            let edges = (top..top + CELL)
+                .flat_map(|y| (left..left + CELL).map(move |x| y * w + x))
+                .filter(|&at| (gray[at + 1] - gray[at]).abs() >= EDGE_STEP)
+                .count();
+            cells += 1;
+            text_cells += TEXT_EDGES.contains(&(edges as f64 / (CELL * CELL) as f64)) as usize;
+        }
+    }
+    let text_density = if cells == 0 { 0.0 } else { text_cells as f64 / cells as f64 };
+
+    Quality { sharpness, entropy, text_density }
+}
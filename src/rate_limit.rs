@@ -0,0 +1,124 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Throttles expensive calls (LLM analysis) in continuous modes: a minimum gap
+/// between calls plus a cap on calls within any sliding minute. Times are on
+/// tokio's clock, which the waits for the limiter sleep on.
+pub struct RateLimiter {
+    min_interval: Option<Duration>,
+    max_per_minute: Option<u32>,
+    history: VecDeque<Instant>,
+}
+
+impl RateLimiter {
+    pub fn new(min_interval: Option<Duration>, max_per_minute: Option<u32>) -> Self {
+        Self {
+            min_interval,
+            max_per_minute: max_per_minute.filter(|max| *max > 0),
+            history: VecDeque::new(),
+        }
+    }
+
+    /// How long until another call is allowed; zero when one may go now.
+    pub fn wait_time(&mut self, now: Instant) -> Duration {
+        while self.history.front().is_some_and(|at| now.duration_since(*at) >= WINDOW) {
+            self.history.pop_front();
+        }
+
+        let mut wait = Duration::ZERO;
+        if let (Some(interval), Some(last)) = (self.min_interval, self.history.back()) {
+            wait = wait.max(interval.saturating_sub(now.duration_since(*last)));
+        }
+        if let Some(max) = self.max_per_minute
+            && self.history.len() >= max as usize
+        {
+            // The oldest call in the window has to age out first
+            let oldest = self.history[self.history.len() - max as usize];
+            wait = wait.max(WINDOW.saturating_sub(now.duration_since(oldest)));
+        }
+        wait
+    }
+
+    pub fn record(&mut self, now: Instant) {
+        self.history.push_back(now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Make calls while the limiter allows them, sleeping out each wait, and
+    /// return when each call went out, in seconds from the start.
+    async fn calls(limiter: &mut RateLimiter, count: usize) -> Vec<u64> {
+        let start = Instant::now();
+        let mut times = Vec::with_capacity(count);
+        for _ in 0..count {
+            tokio::time::sleep(limiter.wait_time(Instant::now())).await;
+            limiter.record(Instant::now());
+            times.push(start.elapsed().as_secs());
+        }
+        times
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn unlimited_calls_never_wait() {
+        let mut limiter = RateLimiter::new(None, None);
+        assert_eq!(calls(&mut limiter, 100).await, [0; 100]);
+        // A cap of zero means no cap
+        let mut limiter = RateLimiter::new(None, Some(0));
+        assert_eq!(calls(&mut limiter, 10).await, [0; 10]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn a_burst_up_to_the_cap_goes_at_once() {
+        let mut limiter = RateLimiter::new(None, Some(3));
+        for _ in 0..3 {
+            assert_eq!(limiter.wait_time(Instant::now()), Duration::ZERO);
+            limiter.record(Instant::now());
+        }
+        assert_eq!(limiter.wait_time(Instant::now()), WINDOW);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn calls_at_the_cap_wait_for_the_oldest_to_age_out() {
+        let mut limiter = RateLimiter::new(None, Some(2));
+        limiter.record(Instant::now());
+        tokio::time::advance(Duration::from_secs(20)).await;
+        limiter.record(Instant::now());
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(limiter.wait_time(Instant::now()), Duration::from_secs(30));
+
+        // One slot frees up when the first call is a minute old, the next 20s later
+        assert_eq!(calls(&mut limiter, 3).await, [30, 50, 90]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_cap_refills_after_a_quiet_minute() {
+        let mut limiter = RateLimiter::new(None, Some(3));
+        assert_eq!(calls(&mut limiter, 3).await, [0, 0, 0]);
+        tokio::time::advance(WINDOW).await;
+        assert_eq!(limiter.wait_time(Instant::now()), Duration::ZERO);
+        assert_eq!(calls(&mut limiter, 4).await, [0, 0, 0, 60]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn calls_keep_the_minimum_gap() {
+        let mut limiter = RateLimiter::new(Some(Duration::from_secs(5)), None);
+        assert_eq!(calls(&mut limiter, 4).await, [0, 5, 10, 15]);
+        tokio::time::advance(Duration::from_secs(2)).await;
+        assert_eq!(limiter.wait_time(Instant::now()), Duration::from_secs(3));
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(limiter.wait_time(Instant::now()), Duration::ZERO);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn the_longer_of_gap_and_cap_applies() {
+        let mut limiter = RateLimiter::new(Some(Duration::from_secs(10)), Some(4));
+        assert_eq!(calls(&mut limiter, 6).await, [0, 10, 20, 30, 60, 70]);
+    }
+}
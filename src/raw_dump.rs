@@ -0,0 +1,82 @@
+use std::fs::File;
+use std::io::Write;
+
+use scap::frame::{Frame, VideoFrame};
+use serde_json::json;
+
+/// Write the frame exactly as scap delivered it to `path`, plus a JSON
+/// descriptor at `<path>.json` describing how to interpret the bytes.
+pub fn dump_raw_frame(frame: &Frame, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let video_frame = match frame {
+        Frame::Video(video_frame) => video_frame,
+        Frame::Audio(_) => return Err("Cannot dump an audio frame as raw video".into()),
+    };
+
+    let mut file = File::create(path)?;
+
+    let descriptor = match video_frame {
+        VideoFrame::YUVFrame(f) => {
+            // NV12: full resolution luminance plane followed by the interleaved chrominance plane
+            file.write_all(&f.luminance_bytes)?;
+            file.write_all(&f.chrominance_bytes)?;
+            json!({
+                "pixel_format": "NV12",
+                "width": f.width,
+                "height": f.height,
+                "display_time": format!("{:?}", f.display_time),
+                "planes": [
+                    {
+                        "name": "luminance",
+                        "offset": 0,
+                        "length": f.luminance_bytes.len(),
+                        "stride": f.luminance_stride,
+                    },
+                    {
+                        "name": "chrominance",
+                        "offset": f.luminance_bytes.len(),
+                        "length": f.chrominance_bytes.len(),
+                        "stride": f.chrominance_stride,
+                    },
+                ],
+            })
+        }
+        VideoFrame::RGB(f) => packed_descriptor(&mut file, "RGB", 3, f.width, f.height, &f.data, &f.display_time)?,
+        VideoFrame::RGBx(f) => packed_descriptor(&mut file, "RGBx", 4, f.width, f.height, &f.data, &f.display_time)?,
+        VideoFrame::XBGR(f) => packed_descriptor(&mut file, "XBGR", 4, f.width, f.height, &f.data, &f.display_time)?,
+        VideoFrame::BGRx(f) => packed_descriptor(&mut file, "BGRx", 4, f.width, f.height, &f.data, &f.display_time)?,
+        VideoFrame::BGR0(f) => packed_descriptor(&mut file, "BGR0", 4, f.width, f.height, &f.data, &f.display_time)?,
+        VideoFrame::BGRA(f) => packed_descriptor(&mut file, "BGRA", 4, f.width, f.height, &f.data, &f.display_time)?,
+    };
+
+    let descriptor_path = format!("{}.json", path);
+    std::fs::write(&descriptor_path, serde_json::to_string_pretty(&descriptor)?)?;
+
+    status!("Dumped raw frame to {} (descriptor: {})", path, descriptor_path);
+    Ok(())
+}
+
+fn packed_descriptor(
+    file: &mut File,
+    pixel_format: &str,
+    bytes_per_pixel: usize,
+    width: i32,
+    height: i32,
+    data: &[u8],
+    display_time: &impl std::fmt::Debug,
+) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    file.write_all(data)?;
+
+    // Packed frames don't carry an explicit stride; derive it from the buffer
+    // so padded rows from the backend are visible in the descriptor
+    let stride = if height > 0 { data.len() / height as usize } else { 0 };
+
+    Ok(json!({
+        "pixel_format": pixel_format,
+        "bytes_per_pixel": bytes_per_pixel,
+        "width": width,
+        "height": height,
+        "stride": stride,
+        "length": data.len(),
+        "display_time": format!("{:?}", display_time),
+    }))
+}
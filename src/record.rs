@@ -0,0 +1,350 @@
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+
+use scap::frame::Frame;
+use tokio_util::sync::CancellationToken;
+
+use crate::audio::{self, AppAudio};
+use crate::capture::{self, AsyncCaptureSession};
+use crate::cursor::ClickHighlighter;
+use crate::error::{CaptestError, ErrorKind};
+use crate::follow::{self, WindowChange, WindowFollower};
+use crate::gif::GifWriter;
+use crate::keys::{self, KeyCaptions};
+use crate::mkv::{self, MkvWriter};
+use crate::mp4::{self, Mp4Writer};
+use crate::output::RecordReport;
+use crate::pause::PauseMonitor;
+use crate::privacy::{self, PrivacyMonitor};
+use crate::session_pool::{self, TargetKey};
+use crate::timestamp::{self, FrameTimes};
+use crate::{config, convert, RecordArgs};
+
+/// Container a recording is written in, picked by the output's extension.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Container {
+    Mkv,
+    Mp4,
+    Gif,
+}
+
+impl Container {
+    fn from_path(path: &Path) -> Self {
+        let extension = path.extension().map(|extension| extension.to_string_lossy().to_lowercase());
+        match extension.as_deref() {
+            Some("mp4" | "m4v") => Container::Mp4,
+            Some("gif") => Container::Gif,
+            _ => Container::Mkv,
+        }
+    }
+}
+
+enum VideoWriter {
+    Mkv(MkvWriter),
+    Mp4(Mp4Writer),
+    Gif(GifWriter),
+}
+
+impl VideoWriter {
+    fn create(container: Container, path: &Path, width: u32, height: u32, audio: Option<(u32, u16)>) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(match container {
+            Container::Mkv => VideoWriter::Mkv(MkvWriter::create(path, width, height, audio)?),
+            Container::Mp4 => VideoWriter::Mp4(Mp4Writer::create(path, width, height)?),
+            Container::Gif => VideoWriter::Gif(GifWriter::create(path)?),
+        })
+    }
+
+    fn write_frame(&mut self, timestamp_ms: u64, width: u32, height: u32, rgb_data: &[u8], segment_ms: u64) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            VideoWriter::Mkv(mkv) => {
+                let jpeg_bytes = crate::rgb8_to_jpeg_bytes(width, height, rgb_data)?;
+                mkv.write_frame(timestamp_ms, &jpeg_bytes, segment_ms)?;
+            }
+            VideoWriter::Mp4(mp4) => mp4.write_frame(timestamp_ms, width, height, rgb_data, segment_ms)?,
+            VideoWriter::Gif(gif) => gif.write_frame(timestamp_ms, width, height, rgb_data)?,
+        }
+        Ok(())
+    }
+
+    /// Finalize the file, with the last frame shown until `end_ms`. Returns
+    /// the frames written and the duration.
+    fn finish(self, path: &Path, end_ms: u64) -> Result<(usize, u64), Box<dyn std::error::Error>> {
+        match self {
+            VideoWriter::Mkv(mkv) => {
+                let recovery = mkv.finish(path)?;
+                Ok((recovery.frames, recovery.duration_ms))
+            }
+            VideoWriter::Mp4(mp4) => Ok((mp4.finish(end_ms)?, end_ms)),
+            VideoWriter::Gif(gif) => Ok((gif.finish(end_ms)?, end_ms)),
+        }
+    }
+}
+
+/// Record `key` until Ctrl-C or `--duration`, to Matroska (JPEG frames),
+/// MP4 (H.264, with the `mp4` feature) or an animated GIF depending on the
+/// output's extension. Matroska frames are grouped into clusters that are
+/// synced to disk as they complete, so a crash loses at most the last
+/// `--segment` seconds; the other containers are only written out at the end.
+/// A recorded window that closes ends the recording, unless
+/// `--reattach-by-title` waits for a window with the same title. With
+/// `--audio app` the window's application audio goes into a second track
+/// (Matroska only).
+pub async fn record(key: TargetKey, args: &RecordArgs, cancel: &CancellationToken) -> Result<RecordReport, Box<dyn std::error::Error>> {
+    capture::ensure_capture_allowed()?;
+    let output = config::resolve_output_path(&args.output);
+    let path = Path::new(&output);
+    let container = Container::from_path(path);
+    if container == Container::Mp4 {
+        mp4::check_available()?;
+    }
+    if container != Container::Mkv && args.audio.is_some() {
+        return Err("--audio needs a Matroska (.mkv) output".into());
+    }
+    let captions = match args.show_keys {
+        true => {
+            keys::confirm_consent(args.accept_keystroke_capture)?;
+            Some(KeyCaptions::start()?)
+        }
+        false => None,
+    };
+    let mut options = session_pool::capture_options(key, args.fps.max(1))?;
+    options.show_cursor = args.show_cursor || args.highlight_clicks;
+    let highlighter = if args.highlight_clicks { ClickHighlighter::start() } else { None };
+    if args.highlight_clicks && highlighter.is_none() {
+        status!("Warning: the pointer can't be read on this platform, clicks won't be highlighted");
+    }
+    let mut app_audio = match args.audio {
+        Some(_) => AppAudio::start(&mut options)?,
+        None => None,
+    };
+    let mut session = AsyncCaptureSession::start(options.clone(), cancel).await?;
+    let mut follower = WindowFollower::new(&options);
+
+    if let Some(parent) = path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+    let sidecar_path = args.timestamp_sidecar.then(|| format!("{}.frames.jsonl", output));
+    let mut sidecar = match &sidecar_path {
+        Some(sidecar_path) => Some(std::fs::File::create(sidecar_path)?),
+        None => None,
+    };
+
+    let segment_ms = (args.segment * 1000.0).clamp(1.0, mkv::MAX_CLUSTER_MS as f64) as u64;
+    let deadline = args.duration.map(|duration| Instant::now() + Duration::from_secs_f64(duration.max(0.0)));
+    let mut writer: Option<(VideoWriter, u32, u32)> = None;
+    let mut started = Instant::now();
+    let mut frames = 0usize;
+    let mut monitor = PauseMonitor::default();
+    let mut privacy_monitor = PrivacyMonitor::default();
+    let mut gaps = Vec::new();
+    // Paused time is left out of frame timestamps
+    let mut paused = Duration::ZERO;
+    let mut session_ended = false;
+    let mut ended = "stopped";
+
+    status!("Recording {} to {} (Ctrl-C to stop)", key, output);
+    loop {
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        if remaining.is_some_and(|remaining| remaining.is_zero()) {
+            ended = "duration";
+            break;
+        }
+        if let Some(reason) = monitor.poll() {
+            let gap = tokio::select! {
+                _ = cancel.cancelled() => break,
+                _ = tokio::time::sleep(remaining.unwrap_or(Duration::MAX)) => {
+                    ended = "duration";
+                    break;
+                }
+                gap = monitor.wait_for_resume(reason) => gap,
+            };
+            paused += Duration::from_millis(gap.duration_ms as u64);
+            gaps.push(gap);
+            // Frames and audio queued before the pause are stale, start over
+            drop(session);
+            session = AsyncCaptureSession::start(options.clone(), cancel).await?;
+            if let Some(app_audio) = &app_audio {
+                app_audio.drain();
+            }
+        }
+        if let Some(follower) = &mut follower {
+            let change = if session_ended { Some(WindowChange::Closed) } else { follower.poll(&options) };
+            match change {
+                Some(WindowChange::Moved(moved)) => {
+                    drop(session);
+                    session = AsyncCaptureSession::start(moved.clone(), cancel).await?;
+                    options = moved;
+                }
+                Some(WindowChange::Closed) if args.reattach_by_title => {
+                    status!("Window \"{}\" closed, waiting for it to reappear", follower.title());
+                    let mut reattached = tokio::select! {
+                        _ = cancel.cancelled() => break,
+                        _ = tokio::time::sleep(remaining.unwrap_or(Duration::MAX)) => {
+                            ended = "duration";
+                            break;
+                        }
+                        reattached = follower.wait_for_reattach(&options) => reattached,
+                    };
+                    drop(session);
+                    // The reappeared window may belong to a new process
+                    if app_audio.is_some() {
+                        drop(app_audio.take());
+                        app_audio = AppAudio::start(&mut reattached)?;
+                    }
+                    session = AsyncCaptureSession::start(reattached.clone(), cancel).await?;
+                    options = reattached;
+                    session_ended = false;
+                }
+                Some(WindowChange::Closed) => {
+                    status!("Window \"{}\" closed, stopping", follower.title());
+                    ended = "target_closed";
+                    break;
+                }
+                None => {}
+            }
+        }
+        match privacy_monitor.poll(&options) {
+            Ok(false) => {}
+            Ok(true) => {
+                privacy::exclude_sensitive(&mut options)?;
+                drop(session);
+                session = AsyncCaptureSession::start(options.clone(), cancel).await?;
+            }
+            Err(e) => {
+                status!("Recording stopped: {}", e);
+                ended = "privacy_blocked";
+                break;
+            }
+        }
+        // Audio from before the first video frame has nowhere to go and is dropped
+        if let Some(app_audio) = &app_audio {
+            let samples = app_audio.drain();
+            if let Some((VideoWriter::Mkv(mkv), _, _)) = &mut writer
+                && !samples.is_empty()
+            {
+                mkv.write_audio(started.elapsed().saturating_sub(paused).as_millis() as u64, &samples, segment_ms)?;
+            }
+        }
+        let frame = tokio::select! {
+            _ = tokio::time::sleep(remaining.unwrap_or(Duration::MAX)) => {
+                ended = "duration";
+                break;
+            }
+            // Unchanging screens and windows deliver no frames, keep checking
+            // window geometry and the session state
+            _ = tokio::time::sleep(follow::CHECK_INTERVAL) => continue,
+            frame = session.next_frame() => match frame {
+                Ok(frame) => frame,
+                // Ctrl-C ends the recording normally
+                Err(e) if e.kind == ErrorKind::Cancelled => break,
+                // The backend may end the session of a window that went away
+                Err(_) if follower.as_ref().is_some_and(WindowFollower::is_gone) => {
+                    session_ended = true;
+                    continue;
+                }
+                Err(e) => {
+                    status!("Recording stopped: {}", e);
+                    ended = "capture_ended";
+                    break;
+                }
+            },
+        };
+        let mut video_frame = match frame {
+            Frame::Video(video_frame) => video_frame,
+            Frame::Audio(audio_frame) => {
+                if let Some((VideoWriter::Mkv(mkv), _, _)) = &mut writer {
+                    mkv.write_audio(started.elapsed().saturating_sub(paused).as_millis() as u64, &audio::frame_to_samples(&audio_frame), segment_ms)?;
+                }
+                continue;
+            }
+        };
+        privacy::mask_frame(&options, &mut video_frame);
+        let times = FrameTimes::new(convert::video_frame_display_time(&video_frame), SystemTime::now(), Instant::now());
+
+        let (mut width, mut height, mut rgb_data) = convert::video_frame_to_stream_rgb8(video_frame, None, args.max_width)?;
+        if let Some(highlighter) = &highlighter {
+            highlighter.draw(&options, width, height, &mut rgb_data);
+        }
+        if let Some(captions) = &captions {
+            captions.draw(width, height, &mut rgb_data);
+        }
+        if args.timestamp_overlay {
+            timestamp::draw_overlay(width, height, &mut rgb_data, &times);
+        }
+        // MP4 and GIF frames can't change size mid-stream
+        if (args.lock_size || container != Container::Mkv)
+            && let Some((_, locked_width, locked_height)) = writer
+        {
+            rgb_data = convert::letterbox_rgb8(width, height, rgb_data, locked_width, locked_height);
+            (width, height) = (locked_width, locked_height);
+        }
+
+        let (video, _, _) = match &mut writer {
+            Some(writer) => writer,
+            None => {
+                started = Instant::now();
+                paused = Duration::ZERO;
+                let audio = args.audio.map(|_| (audio::SAMPLE_RATE, audio::CHANNELS));
+                writer.insert((VideoWriter::create(container, path, width, height, audio)?, width, height))
+            }
+        };
+        let timestamp_ms = started.elapsed().saturating_sub(paused).as_millis() as u64;
+        video.write_frame(timestamp_ms, width, height, &rgb_data, segment_ms)?;
+        if let Some(sidecar) = &mut sidecar {
+            // One line per frame, written as it goes so a crash keeps the lines of the frames that made it
+            let line = serde_json::json!({ "frame": frames, "run_id": config::settings().run_id, "timestamp_ms": timestamp_ms, "frame_times": times });
+            writeln!(sidecar, "{}", line)?;
+        }
+        frames += 1;
+    }
+    drop(session);
+    let end_ms = started.elapsed().saturating_sub(paused).as_millis() as u64;
+
+    let Some((writer, _, _)) = writer else {
+        return Err(CaptestError::new(ErrorKind::CaptureTimeout, "No frames were recorded").into());
+    };
+    let (recorded_frames, duration_ms) = writer.finish(path, end_ms)?;
+    status!("Recorded {} frames ({:.1}s) to {}", recorded_frames, duration_ms as f64 / 1000.0, output);
+    result!("{}", output);
+
+    Ok(RecordReport {
+        status: "ok".to_string(),
+        target: key.to_string(),
+        output,
+        frames: recorded_frames,
+        duration_ms,
+        gaps,
+        ended: ended.to_string(),
+        frame_times: sidecar_path,
+        failure: match ended {
+            "target_closed" => Some(ErrorKind::TargetClosed),
+            "privacy_blocked" => Some(ErrorKind::PrivacyBlocked),
+            _ => None,
+        },
+    })
+}
+
+/// Finalize a recording that was cut short so players can seek in it.
+pub fn recover(path: &Path) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    if Container::from_path(path) != Container::Mkv {
+        return Err("Only Matroska (.mkv) recordings can be recovered".into());
+    }
+    let recovery = mkv::finalize(path)?;
+    if recovery.finalized {
+        status!(
+            "Recovered {} frames ({:.1}s) in {} clusters; removed {} bytes of incomplete data",
+            recovery.frames, recovery.duration_ms as f64 / 1000.0, recovery.clusters, recovery.truncated_bytes
+        );
+    } else {
+        status!("{} is already complete ({} frames)", path.display(), recovery.frames);
+    }
+
+    Ok(serde_json::json!({
+        "status": "ok",
+        "output": path.display().to_string(),
+        "recovery": recovery,
+    }))
+}
@@ -0,0 +1,71 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tokio_util::sync::CancellationToken;
+
+use crate::change::RegionArg;
+use crate::error::{CaptestError, ErrorKind};
+use crate::session_pool::TargetKey;
+use crate::{capture, convert, digest};
+
+/// Shortest prefix of a hash accepted by `--expect`.
+const MIN_PREFIX: usize = 8;
+
+#[derive(Serialize)]
+pub struct HashReport {
+    /// "ok", "mismatch", or "captured" when nothing was expected
+    pub status: String,
+    pub target: String,
+    pub region: String,
+    /// Hex SHA-256 of the region's size and pixels
+    pub hash: String,
+    /// The `--expect` value the hash matched
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matched: Option<String>,
+    #[serde(skip)]
+    pub failure: Option<ErrorKind>,
+}
+
+/// Hash of an RGB8 image: its size and pixels, so two crops of different
+/// shapes never share a hash.
+pub fn hash(width: u32, height: u32, rgb_data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(width.to_le_bytes());
+    hasher.update(height.to_le_bytes());
+    hasher.update(rgb_data);
+    digest::hex(&hasher.finalize())
+}
+
+/// The `assert-region-hash` command: capture `region` of the target, hash
+/// it and compare the hash with each of `expect` (any one matching passes;
+/// a prefix of at least MIN_PREFIX hex digits is enough). Without `expect`
+/// the hash is only printed, to learn the value to check for.
+pub async fn assert_region_hash(key: TargetKey, region: &RegionArg, expect: &[String], cancel: &CancellationToken) -> Result<HashReport, Box<dyn std::error::Error>> {
+    let expect: Vec<String> = expect.iter().map(|hash| hash.trim().to_lowercase()).collect();
+    if let Some(short) = expect.iter().find(|hash| hash.len() < MIN_PREFIX || !hash.chars().all(|c| c.is_ascii_hexdigit())) {
+        return Err(format!("--expect {} must be at least {} hex digits of a hash", short, MIN_PREFIX).into());
+    }
+    let rect = region.resolve()?.rect;
+    let region_name = match region {
+        RegionArg::Named(name) => format!("@{}", name),
+        RegionArg::Rect(rect) => format!("{},{},{},{}", rect.x, rect.y, rect.width, rect.height),
+    };
+
+    // A downscaled frame would hash resampled pixels at the wrong offsets
+    let (width, height, rgb_data) = capture::grab_rgb8_full_resolution(key, cancel).await?;
+    let crop = convert::Crop { x: rect.x, y: rect.y, width: rect.width, height: rect.height };
+    let rgb_data = convert::crop_rgb8(width, height, rgb_data, crop)
+        .map_err(|e| CaptestError::new(ErrorKind::Failure, format!("Region {}: {}", region_name, e)))?;
+    let hash = hash(crop.width, crop.height, &rgb_data);
+    result!("{}", hash);
+
+    let matched = expect.iter().find(|expected| hash.starts_with(expected.as_str())).cloned();
+    let (status, failure) = match (&matched, expect.is_empty()) {
+        (_, true) => ("captured", None),
+        (Some(_), false) => ("ok", None),
+        (None, false) => {
+            status!("Region {} hash {} matches none of {}", region_name, hash, expect.join(", "));
+            ("mismatch", Some(ErrorKind::HashMismatch))
+        }
+    };
+    Ok(HashReport { status: status.to_string(), target: key.to_string(), region: region_name, hash, matched, failure })
+}
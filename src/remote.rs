@@ -0,0 +1,179 @@
+use std::process::Stdio;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+use tokio::process::{Child, Command};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{CaptestError, ErrorKind};
+use crate::session_pool::TargetKey;
+use crate::{capture, config, RemoteCaptureArgs};
+
+/// How long ssh may take to log in and the daemon to answer `/health`.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How often the tunnel is probed while it comes up.
+const PROBE_INTERVAL: Duration = Duration::from_millis(250);
+/// How long a capture or analysis request may take once connected.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// An `ssh -L` port forward from a free local port to `captest serve` on the
+/// remote machine's loopback interface. The daemon never has to listen on
+/// the network; ssh provides authentication and encryption. The forward is
+/// closed when the tunnel is dropped.
+struct Tunnel {
+    ssh: Child,
+    local_port: u16,
+    destination: String,
+    remote_port: u16,
+}
+
+impl Tunnel {
+    /// Start ssh and wait until the daemon answers through the forward.
+    async fn open(destination: &str, remote_port: u16, cancel: &CancellationToken) -> Result<Self, Box<dyn std::error::Error>> {
+        // Let the OS pick a free port, then hand it to ssh
+        let local_port = std::net::TcpListener::bind("127.0.0.1:0")?.local_addr()?.port();
+        let mut command = Command::new("ssh");
+        command.args(["-N", "-o", "ExitOnForwardFailure=yes", "-L", &format!("127.0.0.1:{}:127.0.0.1:{}", local_port, remote_port)]);
+        if capture::non_interactive() {
+            // Fail instead of waiting for a password nobody will type
+            command.args(["-o", "BatchMode=yes"]);
+        }
+        // `--` keeps a destination starting with `-` from being read as an ssh option
+        let ssh = command.args(["--", destination]).stdin(Stdio::inherit()).stdout(Stdio::null()).kill_on_drop(true).spawn()
+            .map_err(|e| format!("Failed to run ssh: {}", e))?;
+        let mut tunnel = Self { ssh, local_port, destination: destination.to_string(), remote_port };
+
+        status!("Connecting to {} through ssh...", destination);
+        let started = Instant::now();
+        let client = reqwest::Client::builder().timeout(PROBE_INTERVAL * 4).build()?;
+        loop {
+            if let Some(exit) = tunnel.ssh.try_wait()? {
+                return Err(format!("ssh to {} exited ({})", destination, exit).into());
+            }
+            if let Ok(response) = client.get(tunnel.url("/health")).send().await
+                && response.status().is_success()
+            {
+                status!("Connected to captest serve on {}", destination);
+                return Ok(tunnel);
+            }
+            if started.elapsed() > CONNECT_TIMEOUT {
+                return Err(tunnel.unreachable().into());
+            }
+            tokio::select! {
+                _ = cancel.cancelled() => return Err(CaptestError::new(ErrorKind::Cancelled, "Remote capture cancelled").into()),
+                _ = tokio::time::sleep(PROBE_INTERVAL) => {}
+            }
+        }
+    }
+
+    fn url(&self, path_and_query: &str) -> String {
+        format!("http://127.0.0.1:{}{}", self.local_port, path_and_query)
+    }
+
+    fn unreachable(&self) -> String {
+        format!("captest serve isn't answering on {} port {}; start it there with `captest serve --bind 127.0.0.1:{}`",
+            self.destination, self.remote_port, self.remote_port)
+    }
+
+    /// GET `path_and_query` through the tunnel, failing on error statuses
+    /// with the daemon's message.
+    async fn get(&self, path_and_query: &str, cancel: &CancellationToken) -> Result<reqwest::Response, Box<dyn std::error::Error>> {
+        let request = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build()?.get(self.url(path_and_query)).send();
+        let response = tokio::select! {
+            _ = cancel.cancelled() => return Err(CaptestError::new(ErrorKind::Cancelled, "Remote capture cancelled").into()),
+            response = request => response.map_err(|e| format!("{} ({})", self.unreachable(), e))?,
+        };
+        let status = response.status();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let message = response.text().await.unwrap_or_default();
+        // The daemon answers 503 when it can't capture the target
+        let kind = match status.as_u16() {
+            503 if message.to_lowercase().contains("not found") => ErrorKind::TargetNotFound,
+            503 => ErrorKind::CaptureTimeout,
+            502 => ErrorKind::LlmFailed,
+            _ => ErrorKind::Failure,
+        };
+        Err(CaptestError::new(kind, format!("{} answered {}: {}", self.destination, status.as_u16(), message.trim())).into())
+    }
+}
+
+/// A query selecting `key` the way `captest serve` expects, plus whichever
+/// of `params` are set.
+fn serve_query(key: TargetKey, params: &[(&str, Option<String>)]) -> String {
+    let mut query = url::form_urlencoded::Serializer::new(String::new());
+    match key {
+        TargetKey::Screen(index) => query.append_pair("screen", &index.to_string()),
+        TargetKey::Window(index) => query.append_pair("window", &index.to_string()),
+    };
+    for (name, value) in params {
+        if let Some(value) = value {
+            query.append_pair(name, value);
+        }
+    }
+    query.finish()
+}
+
+/// A JPEG fetched from `captest serve`.
+pub struct Fetched {
+    pub jpeg_bytes: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+    pub elapsed_ms: u128,
+}
+
+/// Fetch a JPEG of `key` from `captest serve` on `destination` through an
+/// SSH tunnel.
+pub async fn fetch(destination: &str, port: u16, key: TargetKey, crop: Option<&str>, max_width: Option<u32>, cancel: &CancellationToken) -> Result<Fetched, Box<dyn std::error::Error>> {
+    let tunnel = Tunnel::open(destination, port, cancel).await?;
+    let query = serve_query(key, &[("crop", crop.map(str::to_string)), ("max_width", max_width.map(|width| width.to_string()))]);
+
+    let started = Instant::now();
+    let path = format!("/capture?{}", query);
+    let response = tunnel.get(&path, cancel).await?;
+    let jpeg_bytes = response.bytes().await?.to_vec();
+    let (width, height) = image::load_from_memory(&jpeg_bytes).map(|image| (image.width(), image.height())).unwrap_or((0, 0));
+    Ok(Fetched { jpeg_bytes, width, height, elapsed_ms: started.elapsed().as_millis() })
+}
+
+/// The `remote ... capture` and `remote ... capture-window` commands: fetch
+/// a JPEG of `key` from `captest serve` on `destination` through an SSH
+/// tunnel and save it locally.
+pub async fn capture(destination: &str, port: u16, key: TargetKey, args: &RemoteCaptureArgs, cancel: &CancellationToken) -> Result<Value, Box<dyn std::error::Error>> {
+    let fetched = fetch(destination, port, key, args.crop.as_deref(), args.max_width, cancel).await?;
+    let output = config::resolve_output_path(&args.output.clone().unwrap_or_else(|| {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        format!("remote_{}.jpg", timestamp)
+    }));
+    let output = crate::save_image_bytes(&fetched.jpeg_bytes, &output)?;
+    result!("Saved {} of {} ({}x{}) to {}", key, destination, fetched.width, fetched.height, output);
+
+    Ok(json!({
+        "status": "ok",
+        "destination": destination,
+        "target": key.to_string(),
+        "output": output,
+        "width": fetched.width,
+        "height": fetched.height,
+        "bytes": fetched.jpeg_bytes.len(),
+        "elapsed_ms": fetched.elapsed_ms,
+    }))
+}
+
+/// The `remote ... analyze` command: have the daemon on `destination`
+/// capture `key` and analyze it with its LLM.
+pub async fn analyze(destination: &str, port: u16, key: TargetKey, prompt: Option<&str>, cancel: &CancellationToken) -> Result<Value, Box<dyn std::error::Error>> {
+    let tunnel = Tunnel::open(destination, port, cancel).await?;
+    let query = serve_query(key, &[("prompt", prompt.map(str::to_string))]);
+
+    status!("Analyzing {} of {}...", key, destination);
+    let path = format!("/analyze?{}", query);
+    let response = tunnel.get(&path, cancel).await?;
+    let mut report: Value = response.json().await?;
+    if let Some(analysis) = report.get("analysis").and_then(Value::as_str) {
+        result!("{}", analysis);
+    }
+    report["destination"] = json!(destination);
+    Ok(report)
+}
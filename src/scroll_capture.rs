@@ -0,0 +1,199 @@
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use scap::Target;
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{CaptestError, ErrorKind};
+use crate::session_pool::{self, TargetKey};
+use crate::{capture, config, memory, platforms};
+
+/// Fewest comparable rows an overlap may be judged on.
+const MIN_OVERLAP_ROWS: usize = 8;
+/// Share of comparable overlapping rows that must be identical.
+const MIN_MATCH: f64 = 0.9;
+/// Scores this close count as a tie, broken by the previous step's shift.
+const TIE: f64 = 0.01;
+/// Frame rate of the capturer kept running while scrolling, so the newest
+/// frame after settling shows the scrolled content.
+const CAPTURE_FPS: u32 = 10;
+/// Columns left out at each side when comparing rows, so a moving scroll
+/// bar doesn't make every row differ.
+const EDGE_FRACTION: u32 = 20;
+
+/// One captured frame of the scrolling window.
+struct Shot {
+    width: u32,
+    height: u32,
+    rgb_data: Vec<u8>,
+    /// Per-row fingerprints; None for rows of a single color, which match anything
+    rows: Vec<Option<u64>>,
+}
+
+impl Shot {
+    fn new(width: u32, height: u32, rgb_data: Vec<u8>) -> Self {
+        let (left, right) = ((width / EDGE_FRACTION) as usize, (width - width / EDGE_FRACTION) as usize);
+        let rows = rgb_data.chunks_exact(width as usize * 3).map(|row| {
+            let gray: Vec<u8> = row[left * 3..right * 3].chunks_exact(3)
+                .map(|p| ((p[0] as u32 * 77 + p[1] as u32 * 150 + p[2] as u32 * 29) >> 8) as u8)
+                .collect();
+            let (min, max) = gray.iter().fold((u8::MAX, 0), |(min, max), &g| (min.min(g), max.max(g)));
+            if max - min < 16 {
+                return None;
+            }
+            let mut hasher = DefaultHasher::new();
+            gray.hash(&mut hasher);
+            Some(hasher.finish())
+        }).collect();
+        Self { width, height, rgb_data, rows }
+    }
+
+    fn row(&self, y: u32) -> &[u8] {
+        let stride = self.width as usize * 3;
+        &self.rgb_data[y as usize * stride..(y as usize + 1) * stride]
+    }
+}
+
+/// The `capture-scroll` command: capture a window, scroll it with synthetic
+/// wheel events, capture again, find how far the content moved by matching
+/// the overlap between the frames, and append only the newly revealed rows,
+/// until the content stops moving or `max_frames` is reached. Toolbars and
+/// footers that stay put appear once.
+pub async fn capture_scroll(window: usize, output: Option<&str>, lines: u32, max_frames: usize, settle: f64, cancel: &CancellationToken) -> Result<Value, Box<dyn std::error::Error>> {
+    let key = TargetKey::Window(window);
+    let Target::Window(info) = session_pool::resolve_target(key).map_err(|e| CaptestError::new(ErrorKind::TargetNotFound, e))? else {
+        unreachable!("window keys resolve to windows");
+    };
+    let output = config::resolve_output_path(&output.map(str::to_string).unwrap_or_else(|| {
+        format!("scroll_{}.png", SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0))
+    }));
+    let settle = Duration::from_secs_f64(settle.max(0.0));
+
+    let grabber = capture::FrameGrabber::new(key, CAPTURE_FPS)?;
+    let (width, height, rgb_data) = grabber.grab(cancel).await?;
+    if !memory::fits(width as u64 * height as u64 * 3 * max_frames as u64, &format!("Stitching up to {} frames", max_frames))? {
+        return Err("Not enough memory to stitch that many frames; lower --max-frames".into());
+    }
+    let mut previous = Shot::new(width, height, rgb_data);
+    let mut stitched = previous.rgb_data.clone();
+    // Rows at the top and bottom that don't scroll, found from the first step
+    let mut fixed: Option<(u32, u32)> = None;
+    let mut last_shift = None;
+    let mut frames = 1;
+    let mut reached_end = false;
+
+    status!("Scrolling window {} ('{}') {} lines at a time (Ctrl-C to stop)", window, info.title, lines);
+    while frames < max_frames {
+        let window_id = info.id;
+        tokio::task::spawn_blocking(move || platforms::scroll(window_id, lines as i32)).await??;
+        tokio::select! {
+            _ = cancel.cancelled() => return Err(CaptestError::new(ErrorKind::Cancelled, "Capture cancelled").into()),
+            _ = tokio::time::sleep(settle) => {}
+        }
+        let (width, height, rgb_data) = grabber.grab(cancel).await?;
+        if (width, height) != (previous.width, previous.height) {
+            status!("Window size changed while scrolling, stopping");
+            break;
+        }
+        let next = Shot::new(width, height, rgb_data);
+
+        let (top, bottom) = *fixed.get_or_insert_with(|| fixed_rows(&previous, &next));
+        if top + bottom >= height || (top..height - bottom).all(|y| previous.row(y) == next.row(y)) {
+            reached_end = true;
+            break;
+        }
+        let Some(shift) = find_shift(&previous, &next, top, height - bottom, last_shift) else {
+            status!("Couldn't match frame {} with the previous one, stopping", frames + 1);
+            break;
+        };
+
+        if frames == 1 {
+            // The footer is appended once at the end
+            stitched.truncate((height - bottom) as usize * width as usize * 3);
+        }
+        for y in height - bottom - shift..height - bottom {
+            stitched.extend_from_slice(next.row(y));
+        }
+        status!("Frame {}: content moved {} px", frames + 1, shift);
+        last_shift = Some(shift);
+        previous = next;
+        frames += 1;
+    }
+
+    if let Some((_, bottom)) = fixed
+        && frames > 1
+    {
+        for y in previous.height - bottom..previous.height {
+            stitched.extend_from_slice(previous.row(y));
+        }
+    }
+    let stitched_height = (stitched.len() / (previous.width as usize * 3)) as u32;
+    image::save_buffer(&output, &stitched, previous.width, stitched_height, image::ColorType::Rgb8)
+        .map_err(|e| format!("Failed to save {}: {}", output, e))?;
+    result!("Stitched {} frames into {} ({}x{})", frames, output, previous.width, stitched_height);
+
+    Ok(json!({
+        "status": "ok",
+        "target": { "kind": "window", "index": window, "id": info.id, "title": info.title },
+        "output": output,
+        "width": previous.width,
+        "height": stitched_height,
+        "frames": frames,
+        "reached_end": reached_end,
+    }))
+}
+
+/// Rows at the top and bottom that are the same in both frames, such as a
+/// toolbar or status bar. Single-color rows at the inner edge of each are
+/// given back to the scrolling area, since blank content would otherwise
+/// be mistaken for part of a toolbar.
+fn fixed_rows(previous: &Shot, next: &Shot) -> (u32, u32) {
+    let height = previous.height;
+    let same = |y: u32| previous.row(y) == next.row(y);
+    let mut top = (0..height).take_while(|&y| same(y)).count() as u32;
+    while top > 0 && previous.rows[top as usize - 1].is_none() {
+        top -= 1;
+    }
+    let mut bottom = (0..height - top).take_while(|&i| same(height - 1 - i)).count() as u32;
+    while bottom > 0 && previous.rows[(height - bottom) as usize].is_none() {
+        bottom -= 1;
+    }
+    (top, bottom)
+}
+
+/// How many pixels the content between rows `start` and `end` moved up from
+/// `previous` to `next`: the shift whose overlap matches best. Ties (e.g.
+/// lists of identical rows) go to the shift closest to `hint`, or else the
+/// smallest. None when no shift matches well enough.
+fn find_shift(previous: &Shot, next: &Shot, start: u32, end: u32, hint: Option<u32>) -> Option<u32> {
+    let (before, after) = (&previous.rows[start as usize..end as usize], &next.rows[start as usize..end as usize]);
+    let band = before.len();
+    let mut best: Option<(f64, u32)> = None;
+    for shift in 1..band.saturating_sub(MIN_OVERLAP_ROWS) {
+        let (mut comparable, mut matched) = (0, 0);
+        for (a, b) in before[shift..].iter().zip(after) {
+            if let (Some(a), Some(b)) = (a, b) {
+                comparable += 1;
+                matched += (a == b) as usize;
+            }
+        }
+        if comparable < MIN_OVERLAP_ROWS {
+            continue;
+        }
+        let score = matched as f64 / comparable as f64;
+        let shift = shift as u32;
+        let better = match best {
+            None => true,
+            Some((best_score, _)) if score > best_score + TIE => true,
+            Some((best_score, best_shift)) if score >= best_score - TIE => {
+                hint.is_some_and(|hint| hint.abs_diff(shift) < hint.abs_diff(best_shift))
+            }
+            _ => false,
+        };
+        if better {
+            best = Some((score, shift));
+        }
+    }
+    best.filter(|(score, _)| *score >= MIN_MATCH).map(|(_, shift)| shift)
+}
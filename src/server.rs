@@ -0,0 +1,475 @@
+use std::collections::hash_map::DefaultHasher;
+use std::convert::Infallible;
+use std::hash::{Hash, Hasher};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use scap::frame::Frame;
+use serde_json::json;
+
+use crate::change::{IgnoreRegion, RegionArg};
+use crate::convert::{self, Crop};
+use crate::dirty_rects::DirtyRects;
+use crate::{capture, encode, llm, mqtt, websocket};
+use crate::session_pool::{SessionPool, TargetKey};
+use crate::webhook::{self, WebhookEvent};
+
+static CAPTURES_SERVED: AtomicU64 = AtomicU64::new(0);
+
+/// Separates the JPEG parts of `/stream.mjpg`.
+const MJPEG_BOUNDARY: &str = "captestframe";
+/// Frames per second of `/stream.mjpg` unless `fps=N` is given.
+const DEFAULT_STREAM_FPS: u32 = 2;
+/// Browser viewer for `/stream.ws`.
+const VIEWER_PAGE: &str = include_str!("viewer.html");
+
+/// Run the capture HTTP server until interrupted.
+///
+/// `GET /capture?screen=N` or `GET /capture?window=N` returns a JPEG of the
+/// latest frame for that target, reusing a warm capture session when one exists.
+/// `GET /analyze` takes the same target plus an optional `prompt` and returns the
+/// LLM analysis as JSON. `GET /metrics` reports captures served, warm sessions and token usage.
+/// `GET /snapshot.jpg` and `GET /stream.mjpg` serve the target as a camera
+/// (still image with an ETag, and an MJPEG stream) for Home Assistant.
+/// `GET /stream.ws` is a WebSocket that only sends the parts of the frame
+/// that changed, and `GET /view` a page that shows it in a browser.
+pub async fn serve(addr: SocketAddr, idle_timeout: Duration, fps: u32) -> Result<(), Box<dyn std::error::Error>> {
+    if !scap::is_supported() {
+        return Err("Screen capture not supported".into());
+    }
+    if !scap::has_permission() {
+        capture::request_permission();
+        return Err("Please grant screen recording permission and rerun.".into());
+    }
+
+    mqtt::start().await?;
+    let pool = Arc::new(SessionPool::new(idle_timeout, fps));
+
+    // Periodically stop sessions nobody has asked for recently
+    let expiry_pool = pool.clone();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(1).max(idle_timeout / 4));
+        loop {
+            interval.tick().await;
+            expiry_pool.expire_idle();
+        }
+    });
+
+    let make_service = make_service_fn(move |_conn| {
+        let pool = pool.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req| handle_request(req, pool.clone())))
+        }
+    });
+
+    status!("Serving captures on http://{}", addr);
+    Server::try_bind(&addr)?.serve(make_service).await?;
+
+    Ok(())
+}
+
+async fn handle_request(req: Request<Body>, pool: Arc<SessionPool>) -> Result<Response<Body>, Infallible> {
+    if req.method() != Method::GET {
+        return Ok(text_response(StatusCode::METHOD_NOT_ALLOWED, "Only GET is supported"));
+    }
+
+    // The upgrade needs the request itself
+    if req.uri().path() == "/stream.ws" {
+        return Ok(patch_stream_response(req, pool));
+    }
+
+    let response = match req.uri().path() {
+        "/health" => text_response(StatusCode::OK, "ok"),
+        "/view" => Response::builder()
+            .header("Content-Type", "text/html; charset=utf-8")
+            .body(Body::from(VIEWER_PAGE))
+            .unwrap(),
+        "/metrics" => json_response(StatusCode::OK, &metrics(&pool)),
+        "/capture" => match (parse_target(req.uri().query()), parse_transform(req.uri().query())) {
+            (Some(key), Ok(transform)) => capture_response(key, transform, pool).await,
+            (None, _) => text_response(StatusCode::BAD_REQUEST, "Expected ?screen=N or ?window=N"),
+            (_, Err(message)) => text_response(StatusCode::BAD_REQUEST, &message),
+        },
+        "/snapshot.jpg" => match (parse_target(req.uri().query()), parse_transform(req.uri().query())) {
+            (Some(key), Ok(transform)) => {
+                let if_none_match = req.headers().get("If-None-Match").and_then(|value| value.to_str().ok()).map(str::to_string);
+                snapshot_response(key, transform, if_none_match, pool).await
+            }
+            (None, _) => text_response(StatusCode::BAD_REQUEST, "Expected ?screen=N or ?window=N"),
+            (_, Err(message)) => text_response(StatusCode::BAD_REQUEST, &message),
+        },
+        "/stream.mjpg" => match (parse_target(req.uri().query()), parse_transform(req.uri().query()), parse_stream_fps(req.uri().query())) {
+            (Some(key), Ok(transform), Ok(fps)) => stream_response(key, transform, fps, pool),
+            (None, _, _) => text_response(StatusCode::BAD_REQUEST, "Expected ?screen=N or ?window=N"),
+            (_, Err(message), _) | (_, _, Err(message)) => text_response(StatusCode::BAD_REQUEST, &message),
+        },
+        "/analyze" => match parse_target(req.uri().query()) {
+            Some(key) => analyze_response(key, query_param(req.uri().query(), "prompt"), pool).await,
+            None => text_response(StatusCode::BAD_REQUEST, "Expected ?screen=N or ?window=N"),
+        },
+        _ => text_response(StatusCode::NOT_FOUND, "Not found"),
+    };
+
+    Ok(response)
+}
+
+async fn capture_response(key: TargetKey, transform: Transform, pool: Arc<SessionPool>) -> Response<Body> {
+    match latest_jpeg(key, transform, pool).await {
+        Ok(jpeg_bytes) => Response::builder()
+            .header("Content-Type", "image/jpeg")
+            .body(Body::from(jpeg_bytes))
+            .unwrap(),
+        Err(response) => response,
+    }
+}
+
+/// The latest frame as a camera snapshot. Its ETag lets pollers skip
+/// downloading a screen that hasn't changed.
+async fn snapshot_response(key: TargetKey, transform: Transform, if_none_match: Option<String>, pool: Arc<SessionPool>) -> Response<Body> {
+    let jpeg_bytes = match latest_jpeg(key, transform, pool).await {
+        Ok(bytes) => bytes,
+        Err(response) => return response,
+    };
+    let mut hasher = DefaultHasher::new();
+    jpeg_bytes.hash(&mut hasher);
+    let etag = format!("\"{:016x}\"", hasher.finish());
+
+    let response = Response::builder()
+        .header("ETag", &etag)
+        // Always revalidate: the screen can change at any moment
+        .header("Cache-Control", "no-cache, max-age=0");
+    if if_none_match.is_some_and(|tags| tags.split(',').any(|tag| tag.trim() == etag || tag.trim() == "*")) {
+        return response.status(StatusCode::NOT_MODIFIED).body(Body::empty()).unwrap();
+    }
+    response
+        .header("Content-Type", "image/jpeg")
+        .header("Content-Length", jpeg_bytes.len())
+        .body(Body::from(jpeg_bytes))
+        .unwrap()
+}
+
+/// A `multipart/x-mixed-replace` MJPEG stream of the target at `fps`, until
+/// the client disconnects. Stream frames don't trigger webhooks or MQTT
+/// capture events.
+fn stream_response(key: TargetKey, transform: Transform, fps: u32, pool: Arc<SessionPool>) -> Response<Body> {
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / fps as f64));
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+        loop {
+            interval.tick().await;
+            let jpeg_bytes = match encode_latest_frame(key, transform, pool.clone()).await {
+                Ok((jpeg_bytes, _, _)) => jpeg_bytes,
+                Err((_, message)) => {
+                    status!("Stream of {} ended: {}", key, message);
+                    return;
+                }
+            };
+            let header = format!("--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n", MJPEG_BOUNDARY, jpeg_bytes.len());
+            let mut part = header.into_bytes();
+            part.extend_from_slice(&jpeg_bytes);
+            part.extend_from_slice(b"\r\n");
+            // Fails once the client has gone away
+            if sender.send_data(part.into()).await.is_err() {
+                return;
+            }
+        }
+    });
+    Response::builder()
+        .header("Content-Type", format!("multipart/x-mixed-replace; boundary={}", MJPEG_BOUNDARY))
+        .header("Cache-Control", "no-cache, no-store")
+        .body(body)
+        .unwrap()
+}
+
+/// Image format of `/stream.ws` patches.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum PatchFormat {
+    Jpeg,
+    /// Lossless, and smaller than JPEG for flat UI areas
+    Png,
+}
+
+/// Upgrade to a WebSocket that streams the target at `fps` as dirty
+/// rectangles: each binary message updates the viewer's copy of the frame.
+/// A message is the frame width, height and patch count, then for each patch
+/// its x, y, width, height and image length followed by the image; all
+/// numbers are big-endian u32. The first message covers the whole frame and
+/// nothing is sent while the screen doesn't change.
+fn patch_stream_response(req: Request<Body>, pool: Arc<SessionPool>) -> Response<Body> {
+    let query = req.uri().query();
+    let (key, transform, fps, format) = match (parse_target(query), parse_transform(query), parse_stream_fps(query), parse_patch_format(query)) {
+        (Some(key), Ok(transform), Ok(fps), Ok(format)) => (key, transform, fps, format),
+        (None, _, _, _) => return text_response(StatusCode::BAD_REQUEST, "Expected ?screen=N or ?window=N"),
+        (_, Err(message), _, _) | (_, _, Err(message), _) | (_, _, _, Err(message)) => return text_response(StatusCode::BAD_REQUEST, &message),
+    };
+    let upgrade = req.headers().get("Upgrade").and_then(|value| value.to_str().ok());
+    let Some(client_key) = req.headers().get("Sec-WebSocket-Key").and_then(|value| value.to_str().ok())
+        .filter(|_| upgrade.is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket")))
+    else {
+        return text_response(StatusCode::UPGRADE_REQUIRED, "Expected a WebSocket upgrade");
+    };
+    let accept = websocket::accept_key(client_key);
+
+    tokio::spawn(async move {
+        match hyper::upgrade::on(req).await {
+            Ok(upgraded) => stream_patches(upgraded, key, transform, fps, format, pool).await,
+            Err(e) => status!("WebSocket upgrade for {} failed: {}", key, e),
+        }
+    });
+    Response::builder()
+        .status(StatusCode::SWITCHING_PROTOCOLS)
+        .header("Upgrade", "websocket")
+        .header("Connection", "Upgrade")
+        .header("Sec-WebSocket-Accept", accept)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Send dirty rectangles of the target until the viewer closes the socket or
+/// the capture fails. Like `/stream.mjpg`, no webhooks or MQTT events.
+async fn stream_patches(upgraded: hyper::upgrade::Upgraded, key: TargetKey, transform: Transform, fps: u32, format: PatchFormat, pool: Arc<SessionPool>) {
+    let (mut reader, mut writer) = tokio::io::split(upgraded);
+
+    // The viewer only sends pings and the close handshake; answer them from
+    // the sending loop so frames don't interleave
+    let (control_sender, mut control) = tokio::sync::mpsc::channel::<(u8, Vec<u8>)>(4);
+    tokio::spawn(async move {
+        loop {
+            match websocket::read_frame(&mut reader).await {
+                Ok((websocket::OPCODE_PING, payload)) => {
+                    if control_sender.send((websocket::OPCODE_PONG, payload)).await.is_err() {
+                        return;
+                    }
+                }
+                Ok((websocket::OPCODE_CLOSE, _)) | Err(_) => {
+                    let _ = control_sender.send((websocket::OPCODE_CLOSE, Vec::new())).await;
+                    return;
+                }
+                Ok(_) => {}
+            }
+        }
+    });
+
+    let mut dirty = DirtyRects::default();
+    let (mut updates, mut bytes_sent) = (0u64, 0u64);
+    let mut interval = tokio::time::interval(Duration::from_secs_f64(1.0 / fps as f64));
+    interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+    let ended = loop {
+        tokio::select! {
+            message = control.recv() => match message {
+                Some((websocket::OPCODE_PONG, payload)) => {
+                    if websocket::write_frame(&mut writer, websocket::OPCODE_PONG, &payload).await.is_err() {
+                        break "connection lost".to_string();
+                    }
+                }
+                _ => {
+                    let _ = websocket::write_frame(&mut writer, websocket::OPCODE_CLOSE, &[]).await;
+                    break "viewer closed the stream".to_string();
+                }
+            },
+            _ = interval.tick() => {
+                let (width, height, rgb_data) = match latest_rgb8(key, transform, pool.clone()).await {
+                    Ok(frame) => frame,
+                    Err((_, message)) => {
+                        // 1011: the server hit an error
+                        let _ = websocket::write_frame(&mut writer, websocket::OPCODE_CLOSE, &1011u16.to_be_bytes()).await;
+                        break message;
+                    }
+                };
+                let rects = dirty.update(width, height, rgb_data);
+                if rects.is_empty() {
+                    continue;
+                }
+                let message = match encode_patches(&dirty, width, height, &rects, format) {
+                    Ok(message) => message,
+                    Err(e) => break e.to_string(),
+                };
+                if websocket::write_frame(&mut writer, websocket::OPCODE_BINARY, &message).await.is_err() {
+                    break "connection lost".to_string();
+                }
+                updates += 1;
+                bytes_sent += message.len() as u64;
+            }
+        }
+    };
+    status!("Patch stream of {} ended ({}): {} updates, {} KB", key, ended, updates, bytes_sent / 1024);
+}
+
+/// One `/stream.ws` message with `rects` of the frame `dirty` holds.
+fn encode_patches(dirty: &DirtyRects, width: u32, height: u32, rects: &[Crop], format: PatchFormat) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut message = Vec::new();
+    for value in [width, height, rects.len() as u32] {
+        message.extend_from_slice(&value.to_be_bytes());
+    }
+    for &rect in rects {
+        let rgb_data = convert::crop_scaled_rgb8(width, height, dirty.frame(), rect, rect.width, rect.height)?;
+        let image = match format {
+            PatchFormat::Jpeg => encode::rgb8_to_jpeg(rect.width, rect.height, &rgb_data, 75)?,
+            PatchFormat::Png => {
+                use image::ImageEncoder;
+                let mut png_bytes = Vec::new();
+                image::codecs::png::PngEncoder::new(&mut png_bytes).write_image(&rgb_data, rect.width, rect.height, image::ColorType::Rgb8)?;
+                png_bytes
+            }
+        };
+        for value in [rect.x, rect.y, rect.width, rect.height, image.len() as u32] {
+            message.extend_from_slice(&value.to_be_bytes());
+        }
+        message.extend_from_slice(&image);
+    }
+    Ok(message)
+}
+
+async fn analyze_response(key: TargetKey, prompt: Option<String>, pool: Arc<SessionPool>) -> Response<Body> {
+    let jpeg_bytes = match latest_jpeg(key, Transform::default(), pool).await {
+        Ok(bytes) => bytes,
+        Err(response) => return response,
+    };
+
+    // Frames change constantly, so the response cache is skipped here
+    match llm::analyze_image_cached(&jpeg_bytes, prompt.as_deref(), None).await {
+        Ok(analysis) => json_response(StatusCode::OK, &json!({
+            "status": "ok",
+            "target": key.to_string(),
+            "analysis": analysis.text,
+            "usage": analysis.usage,
+        })),
+        Err(e) => text_response(StatusCode::BAD_GATEWAY, &format!("LLM analysis failed: {}", e)),
+    }
+}
+
+/// Optional crop and downscale applied to a served frame.
+#[derive(Clone, Copy, Default)]
+struct Transform {
+    crop: Option<Crop>,
+    max_width: Option<u32>,
+}
+
+/// Grab the latest frame for `key` and encode it, or build the error response.
+async fn latest_jpeg(key: TargetKey, transform: Transform, pool: Arc<SessionPool>) -> Result<Vec<u8>, Response<Body>> {
+    let result = encode_latest_frame(key, transform, pool).await;
+    match &result {
+        Ok((jpeg_bytes, width, height)) => {
+            let details = json!({ "width": width, "height": height, "bytes": jpeg_bytes.len() });
+            mqtt::publish_event(&json!({ "event": "capture", "target": key.to_string(), "width": width, "height": height }));
+            webhook::notify(WebhookEvent::CaptureComplete, key, format!("Served {} ({}x{})", key, width, height), details);
+        }
+        // Bad requests are the client's problem, not worth a notification
+        Err((status, message)) if *status != StatusCode::BAD_REQUEST => {
+            webhook::notify(WebhookEvent::Error, key, format!("{}: {}", key, message), json!({ "error": message }));
+        }
+        Err(_) => {}
+    }
+    result.map(|(jpeg_bytes, _, _)| jpeg_bytes).map_err(|(status, message)| text_response(status, &message))
+}
+
+/// Grab and encode the latest frame for `key`, returning it with its size.
+async fn encode_latest_frame(key: TargetKey, transform: Transform, pool: Arc<SessionPool>) -> Result<(Vec<u8>, u32, u32), (StatusCode, String)> {
+    let started = Instant::now();
+    let (width, height, rgb_data) = latest_rgb8(key, transform, pool).await?;
+    let jpeg_bytes = crate::rgb8_to_jpeg_bytes(width, height, &rgb_data)
+        .map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    CAPTURES_SERVED.fetch_add(1, Ordering::Relaxed);
+    status!("Served {} ({}x{}) in {} ms", key, width, height, started.elapsed().as_millis());
+    Ok((jpeg_bytes, width, height))
+}
+
+/// The latest frame for `key` as RGB8, cropped and scaled by `transform`.
+async fn latest_rgb8(key: TargetKey, transform: Transform, pool: Arc<SessionPool>) -> Result<(u32, u32, Vec<u8>), (StatusCode, String)> {
+    // Waiting on the session's first frame blocks, keep it off the runtime workers
+    let frame = match tokio::task::spawn_blocking(move || pool.latest_frame(key)).await {
+        Ok(Ok(frame)) => frame,
+        Ok(Err(e)) => return Err((StatusCode::SERVICE_UNAVAILABLE, e.to_string())),
+        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string())),
+    };
+
+    let Frame::Video(video_frame) = frame else {
+        return Err((StatusCode::INTERNAL_SERVER_ERROR, "Received a non-video frame".to_string()));
+    };
+
+    convert::video_frame_to_stream_rgb8(video_frame, transform.crop, transform.max_width)
+        .map_err(|e| (StatusCode::BAD_REQUEST, e))
+}
+
+fn metrics(pool: &SessionPool) -> serde_json::Value {
+    let sessions: Vec<String> = pool.targets().iter().map(TargetKey::to_string).collect();
+    json!({
+        "captures_served": CAPTURES_SERVED.load(Ordering::Relaxed),
+        "sessions": sessions,
+        "llm": llm::session_usage(),
+    })
+}
+
+fn parse_target(query: Option<&str>) -> Option<TargetKey> {
+    url::form_urlencoded::parse(query?.as_bytes()).find_map(|(name, value)| {
+        let index = value.parse().ok()?;
+        match name.as_ref() {
+            "screen" => Some(TargetKey::Screen(index)),
+            "window" => Some(TargetKey::Window(index)),
+            _ => None,
+        }
+    })
+}
+
+/// `crop=X,Y,WIDTH,HEIGHT` (or `crop=@NAME` for a region from the config
+/// file) and `max_width=N`.
+fn parse_transform(query: Option<&str>) -> Result<Transform, String> {
+    let crop = match query_param(query, "crop") {
+        Some(crop) => {
+            let region: RegionArg = crop.parse().map_err(|_| "Expected crop=X,Y,WIDTH,HEIGHT or crop=@NAME".to_string())?;
+            let IgnoreRegion { x, y, width, height } = region.resolve()?.rect;
+            Some(Crop { x, y, width, height })
+        }
+        None => None,
+    };
+    let max_width = match query_param(query, "max_width") {
+        Some(max_width) => Some(max_width.parse().map_err(|_| "Expected max_width=N".to_string())?),
+        None => None,
+    };
+    Ok(Transform { crop, max_width })
+}
+
+/// `fps=N` for `/stream.mjpg`.
+fn parse_stream_fps(query: Option<&str>) -> Result<u32, String> {
+    match query_param(query, "fps") {
+        Some(fps) => fps.parse().ok().filter(|fps| (1..=30).contains(fps)).ok_or_else(|| "Expected fps=N between 1 and 30".to_string()),
+        None => Ok(DEFAULT_STREAM_FPS),
+    }
+}
+
+/// `format=jpeg` (default) or `format=png` for `/stream.ws`.
+fn parse_patch_format(query: Option<&str>) -> Result<PatchFormat, String> {
+    match query_param(query, "format").as_deref() {
+        None | Some("jpeg") | Some("jpg") => Ok(PatchFormat::Jpeg),
+        Some("png") => Ok(PatchFormat::Png),
+        Some(_) => Err("Expected format=jpeg or format=png".to_string()),
+    }
+}
+
+fn query_param(query: Option<&str>, param: &str) -> Option<String> {
+    url::form_urlencoded::parse(query?.as_bytes())
+        .find(|(name, _)| name == param)
+        .map(|(_, value)| value.into_owned())
+}
+
+fn text_response(status: StatusCode, message: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "text/plain; charset=utf-8")
+        .body(Body::from(message.to_string()))
+        .unwrap()
+}
+
+fn json_response(status: StatusCode, document: &serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(document.to_string()))
+        .unwrap()
+}
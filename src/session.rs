@@ -0,0 +1,408 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::events::{self, CaptureEvent, CapturedFrame, SubscribeOptions};
+use crate::mkv::MkvWriter;
+use crate::session_pool::TargetKey;
+use crate::zip::{ZipReader, ZipWriter};
+use crate::{capture, config, convert, privacy, SessionArgs};
+
+const MANIFEST: &str = "manifest.json";
+const EVENTS: &str = "events.jsonl";
+const FORMAT: &str = "captest-session";
+const VERSION: u32 = 1;
+/// How often the active window is checked.
+const WINDOW_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+/// Length of the clusters of exported videos.
+const EXPORT_CLUSTER_MS: u64 = 2000;
+
+/// Describes a bundle; written last, so a bundle without one was cut short.
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    pub format: String,
+    pub version: u32,
+    /// The run that recorded the bundle; empty in bundles from older versions
+    #[serde(default)]
+    pub run_id: String,
+    pub target: String,
+    /// Unix milliseconds
+    pub started_at: u128,
+    pub ended_at: u128,
+    pub duration_ms: u128,
+    pub frames: usize,
+    pub ocr_snapshots: usize,
+    pub events: usize,
+    /// "stopped", "duration", "target_closed" or "capture_ended"
+    pub ended: String,
+}
+
+/// Where a bundle being recorded goes: a directory, or a zip archive when
+/// the output ends in `.zip`. Directories get their event log appended as it
+/// happens, archives get it when the recording ends.
+enum BundleWriter {
+    Directory { path: PathBuf, events: BufWriter<File> },
+    Zip { writer: ZipWriter, events: Vec<u8> },
+}
+
+impl BundleWriter {
+    fn create(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        if is_zip(path) {
+            if let Some(parent) = path.parent()
+                && !parent.as_os_str().is_empty()
+            {
+                std::fs::create_dir_all(parent)?;
+            }
+            return Ok(Self::Zip { writer: ZipWriter::create(path)?, events: Vec::new() });
+        }
+        if path.join(MANIFEST).exists() || path.join(EVENTS).exists() {
+            return Err(format!("{} already contains a session", path.display()).into());
+        }
+        std::fs::create_dir_all(path.join("frames"))?;
+        let events = BufWriter::new(File::create(path.join(EVENTS))?);
+        Ok(Self::Directory { path: path.to_path_buf(), events })
+    }
+
+    fn add(&mut self, name: &str, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        match self {
+            Self::Directory { path, .. } => std::fs::write(path.join(name), data)?,
+            Self::Zip { writer, .. } => writer.add(name, data)?,
+        }
+        Ok(())
+    }
+
+    fn event(&mut self, event: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        let line = format!("{}\n", event);
+        match self {
+            Self::Directory { events, .. } => {
+                events.write_all(line.as_bytes())?;
+                events.flush()?;
+            }
+            Self::Zip { events, .. } => events.extend(line.as_bytes()),
+        }
+        Ok(())
+    }
+
+    fn finish(self, manifest: &Manifest) -> Result<(), Box<dyn std::error::Error>> {
+        let manifest = serde_json::to_vec_pretty(manifest)?;
+        match self {
+            Self::Directory { path, mut events } => {
+                events.flush()?;
+                std::fs::write(path.join(MANIFEST), manifest)?;
+            }
+            Self::Zip { mut writer, events } => {
+                writer.add(EVENTS, &events)?;
+                writer.add(MANIFEST, &manifest)?;
+                writer.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A recorded bundle opened for reading.
+enum Bundle {
+    Directory(PathBuf),
+    Zip(ZipReader),
+}
+
+impl Bundle {
+    fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        if path.is_dir() {
+            Ok(Self::Directory(path.to_path_buf()))
+        } else {
+            Ok(Self::Zip(ZipReader::open(path)?))
+        }
+    }
+
+    fn read(&mut self, name: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        match self {
+            Self::Directory(path) => std::fs::read(path.join(name)).map_err(|e| format!("Failed to read {}: {}", name, e).into()),
+            Self::Zip(reader) => reader.read(name),
+        }
+    }
+
+    fn has(&self, name: &str) -> bool {
+        match self {
+            Self::Directory(path) => path.join(name).exists(),
+            Self::Zip(reader) => reader.names().any(|entry| entry == name),
+        }
+    }
+
+    /// The manifest, or None for a bundle whose recording was cut short.
+    fn manifest(&mut self) -> Result<Option<Manifest>, Box<dyn std::error::Error>> {
+        if !self.has(MANIFEST) {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_slice(&self.read(MANIFEST)?)?))
+    }
+
+    fn events(&mut self) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+        let events = self.read(EVENTS)?;
+        // A crash can leave the last line incomplete
+        Ok(String::from_utf8_lossy(&events).lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+    }
+}
+
+fn is_zip(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+fn unix_millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0)
+}
+
+/// Record `key` into a bundle until Ctrl-C or `--duration`: changed frames as
+/// JPEG files, OCR snapshots, the active window and capture events in a
+/// timestamped event log, and a manifest.
+pub async fn record(key: TargetKey, args: &SessionArgs, cancel: &CancellationToken) -> Result<Value, Box<dyn std::error::Error>> {
+    capture::ensure_capture_allowed()?;
+    let output = config::resolve_output_path(&args.output);
+    let mut bundle = BundleWriter::create(Path::new(&output))?;
+
+    let mut subscription = events::subscribe(key, SubscribeOptions {
+        interval: Duration::from_secs_f64(args.interval.max(0.05)),
+        threshold: args.threshold,
+        frames: true,
+        ocr: args.ocr,
+        ignore: config::settings().ignore_regions.clone(),
+        ..SubscribeOptions::default()
+    });
+
+    let started_at = SystemTime::now();
+    let started = Instant::now();
+    let deadline = args.duration.map(|duration| started + Duration::from_secs_f64(duration.max(0.0)));
+    let mut window_check = tokio::time::interval(WINDOW_CHECK_INTERVAL);
+    let mut window: Option<(String, String)> = None;
+    let mut frame: Option<CapturedFrame> = None;
+    let (mut frames, mut ocr_snapshots, mut event_count) = (0usize, 0usize, 0usize);
+    let mut ended = "stopped";
+
+    let mut log = |bundle: &mut BundleWriter, mut event: Value| -> Result<(), Box<dyn std::error::Error>> {
+        event["offset_ms"] = json!(started.elapsed().as_millis());
+        event["timestamp"] = json!(unix_millis(SystemTime::now()));
+        event_count += 1;
+        bundle.event(&event)
+    };
+
+    status!("Recording session of {} to {} (Ctrl-C to stop)", key, output);
+    loop {
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        let event = tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = tokio::time::sleep(remaining.unwrap_or(Duration::MAX)) => {
+                ended = "duration";
+                break;
+            }
+            _ = window_check.tick() => {
+                let active = privacy::active_window();
+                if active != window {
+                    if let Some((app, title)) = &active {
+                        log(&mut bundle, json!({ "event": "window", "app": app, "title": title }))?;
+                    }
+                    window = active;
+                }
+                continue;
+            }
+            event = subscription.next() => event,
+        };
+
+        match event {
+            None => {
+                ended = "capture_ended";
+                break;
+            }
+            Some(CaptureEvent::Frame(captured)) => frame = Some(captured),
+            Some(CaptureEvent::Changed { difference, region }) => {
+                let Some(CapturedFrame { width, height, rgb_data, .. }) = frame.take() else {
+                    continue;
+                };
+                frames += 1;
+                let name = format!("frames/{:06}.jpg", frames);
+                bundle.add(&name, &crate::rgb8_to_jpeg_bytes(width, height, &rgb_data)?)?;
+                log(&mut bundle, json!({
+                    "event": "frame",
+                    "file": name,
+                    "width": width,
+                    "height": height,
+                    "difference": difference,
+                    "region": region,
+                }))?;
+            }
+            Some(CaptureEvent::Ocr { lines, added, removed }) => {
+                ocr_snapshots += 1;
+                log(&mut bundle, json!({ "event": "ocr", "frame": frames, "lines": lines, "added": added, "removed": removed }))?;
+            }
+            Some(CaptureEvent::Closed { title, reattaching }) => {
+                log(&mut bundle, json!({ "event": "closed", "title": title, "reattaching": reattaching }))?;
+                if !reattaching {
+                    status!("Window \"{}\" closed, stopping", title);
+                    ended = "target_closed";
+                    break;
+                }
+            }
+            Some(CaptureEvent::Reattached { title }) => log(&mut bundle, json!({ "event": "reattached", "title": title }))?,
+            Some(CaptureEvent::Paused { reason }) => log(&mut bundle, json!({ "event": "paused", "reason": reason }))?,
+            Some(CaptureEvent::Resumed { gap }) => log(&mut bundle, json!({ "event": "resumed", "gap": gap }))?,
+            Some(CaptureEvent::Error { message, fatal }) => {
+                log(&mut bundle, json!({ "event": "error", "message": message, "fatal": fatal }))?;
+                status!("{}", message);
+                if fatal {
+                    ended = "capture_ended";
+                    break;
+                }
+            }
+        }
+    }
+    drop(subscription);
+
+    let ended_at = SystemTime::now();
+    let manifest = Manifest {
+        format: FORMAT.to_string(),
+        version: VERSION,
+        run_id: config::settings().run_id.clone(),
+        target: key.to_string(),
+        started_at: unix_millis(started_at),
+        ended_at: unix_millis(ended_at),
+        duration_ms: started.elapsed().as_millis(),
+        frames,
+        ocr_snapshots,
+        events: event_count,
+        ended: ended.to_string(),
+    };
+    bundle.finish(&manifest)?;
+    status!("Recorded {} frames and {} events in {:.1}s to {}", frames, event_count, manifest.duration_ms as f64 / 1000.0, output);
+    result!("{}", output);
+
+    Ok(json!({
+        "status": "ok",
+        "output": output,
+        "manifest": manifest,
+    }))
+}
+
+/// Summarize a bundle: its manifest, how many events of each kind it holds
+/// and how long each window was active.
+pub fn inspect(path: &Path) -> Result<Value, Box<dyn std::error::Error>> {
+    let mut bundle = Bundle::open(path)?;
+    let manifest = bundle.manifest()?;
+    let events = bundle.events()?;
+
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for event in &events {
+        *counts.entry(event["event"].as_str().unwrap_or("unknown").to_string()).or_default() += 1;
+    }
+
+    // Each window is active until the next window event, or the end of the recording
+    let end_ms = manifest
+        .as_ref()
+        .map(|manifest| manifest.duration_ms as u64)
+        .or_else(|| events.last().and_then(|event| event["offset_ms"].as_u64()))
+        .unwrap_or(0);
+    let switches: Vec<(&Value, u64)> = events
+        .iter()
+        .filter(|event| event["event"] == "window")
+        .map(|event| (event, event["offset_ms"].as_u64().unwrap_or(0)))
+        .collect();
+    let mut windows: Vec<Value> = Vec::new();
+    for (index, (event, offset)) in switches.iter().enumerate() {
+        let until = switches.get(index + 1).map(|(_, next)| *next).unwrap_or(end_ms);
+        let duration_ms = until.saturating_sub(*offset);
+        match windows.iter_mut().find(|window| window["app"] == event["app"] && window["title"] == event["title"]) {
+            Some(window) => window["duration_ms"] = json!(window["duration_ms"].as_u64().unwrap_or(0) + duration_ms),
+            None => windows.push(json!({ "app": event["app"], "title": event["title"], "duration_ms": duration_ms })),
+        }
+    }
+    windows.sort_by_key(|window| std::cmp::Reverse(window["duration_ms"].as_u64().unwrap_or(0)));
+
+    match &manifest {
+        Some(manifest) => result!(
+            "Session of {} ({}): {:.1}s, {} frames, {} OCR snapshots, ended: {}",
+            manifest.target, path.display(), manifest.duration_ms as f64 / 1000.0, manifest.frames, manifest.ocr_snapshots, manifest.ended
+        ),
+        None => result!("Session {} has no manifest; the recording was cut short", path.display()),
+    }
+    for (kind, count) in &counts {
+        result!("  {:<12} {}", kind, count);
+    }
+    if !windows.is_empty() {
+        result!("Active windows:");
+        for window in &windows {
+            result!(
+                "  {:>8.1}s  {} - {}",
+                window["duration_ms"].as_u64().unwrap_or(0) as f64 / 1000.0,
+                window["app"].as_str().unwrap_or(""),
+                window["title"].as_str().unwrap_or("")
+            );
+        }
+    }
+
+    Ok(json!({
+        "status": "ok",
+        "bundle": path.display().to_string(),
+        "complete": manifest.is_some(),
+        "manifest": manifest,
+        "event_counts": counts,
+        "windows": windows,
+    }))
+}
+
+/// Render the frames of a bundle into a Matroska video that plays them back
+/// with their recorded timing. Frames of other sizes than the first are
+/// letterboxed into it.
+pub fn export_video(path: &Path, video: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let mut bundle = Bundle::open(path)?;
+    let events = bundle.events()?;
+    let output = config::resolve_output_path(video);
+    let output_path = Path::new(&output);
+    if let Some(parent) = output_path.parent()
+        && !parent.as_os_str().is_empty()
+    {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let mut writer: Option<(MkvWriter, u32, u32)> = None;
+    let mut first_offset = None;
+    for event in events.iter().filter(|event| event["event"] == "frame") {
+        let (Some(name), Some(offset)) = (event["file"].as_str(), event["offset_ms"].as_u64()) else {
+            continue;
+        };
+        let mut jpeg_bytes = bundle.read(name)?;
+        let width = event["width"].as_u64().unwrap_or(0) as u32;
+        let height = event["height"].as_u64().unwrap_or(0) as u32;
+        let (mkv, locked_width, locked_height) = match &mut writer {
+            Some(writer) => writer,
+            None => writer.insert((MkvWriter::create(output_path, width, height, None)?, width, height)),
+        };
+        if (width, height) != (*locked_width, *locked_height) {
+            let rgb_data = image::load_from_memory(&jpeg_bytes)?.to_rgb8();
+            let (width, height) = rgb_data.dimensions();
+            let letterboxed = convert::letterbox_rgb8(width, height, rgb_data.into_raw(), *locked_width, *locked_height);
+            jpeg_bytes = crate::rgb8_to_jpeg_bytes(*locked_width, *locked_height, &letterboxed)?;
+        }
+        // Playback starts at the first frame
+        let start = *first_offset.get_or_insert(offset);
+        mkv.write_frame(offset.saturating_sub(start), &jpeg_bytes, EXPORT_CLUSTER_MS)?;
+    }
+
+    let Some((writer, _, _)) = writer else {
+        return Err(format!("{} contains no frames", path.display()).into());
+    };
+    let recording = writer.finish(output_path)?;
+    status!("Exported {} frames ({:.1}s) to {}", recording.frames, recording.duration_ms as f64 / 1000.0, output);
+    result!("{}", output);
+
+    Ok(json!({
+        "status": "ok",
+        "bundle": path.display().to_string(),
+        "output": output,
+        "frames": recording.frames,
+        "duration_ms": recording.duration_ms,
+    }))
+}
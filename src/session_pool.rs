@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use scap::capturer::Options;
+use scap::frame::Frame;
+use scap::Target;
+
+use crate::capture;
+use crate::capture_manager::CaptureManager;
+use crate::config;
+use crate::error::{CaptestError, ErrorKind};
+use crate::privacy::{self, PrivacyMonitor};
+
+/// How long a newly started session may take to deliver its first frame.
+const FIRST_FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TargetKey {
+    Screen(usize),
+    Window(usize),
+}
+
+impl std::fmt::Display for TargetKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TargetKey::Screen(index) => write!(f, "screen {}", index),
+            TargetKey::Window(index) => write!(f, "window {}", index),
+        }
+    }
+}
+
+/// Keeps capturers running for recently requested targets so repeated
+/// captures skip backend session setup. Each session is a [`CaptureManager`]
+/// target whose thread continuously drains frames, so the latest one is always
+/// at hand.
+pub struct SessionPool {
+    captures: CaptureManager,
+    last_used: Mutex<HashMap<TargetKey, Instant>>,
+    /// Options each session was started with, and its check for newly opened
+    /// sensitive windows
+    privacy: Mutex<HashMap<TargetKey, (Options, PrivacyMonitor)>>,
+    idle_timeout: Duration,
+    fps: u32,
+}
+
+impl SessionPool {
+    pub fn new(idle_timeout: Duration, fps: u32) -> Self {
+        Self {
+            // Only the newest frame matters here
+            captures: CaptureManager::new(1),
+            last_used: Mutex::new(HashMap::new()),
+            privacy: Mutex::new(HashMap::new()),
+            idle_timeout,
+            fps: fps.max(1),
+        }
+    }
+
+    /// Return the most recent frame for `key`, starting a warm session if needed.
+    /// Blocks while a new session waits for its first frame.
+    pub fn latest_frame(&self, key: TargetKey) -> Result<Frame, CaptestError> {
+        // Sessions whose capture thread has exited get restarted, and so do
+        // sessions that have to exclude a newly opened sensitive window
+        let queue = match self.captures.queue(key).filter(|queue| !queue.is_closed()) {
+            Some(queue) if !self.privacy_restart(key)? => queue,
+            _ => {
+                self.captures.stop(key);
+                self.captures.start(key, self.options(key)?)
+            }
+        };
+        self.last_used.lock().unwrap().insert(key, Instant::now());
+
+        let mut frame = queue.latest(FIRST_FRAME_TIMEOUT).ok_or_else(|| CaptestError::new(ErrorKind::CaptureTimeout, match queue.is_closed() {
+            true => format!("Capture session for {} ended", key),
+            false => format!("Timed out waiting for the first frame from {}", key),
+        }))?;
+        if let Frame::Video(video_frame) = &mut frame
+            && let Some((options, _)) = self.privacy.lock().unwrap().get(&key)
+        {
+            privacy::mask_frame(options, video_frame);
+        }
+        Ok(frame)
+    }
+
+    /// Whether the running session for `key` has to restart to exclude a
+    /// newly opened sensitive window; fails under `--privacy-strict`.
+    fn privacy_restart(&self, key: TargetKey) -> Result<bool, CaptestError> {
+        match self.privacy.lock().unwrap().get_mut(&key) {
+            Some((options, monitor)) => monitor.poll(options),
+            None => Ok(false),
+        }
+    }
+
+    /// Stop sessions that haven't been used within the idle timeout.
+    pub fn expire_idle(&self) {
+        let mut last_used = self.last_used.lock().unwrap();
+        last_used.retain(|key, used| {
+            let alive = self.captures.queue(*key).is_some_and(|queue| !queue.is_closed());
+            let keep = alive && used.elapsed() < self.idle_timeout;
+            if !keep {
+                status!("Stopping idle capture session for {}", key);
+                self.captures.stop(*key);
+                self.privacy.lock().unwrap().remove(key);
+            }
+            keep
+        });
+    }
+
+    /// Targets with a warm session.
+    pub fn targets(&self) -> Vec<TargetKey> {
+        self.captures.targets()
+    }
+
+    fn options(&self, key: TargetKey) -> Result<Options, CaptestError> {
+        let options = capture_options(key, self.fps)?;
+        self.privacy.lock().unwrap().insert(key, (options.clone(), PrivacyMonitor::default()));
+        Ok(options)
+    }
+}
+
+/// Options for a continuous BGRA capture of `key`.
+/// Sensitive windows are kept out of screen captures.
+pub fn capture_options(key: TargetKey, fps: u32) -> Result<Options, CaptestError> {
+    let mut options = Options {
+        fps,
+        show_highlight: config::settings().show_highlight,
+        output_type: scap::frame::FrameType::BGRAFrame,
+        target: Some(resolve_target(key).map_err(|e| CaptestError::new(ErrorKind::TargetNotFound, e))?),
+        ..Default::default()
+    };
+    privacy::exclude_sensitive(&mut options)?;
+    Ok(options)
+}
+
+pub fn resolve_target(key: TargetKey) -> Result<Target, String> {
+    let targets = capture::all_targets();
+    let target = match key {
+        TargetKey::Screen(index) => targets
+            .into_iter()
+            .filter(|target| matches!(target, Target::Display(_)))
+            .nth(index),
+        TargetKey::Window(index) => targets
+            .into_iter()
+            .filter(|target| matches!(target, Target::Window(_)))
+            .nth(index),
+    };
+    target.ok_or_else(|| format!("{} not found", key))
+}
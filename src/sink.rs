@@ -0,0 +1,401 @@
+use std::future::Future;
+use std::io::{IsTerminal, Write};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use url::Url;
+
+use crate::digest::{hex, hmac_sha256, sha256_hex};
+use crate::encode::ImageFormat;
+use crate::{clipboard, config, mqtt, upload};
+
+/// Region used for S3 when neither AWS_REGION nor AWS_DEFAULT_REGION is set.
+const DEFAULT_S3_REGION: &str = "us-east-1";
+
+/// An encoded capture on its way to the `--to` sinks.
+pub struct Capture<'a> {
+    pub image_bytes: &'a [u8],
+    pub format: ImageFormat,
+    /// Name of the file, for sinks that write into a directory or bucket prefix
+    pub file_name: &'a str,
+}
+
+pub type SinkFuture<'a> = Pin<Box<dyn Future<Output = Result<String, Box<dyn std::error::Error>>> + Send + 'a>>;
+
+/// A place `--to` can send a capture. Every sink gets the same image, so one
+/// capture fans out to several destinations without being encoded again.
+pub trait Sink: Send + Sync {
+    /// The destination as given on the command line, without any password.
+    fn describe(&self) -> String;
+
+    /// Deliver the capture and return where it ended up: a path, URL or topic.
+    fn write<'a>(&'a self, capture: &'a Capture<'a>) -> SinkFuture<'a>;
+
+    /// Whether the sink writes the capture to stdout, which then can't carry text.
+    fn uses_stdout(&self) -> bool {
+        false
+    }
+}
+
+/// Parse a `--to` destination: `file:PATH`, `clipboard`, `stdout` (or `-`),
+/// an `http(s)://` URL, `s3://BUCKET/KEY`, an `--upload` style `sftp://`,
+/// `scp://` or `dav(s)://` URL, or `mqtt:TOPIC`.
+pub fn parse(value: &str) -> Result<Arc<dyn Sink>, String> {
+    let invalid = |reason: &str| format!("Invalid --to destination \"{}\": {}", value, reason);
+    let (scheme, rest) = value.split_once(':').unwrap_or((value, ""));
+    let sink: Arc<dyn Sink> = match scheme {
+        "clipboard" if rest.is_empty() => Arc::new(ClipboardSink),
+        "stdout" | "-" if rest.is_empty() => Arc::new(StdoutSink),
+        "file" => {
+            // Accept file:///path as well as file:path
+            let path = rest.strip_prefix("//").unwrap_or(rest);
+            if path.is_empty() {
+                return Err(invalid("no path"));
+            }
+            Arc::new(FileSink { path: path.to_string() })
+        }
+        "http" | "https" => {
+            let url = Url::parse(value).map_err(|e| invalid(&e.to_string()))?;
+            if url.host_str().is_none_or(str::is_empty) {
+                return Err(invalid("no host"));
+            }
+            Arc::new(HttpSink { url })
+        }
+        "s3" => {
+            let location = rest.strip_prefix("//").ok_or_else(|| invalid("expected s3://BUCKET/KEY"))?;
+            let (bucket, key) = location.split_once('/').unwrap_or((location, ""));
+            if bucket.is_empty() {
+                return Err(invalid("no bucket"));
+            }
+            Arc::new(S3Sink { bucket: bucket.to_string(), key: key.to_string() })
+        }
+        "sftp" | "scp" | "dav" | "davs" => Arc::new(UploadSink(value.parse()?)),
+        "mqtt" => {
+            if rest.is_empty() || rest.contains(['+', '#']) {
+                return Err(invalid("expected mqtt:TOPIC, without wildcards"));
+            }
+            Arc::new(MqttSink { topic: rest.to_string() })
+        }
+        _ => return Err(invalid("expected file:PATH, clipboard, stdout, http(s)://..., s3://..., sftp://..., scp://..., dav(s)://... or mqtt:TOPIC")),
+    };
+    Ok(sink)
+}
+
+/// `file:PATH`: save to PATH, or into it when it ends with `/` or is a directory.
+struct FileSink {
+    path: String,
+}
+
+impl Sink for FileSink {
+    fn describe(&self) -> String {
+        format!("file:{}", self.path)
+    }
+
+    fn write<'a>(&'a self, capture: &'a Capture<'a>) -> SinkFuture<'a> {
+        Box::pin(async move {
+            let path = config::resolve_output_path(&self.path);
+            let path = match path.ends_with(['/', '\\']) || std::path::Path::new(&path).is_dir() {
+                true => std::path::Path::new(&path).join(capture.file_name).to_string_lossy().into_owned(),
+                false => path,
+            };
+            crate::save_image_bytes(capture.image_bytes, &path)
+        })
+    }
+}
+
+/// `clipboard`: put the image on the clipboard.
+struct ClipboardSink;
+
+impl Sink for ClipboardSink {
+    fn describe(&self) -> String {
+        "clipboard".to_string()
+    }
+
+    fn write<'a>(&'a self, capture: &'a Capture<'a>) -> SinkFuture<'a> {
+        Box::pin(async move {
+            // Clipboard tools are run and waited on, which mustn't hold an executor thread
+            let (image_bytes, format) = (capture.image_bytes.to_vec(), capture.format);
+            tokio::task::spawn_blocking(move || clipboard::copy_image(&image_bytes, format)).await??;
+            Ok("clipboard".to_string())
+        })
+    }
+}
+
+/// `stdout` or `-`: write the image to stdout, for piping into other tools.
+struct StdoutSink;
+
+impl Sink for StdoutSink {
+    fn describe(&self) -> String {
+        "stdout".to_string()
+    }
+
+    fn write<'a>(&'a self, capture: &'a Capture<'a>) -> SinkFuture<'a> {
+        Box::pin(async move {
+            let mut stdout = std::io::stdout().lock();
+            if stdout.is_terminal() {
+                return Err("Not writing an image to the terminal; redirect or pipe stdout".into());
+            }
+            stdout.write_all(capture.image_bytes)?;
+            stdout.flush()?;
+            Ok("stdout".to_string())
+        })
+    }
+
+    fn uses_stdout(&self) -> bool {
+        true
+    }
+}
+
+/// `http(s)://...`: POST the image as the request body.
+struct HttpSink {
+    url: Url,
+}
+
+impl Sink for HttpSink {
+    fn describe(&self) -> String {
+        upload::redacted(&self.url)
+    }
+
+    fn write<'a>(&'a self, capture: &'a Capture<'a>) -> SinkFuture<'a> {
+        Box::pin(async move {
+            let mut url = self.url.clone();
+            let (username, password) = (url.username().to_string(), url.password().map(str::to_string));
+            let _ = url.set_username("");
+            let _ = url.set_password(None);
+            let mut request = reqwest::Client::new().post(url.clone())
+                .header("Content-Type", capture.format.mime_type())
+                .header("Content-Disposition", format!("attachment; filename=\"{}\"", capture.file_name.replace('"', "")))
+                .body(capture.image_bytes.to_vec());
+            if !username.is_empty() {
+                request = request.basic_auth(username, password);
+            }
+            let response = request.send().await?;
+            let status = response.status();
+            if !status.is_success() {
+                return Err(format!("POST to {} failed with status {}", url, status.as_u16()).into());
+            }
+            // Servers that store the upload usually say where
+            let location = response.headers().get("Location").and_then(|value| value.to_str().ok())
+                .and_then(|location| url.join(location).ok());
+            Ok(location.unwrap_or(url).to_string())
+        })
+    }
+}
+
+/// `s3://BUCKET/KEY`: PUT the image with a SigV4 signed request. A KEY that
+/// is empty or ends with `/` is a prefix for the capture's file name.
+/// Credentials and region come from the usual AWS_* environment variables;
+/// AWS_ENDPOINT_URL_S3 (or AWS_ENDPOINT_URL) points at S3 compatible
+/// storage such as MinIO, addressed path-style.
+struct S3Sink {
+    bucket: String,
+    key: String,
+}
+
+impl Sink for S3Sink {
+    fn describe(&self) -> String {
+        format!("s3://{}/{}", self.bucket, self.key)
+    }
+
+    fn write<'a>(&'a self, capture: &'a Capture<'a>) -> SinkFuture<'a> {
+        Box::pin(async move {
+            let env = |name: &str| std::env::var(name).ok().filter(|value| !value.is_empty());
+            let access_key = env("AWS_ACCESS_KEY_ID").ok_or("Uploading to S3 needs AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY")?;
+            let secret_key = env("AWS_SECRET_ACCESS_KEY").ok_or("Uploading to S3 needs AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY")?;
+            let session_token = env("AWS_SESSION_TOKEN");
+            let region = env("AWS_REGION").or_else(|| env("AWS_DEFAULT_REGION")).unwrap_or_else(|| DEFAULT_S3_REGION.to_string());
+
+            let key = match self.key.is_empty() || self.key.ends_with('/') {
+                true => format!("{}{}", self.key, capture.file_name),
+                false => self.key.clone(),
+            };
+            let url = match env("AWS_ENDPOINT_URL_S3").or_else(|| env("AWS_ENDPOINT_URL")) {
+                Some(endpoint) => format!("{}/{}/{}", endpoint.trim_end_matches('/'), self.bucket, uri_encode(&key)),
+                None => format!("https://{}.s3.{}.amazonaws.com/{}", self.bucket, region, uri_encode(&key)),
+            };
+            let url = Url::parse(&url).map_err(|e| format!("Invalid S3 URL {}: {}", url, e))?;
+            let host = match url.port() {
+                Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+                None => url.host_str().unwrap_or_default().to_string(),
+            };
+
+            let (date, timestamp) = amz_dates(SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+            let payload_hash = sha256_hex(capture.image_bytes);
+            let mut headers = vec![
+                ("content-type", capture.format.mime_type().to_string()),
+                ("host", host),
+                ("x-amz-content-sha256", payload_hash.clone()),
+                ("x-amz-date", timestamp.clone()),
+            ];
+            if let Some(token) = session_token {
+                headers.push(("x-amz-security-token", token));
+            }
+            let signature = sign_v4("PUT", url.path(), &headers, &payload_hash, &secret_key, &region, (&date, &timestamp));
+
+            let mut request = reqwest::Client::new().put(url.clone()).body(capture.image_bytes.to_vec());
+            for (name, value) in &headers {
+                // reqwest derives Host from the URL
+                if *name != "host" {
+                    request = request.header(*name, value);
+                }
+            }
+            let authorization = format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}", access_key, signature.scope, signature.signed_headers, signature.signature);
+            let response = request.header("Authorization", authorization).send().await?;
+            let status = response.status();
+            if !status.is_success() {
+                let body = response.text().await.unwrap_or_default();
+                return Err(format!("S3 upload to s3://{}/{} failed with status {}: {}", self.bucket, key, status.as_u16(), body.trim()).into());
+            }
+            Ok(format!("s3://{}/{}", self.bucket, key))
+        })
+    }
+}
+
+/// `sftp://`, `scp://` or `dav(s)://`: the same transfers as `--upload`.
+struct UploadSink(upload::Destination);
+
+impl Sink for UploadSink {
+    fn describe(&self) -> String {
+        self.0.describe()
+    }
+
+    fn write<'a>(&'a self, capture: &'a Capture<'a>) -> SinkFuture<'a> {
+        Box::pin(async move {
+            let report = upload::upload(capture.image_bytes, capture.file_name, &self.0).await?;
+            Ok(report.location)
+        })
+    }
+}
+
+/// `mqtt:TOPIC`: publish the image to TOPIC on the broker from the config
+/// file's `[mqtt]` table.
+struct MqttSink {
+    topic: String,
+}
+
+impl Sink for MqttSink {
+    fn describe(&self) -> String {
+        format!("mqtt:{}", self.topic)
+    }
+
+    fn write<'a>(&'a self, capture: &'a Capture<'a>) -> SinkFuture<'a> {
+        Box::pin(async move {
+            mqtt::publish_once(&self.topic, capture.image_bytes.to_vec()).await?;
+            Ok(self.topic.clone())
+        })
+    }
+}
+
+/// Percent-encode an S3 key the way SigV4 expects, keeping the `/` separators.
+fn uri_encode(key: &str) -> String {
+    key.bytes().map(|b| match b {
+        b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (b as char).to_string(),
+        _ => format!("%{:02X}", b),
+    }).collect()
+}
+
+/// The UTC date (`YYYYMMDD`) and timestamp (`YYYYMMDDTHHMMSSZ`) of Unix
+/// time `secs`, as SigV4 uses them.
+fn amz_dates(secs: u64) -> (String, String) {
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let days = (secs / 86400) as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    let date = format!("{:04}{:02}{:02}", year, month, day);
+    let time = secs % 86400;
+    let timestamp = format!("{}T{:02}{:02}{:02}Z", date, time / 3600, time / 60 % 60, time % 60);
+    (date, timestamp)
+}
+
+/// The parts of a SigV4 `Authorization` header besides the access key.
+struct SigV4Signature {
+    /// `DATE/REGION/s3/aws4_request`
+    scope: String,
+    /// Lowercase header names, sorted and joined with `;`
+    signed_headers: String,
+    signature: String,
+}
+
+/// Sign an S3 request with AWS Signature Version 4. `path` is the already
+/// URI-encoded path, `headers` have lowercase names, and `dates` are the
+/// request's date and timestamp from [`amz_dates`]. Requests have no query
+/// string.
+fn sign_v4(method: &str, path: &str, headers: &[(&str, String)], payload_hash: &str, secret_key: &str, region: &str, dates: (&str, &str)) -> SigV4Signature {
+    let (date, timestamp) = dates;
+    let mut headers = headers.to_vec();
+    headers.sort_by_key(|(name, _)| *name);
+    let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+    let canonical_headers: String = headers.iter().map(|(name, value)| format!("{}:{}\n", name, value.trim())).collect();
+    let canonical_request = format!("{}\n{}\n\n{}\n{}\n{}", method, path, canonical_headers, signed_headers, payload_hash);
+    let scope = format!("{}/{}/s3/aws4_request", date, region);
+    let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", timestamp, scope, sha256_hex(canonical_request.as_bytes()));
+    let signing_key = [region, "s3", "aws4_request"].iter()
+        .fold(hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes()), |key, part| hmac_sha256(&key, part.as_bytes()));
+    let signature = hex(&hmac_sha256(&signing_key, string_to_sign.as_bytes()));
+    SigV4Signature { scope, signed_headers, signature }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The example credentials of the AWS SigV4 documentation for S3.
+    const SECRET_KEY: &str = "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY";
+
+    #[test]
+    fn signs_the_documented_put_object_example() {
+        let payload_hash = sha256_hex(b"Welcome to Amazon S3.");
+        assert_eq!(payload_hash, "44ce7dd67c959e0d3524ffac1771dfbba87d2b6b4b4e99e42034a8b803f8b072");
+        let headers = [
+            ("host", "examplebucket.s3.amazonaws.com".to_string()),
+            ("date", "Fri, 24 May 2013 00:00:00 GMT".to_string()),
+            ("x-amz-date", "20130524T000000Z".to_string()),
+            ("x-amz-storage-class", "REDUCED_REDUNDANCY".to_string()),
+            ("x-amz-content-sha256", payload_hash.clone()),
+        ];
+        let signature = sign_v4("PUT", &uri_encode("/test$file.text"), &headers, &payload_hash, SECRET_KEY, "us-east-1", ("20130524", "20130524T000000Z"));
+        assert_eq!(signature.scope, "20130524/us-east-1/s3/aws4_request");
+        assert_eq!(signature.signed_headers, "date;host;x-amz-content-sha256;x-amz-date;x-amz-storage-class");
+        assert_eq!(signature.signature, "98ad721746da40c64f1a55b78f14c238d841ea1380cd77a1b5971af0ece108bd");
+    }
+
+    #[test]
+    fn signs_the_documented_get_object_example() {
+        let payload_hash = sha256_hex(b"");
+        let headers = [
+            ("host", "examplebucket.s3.amazonaws.com".to_string()),
+            ("range", "bytes=0-9".to_string()),
+            ("x-amz-content-sha256", payload_hash.clone()),
+            ("x-amz-date", "20130524T000000Z".to_string()),
+        ];
+        let signature = sign_v4("GET", "/test.txt", &headers, &payload_hash, SECRET_KEY, "us-east-1", ("20130524", "20130524T000000Z"));
+        assert_eq!(signature.signed_headers, "host;range;x-amz-content-sha256;x-amz-date");
+        assert_eq!(signature.signature, "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41");
+    }
+
+    #[test]
+    fn uri_encodes_keys_but_keeps_separators() {
+        assert_eq!(uri_encode("/shots/a b+c$.jpg"), "/shots/a%20b%2Bc%24.jpg");
+        assert_eq!(uri_encode("caf\u{e9}~_-.png"), "caf%C3%A9~_-.png");
+    }
+
+    #[test]
+    fn amz_dates_of_known_times() {
+        let dates = amz_dates;
+        assert_eq!(dates(0), ("19700101".to_string(), "19700101T000000Z".to_string()));
+        assert_eq!(dates(1369353600), ("20130524".to_string(), "20130524T000000Z".to_string()));
+        assert_eq!(dates(946684799), ("19991231".to_string(), "19991231T235959Z".to_string()));
+        // Leap days, including the century rules
+        assert_eq!(dates(951827696), ("20000229".to_string(), "20000229T123456Z".to_string()));
+        assert_eq!(dates(1709164800), ("20240229".to_string(), "20240229T000000Z".to_string()));
+        assert_eq!(dates(4107542400), ("21000301".to_string(), "21000301T000000Z".to_string()));
+    }
+}
@@ -0,0 +1,272 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::config;
+
+/// Most edits a correction may be from the recognized word.
+const MAX_DISTANCE: usize = 2;
+/// Shorter words have too many close neighbours to correct safely.
+const MIN_WORD_LEN: usize = 3;
+/// Words shorter than this may only be one edit away from their correction.
+const LONG_WORD_LEN: usize = 6;
+/// Characters the recognizer mixes up, compared lowercase.
+const CONFUSIONS: &[(char, char)] = &[
+    ('0', 'o'), ('1', 'l'), ('1', 'i'), ('l', 'i'), ('5', 's'), ('8', 'b'), ('6', 'b'), ('2', 'z'), ('c', 'e'), ('u', 'v'),
+];
+
+static DICTIONARY: OnceLock<Option<Dictionary>> = OnceLock::new();
+
+/// A word list indexed for symmetric delete lookups (as in SymSpell): every
+/// word is stored under each string reachable by deleting up to
+/// `MAX_DISTANCE` characters, so finding close words only needs the deletes
+/// of the misread word instead of a comparison with every entry.
+pub struct Dictionary {
+    /// Lowercase word to its spelling in the list and its count
+    words: HashMap<String, (String, u64)>,
+    deletes: HashMap<String, Vec<String>>,
+}
+
+impl Dictionary {
+    /// Read a word list with one word per line, optionally followed by a
+    /// frequency count (the SymSpell dictionary format). `#` starts a comment.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut dictionary = Self { words: HashMap::new(), deletes: HashMap::new() };
+        for line in contents.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let mut fields = line.split_whitespace();
+            if let Some(word) = fields.next() {
+                dictionary.insert(word, fields.next().and_then(|count| count.parse().ok()).unwrap_or(1));
+            }
+        }
+        if dictionary.words.is_empty() {
+            return Err(format!("No words in {}", path.display()));
+        }
+        Ok(dictionary)
+    }
+
+    fn insert(&mut self, word: &str, count: u64) {
+        let key = word.to_lowercase();
+        if let Some(entry) = self.words.get_mut(&key) {
+            entry.1 += count;
+            return;
+        }
+        for delete in deletes(&key) {
+            self.deletes.entry(delete).or_default().push(key.clone());
+        }
+        self.words.insert(key, (word.to_string(), count));
+    }
+
+    fn contains(&self, word: &str) -> bool {
+        self.words.contains_key(&word.to_lowercase())
+    }
+
+    /// The listed spelling of the closest word within `max_distance` edits
+    /// that `accept` allows, preferring fewer edits, then the higher count.
+    fn closest(&self, word: &str, max_distance: f32, accept: impl Fn(&str, &str) -> bool) -> Option<&str> {
+        let key = word.to_lowercase();
+        let mut best: Option<(f32, u64, &str)> = None;
+        for delete in deletes(&key) {
+            for candidate in self.deletes.get(&delete).into_iter().flatten() {
+                let distance = distance(&key, candidate);
+                let count = self.words[candidate].1;
+                if distance <= max_distance
+                    && accept(&key, candidate)
+                    && best.is_none_or(|(best_distance, best_count, _)| (distance, std::cmp::Reverse(count)) < (best_distance, std::cmp::Reverse(best_count)))
+                {
+                    best = Some((distance, count, candidate));
+                }
+            }
+        }
+        best.map(|(_, _, key)| self.words[key].0.as_str())
+    }
+}
+
+/// The `--ocr-dictionary` word list, loaded on first use. None when no list
+/// is configured or it can't be read.
+fn dictionary() -> Option<&'static Dictionary> {
+    DICTIONARY.get_or_init(|| {
+        let path = config::settings().ocr_dictionary.as_ref()?;
+        match Dictionary::load(path) {
+            Ok(dictionary) => Some(dictionary),
+            Err(e) => {
+                status!("Warning: not correcting OCR text: {}", e);
+                None
+            }
+        }
+    }).as_ref()
+}
+
+/// Correct recognized lines against the `--ocr-dictionary` word list. None
+/// when no dictionary is configured, so callers can keep only the raw text.
+pub fn correct_lines(lines: &[String]) -> Option<Vec<String>> {
+    let dictionary = dictionary()?;
+    Some(lines.iter().map(|line| {
+        line.split(' ').map(|token| correct_token(dictionary, token).unwrap_or_else(|| token.to_string())).collect::<Vec<_>>().join(" ")
+    }).collect())
+}
+
+/// The corrected form of one whitespace-separated token, or None to keep it.
+/// Surrounding punctuation is left alone.
+fn correct_token(dictionary: &Dictionary, token: &str) -> Option<String> {
+    let start = token.find(|c: char| c.is_alphanumeric())?;
+    let end = token.rfind(|c: char| c.is_alphanumeric()).map(|end| end + token[end..].chars().next().map_or(1, char::len_utf8))?;
+    let (prefix, word, suffix) = (&token[..start], &token[start..end], &token[end..]);
+    if word.chars().count() < MIN_WORD_LEN || dictionary.contains(word) {
+        return None;
+    }
+
+    let digits = word.chars().filter(char::is_ascii_digit).count();
+    let letters = word.chars().filter(|c| c.is_alphabetic()).count();
+    // Numbers with a letter misread in them: 1O5 is 105, l00 is 100
+    if digits > 0 && digits >= letters && word.chars().all(|c| c.is_ascii_digit() || "OoIl.,:".contains(c)) {
+        let number: String = word.chars().map(|c| match c {
+            'O' | 'o' => '0',
+            'I' | 'l' => '1',
+            c => c,
+        }).collect();
+        return (number != word).then(|| format!("{}{}{}", prefix, number, suffix));
+    }
+    if letters == 0 {
+        return None;
+    }
+
+    // A word may just be missing from the list, and identifiers like ipv6 or
+    // h264 sit next to other real words, so only accept look-alike
+    // substitutions rather than any close word
+    let max_distance = if word.chars().count() < LONG_WORD_LEN { 1.0 } else { MAX_DISTANCE as f32 };
+    let correction = dictionary.closest(word, max_distance, only_confusions)?;
+    Some(format!("{}{}{}", prefix, match_case(word, correction), suffix))
+}
+
+/// Whether `word` and `candidate` only differ by confusable characters.
+fn only_confusions(word: &str, candidate: &str) -> bool {
+    word.chars().count() == candidate.chars().count()
+        && word.chars().zip(candidate.chars()).all(|(a, b)| a == b || confusable(a, b))
+}
+
+fn confusable(a: char, b: char) -> bool {
+    CONFUSIONS.iter().any(|&(x, y)| (a, b) == (x, y) || (a, b) == (y, x))
+}
+
+/// Use the listed spelling when it has capitals (names, acronyms), otherwise
+/// keep the recognized word's capitalization. A leading capital that was
+/// itself the misread (Iogin for login) isn't kept.
+fn match_case(word: &str, correction: &str) -> String {
+    if correction.chars().any(char::is_uppercase) {
+        return correction.to_string();
+    }
+    let letters: Vec<char> = word.chars().filter(|c| c.is_alphabetic()).collect();
+    if letters.len() > 1 && letters.iter().all(|c| c.is_uppercase()) {
+        return correction.to_uppercase();
+    }
+    let mut chars = correction.chars();
+    let capitalized = word.chars().next().filter(|first| first.is_uppercase());
+    match (capitalized, chars.next()) {
+        (Some(original), Some(first)) if original.to_lowercase().eq(first.to_lowercase()) => first.to_uppercase().chain(chars).collect(),
+        _ => correction.to_string(),
+    }
+}
+
+/// `word` and every string made by deleting up to `MAX_DISTANCE` of its characters.
+fn deletes(word: &str) -> HashSet<String> {
+    let mut all = HashSet::from([word.to_string()]);
+    let mut current = vec![word.to_string()];
+    for _ in 0..MAX_DISTANCE {
+        let mut next = Vec::new();
+        for word in &current {
+            let chars: Vec<char> = word.chars().collect();
+            for skip in 0..chars.len() {
+                let delete: String = chars.iter().enumerate().filter(|&(i, _)| i != skip).map(|(_, c)| c).collect();
+                if all.insert(delete.clone()) {
+                    next.push(delete);
+                }
+            }
+        }
+        current = next;
+    }
+    all
+}
+
+/// Edit distance with adjacent transpositions, where swapping confusable
+/// characters only costs half an edit.
+fn distance(a: &str, b: &str) -> f32 {
+    let (a, b): (Vec<char>, Vec<char>) = (a.chars().collect(), b.chars().collect());
+    let mut rows = vec![vec![0.0f32; b.len() + 1]; a.len() + 1];
+    for (i, row) in rows.iter_mut().enumerate() {
+        row[0] = i as f32;
+    }
+    for (j, cell) in rows[0].iter_mut().enumerate() {
+        *cell = j as f32;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let substitution = match (a[i - 1], b[j - 1]) {
+                (x, y) if x == y => 0.0,
+                (x, y) if confusable(x, y) => 0.5,
+                _ => 1.0,
+            };
+            let mut cost = (rows[i - 1][j] + 1.0).min(rows[i][j - 1] + 1.0).min(rows[i - 1][j - 1] + substitution);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                cost = cost.min(rows[i - 2][j - 2] + 1.0);
+            }
+            rows[i][j] = cost;
+        }
+    }
+    rows[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dictionary(words: &[(&str, u64)]) -> Dictionary {
+        let mut dictionary = Dictionary { words: HashMap::new(), deletes: HashMap::new() };
+        for &(word, count) in words {
+            dictionary.insert(word, count);
+        }
+        dictionary
+    }
+
+    #[test]
+    fn closest_prefers_fewer_edits_then_higher_counts() {
+        let dictionary = dictionary(&[("cart", 10), ("card", 50), ("carts", 100)]);
+        assert_eq!(dictionary.closest("cart", 1.0, |_, _| true), Some("cart"));
+        assert_eq!(dictionary.closest("carx", 1.0, |_, _| true), Some("card"));
+        assert_eq!(dictionary.closest("cxrx", 1.0, |_, _| true), None);
+        assert_eq!(dictionary.closest("carx", 1.0, |_, candidate| candidate != "card"), Some("cart"));
+    }
+
+    #[test]
+    fn fixes_look_alike_confusions() {
+        let dictionary = dictionary(&[("login", 1), ("settings", 1), ("Windows", 1)]);
+        assert_eq!(correct_token(&dictionary, "l0gin").as_deref(), Some("login"));
+        assert_eq!(correct_token(&dictionary, "Iogin:").as_deref(), Some("login:"));
+        assert_eq!(correct_token(&dictionary, "(5ettings)").as_deref(), Some("(settings)"));
+        assert_eq!(correct_token(&dictionary, "WINDOW5").as_deref(), Some("Windows"));
+    }
+
+    #[test]
+    fn leaves_real_and_unlisted_words_alone() {
+        let dictionary = dictionary(&[("login", 1), ("cart", 1), ("card", 1)]);
+        assert_eq!(correct_token(&dictionary, "cart"), None);
+        assert_eq!(correct_token(&dictionary, "logon"), None);
+        assert_eq!(correct_token(&dictionary, "cord"), None);
+    }
+
+    #[test]
+    fn keeps_identifiers_with_digits_next_to_listed_words() {
+        let dictionary = dictionary(&[("ipv4", 1), ("h265", 1), ("utf8", 1)]);
+        assert_eq!(correct_token(&dictionary, "ipv6"), None);
+        assert_eq!(correct_token(&dictionary, "h264"), None);
+        assert_eq!(correct_token(&dictionary, "utf16"), None);
+    }
+
+    #[test]
+    fn reads_letters_in_numbers_as_digits() {
+        let dictionary = dictionary(&[("login", 1)]);
+        assert_eq!(correct_token(&dictionary, "1O5").as_deref(), Some("105"));
+        assert_eq!(correct_token(&dictionary, "l00%").as_deref(), Some("100%"));
+        assert_eq!(correct_token(&dictionary, "2024"), None);
+    }
+}
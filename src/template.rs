@@ -0,0 +1,400 @@
+use std::collections::HashMap;
+
+use image::RgbImage;
+use serde::Serialize;
+
+use crate::error::ErrorKind;
+
+/// Template sizes tried, relative to the needle file: the same screen, and
+/// the needle taken at another display scaling (100%, 125%, 150%, 200%).
+pub const DEFAULT_SCALES: &[f64] = &[0.5, 0.75, 1.0, 1.25, 1.5, 2.0];
+/// Short side, in pixels, a template is shrunk to for the coarse search.
+const COARSE_SIZE: usize = 12;
+/// How far below the threshold a coarse score may be and still be refined;
+/// shrinking blurs away detail the full resolution score rewards.
+const COARSE_SLACK: f64 = 0.25;
+/// Most coarse candidates refined per scale.
+const MAX_CANDIDATES: usize = 32;
+/// Share of the smaller of two matches covered by the other for the weaker
+/// one to be dropped.
+const OVERLAPPING: f64 = 0.5;
+/// Largest needle scale accepted; beyond it the needle outgrows any screen.
+const MAX_SCALE: f64 = 16.0;
+
+/// Where a template was found, in frame pixels.
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct Match {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Center of the match, e.g. for clicking it
+    pub center_x: u32,
+    pub center_y: u32,
+    /// Normalized cross-correlation, -1 to 1
+    pub score: f64,
+    /// Size of the match relative to the needle image
+    pub scale: f64,
+}
+
+impl Match {
+    pub fn describe(&self) -> String {
+        format!("{},{} {}x{} score {:.3} scale {}", self.x, self.y, self.width, self.height, self.score, self.scale)
+    }
+
+    fn overlap(&self, other: &Match) -> u32 {
+        let width = (self.x + self.width).min(other.x + other.width).saturating_sub(self.x.max(other.x));
+        let height = (self.y + self.height).min(other.y + other.height).saturating_sub(self.y.max(other.y));
+        width * height
+    }
+}
+
+#[derive(Serialize)]
+pub struct FindReport {
+    /// "ok", or "not_found" when nothing scored at least the threshold
+    pub status: String,
+    pub width: u32,
+    pub height: u32,
+    /// Best match first
+    pub matches: Vec<Match>,
+    #[serde(skip)]
+    pub failure: Option<ErrorKind>,
+}
+
+impl FindReport {
+    pub fn new(width: u32, height: u32, matches: Vec<Match>) -> Self {
+        let failure = matches.is_empty().then_some(ErrorKind::ImageNotFound);
+        let status = if failure.is_some() { "not_found" } else { "ok" }.to_string();
+        Self { status, width, height, matches, failure }
+    }
+}
+
+#[derive(Serialize)]
+pub struct WaitReport {
+    /// "ok", or "timeout" when the image didn't appear in time
+    pub status: String,
+    pub width: u32,
+    pub height: u32,
+    /// Best match first, in the frame the image appeared in
+    pub matches: Vec<Match>,
+    /// Frames searched
+    pub attempts: usize,
+    pub elapsed_ms: u128,
+    #[serde(skip)]
+    pub failure: Option<ErrorKind>,
+}
+
+/// A brightness image.
+struct Gray {
+    width: usize,
+    height: usize,
+    data: Vec<f64>,
+}
+
+impl Gray {
+    fn from_rgb(width: u32, height: u32, rgb_data: &[u8]) -> Self {
+        let data = rgb_data.chunks_exact(3)
+            .map(|p| 0.299 * p[0] as f64 + 0.587 * p[1] as f64 + 0.114 * p[2] as f64)
+            .collect();
+        Self { width: width as usize, height: height as usize, data }
+    }
+
+    /// Average each `factor` x `factor` block into one pixel.
+    fn shrink(&self, factor: usize) -> Self {
+        if factor <= 1 {
+            return Self { width: self.width, height: self.height, data: self.data.clone() };
+        }
+        let (width, height) = (self.width / factor, self.height / factor);
+        let mut data = vec![0.0; width * height];
+        for y in 0..height * factor {
+            for x in 0..width * factor {
+                data[(y / factor) * width + x / factor] += self.data[y * self.width + x];
+            }
+        }
+        let area = (factor * factor) as f64;
+        data.iter_mut().for_each(|value| *value /= area);
+        Self { width, height, data }
+    }
+}
+
+/// Summed-area tables of an image and its squares, for the mean and spread
+/// of any window in constant time.
+struct Integral {
+    stride: usize,
+    sum: Vec<f64>,
+    squares: Vec<f64>,
+}
+
+impl Integral {
+    fn new(image: &Gray) -> Self {
+        let stride = image.width + 1;
+        let mut sum = vec![0.0; stride * (image.height + 1)];
+        let mut squares = sum.clone();
+        for y in 0..image.height {
+            let (mut row, mut row_squares) = (0.0, 0.0);
+            for x in 0..image.width {
+                let value = image.data[y * image.width + x];
+                row += value;
+                row_squares += value * value;
+                sum[(y + 1) * stride + x + 1] = sum[y * stride + x + 1] + row;
+                squares[(y + 1) * stride + x + 1] = squares[y * stride + x + 1] + row_squares;
+            }
+        }
+        Self { stride, sum, squares }
+    }
+
+    /// Sum and sum of squares of the `width` x `height` window at (x, y).
+    fn window(&self, x: usize, y: usize, width: usize, height: usize) -> (f64, f64) {
+        let at = |table: &[f64]| {
+            table[(y + height) * self.stride + x + width] - table[y * self.stride + x + width]
+                - table[(y + height) * self.stride + x] + table[y * self.stride + x]
+        };
+        (at(&self.sum), at(&self.squares))
+    }
+}
+
+/// A template with its mean removed, ready to correlate.
+struct Template {
+    width: usize,
+    height: usize,
+    centered: Vec<f64>,
+    norm: f64,
+}
+
+impl Template {
+    fn new(image: &Gray) -> Self {
+        let mean = image.data.iter().sum::<f64>() / image.data.len().max(1) as f64;
+        let centered: Vec<f64> = image.data.iter().map(|value| value - mean).collect();
+        let norm = centered.iter().map(|value| value * value).sum::<f64>().sqrt();
+        Self { width: image.width, height: image.height, centered, norm }
+    }
+
+    /// Normalized cross-correlation with the window of `image` at (x, y).
+    fn score(&self, image: &Gray, integral: &Integral, x: usize, y: usize) -> f64 {
+        let (sum, squares) = integral.window(x, y, self.width, self.height);
+        let count = (self.width * self.height) as f64;
+        let spread = (squares - sum * sum / count).max(0.0).sqrt();
+        if spread < 1e-6 {
+            return 0.0;
+        }
+        let mut product = 0.0;
+        for row in 0..self.height {
+            let image_row = &image.data[(y + row) * image.width + x..][..self.width];
+            let template_row = &self.centered[row * self.width..][..self.width];
+            product += image_row.iter().zip(template_row).map(|(a, b)| a * b).sum::<f64>();
+        }
+        product / (self.norm * spread)
+    }
+}
+
+/// Every position in `image` where `template` scores at least `minimum`,
+/// as (score, x, y). Rows are split between threads.
+fn coarse_scores(image: &Gray, integral: &Integral, template: &Template, minimum: f64) -> Vec<(f64, usize, usize)> {
+    let rows = image.height - template.height + 1;
+    let threads = std::thread::available_parallelism().map_or(1, usize::from).min(rows);
+    let chunk = rows.div_ceil(threads);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|thread| scope.spawn(move || {
+                let mut found = Vec::new();
+                for y in thread * chunk..((thread + 1) * chunk).min(rows) {
+                    for x in 0..=image.width - template.width {
+                        let score = template.score(image, integral, x, y);
+                        if score >= minimum {
+                            found.push((score, x, y));
+                        }
+                    }
+                }
+                found
+            }))
+            .collect();
+        workers.into_iter().flat_map(|worker| worker.join().unwrap_or_default()).collect()
+    })
+}
+
+/// Value parser for `--threshold`: a correlation from -1 to 1, since no
+/// score outside that range can ever match.
+pub fn parse_threshold(value: &str) -> Result<f64, String> {
+    let threshold: f64 = value.parse().map_err(|_| format!("\"{}\" is not a number", value))?;
+    if !(-1.0..=1.0).contains(&threshold) {
+        return Err(format!("Threshold {} is out of range (-1 to 1)", value));
+    }
+    Ok(threshold)
+}
+
+/// Value parser for each of `--scales`: a finite size above 0, relative to
+/// the needle file.
+pub fn parse_scale(value: &str) -> Result<f64, String> {
+    let scale: f64 = value.parse().map_err(|_| format!("\"{}\" is not a number", value))?;
+    if !(scale > 0.0 && scale <= MAX_SCALE) {
+        return Err(format!("Scale {} is out of range (above 0, up to {})", value, MAX_SCALE));
+    }
+    Ok(scale)
+}
+
+/// Find where `needle` appears in a `width` x `height` RGB8 frame at any of
+/// `scales`, best match first. Each scale is searched coarse to fine: both
+/// images are shrunk until the template is about COARSE_SIZE pixels, every
+/// position is scored there, and the promising ones are scored again at full
+/// resolution around their spot.
+pub fn find(width: u32, height: u32, rgb_data: &[u8], needle: &RgbImage, scales: &[f64], threshold: f64) -> Result<Vec<Match>, String> {
+    let haystack = Gray::from_rgb(width, height, rgb_data);
+    let full = Integral::new(&haystack);
+    let mut shrunk: HashMap<usize, (Gray, Integral)> = HashMap::new();
+    let mut matches = Vec::new();
+    let mut searched = false;
+
+    for &scale in scales {
+        let (needle_width, needle_height) = ((needle.width() as f64 * scale).round() as u32, (needle.height() as f64 * scale).round() as u32);
+        if needle_width < 4 || needle_height < 4 || needle_width > width || needle_height > height {
+            continue;
+        }
+        let resized = match scale == 1.0 {
+            true => needle.clone(),
+            false => image::imageops::resize(needle, needle_width, needle_height, image::imageops::FilterType::Triangle),
+        };
+        let needle_gray = Gray::from_rgb(needle_width, needle_height, resized.as_raw());
+        let template = Template::new(&needle_gray);
+        if template.norm < 1e-3 {
+            return Err("The needle image is a single color, so it matches anywhere of that color".to_string());
+        }
+        searched = true;
+
+        let factor = (needle_width.min(needle_height) as usize / COARSE_SIZE).max(1);
+        let (coarse_haystack, coarse_integral) = shrunk.entry(factor).or_insert_with(|| {
+            let image = haystack.shrink(factor);
+            let integral = Integral::new(&image);
+            (image, integral)
+        });
+        let coarse = Template::new(&needle_gray.shrink(factor));
+        if coarse.width > coarse_haystack.width || coarse.height > coarse_haystack.height {
+            continue;
+        }
+
+        let mut candidates = coarse_scores(coarse_haystack, coarse_integral, &coarse, threshold - COARSE_SLACK);
+        candidates.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        let mut refined: Vec<(usize, usize)> = Vec::new();
+        for (_, coarse_x, coarse_y) in candidates {
+            if refined.len() == MAX_CANDIDATES {
+                break;
+            }
+            // Candidates next to an already refined one are the same spot
+            let (center_x, center_y) = (coarse_x * factor, coarse_y * factor);
+            if refined.iter().any(|&(x, y)| x.abs_diff(center_x) <= factor * 2 && y.abs_diff(center_y) <= factor * 2) {
+                continue;
+            }
+            refined.push((center_x, center_y));
+
+            let (max_x, max_y) = (haystack.width - template.width, haystack.height - template.height);
+            let mut best = (f64::MIN, 0, 0);
+            for y in center_y.saturating_sub(factor)..=(center_y + factor).min(max_y) {
+                for x in center_x.saturating_sub(factor)..=(center_x + factor).min(max_x) {
+                    let score = template.score(&haystack, &full, x, y);
+                    if score > best.0 {
+                        best = (score, x, y);
+                    }
+                }
+            }
+            let (score, x, y) = best;
+            if score >= threshold {
+                matches.push(Match {
+                    x: x as u32,
+                    y: y as u32,
+                    width: needle_width,
+                    height: needle_height,
+                    center_x: (x + template.width / 2) as u32,
+                    center_y: (y + template.height / 2) as u32,
+                    score: (score * 1000.0).round() / 1000.0,
+                    scale,
+                });
+            }
+        }
+    }
+    if !searched {
+        return Err(format!("The needle ({}x{}) doesn't fit in the {}x{} frame at any scale", needle.width(), needle.height(), width, height));
+    }
+
+    // One match per spot: the best scale and position
+    matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+    let mut kept: Vec<Match> = Vec::new();
+    for candidate in matches {
+        let smaller = |other: &Match| (candidate.width * candidate.height).min(other.width * other.height) as f64;
+        if !kept.iter().any(|other| candidate.overlap(other) as f64 >= OVERLAPPING * smaller(other)) {
+            kept.push(candidate);
+        }
+    }
+    Ok(kept)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A frame of noise from a fixed seed, so every window has some texture.
+    fn noise(width: u32, height: u32) -> RgbImage {
+        let mut state: u32 = 12345;
+        RgbImage::from_fn(width, height, |_, _| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            let value = (state >> 16) as u8;
+            image::Rgb([value, value / 2, 255 - value])
+        })
+    }
+
+    /// A needle of stripes and a bright corner, unlike the noise around it.
+    fn needle() -> RgbImage {
+        RgbImage::from_fn(40, 30, |x, y| match (x < 12 && y < 10, (x / 5 + y / 6) % 2) {
+            (true, _) => image::Rgb([255, 255, 255]),
+            (false, 0) => image::Rgb([20, 40, 200]),
+            (false, _) => image::Rgb([220, 180, 30]),
+        })
+    }
+
+    fn find_planted(needle: &RgbImage, planted: &RgbImage, x: u32, y: u32, scales: &[f64]) -> Vec<Match> {
+        let mut frame = noise(320, 240);
+        image::imageops::replace(&mut frame, planted, x as i64, y as i64);
+        find(frame.width(), frame.height(), frame.as_raw(), needle, scales, 0.9).unwrap()
+    }
+
+    #[test]
+    fn finds_a_needle_at_its_own_size() {
+        let needle = needle();
+        let matches = find_planted(&needle, &needle, 137, 81, &[1.0]);
+        assert_eq!(matches.len(), 1);
+        let found = matches[0];
+        assert_eq!((found.x, found.y, found.width, found.height), (137, 81, 40, 30));
+        assert_eq!((found.center_x, found.center_y), (157, 96));
+        assert!(found.score > 0.99, "score {}", found.score);
+    }
+
+    #[test]
+    fn finds_a_scaled_needle_at_the_matching_scale() {
+        let needle = needle();
+        let planted = image::imageops::resize(&needle, 60, 45, image::imageops::FilterType::Triangle);
+        let matches = find_planted(&needle, &planted, 201, 33, &[1.0, 1.5]);
+        let best = matches[0];
+        assert_eq!((best.x, best.y, best.width, best.height, best.scale), (201, 33, 60, 45, 1.5));
+        assert!(best.score > 0.99, "score {}", best.score);
+        // The 1.0 scale match of the same spot is suppressed
+        assert!(matches.iter().skip(1).all(|other| best.overlap(other) == 0));
+    }
+
+    #[test]
+    fn finds_nothing_in_noise() {
+        let frame = noise(320, 240);
+        let matches = find(frame.width(), frame.height(), frame.as_raw(), &needle(), &[1.0, 1.5], 0.9).unwrap();
+        assert!(matches.is_empty());
+    }
+
+    #[test]
+    fn rejects_thresholds_and_scales_that_can_never_match() {
+        assert_eq!(parse_threshold("0.85"), Ok(0.85));
+        assert!(parse_threshold("1.5").is_err());
+        assert!(parse_threshold("NaN").is_err());
+        assert_eq!(parse_scale("1.25"), Ok(1.25));
+        assert!(parse_scale("0").is_err());
+        assert!(parse_scale("-1").is_err());
+        assert!(parse_scale("NaN").is_err());
+        assert!(parse_scale("inf").is_err());
+    }
+}
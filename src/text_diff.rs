@@ -0,0 +1,139 @@
+use std::path::Path;
+
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+
+use crate::error::{CaptestError, ErrorKind};
+use crate::session_pool::TargetKey;
+use crate::{capture, diff, ocr};
+
+/// A line whose text changed in place.
+#[derive(Serialize)]
+pub struct ChangedLine {
+    pub before: String,
+    pub after: String,
+}
+
+#[derive(Serialize)]
+pub struct TextDiffReport {
+    /// "same" or "different" (any line was added, removed or changed)
+    pub status: String,
+    pub baseline: String,
+    pub current: String,
+    /// Recognized lines of each image, in reading order
+    pub baseline_lines: Vec<String>,
+    pub current_lines: Vec<String>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub changed: Vec<ChangedLine>,
+    /// Set when the text differs, used for the exit code
+    #[serde(skip)]
+    pub failure: Option<ErrorKind>,
+}
+
+/// Compare the text of two image files.
+pub async fn diff_files(baseline: &Path, current: &Path) -> Result<TextDiffReport, Box<dyn std::error::Error>> {
+    let baseline_lines = recognize(baseline).await?;
+    let current_lines = recognize(current).await?;
+    Ok(report(baseline, &current.display().to_string(), baseline_lines, current_lines))
+}
+
+/// Compare the text of a live capture of `key` with a baseline image.
+pub async fn verify(key: TargetKey, baseline: &Path, cancel: &CancellationToken) -> Result<TextDiffReport, Box<dyn std::error::Error>> {
+    let baseline_lines = recognize(baseline).await?;
+    let (width, height, rgb_data) = capture::grab_rgb8(key, cancel).await?;
+    let current_lines = extract(width, height, &rgb_data).await?;
+    Ok(report(baseline, &key.to_string(), baseline_lines, current_lines))
+}
+
+async fn recognize(path: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let image = diff::open_rgb8(path)?;
+    status!("Recognizing text in {}", path.display());
+    extract(image.width(), image.height(), image.as_raw()).await
+}
+
+/// OCR lines with whitespace collapsed, so spacing differences between
+/// renderings don't count as changes.
+async fn extract(width: u32, height: u32, rgb_data: &[u8]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let lines = ocr::extract_text(width, height, rgb_data, true)
+        .await
+        .map_err(|e| CaptestError::new(ErrorKind::OcrFailed, e.to_string()))?;
+    Ok(lines
+        .iter()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>().join(" "))
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+fn report(baseline: &Path, current: &str, baseline_lines: Vec<String>, current_lines: Vec<String>) -> TextDiffReport {
+    let (added, removed, changed) = line_diff(&baseline_lines, &current_lines);
+    for line in &removed {
+        result!("- {}", line);
+    }
+    for line in &added {
+        result!("+ {}", line);
+    }
+    for line in &changed {
+        result!("~ {} -> {}", line.before, line.after);
+    }
+    let different = !(added.is_empty() && removed.is_empty() && changed.is_empty());
+    status!(
+        "{} lines added, {} removed, {} changed ({} lines in the baseline, {} now)",
+        added.len(), removed.len(), changed.len(), baseline_lines.len(), current_lines.len()
+    );
+    result!("{}", if different { "different" } else { "same" });
+
+    TextDiffReport {
+        status: if different { "different" } else { "same" }.to_string(),
+        baseline: baseline.display().to_string(),
+        current: current.to_string(),
+        baseline_lines,
+        current_lines,
+        added,
+        removed,
+        changed,
+        failure: different.then_some(ErrorKind::DiffOverThreshold),
+    }
+}
+
+/// Added, removed and changed lines between two transcripts. Lines are
+/// matched by longest common subsequence, so moved layout doesn't matter
+/// as long as the reading order holds; removals and additions at the same
+/// spot pair up as changes.
+fn line_diff(before: &[String], after: &[String]) -> (Vec<String>, Vec<String>, Vec<ChangedLine>) {
+    // common[i][j]: length of the LCS of before[i..] and after[j..]
+    let mut common = vec![vec![0usize; after.len() + 1]; before.len() + 1];
+    for i in (0..before.len()).rev() {
+        for j in (0..after.len()).rev() {
+            common[i][j] = if before[i] == after[j] { common[i + 1][j + 1] + 1 } else { common[i + 1][j].max(common[i][j + 1]) };
+        }
+    }
+
+    let (mut added, mut removed, mut changed) = (Vec::new(), Vec::new(), Vec::new());
+    let (mut hunk_removed, mut hunk_added): (Vec<&String>, Vec<&String>) = (Vec::new(), Vec::new());
+    let mut flush = |hunk_removed: &mut Vec<&String>, hunk_added: &mut Vec<&String>| {
+        let paired = hunk_removed.len().min(hunk_added.len());
+        for (before, after) in hunk_removed.iter().zip(hunk_added.iter()) {
+            changed.push(ChangedLine { before: before.to_string(), after: after.to_string() });
+        }
+        removed.extend(hunk_removed.drain(..).skip(paired).cloned());
+        added.extend(hunk_added.drain(..).skip(paired).cloned());
+    };
+
+    let (mut i, mut j) = (0, 0);
+    while i < before.len() || j < after.len() {
+        if i < before.len() && j < after.len() && before[i] == after[j] {
+            flush(&mut hunk_removed, &mut hunk_added);
+            i += 1;
+            j += 1;
+        } else if j < after.len() && (i == before.len() || common[i][j + 1] >= common[i + 1][j]) {
+            hunk_added.push(&after[j]);
+            j += 1;
+        } else {
+            hunk_removed.push(&before[i]);
+            i += 1;
+        }
+    }
+    flush(&mut hunk_removed, &mut hunk_added);
+    (added, removed, changed)
+}
@@ -0,0 +1,115 @@
+use std::sync::LazyLock;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use embedded_graphics::mono_font::ascii::FONT_10X20;
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::pixelcolor::Rgb888;
+use embedded_graphics::prelude::*;
+use embedded_graphics::text::{Baseline, Text};
+use serde::Serialize;
+
+use crate::keys::Canvas;
+
+/// Zero of the monotonic clock reported with frames.
+static CLOCK_START: LazyLock<Instant> = LazyLock::new(Instant::now);
+
+/// Frame height that gets the overlay font at its native size.
+const BASE_FRAME_HEIGHT: u32 = 720;
+const BACKGROUND_OPACITY: f64 = 0.6;
+
+/// When a frame was shown and when captest got it, for lining captures up
+/// with application logs.
+#[derive(Serialize, Clone, Debug)]
+pub struct FrameTimes {
+    /// When the backend says the frame was on screen (scap's display_time), Unix milliseconds
+    pub display_time_ms: f64,
+    /// Wall-clock time the frame reached captest, Unix milliseconds
+    pub received_at_ms: f64,
+    /// `received_at_ms` as UTC ISO 8601
+    pub received_at: String,
+    /// Milliseconds since captest started when the frame arrived; unlike
+    /// wall-clock time it never jumps with clock adjustments
+    pub monotonic_ms: f64,
+}
+
+impl FrameTimes {
+    /// Times of a frame the backend stamped `display_time` that arrived at
+    /// `received_at`, which is `received` on the monotonic clock.
+    pub fn new(display_time: SystemTime, received_at: SystemTime, received: Instant) -> Self {
+        let received_at_ms = unix_ms(received_at);
+        Self {
+            display_time_ms: unix_ms(display_time),
+            received_at_ms,
+            received_at: utc(received_at_ms),
+            monotonic_ms: round_ms(received.duration_since(*CLOCK_START).as_secs_f64() * 1000.0),
+        }
+    }
+
+    /// The overlay text: wall-clock time and monotonic offset.
+    fn caption(&self) -> String {
+        format!("{} +{:.3}s", self.received_at, self.monotonic_ms / 1000.0)
+    }
+}
+
+/// Start the monotonic clock, so frame offsets count from process start
+/// rather than from the first frame.
+pub fn start_clock() {
+    LazyLock::force(&CLOCK_START);
+}
+
+/// Time since captest started, on the monotonic clock.
+pub fn since_start() -> std::time::Duration {
+    CLOCK_START.elapsed()
+}
+
+/// Burn the frame's times into the top left corner of an RGB8 frame.
+pub fn draw_overlay(width: u32, height: u32, rgb_data: &mut [u8], times: &FrameTimes) {
+    let caption = times.caption();
+    let scale = (height / BASE_FRAME_HEIGHT).max(1);
+    let font = &FONT_10X20;
+    let padding = 4 * scale;
+    let box_width = (font.character_size.width * caption.chars().count() as u32 * scale + 2 * padding).min(width);
+    let box_height = (font.character_size.height * scale + 2 * padding).min(height);
+    for y in 0..box_height {
+        let row = (y * width) as usize * 3;
+        for value in &mut rgb_data[row..row + box_width as usize * 3] {
+            *value = (*value as f64 * (1.0 - BACKGROUND_OPACITY)) as u8;
+        }
+    }
+
+    let mut canvas = Canvas { rgb_data, width, height, scale };
+    let style = MonoTextStyle::new(font, Rgb888::WHITE);
+    let origin = Point::new((padding / scale) as i32, (padding / scale) as i32);
+    let _ = Text::with_baseline(&caption, origin, style, Baseline::Top).draw(&mut canvas);
+}
+
+fn unix_ms(time: SystemTime) -> f64 {
+    // Clocks before 1970 only happen on badly broken systems
+    round_ms(time.duration_since(UNIX_EPOCH).map(|d| d.as_secs_f64() * 1000.0).unwrap_or(0.0))
+}
+
+/// Keep microseconds; anything finer is noise.
+fn round_ms(ms: f64) -> f64 {
+    (ms * 1000.0).round() / 1000.0
+}
+
+/// Unix milliseconds as `YYYY-MM-DDTHH:MM:SS.mmmZ`.
+fn utc(unix_ms: f64) -> String {
+    let total_ms = unix_ms as i64;
+    let (days, ms_of_day) = (total_ms.div_euclid(86_400_000), total_ms.rem_euclid(86_400_000));
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day,
+        ms_of_day / 3_600_000, ms_of_day / 60_000 % 60, ms_of_day / 1000 % 60, ms_of_day % 1000
+    )
+}
@@ -0,0 +1,121 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::timestamp;
+
+/// A step of the capture pipeline that `--timings` accounts for.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// Listing screens and windows from the backend
+    Enumerate,
+    /// Building and starting a capturer
+    BuildCapturer,
+    /// From a started capturer to its first frame
+    FirstFrame,
+    /// Converting frames to RGB8
+    Convert,
+    Encode,
+    Save,
+    Ocr,
+    Llm,
+}
+
+const STAGES: [Stage; 8] = [
+    Stage::Enumerate, Stage::BuildCapturer, Stage::FirstFrame, Stage::Convert,
+    Stage::Encode, Stage::Save, Stage::Ocr, Stage::Llm,
+];
+
+impl Stage {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Enumerate => "enumerate_targets",
+            Stage::BuildCapturer => "build_capturer",
+            Stage::FirstFrame => "first_frame",
+            Stage::Convert => "convert",
+            Stage::Encode => "encode",
+            Stage::Save => "save",
+            Stage::Ocr => "ocr",
+            Stage::Llm => "llm",
+        }
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+/// Time spent and times entered, per stage in `STAGES` order.
+static TOTALS: Mutex<[(Duration, u32); 8]> = Mutex::new([(Duration::ZERO, 0); 8]);
+
+/// Report the breakdown when the command finishes (`--timings`).
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Add `elapsed` to `stage`.
+pub fn record(stage: Stage, elapsed: Duration) {
+    let index = STAGES.iter().position(|s| *s == stage).unwrap_or(0);
+    let mut totals = TOTALS.lock().unwrap();
+    totals[index].0 += elapsed;
+    totals[index].1 += 1;
+}
+
+/// Times `stage` until dropped, so early returns are counted too.
+pub struct Span {
+    stage: Stage,
+    started: Instant,
+}
+
+pub fn span(stage: Stage) -> Span {
+    Span { stage, started: Instant::now() }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        record(self.stage, self.started.elapsed());
+    }
+}
+
+#[derive(Serialize)]
+pub struct StageTiming {
+    pub stage: &'static str,
+    pub ms: f64,
+    /// How often the stage ran, e.g. once per frame when recording
+    pub count: u32,
+}
+
+/// Where a command's time went. Stages that didn't run are left out; the
+/// rest of `total_ms` is argument parsing, waiting and other work.
+#[derive(Serialize)]
+pub struct Timings {
+    pub stages: Vec<StageTiming>,
+    /// Since captest started
+    pub total_ms: f64,
+}
+
+pub fn summary() -> Timings {
+    let totals = TOTALS.lock().unwrap();
+    let stages = STAGES.iter().zip(totals.iter())
+        .filter(|(_, (_, count))| *count > 0)
+        .map(|(stage, (elapsed, count))| StageTiming { stage: stage.name(), ms: millis(*elapsed), count: *count })
+        .collect();
+    Timings { stages, total_ms: millis(timestamp::since_start()) }
+}
+
+/// Print the breakdown as a table, shown even with --quiet since it was asked for.
+pub fn print(timings: &Timings) {
+    result!("Timings:");
+    for stage in &timings.stages {
+        let count = if stage.count > 1 { format!(" ({}x)", stage.count) } else { String::new() };
+        result!("  {:<18} {:>10.1} ms{}", stage.stage, stage.ms, count);
+    }
+    result!("  {:<18} {:>10.1} ms", "total", timings.total_ms);
+}
+
+fn millis(duration: Duration) -> f64 {
+    (duration.as_secs_f64() * 1_000_000.0).round() / 1000.0
+}
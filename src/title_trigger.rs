@@ -0,0 +1,76 @@
+use std::time::{Duration, Instant};
+
+use regex::Regex;
+use scap::Target;
+
+use crate::capture;
+use crate::privacy;
+use crate::session_pool::{self, TargetKey};
+
+/// How often the title is read. Listing windows is cheap but not free.
+pub const CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A title that changed to match one of the trigger patterns.
+pub struct TitleMatch {
+    pub title: String,
+    pub previous: Option<String>,
+    pub pattern: String,
+}
+
+/// Polls the title of a watched window (or the active window, for screen
+/// targets) and reports when it changes to one matching a pattern, e.g. an
+/// IDE or CI dashboard showing "Build failed" in its title bar.
+pub struct TitleTrigger {
+    patterns: Vec<Regex>,
+    /// None for screen targets, which follow the active window instead
+    window_id: Option<u32>,
+    last: Option<String>,
+    next_check: Instant,
+}
+
+impl TitleTrigger {
+    /// A trigger for `key`, or None without patterns. The current title is
+    /// the starting point, so a title that already matches doesn't fire.
+    pub fn new(key: TargetKey, patterns: &[Regex]) -> Option<Self> {
+        if patterns.is_empty() {
+            return None;
+        }
+        let window_id = match session_pool::resolve_target(key) {
+            Ok(Target::Window(window)) => Some(window.id),
+            _ => None,
+        };
+        let mut trigger = Self { patterns: patterns.to_vec(), window_id, last: None, next_check: Instant::now() };
+        trigger.last = trigger.current_title();
+        Some(trigger)
+    }
+
+    /// How long until the next check is due.
+    pub fn wait_time(&self) -> Duration {
+        self.next_check.saturating_duration_since(Instant::now())
+    }
+
+    /// Read the title and return a match when it changed to one a pattern
+    /// accepts. A title that disappears (window closed, nothing focused)
+    /// doesn't count as a change.
+    pub fn poll(&mut self) -> Option<TitleMatch> {
+        self.next_check = Instant::now() + CHECK_INTERVAL;
+        let title = self.current_title()?;
+        if self.last.as_ref() == Some(&title) {
+            return None;
+        }
+        let previous = self.last.replace(title.clone());
+        let pattern = self.patterns.iter().find(|pattern| pattern.is_match(&title))?;
+        Some(TitleMatch { pattern: pattern.as_str().to_string(), title, previous })
+    }
+
+    fn current_title(&self) -> Option<String> {
+        match self.window_id {
+            Some(window_id) => capture::all_targets().into_iter().find_map(|target| match target {
+                Target::Window(window) if window.id == window_id => Some(window.title),
+                _ => None,
+            }),
+            // Sensitive titles come back as [hidden], never their text
+            None => privacy::active_window().map(|(_, title)| title),
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use image::RgbImage;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio_util::sync::CancellationToken;
+
+use crate::session_pool::TargetKey;
+use crate::{capture, config, platforms, privacy, TrackArgs};
+
+/// Timeline file format.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TimelineFormat {
+    /// One JSON object per line
+    Jsonl,
+    /// Comma-separated values with a header row
+    Csv,
+}
+
+/// One sample of the timeline.
+#[derive(Serialize)]
+struct Sample {
+    /// Unix milliseconds
+    timestamp: u128,
+    /// "active", or why the screen is unavailable ("locked", "screensaver", "display_asleep")
+    state: &'static str,
+    app: String,
+    title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    thumbnail: Option<String>,
+}
+
+/// Time spent per app over the run.
+#[derive(Serialize)]
+struct AppTime {
+    app: String,
+    duration_ms: u128,
+}
+
+struct Timeline {
+    file: BufWriter<File>,
+    format: TimelineFormat,
+}
+
+impl Timeline {
+    /// Open `path` for appending, so a restarted run extends the same timeline.
+    fn open(path: &Path, format: TimelineFormat) -> Result<Self, Box<dyn std::error::Error>> {
+        if let Some(parent) = path.parent()
+            && !parent.as_os_str().is_empty()
+        {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        let empty = file.metadata()?.len() == 0;
+        let mut timeline = Self { file: BufWriter::new(file), format };
+        if empty && format == TimelineFormat::Csv {
+            timeline.file.write_all(b"timestamp,state,app,title,thumbnail\n")?;
+        }
+        Ok(timeline)
+    }
+
+    fn write(&mut self, sample: &Sample) -> Result<(), Box<dyn std::error::Error>> {
+        match self.format {
+            TimelineFormat::Jsonl => writeln!(self.file, "{}", serde_json::to_string(sample)?)?,
+            TimelineFormat::Csv => writeln!(
+                self.file,
+                "{},{},{},{},{}",
+                sample.timestamp,
+                sample.state,
+                csv_field(&sample.app),
+                csv_field(&sample.title),
+                csv_field(sample.thumbnail.as_deref().unwrap_or(""))
+            )?,
+        }
+        // Each sample reaches the disk, so stopping at any point keeps the timeline
+        self.file.flush()?;
+        Ok(())
+    }
+}
+
+/// Quote a CSV value when it needs it.
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// The format `--format` asks for, or the one the output's extension implies.
+fn timeline_format(args: &TrackArgs, output: &str) -> TimelineFormat {
+    args.format.unwrap_or_else(|| match Path::new(output).extension() {
+        Some(ext) if ext.eq_ignore_ascii_case("csv") => TimelineFormat::Csv,
+        _ => TimelineFormat::Jsonl,
+    })
+}
+
+/// Sample the active window's app and title every `--interval` seconds until
+/// Ctrl-C or `--duration`, appending each sample to a timeline. No pixels are
+/// captured unless `--with-thumbnails` is given.
+pub async fn track(key: TargetKey, args: &TrackArgs, cancel: &CancellationToken) -> Result<Value, Box<dyn std::error::Error>> {
+    let output = config::resolve_output_path(&args.output);
+    let format = timeline_format(args, &output);
+    let mut timeline = Timeline::open(Path::new(&output), format)?;
+    let thumbnail_dir = args.with_thumbnails.then(|| thumbnail_dir(&output));
+    if let Some(dir) = &thumbnail_dir {
+        capture::ensure_capture_allowed()?;
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let interval = Duration::from_secs_f64(args.interval.max(0.1));
+    let deadline = args.duration.map(|duration| Instant::now() + Duration::from_secs_f64(duration.max(0.0)));
+    let mut ticker = tokio::time::interval(interval);
+    let mut samples = 0usize;
+    let mut apps: Vec<AppTime> = Vec::new();
+
+    status!("Tracking the active window every {:.1}s to {} (Ctrl-C to stop)", interval.as_secs_f64(), output);
+    loop {
+        let remaining = deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()));
+        tokio::select! {
+            _ = cancel.cancelled() => break,
+            _ = tokio::time::sleep(remaining.unwrap_or(Duration::MAX)) => break,
+            _ = ticker.tick() => {}
+        }
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0);
+        let unavailable = platforms::screen_unavailable();
+        let (app, title) = match unavailable {
+            Some(_) => Default::default(),
+            None => privacy::active_window().unwrap_or_default(),
+        };
+
+        let thumbnail = match (&thumbnail_dir, unavailable) {
+            (Some(dir), None) => match save_thumbnail(key, dir, timestamp, args.thumbnail_width, cancel).await {
+                Ok(path) => Some(path),
+                Err(e) => {
+                    status!("Failed to save thumbnail: {}", e);
+                    None
+                }
+            },
+            _ => None,
+        };
+
+        if unavailable.is_none() && !app.is_empty() {
+            match apps.iter_mut().find(|time| time.app == app) {
+                Some(time) => time.duration_ms += interval.as_millis(),
+                None => apps.push(AppTime { app: app.clone(), duration_ms: interval.as_millis() }),
+            }
+        }
+        let sample = Sample { timestamp, state: unavailable.unwrap_or("active"), app, title, thumbnail };
+        status!("{} {}", if sample.app.is_empty() { sample.state } else { &sample.app }, sample.title);
+        timeline.write(&sample)?;
+        samples += 1;
+    }
+
+    apps.sort_by_key(|time| std::cmp::Reverse(time.duration_ms));
+    status!("Recorded {} samples to {}", samples, output);
+    for time in &apps {
+        result!("{:>10.1} min  {}", time.duration_ms as f64 / 60_000.0, time.app);
+    }
+
+    Ok(json!({
+        "status": "ok",
+        "output": output,
+        "samples": samples,
+        "apps": apps,
+    }))
+}
+
+/// `<output stem>_thumbnails` next to the timeline.
+fn thumbnail_dir(output: &str) -> PathBuf {
+    let path = Path::new(output);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    path.with_file_name(format!("{}_thumbnails", stem))
+}
+
+async fn save_thumbnail(key: TargetKey, dir: &Path, timestamp: u128, width: u32, cancel: &CancellationToken) -> Result<String, Box<dyn std::error::Error>> {
+    let (frame_width, frame_height, rgb_data) = capture::grab_rgb8(key, cancel).await?;
+    let image = RgbImage::from_raw(frame_width, frame_height, rgb_data).ok_or("Captured frame has an unexpected size")?;
+    let width = width.clamp(1, frame_width.max(1));
+    let height = ((frame_height as u64 * width as u64) / frame_width.max(1) as u64).max(1) as u32;
+    let thumbnail = image::imageops::thumbnail(&image, width, height);
+
+    let path = dir.join(format!("{}.jpg", timestamp));
+    crate::save_image_bytes(&crate::rgb8_to_jpeg_bytes(width, height, thumbnail.as_raw())?, &path.display().to_string())
+}
@@ -0,0 +1,235 @@
+use crate::layout::{self, LineBox};
+
+/// Marks a placeholder line that stands in for a detected table.
+const TABLE_MARKER: &str = "\u{0}table:";
+
+/// Render OCR lines as Markdown. Line height is used as a font-size proxy for
+/// headings, bullet and number prefixes become list items, rows of aligned
+/// cells become tables, and the remaining lines of a block are joined into a
+/// paragraph.
+pub fn to_markdown(lines: Vec<LineBox>) -> String {
+    if lines.is_empty() {
+        return String::new();
+    }
+
+    let body_height = median_height(&lines);
+    let (lines, tables) = extract_tables(lines, body_height);
+
+    let mut out: Vec<String> = Vec::new();
+    for block in layout::reading_order_blocks(lines) {
+        let mut paragraph: Vec<&str> = Vec::new();
+        for line in &block {
+            let text = line.text.trim();
+
+            if let Some(index) = text.strip_prefix(TABLE_MARKER) {
+                flush_paragraph(&mut out, &mut paragraph);
+                out.push(tables[index.parse::<usize>().unwrap_or(0)].clone());
+            } else if let Some(level) = heading_level(line, body_height) {
+                flush_paragraph(&mut out, &mut paragraph);
+                out.push(format!("{} {}", "#".repeat(level), text));
+            } else if let Some(item) = list_item(text) {
+                flush_paragraph(&mut out, &mut paragraph);
+                // Consecutive items belong to one list, without blank lines between
+                match out.last_mut() {
+                    Some(last) if is_list(last) => {
+                        last.push('\n');
+                        last.push_str(&item);
+                    }
+                    _ => out.push(item),
+                }
+            } else {
+                paragraph.push(text);
+            }
+        }
+        flush_paragraph(&mut out, &mut paragraph);
+    }
+
+    out.join("\n\n") + "\n"
+}
+
+fn flush_paragraph(out: &mut Vec<String>, paragraph: &mut Vec<&str>) {
+    if paragraph.is_empty() {
+        return;
+    }
+    // Words hyphenated at the end of a line are joined back together
+    let mut text = String::new();
+    for line in paragraph.drain(..) {
+        if text.ends_with('-') && line.starts_with(char::is_lowercase) {
+            text.pop();
+        } else if !text.is_empty() {
+            text.push(' ');
+        }
+        text.push_str(line);
+    }
+    out.push(text);
+}
+
+fn median_height(lines: &[LineBox]) -> i32 {
+    let mut heights: Vec<i32> = lines.iter().map(LineBox::height).collect();
+    heights.sort_unstable();
+    heights[heights.len() / 2]
+}
+
+/// Lines noticeably taller than body text are headings, and short ones at that.
+fn heading_level(line: &LineBox, body_height: i32) -> Option<usize> {
+    let ratio = line.height() as f64 / body_height.max(1) as f64;
+    let words = line.text.split_whitespace().count();
+    if words == 0 || words > 12 {
+        return None;
+    }
+    if ratio >= 1.8 {
+        Some(1)
+    } else if ratio >= 1.4 {
+        Some(2)
+    } else if ratio >= 1.2 {
+        Some(3)
+    } else {
+        None
+    }
+}
+
+fn list_item(text: &str) -> Option<String> {
+    for bullet in ['•', '◦', '▪', '·', '●', '○', '-', '*', '–'] {
+        if let Some(rest) = text.strip_prefix(bullet)
+            && rest.starts_with(' ')
+        {
+            return Some(format!("- {}", rest.trim()));
+        }
+    }
+
+    // "1." or "1)" numbering
+    let digits = text.chars().take_while(char::is_ascii_digit).count();
+    let rest = &text[digits..];
+    if digits > 0 && digits <= 3 && (rest.starts_with(". ") || rest.starts_with(") ")) {
+        return Some(format!("{}. {}", &text[..digits], rest[2..].trim()));
+    }
+    None
+}
+
+fn is_list(block: &str) -> bool {
+    block.lines().all(|line| {
+        line.starts_with("- ") || line.split_once(". ").is_some_and(|(n, _)| n.chars().all(|c| c.is_ascii_digit()))
+    })
+}
+
+/// Find runs of rows with the same number of horizontally separated cells,
+/// render them as Markdown tables and replace their lines with a placeholder
+/// line covering the table's area, so it keeps its place in reading order.
+fn extract_tables(lines: Vec<LineBox>, body_height: i32) -> (Vec<LineBox>, Vec<String>) {
+    let rows = group_rows(lines);
+    let mut remaining = Vec::new();
+    let mut tables = Vec::new();
+
+    let mut i = 0;
+    while i < rows.len() {
+        let columns = if is_table_row(&rows[i]) { rows[i].len() } else { 0 };
+        let mut end = i + 1;
+        while columns >= 2
+            && end < rows.len()
+            && is_table_row(&rows[end])
+            && rows[end].len() == columns
+            && columns_align(&rows[i], &rows[end], body_height)
+        {
+            end += 1;
+        }
+
+        if columns >= 2 && end - i >= 2 {
+            let table_rows = &rows[i..end];
+            let cells = |row: &Vec<LineBox>| format!("| {} |", row.iter().map(|cell| cell.text.trim().replace('|', "\\|")).collect::<Vec<_>>().join(" | "));
+            let mut table = vec![cells(&table_rows[0]), format!("|{}", " --- |".repeat(columns))];
+            table.extend(table_rows[1..].iter().map(cells));
+
+            let all = table_rows.iter().flatten();
+            remaining.push(LineBox {
+                text: format!("{}{}", TABLE_MARKER, tables.len()),
+                left: all.clone().map(|cell| cell.left).min().unwrap_or(0),
+                top: all.clone().map(|cell| cell.top).min().unwrap_or(0),
+                right: all.clone().map(|cell| cell.right).max().unwrap_or(0),
+                bottom: all.map(|cell| cell.bottom).max().unwrap_or(0),
+                words: Vec::new(),
+            });
+            tables.push(table.join("\n"));
+            i = end;
+        } else {
+            remaining.extend(rows[i].iter().cloned());
+            i += 1;
+        }
+    }
+
+    (remaining, tables)
+}
+
+/// Table cells are short. Side-by-side columns of prose also form rows of
+/// aligned lines, but with many words per line.
+fn is_table_row(row: &[LineBox]) -> bool {
+    let words: usize = row.iter().map(|cell| cell.text.split_whitespace().count()).sum();
+    row.len() >= 2 && words <= row.len() * 5
+}
+
+/// Group lines whose vertical extents mostly overlap into rows, left to right.
+fn group_rows(mut lines: Vec<LineBox>) -> Vec<Vec<LineBox>> {
+    lines.sort_by_key(|line| (line.top, line.left));
+    let mut rows: Vec<Vec<LineBox>> = Vec::new();
+    for line in lines {
+        let center = (line.top + line.bottom) / 2;
+        match rows.last_mut() {
+            Some(row) if row.iter().all(|cell| center > cell.top && center < cell.bottom) => row.push(line),
+            _ => rows.push(vec![line]),
+        }
+    }
+    for row in &mut rows {
+        row.sort_by_key(|cell| cell.left);
+    }
+    rows
+}
+
+/// Cells of two rows line up when each column starts at about the same x.
+fn columns_align(a: &[LineBox], b: &[LineBox], body_height: i32) -> bool {
+    let tolerance = body_height * 2;
+    a.iter().zip(b).all(|(a, b)| (a.left - b.left).abs() <= tolerance || (a.right - b.right).abs() <= tolerance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(text: &str, left: i32, top: i32, right: i32, bottom: i32) -> LineBox {
+        LineBox { text: text.to_string(), left, top, right, bottom, words: Vec::new() }
+    }
+
+    #[test]
+    fn renders_headings_paragraphs_and_lists() {
+        let lines = vec![
+            line("Release notes", 0, 0, 400, 40),
+            line("This version fixes several long-", 0, 60, 600, 80),
+            line("standing bugs.", 0, 85, 300, 105),
+            line("• Faster startup", 0, 200, 300, 220),
+            line("• Smaller binaries", 0, 225, 320, 245),
+        ];
+        assert_eq!(to_markdown(lines), "# Release notes\n\nThis version fixes several longstanding bugs.\n\n- Faster startup\n- Smaller binaries\n");
+    }
+
+    #[test]
+    fn renders_numbered_lists() {
+        let lines = vec![line("1. Download it", 0, 0, 300, 20), line("2) Run it", 0, 25, 300, 45)];
+        assert_eq!(to_markdown(lines), "1. Download it\n2. Run it\n");
+    }
+
+    #[test]
+    fn renders_aligned_rows_as_a_table() {
+        let lines = vec![
+            line("Name", 0, 0, 100, 20),
+            line("Size", 300, 0, 380, 20),
+            line("a|b.txt", 0, 25, 120, 45),
+            line("12 KB", 300, 25, 390, 45),
+            line("c.txt", 0, 50, 100, 70),
+            line("3 KB", 300, 50, 380, 70),
+        ];
+        assert_eq!(to_markdown(lines), "| Name | Size |\n| --- | --- |\n| a\\|b.txt | 12 KB |\n| c.txt | 3 KB |\n");
+    }
+
+    #[test]
+    fn renders_nothing_for_no_lines() {
+        assert_eq!(to_markdown(Vec::new()), "");
+    }
+}
@@ -0,0 +1,324 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use reqwest::multipart::{Form, Part};
+use url::Url;
+
+use crate::config;
+use crate::encode::ImageFormat;
+use crate::output::{ShareReport, UploadReport};
+
+pub const DEFAULT_ZERO_X_ZERO_URL: &str = "https://0x0.st";
+const IMGUR_UPLOAD_URL: &str = "https://api.imgur.com/3/image";
+
+/// Public hosts `--share` can upload to.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum ShareHost {
+    /// 0x0.st or another instance of its software (`url` in `[share]`); supports expiry
+    #[default]
+    #[value(name = "0x0")]
+    ZeroXZero,
+    /// imgur, anonymously with an API client ID (`imgur_client_id` in `[share]`)
+    Imgur,
+}
+
+impl ShareHost {
+    fn name(self) -> &'static str {
+        match self {
+            ShareHost::ZeroXZero => "0x0",
+            ShareHost::Imgur => "imgur",
+        }
+    }
+}
+
+/// The config file's `[share]` table, with defaults applied.
+#[derive(Default)]
+pub struct ShareSettings {
+    pub host: ShareHost,
+    /// Upload endpoint for 0x0; None uses 0x0.st
+    pub url: Option<String>,
+    pub imgur_client_id: Option<String>,
+    /// Hours until uploads expire, unless `--share-expires` is given
+    pub expires_hours: Option<u32>,
+    /// Copy the URL to the clipboard without `--copy-url`
+    pub copy_url: bool,
+}
+
+/// Upload an image to the configured share host and return its public URL.
+/// `expires_hours` asks the host to delete it after that many hours.
+pub async fn share(image_bytes: &[u8], file_name: &str, expires_hours: Option<u32>) -> Result<ShareReport, Box<dyn std::error::Error>> {
+    let settings = &config::settings().share;
+    let file = Part::bytes(image_bytes.to_vec()).file_name(file_name.to_string()).mime_str(mime_type(file_name))?;
+    let client = reqwest::Client::builder()
+        // 0x0.st rejects requests without a descriptive user agent
+        .user_agent(concat!("captest/", env!("CARGO_PKG_VERSION")))
+        .build()?;
+    status!("Uploading {} to {}", file_name, settings.host.name());
+
+    match settings.host {
+        ShareHost::ZeroXZero => {
+            let mut form = Form::new().part("file", file);
+            if let Some(hours) = expires_hours {
+                form = form.text("expires", hours.to_string());
+            }
+            let response = client.post(settings.url.as_deref().unwrap_or(DEFAULT_ZERO_X_ZERO_URL)).multipart(form).send().await?;
+            let status = response.status();
+            let header = |name: &str| response.headers().get(name).and_then(|value| value.to_str().ok()).map(str::to_string);
+            let (expires_at, delete_token) = (header("X-Expires").and_then(|ms| ms.parse().ok()), header("X-Token"));
+            let body = response.text().await?;
+            if !status.is_success() {
+                return Err(format!("Upload failed with status {}: {}", status.as_u16(), body.trim()).into());
+            }
+            Ok(ShareReport { url: body.trim().to_string(), host: settings.host.name().to_string(), expires_at, delete_token })
+        }
+        ShareHost::Imgur => {
+            let client_id = settings.imgur_client_id.as_deref().ok_or("Sharing to imgur needs imgur_client_id in the [share] config")?;
+            if expires_hours.is_some() {
+                status!("Warning: imgur uploads don't expire, ignoring the expiry");
+            }
+            let response = client.post(IMGUR_UPLOAD_URL)
+                .header("Authorization", format!("Client-ID {}", client_id))
+                .multipart(Form::new().part("image", file))
+                .send()
+                .await?;
+            let status = response.status();
+            let body: serde_json::Value = response.json().await?;
+            if !status.is_success() {
+                return Err(format!("Upload failed with status {}: {}", status.as_u16(), body["data"]["error"]).into());
+            }
+            let url = body["data"]["link"].as_str().ok_or("No link in the imgur response")?;
+            Ok(ShareReport {
+                url: url.to_string(),
+                host: settings.host.name().to_string(),
+                expires_at: None,
+                delete_token: body["data"]["deletehash"].as_str().map(str::to_string),
+            })
+        }
+    }
+}
+
+/// Credentials for `--upload` from the config file's `[upload]` table.
+#[derive(Default)]
+pub struct UploadSettings {
+    /// Private key for SFTP and SCP; None leaves the choice to ssh (agent, ~/.ssh/config)
+    pub ssh_key: Option<PathBuf>,
+    /// WebDAV basic auth, unless the destination URL has its own user
+    pub webdav_username: Option<String>,
+    pub webdav_password: Option<String>,
+}
+
+/// Where `--upload` puts a capture.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Destination {
+    /// `sftp://[USER@]HOST[:PORT]/PATH`, through the `sftp` client
+    Sftp(Url),
+    /// `scp://[USER@]HOST[:PORT]/PATH`, through the `scp` client
+    Scp(Url),
+    /// `dav://` or `davs://` (HTTP or HTTPS) WebDAV URL, uploaded with PUT
+    WebDav(Url),
+}
+
+impl FromStr for Destination {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, String> {
+        let url = Url::parse(value).map_err(|e| format!("Invalid upload destination \"{}\": {}", value, e))?;
+        if url.host_str().is_none_or(str::is_empty) {
+            return Err(format!("Invalid upload destination \"{}\": no host", value));
+        }
+        match url.scheme() {
+            "sftp" => Ok(Self::Sftp(url)),
+            "scp" => Ok(Self::Scp(url)),
+            "dav" | "davs" => Ok(Self::WebDav(url)),
+            scheme => Err(format!("Invalid upload destination \"{}\": unsupported scheme {} (expected sftp, scp, dav or davs)", value, scheme)),
+        }
+    }
+}
+
+impl Destination {
+    fn url(&self) -> &Url {
+        match self {
+            Self::Sftp(url) | Self::Scp(url) | Self::WebDav(url) => url,
+        }
+    }
+
+    /// Remote path to write: the destination's path, plus `file_name` when
+    /// it names a directory (ends with `/`).
+    fn remote_path(&self, file_name: &str) -> String {
+        let path = percent_decode(self.url().path());
+        // sftp://host/~/shots/ is relative to the login directory
+        let path = path.strip_prefix("/~/").map(str::to_string).unwrap_or(path);
+        match path.is_empty() || path.ends_with('/') {
+            true => format!("{}{}", path, file_name),
+            false => path,
+        }
+    }
+
+    /// The destination without its password, for messages and reports.
+    pub fn describe(&self) -> String {
+        redacted(self.url())
+    }
+
+    /// `[USER@]HOST` for ssh clients.
+    fn ssh_host(&self) -> String {
+        let url = self.url();
+        let host = url.host_str().unwrap_or_default();
+        match url.username() {
+            "" => host.to_string(),
+            user => format!("{}@{}", percent_decode(user), host),
+        }
+    }
+}
+
+/// Content type of an upload, from its file name; JPEG unless it says otherwise.
+fn mime_type(file_name: &str) -> &'static str {
+    ImageFormat::from_path(file_name).unwrap_or_default().mime_type()
+}
+
+/// Upload an image to `destination` and report where it went.
+pub async fn upload(image_bytes: &[u8], file_name: &str, destination: &Destination) -> Result<UploadReport, Box<dyn std::error::Error>> {
+    let settings = &config::settings().upload;
+    let remote_path = destination.remote_path(file_name);
+    status!("Uploading {} to {}", file_name, redacted(destination.url()));
+
+    let location = match destination {
+        Destination::WebDav(url) => {
+            let scheme = if url.scheme() == "davs" { "https" } else { "http" };
+            let mut url = Url::parse(&format!("{}{}", scheme, &url.as_str()[url.scheme().len()..]))?;
+            if url.path().ends_with('/') {
+                url = url.join(file_name)?;
+            }
+            let username = match url.username() {
+                "" => settings.webdav_username.clone(),
+                user => Some(percent_decode(user)),
+            };
+            let password = url.password().map(percent_decode).or_else(|| settings.webdav_password.clone());
+            let _ = url.set_username("");
+            let _ = url.set_password(None);
+
+            let mut request = reqwest::Client::new().put(url.clone()).header("Content-Type", mime_type(file_name)).body(image_bytes.to_vec());
+            if let Some(username) = username {
+                request = request.basic_auth(username, password);
+            }
+            let response = request.send().await?;
+            let status = response.status();
+            if !status.is_success() {
+                return Err(format!("WebDAV upload failed with status {}", status.as_u16()).into());
+            }
+            url.to_string()
+        }
+        Destination::Sftp(_) | Destination::Scp(_) => {
+            // Both clients read the capture from a file. It gets a fresh random
+            // name, is only readable by this user and is removed when dropped.
+            let mut local = tempfile::Builder::new().prefix("captest-upload-").tempfile()?;
+            local.write_all(image_bytes)?;
+            local.flush()?;
+            let (destination, ssh_key, remote) = (destination.clone(), settings.ssh_key.clone(), remote_path.clone());
+            tokio::task::spawn_blocking(move || run_ssh_upload(&destination, ssh_key.as_deref(), local.path(), &remote)).await??;
+            remote_path
+        }
+    };
+
+    Ok(UploadReport { destination: redacted(destination.url()), location })
+}
+
+/// Copy `local` to `remote` with `scp` or a batch `sftp` session. Both run in
+/// batch mode, so keys and the ssh agent work but password prompts don't.
+/// `scp` is pinned to its original protocol, where the remote shell expands
+/// the path, so the path is quoted for that shell.
+fn run_ssh_upload(destination: &Destination, ssh_key: Option<&Path>, local: &Path, remote: &str) -> Result<(), String> {
+    let url = destination.url();
+    let mut args: Vec<String> = vec!["-o".to_string(), "BatchMode=yes".to_string()];
+    if let Some(port) = url.port() {
+        args.extend(["-P".to_string(), port.to_string()]);
+    }
+    if let Some(key) = ssh_key {
+        // No shell expands a ~ in the config file
+        let key = match (key.strip_prefix("~"), std::env::var_os("HOME")) {
+            (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+            _ => key.to_path_buf(),
+        };
+        args.extend(["-i".to_string(), key.display().to_string()]);
+    }
+
+    let (program, output) = match destination {
+        Destination::Scp(_) => {
+            let target = format!("{}:{}", destination.ssh_host(), shell_quote(remote));
+            args.extend(["-O".to_string(), "-q".to_string(), "--".to_string(), local.display().to_string(), target]);
+            ("scp", Command::new("scp").args(&args).stdin(Stdio::null()).output())
+        }
+        _ => {
+            args.extend(["-b".to_string(), "-".to_string(), "--".to_string(), destination.ssh_host()]);
+            let quote = |path: &str| path.replace('\\', "\\\\").replace('"', "\\\"");
+            let batch = format!("put \"{}\" \"{}\"\n", quote(&local.display().to_string()), quote(remote));
+            let child = Command::new("sftp").args(&args).stdin(Stdio::piped()).stdout(Stdio::piped()).stderr(Stdio::piped()).spawn();
+            let output = child.and_then(|mut child| {
+                if let Some(mut stdin) = child.stdin.take() {
+                    stdin.write_all(batch.as_bytes())?;
+                }
+                child.wait_with_output()
+            });
+            ("sftp", output)
+        }
+    };
+    let output = output.map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(format!("{} failed: {}", program, stderr.trim()));
+    }
+    Ok(())
+}
+
+/// Quote `path` for a POSIX shell. Single quotes keep everything literal, so
+/// only single quotes themselves need escaping.
+fn shell_quote(path: &str) -> String {
+    format!("'{}'", path.replace('\'', "'\\''"))
+}
+
+/// `url` without its password, for messages and reports.
+pub fn redacted(url: &Url) -> String {
+    let mut url = url.clone();
+    if url.password().is_some() {
+        let _ = url.set_password(Some("***"));
+    }
+    url.to_string()
+}
+
+/// Decode the `%XX` escapes of a URL path or user name. Everything else,
+/// `+`, `&` and `=` included, is kept as written.
+fn percent_decode(value: &str) -> String {
+    let decoded = percent_encoding::percent_decode_str(value);
+    match decoded.clone().decode_utf8() {
+        Ok(text) => text.into_owned(),
+        Err(_) => decoded.decode_utf8_lossy().into_owned(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shell_quote_keeps_paths_literal() {
+        assert_eq!(shell_quote("shots/latest.jpg"), "'shots/latest.jpg'");
+        assert_eq!(shell_quote("a b;$(rm -rf ~)`x`.jpg"), "'a b;$(rm -rf ~)`x`.jpg'");
+        assert_eq!(shell_quote("it's.jpg"), "'it'\\''s.jpg'");
+    }
+
+    #[test]
+    fn percent_decode_keeps_form_characters_literal() {
+        assert_eq!(percent_decode("a&b=c+d"), "a&b=c+d");
+        assert_eq!(percent_decode("p%26ss%3Dw%2Brd"), "p&ss=w+rd");
+        assert_eq!(percent_decode("shots%20and%20clips/%C3%A9t%C3%A9"), "shots and clips/été");
+    }
+
+    #[test]
+    fn destinations_decode_paths_and_users() {
+        let destination = Destination::Scp(Url::parse("scp://me%2Bci@host/shots/a&b=c+d%20e.jpg").unwrap());
+        assert_eq!(destination.ssh_host(), "me+ci@host");
+        assert_eq!(destination.remote_path("x.jpg"), "/shots/a&b=c+d e.jpg");
+    }
+}
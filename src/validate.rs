@@ -0,0 +1,165 @@
+use serde::Serialize;
+
+/// Channel values up to this count as black. Protected content is blanked to
+/// pure black, so this is much stricter than "dark".
+const BLACK_LEVEL: u8 = 2;
+/// Share of black pixels that makes the whole frame count as black.
+const BLACK_FRAME_SHARE: f64 = 0.995;
+/// Smallest share of the frame a black area must cover to be reported.
+const MIN_REGION_SHARE: f64 = 0.05;
+/// Share of black pixels inside a black area's bounding box.
+const REGION_FILL: f64 = 0.98;
+/// Share of pixels ignored at each end of the brightness range, so a lone
+/// cursor or speck of noise doesn't make a blank frame count as content.
+const OUTLIER_SHARE: f64 = 0.001;
+/// Brightness spread (0-255) within which a frame counts as one color.
+const MAX_BLANK_SPREAD: u8 = 6;
+/// Brightness entropy in bits below which a frame counts as empty. A page of
+/// sparse text is around 0.1; a blank one is nearly 0.
+const MIN_ENTROPY: f64 = 0.01;
+
+/// A frame, or an area of it, that the capture backend returned as pure
+/// black. DRM-protected video, secure input fields and windows that opt out
+/// of capture come out this way instead of failing.
+#[derive(Serialize)]
+pub struct BlackFrame {
+    /// "frame" when the whole capture is black, "region" for a black area in it
+    pub scope: &'static str,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Percentage of the frame that is black
+    pub percent: f64,
+}
+
+/// Look for protected content in an RGB8 frame: None unless the whole frame,
+/// or a large rectangle of it, is pure black.
+pub fn detect_black(width: u32, height: u32, rgb_data: &[u8]) -> Option<BlackFrame> {
+    let (w, h) = (width as usize, height as usize);
+    if w == 0 || h == 0 {
+        return None;
+    }
+    let mut row_counts = vec![0usize; h];
+    let mut column_counts = vec![0usize; w];
+    for (index, pixel) in rgb_data.chunks_exact(3).enumerate() {
+        if pixel.iter().all(|&value| value <= BLACK_LEVEL) {
+            row_counts[index / w] += 1;
+            column_counts[index % w] += 1;
+        }
+    }
+    let black: usize = row_counts.iter().sum();
+    let percent = black as f64 * 100.0 / (w * h) as f64;
+    if percent >= BLACK_FRAME_SHARE * 100.0 {
+        return Some(BlackFrame { scope: "frame", x: 0, y: 0, width, height, percent });
+    }
+
+    // A black video player or field shows up as a run of rows and a run of
+    // columns that are mostly black
+    let (top, bottom) = longest_run(&row_counts, w / 10)?;
+    let (left, right) = longest_run(&column_counts, h / 10)?;
+    let area = (bottom - top) * (right - left);
+    if (area as f64) < MIN_REGION_SHARE * (w * h) as f64 {
+        return None;
+    }
+    let inside = (top..bottom)
+        .map(|y| rgb_data[(y * w + left) * 3..(y * w + right) * 3].chunks_exact(3).filter(|pixel| pixel.iter().all(|&value| value <= BLACK_LEVEL)).count())
+        .sum::<usize>();
+    (inside as f64 >= REGION_FILL * area as f64).then_some(BlackFrame {
+        scope: "region",
+        x: left as u32,
+        y: top as u32,
+        width: (right - left) as u32,
+        height: (bottom - top) as u32,
+        percent,
+    })
+}
+
+/// A frame with (almost) nothing in it: a single color or near-zero
+/// entropy, the usual symptom of missing permissions or a backend that
+/// delivered an empty buffer.
+#[derive(Serialize)]
+pub struct BlankFrame {
+    /// The most common color, as #RRGGBB
+    pub color: String,
+    /// Brightness range covered by all but the outermost pixels
+    pub spread: u8,
+    /// Shannon entropy of the brightness histogram, in bits
+    pub entropy: f64,
+}
+
+impl BlankFrame {
+    pub fn describe(&self) -> String {
+        match self.spread <= MAX_BLANK_SPREAD {
+            true => format!("a single color ({})", self.color),
+            false => format!("almost no detail (entropy {:.3} bits)", self.entropy),
+        }
+    }
+}
+
+/// Check that an RGB8 frame has content: None unless it is one color or its
+/// brightness entropy is near zero.
+pub fn detect_blank(rgb_data: &[u8]) -> Option<BlankFrame> {
+    let pixels = rgb_data.len() / 3;
+    if pixels == 0 {
+        return None;
+    }
+    let mut histogram = [0usize; 256];
+    let mut colors = std::collections::HashMap::new();
+    for (index, pixel) in rgb_data.chunks_exact(3).enumerate() {
+        histogram[((pixel[0] as u32 * 77 + pixel[1] as u32 * 150 + pixel[2] as u32 * 29) >> 8) as usize] += 1;
+        // Sampling is enough to name the dominant color
+        if index % 64 == 0 {
+            *colors.entry([pixel[0], pixel[1], pixel[2]]).or_insert(0usize) += 1;
+        }
+    }
+
+    let entropy = -histogram.iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| count as f64 / pixels as f64)
+        .map(|p| p * p.log2())
+        .sum::<f64>();
+    let outliers = (pixels as f64 * OUTLIER_SHARE) as usize;
+    let low = past_outliers(&histogram, 0..256, outliers);
+    let high = past_outliers(&histogram, (0..256).rev(), outliers);
+    let spread = high.saturating_sub(low);
+    if spread > MAX_BLANK_SPREAD && entropy >= MIN_ENTROPY {
+        return None;
+    }
+
+    let color = colors.into_iter().max_by_key(|&(_, count)| count).map(|(color, _)| color).unwrap_or_default();
+    Some(BlankFrame {
+        color: format!("#{:02X}{:02X}{:02X}", color[0], color[1], color[2]),
+        spread,
+        entropy,
+    })
+}
+
+/// The first level in `levels` once more than `outliers` pixels have been passed.
+fn past_outliers(histogram: &[usize; 256], levels: impl Iterator<Item = usize>, outliers: usize) -> u8 {
+    let mut seen = 0;
+    let mut levels = levels.skip_while(|&level| {
+        seen += histogram[level];
+        seen <= outliers
+    });
+    levels.next().unwrap_or(0) as u8
+}
+
+/// The longest stretch of consecutive entries above `min`, as start and end.
+fn longest_run(counts: &[usize], min: usize) -> Option<(usize, usize)> {
+    let mut best: Option<(usize, usize)> = None;
+    let mut start = None;
+    for (index, &count) in counts.iter().chain([&0]).enumerate() {
+        match (count > min, start) {
+            (true, None) => start = Some(index),
+            (false, Some(first)) => {
+                if best.is_none_or(|(a, b)| index - first > b - a) {
+                    best = Some((first, index));
+                }
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    best
+}
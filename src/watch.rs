@@ -0,0 +1,248 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+use crate::events::{self, CaptureEvent, CapturedFrame, SubscribeOptions};
+use crate::rate_limit::RateLimiter;
+use crate::session_pool::TargetKey;
+use crate::title_trigger::{TitleMatch, TitleTrigger};
+use crate::error::{CaptestError, ErrorKind};
+use crate::webhook::{self, WebhookEvent};
+use crate::{capture, change, config, llm, mqtt, output, WatchArgs};
+
+/// A changed frame waiting for the analysis rate limit.
+struct PendingAnalysis {
+    jpeg_bytes: Vec<u8>,
+    changed_at: SystemTime,
+    /// Changes replaced by a newer frame while waiting
+    coalesced: usize,
+}
+
+/// Follow a target's change subscription until interrupted, reporting every
+/// visible change. OCR runs on each change; LLM analysis is throttled and only
+/// the newest pending change is analyzed when the limiter allows another
+/// request.
+pub async fn watch(key: TargetKey, args: &WatchArgs) -> Result<(), Box<dyn std::error::Error>> {
+    capture::ensure_capture_allowed()?;
+    mqtt::start().await?;
+
+    let interval = Duration::from_secs_f64(args.interval.max(0.05));
+    let mut subscription = events::subscribe(key, SubscribeOptions {
+        interval,
+        threshold: args.threshold,
+        frames: true,
+        ocr: args.ocr,
+        lock_size: args.lock_size,
+        reattach_by_title: args.reattach_by_title,
+        ignore: [config::settings().ignore_regions.clone(), change::resolve_regions(&args.ignore_region)?].concat(),
+    });
+    let mut limiter = RateLimiter::new(
+        args.analyze_interval.map(Duration::from_secs_f64),
+        args.max_analyses_per_minute,
+    );
+
+    let mut title_trigger = TitleTrigger::new(key, &args.title_trigger);
+
+    let mut frame: Option<CapturedFrame> = None;
+    let mut pending: Option<PendingAnalysis> = None;
+    let mut changes = 0usize;
+
+    status!("Watching {} every {:.2}s (Ctrl-C to stop)", key, interval.as_secs_f64());
+    loop {
+        // A queued analysis runs as soon as the limiter allows it
+        let retry_in = match pending {
+            Some(_) => limiter.wait_time(Instant::now()),
+            None => Duration::MAX,
+        };
+        let title_check_in = title_trigger.as_ref().map_or(Duration::MAX, TitleTrigger::wait_time);
+        let event = tokio::select! {
+            _ = tokio::signal::ctrl_c() => break,
+            _ = tokio::time::sleep(retry_in) => {
+                run_pending_analysis(&mut pending, &mut limiter, args).await;
+                continue;
+            }
+            _ = tokio::time::sleep(title_check_in) => {
+                if let Some(matched) = title_trigger.as_mut().and_then(TitleTrigger::poll) {
+                    title_changed(key, matched).await;
+                }
+                continue;
+            }
+            event = subscription.next() => event,
+        };
+
+        match event {
+            None => break,
+            Some(CaptureEvent::Frame(captured)) => frame = Some(captured),
+            Some(CaptureEvent::Changed { difference, region }) => {
+                let Some(CapturedFrame { width, height, rgb_data, captured_at }) = frame.take() else {
+                    continue;
+                };
+                changes += 1;
+                match difference {
+                    Some(difference) => status!("Change #{} on {} ({:.1}% different)", changes, key, difference),
+                    None => status!("Initial frame from {} ({}x{})", key, width, height),
+                }
+
+                let jpeg_bytes = crate::rgb8_to_jpeg_bytes(width, height, &rgb_data)?;
+                mqtt::publish_thumbnail(key, width, height, &rgb_data);
+                let mut event = json!({
+                    "event": "change",
+                    "target": key.to_string(),
+                    "change": changes,
+                    "difference": difference,
+                    "region": region,
+                    "width": width,
+                    "height": height,
+                    "timestamp": unix_millis(captured_at),
+                });
+
+                if let Some(difference) = difference {
+                    webhook::notify(WebhookEvent::MotionDetected, key, format!("{} changed ({:.1}% different)", key, difference), event.clone());
+                }
+                if args.save {
+                    let filename = config::resolve_output_path(&format!("watch_{}.jpg", unix_millis(captured_at)));
+                    match crate::save_image_bytes(&jpeg_bytes, &filename) {
+                        Ok(saved) => {
+                            event["output"] = json!(saved);
+                            webhook::notify(WebhookEvent::CaptureComplete, key, format!("Saved {} to {}", key, saved), event.clone());
+                        }
+                        Err(e) => status!("Failed to save {}: {}", filename, e),
+                    }
+                }
+                emit(&event);
+
+                if args.analyze {
+                    // Rapid changes collapse into the newest frame
+                    let coalesced = pending.take().map(|previous| previous.coalesced + 1).unwrap_or(0);
+                    pending = Some(PendingAnalysis { jpeg_bytes, changed_at: captured_at, coalesced });
+                    run_pending_analysis(&mut pending, &mut limiter, args).await;
+                }
+            }
+            Some(CaptureEvent::Ocr { lines, added, removed }) => {
+                result!("OCR Text Extraction:\n{}", lines.join("\n"));
+                let event = json!({
+                    "event": "ocr",
+                    "target": key.to_string(),
+                    "change": changes,
+                    "text": lines.join("\n"),
+                    "lines": lines,
+                    "added": added,
+                    "removed": removed,
+                });
+                if !added.is_empty() {
+                    webhook::notify(WebhookEvent::TextFound, key, format!("Text found on {}: {}", key, added.join(" | ")), event.clone());
+                }
+                emit(&event);
+            }
+            Some(CaptureEvent::Closed { title, reattaching }) => {
+                emit(&json!({
+                    "event": "closed",
+                    "target": key.to_string(),
+                    "title": title,
+                    "reattaching": reattaching,
+                }));
+                if !reattaching {
+                    status!("Stopped watching {} after {} change(s)", key, changes);
+                    return Err(CaptestError::new(ErrorKind::TargetClosed, format!("Window \"{}\" closed", title)).into());
+                }
+                status!("Window \"{}\" closed, waiting for it to reappear", title);
+            }
+            Some(CaptureEvent::Reattached { title }) => emit(&json!({
+                "event": "reattached",
+                "target": key.to_string(),
+                "title": title,
+            })),
+            Some(CaptureEvent::Paused { reason }) => emit(&json!({
+                "event": "paused",
+                "target": key.to_string(),
+                "reason": reason,
+            })),
+            Some(CaptureEvent::Resumed { gap }) => emit(&json!({
+                "event": "resumed",
+                "target": key.to_string(),
+                "reason": gap.reason,
+                "paused_at": gap.started_at,
+                "paused_ms": gap.duration_ms,
+            })),
+            Some(CaptureEvent::Error { message, fatal }) => {
+                webhook::notify(WebhookEvent::Error, key, format!("{}: {}", key, message), json!({ "error": message, "fatal": fatal }));
+                if fatal {
+                    return Err(message.into());
+                }
+                status!("{}", message);
+            }
+        }
+    }
+
+    status!("Stopped watching {} after {} change(s)", key, changes);
+    Ok(())
+}
+
+/// Fire a title trigger: capture the target, save it as
+/// title_<timestamp>.jpg and notify webhooks.
+async fn title_changed(key: TargetKey, matched: TitleMatch) {
+    let timestamp = unix_millis(SystemTime::now());
+    status!("Title of {} changed to \"{}\"", key, matched.title);
+    let mut event = json!({
+        "event": "title",
+        "target": key.to_string(),
+        "title": matched.title,
+        "previous": matched.previous,
+        "pattern": matched.pattern,
+        "timestamp": timestamp,
+    });
+
+    let saved = match capture::grab_rgb8(key, &CancellationToken::new()).await {
+        Ok((width, height, rgb_data)) => {
+            let filename = config::resolve_output_path(&format!("title_{}.jpg", timestamp));
+            crate::rgb8_to_jpeg_bytes(width, height, &rgb_data)
+                .and_then(|jpeg_bytes| crate::save_image_bytes(&jpeg_bytes, &filename))
+        }
+        Err(e) => Err(e),
+    };
+    match saved {
+        Ok(filename) => event["output"] = json!(filename),
+        Err(e) => status!("Failed to capture {} for the title change: {}", key, e),
+    }
+    webhook::notify(WebhookEvent::TitleChanged, key, format!("Title of {} changed to \"{}\"", key, matched.title), event.clone());
+    emit(&event);
+}
+
+async fn run_pending_analysis(pending: &mut Option<PendingAnalysis>, limiter: &mut RateLimiter, args: &WatchArgs) {
+    if !limiter.wait_time(Instant::now()).is_zero() {
+        return;
+    }
+    let Some(analysis_request) = pending.take() else {
+        return;
+    };
+
+    limiter.record(Instant::now());
+    if analysis_request.coalesced > 0 {
+        status!("Analyzing latest change ({} earlier change(s) coalesced)", analysis_request.coalesced);
+    }
+    match llm::analyze_image_cached(&analysis_request.jpeg_bytes, args.prompt.as_deref(), None).await {
+        Ok(analysis) => {
+            result!("LLM Analysis:\n{}", analysis.text);
+            emit(&json!({
+                "event": "analysis",
+                "timestamp": unix_millis(analysis_request.changed_at),
+                "coalesced": analysis_request.coalesced,
+                "analysis": analysis.text,
+                "usage": analysis.usage,
+            }));
+        }
+        Err(e) => status!("LLM analysis failed: {}", e),
+    }
+}
+
+/// Print an event line and publish it to MQTT.
+fn emit(event: &Value) {
+    output::event(event);
+    mqtt::publish_event(event);
+}
+
+fn unix_millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0)
+}
@@ -0,0 +1,168 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use clap::ValueEnum;
+use regex::Regex;
+use serde_json::{json, Value};
+
+use crate::config::{self, WebhookConfig};
+use crate::digest;
+use crate::session_pool::TargetKey;
+
+/// How long one delivery may take before it is given up.
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+/// How long `flush` waits for deliveries still in flight at exit.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+static IN_FLIGHT: AtomicUsize = AtomicUsize::new(0);
+
+/// What a webhook can be notified about.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum WebhookEvent {
+    /// A frame was saved (`watch --save`) or served (`serve`)
+    CaptureComplete,
+    /// `watch` saw the target change
+    MotionDetected,
+    /// `watch --ocr` found new text lines, optionally only lines matching the webhook's `match`
+    TextFound,
+    /// `watch --title-trigger` saw the title change to one matching a pattern
+    TitleChanged,
+    /// Capture or OCR failed
+    Error,
+}
+
+impl WebhookEvent {
+    fn name(self) -> &'static str {
+        match self {
+            WebhookEvent::CaptureComplete => "capture-complete",
+            WebhookEvent::MotionDetected => "motion-detected",
+            WebhookEvent::TextFound => "text-found",
+            WebhookEvent::TitleChanged => "title-changed",
+            WebhookEvent::Error => "error",
+        }
+    }
+}
+
+/// Body sent to a webhook.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum WebhookFormat {
+    /// The event as a JSON object
+    #[default]
+    Json,
+    /// `{"text": ...}` with a one-line summary, for Slack and Mattermost incoming webhooks
+    Slack,
+}
+
+/// A `[[webhooks]]` entry from the config file.
+pub struct Webhook {
+    url: String,
+    /// Empty means every event
+    events: Vec<WebhookEvent>,
+    secret: Option<String>,
+    text_match: Option<Regex>,
+    format: WebhookFormat,
+}
+
+impl Webhook {
+    pub fn from_config(config: &WebhookConfig) -> Result<Self, String> {
+        let url = url::Url::parse(&config.url).map_err(|e| format!("Invalid url \"{}\": {}", config.url, e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("Invalid url \"{}\": expected http or https", config.url));
+        }
+        let events = config.events.iter()
+            .map(|event| WebhookEvent::from_str(event, true).map_err(|e| format!("Invalid event \"{}\": {}", event, e)))
+            .collect::<Result<_, _>>()?;
+        let text_match = match &config.text_match {
+            Some(pattern) => Some(Regex::new(pattern).map_err(|e| format!("Invalid match \"{}\": {}", pattern, e))?),
+            None => None,
+        };
+        let format = match &config.format {
+            Some(format) => WebhookFormat::from_str(format, true).map_err(|e| format!("Invalid format \"{}\": {}", format, e))?,
+            None => WebhookFormat::default(),
+        };
+        Ok(Self { url: config.url.clone(), events, secret: config.secret.clone(), text_match, format })
+    }
+
+    fn wants(&self, event: WebhookEvent) -> bool {
+        self.events.is_empty() || self.events.contains(&event)
+    }
+}
+
+/// Send `event` about `target` to every webhook that wants it, in the
+/// background. `summary` is a one-line description; the fields of `details`
+/// (a JSON object) are added to the payload. For `TextFound`,
+/// `details["added"]` holds the new lines, narrowed per webhook to those its
+/// `match` accepts.
+pub fn notify(event: WebhookEvent, target: TargetKey, summary: String, details: Value) {
+    for webhook in config::settings().webhooks.iter().filter(|webhook| webhook.wants(event)) {
+        let mut payload = json!({
+            "event": event.name(),
+            "run_id": config::settings().run_id,
+            "target": target.to_string(),
+            "timestamp": SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis()).unwrap_or(0),
+            "summary": summary,
+        });
+        if let (Some(payload), Some(details)) = (payload.as_object_mut(), details.as_object()) {
+            for (key, value) in details {
+                payload.entry(key.clone()).or_insert_with(|| value.clone());
+            }
+        }
+        let mut summary = summary.clone();
+        if event == WebhookEvent::TextFound
+            && let Some(pattern) = &webhook.text_match
+        {
+            let matches: Vec<&str> = details["added"].as_array().into_iter().flatten()
+                .filter_map(Value::as_str)
+                .filter(|line| pattern.is_match(line))
+                .collect();
+            if matches.is_empty() {
+                continue;
+            }
+            summary = format!("Text found on {}: {}", target, matches.join(" | "));
+            payload["matches"] = json!(matches);
+            payload["summary"] = json!(summary);
+        }
+        let body = match webhook.format {
+            WebhookFormat::Json => payload.to_string(),
+            WebhookFormat::Slack => json!({ "text": summary }).to_string(),
+        };
+
+        let request = reqwest::Client::new()
+            .post(&webhook.url)
+            .timeout(DELIVERY_TIMEOUT)
+            .header("Content-Type", "application/json")
+            .header("X-Captest-Event", event.name())
+            .header(config::RUN_ID_HEADER, &config::settings().run_id);
+        let request = match &webhook.secret {
+            Some(secret) => request.header("X-Captest-Signature", format!("sha256={}", sign(secret, &body))),
+            None => request,
+        };
+        let url = webhook.url.clone();
+        IN_FLIGHT.fetch_add(1, Ordering::SeqCst);
+        tokio::spawn(async move {
+            match request.body(body).send().await {
+                Ok(response) if !response.status().is_success() => {
+                    status!("Webhook {} answered {} to {}", url, response.status().as_u16(), event.name());
+                }
+                Ok(_) => {}
+                Err(e) => status!("Webhook {} failed: {}", url, e),
+            }
+            IN_FLIGHT.fetch_sub(1, Ordering::SeqCst);
+        });
+    }
+}
+
+/// Wait (briefly) for deliveries still in flight, so events sent just before
+/// the process exits aren't lost.
+pub async fn flush() {
+    let started = Instant::now();
+    while IN_FLIGHT.load(Ordering::SeqCst) > 0 && started.elapsed() < FLUSH_TIMEOUT {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+/// Hex HMAC-SHA256 of `body`, so receivers can check a payload came from
+/// captest and wasn't altered.
+fn sign(secret: &str, body: &str) -> String {
+    digest::hex(&digest::hmac_sha256(secret.as_bytes(), body.as_bytes()))
+}
@@ -0,0 +1,175 @@
+use std::path::Path;
+
+use crate::error::{CaptestError, ErrorKind};
+use crate::output::WindowSummary;
+
+/// A window picked by its title or owner rather than by index, which shifts
+/// whenever windows open or close. Every given criterion has to match.
+#[derive(Default)]
+pub struct WindowQuery {
+    /// Case-insensitive substring of the title
+    pub title: Option<String>,
+    pub pid: Option<u32>,
+    /// Owning app or executable, case-insensitive; "chrome" matches "chrome.exe"
+    pub process: Option<String>,
+}
+
+impl WindowQuery {
+    pub fn is_empty(&self) -> bool {
+        self.title.is_none() && self.pid.is_none() && self.process.is_none()
+    }
+
+    fn matches(&self, window: &WindowSummary) -> bool {
+        self.title.as_ref().is_none_or(|title| window.title.to_lowercase().contains(&title.to_lowercase()))
+            && self.pid.is_none_or(|pid| window.pid == Some(pid))
+            && self.process.as_ref().is_none_or(|process| window.process.as_deref().is_some_and(|name| process_matches(name, process)))
+    }
+
+    fn describe(&self) -> String {
+        let mut criteria = Vec::new();
+        if let Some(title) = &self.title {
+            criteria.push(format!("title containing \"{}\"", title));
+        }
+        if let Some(pid) = self.pid {
+            criteria.push(format!("pid {}", pid));
+        }
+        if let Some(process) = &self.process {
+            criteria.push(format!("process \"{}\"", process));
+        }
+        criteria.join(", ")
+    }
+}
+
+/// Index of the window matching `query`. Several matches are an error that
+/// lists them, unless `first` is set, which takes the first in list order.
+pub fn find_window(query: &WindowQuery, windows: &[WindowSummary], first: bool) -> Result<usize, CaptestError> {
+    let matches: Vec<&WindowSummary> = windows.iter().filter(|window| query.matches(window)).collect();
+    match matches.as_slice() {
+        [] => {
+            let mut message = format!("No window matches {}", query.describe());
+            if (query.pid.is_some() || query.process.is_some()) && windows.iter().all(|window| window.pid.is_none()) {
+                message.push_str("; this platform doesn't report window owners, match by --title instead");
+            }
+            Err(CaptestError::new(ErrorKind::TargetNotFound, message))
+        }
+        [window] => Ok(window.index),
+        [window, ..] if first => Ok(window.index),
+        candidates => {
+            let listed: Vec<String> = candidates.iter().map(|window| format!("  {}", describe_window(window))).collect();
+            Err(CaptestError::new(ErrorKind::TargetNotFound, format!(
+                "{} windows match {}; narrow the match or pass --first:\n{}",
+                candidates.len(), query.describe(), listed.join("\n")
+            )))
+        }
+    }
+}
+
+/// "3: Inbox - Slack (slack.exe, pid 1234)"
+fn describe_window(window: &WindowSummary) -> String {
+    let owner: Vec<String> = window.process.iter().cloned()
+        .chain(window.pid.map(|pid| format!("pid {}", pid)))
+        .collect();
+    if owner.is_empty() {
+        format!("{}: {}", window.index, window.title)
+    } else {
+        format!("{}: {} ({})", window.index, window.title, owner.join(", "))
+    }
+}
+
+fn process_matches(name: &str, query: &str) -> bool {
+    let (name, query) = (name.to_lowercase(), query.to_lowercase());
+    name == query || Path::new(&name).file_stem().is_some_and(|stem| stem.to_string_lossy() == query)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(index: usize, title: &str, owner: Option<(u32, &str)>) -> WindowSummary {
+        WindowSummary {
+            index,
+            id: index as u32 + 100,
+            title: title.to_string(),
+            pid: owner.map(|(pid, _)| pid),
+            process: owner.map(|(_, process)| process.to_string()),
+            bounds: None,
+        }
+    }
+
+    fn windows() -> Vec<WindowSummary> {
+        vec![
+            window(0, "Inbox - Slack", Some((100, "slack.exe"))),
+            window(1, "Google - Chrome", Some((200, "chrome.exe"))),
+            window(2, "Docs - Chrome", Some((200, "chrome.exe"))),
+            window(3, "Huddle", Some((300, "Slack"))),
+            window(4, "Terminal", None),
+        ]
+    }
+
+    fn query(title: Option<&str>, pid: Option<u32>, process: Option<&str>) -> WindowQuery {
+        WindowQuery { title: title.map(str::to_string), pid, process: process.map(str::to_string) }
+    }
+
+    #[test]
+    fn matches_titles_case_insensitively() {
+        assert_eq!(find_window(&query(Some("INBOX"), None, None), &windows(), false).unwrap(), 0);
+        assert_eq!(find_window(&query(Some("termin"), None, None), &windows(), false).unwrap(), 4);
+    }
+
+    #[test]
+    fn matches_processes_with_or_without_the_extension() {
+        for process in ["chrome", "Chrome.EXE"] {
+            assert_eq!(find_window(&query(Some("docs"), None, Some(process)), &windows(), false).unwrap(), 2, "{}", process);
+        }
+        // Names match whole, not as substrings
+        assert!(find_window(&query(None, None, Some("chrom")), &windows(), false).is_err());
+    }
+
+    #[test]
+    fn every_criterion_has_to_match() {
+        assert_eq!(find_window(&query(None, Some(300), Some("slack")), &windows(), false).unwrap(), 3);
+        assert_eq!(find_window(&query(Some("slack"), Some(100), None), &windows(), false).unwrap(), 0);
+        assert!(find_window(&query(Some("inbox"), Some(200), None), &windows(), false).is_err());
+    }
+
+    #[test]
+    fn lists_the_candidates_when_several_match() {
+        let error = find_window(&query(Some("chrome"), None, None), &windows(), false).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::TargetNotFound);
+        assert_eq!(error.message, [
+            "2 windows match title containing \"chrome\"; narrow the match or pass --first:",
+            "  1: Google - Chrome (chrome.exe, pid 200)",
+            "  2: Docs - Chrome (chrome.exe, pid 200)",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn first_takes_the_earliest_match() {
+        assert_eq!(find_window(&query(None, None, Some("slack")), &windows(), true).unwrap(), 0);
+        assert_eq!(find_window(&query(Some("chrome"), None, None), &windows(), true).unwrap(), 1);
+    }
+
+    #[test]
+    fn reports_the_criteria_when_nothing_matches() {
+        let error = find_window(&query(Some("mail"), Some(100), Some("outlook")), &windows(), true).unwrap_err();
+        assert_eq!(error.kind, ErrorKind::TargetNotFound);
+        assert_eq!(error.message, "No window matches title containing \"mail\", pid 100, process \"outlook\"");
+    }
+
+    #[test]
+    fn explains_when_the_platform_has_no_owners() {
+        let windows = [window(0, "Inbox - Slack", None), window(1, "Terminal", None)];
+        let error = find_window(&query(None, None, Some("slack")), &windows, false).unwrap_err();
+        assert!(error.message.ends_with("match by --title instead"), "{}", error.message);
+        let error = find_window(&query(Some("mail"), None, None), &windows, false).unwrap_err();
+        assert_eq!(error.message, "No window matches title containing \"mail\"");
+    }
+
+    #[test]
+    fn describes_windows_with_what_is_known_of_their_owner() {
+        assert_eq!(describe_window(&window(0, "Inbox - Slack", Some((100, "slack.exe")))), "0: Inbox - Slack (slack.exe, pid 100)");
+        assert_eq!(describe_window(&window(4, "Terminal", None)), "4: Terminal");
+        assert!(query(None, None, None).is_empty());
+        assert!(!query(None, Some(1), None).is_empty());
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const LOCAL_HEADER: u32 = 0x04034b50;
+const CENTRAL_HEADER: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x06054b50;
+/// Version 2.0, the minimum for stored entries in folders
+const VERSION: u16 = 20;
+/// General purpose flag: names are UTF-8
+const UTF8_NAMES: u16 = 0x0800;
+/// Fixed sizes of the records before their variable-length fields
+const LOCAL_HEADER_SIZE: usize = 30;
+const CENTRAL_HEADER_SIZE: usize = 46;
+const END_RECORD_SIZE: usize = 22;
+
+/// Writes a zip archive of uncompressed entries. Frames are JPEG already, so
+/// compression wouldn't gain anything. Archives are limited to 4 GB and
+/// 65535 entries (no Zip64).
+pub struct ZipWriter {
+    file: BufWriter<File>,
+    central: Vec<u8>,
+    entries: u16,
+    offset: u32,
+}
+
+impl ZipWriter {
+    pub fn create(path: &Path) -> std::io::Result<Self> {
+        Ok(Self { file: BufWriter::new(File::create(path)?), central: Vec::new(), entries: 0, offset: 0 })
+    }
+
+    pub fn add(&mut self, name: &str, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        let Ok(name_length) = u16::try_from(name.len()) else {
+            return Err(format!("{} is too long for a zip entry name", name).into());
+        };
+        // The next entry starts after this one's header, name and data, and
+        // its offset has to fit the 32 bits the central directory has for it
+        let size = u32::try_from(data.len()).ok();
+        let next_offset = size.and_then(|size| self.offset.checked_add(LOCAL_HEADER_SIZE as u32 + name_length as u32)?.checked_add(size));
+        let (Some(size), Some(next_offset), Some(entries)) = (size, next_offset, self.entries.checked_add(1)) else {
+            return Err("Zip bundles are limited to 4 GB and 65535 files, record to a directory instead".into());
+        };
+        let crc = crc32(data);
+
+        let mut header = Vec::with_capacity(LOCAL_HEADER_SIZE + name.len());
+        header.extend(LOCAL_HEADER.to_le_bytes());
+        header.extend(VERSION.to_le_bytes());
+        header.extend(entry_fields(crc, size, name_length));
+        header.extend(name.as_bytes());
+        self.file.write_all(&header)?;
+        self.file.write_all(data)?;
+
+        self.central.extend(CENTRAL_HEADER.to_le_bytes());
+        self.central.extend(VERSION.to_le_bytes());
+        self.central.extend(VERSION.to_le_bytes());
+        self.central.extend(entry_fields(crc, size, name_length));
+        // Comment length, disk number, internal and external attributes
+        self.central.extend([0u8; 10]);
+        self.central.extend(self.offset.to_le_bytes());
+        self.central.extend(name.as_bytes());
+
+        self.offset = next_offset;
+        self.entries = entries;
+        Ok(())
+    }
+
+    /// Write the central directory; the archive can't be read without it.
+    pub fn finish(mut self) -> std::io::Result<()> {
+        let central_size = u32::try_from(self.central.len())
+            .map_err(|_| std::io::Error::other("Zip bundles are limited to 4 GB, record to a directory instead"))?;
+        self.file.write_all(&self.central)?;
+        let mut end = Vec::with_capacity(END_RECORD_SIZE);
+        end.extend(END_OF_CENTRAL_DIRECTORY.to_le_bytes());
+        // This disk and the disk the central directory starts on
+        end.extend([0u8; 4]);
+        end.extend(self.entries.to_le_bytes());
+        end.extend(self.entries.to_le_bytes());
+        end.extend(central_size.to_le_bytes());
+        end.extend(self.offset.to_le_bytes());
+        // Comment length
+        end.extend([0u8; 2]);
+        self.file.write_all(&end)?;
+        self.file.flush()?;
+        self.file.get_ref().sync_all()
+    }
+}
+
+/// Fields shared by local and central headers, from the flags to the extra
+/// field length.
+fn entry_fields(crc: u32, size: u32, name_length: u16) -> Vec<u8> {
+    let mut fields = Vec::with_capacity(24);
+    fields.extend(UTF8_NAMES.to_le_bytes());
+    // Stored, with a zero DOS timestamp
+    fields.extend([0u8; 6]);
+    fields.extend(crc.to_le_bytes());
+    fields.extend(size.to_le_bytes());
+    fields.extend(size.to_le_bytes());
+    fields.extend(name_length.to_le_bytes());
+    fields.extend([0u8; 2]);
+    fields
+}
+
+/// Reads uncompressed entries from a zip archive such as those written by
+/// [`ZipWriter`].
+pub struct ZipReader {
+    file: File,
+    /// Entry name to (local header offset, size, compression method)
+    entries: HashMap<String, (u32, u32, u16)>,
+}
+
+impl ZipReader {
+    pub fn open(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut file = File::open(path)?;
+        let length = file.seek(SeekFrom::End(0))?;
+        // The end record is last, after a comment of at most 64 KB
+        let tail_length = length.min((END_RECORD_SIZE + u16::MAX as usize) as u64);
+        file.seek(SeekFrom::Start(length - tail_length))?;
+        let mut tail = vec![0u8; tail_length as usize];
+        file.read_exact(&mut tail)?;
+        let end = (0..tail.len().saturating_sub(END_RECORD_SIZE - 1))
+            .rev()
+            .find(|&at| tail[at..at + 4] == END_OF_CENTRAL_DIRECTORY.to_le_bytes())
+            .ok_or_else(|| format!("{} is not a zip archive or is incomplete", path.display()))?;
+        let count = u16_at(&tail, end + 10);
+        let central_size = u32_at(&tail, end + 12);
+        let central_offset = u32_at(&tail, end + 16);
+
+        file.seek(SeekFrom::Start(central_offset as u64))?;
+        let mut central = vec![0u8; central_size as usize];
+        file.read_exact(&mut central)?;
+        let mut entries = HashMap::new();
+        let mut at = 0;
+        for _ in 0..count {
+            if central.len() < at + CENTRAL_HEADER_SIZE || u32_at(&central, at) != CENTRAL_HEADER {
+                return Err(format!("{} has a damaged central directory", path.display()).into());
+            }
+            let method = u16_at(&central, at + 10);
+            let size = u32_at(&central, at + 20);
+            let name_length = u16_at(&central, at + 28) as usize;
+            let extra_length = u16_at(&central, at + 30) as usize;
+            let comment_length = u16_at(&central, at + 32) as usize;
+            let offset = u32_at(&central, at + 42);
+            let name = central
+                .get(at + CENTRAL_HEADER_SIZE..at + CENTRAL_HEADER_SIZE + name_length)
+                .ok_or_else(|| format!("{} has a damaged central directory", path.display()))?;
+            entries.insert(String::from_utf8_lossy(name).into_owned(), (offset, size, method));
+            at += CENTRAL_HEADER_SIZE + name_length + extra_length + comment_length;
+        }
+        Ok(Self { file, entries })
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.entries.keys()
+    }
+
+    pub fn read(&mut self, name: &str) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let &(offset, size, method) = self.entries.get(name).ok_or_else(|| format!("{} is missing from the bundle", name))?;
+        if method != 0 {
+            return Err(format!("{} is compressed; only bundles written by captest can be read", name).into());
+        }
+        let mut header = [0u8; LOCAL_HEADER_SIZE];
+        self.file.seek(SeekFrom::Start(offset as u64))?;
+        self.file.read_exact(&mut header)?;
+        let skip = u16_at(&header, 26) as i64 + u16_at(&header, 28) as i64;
+        self.file.seek(SeekFrom::Current(skip))?;
+        let mut data = vec![0u8; size as usize];
+        self.file.read_exact(&mut data)?;
+        Ok(data)
+    }
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// CRC-32 (IEEE) as zip entries need it.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB88320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("captest-zip-{}-{}.zip", std::process::id(), name))
+    }
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+    }
+
+    #[test]
+    fn reads_back_what_was_written() {
+        let path = temp_path("round-trip");
+        let mut writer = ZipWriter::create(&path).unwrap();
+        writer.add("session.json", b"{}").unwrap();
+        writer.add("frames/0001.jpg", &[0xFF, 0xD8, 0xFF, 0xD9]).unwrap();
+        writer.add("empty", b"").unwrap();
+        writer.add("naïve.txt", "ünïcödé".as_bytes()).unwrap();
+        writer.finish().unwrap();
+
+        let mut reader = ZipReader::open(&path).unwrap();
+        let mut names: Vec<&String> = reader.names().collect();
+        names.sort();
+        assert_eq!(names, ["empty", "frames/0001.jpg", "naïve.txt", "session.json"]);
+        assert_eq!(reader.read("session.json").unwrap(), b"{}");
+        assert_eq!(reader.read("frames/0001.jpg").unwrap(), [0xFF, 0xD8, 0xFF, 0xD9]);
+        assert_eq!(reader.read("empty").unwrap(), b"");
+        assert_eq!(reader.read("naïve.txt").unwrap(), "ünïcödé".as_bytes());
+        assert!(reader.read("missing").is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_names_longer_than_a_zip_header_can_hold() {
+        let path = temp_path("long-name");
+        let mut writer = ZipWriter::create(&path).unwrap();
+        assert!(writer.add(&"a".repeat(u16::MAX as usize + 1), b"data").is_err());
+        drop(writer);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_entries_that_would_start_past_4_gb() {
+        let path = temp_path("limit");
+        let mut writer = ZipWriter::create(&path).unwrap();
+        // The header and name count toward the limit, not only the data
+        writer.offset = u32::MAX - (LOCAL_HEADER_SIZE as u32 + 4) - 2;
+        assert!(writer.add("name", b"abc").is_err());
+        assert!(writer.add("name", b"ab").is_ok());
+        assert!(writer.add("", b"").is_err());
+        drop(writer);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_zip_archives() {
+        let path = temp_path("not-zip");
+        std::fs::write(&path, b"not a zip archive").unwrap();
+        assert!(ZipReader::open(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}